@@ -1,18 +1,22 @@
 //! ABI abstraction layer for x86-64 calling conventions
 //!
 //! Provides platform-specific constants for System V AMD64 (Linux, macOS, BSD)
-//! and Win64 (Windows) ABIs.
+//! and Win64 (Windows) ABIs, and the target system a program is compiled
+//! for, which picks between them.
 
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
+use clap::ValueEnum;
+
 /// Calling convention abstraction for x86-64
 pub trait Abi {
     /// Integer/pointer argument registers (in order)
     const INT_ARG_REGS: &'static [&'static str];
 
-    /// Symbol prefix for external symbols ("_" on macOS, "" elsewhere)
-    const SYMBOL_PREFIX: &'static str;
+    /// Stack space the caller reserves above the return address for the
+    /// callee's use
+    const SHADOW_SPACE: i32;
 }
 
 /// System V AMD64 ABI (Linux, macOS, BSD)
@@ -20,29 +24,76 @@ pub struct SysV64;
 
 impl Abi for SysV64 {
     const INT_ARG_REGS: &'static [&'static str] = &["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
-
-    #[cfg(target_os = "macos")]
-    const SYMBOL_PREFIX: &'static str = "_";
-    #[cfg(not(target_os = "macos"))]
-    const SYMBOL_PREFIX: &'static str = "";
+    const SHADOW_SPACE: i32 = 0;
 }
 
 /// Windows x64 ABI
-#[cfg(any(windows, test))]
 pub struct Win64;
 
-#[cfg(any(windows, test))]
 impl Abi for Win64 {
     const INT_ARG_REGS: &'static [&'static str] = &["rcx", "rdx", "r8", "r9"];
-    const SYMBOL_PREFIX: &'static str = "";
+    const SHADOW_SPACE: i32 = 32;
+}
+
+/// The system a program is compiled for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Target {
+    /// Linux with glibc
+    #[default]
+    Linux,
+    /// macOS (also x86_64-apple): x86-64 code, which Apple Silicon runs
+    /// through Rosetta 2
+    #[value(alias = "x86_64-apple")]
+    Macos,
+    /// Windows, through the Win32 API
+    Windows,
 }
 
-/// Type alias for the current platform's ABI
-#[cfg(windows)]
-pub type PlatformAbi = Win64;
+impl Target {
+    /// The system the compiler runs on
+    pub fn host() -> Self {
+        if cfg!(windows) {
+            Target::Windows
+        } else if cfg!(target_os = "macos") {
+            Target::Macos
+        } else {
+            Target::Linux
+        }
+    }
+
+    /// Integer/pointer argument registers of the target's ABI
+    pub fn int_arg_regs(self) -> &'static [&'static str] {
+        match self {
+            Target::Windows => Win64::INT_ARG_REGS,
+            Target::Linux | Target::Macos => SysV64::INT_ARG_REGS,
+        }
+    }
+
+    /// Shadow space the target's ABI has callers reserve
+    pub fn shadow_space(self) -> i32 {
+        match self {
+            Target::Windows => Win64::SHADOW_SPACE,
+            Target::Linux | Target::Macos => SysV64::SHADOW_SPACE,
+        }
+    }
 
-#[cfg(not(windows))]
-pub type PlatformAbi = SysV64;
+    /// Symbol prefix for external symbols ("_" on macOS, "" elsewhere)
+    pub fn symbol_prefix(self) -> &'static str {
+        match self {
+            Target::Macos => "_",
+            Target::Linux | Target::Windows => "",
+        }
+    }
+
+    /// Directive starting the read-only data section
+    pub fn rodata_section(self) -> &'static str {
+        match self {
+            Target::Linux => ".section .rodata",
+            Target::Macos => ".section __TEXT,__const",
+            Target::Windows => ".section .rdata,\"dr\"",
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -59,4 +110,14 @@ mod tests {
         assert_eq!(Win64::INT_ARG_REGS.len(), 4);
         assert_eq!(Win64::INT_ARG_REGS[0], "rcx");
     }
+
+    #[test]
+    fn test_target_abi() {
+        assert_eq!(Target::Windows.int_arg_regs()[0], "rcx");
+        assert_eq!(Target::Windows.shadow_space(), 32);
+        assert_eq!(Target::Linux.int_arg_regs()[0], "rdi");
+        assert_eq!(Target::Linux.shadow_space(), 0);
+        assert_eq!(Target::Macos.symbol_prefix(), "_");
+        assert_eq!(Target::Windows.symbol_prefix(), "");
+    }
 }
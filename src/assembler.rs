@@ -0,0 +1,1086 @@
+//! Built-in assembler - turns the compiler's Intel-syntax assembly into an
+//! ELF relocatable object, so building on Linux needs no external tools
+//! but the linker
+//!
+//! It reads the subset of GNU `as` syntax that codegen and the runtime
+//! write: instructions in `.intel_syntax noprefix` form (see encoder.rs),
+//! labels, `#` comments, `.text` / `.data` / `.bss` / `.rodata`, `.globl`,
+//! `.p2align`, `.equ`, `.rept` / `.endr`, and the data directives `.byte`,
+//! `.short`, `.long`, `.quad`, `.double`, `.ascii`, `.asciz` and `.skip`.
+//!
+//! Layout takes a single pass, since no instruction's size depends on an
+//! address. Then each fixup is patched in place when its label is in the
+//! same section, and otherwise becomes a relocation for the linker.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::elf::{self, Object, Section, SectionKind, Target};
+use crate::encoder::{self, FixupKind, Mem, Operand, Reg, Value};
+use std::collections::{HashMap, HashSet};
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Assemble a program and its runtime into an object. Errors name the
+/// offending line.
+pub fn assemble(text: &str) -> Result<Object> {
+    let lines = expand(text)?;
+    let mut asm = Assembler::new();
+    for &(number, line) in &lines {
+        if let Some(rest) = directive_args(line, &[".equ", ".set"]) {
+            asm.equ(rest)
+                .map_err(|e| format!("line {}: {}", number, e))?;
+        }
+    }
+    for &(number, line) in &lines {
+        asm.number = number;
+        asm.line(line)
+            .map_err(|e| format!("line {}: {}", number, e))?;
+    }
+    asm.finish()
+}
+
+/// The code on each line, without comments or blank lines, with `.rept`
+/// blocks repeated, and numbered by source line
+fn expand(text: &str) -> Result<Vec<(usize, &str)>> {
+    let mut lines = Vec::new();
+    // Open .rept blocks: repeat count and where the block starts
+    let mut repeats: Vec<(usize, usize)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let code = strip_comment(line).trim();
+        if let Some(count) = directive_args(code, &[".rept"]) {
+            let count = parse_number(count.trim())
+                .filter(|&n| n >= 0)
+                .ok_or_else(|| format!("line {}: bad .rept count", i + 1))?;
+            repeats.push((count as usize, lines.len()));
+        } else if code == ".endr" {
+            let (count, start) = repeats
+                .pop()
+                .ok_or_else(|| format!("line {}: .endr without .rept", i + 1))?;
+            let body = lines.split_off(start);
+            for _ in 0..count {
+                lines.extend_from_slice(&body);
+            }
+        } else if !code.is_empty() {
+            lines.push((i + 1, code));
+        }
+    }
+    if !repeats.is_empty() {
+        return Err(".rept without .endr".to_string());
+    }
+    Ok(lines)
+}
+
+/// A line without its `#` comment, skipping over strings and characters
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'\'' => {
+                i += if bytes.get(i + 1) == Some(&b'\\') {
+                    2
+                } else {
+                    1
+                };
+                if bytes.get(i + 1) == Some(&b'\'') {
+                    i += 1;
+                }
+            }
+            b'#' => return &line[..i],
+            _ => {}
+        }
+        i += 1;
+    }
+    line
+}
+
+/// The arguments of a line that's one of these directives
+fn directive_args<'a>(line: &'a str, names: &[&str]) -> Option<&'a str> {
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    names.contains(&name).then_some(rest)
+}
+
+/// A decimal, hex or binary integer
+fn parse_number(s: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        (hex, 16)
+    } else if let Some(bin) = s.strip_prefix("0b").or(s.strip_prefix("0B")) {
+        (bin, 2)
+    } else {
+        (s, 10)
+    };
+    // Hex constants may fill all 64 bits
+    u64::from_str_radix(digits, radix).ok().map(|n| n as i64)
+}
+
+/// Split on commas that aren't inside strings, characters or brackets
+fn split_args(s: &str) -> Vec<&str> {
+    let bytes = s.as_bytes();
+    let mut args = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'\'' => {
+                i += if bytes.get(i + 1) == Some(&b'\\') {
+                    2
+                } else {
+                    1
+                };
+                if bytes.get(i + 1) == Some(&b'\'') {
+                    i += 1;
+                }
+            }
+            b'[' | b'(' => depth += 1,
+            b']' | b')' => depth -= 1,
+            b',' if depth == 0 => {
+                args.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    let last = s[start..].trim();
+    if !last.is_empty() || !args.is_empty() {
+        args.push(last);
+    }
+    args
+}
+
+/// The bytes of a string literal, escapes decoded
+fn parse_string(s: &str) -> Result<Vec<u8>> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string: {}", s))?;
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.bytes().peekable();
+    while let Some(b) = chars.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let escaped = chars.next().ok_or("string ends in a backslash")?;
+        bytes.push(match escaped {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'b' => 8,
+            b'f' => 12,
+            b'x' => {
+                let mut n = 0u8;
+                while let Some(digit) = chars.peek().and_then(|&c| (c as char).to_digit(16)) {
+                    n = n.wrapping_mul(16).wrapping_add(digit as u8);
+                    chars.next();
+                }
+                n
+            }
+            b'0'..=b'7' => {
+                let mut n = escaped - b'0';
+                for _ in 0..2 {
+                    match chars.peek() {
+                        Some(&c @ b'0'..=b'7') => {
+                            n = n.wrapping_mul(8).wrapping_add(c - b'0');
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+                n
+            }
+            other => other,
+        });
+    }
+    Ok(bytes)
+}
+
+/// A token of an expression or memory operand
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tok<'a> {
+    Num(i64),
+    Ident(&'a str),
+    Op(u8),
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'$')
+}
+
+fn tokenize(s: &str) -> Result<Vec<Tok<'_>>> {
+    let bytes = s.as_bytes();
+    let mut toks = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b.is_ascii_whitespace() {
+            i += 1;
+        } else if b == b'\'' {
+            // 'c', '\n' or GNU's unterminated 'c
+            let (value, len) = match bytes.get(i + 1) {
+                Some(b'\\') => match bytes.get(i + 2) {
+                    Some(b'n') => (b'\n', 3),
+                    Some(b't') => (b'\t', 3),
+                    Some(b'0') => (0, 3),
+                    Some(&c) => (c, 3),
+                    None => return Err(format!("bad character in {}", s)),
+                },
+                Some(&c) => (c, 2),
+                None => return Err(format!("bad character in {}", s)),
+            };
+            i += len;
+            if bytes.get(i) == Some(&b'\'') {
+                i += 1;
+            }
+            toks.push(Tok::Num(value as i64));
+        } else if b.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let n =
+                parse_number(&s[start..i]).ok_or_else(|| format!("bad number {}", &s[start..i]))?;
+            toks.push(Tok::Num(n));
+        } else if is_ident(b) {
+            let start = i;
+            while i < bytes.len() && is_ident(bytes[i]) {
+                i += 1;
+            }
+            toks.push(Tok::Ident(&s[start..i]));
+        } else if b"+-*/~()".contains(&b) {
+            toks.push(Tok::Op(b));
+            i += 1;
+        } else {
+            return Err(format!("unexpected '{}' in {}", b as char, s));
+        }
+    }
+    Ok(toks)
+}
+
+/// `a + b`, where at most one side may be a symbol's address
+fn add(a: Value, b: Value) -> Result<Value> {
+    let both = |x: Option<String>, y: Option<String>| match (x, y) {
+        (Some(_), Some(_)) => Err("can't add two addresses".to_string()),
+        (x, y) => Ok(x.or(y)),
+    };
+    Ok(Value {
+        symbol: both(a.symbol, b.symbol)?,
+        minus: both(a.minus, b.minus)?,
+        addend: a.addend.wrapping_add(b.addend),
+    })
+}
+
+/// `-a`, which turns an address into one to subtract
+fn negate(a: Value) -> Value {
+    Value {
+        symbol: a.minus,
+        minus: a.symbol,
+        addend: a.addend.wrapping_neg(),
+    }
+}
+
+fn constant(v: &Value) -> Result<i64> {
+    v.as_constant()
+        .ok_or_else(|| "expected a constant, not an address".to_string())
+}
+
+/// A recursive-descent expression parser over tokens
+struct Expr<'t, 'a> {
+    toks: &'t [Tok<'a>],
+    pos: usize,
+    equs: &'t HashMap<String, i64>,
+}
+
+impl<'a> Expr<'_, 'a> {
+    fn peek(&self) -> Option<Tok<'a>> {
+        self.toks.get(self.pos).copied()
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Value> {
+        let mut value = self.term()?;
+        while let Some(Tok::Op(op @ (b'+' | b'-'))) = self.peek() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = add(value, if op == b'+' { rhs } else { negate(rhs) })?;
+        }
+        Ok(value)
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Value> {
+        let mut value = self.unary()?;
+        while let Some(Tok::Op(op @ (b'*' | b'/'))) = self.peek() {
+            self.pos += 1;
+            let (a, b) = (constant(&value)?, constant(&self.unary()?)?);
+            value = Value::constant(if op == b'*' {
+                a.wrapping_mul(b)
+            } else {
+                a.checked_div(b).ok_or("division by zero")?
+            });
+        }
+        Ok(value)
+    }
+
+    // unary := ('-' | '+' | '~') unary | atom
+    fn unary(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some(Tok::Op(b'-')) => {
+                self.pos += 1;
+                Ok(negate(self.unary()?))
+            }
+            Some(Tok::Op(b'+')) => {
+                self.pos += 1;
+                self.unary()
+            }
+            Some(Tok::Op(b'~')) => {
+                self.pos += 1;
+                Ok(Value::constant(!constant(&self.unary()?)?))
+            }
+            _ => self.atom(),
+        }
+    }
+
+    // atom := number | symbol | '(' expr ')'
+    fn atom(&mut self) -> Result<Value> {
+        let tok = self.peek().ok_or("expression ends early")?;
+        self.pos += 1;
+        match tok {
+            Tok::Num(n) => Ok(Value::constant(n)),
+            Tok::Ident(name) => Ok(match self.equs.get(name) {
+                Some(&n) => Value::constant(n),
+                None => Value {
+                    symbol: Some(name.to_string()),
+                    ..Value::default()
+                },
+            }),
+            Tok::Op(b'(') => {
+                let value = self.expr()?;
+                match self.peek() {
+                    Some(Tok::Op(b')')) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Tok::Op(op) => Err(format!("unexpected '{}'", op as char)),
+        }
+    }
+}
+
+/// A fixup waiting for layout to finish
+struct Pending {
+    section: usize,
+    offset: u64,
+    kind: FixupKind,
+    value: Value,
+    /// Source line, for errors
+    number: usize,
+}
+
+struct Assembler {
+    /// .text, .data, .bss and .rodata
+    sections: Vec<Section>,
+    current: usize,
+    /// Section and offset of each label
+    labels: HashMap<String, (usize, u64)>,
+    /// Labels that go in the symbol table (not `.L` ones), in order
+    named: Vec<String>,
+    globals: HashSet<String>,
+    equs: HashMap<String, i64>,
+    fixups: Vec<Pending>,
+    /// The line being assembled
+    number: usize,
+}
+
+const TEXT: usize = 0;
+const BSS: usize = 2;
+const RODATA: usize = 3;
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler {
+            sections: vec![
+                Section::new(".text", SectionKind::Text),
+                Section::new(".data", SectionKind::Data),
+                Section::new(".bss", SectionKind::Bss),
+                Section::new(".rodata", SectionKind::Rodata),
+            ],
+            current: TEXT,
+            labels: HashMap::new(),
+            named: Vec::new(),
+            globals: HashSet::new(),
+            equs: HashMap::new(),
+            fixups: Vec::new(),
+            number: 0,
+        }
+    }
+
+    fn value(&self, s: &str) -> Result<Value> {
+        let toks = tokenize(s)?;
+        let mut parser = Expr {
+            toks: &toks,
+            pos: 0,
+            equs: &self.equs,
+        };
+        let value = parser.expr()?;
+        if parser.pos != toks.len() {
+            return Err(format!("bad expression {}", s));
+        }
+        Ok(value)
+    }
+
+    /// `.equ NAME, value`
+    fn equ(&mut self, args: &str) -> Result<()> {
+        let (name, value) = args
+            .split_once(',')
+            .ok_or_else(|| format!("bad .equ {}", args))?;
+        let value = constant(&self.value(value)?)?;
+        self.equs.insert(name.trim().to_string(), value);
+        Ok(())
+    }
+
+    fn offset(&self) -> u64 {
+        self.sections[self.current].size
+    }
+
+    /// Append bytes to the current section
+    fn emit(&mut self, bytes: &[u8]) -> Result<()> {
+        let section = &mut self.sections[self.current];
+        if section.kind == SectionKind::Bss {
+            if bytes.iter().any(|&b| b != 0) {
+                return Err("data in .bss".to_string());
+            }
+        } else {
+            section.data.extend_from_slice(bytes);
+        }
+        section.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Pad the current section to a multiple of `align` bytes, with nops
+    /// in code
+    fn align(&mut self, align: u64) -> Result<()> {
+        if !align.is_power_of_two() {
+            return Err(format!("bad alignment {}", align));
+        }
+        let section = &mut self.sections[self.current];
+        section.align = section.align.max(align);
+        let fill = if self.current == TEXT { 0x90 } else { 0 };
+        let padding = section.size.next_multiple_of(align) - section.size;
+        self.emit(&vec![fill; padding as usize])
+    }
+
+    fn define(&mut self, label: &str) -> Result<()> {
+        let place = (self.current, self.offset());
+        if self.labels.insert(label.to_string(), place).is_some() {
+            return Err(format!("label {} defined twice", label));
+        }
+        if !label.starts_with(".L") {
+            self.named.push(label.to_string());
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> Result<()> {
+        // Labels, possibly followed by more on the same line
+        let mut rest = line;
+        loop {
+            let len = rest.bytes().take_while(|&b| is_ident(b)).count();
+            match rest[len..].strip_prefix(':') {
+                Some(after) if len > 0 => {
+                    self.define(&rest[..len])?;
+                    rest = after.trim_start();
+                }
+                _ => break,
+            }
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+        let (name, args) = rest
+            .split_once(char::is_whitespace)
+            .map_or((rest, ""), |(name, args)| (name, args.trim()));
+        if name.starts_with('.') {
+            self.directive(name, args)
+        } else {
+            self.instruction(name, args)
+        }
+    }
+
+    fn directive(&mut self, name: &str, args: &str) -> Result<()> {
+        match name {
+            ".intel_syntax" | ".equ" | ".set" | ".type" | ".size" | ".file" | ".ident" => {}
+            ".text" => self.current = TEXT,
+            ".data" => self.current = 1,
+            ".bss" => self.current = BSS,
+            ".section" => {
+                let section = split_args(args).first().copied().unwrap_or("");
+                self.current = match section {
+                    ".text" => TEXT,
+                    ".data" => 1,
+                    ".bss" => BSS,
+                    ".rodata" => RODATA,
+                    _ => return Err(format!("unknown section {}", section)),
+                };
+            }
+            ".globl" | ".global" => {
+                for symbol in split_args(args) {
+                    self.globals.insert(symbol.to_string());
+                }
+            }
+            ".p2align" | ".balign" | ".align" => {
+                let n = constant(&self.value(split_args(args).first().copied().unwrap_or(""))?)?;
+                let align = if name == ".p2align" {
+                    1u64.checked_shl(n as u32).unwrap_or(0)
+                } else {
+                    n as u64
+                };
+                self.align(align)?;
+            }
+            ".byte" | ".short" | ".word" | ".value" | ".2byte" | ".long" | ".int" | ".4byte"
+            | ".quad" | ".8byte" => {
+                let width = match name {
+                    ".byte" => 1,
+                    ".short" | ".word" | ".value" | ".2byte" => 2,
+                    ".long" | ".int" | ".4byte" => 4,
+                    _ => 8,
+                };
+                for arg in split_args(args) {
+                    let value = self.value(arg)?;
+                    match value.as_constant() {
+                        Some(n) => {
+                            if width < 8 && !fits(n, width) {
+                                return Err(format!("{} doesn't fit in {}", n, name));
+                            }
+                            self.emit(&n.to_le_bytes()[..width])?;
+                        }
+                        None => {
+                            self.fixup(0, FixupKind::Abs(width as u8, false), value);
+                            self.emit(&vec![0; width])?;
+                        }
+                    }
+                }
+            }
+            ".double" | ".float" => {
+                for arg in split_args(args) {
+                    let x: f64 = arg
+                        .parse()
+                        .map_err(|_| format!("bad floating-point number {}", arg))?;
+                    if name == ".double" {
+                        self.emit(&x.to_le_bytes())?;
+                    } else {
+                        self.emit(&(x as f32).to_le_bytes())?;
+                    }
+                }
+            }
+            ".ascii" | ".asciz" | ".string" => {
+                for arg in split_args(args) {
+                    let mut bytes = parse_string(arg)?;
+                    if name != ".ascii" {
+                        bytes.push(0);
+                    }
+                    self.emit(&bytes)?;
+                }
+            }
+            ".skip" | ".space" | ".zero" => {
+                let args = split_args(args);
+                let count = constant(&self.value(args.first().copied().unwrap_or(""))?)?;
+                let fill = match args.get(1) {
+                    Some(fill) => constant(&self.value(fill)?)? as u8,
+                    None => 0,
+                };
+                if count < 0 {
+                    return Err(format!("negative {} {}", name, count));
+                }
+                if self.current == BSS && fill == 0 {
+                    self.sections[BSS].size += count as u64;
+                } else {
+                    self.emit(&vec![fill; count as usize])?;
+                }
+            }
+            _ => return Err(format!("unknown directive {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Record a fixup `at` bytes past the current offset
+    fn fixup(&mut self, at: usize, kind: FixupKind, value: Value) {
+        self.fixups.push(Pending {
+            section: self.current,
+            offset: self.offset() + at as u64,
+            kind,
+            value,
+            number: self.number,
+        });
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand> {
+        let (size, rest) = match text.split_once(char::is_whitespace) {
+            Some((word, rest)) => {
+                let size = match word.to_ascii_uppercase().as_str() {
+                    "BYTE" => Some(1),
+                    "WORD" => Some(2),
+                    "DWORD" => Some(4),
+                    "QWORD" => Some(8),
+                    "XMMWORD" => Some(16),
+                    _ => None,
+                };
+                match (size, rest.trim_start().split_once(char::is_whitespace)) {
+                    (Some(size), Some((ptr, rest))) if ptr.eq_ignore_ascii_case("PTR") => {
+                        (Some(size), rest.trim())
+                    }
+                    (Some(_), _) => return Err(format!("expected PTR in {}", text)),
+                    (None, _) => (None, text),
+                }
+            }
+            None => (None, text),
+        };
+        if let Some(inner) = rest.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+            let mut mem = self.memory(inner)?;
+            mem.size = size;
+            return Ok(Operand::Mem(mem));
+        }
+        if size.is_some() {
+            return Err(format!("expected a memory operand: {}", text));
+        }
+        match Reg::parse(rest) {
+            Some(reg) => Ok(Operand::Reg(reg)),
+            None => Ok(Operand::Imm(self.value(rest)?)),
+        }
+    }
+
+    /// The inside of `[...]`: registers, scaled index and displacement
+    /// terms joined by `+` and `-`
+    fn memory(&self, inner: &str) -> Result<Mem> {
+        let toks = tokenize(inner)?;
+        // Split into terms at binary + and -
+        let mut terms: Vec<(bool, &[Tok])> = Vec::new();
+        let (mut start, mut negative, mut depth, mut operand) = (0, false, 0, false);
+        for (i, tok) in toks.iter().enumerate() {
+            match tok {
+                Tok::Op(op @ (b'+' | b'-')) if operand && depth == 0 => {
+                    terms.push((negative, &toks[start..i]));
+                    (start, negative, operand) = (i + 1, *op == b'-', false);
+                }
+                Tok::Op(b'(') => (depth, operand) = (depth + 1, false),
+                Tok::Op(b')') => (depth, operand) = (depth - 1, true),
+                Tok::Op(_) => operand = false,
+                _ => operand = true,
+            }
+        }
+        terms.push((negative, &toks[start..]));
+
+        let mut mem = Mem::default();
+        let gp64 = |tok: &Tok| match tok {
+            Tok::Ident(name) => match Reg::parse(name) {
+                Some(Reg::Gp(num, 8)) => Some(num),
+                _ => None,
+            },
+            _ => None,
+        };
+        for (negative, term) in terms {
+            let register = match term {
+                [Tok::Ident("rip")] => {
+                    mem.rip = true;
+                    true
+                }
+                [reg] if gp64(reg).is_some() => {
+                    let num = gp64(reg).unwrap();
+                    if mem.base.is_none() {
+                        mem.base = Some(num);
+                    } else if mem.index.is_none() {
+                        mem.index = Some((num, 1));
+                    } else {
+                        return Err(format!("too many registers in [{}]", inner));
+                    }
+                    true
+                }
+                [reg, Tok::Op(b'*'), Tok::Num(scale)] | [Tok::Num(scale), Tok::Op(b'*'), reg]
+                    if gp64(reg).is_some() =>
+                {
+                    if mem.index.is_some() {
+                        return Err(format!("two index registers in [{}]", inner));
+                    }
+                    mem.index = Some((gp64(reg).unwrap(), *scale as u8));
+                    true
+                }
+                _ => {
+                    let mut parser = Expr {
+                        toks: term,
+                        pos: 0,
+                        equs: &self.equs,
+                    };
+                    let value = parser.expr()?;
+                    if parser.pos != term.len() {
+                        return Err(format!("bad memory operand [{}]", inner));
+                    }
+                    mem.disp = add(mem.disp, if negative { negate(value) } else { value })?;
+                    false
+                }
+            };
+            if register && negative {
+                return Err(format!("can't subtract a register in [{}]", inner));
+            }
+        }
+        if mem.rip && (mem.base.is_some() || mem.index.is_some()) {
+            return Err(format!("rip with other registers in [{}]", inner));
+        }
+        Ok(mem)
+    }
+
+    fn instruction(&mut self, mnemonic: &str, args: &str) -> Result<()> {
+        if self.current != TEXT {
+            return Err(format!("instruction outside .text: {}", mnemonic));
+        }
+        // A repeat prefix applies to the string instruction after it
+        let (prefix, mnemonic, args) = match mnemonic {
+            "rep" | "repe" | "repz" | "repne" | "repnz" => {
+                let (inner, rest) = args
+                    .split_once(char::is_whitespace)
+                    .map_or((args, ""), |(name, rest)| (name, rest.trim()));
+                let prefix = if mnemonic.starts_with("repn") {
+                    0xF2
+                } else {
+                    0xF3
+                };
+                (Some(prefix), inner, rest)
+            }
+            _ => (None, mnemonic, args),
+        };
+        let mnemonic = mnemonic.to_ascii_lowercase();
+        let operands = split_args(args)
+            .into_iter()
+            .map(|arg| self.operand(arg))
+            .collect::<Result<Vec<_>>>()?;
+        let encoded = encoder::encode(&mnemonic, &operands)?;
+        let mut bytes = Vec::with_capacity(encoded.bytes.len() + 1);
+        bytes.extend(prefix);
+        bytes.extend_from_slice(&encoded.bytes);
+        for fixup in encoded.fixups {
+            let at = fixup.offset + bytes.len() - encoded.bytes.len();
+            self.fixup(at, fixup.kind, fixup.value);
+        }
+        self.emit(&bytes)
+    }
+
+    /// Patch or relocate every fixup and build the object
+    fn finish(mut self) -> Result<Object> {
+        let mut symbols: Vec<elf::Symbol> = self
+            .named
+            .iter()
+            .map(|name| {
+                let (section, value) = self.labels[name];
+                elf::Symbol {
+                    name: name.clone(),
+                    section: Some(section),
+                    value,
+                    global: self.globals.contains(name),
+                }
+            })
+            .collect();
+        let mut index: HashMap<String, usize> = symbols
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.name.clone(), i))
+            .collect();
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let error = |message: String| format!("line {}: {}", fixup.number, message);
+            let width = match fixup.kind {
+                FixupKind::Pc32 | FixupKind::Branch32 => 4,
+                FixupKind::Abs(width, _) => width as usize,
+            };
+            let place = |name: &str| {
+                self.labels
+                    .get(name)
+                    .copied()
+                    .ok_or_else(|| error(format!("undefined label {}", name)))
+            };
+            let mut value = fixup.value.clone();
+
+            // The distance between two labels in one section is a constant
+            if let Some(minus) = value.minus.take() {
+                let symbol = value
+                    .symbol
+                    .take()
+                    .ok_or_else(|| error(format!("can't negate the address of {}", minus)))?;
+                let ((from, start), (to, end)) = (place(&minus)?, place(&symbol)?);
+                if from != to {
+                    return Err(error(format!(
+                        "{} and {} are in different sections",
+                        symbol, minus
+                    )));
+                }
+                value.addend += end as i64 - start as i64;
+                if fixup.kind == FixupKind::Pc32 || fixup.kind == FixupKind::Branch32 {
+                    return Err(error("bad PC-relative difference".to_string()));
+                }
+                self.patch(&fixup, width, value.addend).map_err(error)?;
+                continue;
+            }
+
+            let symbol = value.symbol.take().expect("fixups refer to symbols");
+            let pc_relative = matches!(fixup.kind, FixupKind::Pc32 | FixupKind::Branch32);
+            let defined = self.labels.get(&symbol).copied();
+            let target = match defined {
+                // A jump or rip-relative reference within the section
+                Some((section, offset)) if pc_relative && section == fixup.section => {
+                    let distance = offset as i64 + value.addend - fixup.offset as i64;
+                    self.patch(&fixup, width, distance).map_err(error)?;
+                    continue;
+                }
+                Some((section, offset)) if !self.globals.contains(&symbol) => {
+                    value.addend += offset as i64;
+                    Target::Section(section)
+                }
+                _ => {
+                    if symbol.starts_with(".L") && defined.is_none() {
+                        return Err(error(format!("undefined label {}", symbol)));
+                    }
+                    let i = *index.entry(symbol.clone()).or_insert_with(|| {
+                        symbols.push(elf::Symbol {
+                            name: symbol.clone(),
+                            section: None,
+                            value: 0,
+                            global: true,
+                        });
+                        symbols.len() - 1
+                    });
+                    Target::Symbol(i)
+                }
+            };
+            let kind = match fixup.kind {
+                FixupKind::Pc32 => elf::R_X86_64_PC32,
+                FixupKind::Branch32 => elf::R_X86_64_PLT32,
+                FixupKind::Abs(8, _) => elf::R_X86_64_64,
+                FixupKind::Abs(4, true) => elf::R_X86_64_32S,
+                FixupKind::Abs(4, false) => elf::R_X86_64_32,
+                FixupKind::Abs(2, _) => elf::R_X86_64_16,
+                FixupKind::Abs(_, _) => elf::R_X86_64_8,
+            };
+            self.sections[fixup.section].relocs.push(elf::Reloc {
+                offset: fixup.offset,
+                kind,
+                target,
+                addend: value.addend,
+            });
+        }
+        // .globl names that are never defined still go in the table
+        let mut undefined: Vec<&String> = self
+            .globals
+            .iter()
+            .filter(|name| !index.contains_key(*name))
+            .collect();
+        undefined.sort();
+        for name in undefined {
+            symbols.push(elf::Symbol {
+                name: name.clone(),
+                section: None,
+                value: 0,
+                global: true,
+            });
+        }
+        Ok(Object {
+            sections: self.sections,
+            symbols,
+        })
+    }
+
+    /// Write a resolved fixup's value into its section
+    fn patch(&mut self, fixup: &Pending, width: usize, n: i64) -> Result<()> {
+        if width < 8 && !fits(n, width) {
+            return Err(format!("{} doesn't fit in {} bytes", n, width));
+        }
+        let at = fixup.offset as usize;
+        let data = &mut self.sections[fixup.section].data;
+        if data.len() < at + width {
+            return Err("data in .bss".to_string());
+        }
+        data[at..at + width].copy_from_slice(&n.to_le_bytes()[..width]);
+        Ok(())
+    }
+}
+
+/// Whether `n` fits in `width` bytes, signed or unsigned
+fn fits(n: i64, width: usize) -> bool {
+    let bits = 8 * width as u32;
+    (-(1i64 << (bits - 1))..(1i64 << bits)).contains(&n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(asm: &Assembler, i: usize) -> &[u8] {
+        &asm.sections[i].data
+    }
+
+    fn assembled(text: &str) -> Assembler {
+        let mut asm = Assembler::new();
+        for (_, line) in expand(text).unwrap() {
+            if let Some(rest) = directive_args(line, &[".equ", ".set"]) {
+                asm.equ(rest).unwrap();
+            }
+        }
+        for (_, line) in expand(text).unwrap() {
+            asm.line(line).unwrap();
+        }
+        asm
+    }
+
+    // ===================
+    // Syntax Tests
+    // ===================
+
+    #[test]
+    fn test_operands() {
+        let mut asm = Assembler::new();
+        asm.equs.insert("EVT_TIMER".to_string(), 3);
+        assert_eq!(
+            asm.operand("QWORD PTR [rbp + -8 - 8]").unwrap(),
+            Operand::Mem(Mem {
+                size: Some(8),
+                base: Some(5),
+                disp: Value::constant(-16),
+                ..Mem::default()
+            })
+        );
+        assert_eq!(
+            asm.operand("[rax+rbx*8]").unwrap(),
+            Operand::Mem(Mem {
+                base: Some(0),
+                index: Some((3, 8)),
+                ..Mem::default()
+            })
+        );
+        assert_eq!(
+            asm.operand("BYTE PTR [rip + _evt_state + EVT_TIMER * 8]")
+                .unwrap(),
+            Operand::Mem(Mem {
+                size: Some(1),
+                rip: true,
+                disp: Value {
+                    symbol: Some("_evt_state".to_string()),
+                    minus: None,
+                    addend: 24,
+                },
+                ..Mem::default()
+            })
+        );
+        assert_eq!(
+            asm.operand("'A' - '0'").unwrap(),
+            Operand::Imm(Value::constant(17))
+        );
+        assert_eq!(
+            asm.operand("~EVT_TIMER").unwrap(),
+            Operand::Imm(Value::constant(!3))
+        );
+        assert!(asm.operand("[rax - rbx]").is_err());
+    }
+
+    #[test]
+    fn test_comments_and_strings() {
+        assert_eq!(
+            strip_comment("    mov al, '#'  # hash"),
+            "    mov al, '#'  "
+        );
+        assert_eq!(strip_comment(r#".ascii "a#\"b" # x"#), r#".ascii "a#\"b" "#);
+        assert_eq!(
+            split_args(r#""a,b", ',', [rax]"#),
+            [r#""a,b""#, "','", "[rax]"]
+        );
+        assert_eq!(parse_string(r#""x\n\0\"\\\101""#).unwrap(), b"x\n\0\"\\A");
+    }
+
+    // ===================
+    // Layout Tests
+    // ===================
+
+    #[test]
+    fn test_labels_and_data() {
+        let asm = assembled(
+            "
+.equ ONE, 1
+.data
+_table: .quad 7
+    .rept 2
+    .byte ONE, 2
+    .endr
+    .asciz \"hi\"
+.bss
+    .p2align 3
+_buf: .skip 4096
+",
+        );
+        assert_eq!(
+            section(&asm, 1),
+            [7, 0, 0, 0, 0, 0, 0, 0, 1, 2, 1, 2, b'h', b'i', 0]
+        );
+        assert_eq!(asm.sections[BSS].size, 4096);
+        assert_eq!(asm.labels["_buf"], (BSS, 0));
+
+        let asm = assembled(".section .rodata\n_str_0: .ascii \"ab\"\n");
+        assert_eq!(section(&asm, RODATA), b"ab");
+        assert_eq!(asm.labels["_str_0"], (RODATA, 0));
+    }
+
+    #[test]
+    fn test_fixups_resolved() {
+        // Local jumps and jump-table distances are patched in place; the
+        // libc call and the .data reference become relocations
+        let object = assembled(
+            "
+.text
+main:
+    jmp .Lend
+.Ltable:
+    .long .Lend - .Ltable
+    call puts
+    lea rax, [rip + _msg]
+.Lend:
+    ret
+.data
+_msg: .ascii \"x\"
+",
+        )
+        .finish()
+        .unwrap();
+        let text = &object.sections[TEXT];
+        assert_eq!(&text.data[..5], [0xE9, 4 + 5 + 7, 0, 0, 0]);
+        assert_eq!(&text.data[5..9], [5 + 7 + 4, 0, 0, 0]);
+        assert_eq!(text.relocs.len(), 2);
+        assert_eq!(text.relocs[0].kind, elf::R_X86_64_PLT32);
+        assert_eq!(text.relocs[1].kind, elf::R_X86_64_PC32);
+        assert_eq!(text.relocs[1].target, Target::Section(1));
+        assert!(
+            object
+                .symbols
+                .iter()
+                .any(|s| s.name == "puts" && s.section.is_none())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(assemble("    frob rax").unwrap_err().starts_with("line 1:"));
+        assert!(assemble("\n    jmp .Lmissing").is_err());
+        assert!(assemble("x:\nx:").is_err());
+        assert!(assemble(".bss\n.byte 1").is_err());
+    }
+}
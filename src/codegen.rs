@@ -2,9 +2,12 @@
 //!
 //! # Architecture Overview
 //!
-//! This module translates the BASIC AST directly to x86-64 assembly using Intel syntax.
-//! There is no intermediate representation - we generate assembly in a single pass over
-//! the AST (after a preliminary pass to collect DATA statements).
+//! This module lowers the BASIC AST to the typed IR in ir.rs, in a single pass over
+//! the AST (after a preliminary pass to collect DATA statements). The backend in
+//! emit.rs turns the IR into x86-64 assembly using Intel syntax. Expressions,
+//! conversions, loads and stores, branches and frames are IR instructions; the
+//! rest (runtime calling sequences, array addressing) is still written as
+//! assembly text in `Asm` instructions.
 //!
 //! The generated code follows the System V AMD64 ABI for compatibility with libc functions.
 //! Output is assembled with the system assembler (`as`) and linked with `cc`.
@@ -147,9 +150,14 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-use crate::abi::{Abi, PlatformAbi};
+use crate::abi::Target;
+use crate::fold;
+use crate::ir::{Const, Frame, GOSUB_STACK_SIZE, Inst, Module, Symbol};
+use crate::lexer::Dialect;
 use crate::parser::*;
-use std::collections::HashMap;
+use crate::types::{self, TypeEnv};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write};
 use std::sync::LazyLock;
 
 /// Simple math functions: BASIC name -> libc function name
@@ -161,6 +169,7 @@ static LIBC_MATH_FNS: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::
         ("ATN", "atan"),
         ("EXP", "exp"),
         ("LOG", "log"),
+        ("LOG10", "log10"),
     ])
 });
 
@@ -173,373 +182,645 @@ static INLINE_MATH_FNS: LazyLock<HashMap<&'static str, &'static str>> = LazyLock
     ])
 });
 
-/// Symbol prefix from platform ABI (underscore on macOS, empty on Linux/Windows)
-const PREFIX: &str = PlatformAbi::SYMBOL_PREFIX;
-
-/// Win64 ABI requires 32 bytes of shadow space before each call
-#[cfg(windows)]
-const WIN64_SHADOW_SPACE: i32 = 32;
-
-/// Win64: stack space for calls with 5 args (shadow + 5th arg + alignment)
-#[cfg(windows)]
-const WIN64_5ARG_STACK_SPACE: i32 = 48;
-
-/// Win64: offset to 5th argument on stack (after shadow space)
-#[cfg(windows)]
-const WIN64_5TH_ARG_OFFSET: i32 = 32;
-
 /// Stack space for temporary values (must be 16-byte aligned)
 const STACK_TEMP_SPACE: i32 = 16;
 
-/// Maximum expression nesting depth before warning (each level uses 16 bytes of stack)
-const MAX_EXPR_DEPTH: u32 = 256;
+/// ASCII character codes
+const ASCII_QUOTE: i64 = 34;
+const ASCII_COMMA: i64 = 44;
+
+/// Callee-saved registers that hold integer FOR counters across the loop
+/// body, as (64-bit, 32-bit, 16-bit) names; nested loops take the next one
+const LOOP_REGS: [(&str, &str, &str); 5] = [
+    ("rbx", "ebx", "bx"),
+    ("r12", "r12d", "r12w"),
+    ("r13", "r13d", "r13w"),
+    ("r14", "r14d", "r14w"),
+    ("r15", "r15d", "r15w"),
+];
+
+/// Array descriptor layout (byte offsets): data pointer, total element count,
+/// number of dimensions, then the element count of each dimension
+const DESC_DATA: i32 = 0;
+const DESC_COUNT: i32 = 8;
+const DESC_NDIMS: i32 = 16;
+const DESC_DIMS: i32 = 24;
+
+/// Dispatch over at least this many integer constants (ON GOTO targets,
+/// SELECT CASE values) goes through a jump table or a binary search
+/// instead of comparing against each in turn
+const MIN_DISPATCH_CASES: usize = 4;
+
+/// Largest array (in bytes) laid out in .bss; bigger ones are malloc'd, so
+/// all static data stays within reach of rip-relative addressing
+const MAX_STATIC_ARRAY: i32 = 1 << 24;
+
+/// The value of an integer constant, possibly negated (a FOR loop's STEP,
+/// a CASE value)
+pub(crate) fn const_int(expr: &Expr) -> Option<i32> {
+    match &expr.kind {
+        ExprKind::Literal(Literal::Integer(n)) => i32::try_from(*n).ok(),
+        ExprKind::Literal(Literal::Typed(n, data_type)) if data_type.is_integer() => {
+            Some(*n as i32)
+        }
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => const_int(operand)?.checked_neg(),
+        _ => None,
+    }
+}
+
+/// The values of a SELECT CASE's cases when each is an integer constant,
+/// save a final CASE ELSE, and there are enough to dispatch on
+fn case_constants(cases: &[(Option<Expr>, Vec<Stmt>)]) -> Option<Vec<i32>> {
+    let valued = match cases.last() {
+        Some((None, _)) => &cases[..cases.len() - 1],
+        _ => cases,
+    };
+    let values = valued
+        .iter()
+        .map(|(value, _)| const_int(value.as_ref()?))
+        .collect::<Option<Vec<_>>>()?;
+    (values.len() >= MIN_DISPATCH_CASES).then_some(values)
+}
 
-/// GOSUB stack size in bytes (64K entries * 8 bytes = 512KB)
-const GOSUB_STACK_SIZE: i32 = 524288;
+/// The element counts of an array's dimensions, when the bounds are
+/// constant expressions and the array is small enough for static storage
+fn constant_dims(dimensions: &[Expr], elem_size: i32) -> Option<Vec<i32>> {
+    let counts = dimensions
+        .iter()
+        .map(|dim| {
+            const_int(&fold::folded(dim))?
+                .checked_add(1)
+                .filter(|&n| n > 0)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let size = counts
+        .iter()
+        .try_fold(elem_size, |size, &n| size.checked_mul(n))?;
+    (size <= MAX_STATIC_ARRAY).then_some(counts)
+}
 
-/// ASCII character codes
-const ASCII_TAB: i64 = 9;
+/// Bytes per array element: strings hold (ptr, len), numbers an 8-byte slot
+fn elem_size(name: &str) -> i32 {
+    match DataType::from_suffix(name) {
+        DataType::String => 16,
+        _ => 8,
+    }
+}
+
+/// Where a variable or array descriptor lives
+#[derive(Clone)]
+enum Storage {
+    Frame(i32),     // [rbp + offset] in the current frame
+    Static(Symbol), // .bss label, for main-program and SHARED names (see emit_data_section)
+}
 
-fn is_string_var(name: &str) -> bool {
-    name.ends_with('$')
+impl Storage {
+    /// Memory operand for the word `disp` bytes from the start (without brackets)
+    fn addr(&self, disp: i32) -> String {
+        match self {
+            Storage::Frame(offset) => format!("rbp + {}", offset + disp),
+            Storage::Static(label) => format!("rip + {} + {}", label, disp),
+        }
+    }
 }
 
 /// Variable storage information
 #[derive(Clone)]
 struct VarInfo {
-    offset: i32,
+    loc: Storage,
     data_type: DataType,
+    addr: Symbol, // loc.addr(0), interned so loads and stores don't format it
 }
 
 /// Metadata for array storage
+#[derive(Clone)]
 struct ArrayInfo {
-    ptr_offset: i32,       // stack offset where array pointer is stored
-    dim_offsets: Vec<i32>, // stack offsets where dimension bounds are stored
+    desc: Storage, // the array descriptor (see gen_dim_array)
+    byref: bool,   // array parameter: the slot holds the caller's descriptor address
+}
+
+/// The variables and arrays visible in the main program or in one procedure.
+/// Procedures don't see the main program's scope; SHARED names are entered
+/// into both scopes with static storage.
+#[derive(Default)]
+struct Scope {
+    vars: HashMap<String, VarInfo>,
+    arrays: HashMap<String, ArrayInfo>,
+}
+
+/// Integer argument to a runtime call (see gen_runtime_call_int)
+enum IntArg<'a> {
+    Expr(&'a Expr), // evaluated and rounded to an integer
+    Imm(i64),       // constant
+    Slot(i32),      // value already stored at [rbp + offset]
+    Addr(&'a Expr), // address of a variable or array element
+    Label(&'a str), // address of a code label
+}
+
+impl<'a> IntArg<'a> {
+    /// An optional argument, with a constant used when it is omitted
+    fn or_imm(expr: &'a Option<Expr>, default: i64) -> Self {
+        expr.as_ref().map_or(IntArg::Imm(default), IntArg::Expr)
+    }
 }
 
 #[derive(Default)]
 pub struct CodeGen {
-    output: String,
-    vars: HashMap<String, VarInfo>, // variable name -> variable info
-    arrays: HashMap<String, ArrayInfo>, // array name -> array metadata
-    stack_offset: i32,              // current stack offset
-    label_counter: u32,             // for generating unique labels
-    string_literals: Vec<String>,   // string constants
-    data_items: Vec<Literal>,       // DATA values
-    current_proc: Option<String>,   // current SUB/FUNCTION name
-    proc_vars: HashMap<String, VarInfo>, // local variables for current proc
-    gosub_used: bool,               // whether GOSUB is used (need return stack)
-    expr_depth: u32,                // current expression nesting depth
+    code: Vec<Inst>,                            // the lowered program
+    frames: Vec<Frame>,                         // frame of each function, by index
+    frame: usize,                               // frame of the function being lowered
+    globals: Scope,                             // main program variables and arrays
+    locals: Scope,                              // current SUB/FUNCTION variables and arrays
+    stack_offset: i32,                          // current stack offset
+    for_slots: Vec<i32>,                        // frame slots finished FOR loops gave back
+    label_counter: u32,                         // for generating unique labels
+    string_literals: Vec<String>,               // string constants
+    string_pool: HashMap<String, usize>,        // string constant -> index, each emitted once
+    data_items: Vec<Literal>,                   // DATA values
+    data_lines: HashMap<u32, usize>,            // line number -> index of the next DATA value
+    current_proc: Option<String>,               // current SUB/FUNCTION name
+    proc_params: HashMap<String, Vec<Param>>,   // parameters of each SUB/FUNCTION
+    lib_procs: HashMap<String, (String, bool)>, // DECLARE ... LIB name -> (C symbol, is FUNCTION)
+    shared_names: HashSet<String>,              // names in DIM SHARED or SHARED, stored statically
+    dim_shared: Vec<Param>,                     // DIM SHARED names, visible in every procedure
+    statics: BTreeMap<String, (i32, i32)>,      // static label -> (bytes before, bytes from label)
+    gosub_used: bool,                           // whether GOSUB is used (need return stack)
+    gosub_base: Option<i32>, // frame slot: GOSUB stack pointer on entry to this procedure
+    events_used: bool,       // whether ON KEY/TIMER/BREAK is used (need event polling)
+    overflow_check: bool,    // raise "Overflow" instead of wrapping INTEGER/LONG results
+    locale: bool,            // take the console's decimal point from the locale
+    target: Target,          // system the program is compiled for
+    dialect: Dialect,        // gives unsuffixed names their type
+    jump_targets: HashSet<u32>, // line numbers that GOTO, GOSUB or ON ... GOTO jump to
+    loop_regs: usize,        // FOR counters currently held in LOOP_REGS
+    saved_regs: usize,       // LOOP_REGS the current function uses (and must preserve)
+    line: Option<u32>,       // numbered line being generated, for runtime error messages
+    source_line: u32,        // source line of the last Source marker
+    symbols: HashSet<Symbol>, // interned labels and variable addresses
+}
+
+impl TypeEnv for CodeGen {
+    fn var_type(&self, name: &str) -> DataType {
+        self.scope()
+            .vars
+            .get(name)
+            .map_or(DataType::of_name(name, self.dialect), |info| info.data_type)
+    }
+
+    fn proc_params(&self, name: &str) -> Option<&[Param]> {
+        self.proc_params.get(name).map(Vec::as_slice)
+    }
+
+    fn is_array(&self, name: &str) -> bool {
+        self.scope().arrays.contains_key(name)
+    }
+
+    fn dialect(&self) -> Dialect {
+        self.dialect
+    }
 }
 
 impl CodeGen {
-    fn emit(&mut self, s: &str) {
-        self.output.push_str(s);
-        self.output.push('\n');
+    /// Create a code generator for `target`; with `overflow_check`, INTEGER
+    /// and LONG results that leave their type's range stop the program with
+    /// "Overflow", and with `locale` the program starts by taking the
+    /// decimal point for the console, STR$ and VAL from its locale
+    pub fn new(target: Target, overflow_check: bool, locale: bool) -> Self {
+        CodeGen {
+            overflow_check,
+            locale,
+            target,
+            ..Default::default()
+        }
+    }
+
+    /// Give unsuffixed names `dialect`'s default type
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Add an instruction the IR doesn't model, as assembly text. Takes
+    /// `format_args!` so the text is written once, straight into its buffer.
+    fn emit(&mut self, s: impl fmt::Display) {
+        let mut text = String::new();
+        write!(text, "{}", s).expect("writing to a String");
+        let indent = text.len() - text.trim_start().len();
+        text.drain(..indent);
+        self.code.push(Inst::Asm(text));
+    }
+
+    /// The shared copy of a label or operand
+    fn intern(&mut self, text: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(text) {
+            return symbol.clone();
+        }
+        let symbol = Symbol::from(text);
+        self.symbols.insert(symbol.clone());
+        symbol
+    }
+
+    /// Storage information for a variable at `loc`
+    fn var_info(&mut self, loc: Storage, data_type: DataType) -> VarInfo {
+        let addr = self.intern(&loc.addr(0));
+        VarInfo {
+            loc,
+            data_type,
+            addr,
+        }
+    }
+
+    fn push(&mut self, inst: Inst) {
+        self.code.push(inst);
+    }
+
+    /// Call a runtime routine or procedure
+    fn call(&mut self, func: &str) {
+        self.push(Inst::Call(func.to_string()));
+    }
+
+    fn jump(&mut self, label: &str) {
+        self.push(Inst::Jump(label.to_string()));
+    }
+
+    /// Enter a function; its frame is filled in by finish_frame once the
+    /// body's locals are known
+    fn enter(&mut self, label: String) {
+        self.frame = self.frames.len();
+        self.frames.push(Frame::default());
+        self.push(Inst::Enter {
+            label,
+            frame: self.frame,
+        });
+        self.saved_regs = 0;
+    }
+
+    /// Return from the current function, restoring the registers it saved
+    fn emit_return(&mut self) {
+        self.push(Inst::Return { frame: self.frame });
+    }
+
+    /// Record the space the current function's frame used and the slots
+    /// where it saves the LOOP_REGS it used
+    fn finish_frame(&mut self) {
+        let mut saved = Vec::new();
+        for (reg, _, _) in &LOOP_REGS[..self.saved_regs] {
+            self.stack_offset -= 8;
+            saved.push((*reg, self.stack_offset));
+        }
+        // We use 16-byte sub/add for all temporaries in expression evaluation,
+        // so the frame only needs rounding up to a multiple of 16
+        let size = (-self.stack_offset + 15) & !15;
+        self.frames[self.frame] = Frame { size, saved };
     }
 
     /// Get the integer argument register for a given argument position (0-based)
-    fn arg_reg(n: usize) -> &'static str {
-        PlatformAbi::INT_ARG_REGS
+    fn arg_reg(&self, n: usize) -> &'static str {
+        self.target
+            .int_arg_regs()
             .get(n)
             .expect("argument index out of bounds")
     }
 
     /// Emit a mov instruction to set up an integer argument from a register
     fn emit_arg_reg(&mut self, arg_n: usize, src_reg: &str) {
-        let dst = Self::arg_reg(arg_n);
+        let dst = self.arg_reg(arg_n);
         if dst != src_reg {
-            self.emit(&format!("    mov {}, {}", dst, src_reg));
+            self.emit(format_args!("    mov {}, {}", dst, src_reg));
         }
     }
 
     /// Emit a mov instruction to set up an integer argument from an immediate
     fn emit_arg_imm(&mut self, arg_n: usize, value: i64) {
-        let dst = Self::arg_reg(arg_n);
-        self.emit(&format!("    mov {}, {}", dst, value));
+        let dst = self.arg_reg(arg_n);
+        self.emit(format_args!("    mov {}, {}", dst, value));
     }
 
     /// Emit a lea instruction to set up an integer argument from a memory reference
     fn emit_arg_lea(&mut self, arg_n: usize, mem: &str) {
-        let dst = Self::arg_reg(arg_n);
-        self.emit(&format!("    lea {}, {}", dst, mem));
+        let dst = self.arg_reg(arg_n);
+        self.emit(format_args!("    lea {}, {}", dst, mem));
     }
 
     /// Call a libc function with proper shadow space on Win64
     fn emit_call_libc(&mut self, func: &str) {
-        #[cfg(windows)]
-        {
-            self.emit(&format!("    sub rsp, {}", WIN64_SHADOW_SPACE));
-            self.emit(&format!("    call {}{}", PREFIX, func));
-            self.emit(&format!("    add rsp, {}", WIN64_SHADOW_SPACE));
+        self.push(Inst::CallLibc(func.to_string()));
+    }
+
+    /// With overflow checking, stop with "Overflow" unless the integer
+    /// result in eax fits `data_type` (Long arithmetic must be followed
+    /// directly by this)
+    fn emit_overflow_check(&mut self, data_type: DataType) {
+        if self.overflow_check && data_type.is_integer() {
+            self.push(Inst::CheckOverflow(data_type));
         }
-        #[cfg(not(windows))]
-        {
-            self.emit(&format!("    call {}{}", PREFIX, func));
+    }
+
+    fn emit_label(&mut self, label: &str) {
+        self.push(Inst::Label(label.to_string()));
+    }
+
+    /// Assembly label for a GOTO/GOSUB target in the main program
+    fn main_label(target: &GotoTarget) -> String {
+        match target {
+            GotoTarget::Line(n) => format!("_line_{}", n),
+            GotoTarget::Label(s) => format!("_label_{}", s),
         }
     }
 
-    /// Emit type-specific instruction for binary operations
-    fn emit_typed(
-        &mut self,
-        work_type: DataType,
-        int_instr: &str,
-        single_instr: &str,
-        double_instr: &str,
-    ) {
-        match work_type {
-            DataType::Integer | DataType::Long => self.emit(int_instr),
-            DataType::Single => self.emit(single_instr),
-            _ => self.emit(double_instr),
-        }
-    }
-
-    /// Convert float operands to integers (truncate). Used for IntDiv, Mod, logical ops.
-    fn emit_cvt_float_to_int(&mut self, work_type: DataType) {
-        if !work_type.is_integer() {
-            self.emit_typed(
-                work_type,
-                "",
-                "    cvttss2si eax, xmm0",
-                "    cvttsd2si eax, xmm0",
-            );
-            self.emit_typed(
-                work_type,
-                "",
-                "    cvttss2si ecx, xmm1",
-                "    cvttsd2si ecx, xmm1",
-            );
-        }
-    }
-
-    /// Convert integer/single operands to double. Used for Div, Pow.
-    fn emit_cvt_to_double(&mut self, work_type: DataType) {
-        match work_type {
-            DataType::Integer | DataType::Long => {
-                self.emit("    cvtsi2sd xmm0, eax");
-                self.emit("    cvtsi2sd xmm1, ecx");
-            }
-            DataType::Single => {
-                self.emit("    cvtss2sd xmm0, xmm0");
-                self.emit("    cvtss2sd xmm1, xmm1");
-            }
-            _ => {}
+    /// Assembly label for a GOTO/GOSUB target. Inside a SUB or FUNCTION, line
+    /// numbers belong to that procedure, so the same number may appear in
+    /// several procedures and in the main program
+    fn target_label(&self, target: &GotoTarget) -> String {
+        match &self.current_proc {
+            Some(name) => format!("{}{}", Self::proc_label(name), Self::main_label(target)),
+            None => Self::main_label(target),
         }
     }
 
-    fn emit_label(&mut self, label: &str) {
-        self.output.push_str(label);
-        self.output.push_str(":\n");
+    /// Assembly label for a SUB/FUNCTION; type suffixes aren't valid in symbols
+    fn proc_label(name: &str) -> String {
+        Self::mangle("_proc_", name)
+    }
+
+    /// Assembler-safe symbol for a BASIC name: the type suffix becomes a tag
+    fn mangle(prefix: &str, name: &str) -> String {
+        let base = name.trim_end_matches(['%', '&', '!', '#', '$']);
+        let suffix = match name.chars().last() {
+            Some('%') => "_int",
+            Some('&') => "_lng",
+            Some('!') => "_sng",
+            Some('#') => "_dbl",
+            Some('$') => "_str",
+            _ => "",
+        };
+        format!("{}{}{}", prefix, base, suffix)
+    }
+
+    /// Runtime encoding of a trap state (see event.s)
+    fn trap_state(state: TrapState) -> i64 {
+        match state {
+            TrapState::Off => 0,
+            TrapState::On => 1,
+            TrapState::Stop => 2,
+        }
+    }
+
+    /// Push a return address onto the GOSUB stack (clobbers rax, rcx)
+    fn emit_gosub_push(&mut self, ret_label: &str) {
+        // Check for stack overflow before push
+        self.emit("    mov rcx, QWORD PTR [rip + _gosub_sp]");
+        self.emit("    sub rcx, 8");
+        self.emit("    lea rax, [rip + _gosub_stack]");
+        self.emit("    cmp rcx, rax");
+        self.emit("    jb _rt_gosub_overflow");
+        // Push return address to GOSUB stack
+        self.emit(format_args!("    lea rax, [rip + {}]", ret_label));
+        self.emit("    mov QWORD PTR [rcx], rax");
+        self.emit("    mov QWORD PTR [rip + _gosub_sp], rcx");
+    }
+
+    /// Poll for trapped events (ON KEY, ON TIMER) and run a pending handler as a GOSUB.
+    /// Emitted at statement boundaries and loop tops of the main program.
+    fn emit_event_poll(&mut self) {
+        if !self.events_used || self.current_proc.is_some() {
+            return;
+        }
+        let ret_label = self.new_label("event_ret");
+        let done_label = self.new_label("event_done");
+        self.call("_rt_event_poll");
+        self.emit("    test rax, rax");
+        self.emit(format_args!("    jz {}", done_label));
+        self.emit("    mov rdx, rax");
+        self.emit_gosub_push(&ret_label);
+        self.emit("    jmp rdx");
+        self.emit_label(&ret_label);
+        self.call("_rt_event_return");
+        self.emit_line_update();
+        self.emit_label(&done_label);
+    }
+
+    /// Record the current line number in `_rt_cur_line` for runtime error
+    /// messages: at each numbered line, and again when a GOSUB or procedure
+    /// call returns to it
+    fn emit_line_update(&mut self) {
+        if let Some(n) = self.line {
+            self.emit(format_args!(
+                "    mov QWORD PTR [rip + _rt_cur_line], {}",
+                n
+            ));
+        }
     }
 
+    /// A unique local label; on a numbered line, the label names it, so the
+    /// assembly reads `.Lelse_4_line20`
     fn new_label(&mut self, prefix: &str) -> String {
-        let label = format!(".L{}_{}", prefix, self.label_counter);
+        let label = match self.line {
+            Some(n) => format!(".L{}_{}_line{}", prefix, self.label_counter, n),
+            None => format!(".L{}_{}", prefix, self.label_counter),
+        };
         self.label_counter += 1;
         label
     }
 
+    /// The index of a string constant; identical ones share a `_str_N`
     fn add_string_literal(&mut self, s: &str) -> usize {
+        if let Some(&idx) = self.string_pool.get(s) {
+            return idx;
+        }
         let idx = self.string_literals.len();
         self.string_literals.push(s.to_string());
+        self.string_pool.insert(s.to_string(), idx);
         idx
     }
 
-    /// Get variable info, allocating if necessary
-    fn get_var_info(&mut self, name: &str) -> VarInfo {
+    /// The scope names resolve in: the current procedure's, else the main program's
+    fn scope(&self) -> &Scope {
         if self.current_proc.is_some() {
-            // Check local variables first
-            if let Some(info) = self.proc_vars.get(name) {
-                return info.clone();
-            }
+            &self.locals
+        } else {
+            &self.globals
         }
+    }
 
-        if let Some(info) = self.vars.get(name) {
-            return info.clone();
+    fn scope_mut(&mut self) -> &mut Scope {
+        if self.current_proc.is_some() {
+            &mut self.locals
+        } else {
+            &mut self.globals
         }
+    }
 
-        // Allocate new variable - determine type from suffix
-        let data_type = DataType::from_suffix(name);
-        self.stack_offset -= 8; // All types use 8 bytes for alignment
-        let offset = self.stack_offset;
-
-        let info = VarInfo { offset, data_type };
+    /// Get variable info, allocating if necessary
+    fn get_var_info(&mut self, name: &str) -> VarInfo {
+        if let Some(info) = self.scope().vars.get(name) {
+            return info.clone();
+        }
 
-        if self.current_proc.is_some() {
-            self.proc_vars.insert(name.to_string(), info.clone());
+        // Allocate new variable - determine type from suffix. Main-program
+        // variables live in static storage, where procedures can reach the
+        // SHARED ones; the main frame only holds temporaries
+        let data_type = DataType::of_name(name, self.dialect);
+        let loc = if self.current_proc.is_none() {
+            self.static_var(name, data_type)
         } else {
-            self.vars.insert(name.to_string(), info.clone());
-        }
+            Storage::Frame(self.alloc_var(data_type))
+        };
 
+        let info = self.var_info(loc, data_type);
+        self.scope_mut().vars.insert(name.to_string(), info.clone());
         info
     }
 
-    /// Get just the stack offset for a variable (convenience method)
-    fn get_var_offset(&mut self, name: &str) -> i32 {
-        self.get_var_info(name).offset
+    /// Static storage for a main-program or SHARED scalar. Strings keep the
+    /// length 8 bytes below the pointer, as in the frame
+    fn static_var(&mut self, name: &str, data_type: DataType) -> Storage {
+        let label = self.intern(&Self::mangle("_var_", name));
+        let before = if data_type == DataType::String { 8 } else { 0 };
+        self.statics.insert(label.to_string(), (before, 8));
+        Storage::Static(label)
     }
 
-    /// Determine the result type of an expression
-    fn expr_type(&self, expr: &Expr) -> DataType {
-        match expr {
-            Expr::Literal(lit) => match lit {
-                Literal::Integer(_) => DataType::Long, // Integer literals are Long
-                Literal::Float(_) => DataType::Double,
-                Literal::String(_) => DataType::String,
-            },
-            Expr::Variable(name) => DataType::from_suffix(name),
-            Expr::ArrayAccess { name, .. } => DataType::from_suffix(name),
-            Expr::FnCall { name, .. } => self.fn_return_type(name),
-            Expr::Unary { operand, .. } => self.expr_type(operand),
-            Expr::Binary { left, right, op } => {
-                let lt = self.expr_type(left);
-                let rt = self.expr_type(right);
-                self.promote_types(lt, rt, *op)
-            }
-        }
+    /// Static storage for a main-program or SHARED array's descriptor with up
+    /// to `ndims` dimensions
+    fn static_array(&mut self, name: &str, ndims: i32) -> Storage {
+        let label = self.intern(&Self::mangle("_arr_", name));
+        let size = self.statics.get(&*label).map_or(0, |&(_, size)| size);
+        self.statics
+            .insert(label.to_string(), (0, size.max(DESC_DIMS + 8 * ndims)));
+        Storage::Static(label)
     }
 
-    /// Get the return type of a function (built-in or user-defined)
-    fn fn_return_type(&self, name: &str) -> DataType {
-        // Built-in functions that return strings
-        let upper = name.to_uppercase();
-        if upper.ends_with('$') {
-            return DataType::String;
-        }
-        // Built-in functions that return integers
-        match upper.as_str() {
-            "LEN" | "ASC" | "INSTR" | "CINT" | "CLNG" => DataType::Long,
-            // Most built-ins and user functions: check suffix, default to Double
-            _ => DataType::from_suffix(name),
+    /// Enter SHARED names into the current procedure's scope
+    fn share(&mut self, names: &[Param]) {
+        for param in names {
+            if param.is_array {
+                let desc = self.static_array(&param.name, 1);
+                let info = ArrayInfo { desc, byref: false };
+                self.locals.arrays.insert(param.name.clone(), info);
+            } else {
+                let data_type = DataType::of_name(&param.name, self.dialect);
+                let loc = self.static_var(&param.name, data_type);
+                let info = self.var_info(loc, data_type);
+                self.locals.vars.insert(param.name.clone(), info);
+            }
         }
     }
 
-    /// Promote two types to a common type for binary operations
-    fn promote_types(&self, left: DataType, right: DataType, op: BinaryOp) -> DataType {
-        // Comparison operators always return Integer (0 or -1 for boolean)
-        if matches!(
-            op,
-            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge
-        ) {
-            return DataType::Long; // Boolean result as Long
-        }
-
-        // Division (/) always produces Double per GW-BASIC
-        if op == BinaryOp::Div {
-            return DataType::Double;
-        }
-
-        // Integer division (\) always produces Long
-        if op == BinaryOp::IntDiv {
-            return DataType::Long;
-        }
-
-        // MOD produces integer type
-        if op == BinaryOp::Mod {
-            return DataType::Long;
-        }
-
-        // Power (^) always produces Double (uses libm pow())
-        if op == BinaryOp::Pow {
-            return DataType::Double;
-        }
-
-        // String concatenation
-        if left == DataType::String && right == DataType::String {
-            return DataType::String;
+    /// Allocate stack space for a variable. Numbers use 8 bytes for alignment;
+    /// strings use 16, with the pointer at the returned offset and the length
+    /// 8 bytes below it.
+    fn alloc_var(&mut self, data_type: DataType) -> i32 {
+        self.stack_offset -= 8;
+        let offset = self.stack_offset;
+        if data_type == DataType::String {
+            self.stack_offset -= 8;
         }
+        offset
+    }
 
-        // Numeric promotion: Integer < Long < Single < Double
-        match (left, right) {
-            (DataType::Double, _) | (_, DataType::Double) => DataType::Double,
-            (DataType::Single, _) | (_, DataType::Single) => DataType::Single,
-            (DataType::Long, _) | (_, DataType::Long) => DataType::Long,
-            _ => DataType::Integer,
-        }
+    /// Determine the result type of an expression. Programs whose types
+    /// don't fit together were already rejected by the checker.
+    fn expr_type(&self, expr: &Expr) -> DataType {
+        types::infer(self, expr).expect("expression types are checked before codegen")
     }
 
     /// Generate code to coerce a value from one type to another.
     /// Convention: integers in eax, floats in xmm0
     fn gen_coercion(&mut self, from: DataType, to: DataType) {
-        if from == to {
-            return;
+        if from != to {
+            self.push(Inst::Convert {
+                from,
+                to,
+                checked: self.overflow_check,
+            });
         }
+    }
 
-        match (from, to) {
-            // Integer to Long (sign extension, but both in eax so just use movsxd conceptually)
-            (DataType::Integer, DataType::Long) => {
-                self.emit("    movsx eax, ax"); // sign-extend 16-bit to 32-bit
-            }
-            // Long to Integer (truncation - just use lower 16 bits)
-            (DataType::Long, DataType::Integer) => {
-                // No-op in eax, value is truncated when stored
-            }
-            // Integer/Long to Single
-            (DataType::Integer | DataType::Long, DataType::Single) => {
-                self.emit("    cvtsi2ss xmm0, eax");
-            }
-            // Integer/Long to Double
-            (DataType::Integer | DataType::Long, DataType::Double) => {
-                self.emit("    cvtsi2sd xmm0, eax");
-            }
-            // Single to Double
-            (DataType::Single, DataType::Double) => {
-                self.emit("    cvtss2sd xmm0, xmm0");
-            }
-            // Double to Single
-            (DataType::Double, DataType::Single) => {
-                self.emit("    cvtsd2ss xmm0, xmm0");
-            }
-            // Single to Integer/Long (truncate)
-            (DataType::Single, DataType::Integer | DataType::Long) => {
-                self.emit("    cvttss2si eax, xmm0");
-            }
-            // Double to Integer/Long (truncate)
-            (DataType::Double, DataType::Integer | DataType::Long) => {
-                self.emit("    cvttsd2si eax, xmm0");
-            }
-            // String conversions are not supported implicitly
-            (DataType::String, _) | (_, DataType::String) => {
-                panic!("Cannot implicitly convert to/from String");
+    /// Load a value of the given type from memory (`addr` without brackets):
+    /// integers into eax, floats into xmm0, strings into rax/rdx
+    fn emit_load(&mut self, data_type: DataType, addr: impl Into<Symbol>) {
+        self.push(Inst::Load {
+            ty: data_type,
+            addr: addr.into(),
+        });
+    }
+
+    /// Store eax, xmm0 or rax/rdx as a value of the given type (`addr`
+    /// without brackets)
+    fn emit_store(&mut self, data_type: DataType, addr: impl Into<Symbol>) {
+        self.push(Inst::Store {
+            ty: data_type,
+            addr: addr.into(),
+        });
+    }
+
+    /// Store a Double result (from INPUT, READ and friends) into a numeric variable
+    /// Store the value `value` generates in an INPUT or READ target: a
+    /// variable or an array element
+    fn gen_assign(&mut self, target: &Expr, value: impl FnOnce(&mut Self) -> DataType) {
+        match &target.kind {
+            ExprKind::Variable(name) => {
+                let val_type = value(self);
+                let info = self.get_var_info(name);
+                if info.data_type != DataType::String {
+                    self.gen_coercion(val_type, info.data_type);
+                }
+                self.emit_store(info.data_type, info.addr.clone());
             }
-            // Same type - no conversion needed (shouldn't reach here due to early return)
-            _ => {}
+            ExprKind::ArrayAccess { name, indices } => self.gen_array_store(name, indices, value),
+            _ => panic!("INPUT and READ need a variable or array element"),
         }
     }
 
-    pub fn generate(&mut self, program: &Program) -> String {
+    /// Lower a checked program to IR
+    pub fn generate(&mut self, program: &Program) -> Module {
+        self.lower(program, true)
+    }
+
+    /// Lower a library module - one holding only procedures, for linking
+    /// with a main program compiled separately (see modules.rs)
+    pub fn generate_library(&mut self, program: &Program) -> Module {
+        self.lower(program, false)
+    }
+
+    /// Lower the procedures, and the main program when `main` is set. The
+    /// procedures are exported, so other modules can call them.
+    fn lower(&mut self, program: &Program, main: bool) -> Module {
         // First pass: collect DATA statements and check for GOSUB
         for stmt in &program.statements {
             self.preprocess(stmt);
         }
 
-        // Emit assembly header
-        self.emit(".intel_syntax noprefix");
-        self.emit(".text");
-        let p = PREFIX;
-        self.emit(&format!(".globl {}main", p));
-        self.emit("");
-
         // Generate procedures first
+        let mut exports = Vec::new();
         for stmt in &program.statements {
-            if let Stmt::Sub { name, params, body } = stmt {
+            if let StmtKind::Sub { name, params, body } = &stmt.kind {
+                self.mark_source(stmt);
                 self.gen_procedure(name, params, body, false);
-            } else if let Stmt::Function { name, params, body } = stmt {
+                exports.push(Self::proc_label(name));
+            } else if let StmtKind::Function { name, params, body } = &stmt.kind {
+                self.mark_source(stmt);
                 self.gen_procedure(name, params, body, true);
+                exports.push(Self::proc_label(name));
             }
         }
+        if !main {
+            return self.finish_module(exports);
+        }
 
         // Generate main
-        self.emit_label(&format!("{}main", p));
-        self.emit("    push rbp");
-        self.emit("    mov rbp, rsp");
-
-        // Reserve stack space (will patch later)
-        self.emit("    sub rsp, 0         # STACK_RESERVE");
+        let main_label = format!("{}main", self.target.symbol_prefix());
+        exports.insert(0, main_label.clone());
+        self.enter(main_label);
 
         // Initialize GOSUB return stack if needed
         if self.gosub_used {
             self.emit("    # Initialize GOSUB return stack");
-            self.emit(&format!(
+            self.emit(format_args!(
                 "    lea rax, [rip + _gosub_stack + {}]",
                 GOSUB_STACK_SIZE
             )); // Point to end (stack grows down)
@@ -547,143 +828,213 @@ impl CodeGen {
         }
 
         // Windows: Initialize console handles for Win32 API
-        #[cfg(windows)]
-        {
+        if self.target == Target::Windows {
             self.emit("    # Initialize Windows console handles");
-            self.emit("    call _rt_init_console");
-            self.emit("    call _rt_init_input");
+            self.call("_rt_init_console");
+            self.call("_rt_init_input");
+        }
+
+        // Ctrl-C ends the program through the runtime with "Break", which
+        // flushes the output, or runs ON BREAK
+        self.call("_rt_init_break");
+
+        if self.locale {
+            self.call("_rt_init_locale");
         }
 
         // Generate main body
         for stmt in &program.statements {
-            match stmt {
-                Stmt::Sub { .. } | Stmt::Function { .. } => {}
+            match &stmt.kind {
+                StmtKind::Sub { .. } | StmtKind::Function { .. } => {}
                 _ => self.gen_stmt(stmt),
             }
         }
 
         // Exit
         self.emit("    xor eax, eax");
-        self.emit("    leave");
-        self.emit("    ret");
+        self.emit_return();
         self.emit("");
 
-        // Patch stack reserve
-        // System V AMD64 ABI stack alignment rules:
-        // - On function entry (after call pushed return addr): rsp % 16 == 8
-        // - After push rbp: rsp % 16 == 0
-        // - Before any call: rsp % 16 == 0
-        //
-        // Since we use 16-byte sub/add for all temporaries in expression evaluation,
-        // we just need sub rsp, N where N is a multiple of 16 to maintain alignment.
-        let stack_needed = -self.stack_offset;
-        let stack_size = (stack_needed + 15) & !15; // Round up to multiple of 16
-        let old = "    sub rsp, 0         # STACK_RESERVE";
-        let new = format!("    sub rsp, {}        # STACK_RESERVE", stack_size);
-        self.output = self.output.replace(old, &new);
-
-        // Emit data section
-        self.emit_data_section();
+        self.finish_frame();
+        self.finish_module(exports)
+    }
 
-        self.output.clone()
+    fn finish_module(&mut self, exports: Vec<String>) -> Module {
+        self.string_pool.clear();
+        Module {
+            code: std::mem::take(&mut self.code),
+            frames: std::mem::take(&mut self.frames),
+            strings: std::mem::take(&mut self.string_literals),
+            data: std::mem::take(&mut self.data_items),
+            statics: std::mem::take(&mut self.statics),
+            gosub_stack: self.gosub_used,
+            exports,
+        }
     }
 
     /// Preprocess statement: collect DATA items and check for GOSUB usage
     fn preprocess(&mut self, stmt: &Stmt) {
-        match stmt {
-            Stmt::Data(values) => self.data_items.extend(values.clone()),
-            Stmt::Gosub(_) => self.gosub_used = true,
+        match &stmt.kind {
+            StmtKind::Data(values) => self.data_items.extend(values.clone()),
+            StmtKind::Label(n) => {
+                // RESTORE n continues from the first DATA at or after line n
+                self.data_lines.entry(*n).or_insert(self.data_items.len());
+            }
+            StmtKind::Goto(GotoTarget::Line(n)) => {
+                self.jump_targets.insert(*n);
+            }
+            StmtKind::Gosub(target) => {
+                self.gosub_used = true;
+                if let GotoTarget::Line(n) = target {
+                    self.jump_targets.insert(*n);
+                }
+            }
+            // A RETURN with no GOSUB anywhere still checks the (empty) stack
+            StmtKind::Return => self.gosub_used = true,
+            StmtKind::OnGoto { targets, .. } => {
+                self.jump_targets
+                    .extend(targets.iter().filter_map(|target| match target {
+                        GotoTarget::Line(n) => Some(*n),
+                        GotoTarget::Label(_) => None,
+                    }));
+            }
+            StmtKind::OnKey { .. }
+            | StmtKind::OnTimer { .. }
+            | StmtKind::OnBreak { .. }
+            | StmtKind::OnMouse { .. } => {
+                // Handlers run as GOSUBs from the poll points
+                self.gosub_used = true;
+                self.events_used = true;
+            }
+            // MOUSE ON without a handler still switches the terminal, which
+            // Ctrl-C must put back
+            StmtKind::MouseTrap(_) => {
+                self.gosub_used = true;
+                self.events_used = true;
+            }
+            StmtKind::Sub { name, params, .. } | StmtKind::Function { name, params, .. } => {
+                self.proc_params.insert(name.clone(), params.clone());
+            }
+            StmtKind::Declare {
+                name,
+                params,
+                is_function,
+                foreign,
+            } => {
+                if let Some(foreign) = foreign {
+                    self.lib_procs
+                        .insert(name.clone(), (foreign.symbol.clone(), *is_function));
+                }
+                // The definition, if it's in this module, matches
+                self.proc_params
+                    .entry(name.clone())
+                    .or_insert_with(|| params.clone());
+            }
+            StmtKind::Dim {
+                arrays,
+                shared: true,
+            } => {
+                for arr in arrays {
+                    self.shared_names.insert(arr.name.clone());
+                    self.dim_shared.push(Param {
+                        name: arr.name.clone(),
+                        data_type: DataType::of_name(&arr.name, self.dialect),
+                        is_array: !arr.dimensions.is_empty(),
+                    });
+                }
+            }
+            StmtKind::Shared(names) => {
+                self.shared_names
+                    .extend(names.iter().map(|p| p.name.clone()));
+            }
             _ => {}
         }
         // Recurse into nested statements
-        let bodies: Vec<&[Stmt]> = match stmt {
-            Stmt::If {
-                then_branch,
-                else_branch,
-                ..
-            } => {
-                let mut v = vec![then_branch.as_slice()];
-                if let Some(eb) = else_branch {
-                    v.push(eb.as_slice());
-                }
-                v
-            }
-            Stmt::For { body, .. }
-            | Stmt::While { body, .. }
-            | Stmt::DoLoop { body, .. }
-            | Stmt::Sub { body, .. }
-            | Stmt::Function { body, .. } => vec![body.as_slice()],
-            _ => vec![],
-        };
-        for body in bodies {
+        for body in stmt.kind.bodies() {
             for s in body {
                 self.preprocess(s);
             }
         }
     }
 
-    fn gen_procedure(&mut self, name: &str, params: &[String], body: &[Stmt], is_function: bool) {
+    fn gen_procedure(&mut self, name: &str, params: &[Param], body: &[Stmt], is_function: bool) {
         self.current_proc = Some(name.to_string());
-        self.proc_vars.clear();
+        let old_line = self.line.take();
+        self.locals = Scope::default();
+        let dim_shared = self.dim_shared.clone();
+        self.share(&dim_shared);
         let old_stack_offset = self.stack_offset;
         self.stack_offset = 0;
+        let old_for_slots = std::mem::take(&mut self.for_slots);
 
-        // Procedure label
-        self.emit_label(&format!("_proc_{}", name));
-        self.emit("    push rbp");
-        self.emit("    mov rbp, rsp");
+        // Procedure label and frame (sized once the body is lowered)
+        self.enter(Self::proc_label(name));
 
-        // Reserve stack space (will patch later with actual size)
-        let placeholder = format!("    sub rsp, 0         # STACK_RESERVE_PROC_{}", name);
-        self.emit(&placeholder);
+        // GOSUBs in the body use the shared return stack; remember where this
+        // call's entries start so RETURN can't pop the caller's and leftovers
+        // are dropped on exit
+        if self.gosub_used {
+            self.stack_offset -= 8;
+            let base = self.stack_offset;
+            self.gosub_base = Some(base);
+            self.emit("    mov rax, QWORD PTR [rip + _gosub_sp]");
+            self.emit(format_args!("    mov QWORD PTR [rbp + {}], rax", base));
+        }
 
-        // Parameters are passed in registers (per platform ABI)
-        // First N params in registers, rest on stack at [rbp+16], [rbp+24], etc.
-        // Store them all in our local stack space
-        let int_regs = PlatformAbi::INT_ARG_REGS;
+        // Parameters are passed in 8-byte slots (see gen_call): first N slots in
+        // registers (per platform ABI), rest on stack at [rbp+16], [rbp+24], etc.
+        // Strings take two slots (ptr, len). Store them all in our local stack space
+        let int_regs = self.target.int_arg_regs();
         let max_reg_args = int_regs.len();
-        for (i, param) in params.iter().enumerate() {
-            self.stack_offset -= 8;
-            let data_type = DataType::from_suffix(param);
-            self.proc_vars.insert(
-                param.clone(),
-                VarInfo {
-                    offset: self.stack_offset,
-                    data_type,
-                },
-            );
-            if i < max_reg_args {
-                // Parameter in register - store to our local stack
-                self.emit(&format!(
-                    "    mov QWORD PTR [rbp + {}], {}",
-                    self.stack_offset, int_regs[i]
-                ));
+        let mut slot = 0;
+        for param in params {
+            let data_type = param.data_type;
+            let words;
+            let offset;
+            if param.is_array {
+                // Array parameter: the caller's descriptor address
+                self.stack_offset -= 8;
+                offset = self.stack_offset;
+                let info = ArrayInfo {
+                    desc: Storage::Frame(offset),
+                    byref: true,
+                };
+                self.locals.arrays.insert(param.name.clone(), info);
+                words = 1;
             } else {
-                // Parameter on call stack - copy to our local stack
-                // Overflow args are at [rbp+16], [rbp+24], etc. (after saved rbp and ret addr)
-                let stack_arg_offset = 16 + (i - max_reg_args) * 8;
-                self.emit(&format!(
-                    "    mov rax, QWORD PTR [rbp + {}]",
-                    stack_arg_offset
-                ));
-                self.emit(&format!(
-                    "    mov QWORD PTR [rbp + {}], rax",
-                    self.stack_offset
-                ));
+                offset = self.alloc_var(data_type);
+                let info = self.var_info(Storage::Frame(offset), data_type);
+                self.locals.vars.insert(param.name.clone(), info);
+                words = if data_type == DataType::String { 2 } else { 1 };
+            }
+            for word in 0..words {
+                let dest = offset - 8 * word;
+                if slot < max_reg_args {
+                    // Parameter in register - store to our local stack
+                    self.emit(format_args!(
+                        "    mov QWORD PTR [rbp + {}], {}",
+                        dest, int_regs[slot]
+                    ));
+                } else {
+                    // Parameter on call stack - copy to our local stack
+                    // Overflow args are at [rbp+16], [rbp+24], etc. (after saved rbp and ret addr)
+                    let stack_arg_offset = 16 + (slot - max_reg_args) * 8;
+                    self.emit(format_args!(
+                        "    mov rax, QWORD PTR [rbp + {}]",
+                        stack_arg_offset
+                    ));
+                    self.emit(format_args!("    mov QWORD PTR [rbp + {}], rax", dest));
+                }
+                slot += 1;
             }
         }
 
-        // If function, allocate return value slot
+        // If function, allocate return value slot (type from the name's suffix)
         if is_function {
-            self.stack_offset -= 8;
-            let data_type = DataType::from_suffix(name);
-            self.proc_vars.insert(
-                name.to_string(),
-                VarInfo {
-                    offset: self.stack_offset,
-                    data_type,
-                },
-            );
+            let data_type = DataType::of_name(name, self.dialect);
+            let offset = self.alloc_var(data_type);
+            let info = self.var_info(Storage::Frame(offset), data_type);
+            self.locals.vars.insert(name.to_string(), info);
         }
 
         // Generate body
@@ -693,63 +1044,65 @@ impl CodeGen {
 
         // Return - load return value into appropriate register based on type
         if is_function {
-            let ret_info = &self.proc_vars[name];
-            let offset = ret_info.offset;
-            let data_type = ret_info.data_type;
-            match data_type {
-                DataType::Integer => {
-                    self.emit(&format!("    movsx eax, WORD PTR [rbp + {}]", offset));
-                }
-                DataType::Long => {
-                    self.emit(&format!("    mov eax, DWORD PTR [rbp + {}]", offset));
-                }
-                DataType::Single => {
-                    self.emit(&format!("    movss xmm0, DWORD PTR [rbp + {}]", offset));
-                }
-                DataType::Double => {
-                    self.emit(&format!("    movsd xmm0, QWORD PTR [rbp + {}]", offset));
-                }
-                DataType::String => {
-                    // Load string (ptr, len) into rax, rdx
-                    self.emit(&format!("    mov rax, QWORD PTR [rbp + {}]", offset));
-                    self.emit(&format!("    mov rdx, QWORD PTR [rbp + {}]", offset - 8));
-                }
-            }
+            let ret = self.locals.vars[name].clone();
+            self.emit_load(ret.data_type, ret.addr.clone());
         }
 
-        self.emit("    leave");
-        self.emit("    ret");
+        if let Some(base) = self.gosub_base.take() {
+            // rax/rdx and xmm0 hold the return value
+            self.emit(format_args!("    mov rcx, QWORD PTR [rbp + {}]", base));
+            self.emit("    mov QWORD PTR [rip + _gosub_sp], rcx");
+        }
+        self.emit_return();
         self.emit("");
 
-        // Patch the stack reserve placeholder with actual size
-        let stack_needed = -self.stack_offset;
-        let stack_size = (stack_needed + 15) & !15; // Round up to multiple of 16
-        let old_placeholder = format!("    sub rsp, 0         # STACK_RESERVE_PROC_{}", name);
-        let new_instruction = format!(
-            "    sub rsp, {}        # STACK_RESERVE_PROC_{}",
-            stack_size, name
-        );
-        self.output = self.output.replace(&old_placeholder, &new_instruction);
+        // Size the frame; every local, FOR temporary and DIM'd descriptor
+        // lives in it, so each recursive call gets its own copy
+        self.finish_frame();
 
         self.current_proc = None;
         self.stack_offset = old_stack_offset;
+        self.for_slots = old_for_slots;
+        self.line = old_line;
+    }
+
+    /// Mark where the code for a statement on a new source line starts
+    fn mark_source(&mut self, stmt: &Stmt) {
+        if stmt.span.line > 0 && stmt.span.line != self.source_line {
+            self.source_line = stmt.span.line;
+            self.push(Inst::Source(stmt.span.line));
+        }
     }
 
     fn gen_stmt(&mut self, stmt: &Stmt) {
-        match stmt {
-            Stmt::Label(n) => {
-                self.emit_label(&format!("_line_{}", n));
+        self.mark_source(stmt);
+        if !matches!(
+            &stmt.kind,
+            StmtKind::Label(_)
+                | StmtKind::Data(_)
+                | StmtKind::Sub { .. }
+                | StmtKind::Function { .. }
+        ) {
+            self.emit_event_poll();
+        }
+
+        match &stmt.kind {
+            StmtKind::Label(n) => {
+                let label = self.target_label(&GotoTarget::Line(*n));
+                self.emit_label(&label);
+                self.line = Some(*n);
+                self.emit_line_update();
             }
 
-            Stmt::Let {
+            StmtKind::Let {
                 name,
                 indices,
                 value,
             } => {
                 if indices.is_some() {
                     // Array assignment
-                    self.gen_array_store(name, indices.as_ref().unwrap(), value);
-                } else if is_string_var(name) {
+                    self.gen_array_store(name, indices.as_ref().unwrap(), |cg| cg.gen_expr(value));
+                } else if self.var_type(name) == DataType::String {
                     self.gen_string_assign(name, value);
                 } else {
                     // Evaluate expression and get its type
@@ -760,89 +1113,95 @@ impl CodeGen {
                     self.gen_coercion(expr_type, var_info.data_type);
 
                     // Store based on target type
-                    match var_info.data_type {
-                        DataType::Integer => {
-                            self.emit(&format!("    mov WORD PTR [rbp + {}], ax", var_info.offset));
-                        }
-                        DataType::Long => {
-                            self.emit(&format!(
-                                "    mov DWORD PTR [rbp + {}], eax",
-                                var_info.offset
-                            ));
-                        }
-                        DataType::Single => {
-                            self.emit(&format!(
-                                "    movss DWORD PTR [rbp + {}], xmm0",
-                                var_info.offset
-                            ));
-                        }
-                        DataType::Double => {
-                            self.emit(&format!(
-                                "    movsd QWORD PTR [rbp + {}], xmm0",
-                                var_info.offset
-                            ));
-                        }
-                        DataType::String => {
-                            // Should be handled by gen_string_assign above
-                            unreachable!("String assignment should be handled separately");
-                        }
-                    }
+                    self.emit_store(var_info.data_type, var_info.addr.clone());
                 }
             }
 
-            Stmt::Print { items, newline } => {
+            StmtKind::Print { items, newline } => {
                 for item in items {
                     match item {
                         PrintItem::Expr(expr) => {
                             self.gen_print_expr(expr);
                         }
                         PrintItem::Tab => {
-                            self.emit_arg_imm(0, ASCII_TAB);
-                            self.emit("    call _rt_print_char");
+                            self.call("_rt_print_zone");
                         }
                         PrintItem::Empty => {}
                     }
                 }
                 if *newline {
-                    self.emit("    call _rt_print_newline");
+                    self.call("_rt_print_newline");
                 }
             }
 
-            Stmt::Input { prompt, vars } => {
-                if let Some(pstr) = prompt {
-                    let idx = self.add_string_literal(pstr);
-                    self.emit_arg_lea(0, &format!("[rip + _str_{}]", idx));
-                    self.emit_arg_imm(1, pstr.len() as i64);
-                    self.emit("    call _rt_print_string");
+            StmtKind::Input {
+                prompt,
+                question,
+                vars,
+            } => {
+                // The runtime prompts and reads the whole line, redoing it until
+                // there is one valid field per target ('N' number, 'S' string)
+                let mut text = prompt.clone().unwrap_or_default();
+                if *question {
+                    text.push_str("? ");
                 }
+                let kinds: String = vars
+                    .iter()
+                    .map(|var| match self.expr_type(var) {
+                        DataType::String => 'S',
+                        _ => 'N',
+                    })
+                    .collect();
+                let prompt_idx = self.add_string_literal(&text);
+                let kinds_idx = self.add_string_literal(&kinds);
+                self.emit_arg_lea(0, &format!("[rip + _str_{}]", prompt_idx));
+                self.emit_arg_imm(1, text.len() as i64);
+                self.emit_arg_lea(2, &format!("[rip + _str_{}]", kinds_idx));
+                self.emit_arg_imm(3, kinds.len() as i64);
+                self.call("_rt_input_line");
                 for var in vars {
-                    if is_string_var(var) {
-                        self.emit("    call _rt_input_string");
-                        let offset = self.get_var_offset(var);
-                        self.emit(&format!("    mov QWORD PTR [rbp + {}], rax", offset));
-                        self.emit(&format!("    mov QWORD PTR [rbp + {}], rdx", offset - 8));
-                    } else {
-                        self.emit("    call _rt_input_number");
-                        let offset = self.get_var_offset(var);
-                        self.emit(&format!("    movsd QWORD PTR [rbp + {}], xmm0", offset));
-                    }
+                    self.gen_assign(var, |cg| {
+                        if cg.expr_type(var) == DataType::String {
+                            cg.call("_rt_input_next_string");
+                            DataType::String
+                        } else {
+                            cg.call("_rt_input_next_number");
+                            DataType::Double
+                        }
+                    });
                 }
             }
 
-            Stmt::LineInput { prompt, var } => {
+            StmtKind::LineInput {
+                file_num: Some(file_num),
+                var,
+                ..
+            } => {
+                self.gen_assign(var, |cg| {
+                    cg.emit_arg_imm(0, *file_num as i64);
+                    cg.call("_rt_file_line_input");
+                    DataType::String
+                });
+            }
+
+            StmtKind::LineInput {
+                file_num: None,
+                prompt,
+                var,
+            } => {
                 if let Some(pstr) = prompt {
                     let idx = self.add_string_literal(pstr);
                     self.emit_arg_lea(0, &format!("[rip + _str_{}]", idx));
                     self.emit_arg_imm(1, pstr.len() as i64);
-                    self.emit("    call _rt_print_string");
+                    self.call("_rt_print_string");
                 }
-                self.emit("    call _rt_input_string");
-                let offset = self.get_var_offset(var);
-                self.emit(&format!("    mov QWORD PTR [rbp + {}], rax", offset));
-                self.emit(&format!("    mov QWORD PTR [rbp + {}], rdx", offset - 8));
+                self.gen_assign(var, |cg| {
+                    cg.call("_rt_input_string");
+                    DataType::String
+                });
             }
 
-            Stmt::If {
+            StmtKind::If {
                 condition,
                 then_branch,
                 else_branch,
@@ -850,21 +1209,12 @@ impl CodeGen {
                 let else_label = self.new_label("else");
                 let end_label = self.new_label("endif");
 
-                let cond_type = self.gen_expr(condition);
-                // Compare with 0 - conditions typically return Long (integer) now
-                if cond_type.is_integer() {
-                    self.emit("    test eax, eax");
-                    self.emit(&format!("    je {}", else_label));
-                } else {
-                    self.emit("    xorpd xmm1, xmm1");
-                    self.emit("    ucomisd xmm0, xmm1");
-                    self.emit(&format!("    je {}", else_label));
-                }
+                self.gen_branch(condition, true, &else_label);
 
                 for s in then_branch {
                     self.gen_stmt(s);
                 }
-                self.emit(&format!("    jmp {}", end_label));
+                self.jump(&end_label);
 
                 self.emit_label(&else_label);
                 if let Some(eb) = else_branch {
@@ -876,111 +1226,39 @@ impl CodeGen {
                 self.emit_label(&end_label);
             }
 
-            Stmt::For {
+            StmtKind::For {
                 var,
                 start,
                 end,
                 step,
                 body,
             } => {
-                let start_label = self.new_label("for");
-                let end_label = self.new_label("endfor");
-                let var_offset = self.get_var_offset(var);
+                let step_value = step.as_ref().map_or(Some(1), const_int);
+                match step_value {
+                    Some(step) if self.var_type(var).is_integer() => {
+                        self.gen_int_for(var, start, end, step, body)
+                    }
+                    _ => self.gen_float_for(var, start, end, step.as_ref(), body),
+                }
+            }
 
-                // Initialize loop variable - coerce to double
-                let start_type = self.gen_expr(start);
-                self.gen_coercion(start_type, DataType::Double);
-                self.emit(&format!("    movsd QWORD PTR [rbp + {}], xmm0", var_offset));
+            StmtKind::While { condition, body } => {
+                let start_label = self.new_label("while");
+                let end_label = self.new_label("endwhile");
 
-                // Store end value - coerce to double
-                self.stack_offset -= 8;
-                let end_offset = self.stack_offset;
-                let end_type = self.gen_expr(end);
-                self.gen_coercion(end_type, DataType::Double);
-                self.emit(&format!("    movsd QWORD PTR [rbp + {}], xmm0", end_offset));
+                self.emit_label(&start_label);
+                self.emit_event_poll();
+                self.gen_branch(condition, true, &end_label);
 
-                // Store step value - coerce to double
-                self.stack_offset -= 8;
-                let step_offset = self.stack_offset;
-                if let Some(s) = step {
-                    let step_type = self.gen_expr(s);
-                    self.gen_coercion(step_type, DataType::Double);
-                } else {
-                    self.emit("    mov rax, 0x3FF0000000000000  # 1.0");
-                    self.emit("    movq xmm0, rax");
-                }
-                self.emit(&format!(
-                    "    movsd QWORD PTR [rbp + {}], xmm0",
-                    step_offset
-                ));
-
-                self.emit_label(&start_label);
-
-                // Check condition (var > end for positive step, var < end for negative)
-                self.emit(&format!("    movsd xmm0, QWORD PTR [rbp + {}]", var_offset));
-                self.emit(&format!("    movsd xmm1, QWORD PTR [rbp + {}]", end_offset));
-                self.emit(&format!(
-                    "    movsd xmm2, QWORD PTR [rbp + {}]",
-                    step_offset
-                ));
-                self.emit("    xorpd xmm3, xmm3");
-                self.emit("    ucomisd xmm2, xmm3");
-                self.emit(&format!("    jb .Lfor_neg_{}", self.label_counter));
-
-                // Positive step: exit if var > end
-                self.emit("    ucomisd xmm0, xmm1");
-                self.emit(&format!("    ja {}", end_label));
-                self.emit(&format!("    jmp .Lfor_body_{}", self.label_counter));
-
-                // Negative step: exit if var < end
-                self.emit_label(&format!(".Lfor_neg_{}", self.label_counter));
-                self.emit("    ucomisd xmm0, xmm1");
-                self.emit(&format!("    jb {}", end_label));
-
-                self.emit_label(&format!(".Lfor_body_{}", self.label_counter));
-                self.label_counter += 1;
-
-                // Body
                 for s in body {
                     self.gen_stmt(s);
                 }
-
-                // Increment
-                self.emit(&format!("    movsd xmm0, QWORD PTR [rbp + {}]", var_offset));
-                self.emit(&format!(
-                    "    addsd xmm0, QWORD PTR [rbp + {}]",
-                    step_offset
-                ));
-                self.emit(&format!("    movsd QWORD PTR [rbp + {}], xmm0", var_offset));
-                self.emit(&format!("    jmp {}", start_label));
+                self.jump(&start_label);
 
                 self.emit_label(&end_label);
             }
 
-            Stmt::While { condition, body } => {
-                let start_label = self.new_label("while");
-                let end_label = self.new_label("endwhile");
-
-                self.emit_label(&start_label);
-                let cond_type = self.gen_expr(condition);
-                if cond_type.is_integer() {
-                    self.emit("    test eax, eax");
-                    self.emit(&format!("    je {}", end_label));
-                } else {
-                    self.emit("    xorpd xmm1, xmm1");
-                    self.emit("    ucomisd xmm0, xmm1");
-                    self.emit(&format!("    je {}", end_label));
-                }
-
-                for s in body {
-                    self.gen_stmt(s);
-                }
-                self.emit(&format!("    jmp {}", start_label));
-
-                self.emit_label(&end_label);
-            }
-
-            Stmt::DoLoop {
+            StmtKind::DoLoop {
                 condition,
                 cond_at_start,
                 is_until,
@@ -990,26 +1268,12 @@ impl CodeGen {
                 let end_label = self.new_label("enddo");
 
                 self.emit_label(&start_label);
+                self.emit_event_poll();
 
+                // Leave when a WHILE condition is false or an UNTIL one true
                 if *cond_at_start {
                     if let Some(cond) = condition {
-                        let cond_type = self.gen_expr(cond);
-                        if cond_type.is_integer() {
-                            self.emit("    test eax, eax");
-                            if *is_until {
-                                self.emit(&format!("    jne {}", end_label));
-                            } else {
-                                self.emit(&format!("    je {}", end_label));
-                            }
-                        } else {
-                            self.emit("    xorpd xmm1, xmm1");
-                            self.emit("    ucomisd xmm0, xmm1");
-                            if *is_until {
-                                self.emit(&format!("    jne {}", end_label));
-                            } else {
-                                self.emit(&format!("    je {}", end_label));
-                            }
-                        }
+                        self.gen_branch(cond, !*is_until, &end_label);
                     }
                 }
 
@@ -1017,150 +1281,208 @@ impl CodeGen {
                     self.gen_stmt(s);
                 }
 
-                if !*cond_at_start {
-                    if let Some(cond) = condition {
-                        let cond_type = self.gen_expr(cond);
-                        if cond_type.is_integer() {
-                            self.emit("    test eax, eax");
-                            if *is_until {
-                                self.emit(&format!("    je {}", start_label));
-                            } else {
-                                self.emit(&format!("    jne {}", start_label));
-                            }
-                        } else {
-                            self.emit("    xorpd xmm1, xmm1");
-                            self.emit("    ucomisd xmm0, xmm1");
-                            if *is_until {
-                                self.emit(&format!("    je {}", start_label));
-                            } else {
-                                self.emit(&format!("    jne {}", start_label));
-                            }
-                        }
-                    } else {
-                        self.emit(&format!("    jmp {}", start_label));
-                    }
-                } else {
-                    self.emit(&format!("    jmp {}", start_label));
+                // Repeat while a WHILE condition is true or an UNTIL one false
+                match condition.as_ref().filter(|_| !*cond_at_start) {
+                    Some(cond) => self.gen_branch(cond, *is_until, &start_label),
+                    None => self.jump(&start_label),
                 }
 
                 self.emit_label(&end_label);
             }
 
-            Stmt::Goto(target) => {
-                let label = match target {
-                    GotoTarget::Line(n) => format!("_line_{}", n),
-                    GotoTarget::Label(s) => format!("_label_{}", s),
-                };
-                self.emit(&format!("    jmp {}", label));
+            StmtKind::Goto(target) => {
+                let label = self.target_label(target);
+                self.jump(&label);
             }
 
-            Stmt::Gosub(target) => {
-                let label = match target {
-                    GotoTarget::Line(n) => format!("_line_{}", n),
-                    GotoTarget::Label(s) => format!("_label_{}", s),
-                };
+            StmtKind::Gosub(target) => {
+                let label = self.target_label(target);
                 let ret_label = self.new_label("gosub_ret");
-                // Check for stack overflow before push
-                self.emit("    mov rcx, QWORD PTR [rip + _gosub_sp]");
-                self.emit("    sub rcx, 8");
-                self.emit("    lea rax, [rip + _gosub_stack]");
-                self.emit("    cmp rcx, rax");
-                self.emit("    jb _rt_gosub_overflow");
-                // Push return address to GOSUB stack
-                self.emit(&format!("    lea rax, [rip + {}]", ret_label));
-                self.emit("    mov QWORD PTR [rcx], rax");
-                self.emit("    mov QWORD PTR [rip + _gosub_sp], rcx");
-                self.emit(&format!("    jmp {}", label));
+                self.emit_gosub_push(&ret_label);
+                self.jump(&label);
                 self.emit_label(&ret_label);
+                self.emit_line_update();
             }
 
-            Stmt::Return => {
-                // Pop return address from GOSUB stack and jump (use rcx - caller-saved on both ABIs)
+            StmtKind::Return => {
+                // Pop return address from GOSUB stack and jump (use rcx - caller-saved on both ABIs).
+                // Only addresses pushed by the main program, or by this procedure
+                // call, may be popped
                 self.emit("    mov rcx, QWORD PTR [rip + _gosub_sp]");
+                match self.gosub_base {
+                    Some(base) => {
+                        self.emit(format_args!("    cmp rcx, QWORD PTR [rbp + {}]", base));
+                    }
+                    None => {
+                        self.emit(format_args!(
+                            "    lea rax, [rip + _gosub_stack + {}]",
+                            GOSUB_STACK_SIZE
+                        ));
+                        self.emit("    cmp rcx, rax");
+                    }
+                }
+                self.emit("    jae _rt_gosub_underflow");
                 self.emit("    mov rax, QWORD PTR [rcx]");
                 self.emit("    add rcx, 8");
                 self.emit("    mov QWORD PTR [rip + _gosub_sp], rcx");
                 self.emit("    jmp rax");
             }
 
-            Stmt::OnGoto { expr, targets } => {
+            StmtKind::OnGoto { expr, targets } => {
                 let expr_type = self.gen_expr(expr);
                 // Convert to integer in rax
                 if expr_type.is_integer() {
                     self.emit("    movsxd rax, eax");
                 } else {
+                    self.gen_coercion(expr_type, DataType::Double);
                     self.emit("    cvttsd2si rax, xmm0");
                 }
-                // Create jump table
-                for (i, target) in targets.iter().enumerate() {
-                    let label = match target {
-                        GotoTarget::Line(n) => format!("_line_{}", n),
-                        GotoTarget::Label(s) => format!("_label_{}", s),
-                    };
-                    self.emit(&format!("    cmp rax, {}", i + 1));
-                    self.emit(&format!("    je {}", label));
+                let labels: Vec<String> = targets.iter().map(|t| self.target_label(t)).collect();
+                if labels.len() >= MIN_DISPATCH_CASES {
+                    // Out of range falls through to the next statement
+                    let end_label = self.new_label("endon");
+                    let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+                    self.gen_jump_table(1, &labels, &end_label);
+                    self.emit_label(&end_label);
+                } else {
+                    for (i, label) in labels.iter().enumerate() {
+                        self.emit(format_args!("    cmp rax, {}", i + 1));
+                        self.emit(format_args!("    je {}", label));
+                    }
                 }
             }
 
-            Stmt::Dim { arrays } => {
+            StmtKind::OnKey { key, target } => {
+                // Handlers run from the main program's poll points
+                let label = Self::main_label(target);
+                let args = [IntArg::Expr(key), IntArg::Label(&label)];
+                self.gen_runtime_call_int("_rt_on_key", &args);
+            }
+
+            StmtKind::KeyTrap { key, state } => {
+                let args = [IntArg::Expr(key), IntArg::Imm(Self::trap_state(*state))];
+                self.gen_runtime_call_int("_rt_key_trap", &args);
+            }
+
+            StmtKind::KeyDisplay(_) => {
+                // There is no function key line to show or hide
+            }
+
+            StmtKind::OnTimer { interval, target } => {
+                // Handlers run from the main program's poll points
+                let label = Self::main_label(target);
+                let args = [IntArg::Expr(interval), IntArg::Label(&label)];
+                self.gen_runtime_call_int("_rt_on_timer", &args);
+            }
+
+            StmtKind::TimerTrap(state) => {
+                let args = [IntArg::Imm(Self::trap_state(*state))];
+                self.gen_runtime_call_int("_rt_timer_trap", &args);
+            }
+
+            StmtKind::OnBreak { target } => {
+                // Handlers run from the main program's poll points
+                let label = Self::main_label(target);
+                self.gen_runtime_call_int("_rt_on_break", &[IntArg::Label(&label)]);
+            }
+
+            StmtKind::BreakTrap(state) => {
+                let args = [IntArg::Imm(Self::trap_state(*state))];
+                self.gen_runtime_call_int("_rt_break_trap", &args);
+            }
+
+            StmtKind::OnMouse { target } => {
+                // Handlers run from the main program's poll points
+                let label = Self::main_label(target);
+                self.gen_runtime_call_int("_rt_on_mouse", &[IntArg::Label(&label)]);
+            }
+
+            StmtKind::MouseTrap(state) => {
+                let args = [IntArg::Imm(Self::trap_state(*state))];
+                self.gen_runtime_call_int("_rt_mouse_trap", &args);
+            }
+
+            StmtKind::Dim { arrays, .. } => {
                 for arr in arrays {
-                    self.gen_dim_array(arr);
+                    if arr.dimensions.is_empty() {
+                        self.get_var_info(&arr.name);
+                    } else {
+                        self.gen_dim_array(arr);
+                    }
                 }
             }
 
-            Stmt::Sub { .. } | Stmt::Function { .. } => {
+            StmtKind::Shared(names) => self.share(names),
+
+            StmtKind::Sub { .. } | StmtKind::Function { .. } => {
                 // Already handled in first pass
             }
 
-            Stmt::Call { name, args } => {
+            StmtKind::Call { name, args } => {
                 self.gen_call(name, args);
             }
 
-            Stmt::Data(_) => {
+            StmtKind::Data(_) => {
                 // Data already collected in first pass
             }
 
-            Stmt::Read(vars) => {
+            StmtKind::Read(vars) => {
                 for var in vars {
-                    if is_string_var(var) {
-                        self.emit("    call _rt_read_string");
-                        let offset = self.get_var_offset(var);
-                        self.emit(&format!("    mov QWORD PTR [rbp + {}], rax", offset));
-                    } else {
-                        self.emit("    call _rt_read_number");
-                        let offset = self.get_var_offset(var);
-                        self.emit(&format!("    movsd QWORD PTR [rbp + {}], xmm0", offset));
-                    }
+                    self.gen_assign(var, |cg| {
+                        if cg.expr_type(var) == DataType::String {
+                            cg.call("_rt_read_string");
+                            DataType::String
+                        } else {
+                            cg.call("_rt_read_number");
+                            DataType::Double
+                        }
+                    });
                 }
             }
 
-            Stmt::Restore(target) => {
-                let idx = if let Some(_t) = target {
-                    // TODO: find DATA line index
-                    0
-                } else {
-                    0
+            StmtKind::Restore(target) => {
+                let idx = match target {
+                    Some(GotoTarget::Line(n)) => self.data_lines[n],
+                    Some(GotoTarget::Label(_)) => panic!("RESTORE needs a line number"),
+                    None => 0,
                 };
-                self.emit_arg_imm(0, idx);
-                self.emit("    call _rt_restore");
+                self.emit_arg_imm(0, idx as i64);
+                self.call("_rt_restore");
             }
 
-            Stmt::Cls => {
-                self.emit("    call _rt_cls");
+            StmtKind::Cls => {
+                self.call("_rt_cls");
             }
 
-            Stmt::SelectCase { expr, cases } => {
+            StmtKind::Width { file_num, width } => match file_num {
+                None => self.gen_runtime_call_int("_rt_width", &[IntArg::Expr(width)]),
+                Some(n) => {
+                    let args = [IntArg::Imm(*n as i64), IntArg::Expr(width)];
+                    self.gen_runtime_call_int("_rt_file_width", &args);
+                }
+            },
+
+            StmtKind::SelectCase { expr, cases } => {
+                if self.expr_type(expr) != DataType::String {
+                    if let Some(values) = case_constants(cases) {
+                        self.gen_select_dispatch(expr, cases, &values);
+                        return;
+                    }
+                }
                 let end_label = self.new_label("endselect");
 
-                // Evaluate SELECT expression and save to temp
+                // Evaluate SELECT expression and save to temp; strings keep
+                // their length 8 bytes below the pointer, like variables
                 let expr_type = self.gen_expr(expr);
-                self.gen_coercion(expr_type, DataType::Double);
-                self.stack_offset -= 8;
-                let temp_offset = self.stack_offset;
-                self.emit(&format!(
-                    "    movsd QWORD PTR [rbp + {}], xmm0",
-                    temp_offset
-                ));
+                let is_string = expr_type == DataType::String;
+                let temp_offset = self.alloc_var(expr_type);
+                let temp_type = if is_string {
+                    DataType::String
+                } else {
+                    DataType::Double
+                };
+                self.gen_coercion(expr_type, temp_type);
+                self.emit_store(temp_type, format!("rbp + {}", temp_offset));
 
                 // Generate code for each case
                 for (i, (case_value, body)) in cases.iter().enumerate() {
@@ -1170,16 +1492,34 @@ impl CodeGen {
                         end_label.clone()
                     };
 
-                    if let Some(value) = case_value {
+                    if let Some(value) = case_value.as_ref().filter(|_| is_string) {
+                        // Compare strings in the runtime
+                        self.gen_expr(value);
+                        self.emit_arg_reg(3, "rdx"); // case len
+                        self.emit_arg_reg(2, "rax"); // case ptr
+                        let (arg0, arg1) = (self.arg_reg(0), self.arg_reg(1));
+                        self.emit(format_args!(
+                            "    mov {}, QWORD PTR [rbp + {}]",
+                            arg0, temp_offset
+                        ));
+                        self.emit(format_args!(
+                            "    mov {}, QWORD PTR [rbp + {}]",
+                            arg1,
+                            temp_offset - 8
+                        ));
+                        self.call("_rt_strcmp");
+                        self.emit("    test eax, eax");
+                        self.emit(format_args!("    jne {}", next_case_label));
+                    } else if let Some(value) = case_value {
                         // Evaluate case value and compare
                         let val_type = self.gen_expr(value);
                         self.gen_coercion(val_type, DataType::Double);
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    movsd xmm1, QWORD PTR [rbp + {}]",
                             temp_offset
                         ));
                         self.emit("    ucomisd xmm0, xmm1");
-                        self.emit(&format!("    jne {}", next_case_label));
+                        self.emit(format_args!("    jne {}", next_case_label));
                     }
                     // CASE ELSE (None) falls through without comparison
 
@@ -1190,7 +1530,7 @@ impl CodeGen {
 
                     // Jump to end (skip remaining cases)
                     if i + 1 < cases.len() {
-                        self.emit(&format!("    jmp {}", end_label));
+                        self.jump(&end_label);
                         self.emit_label(&next_case_label);
                     }
                 }
@@ -1198,13 +1538,29 @@ impl CodeGen {
                 self.emit_label(&end_label);
             }
 
-            Stmt::End | Stmt::Stop => {
+            StmtKind::End | StmtKind::Stop => {
                 self.emit("    xor eax, eax");
-                self.emit("    leave");
-                self.emit("    ret");
+                self.emit_return();
+            }
+
+            StmtKind::OptionExplicit => {
+                // Checked before code generation (see semantic.rs)
             }
 
-            Stmt::Open {
+            StmtKind::Const(_) => {
+                // Its names were replaced by their values (see fold.rs)
+            }
+
+            StmtKind::Declare { .. } => {
+                // Parameters were collected by preprocess
+            }
+
+            StmtKind::Asm { lines, .. } => {
+                let lines = lines.iter().map(|line| self.asm_operands(line)).collect();
+                self.push(Inst::InlineAsm(lines));
+            }
+
+            StmtKind::Open {
                 filename,
                 mode,
                 file_num,
@@ -1217,18 +1573,96 @@ impl CodeGen {
                     FileMode::Input => 0,
                     FileMode::Output => 1,
                     FileMode::Append => 2,
+                    FileMode::Both => 3,
                 };
                 self.emit_arg_imm(2, mode_num);
                 self.emit_arg_imm(3, *file_num as i64);
-                self.emit("    call _rt_file_open");
+                self.call("_rt_file_open");
             }
 
-            Stmt::Close { file_num } => {
+            StmtKind::Close { file_num } => {
                 self.emit_arg_imm(0, *file_num as i64);
-                self.emit("    call _rt_file_close");
+                self.call("_rt_file_close");
             }
 
-            Stmt::PrintFile {
+            StmtKind::Flush { file_num } => {
+                self.emit_arg_imm(0, *file_num as i64);
+                self.call("_rt_file_flush");
+            }
+
+            StmtKind::Seek { file_num, position } => {
+                // _rt_file_seek(file, position)
+                let args = [IntArg::Imm(*file_num as i64), IntArg::Expr(position)];
+                self.gen_runtime_call_int("_rt_file_seek", &args);
+            }
+
+            StmtKind::DictSet { dict, key, value } => {
+                // _rt_dict_set(dict, key, key_len, value, value_len) or
+                // _rt_dict_del(dict, key, key_len)
+                let (key_ptr, key_len) = self.gen_string_to_slots(key);
+                match value {
+                    Some(value) => {
+                        let (value_ptr, value_len) = self.gen_string_to_slots(value);
+                        let args = [
+                            IntArg::Expr(dict),
+                            IntArg::Slot(key_ptr),
+                            IntArg::Slot(key_len),
+                            IntArg::Slot(value_ptr),
+                            IntArg::Slot(value_len),
+                        ];
+                        self.gen_runtime_call_int("_rt_dict_set", &args);
+                    }
+                    None => {
+                        let args = [
+                            IntArg::Expr(dict),
+                            IntArg::Slot(key_ptr),
+                            IntArg::Slot(key_len),
+                        ];
+                        self.gen_runtime_call_int("_rt_dict_del", &args);
+                    }
+                }
+            }
+
+            StmtKind::Lock {
+                file_num,
+                first,
+                last,
+                unlock,
+            } => {
+                // _rt_file_lock/_rt_file_unlock(file, first, last, ranged);
+                // a lone record is both ends, evaluated once
+                let func = if *unlock {
+                    "_rt_file_unlock"
+                } else {
+                    "_rt_file_lock"
+                };
+                let ranged = IntArg::Imm((first.is_some() || last.is_some()) as i64);
+                match (first, last) {
+                    (Some(record), None) => {
+                        self.gen_rounded_int(record);
+                        let slot = self.alloc_var(DataType::Long);
+                        self.emit(format_args!("    mov QWORD PTR [rbp + {}], rax", slot));
+                        let args = [
+                            IntArg::Imm(*file_num as i64),
+                            IntArg::Slot(slot),
+                            IntArg::Slot(slot),
+                            ranged,
+                        ];
+                        self.gen_runtime_call_int(func, &args);
+                    }
+                    _ => {
+                        let args = [
+                            IntArg::Imm(*file_num as i64),
+                            IntArg::or_imm(first, 1),
+                            IntArg::or_imm(last, 1),
+                            ranged,
+                        ];
+                        self.gen_runtime_call_int(func, &args);
+                    }
+                }
+            }
+
+            StmtKind::PrintFile {
                 file_num,
                 items,
                 newline,
@@ -1240,33 +1674,267 @@ impl CodeGen {
                         }
                         PrintItem::Tab => {
                             self.emit_arg_imm(0, *file_num as i64);
-                            self.emit_arg_imm(1, ASCII_TAB);
-                            self.emit("    call _rt_file_print_char");
+                            self.call("_rt_file_print_zone");
                         }
                         PrintItem::Empty => {}
                     }
                 }
                 if *newline {
                     self.emit_arg_imm(0, *file_num as i64);
-                    self.emit("    call _rt_file_print_newline");
+                    self.call("_rt_file_print_newline");
                 }
             }
 
-            Stmt::InputFile { file_num, vars } => {
-                for var in vars {
-                    if is_string_var(var) {
-                        self.emit_arg_imm(0, *file_num as i64);
-                        self.emit("    call _rt_file_input_string");
-                        let offset = self.get_var_offset(var);
-                        self.emit(&format!("    mov QWORD PTR [rbp + {}], rax", offset));
-                        self.emit(&format!("    mov QWORD PTR [rbp + {}], rdx", offset - 8));
+            StmtKind::PrintUsing {
+                file_num,
+                format,
+                items,
+                newline,
+            } => {
+                // The runtime copies the format, so items may reuse its buffer
+                self.gen_expr(format);
+                self.gen_print_string_call(*file_num, "_rt_print_using", "_rt_file_print_using");
+                for item in items {
+                    if self.expr_type(item) == DataType::String {
+                        self.gen_expr(item);
+                        self.gen_print_string_call(
+                            *file_num,
+                            "_rt_print_using_string",
+                            "_rt_file_print_using_string",
+                        );
                     } else {
-                        self.emit_arg_imm(0, *file_num as i64);
-                        self.emit("    call _rt_file_input_number");
-                        let offset = self.get_var_offset(var);
-                        self.emit(&format!("    movsd QWORD PTR [rbp + {}], xmm0", offset));
+                        let expr_type = self.gen_expr(item);
+                        self.gen_coercion(expr_type, DataType::Double);
+                        self.gen_print_call(
+                            *file_num,
+                            "_rt_print_using_number",
+                            "_rt_file_print_using_number",
+                        );
                     }
                 }
+                self.gen_print_call(*file_num, "_rt_print_using_end", "_rt_file_print_using_end");
+                if *newline {
+                    self.gen_print_call(*file_num, "_rt_print_newline", "_rt_file_print_newline");
+                }
+            }
+
+            StmtKind::Write { file_num, items } => {
+                // Items separated by commas, strings in quotes
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        self.gen_print_char(*file_num, ASCII_COMMA);
+                    }
+                    if self.expr_type(item) == DataType::String {
+                        self.gen_print_char(*file_num, ASCII_QUOTE);
+                        self.gen_expr(item);
+                        self.gen_print_string_call(
+                            *file_num,
+                            "_rt_print_string",
+                            "_rt_file_print_string",
+                        );
+                        self.gen_print_char(*file_num, ASCII_QUOTE);
+                    } else {
+                        let expr_type = self.gen_expr(item);
+                        self.gen_coercion(expr_type, DataType::Double);
+                        if expr_type == DataType::Single {
+                            self.gen_print_call(
+                                *file_num,
+                                "_rt_print_single",
+                                "_rt_file_print_single",
+                            );
+                        } else {
+                            self.gen_print_call(
+                                *file_num,
+                                "_rt_print_float",
+                                "_rt_file_print_float",
+                            );
+                        }
+                    }
+                }
+                self.gen_print_call(*file_num, "_rt_print_newline", "_rt_file_print_newline");
+            }
+
+            StmtKind::InputFile { file_num, vars } => {
+                for var in vars {
+                    self.gen_assign(var, |cg| {
+                        cg.emit_arg_imm(0, *file_num as i64);
+                        if cg.expr_type(var) == DataType::String {
+                            cg.call("_rt_file_input_string");
+                            DataType::String
+                        } else {
+                            cg.call("_rt_file_input_number");
+                            DataType::Double
+                        }
+                    });
+                }
+            }
+
+            StmtKind::Screen { mode } => {
+                self.gen_runtime_call_int("_rt_screen", &[IntArg::Expr(mode)]);
+            }
+
+            StmtKind::Pset {
+                x,
+                y,
+                color,
+                preset,
+            } => {
+                // PRESET defaults to the background color, PSET to the foreground
+                let color = IntArg::or_imm(color, if *preset { 0 } else { -1 });
+                self.gen_runtime_call_int("_rt_pset", &[IntArg::Expr(x), IntArg::Expr(y), color]);
+            }
+
+            StmtKind::GraphicsLine {
+                from,
+                to,
+                color,
+                style,
+            } => {
+                let color = IntArg::or_imm(color, -1);
+                let style = IntArg::Imm(match style {
+                    LineStyle::Line => 0,
+                    LineStyle::Box => 1,
+                    LineStyle::FilledBox => 2,
+                });
+                let (x2, y2) = (IntArg::Expr(&to.0), IntArg::Expr(&to.1));
+                match from {
+                    Some((x1, y1)) => self.gen_runtime_call_int(
+                        "_rt_line",
+                        &[IntArg::Expr(x1), IntArg::Expr(y1), x2, y2, color, style],
+                    ),
+                    None => self.gen_runtime_call_int("_rt_line_to", &[x2, y2, color, style]),
+                }
+            }
+
+            StmtKind::Circle {
+                x,
+                y,
+                radius,
+                color,
+            } => {
+                let args = [
+                    IntArg::Expr(x),
+                    IntArg::Expr(y),
+                    IntArg::Expr(radius),
+                    IntArg::or_imm(color, -1),
+                ];
+                self.gen_runtime_call_int("_rt_circle", &args);
+            }
+
+            StmtKind::Paint {
+                x,
+                y,
+                color,
+                border,
+            } => {
+                let args = [
+                    IntArg::Expr(x),
+                    IntArg::Expr(y),
+                    IntArg::or_imm(color, -1),
+                    IntArg::or_imm(border, -1),
+                ];
+                self.gen_runtime_call_int("_rt_paint", &args);
+            }
+
+            StmtKind::GetImage {
+                from,
+                to,
+                array,
+                indices,
+            } => {
+                // _rt_get_image(x1, y1, x2, y2, buffer, buffer_size)
+                let (buf, size) = self.gen_array_span(array, indices);
+                let args = [
+                    IntArg::Expr(&from.0),
+                    IntArg::Expr(&from.1),
+                    IntArg::Expr(&to.0),
+                    IntArg::Expr(&to.1),
+                    IntArg::Slot(buf),
+                    IntArg::Slot(size),
+                ];
+                self.gen_runtime_call_int("_rt_get_image", &args);
+            }
+
+            StmtKind::PutImage {
+                at,
+                array,
+                indices,
+                mode,
+            } => {
+                // _rt_put_image(x, y, buffer, buffer_size, mode)
+                let (buf, size) = self.gen_array_span(array, indices);
+                let mode = match mode {
+                    PutMode::Pset => 0,
+                    PutMode::Preset => 1,
+                    PutMode::And => 2,
+                    PutMode::Or => 3,
+                    PutMode::Xor => 4,
+                };
+                let args = [
+                    IntArg::Expr(&at.0),
+                    IntArg::Expr(&at.1),
+                    IntArg::Slot(buf),
+                    IntArg::Slot(size),
+                    IntArg::Imm(mode),
+                ];
+                self.gen_runtime_call_int("_rt_put_image", &args);
+            }
+
+            StmtKind::Draw { commands } => {
+                // _rt_draw(ptr, len)
+                self.gen_expr(commands);
+                self.emit_arg_reg(0, "rax");
+                self.emit_arg_reg(1, "rdx");
+                self.call("_rt_draw");
+            }
+
+            StmtKind::Display => {
+                self.call("_rt_gfx_flush");
+            }
+
+            StmtKind::Palette { entry } => match entry {
+                Some((attribute, color)) => self.gen_runtime_call_int(
+                    "_rt_palette",
+                    &[IntArg::Expr(attribute), IntArg::Expr(color)],
+                ),
+                None => self.call("_rt_palette_reset"),
+            },
+
+            StmtKind::DefSeg { segment } => {
+                self.gen_runtime_call_int("_rt_def_seg", &[IntArg::or_imm(segment, 0)]);
+            }
+
+            StmtKind::Poke { address, value } => {
+                let args = [IntArg::Expr(address), IntArg::Expr(value)];
+                self.gen_runtime_call_int("_rt_poke", &args);
+            }
+
+            StmtKind::Bsave {
+                filename,
+                offset,
+                length,
+            } => {
+                // _rt_bsave(name_ptr, name_len, offset, length)
+                let (ptr_slot, len_slot) = self.gen_string_to_slots(filename);
+                let args = [
+                    IntArg::Slot(ptr_slot),
+                    IntArg::Slot(len_slot),
+                    IntArg::Expr(offset),
+                    IntArg::Expr(length),
+                ];
+                self.gen_runtime_call_int("_rt_bsave", &args);
+            }
+
+            StmtKind::Bload { filename, offset } => {
+                // _rt_bload(name_ptr, name_len, offset, offset_given)
+                let (ptr_slot, len_slot) = self.gen_string_to_slots(filename);
+                let args = [
+                    IntArg::Slot(ptr_slot),
+                    IntArg::Slot(len_slot),
+                    IntArg::or_imm(offset, 0),
+                    IntArg::Imm(offset.is_some() as i64),
+                ];
+                self.gen_runtime_call_int("_rt_bload", &args);
             }
         }
     }
@@ -1275,159 +1943,533 @@ impl CodeGen {
     /// Returns the DataType of the result.
     /// Convention: integers in eax, floats in xmm0, strings in rax(ptr)/rdx(len)
     fn gen_expr(&mut self, expr: &Expr) -> DataType {
-        match expr {
-            Expr::Literal(lit) => match lit {
-                Literal::Integer(n) => {
-                    // Load as integer into eax
-                    self.emit(&format!("    mov eax, {}", *n as i32));
-                    DataType::Long
-                }
-                Literal::Float(f) => {
-                    // Load as double into xmm0
-                    let bits = f.to_bits();
-                    self.emit(&format!("    mov rax, 0x{:X}", bits));
-                    self.emit("    movq xmm0, rax");
-                    DataType::Double
-                }
-                Literal::String(s) => {
-                    let idx = self.add_string_literal(s);
-                    self.emit(&format!("    lea rax, [rip + _str_{}]", idx));
-                    self.emit(&format!("    mov rdx, {}", s.len()));
-                    DataType::String
+        match &expr.kind {
+            ExprKind::Literal(lit) => {
+                let value = match lit {
+                    Literal::Integer(n) => match i32::try_from(*n) {
+                        Ok(n) => Const::Long(n),
+                        // Too big for a Long: a Double constant, as in GW-BASIC
+                        Err(_) => Const::Double(*n as f64),
+                    },
+                    Literal::Float(f) => Const::Double(*f),
+                    Literal::Typed(value, data_type) => match data_type {
+                        DataType::Integer | DataType::Long => Const::Long(*value as i32),
+                        DataType::Single => Const::Single(*value as f32),
+                        _ => Const::Double(*value),
+                    },
+                    Literal::String(s) => Const::Str {
+                        index: self.add_string_literal(s),
+                        len: s.len(),
+                    },
+                };
+                self.push(Inst::Const(value));
+                // A typed INTEGER constant is still an INTEGER
+                match lit {
+                    Literal::Typed(_, data_type) => *data_type,
+                    _ => value.data_type(),
                 }
-            },
+            }
 
-            Expr::Variable(name) => {
+            ExprKind::Variable(name) => {
                 let info = self.get_var_info(name);
-                match info.data_type {
-                    DataType::Integer => {
-                        self.emit(&format!("    movsx eax, WORD PTR [rbp + {}]", info.offset));
-                    }
-                    DataType::Long => {
-                        self.emit(&format!("    mov eax, DWORD PTR [rbp + {}]", info.offset));
-                    }
-                    DataType::Single => {
-                        self.emit(&format!(
-                            "    movss xmm0, DWORD PTR [rbp + {}]",
-                            info.offset
-                        ));
-                    }
-                    DataType::Double => {
-                        self.emit(&format!(
-                            "    movsd xmm0, QWORD PTR [rbp + {}]",
-                            info.offset
-                        ));
-                    }
-                    DataType::String => {
-                        self.emit(&format!("    mov rax, QWORD PTR [rbp + {}]", info.offset));
-                        self.emit(&format!(
-                            "    mov rdx, QWORD PTR [rbp + {}]",
-                            info.offset - 8
-                        ));
-                    }
-                }
+                self.emit_load(info.data_type, info.addr.clone());
                 info.data_type
             }
 
-            Expr::ArrayAccess { name, indices } => {
+            ExprKind::ArrayAccess { name, indices } => {
                 self.gen_array_load(name, indices);
-                DataType::from_suffix(name)
+                DataType::of_name(name, self.dialect)
             }
 
-            Expr::Unary { op, operand } => {
+            ExprKind::Unary { op, operand } => {
                 let operand_type = self.gen_expr(operand);
                 match op {
                     UnaryOp::Neg => {
-                        if operand_type.is_integer() {
-                            self.emit("    neg eax");
-                            operand_type
-                        } else {
-                            // Negate float by XORing sign bit
-                            if operand_type == DataType::Single {
-                                self.emit("    mov eax, 0x80000000");
-                                self.emit("    movd xmm1, eax");
-                                self.emit("    xorps xmm0, xmm1");
-                            } else {
-                                self.emit("    mov rax, 0x8000000000000000");
-                                self.emit("    movq xmm1, rax");
-                                self.emit("    xorpd xmm0, xmm1");
-                            }
-                            operand_type
-                        }
+                        self.push(Inst::Neg(operand_type));
+                        self.emit_overflow_check(operand_type);
+                        operand_type
                     }
                     UnaryOp::Not => {
                         // NOT: if 0 then -1, else 0 - result is always Long
-                        if operand_type.is_integer() {
-                            self.emit("    test eax, eax");
-                        } else if operand_type == DataType::Single {
-                            self.emit("    xorps xmm1, xmm1");
-                            self.emit("    ucomiss xmm0, xmm1");
-                        } else {
-                            self.emit("    xorpd xmm1, xmm1");
-                            self.emit("    ucomisd xmm0, xmm1");
-                        }
-                        self.emit("    sete al");
-                        self.emit("    movzx eax, al");
-                        self.emit("    neg eax");
+                        self.push(Inst::Not(operand_type));
                         DataType::Long
                     }
                 }
             }
 
-            Expr::Binary { op, left, right } => self.gen_binary_expr(*op, left, right),
+            ExprKind::Binary { op, left, right } => self.gen_binary_expr(*op, left, right),
 
-            Expr::FnCall { name, args } => {
+            ExprKind::FnCall { name, args } => {
                 self.gen_fn_call(name, args);
-                self.fn_return_type(name)
+                let ty = self.expr_type(expr);
+                // Worked out in double precision, which is exact for these
+                if ty == DataType::Single && types::keeps_single(&name.to_uppercase()) {
+                    self.emit("    cvtsd2ss xmm0, xmm0");
+                }
+                ty
+            }
+        }
+    }
+
+    /// Evaluate a condition and jump to `label` if it is zero (`if_zero`)
+    /// or nonzero
+    fn gen_branch(&mut self, condition: &Expr, if_zero: bool, label: &str) {
+        if let ExprKind::Binary {
+            op: op @ (BinaryOp::AndAlso | BinaryOp::OrElse),
+            left,
+            right,
+        } = &condition.kind
+        {
+            self.gen_short_circuit_branch(*op, left, right, if_zero, label);
+            return;
+        }
+        let ty = self.gen_expr(condition);
+        self.push(Inst::Branch {
+            ty,
+            if_zero,
+            label: label.to_string(),
+        });
+    }
+
+    /// Branch on `left ANDALSO right` or `left ORELSE right`, evaluating the
+    /// right operand only when the left one doesn't decide the result
+    fn gen_short_circuit_branch(
+        &mut self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        if_zero: bool,
+        label: &str,
+    ) {
+        // ANDALSO is decided by a false left operand, ORELSE by a true one
+        let decided_if_zero = op == BinaryOp::AndAlso;
+        if if_zero == decided_if_zero {
+            self.gen_branch(left, if_zero, label);
+            self.gen_branch(right, if_zero, label);
+        } else {
+            let skip_label = self.new_label("shortcircuit");
+            self.gen_branch(left, decided_if_zero, &skip_label);
+            self.gen_branch(right, if_zero, label);
+            self.emit_label(&skip_label);
+        }
+    }
+
+    /// SELECT CASE over integer constants: the case bodies are reached
+    /// through a jump table or binary search on the value instead of
+    /// comparing against each case in turn
+    fn gen_select_dispatch(
+        &mut self,
+        expr: &Expr,
+        cases: &[(Option<Expr>, Vec<Stmt>)],
+        values: &[i32],
+    ) {
+        let end_label = self.new_label("endselect");
+        let body_labels: Vec<String> = cases.iter().map(|_| self.new_label("case")).collect();
+        let default = match body_labels.get(values.len()) {
+            Some(else_label) => else_label.clone(),
+            None => end_label.clone(),
+        };
+
+        // Compared as a Double, a value matches a case only if it is a whole
+        // number; the first case with a value wins
+        let expr_type = self.gen_expr(expr);
+        if expr_type.is_integer() {
+            self.emit("    movsxd rax, eax");
+        } else {
+            self.gen_coercion(expr_type, DataType::Double);
+            self.emit("    cvttsd2si rax, xmm0");
+            self.emit("    cvtsi2sd xmm1, rax");
+            self.emit("    ucomisd xmm0, xmm1");
+            self.emit(format_args!("    jne {}", default));
+            self.emit(format_args!("    jp {}", default));
+        }
+        let mut targets = BTreeMap::new();
+        for (value, label) in values.iter().zip(&body_labels) {
+            targets.entry(*value).or_insert(label.as_str());
+        }
+        let targets: Vec<(i32, &str)> = targets.into_iter().collect();
+        let (min, max) = (targets[0].0, targets[targets.len() - 1].0);
+        // A table when at least half its entries are cases
+        if (max as i64 - min as i64) < 2 * targets.len() as i64 {
+            let table: Vec<&str> = (min..=max)
+                .map(
+                    |value| match targets.binary_search_by_key(&value, |&(v, _)| v) {
+                        Ok(i) => targets[i].1,
+                        Err(_) => default.as_str(),
+                    },
+                )
+                .collect();
+            self.gen_jump_table(min, &table, &default);
+        } else {
+            self.gen_binary_search(&targets, &default);
+        }
+
+        for (i, ((_, body), label)) in cases.iter().zip(&body_labels).enumerate() {
+            self.emit_label(label);
+            for stmt in body {
+                self.gen_stmt(stmt);
+            }
+            if i + 1 < cases.len() {
+                self.jump(&end_label);
+            }
+        }
+        self.emit_label(&end_label);
+    }
+
+    /// Jump to `labels[rax - first]`, or to `default` when rax is out of
+    /// range, through a table of offsets placed after the jump
+    fn gen_jump_table(&mut self, first: i32, labels: &[&str], default: &str) {
+        let table = self.new_label("jumptable");
+        if first != 0 {
+            self.emit(format_args!("    sub rax, {}", first));
+        }
+        // Unsigned, so values below `first` are out of range too
+        self.emit(format_args!("    cmp rax, {}", labels.len() - 1));
+        self.emit(format_args!("    ja {}", default));
+        self.emit(format_args!("    lea rcx, [rip + {}]", table));
+        self.emit("    movsxd rax, DWORD PTR [rcx + rax*4]");
+        self.emit("    add rax, rcx");
+        self.emit("    jmp rax");
+        self.emit_label(&table);
+        for label in labels {
+            self.emit(format_args!("    .long {} - {}", label, table));
+        }
+    }
+
+    /// Jump to the label of the case (sorted by value) equal to rax, or to
+    /// `default`, by binary search
+    fn gen_binary_search(&mut self, cases: &[(i32, &str)], default: &str) {
+        if cases.len() < MIN_DISPATCH_CASES {
+            for (value, label) in cases {
+                self.emit(format_args!("    cmp rax, {}", value));
+                self.emit(format_args!("    je {}", label));
+            }
+            self.jump(default);
+            return;
+        }
+        let mid = cases.len() / 2;
+        let (value, label) = cases[mid];
+        let low_label = self.new_label("caselow");
+        self.emit(format_args!("    cmp rax, {}", value));
+        self.emit(format_args!("    je {}", label));
+        self.emit(format_args!("    jl {}", low_label));
+        self.gen_binary_search(&cases[mid + 1..], default);
+        self.emit_label(&low_label);
+        self.gen_binary_search(&cases[..mid], default);
+    }
+
+    /// FOR with an INTEGER or LONG variable and a constant STEP: the counter
+    /// is stepped and compared as a 32-bit integer, and is kept in a register
+    /// across the body when nothing there can change the variable behind its
+    /// back (see loop_counter_reg)
+    fn gen_int_for(&mut self, var: &str, start: &Expr, end: &Expr, step: i32, body: &[Stmt]) {
+        let body_label = self.new_label("for");
+        let end_label = self.new_label("endfor");
+        let info = self.get_var_info(var);
+        let var_addr = info.addr.clone();
+
+        // Initialize loop variable
+        let start_type = self.gen_expr(start);
+        self.gen_coercion(start_type, info.data_type);
+        self.emit_store(info.data_type, var_addr.clone());
+
+        // Store end value as a Long
+        let end_offset = self.for_slot(body);
+        let end_addr = format!("rbp + {}", end_offset);
+        let end_type = self.gen_expr(end);
+        self.gen_coercion(end_type, DataType::Long);
+        self.emit_store(DataType::Long, end_addr.as_str());
+
+        // The counter: a callee-saved register, or eax reloaded each time
+        let reg = self.loop_counter_reg(var, &info.loc, body);
+        let (_, counter, counter16) = reg.map_or(("rax", "eax", "ax"), |i| LOOP_REGS[i]);
+        self.emit_load(info.data_type, var_addr.clone());
+        if reg.is_some() {
+            self.emit(format_args!("    mov {}, eax", counter));
+            self.loop_regs += 1;
+            self.saved_regs = self.saved_regs.max(self.loop_regs);
+        }
+
+        // Skip a loop that runs zero times
+        let (skip, repeat) = if step < 0 {
+            ("jl", "jge")
+        } else {
+            ("jg", "jle")
+        };
+        self.emit(format_args!(
+            "    cmp {}, DWORD PTR [{}]",
+            counter, end_addr
+        ));
+        self.emit(format_args!("    {} {}", skip, end_label));
+
+        self.emit_label(&body_label);
+        self.emit_event_poll();
+        for s in body {
+            self.gen_stmt(s);
+        }
+
+        // NEXT: step the counter, store it, and repeat until it passes the end
+        if reg.is_none() {
+            self.emit_load(info.data_type, var_addr.clone());
+        }
+        match step {
+            1 => self.emit(format_args!("    inc {}", counter)),
+            -1 => self.emit(format_args!("    dec {}", counter)),
+            _ => self.emit(format_args!("    add {}, {}", counter, step)),
+        }
+        if self.overflow_check {
+            if reg.is_some() {
+                // mov leaves the flags for the overflow check
+                self.emit(format_args!("    mov eax, {}", counter));
             }
+            self.emit_overflow_check(info.data_type);
+        }
+        if info.data_type == DataType::Integer {
+            self.emit(format_args!(
+                "    mov WORD PTR [{}], {}",
+                var_addr, counter16
+            ));
+        } else {
+            self.emit(format_args!(
+                "    mov DWORD PTR [{}], {}",
+                var_addr, counter
+            ));
+        }
+        self.emit(format_args!(
+            "    cmp {}, DWORD PTR [{}]",
+            counter, end_addr
+        ));
+        self.emit(format_args!("    {} {}", repeat, body_label));
+
+        self.emit_label(&end_label);
+        if reg.is_some() {
+            self.loop_regs -= 1;
+        }
+        self.release_for_slots(&[end_offset], body);
+    }
+
+    /// A frame slot for a FOR loop's end or step value. A closed loop (see
+    /// closes_loop) reuses one a finished closed loop gave back; any other
+    /// gets its own, so no loop that might still be running shares it.
+    fn for_slot(&mut self, body: &[Stmt]) -> i32 {
+        match self.for_slots.pop() {
+            Some(offset) if self.closes_loop(body) => offset,
+            popped => {
+                self.for_slots.extend(popped);
+                self.stack_offset -= 8;
+                self.stack_offset
+            }
+        }
+    }
+
+    /// Give a finished closed loop's slots back for later ones
+    fn release_for_slots(&mut self, offsets: &[i32], body: &[Stmt]) {
+        if self.closes_loop(body) {
+            self.for_slots.extend(offsets.iter().rev());
         }
     }
 
+    /// Whether a FOR body can only be left for good while the loop runs: no
+    /// event handler or GOSUB can run other code and come back, no line in
+    /// it is a jump target, and it has no ASM. Two such loops are never
+    /// running at once unless one is inside the other.
+    fn closes_loop(&self, body: &[Stmt]) -> bool {
+        fn closed(codegen: &CodeGen, stmt: &Stmt) -> bool {
+            let leaves = match &stmt.kind {
+                StmtKind::Label(n) => codegen.jump_targets.contains(n),
+                StmtKind::Gosub(_) | StmtKind::Asm { .. } => true,
+                _ => false,
+            };
+            !leaves
+                && stmt
+                    .kind
+                    .bodies()
+                    .iter()
+                    .all(|body| body.iter().all(|s| closed(codegen, s)))
+        }
+        !self.events_used && body.iter().all(|s| closed(self, s))
+    }
+
+    /// Index into LOOP_REGS for an integer FOR counter, if one is free and
+    /// the variable can only change through the counter: it isn't SHARED
+    /// (a called procedure could assign it), there are no event handlers, and
+    /// the body doesn't assign it, GOSUB, POKE, BLOAD, use ASM or contain a
+    /// jump target
+    fn loop_counter_reg(&self, var: &str, loc: &Storage, body: &[Stmt]) -> Option<usize> {
+        let free = self.loop_regs < LOOP_REGS.len()
+            && !self.events_used
+            && (matches!(loc, Storage::Frame(_)) || !self.shared_names.contains(var))
+            && body.iter().all(|stmt| self.keeps_counter(stmt, var));
+        free.then_some(self.loop_regs)
+    }
+
+    fn keeps_counter(&self, stmt: &Stmt, var: &str) -> bool {
+        let keeps = match &stmt.kind {
+            StmtKind::Let {
+                name,
+                indices: None,
+                ..
+            } => name != var,
+            StmtKind::Input { vars, .. }
+            | StmtKind::Read(vars)
+            | StmtKind::InputFile { vars, .. } => !vars
+                .iter()
+                .any(|v| matches!(&v.kind, ExprKind::Variable(v) if v == var)),
+            StmtKind::LineInput {
+                var:
+                    Expr {
+                        kind: ExprKind::Variable(v),
+                        ..
+                    },
+                ..
+            }
+            | StmtKind::For { var: v, .. } => v != var,
+            StmtKind::Label(n) => !self.jump_targets.contains(n),
+            StmtKind::Gosub(_)
+            | StmtKind::Poke { .. }
+            | StmtKind::Bload { .. }
+            | StmtKind::Asm { .. } => false,
+            _ => true,
+        };
+        keeps
+            && stmt
+                .kind
+                .bodies()
+                .iter()
+                .all(|body| body.iter().all(|s| self.keeps_counter(s, var)))
+    }
+
+    /// FOR with a floating-point variable or a computed STEP: the loop runs
+    /// in Double, converting the variable from and to its own type
+    fn gen_float_for(
+        &mut self,
+        var: &str,
+        start: &Expr,
+        end: &Expr,
+        step: Option<&Expr>,
+        body: &[Stmt],
+    ) {
+        let start_label = self.new_label("for");
+        let end_label = self.new_label("endfor");
+        let info = self.get_var_info(var);
+        let var_addr = info.addr.clone();
+
+        // Initialize loop variable
+        let start_type = self.gen_expr(start);
+        self.gen_coercion(start_type, info.data_type);
+        self.emit_store(info.data_type, var_addr.clone());
+
+        // Store end value - coerce to double
+        let end_offset = self.for_slot(body);
+        let end_type = self.gen_expr(end);
+        self.gen_coercion(end_type, DataType::Double);
+        self.emit_store(DataType::Double, format!("rbp + {}", end_offset));
+
+        // Store step value - coerce to double
+        let step_offset = self.for_slot(body);
+        if let Some(s) = step {
+            let step_type = self.gen_expr(s);
+            self.gen_coercion(step_type, DataType::Double);
+        } else {
+            self.push(Inst::Const(Const::Double(1.0)));
+        }
+        self.emit(format_args!(
+            "    movsd QWORD PTR [rbp + {}], xmm0",
+            step_offset
+        ));
+
+        self.emit_label(&start_label);
+        self.emit_event_poll();
+
+        // Check condition (var > end for positive step, var < end for negative)
+        self.emit_load(info.data_type, var_addr.clone());
+        self.gen_coercion(info.data_type, DataType::Double);
+        self.emit(format_args!(
+            "    movsd xmm1, QWORD PTR [rbp + {}]",
+            end_offset
+        ));
+        self.emit(format_args!(
+            "    movsd xmm2, QWORD PTR [rbp + {}]",
+            step_offset
+        ));
+        self.emit("    xorpd xmm3, xmm3");
+        self.emit("    ucomisd xmm2, xmm3");
+        let n = self.label_counter;
+        self.emit(format_args!("    jb .Lfor_neg_{}", n));
+
+        // Positive step: exit if var > end
+        self.emit("    ucomisd xmm0, xmm1");
+        self.emit(format_args!("    ja {}", end_label));
+        self.emit(format_args!("    jmp .Lfor_body_{}", n));
+
+        // Negative step: exit if var < end
+        self.emit_label(&format!(".Lfor_neg_{}", self.label_counter));
+        self.emit("    ucomisd xmm0, xmm1");
+        self.emit(format_args!("    jb {}", end_label));
+
+        self.emit_label(&format!(".Lfor_body_{}", self.label_counter));
+        self.label_counter += 1;
+
+        // Body
+        for s in body {
+            self.gen_stmt(s);
+        }
+
+        // Increment
+        self.emit_load(info.data_type, var_addr.clone());
+        self.gen_coercion(info.data_type, DataType::Double);
+        self.emit(format_args!(
+            "    addsd xmm0, QWORD PTR [rbp + {}]",
+            step_offset
+        ));
+        self.gen_coercion(DataType::Double, info.data_type);
+        self.emit_store(info.data_type, var_addr.clone());
+        self.jump(&start_label);
+
+        self.emit_label(&end_label);
+        self.release_for_slots(&[end_offset, step_offset], body);
+    }
+
     /// Generate code for a binary expression
     fn gen_binary_expr(&mut self, op: BinaryOp, left: &Expr, right: &Expr) -> DataType {
-        // Track expression nesting depth and warn if too deep
-        self.expr_depth += 1;
-        if self.expr_depth == MAX_EXPR_DEPTH + 1 {
-            eprintln!(
-                "Warning: Expression nesting exceeds {} levels, stack overflow risk",
-                MAX_EXPR_DEPTH
-            );
-        }
-
-        let result_type = self.promote_types(self.expr_type(left), self.expr_type(right), op);
-
-        // Handle string concatenation specially
-        if result_type == DataType::String && op == BinaryOp::Add {
-            // Evaluate left string (ptr in rax, len in rdx)
-            self.gen_expr(left);
-            // Save left string on stack using consistent sub rsp pattern (16-byte aligned)
-            self.emit(&format!("    sub rsp, {}", STACK_TEMP_SPACE));
-            self.emit("    mov QWORD PTR [rsp], rax"); // left ptr
-            self.emit("    mov QWORD PTR [rsp + 8], rdx"); // left len
-
-            // Evaluate right string (ptr in rax, len in rdx)
-            self.gen_expr(right);
-            // Now: right ptr in rax, right len in rdx
-
-            // Call runtime string concat: rt_strcat(left_ptr, left_len, right_ptr, right_len)
-            // Save right string temporarily
-            self.emit("    mov r8, rax"); // right ptr
-            self.emit("    mov r9, rdx"); // right len
-            // Restore left string from stack
-            self.emit("    mov rax, QWORD PTR [rsp]"); // left ptr
-            self.emit("    mov rdx, QWORD PTR [rsp + 8]"); // left len
-            self.emit(&format!("    add rsp, {}", STACK_TEMP_SPACE));
-            self.emit_arg_reg(0, "rax"); // left ptr
-            self.emit_arg_reg(1, "rdx"); // left len
-            self.emit_arg_reg(2, "r8"); // right ptr
-            self.emit_arg_reg(3, "r9"); // right len
-            self.emit("    call _rt_strcat");
-            // Result: ptr in rax, len in rdx
-            self.expr_depth -= 1;
-            return DataType::String;
-        }
-
-        // For comparison/logical ops, we'll work in the promoted type but return Long
+        if matches!(op, BinaryOp::AndAlso | BinaryOp::OrElse) {
+            // -1 or 0, from branches
+            let false_label = self.new_label("false");
+            let end_label = self.new_label("endbool");
+            self.gen_short_circuit_branch(op, left, right, true, &false_label);
+            self.push(Inst::Const(Const::Long(-1)));
+            self.jump(&end_label);
+            self.emit_label(&false_label);
+            self.push(Inst::Const(Const::Long(0)));
+            self.emit_label(&end_label);
+            return DataType::Long;
+        }
+
+        let (left_type, right_type) = (self.expr_type(left), self.expr_type(right));
+        let result_type = types::promote(op, left_type, right_type);
+
+        // Strings: + concatenates; comparisons compare them in the runtime and
+        // then test its -1/0/1 result against 0
+        if left_type == DataType::String {
+            self.gen_string_operands(left, right);
+            let result_type = if op == BinaryOp::Add {
+                self.call("_rt_strcat");
+                // Result: ptr in rax, len in rdx
+                DataType::String
+            } else {
+                self.call("_rt_strcmp");
+                self.emit("    xor ecx, ecx");
+                self.push(Inst::Binary {
+                    op,
+                    ty: DataType::Long,
+                });
+                DataType::Long
+            };
+            return result_type;
+        }
+
+        // For comparison/logical ops, we'll work in the promoted type but return
+        // Long; \ and MOD too, so that they can round float operands
         let work_type = if matches!(
             op,
             BinaryOp::Eq
@@ -1439,131 +2481,73 @@ impl CodeGen {
                 | BinaryOp::And
                 | BinaryOp::Or
                 | BinaryOp::Xor
+                | BinaryOp::IntDiv
+                | BinaryOp::Mod
         ) {
-            self.promote_types(self.expr_type(left), self.expr_type(right), BinaryOp::Add)
+            types::widest(left_type, right_type)
         } else {
             result_type
         };
 
-        // Evaluate left operand and coerce to work type
+        // Evaluate left operand and coerce to work type, and save it
         let left_type = self.gen_expr(left);
         self.gen_coercion(left_type, work_type);
-
-        // Save left result - use 16 bytes to maintain 16-byte stack alignment
-        // This ensures any function calls while evaluating right operand have aligned stack
-        self.emit(&format!("    sub rsp, {}", STACK_TEMP_SPACE));
-        if work_type.is_integer() {
-            self.emit("    mov QWORD PTR [rsp], rax");
-        } else if work_type == DataType::Single {
-            self.emit("    movss DWORD PTR [rsp], xmm0");
-        } else {
-            self.emit("    movsd QWORD PTR [rsp], xmm0");
-        }
+        self.push(Inst::Push(work_type));
 
         // Evaluate right operand and coerce to work type
         let right_type = self.gen_expr(right);
         self.gen_coercion(right_type, work_type);
 
-        // Move right to secondary register/location and restore left
-        if work_type.is_integer() {
-            self.emit("    mov ecx, eax"); // right in ecx
-            self.emit("    mov rax, QWORD PTR [rsp]"); // left in rax
-        } else if work_type == DataType::Single {
-            self.emit("    movss xmm1, xmm0"); // right in xmm1
-            self.emit("    movss xmm0, DWORD PTR [rsp]"); // left in xmm0
-        } else {
-            self.emit("    movsd xmm1, xmm0"); // right in xmm1
-            self.emit("    movsd xmm0, QWORD PTR [rsp]"); // left in xmm0
-        }
-        self.emit(&format!("    add rsp, {}", STACK_TEMP_SPACE));
-
-        // Generate operation
-        match op {
-            BinaryOp::Add => self.emit_typed(
-                work_type,
-                "    add eax, ecx",
-                "    addss xmm0, xmm1",
-                "    addsd xmm0, xmm1",
-            ),
-            BinaryOp::Sub => self.emit_typed(
-                work_type,
-                "    sub eax, ecx",
-                "    subss xmm0, xmm1",
-                "    subsd xmm0, xmm1",
-            ),
-            BinaryOp::Mul => self.emit_typed(
-                work_type,
-                "    imul eax, ecx",
-                "    mulss xmm0, xmm1",
-                "    mulsd xmm0, xmm1",
-            ),
-            BinaryOp::Div => {
-                self.emit_cvt_to_double(work_type);
-                self.emit("    divsd xmm0, xmm1");
-            }
-            BinaryOp::IntDiv => {
-                self.emit_cvt_float_to_int(work_type);
-                self.emit("    cdq");
-                self.emit("    idiv ecx");
-            }
-            BinaryOp::Mod => {
-                self.emit_cvt_float_to_int(work_type);
-                self.emit("    cdq");
-                self.emit("    idiv ecx");
-                self.emit("    mov eax, edx");
-            }
-            BinaryOp::Pow => {
-                self.emit_cvt_to_double(work_type);
-                self.emit_call_libc("pow");
-            }
-            BinaryOp::Eq
-            | BinaryOp::Ne
-            | BinaryOp::Lt
-            | BinaryOp::Gt
-            | BinaryOp::Le
-            | BinaryOp::Ge => {
-                // (signed_setcc, unsigned_setcc) - signed for integers, unsigned for floats
-                let (signed, unsigned) = match op {
-                    BinaryOp::Eq => ("sete", "sete"),
-                    BinaryOp::Ne => ("setne", "setne"),
-                    BinaryOp::Lt => ("setl", "setb"),
-                    BinaryOp::Gt => ("setg", "seta"),
-                    BinaryOp::Le => ("setle", "setbe"),
-                    BinaryOp::Ge => ("setge", "setae"),
-                    _ => unreachable!(),
-                };
-                self.emit_typed(
-                    work_type,
-                    "    cmp eax, ecx",
-                    "    ucomiss xmm0, xmm1",
-                    "    ucomisd xmm0, xmm1",
-                );
-                let setcc = if work_type.is_integer() {
-                    signed
-                } else {
-                    unsigned
-                };
-                self.emit(&format!("    {} al", setcc));
-                self.emit("    movzx eax, al");
-                self.emit("    neg eax");
-            }
-            BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => {
-                self.emit_cvt_float_to_int(work_type);
-                let instr = match op {
-                    BinaryOp::And => "and",
-                    BinaryOp::Or => "or",
-                    BinaryOp::Xor => "xor",
-                    _ => unreachable!(),
-                };
-                self.emit(&format!("    {} eax, ecx", instr));
-            }
+        // Move right to the secondary register and restore left
+        self.push(Inst::PopRight(work_type));
+        self.push(Inst::Binary { op, ty: work_type });
+        if matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul) {
+            self.emit_overflow_check(work_type);
         }
 
-        self.expr_depth -= 1;
         result_type
     }
 
+    /// Evaluate two strings into the first four argument registers
+    /// (left ptr, left len, right ptr, right len) for a runtime call
+    fn gen_string_operands(&mut self, left: &Expr, right: &Expr) {
+        // Evaluate left string (ptr in rax, len in rdx)
+        self.gen_expr(left);
+        // Save left string on stack using consistent sub rsp pattern (16-byte aligned)
+        self.emit(format_args!("    sub rsp, {}", STACK_TEMP_SPACE));
+        self.emit("    mov QWORD PTR [rsp], rax"); // left ptr
+        self.emit("    mov QWORD PTR [rsp + 8], rdx"); // left len
+
+        // Evaluate right string (ptr in rax, len in rdx)
+        self.gen_expr(right);
+
+        // Save right string temporarily
+        self.emit("    mov r8, rax"); // right ptr
+        self.emit("    mov r9, rdx"); // right len
+        // Restore left string from stack
+        self.emit("    mov rax, QWORD PTR [rsp]"); // left ptr
+        self.emit("    mov rdx, QWORD PTR [rsp + 8]"); // left len
+        self.emit(format_args!("    add rsp, {}", STACK_TEMP_SPACE));
+        self.emit_arg_reg(0, "rax"); // left ptr
+        self.emit_arg_reg(1, "rdx"); // left len
+        self.emit_arg_reg(2, "r8"); // right ptr
+        self.emit_arg_reg(3, "r9"); // right len
+    }
+
     fn gen_print_expr(&mut self, expr: &Expr) {
+        // TAB(n) and SPC(n) move the cursor instead of printing a value
+        if let ExprKind::FnCall { name, args } = &expr.kind {
+            let func = match name.to_uppercase().as_str() {
+                "TAB" => Some("_rt_print_tab"),
+                "SPC" => Some("_rt_print_spc"),
+                _ => None,
+            };
+            if let (Some(func), [arg]) = (func, args.as_slice()) {
+                self.gen_runtime_call_int(func, &[IntArg::Expr(arg)]);
+                return;
+            }
+        }
+
         // Check the expression type first
         let expected_type = self.expr_type(expr);
 
@@ -1573,16 +2557,34 @@ impl CodeGen {
             self.gen_expr(expr);
             self.emit_arg_reg(0, "rax"); // ptr
             self.emit_arg_reg(1, "rdx"); // len
-            self.emit("    call _rt_print_string");
+            self.call("_rt_print_string");
         } else {
-            // Numeric expression - evaluate and convert to double for printing
+            // Numeric expression - evaluate and convert to double for printing;
+            // SINGLE values print to single precision
             let expr_type = self.gen_expr(expr);
             self.gen_coercion(expr_type, DataType::Double);
-            self.emit("    call _rt_print_float");
+            if expr_type == DataType::Single {
+                self.call("_rt_print_single");
+            } else {
+                self.call("_rt_print_float");
+            }
+        }
+    }
+
+    fn gen_print_expr_to_file(&mut self, expr: &Expr, file_num: i32) {
+        // TAB(n) and SPC(n) move the file's column just as on the console
+        if let ExprKind::FnCall { name, args } = &expr.kind {
+            let func = match name.to_uppercase().as_str() {
+                "TAB" => Some("_rt_file_print_tab"),
+                "SPC" => Some("_rt_file_print_spc"),
+                _ => None,
+            };
+            if let (Some(func), [arg]) = (func, args.as_slice()) {
+                self.gen_runtime_call_int(func, &[IntArg::Imm(file_num as i64), IntArg::Expr(arg)]);
+                return;
+            }
         }
-    }
 
-    fn gen_print_expr_to_file(&mut self, expr: &Expr, file_num: i32) {
         // Check the expression type first
         let expected_type = self.expr_type(expr);
 
@@ -1595,20 +2597,70 @@ impl CodeGen {
             self.emit_arg_reg(2, "rdx"); // len → r8 (on Win64) or rdx (on SysV, no-op)
             self.emit_arg_reg(1, "rax"); // ptr → rdx (on Win64) or rsi (on SysV)
             self.emit_arg_imm(0, file_num as i64); // file_num → rcx or rdi
-            self.emit("    call _rt_file_print_string");
+            self.call("_rt_file_print_string");
         } else {
             // Numeric expression - evaluate and convert to double for printing
             let expr_type = self.gen_expr(expr);
             self.gen_coercion(expr_type, DataType::Double);
             self.emit_arg_imm(0, file_num as i64);
-            self.emit("    call _rt_file_print_float");
+            if expr_type == DataType::Single {
+                self.call("_rt_file_print_single");
+            } else {
+                self.call("_rt_file_print_float");
+            }
+        }
+    }
+
+    /// Call a print routine whose other arguments (a number in xmm0, or
+    /// none) are already in place: the console one, or for PRINT # the file
+    /// one with the file number first
+    fn gen_print_call(&mut self, file_num: Option<i32>, console: &str, file: &str) {
+        match file_num {
+            Some(file_num) => {
+                self.emit_arg_imm(0, file_num as i64);
+                self.call(file);
+            }
+            None => self.call(console),
+        }
+    }
+
+    /// Call a print routine that takes the string in rax (ptr) and rdx (len)
+    fn gen_print_string_call(&mut self, file_num: Option<i32>, console: &str, file: &str) {
+        match file_num {
+            Some(file_num) => {
+                // On Win64 the length goes to r8 before rdx takes the pointer
+                self.emit_arg_reg(2, "rdx");
+                self.emit_arg_reg(1, "rax");
+                self.emit_arg_imm(0, file_num as i64);
+                self.call(file);
+            }
+            None => {
+                self.emit_arg_reg(0, "rax");
+                self.emit_arg_reg(1, "rdx");
+                self.call(console);
+            }
+        }
+    }
+
+    /// Print one character to the console or a file
+    fn gen_print_char(&mut self, file_num: Option<i32>, c: i64) {
+        match file_num {
+            Some(file_num) => {
+                self.emit_arg_imm(0, file_num as i64);
+                self.emit_arg_imm(1, c);
+                self.call("_rt_file_print_char");
+            }
+            None => {
+                self.emit_arg_imm(0, c);
+                self.call("_rt_print_char");
+            }
         }
     }
 
     fn gen_fn_call(&mut self, name: &str, args: &[Expr]) {
         let upper_name = name.to_uppercase();
 
-        // Table-driven: libc math functions (SIN, COS, TAN, ATN, EXP, LOG)
+        // Table-driven: libc math functions (SIN, COS, TAN, ATN, EXP, LOG, LOG10)
         if let Some(libc_fn) = LIBC_MATH_FNS.get(upper_name.as_str()) {
             let arg_type = self.gen_expr(&args[0]);
             self.gen_coercion(arg_type, DataType::Double);
@@ -1620,7 +2672,7 @@ impl CodeGen {
         if let Some(instr) = INLINE_MATH_FNS.get(upper_name.as_str()) {
             let arg_type = self.gen_expr(&args[0]);
             self.gen_coercion(arg_type, DataType::Double);
-            self.emit(&format!("    {}", instr));
+            self.emit(format_args!("    {}", instr));
             return;
         }
 
@@ -1645,12 +2697,46 @@ impl CodeGen {
                 self.emit("    sub eax, ecx");
                 self.emit("    cvtsi2sd xmm0, eax");
             }
+            "MIN" | "MAX" => {
+                // minsd/maxsd give the second operand when either is a NaN
+                let arg_type = self.gen_expr(&args[0]);
+                self.gen_coercion(arg_type, DataType::Double);
+                self.emit(format_args!("    sub rsp, {}", STACK_TEMP_SPACE));
+                self.emit("    movsd QWORD PTR [rsp], xmm0");
+                let arg_type = self.gen_expr(&args[1]);
+                self.gen_coercion(arg_type, DataType::Double);
+                self.emit("    movsd xmm1, xmm0");
+                self.emit("    movsd xmm0, QWORD PTR [rsp]");
+                self.emit(format_args!("    add rsp, {}", STACK_TEMP_SPACE));
+                let instr = if upper_name == "MIN" {
+                    "minsd"
+                } else {
+                    "maxsd"
+                };
+                self.emit(format_args!("    {} xmm0, xmm1", instr));
+            }
+            "ROUND" => {
+                // _rt_round(value in xmm0, digits), digits rounded to an
+                // integer and 0 if left out
+                let arg_type = self.gen_expr(&args[0]);
+                self.gen_coercion(arg_type, DataType::Double);
+                self.emit(format_args!("    sub rsp, {}", STACK_TEMP_SPACE));
+                self.emit("    movsd QWORD PTR [rsp], xmm0");
+                match args.get(1) {
+                    Some(digits) => self.gen_rounded_int(digits),
+                    None => self.emit("    xor eax, eax"),
+                }
+                self.emit_arg_reg(0, "rax");
+                self.emit("    movsd xmm0, QWORD PTR [rsp]");
+                self.emit(format_args!("    add rsp, {}", STACK_TEMP_SPACE));
+                self.call("_rt_round");
+            }
             "RND" => {
                 if !args.is_empty() {
                     let arg_type = self.gen_expr(&args[0]);
                     self.gen_coercion(arg_type, DataType::Double);
                 }
-                self.emit("    call _rt_rnd");
+                self.call("_rt_rnd");
             }
             "LEN" => {
                 self.gen_expr(&args[0]);
@@ -1666,15 +2752,15 @@ impl CodeGen {
                 self.emit("    mov r12, rax"); // save ptr
                 self.emit("    mov r13, rdx"); // save len
                 let count_type = self.gen_expr(&args[1]); // count - safe now
-                let arg2 = Self::arg_reg(2);
+                let arg2 = self.arg_reg(2);
                 if count_type.is_integer() {
-                    self.emit(&format!("    movsxd {}, eax", arg2));
+                    self.emit(format_args!("    movsxd {}, eax", arg2));
                 } else {
-                    self.emit(&format!("    cvttsd2si {}, xmm0", arg2));
+                    self.emit(format_args!("    cvttsd2si {}, xmm0", arg2));
                 }
                 self.emit_arg_reg(0, "r12"); // ptr
                 self.emit_arg_reg(1, "r13"); // len
-                self.emit("    call _rt_left");
+                self.call("_rt_left");
                 self.emit("    pop r13");
                 self.emit("    pop r12");
             }
@@ -1687,15 +2773,15 @@ impl CodeGen {
                 self.emit("    mov r12, rax"); // save ptr
                 self.emit("    mov r13, rdx"); // save len
                 let count_type = self.gen_expr(&args[1]); // count - safe now
-                let arg2 = Self::arg_reg(2);
+                let arg2 = self.arg_reg(2);
                 if count_type.is_integer() {
-                    self.emit(&format!("    movsxd {}, eax", arg2));
+                    self.emit(format_args!("    movsxd {}, eax", arg2));
                 } else {
-                    self.emit(&format!("    cvttsd2si {}, xmm0", arg2));
+                    self.emit(format_args!("    cvttsd2si {}, xmm0", arg2));
                 }
                 self.emit_arg_reg(0, "r12"); // ptr
                 self.emit_arg_reg(1, "r13"); // len
-                self.emit("    call _rt_right");
+                self.call("_rt_right");
                 self.emit("    pop r13");
                 self.emit("    pop r12");
             }
@@ -1714,119 +2800,75 @@ impl CodeGen {
                 } else {
                     self.emit("    cvttsd2si r14, xmm0"); // save start
                 }
-                let arg3 = Self::arg_reg(3);
+                let arg3 = self.arg_reg(3);
                 if args.len() > 2 {
                     let len_type = self.gen_expr(&args[2]); // count - safe now
                     if len_type.is_integer() {
-                        self.emit(&format!("    movsxd {}, eax", arg3));
+                        self.emit(format_args!("    movsxd {}, eax", arg3));
                     } else {
-                        self.emit(&format!("    cvttsd2si {}, xmm0", arg3));
+                        self.emit(format_args!("    cvttsd2si {}, xmm0", arg3));
                     }
                 } else {
-                    self.emit(&format!("    mov {}, -1", arg3)); // rest of string
+                    self.emit(format_args!("    mov {}, -1", arg3)); // rest of string
                 }
                 self.emit_arg_reg(0, "r12"); // ptr
                 self.emit_arg_reg(1, "r13"); // len
                 self.emit_arg_reg(2, "r14"); // start
-                self.emit("    call _rt_mid");
+                self.call("_rt_mid");
                 self.emit("    pop r14");
                 self.emit("    pop r13");
                 self.emit("    pop r12");
             }
-            "INSTR" => {
-                // INSTR([start,] haystack$, needle$)
-                // Args: haystack_ptr, haystack_len, needle_ptr, needle_len, start
-                let (start_arg, hay_arg, needle_arg) = if args.len() == 3 {
-                    (Some(&args[0]), &args[1], &args[2])
-                } else {
-                    (None, &args[0], &args[1])
-                };
-
-                // Evaluate and save start position
-                self.emit("    push rbx"); // save callee-saved reg
-                if let Some(start) = start_arg {
-                    let start_type = self.gen_expr(start);
-                    if start_type.is_integer() {
-                        self.emit("    movsxd rbx, eax");
-                    } else {
-                        self.emit("    cvttsd2si rbx, xmm0");
-                    }
-                } else {
-                    self.emit("    mov rbx, 1");
-                }
-
-                // Evaluate haystack and save
-                self.emit("    push r12");
-                self.emit("    push r13");
-                self.gen_expr(hay_arg);
-                self.emit("    mov r12, rax"); // haystack ptr
-                self.emit("    mov r13, rdx"); // haystack len
-
-                // Evaluate needle
-                self.gen_expr(needle_arg);
-                // rax = needle ptr, rdx = needle len
-
-                // Set up arguments based on ABI
-                // SysV: rdi=hay_ptr, rsi=hay_len, rdx=needle_ptr, rcx=needle_len, r8=start
-                // Win64: rcx=hay_ptr, rdx=hay_len, r8=needle_ptr, r9=needle_len, [rsp+32]=start
-                #[cfg(windows)]
-                {
-                    self.emit(&format!("    sub rsp, {}", WIN64_5ARG_STACK_SPACE));
-                    self.emit(&format!(
-                        "    mov QWORD PTR [rsp + {}], rbx",
-                        WIN64_5TH_ARG_OFFSET
-                    )); // 5th arg: start
-                    self.emit("    mov r9, rdx"); // needle len
-                    self.emit("    mov r8, rax"); // needle ptr
-                    self.emit("    mov rdx, r13"); // haystack len
-                    self.emit("    mov rcx, r12"); // haystack ptr
-                    self.emit("    call _rt_instr");
-                    self.emit(&format!("    add rsp, {}", WIN64_5ARG_STACK_SPACE));
-                }
-                #[cfg(not(windows))]
-                {
-                    self.emit("    mov r8, rbx"); // start
-                    self.emit("    mov rcx, rdx"); // needle len
-                    self.emit("    mov rdx, rax"); // needle ptr
-                    self.emit("    mov rsi, r13"); // haystack len
-                    self.emit("    mov rdi, r12"); // haystack ptr
-                    self.emit("    call _rt_instr");
-                }
-
-                self.emit("    pop r13");
-                self.emit("    pop r12");
-                self.emit("    pop rbx");
-                // Result is in rax
-                self.emit("    mov eax, eax"); // zero-extend/truncate to 32-bit
-            }
+            "INSTR" => self.gen_instr("_rt_instr", args),
+            "INSTRREV" => self.gen_instr("_rt_instrrev", args),
             "ASC" => {
+                // ASC(s$[, n]): the code of the nth character (the first by
+                // default) as a Long; an empty string or n outside 1 to
+                // LEN(s$) is an illegal function call
                 self.gen_expr(&args[0]);
-                self.emit("    movzx eax, BYTE PTR [rax]");
-                // ASC returns integer in eax (Long type)
+                match args.get(1) {
+                    Some(pos) => {
+                        self.emit(format_args!("    sub rsp, {}", STACK_TEMP_SPACE));
+                        self.emit("    mov QWORD PTR [rsp], rax");
+                        self.emit("    mov QWORD PTR [rsp + 8], rdx");
+                        self.gen_truncated_int(pos);
+                        self.emit("    mov rcx, rax");
+                        self.emit("    mov rax, QWORD PTR [rsp]");
+                        self.emit("    mov rdx, QWORD PTR [rsp + 8]");
+                        self.emit(format_args!("    add rsp, {}", STACK_TEMP_SPACE));
+                    }
+                    None => self.emit("    mov ecx, 1"),
+                }
+                self.emit("    cmp rcx, 1");
+                self.emit("    jl _rt_illegal_call");
+                self.emit("    cmp rcx, rdx");
+                self.emit("    jg _rt_illegal_call");
+                self.emit("    movzx eax, BYTE PTR [rax + rcx - 1]");
             }
             "CHR$" => {
-                // _rt_chr(char_code)
-                let arg_type = self.gen_expr(&args[0]);
-                let arg0 = Self::arg_reg(0);
-                if arg_type.is_integer() {
-                    self.emit(&format!("    movsxd {}, eax", arg0));
-                } else {
-                    self.emit(&format!("    cvttsd2si {}, xmm0", arg0));
-                }
-                self.emit("    call _rt_chr");
+                // _rt_chr(char_code); codes outside 0-255 are an illegal
+                // function call
+                self.gen_truncated_int(&args[0]);
+                self.emit("    cmp rax, 255");
+                self.emit("    ja _rt_illegal_call");
+                self.emit_arg_reg(0, "rax");
+                self.call("_rt_chr");
             }
             "VAL" => {
                 // _rt_val(ptr, len)
                 self.gen_expr(&args[0]);
                 self.emit_arg_reg(0, "rax"); // ptr
                 self.emit_arg_reg(1, "rdx"); // len
-                self.emit("    call _rt_val");
+                self.call("_rt_val");
             }
             "STR$" => {
+                // _rt_str(value in xmm0 as a double, significant digits):
+                // a SINGLE to its 7 digits, others as many as read back
                 let arg_type = self.gen_expr(&args[0]);
-                // STR$ expects double in xmm0
                 self.gen_coercion(arg_type, DataType::Double);
-                self.emit("    call _rt_str");
+                let digits = if arg_type == DataType::Single { 7 } else { 0 };
+                self.emit_arg_imm(0, digits);
+                self.call("_rt_str");
             }
             "CINT" | "CLNG" => {
                 let arg_type = self.gen_expr(&args[0]);
@@ -1846,11 +2888,143 @@ impl CodeGen {
                 self.gen_coercion(arg_type, DataType::Double);
             }
             "TIMER" => {
-                self.emit("    call _rt_timer");
+                self.call("_rt_timer");
+            }
+            "SHL" | "SHR" => {
+                // Shift the value rounded to a 64-bit integer (SHR is a
+                // logical shift); counts outside 0-63 give 0. The result is
+                // returned as a Double, exact up to 2^53.
+                self.gen_rounded_int(&args[0]);
+                self.emit(format_args!("    sub rsp, {}", STACK_TEMP_SPACE));
+                self.emit("    mov QWORD PTR [rsp], rax");
+                self.gen_rounded_int(&args[1]);
+                self.emit("    mov rcx, rax");
+                self.emit("    mov rax, QWORD PTR [rsp]");
+                self.emit(format_args!("    add rsp, {}", STACK_TEMP_SPACE));
+                let instr = if upper_name == "SHL" { "shl" } else { "shr" };
+                self.emit(format_args!("    {} rax, cl", instr));
+                self.emit("    xor edx, edx");
+                self.emit("    cmp rcx, 63");
+                self.emit("    cmova rax, rdx");
+                self.emit("    cvtsi2sd xmm0, rax");
+            }
+            "POINT" => {
+                // _rt_point(x, y) -> color index, or -1 if off screen
+                let args = [IntArg::Expr(&args[0]), IntArg::Expr(&args[1])];
+                self.gen_runtime_call_int("_rt_point", &args);
+            }
+            "RGB" => {
+                // _rt_rgb(red, green, blue) -> &HFFrrggbb
+                let args = [
+                    IntArg::Expr(&args[0]),
+                    IntArg::Expr(&args[1]),
+                    IntArg::Expr(&args[2]),
+                ];
+                self.gen_runtime_call_int("_rt_rgb", &args);
+            }
+            "MOUSEX" | "MOUSEY" | "MOUSEB" => {
+                // Pointer column, row and button mask from the last report
+                let func = match upper_name.as_str() {
+                    "MOUSEX" => "_rt_mouse_x",
+                    "MOUSEY" => "_rt_mouse_y",
+                    _ => "_rt_mouse_b",
+                };
+                self.call(func);
+            }
+            "STICK" | "STRIG" => {
+                // _rt_stick(axis) -> 1-200, _rt_strig(n) -> -1 or 0
+                let func = if upper_name == "STICK" {
+                    "_rt_stick"
+                } else {
+                    "_rt_strig"
+                };
+                self.gen_runtime_call_int(func, &[IntArg::Expr(&args[0])]);
+            }
+            "SEEK" => {
+                // _rt_file_seek_pos(file) -> position of the next read or write
+                self.gen_runtime_call_int("_rt_file_seek_pos", &[IntArg::Expr(&args[0])]);
+            }
+            "DICTNEW" => {
+                // _rt_dict_new() -> handle
+                self.call("_rt_dict_new");
+            }
+            "DICTCOUNT" => {
+                self.gen_runtime_call_int("_rt_dict_count", &[IntArg::Expr(&args[0])]);
+            }
+            "DICTKEY$" => {
+                // _rt_dict_key(dict, n) -> the nth key set
+                let args = [IntArg::Expr(&args[0]), IntArg::Expr(&args[1])];
+                self.gen_runtime_call_int("_rt_dict_key", &args);
+            }
+            "DICTGET$" | "DICTHAS" => {
+                // _rt_dict_get(dict, key, key_len) -> value ("" if missing),
+                // _rt_dict_has(dict, key, key_len) -> -1 or 0
+                let func = if upper_name == "DICTHAS" {
+                    "_rt_dict_has"
+                } else {
+                    "_rt_dict_get"
+                };
+                let (key_ptr, key_len) = self.gen_string_to_slots(&args[1]);
+                let args = [
+                    IntArg::Expr(&args[0]),
+                    IntArg::Slot(key_ptr),
+                    IntArg::Slot(key_len),
+                ];
+                self.gen_runtime_call_int(func, &args);
+            }
+            "SCREEN" => {
+                // _rt_screen_char(row, col) -> character code at the cell
+                let args = [IntArg::Expr(&args[0]), IntArg::Expr(&args[1])];
+                self.gen_runtime_call_int("_rt_screen_char", &args);
+            }
+            "LBOUND" => {
+                // Arrays always start at 0
+                self.emit("    xor eax, eax");
+            }
+            "UBOUND" => {
+                // UBOUND(array[, dimension]): elements in the dimension - 1
+                let array = match &args[0].kind {
+                    ExprKind::Variable(array)
+                    | ExprKind::ArrayAccess { name: array, .. }
+                    | ExprKind::FnCall { name: array, .. } => array.clone(),
+                    _ => panic!("UBOUND requires an array"),
+                };
+                match args.get(1) {
+                    Some(dim) => self.gen_rounded_int(dim),
+                    None => self.emit("    mov eax, 1"),
+                }
+                self.gen_array_desc(&array, "rcx");
+                self.emit("    cmp rax, 1");
+                self.emit("    jl _rt_illegal_call");
+                self.emit(format_args!(
+                    "    cmp rax, QWORD PTR [rcx + {}]",
+                    DESC_NDIMS
+                ));
+                self.emit("    jg _rt_illegal_call");
+                self.emit(format_args!(
+                    "    mov rax, QWORD PTR [rcx + rax*8 + {}]",
+                    DESC_DIMS - 8
+                ));
+                self.emit("    dec eax");
+            }
+            "VARPTR" => {
+                // Offset within the segment VARSEG maps the variable into
+                self.gen_var_address(&args[0]);
+                self.emit("    movzx eax, ax");
+            }
+            "VARSEG" => {
+                // _rt_varseg(address) -> segment that PEEK/POKE map onto it
+                self.gen_runtime_call_int("_rt_varseg", &[IntArg::Addr(&args[0])]);
+            }
+            "PEEK" => {
+                // _rt_peek(offset) -> byte at DEF SEG segment:offset
+                self.gen_runtime_call_int("_rt_peek", &[IntArg::Expr(&args[0])]);
             }
             _ => {
                 // User-defined function or array access
-                if self.arrays.contains_key(&upper_name) || upper_name.ends_with('$') {
+                if self.array_info(&upper_name).is_some()
+                    || (upper_name.ends_with('$') && !self.proc_params.contains_key(&upper_name))
+                {
                     // Array access
                     self.gen_array_load(&upper_name, args);
                 } else {
@@ -1861,56 +3035,216 @@ impl CodeGen {
         }
     }
 
+    /// Evaluate an expression and round it to a 64-bit integer in rax.
+    /// Graphics coordinates and colors round like CINT rather than truncate.
+    fn gen_rounded_int(&mut self, expr: &Expr) {
+        match self.gen_expr(expr) {
+            DataType::Integer | DataType::Long => self.emit("    movsxd rax, eax"),
+            DataType::Single => self.emit("    cvtss2si rax, xmm0"),
+            _ => self.emit("    cvtsd2si rax, xmm0"),
+        }
+    }
+
+    /// Evaluate an expression and truncate it to a 64-bit integer in rax,
+    /// as string positions are
+    fn gen_truncated_int(&mut self, expr: &Expr) {
+        match self.gen_expr(expr) {
+            DataType::Integer | DataType::Long => self.emit("    movsxd rax, eax"),
+            DataType::Single => self.emit("    cvttss2si rax, xmm0"),
+            _ => self.emit("    cvttsd2si rax, xmm0"),
+        }
+    }
+
+    /// INSTR([start,] haystack$, needle$) and INSTRREV, the same way:
+    /// `func`(hay_ptr, hay_len, needle_ptr, needle_len, start). Arguments
+    /// are evaluated left to right into a temp area; without a start,
+    /// INSTR searches from the first character and INSTRREV from the last.
+    fn gen_instr(&mut self, func: &str, args: &[Expr]) {
+        let (start, hay, needle) = match args {
+            [start, hay, needle] => (Some(start), hay, needle),
+            [hay, needle] => (None, hay, needle),
+            _ => unreachable!("INSTR arguments are checked before codegen"),
+        };
+        let temp_space = 48;
+        self.emit(format_args!("    sub rsp, {}", temp_space));
+        if let Some(start) = start {
+            self.gen_truncated_int(start);
+            self.emit("    mov QWORD PTR [rsp + 32], rax");
+        }
+        self.gen_expr(hay);
+        self.emit("    mov QWORD PTR [rsp], rax");
+        self.emit("    mov QWORD PTR [rsp + 8], rdx");
+        if start.is_none() {
+            let from = if func == "_rt_instrrev" { "rdx" } else { "1" };
+            self.emit(format_args!("    mov QWORD PTR [rsp + 32], {}", from));
+        }
+        self.gen_expr(needle);
+        self.emit("    mov QWORD PTR [rsp + 16], rax");
+        self.emit("    mov QWORD PTR [rsp + 24], rdx");
+        self.call_with_temps(func, 5, temp_space);
+    }
+
+    /// Load the address of a variable or array element into rax (VARPTR, VARSEG)
+    fn gen_var_address(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Variable(name) => {
+                let info = self.get_var_info(name);
+                self.emit(format_args!("    lea rax, [{}]", info.addr));
+            }
+            ExprKind::ArrayAccess { name, indices }
+            | ExprKind::FnCall {
+                name,
+                args: indices,
+            } if self.array_info(&name.to_uppercase()).is_some() => {
+                let name = name.to_uppercase();
+                self.gen_array_element_address(&name, indices);
+            }
+            _ => panic!("VARPTR and VARSEG require a variable or array element"),
+        }
+    }
+
+    /// Call a runtime function whose arguments are all integers.
+    /// Arguments are evaluated left to right into a temp area, then loaded
+    /// into registers (and stack slots past the register arguments).
+    fn gen_runtime_call_int(&mut self, func: &str, args: &[IntArg]) {
+        let temp_space = (args.len() as i32 * 8 + 15) & !15;
+        self.emit(format_args!("    sub rsp, {}", temp_space));
+        for (i, arg) in args.iter().enumerate() {
+            match arg {
+                IntArg::Expr(expr) => self.gen_rounded_int(expr),
+                IntArg::Imm(value) => self.emit(format_args!("    mov rax, {}", value)),
+                IntArg::Slot(offset) => {
+                    self.emit(format_args!("    mov rax, QWORD PTR [rbp + {}]", offset))
+                }
+                IntArg::Addr(expr) => self.gen_var_address(expr),
+                IntArg::Label(label) => self.emit(format_args!("    lea rax, [rip + {}]", label)),
+            }
+            self.emit(format_args!("    mov QWORD PTR [rsp + {}], rax", i * 8));
+        }
+        self.call_with_temps(func, args.len(), temp_space);
+    }
+
+    /// Call a runtime function with `count` integer arguments stored in
+    /// order at the bottom of the temp area, `temp_space` bytes, and free it
+    fn call_with_temps(&mut self, func: &str, count: usize, temp_space: i32) {
+        let max_reg_args = self.target.int_arg_regs().len();
+        let stack_args = count.saturating_sub(max_reg_args) as i32;
+        let shadow = self.target.shadow_space();
+        let frame = (shadow + stack_args * 8 + 15) & !15;
+        if frame > 0 {
+            self.emit(format_args!("    sub rsp, {}", frame));
+            for i in max_reg_args..count {
+                self.emit(format_args!(
+                    "    mov rax, QWORD PTR [rsp + {}]",
+                    frame + i as i32 * 8
+                ));
+                self.emit(format_args!(
+                    "    mov QWORD PTR [rsp + {}], rax",
+                    shadow + (i - max_reg_args) as i32 * 8
+                ));
+            }
+        }
+        for i in 0..count.min(max_reg_args) {
+            self.emit(format_args!(
+                "    mov {}, QWORD PTR [rsp + {}]",
+                self.arg_reg(i),
+                frame + i as i32 * 8
+            ));
+        }
+        self.call(func);
+        self.emit(format_args!("    add rsp, {}", frame + temp_space));
+    }
+
     fn gen_call(&mut self, name: &str, args: &[Expr]) {
-        let int_regs = PlatformAbi::INT_ARG_REGS;
+        if let Some((symbol, is_function)) = self.lib_procs.get(name).cloned() {
+            self.gen_lib_call(name, &symbol, is_function, args);
+            return;
+        }
+        let int_regs = self.target.int_arg_regs();
         let max_reg_args = int_regs.len();
 
         if args.is_empty() {
-            self.emit(&format!("    call _proc_{}", name));
+            self.call(&Self::proc_label(name));
+            self.emit_line_update();
             return;
         }
 
+        // Each argument is passed as its parameter's declared type; without a
+        // declaration, numbers are passed as Double. Arrays are passed as the
+        // address of their descriptor.
+        let params = self.proc_params.get(name).cloned().unwrap_or_default();
+        let param_types: Vec<DataType> = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| match params.get(i) {
+                Some(param) if param.is_array => DataType::Long,
+                Some(param) => param.data_type,
+                None if self.expr_type(arg) == DataType::String => DataType::String,
+                None => DataType::Double,
+            })
+            .collect();
+
         // Phase 1: Evaluate ALL arguments to stack temporaries
         // This prevents clobbering of registers when args contain nested function calls
-        // Each arg needs 8 bytes (numeric as double bits, string ptr only - len follows)
+        // Each arg needs 8 bytes (number in its type's format, string ptr only - len follows)
         let mut arg_info: Vec<(DataType, i32)> = Vec::new(); // (type, stack_offset)
 
         // Calculate total slots needed (strings need 2 slots: ptr + len)
-        let mut total_slots = 0;
-        for arg in args.iter() {
-            let arg_type = self.expr_type(arg);
-            if arg_type == DataType::String {
-                total_slots += 2; // ptr + len
-            } else {
-                total_slots += 1;
-            }
-        }
+        let total_slots: i32 = param_types
+            .iter()
+            .map(|t| if *t == DataType::String { 2 } else { 1 })
+            .sum();
 
         // Allocate stack space (16-byte aligned)
         let stack_space = (total_slots * 8 + 15) & !15;
-        self.emit(&format!("    sub rsp, {}", stack_space));
+        self.emit(format_args!("    sub rsp, {}", stack_space));
 
         // Evaluate each argument and save to stack
         let mut slot_offset = 0i32;
-        for arg in args.iter() {
+        for (i, (arg, &param_type)) in args.iter().zip(&param_types).enumerate() {
+            if params.get(i).is_some_and(|p| p.is_array) {
+                let array = match &arg.kind {
+                    ExprKind::Variable(array)
+                    | ExprKind::ArrayAccess { name: array, .. }
+                    | ExprKind::FnCall { name: array, .. } => array,
+                    _ => panic!("Argument {} of {} must be an array", i + 1, name),
+                };
+                self.gen_array_desc(array, "rax");
+                self.emit(format_args!(
+                    "    mov QWORD PTR [rsp + {}], rax",
+                    slot_offset
+                ));
+                arg_info.push((param_type, slot_offset));
+                slot_offset += 8;
+                continue;
+            }
             let arg_type = self.gen_expr(arg);
-            if arg_type == DataType::String {
+            if param_type == DataType::String {
                 // String: save ptr and len to consecutive slots
-                self.emit(&format!("    mov QWORD PTR [rsp + {}], rax", slot_offset));
-                self.emit(&format!(
+                self.emit(format_args!(
+                    "    mov QWORD PTR [rsp + {}], rax",
+                    slot_offset
+                ));
+                self.emit(format_args!(
                     "    mov QWORD PTR [rsp + {}], rdx",
                     slot_offset + 8
                 ));
-                arg_info.push((arg_type, slot_offset));
+                arg_info.push((param_type, slot_offset));
                 slot_offset += 16;
             } else {
-                // Numeric: coerce to double and save
-                self.gen_coercion(arg_type, DataType::Double);
-                self.emit(&format!(
-                    "    movsd QWORD PTR [rsp + {}], xmm0",
-                    slot_offset
-                ));
-                arg_info.push((arg_type, slot_offset));
+                // Numeric: coerce to the parameter type and save
+                self.gen_coercion(arg_type, param_type);
+                let store = match param_type {
+                    DataType::Integer | DataType::Long => {
+                        format!("    mov QWORD PTR [rsp + {}], rax", slot_offset)
+                    }
+                    DataType::Single => {
+                        format!("    movss DWORD PTR [rsp + {}], xmm0", slot_offset)
+                    }
+                    _ => format!("    movsd QWORD PTR [rsp + {}], xmm0", slot_offset),
+                };
+                self.emit(&store);
+                arg_info.push((param_type, slot_offset));
                 slot_offset += 8;
             }
         }
@@ -1931,7 +3265,7 @@ impl CodeGen {
         // Phase 3: Handle overflow args (push to call stack for >6 params)
         let overflow_space = if overflow_slots > 0 {
             let space = ((overflow_slots * 8 + 15) & !15) as i32;
-            self.emit(&format!("    sub rsp, {}", space));
+            self.emit(format_args!("    sub rsp, {}", space));
 
             // Copy overflow args from temp stack to call stack
             let mut reg_count = 0;
@@ -1941,21 +3275,21 @@ impl CodeGen {
                     // String takes 2 register slots
                     if reg_count >= max_reg_args {
                         // Both ptr and len are overflow
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    mov rax, QWORD PTR [rsp + {} + {}]",
                             space, temp_offset
                         ));
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    mov QWORD PTR [rsp + {}], rax",
                             overflow_idx * 8
                         ));
                         overflow_idx += 1;
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    mov rax, QWORD PTR [rsp + {} + {}]",
                             space,
                             temp_offset + 8
                         ));
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    mov QWORD PTR [rsp + {}], rax",
                             overflow_idx * 8
                         ));
@@ -1963,12 +3297,12 @@ impl CodeGen {
                     } else if reg_count + 1 >= max_reg_args {
                         // Only len is overflow (ptr fits in last register)
                         reg_count += 1; // ptr in register
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    mov rax, QWORD PTR [rsp + {} + {}]",
                             space,
                             temp_offset + 8
                         ));
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    mov QWORD PTR [rsp + {}], rax",
                             overflow_idx * 8
                         ));
@@ -1978,11 +3312,11 @@ impl CodeGen {
                 } else {
                     if reg_count >= max_reg_args {
                         // This arg is overflow
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    mov rax, QWORD PTR [rsp + {} + {}]",
                             space, temp_offset
                         ));
-                        self.emit(&format!(
+                        self.emit(format_args!(
                             "    mov QWORD PTR [rsp + {}], rax",
                             overflow_idx * 8
                         ));
@@ -2006,14 +3340,14 @@ impl CodeGen {
             if *arg_type == DataType::String {
                 // String: load ptr and len into consecutive registers
                 if reg_idx < max_reg_args {
-                    self.emit(&format!(
+                    self.emit(format_args!(
                         "    mov {}, QWORD PTR [rsp + {} + {}]",
                         int_regs[reg_idx], base_offset, temp_offset
                     ));
                     reg_idx += 1;
                 }
                 if reg_idx < max_reg_args {
-                    self.emit(&format!(
+                    self.emit(format_args!(
                         "    mov {}, QWORD PTR [rsp + {} + {}]",
                         int_regs[reg_idx],
                         base_offset,
@@ -2023,7 +3357,7 @@ impl CodeGen {
                 }
             } else {
                 // Numeric: load as 64-bit value
-                self.emit(&format!(
+                self.emit(format_args!(
                     "    mov {}, QWORD PTR [rsp + {} + {}]",
                     int_regs[reg_idx], base_offset, temp_offset
                 ));
@@ -2032,73 +3366,323 @@ impl CodeGen {
         }
 
         // Make the call
-        self.emit(&format!("    call _proc_{}", name));
+        self.call(&Self::proc_label(name));
 
         // Clean up: overflow space + temp stack space
         let total_cleanup = overflow_space + stack_space;
-        self.emit(&format!("    add rsp, {}", total_cleanup));
+        self.emit(format_args!("    add rsp, {}", total_cleanup));
+        self.emit_line_update();
     }
 
-    fn gen_dim_array(&mut self, arr: &ArrayDecl) {
-        let elem_size = if is_string_var(&arr.name) { 16 } else { 8 };
-
-        // First, evaluate and store all dimension bounds
-        // BASIC DIM A(N) means indices 0..N (N+1 elements), so add 1 to each bound
-        let mut dim_offsets = Vec::new();
-        for dim in arr.dimensions.iter() {
-            let dim_type = self.gen_expr(dim);
-            if dim_type.is_integer() {
-                // Value already in eax, sign-extend to rax
-                self.emit("    movsxd rax, eax");
+    /// An ASM line with each `{NAME}` replaced by the variable's memory
+    /// operand, as `[rbp + -8]` or `[rip + _var_N]`
+    fn asm_operands(&mut self, line: &str) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some((before, after)) = rest.split_once('{') {
+            let (name, after) = after.split_once('}').expect("checked by the parser");
+            let info = self.get_var_info(name);
+            write!(out, "{}[{}]", before, info.addr).expect("writing to a String");
+            rest = after;
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Call a C function DECLAREd with LIB. Arguments are passed by value
+    /// in the target's C ABI: INTEGER and LONG as 64-bit integers, SINGLE
+    /// as float and DOUBLE as double in the XMM registers, and strings as a
+    /// pointer to a NUL-terminated copy. A FUNCTION's result is read as
+    /// its suffix's type; a string result is copied from the C string.
+    fn gen_lib_call(&mut self, name: &str, symbol: &str, is_function: bool, args: &[Expr]) {
+        let params = self.proc_params.get(name).cloned().unwrap_or_default();
+        let arg_types: Vec<DataType> = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| match params.get(i) {
+                Some(param) => param.data_type,
+                None if self.expr_type(arg) == DataType::String => DataType::String,
+                None => DataType::Double,
+            })
+            .collect();
+
+        // Evaluate every argument into a temp area first, as gen_call does
+        let temp_space = (args.len() as i32 * 8 + 15) & !15;
+        if temp_space > 0 {
+            self.emit(format_args!("    sub rsp, {}", temp_space));
+        }
+        for (i, (arg, &arg_type)) in args.iter().zip(&arg_types).enumerate() {
+            let expr_type = self.gen_expr(arg);
+            let slot = i * 8;
+            match arg_type {
+                DataType::String => {
+                    // _rt_strcat(ptr, len, ptr, 0) -> NUL-terminated copy
+                    self.emit_arg_reg(0, "rax");
+                    self.emit_arg_reg(1, "rdx");
+                    self.emit_arg_reg(2, "rax");
+                    self.emit_arg_imm(3, 0);
+                    self.call("_rt_strcat");
+                    self.emit(format_args!("    mov QWORD PTR [rsp + {}], rax", slot));
+                }
+                DataType::Integer | DataType::Long => {
+                    self.gen_coercion(expr_type, arg_type);
+                    self.emit("    movsxd rax, eax");
+                    self.emit(format_args!("    mov QWORD PTR [rsp + {}], rax", slot));
+                }
+                DataType::Single => {
+                    self.gen_coercion(expr_type, arg_type);
+                    self.emit(format_args!("    movss DWORD PTR [rsp + {}], xmm0", slot));
+                }
+                _ => {
+                    self.gen_coercion(expr_type, arg_type);
+                    self.emit(format_args!("    movsd QWORD PTR [rsp + {}], xmm0", slot));
+                }
+            }
+        }
+
+        // Win64 gives each argument the register of its position, integer
+        // or XMM; System V numbers the two kinds separately. The rest go on
+        // the stack, in order.
+        let int_regs = self.target.int_arg_regs();
+        let windows = self.target == Target::Windows;
+        let (mut ints, mut floats) = (0, 0);
+        let mut regs = Vec::new(); // (argument, register)
+        let mut stack = Vec::new(); // arguments passed on the stack
+        for (i, arg_type) in arg_types.iter().enumerate() {
+            let float = matches!(arg_type, DataType::Single | DataType::Double);
+            let reg = if windows && i >= int_regs.len() {
+                None
+            } else if windows && float {
+                Some(format!("xmm{}", i))
+            } else if windows {
+                Some(int_regs[i].to_string())
+            } else if float {
+                floats += 1;
+                (floats <= 8).then(|| format!("xmm{}", floats - 1))
             } else {
-                self.emit("    cvttsd2si rax, xmm0");
+                ints += 1;
+                int_regs.get(ints - 1).map(|reg| reg.to_string())
+            };
+            match reg {
+                Some(reg) => regs.push((i, reg)),
+                None => stack.push(i),
             }
-            self.emit("    inc rax"); // DIM A(N) has N+1 elements (0 to N)
-            self.stack_offset -= 8;
-            dim_offsets.push(self.stack_offset);
-            self.emit(&format!(
-                "    mov QWORD PTR [rbp + {}], rax",
-                self.stack_offset
-            ));
         }
 
-        // Calculate total elements: dim0 * dim1 * dim2 * ...
-        self.emit(&format!(
-            "    mov rax, QWORD PTR [rbp + {}]",
-            dim_offsets[0]
-        ));
-        for offset in dim_offsets.iter().skip(1) {
-            self.emit(&format!("    imul rax, QWORD PTR [rbp + {}]", offset));
+        let frame = (stack.len() as i32 * 8 + 15) & !15;
+        if frame > 0 {
+            self.emit(format_args!("    sub rsp, {}", frame));
+            for (k, i) in stack.iter().enumerate() {
+                self.emit(format_args!(
+                    "    mov rax, QWORD PTR [rsp + {}]",
+                    frame + *i as i32 * 8
+                ));
+                self.emit(format_args!("    mov QWORD PTR [rsp + {}], rax", k * 8));
+            }
+        }
+        for (i, reg) in &regs {
+            let slot = frame + *i as i32 * 8;
+            match arg_types[*i] {
+                DataType::Single => self.emit(format_args!(
+                    "    movss {}, DWORD PTR [rsp + {}]",
+                    reg, slot
+                )),
+                DataType::Double => self.emit(format_args!(
+                    "    movsd {}, QWORD PTR [rsp + {}]",
+                    reg, slot
+                )),
+                _ => self.emit(format_args!("    mov {}, QWORD PTR [rsp + {}]", reg, slot)),
+            }
+        }
+        if !windows {
+            // Variadic functions like printf read the XMM register count in al
+            self.emit(format_args!("    mov eax, {}", floats.min(8)));
+        }
+        self.emit_call_libc(symbol);
+        if frame + temp_space > 0 {
+            self.emit(format_args!("    add rsp, {}", frame + temp_space));
+        }
+
+        if is_function {
+            match DataType::of_name(name, self.dialect) {
+                DataType::Integer => self.emit("    movsx eax, ax"),
+                DataType::String => {
+                    // A NULL result is the empty string
+                    let copy_label = self.new_label("lib_str");
+                    let done_label = self.new_label("lib_str_done");
+                    let empty = self.add_string_literal("");
+                    self.emit("    test rax, rax");
+                    self.emit(format_args!("    jnz {}", copy_label));
+                    self.emit(format_args!("    lea rax, [rip + _str_{}]", empty));
+                    self.emit("    xor edx, edx");
+                    self.jump(&done_label);
+                    self.emit_label(&copy_label);
+                    self.emit("    sub rsp, 16");
+                    self.emit("    mov QWORD PTR [rsp], rax");
+                    self.emit_arg_reg(0, "rax");
+                    self.emit_call_libc("strlen");
+                    // _rt_strcat(ptr, len, ptr, 0) -> a copy BASIC owns
+                    self.emit_arg_reg(1, "rax");
+                    let ptr = self.arg_reg(0);
+                    self.emit(format_args!("    mov {}, QWORD PTR [rsp]", ptr));
+                    self.emit_arg_reg(2, ptr);
+                    self.emit_arg_imm(3, 0);
+                    self.call("_rt_strcat");
+                    self.emit("    add rsp, 16");
+                    self.emit_label(&done_label);
+                }
+                // LONG in eax, SINGLE and DOUBLE in xmm0
+                _ => {}
+            }
         }
+        self.emit_line_update();
+    }
 
-        // Allocate: total_elements * elem_size
-        let arg0 = Self::arg_reg(0);
-        self.emit(&format!("    imul {}, rax, {}", arg0, elem_size));
-        self.emit_call_libc("malloc");
+    /// DIM allocates the array and builds its descriptor in the frame
+    /// (statically in the main program):
+    /// [DESC_DATA] data pointer, [DESC_COUNT] total elements,
+    /// [DESC_NDIMS] number of dimensions, [DESC_DIMS + 8*i] elements in dimension i
+    ///
+    /// A main-program array with constant bounds has its elements in .bss
+    /// too; others are allocated with malloc when the DIM runs.
+    fn gen_dim_array(&mut self, arr: &ArrayDecl) {
+        let elem_size = elem_size(&arr.name);
+        let ndims = arr.dimensions.len() as i32;
+        let desc = if self.current_proc.is_none() {
+            self.static_array(&arr.name, ndims)
+        } else {
+            self.stack_offset -= DESC_DIMS + 8 * ndims;
+            Storage::Frame(self.stack_offset)
+        };
+        let static_counts = match self.current_proc {
+            None => constant_dims(&arr.dimensions, elem_size),
+            Some(_) => None,
+        };
 
-        // Store array pointer
-        self.stack_offset -= 8;
-        let ptr_offset = self.stack_offset;
-        self.emit(&format!("    mov QWORD PTR [rbp + {}], rax", ptr_offset));
+        if let Some(counts) = static_counts {
+            for (i, count) in counts.iter().enumerate() {
+                self.emit(format_args!(
+                    "    mov QWORD PTR [{}], {}",
+                    desc.addr(DESC_DIMS + 8 * i as i32),
+                    count
+                ));
+            }
+            let total: i32 = counts.iter().product();
+            self.emit(format_args!(
+                "    mov QWORD PTR [{}], {}",
+                desc.addr(DESC_COUNT),
+                total
+            ));
+            self.emit(format_args!(
+                "    mov QWORD PTR [{}], {}",
+                desc.addr(DESC_NDIMS),
+                ndims
+            ));
+
+            // The elements, sized for the largest DIM of this name
+            let data = Self::mangle("_arrdata_", &arr.name);
+            let size = self.statics.get(&data).map_or(0, |&(_, size)| size);
+            self.statics
+                .insert(data.clone(), (0, size.max(total * elem_size)));
+            // A DIM that runs again starts over with zeroes, as malloc'd
+            // storage would; the first time, the data pointer is still 0
+            // and .bss is already clear
+            let fresh = self.new_label("dim_fresh");
+            self.emit(format_args!(
+                "    cmp QWORD PTR [{}], 0",
+                desc.addr(DESC_DATA)
+            ));
+            self.emit(format_args!("    je {}", fresh));
+            let args = [self.arg_reg(0), self.arg_reg(1), self.arg_reg(2)];
+            self.emit(format_args!("    lea {}, [rip + {}]", args[0], data));
+            self.emit(format_args!("    mov {}, 0", args[1]));
+            self.emit(format_args!("    mov {}, {}", args[2], total * elem_size));
+            self.emit_call_libc("memset");
+            self.emit_label(&fresh);
+            self.emit(format_args!("    lea rax, [rip + {}]", data));
+            self.emit(format_args!(
+                "    mov QWORD PTR [{}], rax",
+                desc.addr(DESC_DATA)
+            ));
+        } else {
+            // First, evaluate and store all dimension bounds
+            // BASIC DIM A(N) means indices 0..N (N+1 elements), so add 1 to each bound
+            for (i, dim) in arr.dimensions.iter().enumerate() {
+                let dim_type = self.gen_expr(dim);
+                if dim_type.is_integer() {
+                    // Value already in eax, sign-extend to rax
+                    self.emit("    movsxd rax, eax");
+                } else {
+                    self.emit("    cvttsd2si rax, xmm0");
+                }
+                self.emit("    inc rax"); // DIM A(N) has N+1 elements (0 to N)
+                self.emit(format_args!(
+                    "    mov QWORD PTR [{}], rax",
+                    desc.addr(DESC_DIMS + 8 * i as i32)
+                ));
+            }
+
+            // Calculate total elements: dim0 * dim1 * dim2 * ...
+            self.emit(format_args!(
+                "    mov rax, QWORD PTR [{}]",
+                desc.addr(DESC_DIMS)
+            ));
+            for i in 1..ndims {
+                self.emit(format_args!(
+                    "    imul rax, QWORD PTR [{}]",
+                    desc.addr(DESC_DIMS + 8 * i)
+                ));
+            }
+            self.emit(format_args!(
+                "    mov QWORD PTR [{}], rax",
+                desc.addr(DESC_COUNT)
+            ));
+            self.emit(format_args!(
+                "    mov QWORD PTR [{}], {}",
+                desc.addr(DESC_NDIMS),
+                ndims
+            ));
+
+            // Allocate: total_elements * elem_size
+            let arg0 = self.arg_reg(0);
+            self.emit(format_args!("    imul {}, rax, {}", arg0, elem_size));
+            self.emit_call_libc("malloc");
+
+            // Store array pointer
+            self.emit(format_args!(
+                "    mov QWORD PTR [{}], rax",
+                desc.addr(DESC_DATA)
+            ));
+        }
 
         // Record array info
-        self.arrays.insert(
-            arr.name.clone(),
-            ArrayInfo {
-                ptr_offset,
-                dim_offsets,
-            },
-        );
+        let info = ArrayInfo { desc, byref: false };
+        self.scope_mut().arrays.insert(arr.name.clone(), info);
     }
 
-    fn gen_array_load(&mut self, name: &str, indices: &[Expr]) {
-        let arr_info = self.arrays.get(name).expect("Array not declared");
-        let ptr_offset = arr_info.ptr_offset;
-        let dim_offsets = arr_info.dim_offsets.clone();
-        let elem_size = if is_string_var(name) { 16 } else { 8 };
+    /// Look up an array in the current scope
+    fn array_info(&self, name: &str) -> Option<ArrayInfo> {
+        self.scope().arrays.get(name).cloned()
+    }
+
+    /// Load the address of an array's descriptor into a register
+    fn gen_array_desc(&mut self, name: &str, reg: &str) {
+        let info = self.array_info(name).expect("Array not declared");
+        if info.byref {
+            self.emit(format_args!(
+                "    mov {}, QWORD PTR [{}]",
+                reg,
+                info.desc.addr(0)
+            ));
+        } else {
+            self.emit(format_args!("    lea {}, [{}]", reg, info.desc.addr(0)));
+        }
+    }
 
-        // Calculate linear index using row-major order:
-        // For A(i, j, k): linear = ((i * dim1) + j) * dim2 + k
+    /// Compute the row-major linear index of an array element into rax.
+    /// For A(i, j, k): linear = ((i * dim1) + j) * dim2 + k
+    /// A subscript outside its dimension stops with "Subscript out of range".
+    fn gen_array_index(&mut self, name: &str, indices: &[Expr]) {
         // Start with first index
         let idx_type = self.gen_expr(&indices[0]);
         if idx_type.is_integer() {
@@ -2107,11 +3691,22 @@ impl CodeGen {
         } else {
             self.emit("    cvttsd2si rax, xmm0");
         }
+        // So is a wrong number of subscripts. The compare is unsigned, so
+        // negative subscripts are out of range too.
+        self.gen_array_desc(name, "rdx");
+        self.emit(format_args!(
+            "    cmp QWORD PTR [rdx + {}], {}",
+            DESC_NDIMS,
+            indices.len()
+        ));
+        self.emit("    jne _rt_subscript_range");
+        self.emit(format_args!("    cmp rax, QWORD PTR [rdx + {}]", DESC_DIMS));
+        self.emit("    jae _rt_subscript_range");
 
         // For each subsequent index, multiply by dimension bound and add
         for (i, idx_expr) in indices.iter().enumerate().skip(1) {
             // Save current accumulated index - use 16 bytes for alignment
-            self.emit(&format!("    sub rsp, {}", STACK_TEMP_SPACE));
+            self.emit(format_args!("    sub rsp, {}", STACK_TEMP_SPACE));
             self.emit("    mov QWORD PTR [rsp], rax");
             // Evaluate next index
             let idx_type = self.gen_expr(idx_expr);
@@ -2121,146 +3716,133 @@ impl CodeGen {
                 self.emit("    cvttsd2si rcx, xmm0");
             }
             self.emit("    mov rax, QWORD PTR [rsp]");
-            self.emit(&format!("    add rsp, {}", STACK_TEMP_SPACE));
+            self.emit(format_args!("    add rsp, {}", STACK_TEMP_SPACE));
             // rax = rax * dim[i] + indices[i]
-            self.emit(&format!(
-                "    imul rax, QWORD PTR [rbp + {}]",
-                dim_offsets[i]
-            ));
+            self.gen_array_desc(name, "rdx");
+            let dim = DESC_DIMS + 8 * i as i32;
+            self.emit(format_args!("    cmp rcx, QWORD PTR [rdx + {}]", dim));
+            self.emit("    jae _rt_subscript_range");
+            self.emit(format_args!("    imul rax, QWORD PTR [rdx + {}]", dim));
             self.emit("    add rax, rcx");
         }
+    }
 
-        // Multiply by element size and add to base pointer
-        self.emit(&format!("    imul rax, {}", elem_size));
-        self.emit(&format!("    add rax, QWORD PTR [rbp + {}]", ptr_offset));
+    /// Compute the address of an array element into rax
+    fn gen_array_element_address(&mut self, name: &str, indices: &[Expr]) {
+        let elem_size = elem_size(name);
+        self.gen_array_index(name, indices);
+        self.emit(format_args!("    imul rax, {}", elem_size));
+        self.gen_array_desc(name, "rcx");
+        self.emit(format_args!("    add rax, QWORD PTR [rcx + {}]", DESC_DATA));
+    }
 
-        // Load value from computed address
-        if is_string_var(name) {
-            self.emit("    mov rcx, rax");
-            self.emit("    mov rax, QWORD PTR [rcx]");
-            self.emit("    mov rdx, QWORD PTR [rcx + 8]");
+    /// Compute the bytes of an array from an element (or the start) to its
+    /// end, for statements that use an array as a raw buffer (GET/PUT).
+    /// Returns frame slots holding the start address and the byte count.
+    fn gen_array_span(&mut self, name: &str, indices: &[Expr]) -> (i32, i32) {
+        let elem_size = elem_size(name);
+
+        if indices.is_empty() {
+            self.emit("    xor eax, eax");
         } else {
-            self.emit("    movsd xmm0, QWORD PTR [rax]");
+            self.gen_array_index(name, indices);
         }
+
+        // rcx = bytes from the element to the end (0 if past the end)
+        self.gen_array_desc(name, "rdx");
+        self.emit(format_args!(
+            "    mov rcx, QWORD PTR [rdx + {}]",
+            DESC_COUNT
+        ));
+        self.emit("    sub rcx, rax");
+        self.emit(format_args!("    imul rcx, rcx, {}", elem_size));
+        self.emit(format_args!("    imul rax, rax, {}", elem_size));
+        self.emit(format_args!("    add rax, QWORD PTR [rdx + {}]", DESC_DATA));
+        self.emit("    xor edx, edx");
+        self.emit("    test rcx, rcx");
+        self.emit("    cmovs rcx, rdx");
+
+        self.stack_offset -= 8;
+        let buf_offset = self.stack_offset;
+        self.stack_offset -= 8;
+        let size_offset = self.stack_offset;
+        self.emit(format_args!(
+            "    mov QWORD PTR [rbp + {}], rax",
+            buf_offset
+        ));
+        self.emit(format_args!(
+            "    mov QWORD PTR [rbp + {}], rcx",
+            size_offset
+        ));
+        (buf_offset, size_offset)
     }
 
-    fn gen_array_store(&mut self, name: &str, indices: &[Expr], value: &Expr) {
-        let arr_info = self.arrays.get(name).expect("Array not declared");
-        let ptr_offset = arr_info.ptr_offset;
-        let dim_offsets = arr_info.dim_offsets.clone();
-        let elem_size = if is_string_var(name) { 16 } else { 8 };
+    /// Evaluate a string expression and store its pointer and length in new
+    /// frame slots, so it can be passed to gen_runtime_call_int.
+    fn gen_string_to_slots(&mut self, expr: &Expr) -> (i32, i32) {
+        self.gen_expr(expr);
+        self.stack_offset -= 8;
+        let ptr_offset = self.stack_offset;
+        self.stack_offset -= 8;
+        let len_offset = self.stack_offset;
+        self.emit(format_args!(
+            "    mov QWORD PTR [rbp + {}], rax",
+            ptr_offset
+        ));
+        self.emit(format_args!(
+            "    mov QWORD PTR [rbp + {}], rdx",
+            len_offset
+        ));
+        (ptr_offset, len_offset)
+    }
 
-        // Calculate linear index using row-major order (same as gen_array_load)
-        let idx_type = self.gen_expr(&indices[0]);
-        if idx_type.is_integer() {
-            self.emit("    movsxd rax, eax");
-        } else {
-            self.emit("    cvttsd2si rax, xmm0");
-        }
+    fn gen_array_load(&mut self, name: &str, indices: &[Expr]) {
+        self.gen_array_element_address(name, indices);
 
-        for (i, idx_expr) in indices.iter().enumerate().skip(1) {
-            // Save current accumulated index - use 16 bytes for alignment
-            self.emit(&format!("    sub rsp, {}", STACK_TEMP_SPACE));
-            self.emit("    mov QWORD PTR [rsp], rax");
-            let idx_type = self.gen_expr(idx_expr);
-            if idx_type.is_integer() {
-                self.emit("    movsxd rcx, eax");
-            } else {
-                self.emit("    cvttsd2si rcx, xmm0");
-            }
-            self.emit("    mov rax, QWORD PTR [rsp]");
-            self.emit(&format!("    add rsp, {}", STACK_TEMP_SPACE));
-            self.emit(&format!(
-                "    imul rax, QWORD PTR [rbp + {}]",
-                dim_offsets[i]
-            ));
-            self.emit("    add rax, rcx");
+        // Load value from computed address
+        let elem_type = DataType::of_name(name, self.dialect);
+        if elem_type == DataType::String {
+            self.emit("    mov rcx, rax");
+            self.emit("    mov rax, QWORD PTR [rcx]");
+            self.emit("    mov rdx, QWORD PTR [rcx + 8]");
+        } else {
+            self.emit_load(elem_type, "rax");
         }
+    }
 
-        // Compute final address and save it - use 16 bytes for alignment
-        self.emit(&format!("    imul rax, {}", elem_size));
-        self.emit(&format!("    add rax, QWORD PTR [rbp + {}]", ptr_offset));
-        self.emit(&format!("    sub rsp, {}", STACK_TEMP_SPACE));
+    /// Store into an array element the value that `value` generates (an
+    /// expression, or an INPUT or READ call)
+    fn gen_array_store(
+        &mut self,
+        name: &str,
+        indices: &[Expr],
+        value: impl FnOnce(&mut Self) -> DataType,
+    ) {
+        // Compute element address and save it - use 16 bytes for alignment
+        self.gen_array_element_address(name, indices);
+        self.emit(format_args!("    sub rsp, {}", STACK_TEMP_SPACE));
         self.emit("    mov QWORD PTR [rsp], rax"); // save address
 
         // Evaluate value
-        let val_type = self.gen_expr(value);
+        let val_type = value(self);
 
         // Store value at computed address
         self.emit("    mov rcx, QWORD PTR [rsp]");
-        self.emit(&format!("    add rsp, {}", STACK_TEMP_SPACE));
-        if is_string_var(name) {
+        self.emit(format_args!("    add rsp, {}", STACK_TEMP_SPACE));
+        let elem_type = DataType::of_name(name, self.dialect);
+        if elem_type == DataType::String {
             self.emit("    mov QWORD PTR [rcx], rax");
             self.emit("    mov QWORD PTR [rcx + 8], rdx");
         } else {
-            // Coerce to double for array storage
-            self.gen_coercion(val_type, DataType::Double);
-            self.emit("    movsd QWORD PTR [rcx], xmm0");
+            // Elements keep the array's type in an 8-byte slot
+            self.gen_coercion(val_type, elem_type);
+            self.emit_store(elem_type, "rcx");
         }
     }
 
     fn gen_string_assign(&mut self, name: &str, value: &Expr) {
         self.gen_expr(value);
-        let offset = self.get_var_offset(name);
-        // For strings, also allocate space for length
-        self.stack_offset -= 8; // extra space for length
-        self.emit(&format!("    mov QWORD PTR [rbp + {}], rax", offset));
-        self.emit(&format!("    mov QWORD PTR [rbp + {}], rdx", offset - 8));
-    }
-
-    fn emit_data_section(&mut self) {
-        self.output.push_str("\n.data\n");
-
-        // String literals - clone to avoid borrow issues
-        let strings = self.string_literals.clone();
-        for (i, s) in strings.iter().enumerate() {
-            self.output.push_str(&format!("_str_{}:\n", i));
-            let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
-            self.output
-                .push_str(&format!("    .ascii \"{}\"\n", escaped));
-        }
-
-        // DATA table - always define it (even if empty) to avoid linker errors
-        self.output.push_str("_data_table:\n");
-        let data_items = self.data_items.clone();
-        for item in &data_items {
-            match item {
-                Literal::Integer(n) => {
-                    self.output.push_str("    .quad 0  # type int\n");
-                    self.output.push_str(&format!("    .quad {}\n", n));
-                }
-                Literal::Float(f) => {
-                    self.output.push_str("    .quad 1  # type float\n");
-                    self.output
-                        .push_str(&format!("    .quad 0x{:X}\n", f.to_bits()));
-                }
-                Literal::String(s) => {
-                    let idx = self.string_literals.len();
-                    self.string_literals.push(s.clone());
-                    self.output.push_str("    .quad 2  # type string\n");
-                    self.output.push_str(&format!("    .quad _str_{}\n", idx));
-                }
-            }
-        }
-        self.output
-            .push_str(&format!("_data_count: .quad {}\n", data_items.len()));
-
-        // DATA pointer
-        self.emit("_data_ptr: .quad 0");
-
-        // GOSUB return stack pointer
-        if self.gosub_used {
-            self.emit("_gosub_sp: .quad 0");
-        }
-
-        self.emit("");
-        self.emit(".bss");
-        // GOSUB stack (if needed)
-        if self.gosub_used {
-            self.emit(&format!(
-                "_gosub_stack: .skip {}  # GOSUB return stack (64K entries)",
-                GOSUB_STACK_SIZE
-            ));
-        }
+        let addr = self.get_var_info(name).addr;
+        self.emit_store(DataType::String, addr);
     }
 }
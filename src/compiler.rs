@@ -0,0 +1,900 @@
+//! The compiler as a library - BASIC source in, assembly or an executable out
+//!
+//! ```no_run
+//! use xbasic64::{Compiler, CompilerOptions};
+//!
+//! let mut compiler = Compiler::new(CompilerOptions::default());
+//! let asm = compiler.compile_to_asm("PRINT \"Hello\"\n").unwrap();
+//! let exe = compiler.compile_to_executable("hello.bas").unwrap();
+//! ```
+//!
+//! The steps are there one at a time too - read, parse, check, generate,
+//! emit, build - for tools that stop part way or show what a step made, as
+//! the xbasic64 command does for its dumps and for programs built from
+//! several files. Each returns `Diagnostics` on failure; nothing is printed.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::abi::Target;
+use crate::diagnostic::{Diagnostic, Diagnostics, Located};
+use crate::include::Source;
+use crate::ir::Module;
+pub use crate::lexer::Dialect;
+use crate::parser::{Parser, Program, StmtKind};
+use crate::{assembler, codegen, dce, elf, emit, fold, lexer, linker, modules};
+use crate::{peephole, regalloc, runtime, semantic, warnings};
+use std::cell::RefCell;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Where the runtime routines (`_rt_*`) programs call come from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Runtime {
+    /// The compiler's own, the parts a program uses added to its main module
+    #[default]
+    Builtin,
+    /// None: programs only refer to them, and they're linked from elsewhere
+    /// (a library named with -l, or by a later link of the objects)
+    None,
+    /// An object file or static library, linked in instead of the built-in
+    /// runtime
+    Library(String),
+}
+
+impl std::str::FromStr for Runtime {
+    type Err = String;
+
+    /// `builtin`, `none`, or the path of an object file or static library
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let extension = Path::new(s).extension().and_then(|e| e.to_str());
+        match s {
+            "builtin" => Ok(Runtime::Builtin),
+            "none" => Ok(Runtime::None),
+            _ if matches!(extension, Some("o" | "obj" | "a" | "lib")) => {
+                Ok(Runtime::Library(s.to_string()))
+            }
+            _ => Err(
+                "expected builtin, none, or an object file or static library \
+                      (.o, .obj, .a or .lib)"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// Checks that make the compiler, or the programs it builds, stricter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checks {
+    /// Require variables to be assigned or DIM'd before use
+    pub explicit: bool,
+    /// Stop with "Overflow" when an INTEGER or LONG result is out of range
+    pub overflow: bool,
+    /// Fail on warnings
+    pub deny_warnings: bool,
+}
+
+/// How to compile
+#[derive(Debug, Clone)]
+pub struct CompilerOptions {
+    /// System to compile for
+    pub target: Target,
+    /// 0 (none) or 1 (fold constants, remove dead code, peephole, register
+    /// allocation)
+    pub opt_level: u8,
+    /// Which keywords programs use and which extensions they may
+    pub dialect: Dialect,
+    pub checks: Checks,
+    /// Give numbers on the console, and STR$ and VAL, the decimal point of
+    /// the locale the program runs in, instead of always '.'
+    pub locale: bool,
+    /// Directories to search for $INCLUDE files
+    pub include_dirs: Vec<String>,
+    /// Show each source line above its code in the assembly
+    pub annotate: bool,
+    /// Assembler command (None: built in for Linux, else the target's)
+    pub assembler: Option<String>,
+    /// Linker command, run like cc (None: as for the assembler)
+    pub cc: Option<String>,
+    /// Linker library search directories
+    pub lib_dirs: Vec<String>,
+    /// Libraries to link with
+    pub libs: Vec<String>,
+    /// Keep the assembly and object files next to the output
+    pub keep_temps: bool,
+    /// The runtime programs are linked with
+    pub runtime: Runtime,
+    /// Where executables built with an external assembler find the built-in
+    /// runtime as a precompiled static library, made there the first time
+    /// (None: its parts are assembled with each program)
+    pub runtime_cache: Option<PathBuf>,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        CompilerOptions {
+            target: Target::host(),
+            opt_level: 0,
+            dialect: Dialect::default(),
+            checks: Checks::default(),
+            locale: false,
+            include_dirs: Vec::new(),
+            annotate: false,
+            assembler: None,
+            cc: None,
+            lib_dirs: Vec::new(),
+            libs: Vec::new(),
+            keep_temps: false,
+            runtime: Runtime::default(),
+            runtime_cache: default_runtime_cache(),
+        }
+    }
+}
+
+/// $XBASIC64_CACHE_DIR, or xbasic64 in the user's cache directory
+fn default_runtime_cache() -> Option<PathBuf> {
+    let var = |name| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    var("XBASIC64_CACHE_DIR")
+        .or_else(|| var("XDG_CACHE_HOME").map(|dir| dir.join("xbasic64")))
+        .or_else(|| var("LOCALAPPDATA").map(|dir| dir.join("xbasic64")))
+        .or_else(|| var("HOME").map(|dir| dir.join(".cache").join("xbasic64")))
+}
+
+/// What a build writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Assembly,
+    Object,
+    Executable,
+}
+
+/// Compiles BASIC programs with a set of options
+pub struct Compiler {
+    options: CompilerOptions,
+    warnings: Diagnostics,                         // from the last check
+    lib_libs: Vec<String>, // libraries DECLARE ... LIB names, besides the C library
+    steps: RefCell<Vec<(&'static str, Duration)>>, // assemble and link times of the last build
+}
+
+/// A module's assembly, the file it's written to and the object it's
+/// assembled into
+struct Unit {
+    asm: String,
+    asm_file: String,
+    obj_file: String,
+}
+
+/// The libraries `DECLARE ... LIB` statements name that aren't linked
+/// anyway: the C and math libraries always are
+fn foreign_libs(programs: &[Program]) -> Vec<String> {
+    let mut libs: Vec<String> = Vec::new();
+    for stmt in programs.iter().flat_map(|program| &program.statements) {
+        if let StmtKind::Declare {
+            foreign: Some(foreign),
+            ..
+        } = &stmt.kind
+        {
+            let lib = &foreign.lib;
+            if !matches!(lib.as_str(), "c" | "m") && !libs.contains(lib) {
+                libs.push(lib.clone());
+            }
+        }
+    }
+    libs
+}
+
+/// An error that isn't in a source file, such as the assembler failing
+fn build_error(code: &'static str, message: String) -> Diagnostics {
+    let diag = Diagnostic::new(None, message).with_code(code);
+    Located::in_file("", diag).into()
+}
+
+impl Compiler {
+    pub fn new(options: CompilerOptions) -> Self {
+        Compiler {
+            options,
+            warnings: Diagnostics::new(),
+            lib_libs: Vec::new(),
+            steps: RefCell::default(),
+        }
+    }
+
+    pub fn options(&self) -> &CompilerOptions {
+        &self.options
+    }
+
+    /// The warnings the last check found
+    pub fn warnings(&self) -> &Diagnostics {
+        &self.warnings
+    }
+
+    /// How long assembling and linking took in the last build, for the
+    /// steps it ran
+    pub fn build_steps(&self) -> Vec<(&'static str, Duration)> {
+        self.steps.borrow().clone()
+    }
+
+    /// Note how long a build step took since `started`
+    fn step(&self, name: &'static str, started: Instant) {
+        self.steps.borrow_mut().push((name, started.elapsed()));
+    }
+
+    /// The built-in runtime as a static library in the runtime cache,
+    /// assembled with `assembler` the first time; None if there's no cache
+    /// or the library can't be made there
+    fn runtime_library(&self, assembler: &str) -> Option<PathBuf> {
+        let dir = self.options.runtime_cache.as_ref()?;
+        let target = self.options.target;
+        let members = runtime::library_members(target);
+        let mut hasher = DefaultHasher::new();
+        (
+            env!("CARGO_PKG_VERSION"),
+            format!("{:?}", target),
+            assembler,
+        )
+            .hash(&mut hasher);
+        members.hash(&mut hasher);
+        let library = dir.join(format!("libxbasic64rt-{:016x}.a", hasher.finish()));
+        if library.exists() {
+            return Some(library);
+        }
+
+        // Made in a directory of its own and renamed into place, so builds
+        // running side by side, in other processes or this one, never link
+        // half a library
+        let started = Instant::now();
+        let made = fs::create_dir_all(dir)
+            .and_then(|()| TempDir::with_prefix_in("tmp-", dir))
+            .and_then(|work| make_library(assembler, target, &members, work.path(), &library));
+        self.step("runtime", started);
+        made.ok().map(|()| library)
+    }
+
+    /// Compile a program to assembly, the runtime included
+    pub fn compile_to_asm(&mut self, text: &str) -> Result<String, Diagnostics> {
+        let source = self.load("<input>", text.to_string(), None)?;
+        let programs = vec![self.parse(&source)?];
+        let sources = [source];
+        self.check(&sources, &programs, false)?;
+        let modules = self.generate(programs);
+        Ok(self.emit(&sources, &modules).swap_remove(0))
+    }
+
+    /// Compile a program file to an executable next to it, named after it,
+    /// and return the executable's path
+    pub fn compile_to_executable(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<PathBuf, Diagnostics> {
+        let input = path.as_ref().to_string_lossy();
+        let sources = [self.read(&input)?];
+        let programs = vec![self.parse(&sources[0])?];
+        self.check(&sources, &programs, true)?;
+        let modules = self.generate(programs);
+        let asms = self.emit(&sources, &modules);
+        let exe = self.output_file(&input, Output::Executable);
+        self.build(&sources, asms, Output::Executable, &exe)?;
+        Ok(PathBuf::from(exe))
+    }
+
+    /// Read a program file (- for standard input) with its includes
+    /// spliced in
+    pub fn read(&self, input: &str) -> Result<Source, Diagnostics> {
+        let name = source_name(input);
+        let text = if input == "-" {
+            std::io::read_to_string(std::io::stdin())
+        } else {
+            fs::read_to_string(input)
+        };
+        let text = text.map_err(|e| {
+            let diag = Diagnostic::new(None, format!("Can't read the file: {}", e));
+            Diagnostics::from(Located::in_file(name, diag))
+        })?;
+        let path = (input != "-").then(|| Path::new(input));
+        self.load(name, text, path)
+    }
+
+    /// The source of a program named `name`, read from `path` (None: its
+    /// includes are found from the current directory), includes spliced in
+    pub fn load(
+        &self,
+        name: &str,
+        text: String,
+        path: Option<&Path>,
+    ) -> Result<Source, Diagnostics> {
+        let mut source = Source::new(name, text);
+        match source.expand_includes(path, &self.options.include_dirs) {
+            Ok(()) => Ok(source),
+            Err(e) => Err(Located::new(&source, &e).into()),
+        }
+    }
+
+    /// Parse a source file, pulling tokens from the lexer as they are needed
+    pub fn parse(&self, source: &Source) -> Result<Program, Diagnostics> {
+        let mut parser =
+            Parser::new(lexer::Lexer::new(&source.text).with_dialect(self.options.dialect));
+        parser.parse().map_err(|e| Located::new(source, &e).into())
+    }
+
+    /// Check that the modules (the main one first) fit together - when
+    /// `linking` into an executable, every procedure called must be defined
+    /// in one of them - then each on its own, returning the errors of every
+    /// module. The warnings found are kept, and fail the check if the
+    /// options deny them.
+    pub fn check(
+        &mut self,
+        sources: &[Source],
+        programs: &[Program],
+        linking: bool,
+    ) -> Result<(), Diagnostics> {
+        self.warnings = Diagnostics::new();
+        self.lib_libs = foreign_libs(programs);
+        let modules: Vec<(&str, &Program)> =
+            sources.iter().map(Source::name).zip(programs).collect();
+        if let Err((module, e)) = modules::check(&modules, linking) {
+            return Err(Located::new(&sources[module], &e).into());
+        }
+        let mut errors = Vec::new();
+        for (source, program) in sources.iter().zip(programs) {
+            let mut checker =
+                semantic::Checker::new(self.options.checks.explicit, self.options.dialect);
+            match checker.check(program) {
+                Ok(()) => {
+                    for w in warnings::check(program) {
+                        self.warnings.push(Located::new(source, &w));
+                    }
+                }
+                Err(found) => errors.extend(found.iter().map(|e| Located::new(source, e))),
+            }
+        }
+        if !errors.is_empty() {
+            let mut list = std::mem::take(&mut self.warnings);
+            errors.into_iter().for_each(|e| list.push(e));
+            return Err(list);
+        }
+        if self.options.checks.deny_warnings && !self.warnings.is_empty() {
+            let message = format!(
+                "{} warning(s) denied by --deny-warnings",
+                self.warnings.len()
+            );
+            let diag = Diagnostic::new(None, message).with_code("denied-warnings");
+            let mut list = std::mem::take(&mut self.warnings);
+            list.push(Located::in_file("", diag));
+            return Err(list);
+        }
+        Ok(())
+    }
+
+    /// Generate (and optimize) the code for checked modules, the main
+    /// program in the first only
+    pub fn generate(&self, programs: Vec<Program>) -> Vec<Module> {
+        let optimize = self.options.opt_level >= 1;
+        let mut modules = Vec::with_capacity(programs.len());
+        for (i, mut program) in programs.into_iter().enumerate() {
+            fold::substitute_consts(&mut program).expect("CONSTs are checked before generating");
+            if optimize {
+                fold::fold(&mut program, self.options.dialect);
+            }
+            let mut codegen = codegen::CodeGen::new(
+                self.options.target,
+                self.options.checks.overflow,
+                self.options.locale,
+            )
+            .with_dialect(self.options.dialect);
+            let mut module = if i == 0 {
+                codegen.generate(&program)
+            } else {
+                codegen.generate_library(&program)
+            };
+            if optimize {
+                dce::eliminate(&mut module);
+                peephole::optimize(&mut module.code);
+                regalloc::allocate(&mut module.code);
+            }
+            modules.push(module);
+        }
+        modules
+    }
+
+    /// Each module's assembly, with the runtime they all call added to the
+    /// main one unless it comes from elsewhere
+    pub fn emit(&self, sources: &[Source], modules: &[Module]) -> Vec<String> {
+        let target = self.options.target;
+        let mut asms: Vec<String> = sources
+            .iter()
+            .zip(modules)
+            .map(|(source, module)| {
+                let annotate = self.options.annotate.then_some(source);
+                emit::emit(module, target, annotate)
+            })
+            .collect();
+        if self.options.runtime == Runtime::Builtin {
+            let runtime_asm = runtime::generate_runtime(&asms.concat(), target);
+            asms[0] = format!("{}\n{}", asms[0], runtime_asm);
+        }
+        asms
+    }
+
+    /// Where a build of `input` (- for standard input) writes by default:
+    /// next to the input, or a.out (a.o, a.exe) in the current directory
+    /// for standard input
+    pub fn output_file(&self, input: &str, output: Output) -> String {
+        let from_stdin = input == "-";
+        let input_path = Path::new(if from_stdin { "a" } else { input });
+        let stem = input_path.file_stem().unwrap().to_str().unwrap();
+        let input_dir = input_path.parent().unwrap_or(Path::new("."));
+        let file = match output {
+            Output::Assembly => format!("{}.s", stem),
+            Output::Object => format!("{}.o", stem),
+            Output::Executable if self.options.target == Target::Windows => {
+                format!("{}.exe", stem)
+            }
+            Output::Executable if from_stdin => return "a.out".to_string(),
+            Output::Executable => stem.to_string(),
+        };
+        input_dir.join(file).to_string_lossy().to_string()
+    }
+
+    /// Write the modules' assembly (from `emit`, for `sources`) out as
+    /// `output`. The main module's files are named after `path`; the
+    /// others' after their sources, next to them for assembly and objects.
+    /// Returns the files written: the assembly, the objects, or the
+    /// executable.
+    pub fn build(
+        &self,
+        sources: &[Source],
+        asms: Vec<String>,
+        output: Output,
+        path: &str,
+    ) -> Result<Vec<String>, Diagnostics> {
+        self.steps.borrow_mut().clear();
+        let options = &self.options;
+        let target = options.target;
+        let asm_only = output == Output::Assembly;
+        let object_only = output == Output::Object;
+
+        // Assembly and object files go in `dir` when they are the output or
+        // --keep-temps asks for them; otherwise they are temp files in a
+        // directory of this build's own, named by module, removed when the
+        // build finishes or fails
+        let temps = if options.keep_temps {
+            None
+        } else {
+            let dir = TempDir::with_prefix("xbasic64-").map_err(|e| {
+                build_error(
+                    "build-error",
+                    format!("Can't create a temporary directory: {}", e),
+                )
+            })?;
+            Some(dir)
+        };
+        let exe_path = Path::new(path);
+        let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
+        let exe_stem = exe_path.file_stem().unwrap().to_str().unwrap();
+        let file_for = |dir: &Path, stem: &str, module: usize, ext: &str, kept: bool| {
+            let path = match &temps {
+                Some(temps) if !kept => temps.path().join(format!("{}.{}", module, ext)),
+                _ => dir.join(format!("{}.{}", stem, ext)),
+            };
+            path.to_string_lossy().to_string()
+        };
+        let mut units = Vec::with_capacity(asms.len());
+        for (i, (asm, source)) in asms.into_iter().zip(sources).enumerate() {
+            if i == 0 {
+                let asm_file = file_for(exe_dir, exe_stem, i, "s", asm_only);
+                let obj_file = if object_only {
+                    path.to_string()
+                } else {
+                    file_for(exe_dir, exe_stem, i, "o", false)
+                };
+                units.push(Unit {
+                    asm,
+                    asm_file,
+                    obj_file,
+                });
+                continue;
+            }
+            let input = Path::new(source.name());
+            let stem = input.file_stem().unwrap().to_str().unwrap();
+            let dir = if asm_only || object_only {
+                input.parent().unwrap_or(Path::new("."))
+            } else {
+                exe_dir
+            };
+            units.push(Unit {
+                asm,
+                asm_file: file_for(dir, stem, i, "s", asm_only),
+                obj_file: file_for(dir, stem, i, "o", object_only),
+            });
+        }
+
+        // The built-in assembler and linker build Linux programs unless a
+        // command is named for either step or the link needs extra libraries
+        // (or a runtime of its own)
+        let external_as = target != Target::Linux || options.assembler.is_some();
+        let (default_as, default_cc) = default_tools(target);
+        let assembler = options.assembler.as_deref().unwrap_or(default_as);
+
+        // Their executables link the runtime from its library (not for
+        // Windows, whose link.exe doesn't take Unix archives) rather than
+        // assembling its parts each time
+        let runtime_library = if external_as
+            && output == Output::Executable
+            && options.runtime == Runtime::Builtin
+            && target != Target::Windows
+        {
+            self.runtime_library(assembler)
+        } else {
+            None
+        };
+        if runtime_library.is_some() {
+            units[0].asm = runtime::without_runtime(&units[0].asm);
+        }
+
+        if asm_only || external_as || options.keep_temps {
+            for unit in &units {
+                write_file(&unit.asm_file, unit.asm.as_bytes(), "assembly")?;
+            }
+        }
+        if asm_only {
+            return Ok(units.into_iter().map(|unit| unit.asm_file).collect());
+        }
+
+        // Assemble - built in for Linux, clang on Windows, the system (or
+        // cross) assembler otherwise. Linux executables are linked here too,
+        // unless the compiler runs on a Linux without the glibc dynamic
+        // loader they need.
+        if !external_as {
+            let external_link = options.cc.is_some()
+                || !options.lib_dirs.is_empty()
+                || !options.libs.is_empty()
+                || !self.lib_libs.is_empty()
+                || options.runtime != Runtime::Builtin;
+            let link_here = !object_only
+                && !external_link
+                && (Target::host() != Target::Linux || linker::available());
+            let started = Instant::now();
+            let mut objects: Vec<elf::Object> = Vec::with_capacity(units.len());
+            for (i, unit) in units.iter().enumerate() {
+                let assembled = if link_here && i == 0 {
+                    assembler::assemble(&format!("{}\n{}", unit.asm, linker::START))
+                } else {
+                    assembler::assemble(&unit.asm)
+                };
+                let object =
+                    assembled.map_err(|e| build_error("assembler-error", e.to_string()))?;
+                objects.push(object);
+            }
+            self.step("assemble", started);
+            if link_here {
+                if options.keep_temps {
+                    for (unit, object) in units.iter().zip(&objects) {
+                        write_file(&unit.obj_file, &object.write(), "object file")?;
+                    }
+                }
+                let started = Instant::now();
+                let image = linker::link(&objects).map_err(|e| build_error("linker-error", e))?;
+                write_executable(path, &image)?;
+                self.step("link", started);
+                return Ok(vec![path.to_string()]);
+            }
+            for (unit, object) in units.iter().zip(&objects) {
+                write_file(&unit.obj_file, &object.write(), "object file")?;
+            }
+        } else {
+            let started = Instant::now();
+            for unit in &units {
+                let as_status = Command::new(assembler)
+                    .args(assembler_args(
+                        assembler,
+                        target,
+                        &unit.obj_file,
+                        &unit.asm_file,
+                    ))
+                    .status();
+                check_status(as_status, "assembler", assembler)?;
+            }
+            self.step("assemble", started);
+        }
+
+        if object_only {
+            return Ok(units.into_iter().map(|unit| unit.obj_file).collect());
+        }
+
+        // Link - link.exe with UCRT on Windows, cc (or a cross cc) otherwise.
+        // msvcrt.lib provides CRT startup (mainCRTStartup) and imports CRT DLL
+        let started = Instant::now();
+        let linker_command = options.cc.as_deref().unwrap_or(default_cc);
+        let runtime_file = match &options.runtime {
+            Runtime::Library(file) => Some(file.clone()),
+            Runtime::Builtin => runtime_library.map(|file| file.to_string_lossy().to_string()),
+            Runtime::None => None,
+        };
+        let obj_files = units
+            .iter()
+            .map(|unit| unit.obj_file.clone())
+            .chain(runtime_file);
+        let libs = options.libs.iter().chain(&self.lib_libs);
+        let mut link_args: Vec<String> = Vec::new();
+        if linker_command == "link.exe" {
+            link_args.push(format!("/OUT:{}", path));
+            link_args.extend(obj_files);
+            link_args.extend(
+                [
+                    "/SUBSYSTEM:CONSOLE",
+                    "/DEFAULTLIB:msvcrt.lib",
+                    "/DEFAULTLIB:ucrt.lib",
+                    "/DEFAULTLIB:kernel32.lib",
+                    "/DEFAULTLIB:ws2_32.lib",
+                    "/DEFAULTLIB:winmm.lib",
+                    "/DEFAULTLIB:legacy_stdio_definitions.lib",
+                ]
+                .map(String::from),
+            );
+            link_args.extend(options.lib_dirs.iter().map(|d| format!("/LIBPATH:{}", d)));
+            link_args.extend(libs.map(|l| format!("{}.lib", l)));
+        } else {
+            link_args.extend(arch_args(target));
+            link_args.extend(["-o".into(), path.to_string()]);
+            link_args.extend(obj_files);
+            link_args.extend(options.lib_dirs.iter().map(|d| format!("-L{}", d)));
+            link_args.extend(libs.map(|l| format!("-l{}", l)));
+            link_args.push("-lm".into());
+            if target == Target::Linux {
+                link_args.push("-no-pie".into());
+            }
+        }
+        let cc_status = Command::new(linker_command).args(&link_args).status();
+        check_status(cc_status, "linker", linker_command)?;
+        self.step("link", started);
+        Ok(vec![path.to_string()])
+    }
+}
+
+/// Assemble `members` in `work`, a few at a time, and archive them as
+/// `library`
+fn make_library(
+    assembler: &str,
+    target: Target,
+    members: &[String],
+    work: &Path,
+    library: &Path,
+) -> std::io::Result<()> {
+    let failed = |what: &str| std::io::Error::other(format!("{} failed", what));
+    let mut objects = Vec::with_capacity(members.len());
+    for (i, member) in members.iter().enumerate() {
+        let asm_file = work.join(format!("rt{}.s", i));
+        fs::write(&asm_file, member)?;
+        objects.push((asm_file, work.join(format!("rt{}.o", i))));
+    }
+    let jobs = std::thread::available_parallelism().map_or(4, |n| n.get());
+    for batch in objects.chunks(jobs) {
+        let mut children = Vec::with_capacity(batch.len());
+        for (asm_file, obj_file) in batch {
+            let args = assembler_args(
+                assembler,
+                target,
+                &obj_file.to_string_lossy(),
+                &asm_file.to_string_lossy(),
+            );
+            children.push(Command::new(assembler).args(args).spawn()?);
+        }
+        for mut child in children {
+            if !child.wait()?.success() {
+                return Err(failed(assembler));
+            }
+        }
+    }
+    let archive = work.join("runtime.a");
+    let status = Command::new("ar")
+        .arg("rcs")
+        .arg(&archive)
+        .args(objects.iter().map(|(_, obj_file)| obj_file))
+        .status()?;
+    if !status.success() {
+        return Err(failed("ar"));
+    }
+    fs::rename(&archive, library)
+}
+
+/// An input file's name in messages
+pub fn source_name(input: &str) -> &str {
+    match input {
+        "-" => "<stdin>",
+        name => name,
+    }
+}
+
+/// Write an output file, or fail saying what it was for
+fn write_file(path: &str, contents: &[u8], what: &str) -> Result<(), Diagnostics> {
+    fs::write(path, contents).map_err(|e| {
+        build_error(
+            "build-error",
+            format!("Can't write {} {}: {}", what, path, e),
+        )
+    })
+}
+
+/// Arguments for assembling a file: compiler drivers such as clang need
+/// -c to stop before linking, an assembler proper doesn't take it
+fn assembler_args(assembler: &str, target: Target, obj_file: &str, asm_file: &str) -> Vec<String> {
+    let name = Path::new(assembler)
+        .file_stem()
+        .map_or(assembler.into(), |s| s.to_string_lossy());
+    let mut list = Vec::new();
+    if !name.ends_with("as") {
+        list.push("-c".to_string());
+    }
+    list.extend(arch_args(target));
+    list.extend(["-o".into(), obj_file.into(), asm_file.into()]);
+    list
+}
+
+/// Arguments that make Apple's tools build x86-64 code, which Apple
+/// Silicon Macs run under Rosetta 2; there the tools default to arm64
+fn arch_args(target: Target) -> Vec<String> {
+    match target {
+        Target::Macos => vec!["-arch".into(), "x86_64".into()],
+        Target::Linux | Target::Windows => Vec::new(),
+    }
+}
+
+/// The assembler and linker commands that build programs for `target`
+/// when none is named
+fn default_tools(target: Target) -> (&'static str, &'static str) {
+    match (target, Target::host()) {
+        (Target::Windows, Target::Windows) => ("clang", "link.exe"),
+        (Target::Windows, _) => ("x86_64-w64-mingw32-as", "x86_64-w64-mingw32-gcc"),
+        (Target::Macos, _) => ("clang", "clang"),
+        (Target::Linux, _) => ("as", "cc"),
+    }
+}
+
+/// Fail unless an assembler or linker command succeeded
+fn check_status(
+    status: std::io::Result<std::process::ExitStatus>,
+    what: &str,
+    command: &str,
+) -> Result<(), Diagnostics> {
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(build_error(
+            "build-error",
+            format!("{} failed with status: {}", command, status),
+        )),
+        Err(e) => Err(build_error(
+            "build-error",
+            format!("Failed to run {} {}: {}", what, command, e),
+        )),
+    }
+}
+
+/// Write an executable; on Unix only the umask limits its permissions,
+/// as when cc writes one
+fn write_executable(path: &str, contents: &[u8]) -> Result<(), Diagnostics> {
+    use std::io::Write;
+
+    let _ = fs::remove_file(path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o777);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|e| {
+            build_error(
+                "build-error",
+                format!("Can't write executable {}: {}", path, e),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiler() -> Compiler {
+        Compiler::new(CompilerOptions {
+            target: Target::Linux,
+            ..CompilerOptions::default()
+        })
+    }
+
+    // ===================
+    // Library API Tests
+    // ===================
+
+    #[test]
+    fn test_compile_to_asm() {
+        let asm = compiler().compile_to_asm("PRINT \"Hi\"\n").unwrap();
+        assert!(asm.contains(".globl main"), "{}", asm);
+        assert!(asm.contains("_rt_print_string"), "{}", asm);
+    }
+
+    #[test]
+    fn test_compile_errors_and_warnings() {
+        let mut compiler = compiler();
+        let err = compiler.compile_to_asm("PRINT 1\nPRINT (2\n").unwrap_err();
+        assert_eq!(err.len(), 1);
+        let diag = err.iter().next().unwrap();
+        assert_eq!(
+            (diag.diagnostic.kind(), diag.diagnostic.span.unwrap().line),
+            ("Parse error", 2)
+        );
+        assert!(err.to_string().starts_with("<input>:2:"), "{}", err);
+
+        compiler.compile_to_asm("X = 1\nPRINT 2\n").unwrap();
+        assert_eq!(compiler.warnings().len(), 1);
+        assert!(
+            compiler
+                .warnings()
+                .iter()
+                .all(|w| w.diagnostic.is_warning())
+        );
+
+        let mut strict = Compiler::new(CompilerOptions {
+            checks: Checks {
+                deny_warnings: true,
+                ..Checks::default()
+            },
+            ..CompilerOptions::default()
+        });
+        let err = strict.compile_to_asm("X = 1\nPRINT 2\n").unwrap_err();
+        assert_eq!((err.len(), err.error_count()), (2, 1));
+
+        // Every error the check finds comes back, in source order
+        let err = compiler.compile_to_asm("GOTO 99\nGOSUB 30\n").unwrap_err();
+        let lines: Vec<u32> = err
+            .iter()
+            .map(|e| e.diagnostic.span.unwrap().line)
+            .collect();
+        assert_eq!((err.error_count(), lines), (2, vec![1, 2]));
+    }
+
+    #[test]
+    fn test_runtime() {
+        let mut compiler = Compiler::new(CompilerOptions {
+            target: Target::Linux,
+            runtime: Runtime::None,
+            ..CompilerOptions::default()
+        });
+        let asm = compiler.compile_to_asm("PRINT \"Hi\"\n").unwrap();
+        assert!(asm.contains("call _rt_print_string"), "{}", asm);
+        assert!(!asm.contains("_rt_print_string:"), "{}", asm);
+
+        assert_eq!("none".parse(), Ok(Runtime::None));
+        assert_eq!(
+            "lib/librt.a".parse(),
+            Ok(Runtime::Library("lib/librt.a".to_string()))
+        );
+        assert!("rt.c".parse::<Runtime>().is_err());
+    }
+
+    #[test]
+    fn test_output_file() {
+        let compiler = compiler();
+        assert_eq!(compiler.output_file("-", Output::Executable), "a.out");
+        assert_eq!(compiler.output_file("-", Output::Object), "a.o");
+        assert_eq!(
+            compiler.output_file("dir/prog.bas", Output::Executable),
+            "dir/prog"
+        );
+        let windows = Compiler::new(CompilerOptions {
+            target: Target::Windows,
+            ..CompilerOptions::default()
+        });
+        assert_eq!(
+            windows.output_file("prog.bas", Output::Executable),
+            "prog.exe"
+        );
+    }
+}
@@ -0,0 +1,408 @@
+//! Dead code elimination - an IR pass run at -O1 and above
+//!
+//! Two kinds of code are removed:
+//!
+//! - Unreachable code: whatever follows an unconditional jump or a return
+//!   (GOTO, END, RETURN) up to the next label something refers to.
+//! - Dead stores: a store to a variable that nothing ever loads, or that is
+//!   stored again in the same basic block before any load. If the stored
+//!   value was computed without side effects, that computation goes too.
+//!
+//! Liveness is tracked by address. A variable whose address appears in an
+//! `Asm` instruction (array descriptors, FOR counters, INPUT targets and
+//! the like) is treated as read everywhere, since the pass can't see what
+//! the assembly does with it. An ASM block is always kept, as is the code
+//! after it, which its own labels may lead to.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::ir::{Inst, Module};
+use crate::parser::{BinaryOp, DataType};
+use std::collections::{HashMap, HashSet};
+
+/// Remove unreachable code and dead stores from a module
+pub fn eliminate(module: &mut Module) {
+    remove_unreachable(&mut module.code);
+    remove_dead_stores(&mut module.code);
+}
+
+/// Whether control never continues past an instruction to the next one
+fn ends_block(inst: &Inst) -> bool {
+    match inst {
+        Inst::Jump(_) | Inst::Return { .. } => true,
+        Inst::Asm(text) => text.starts_with("jmp "),
+        _ => false,
+    }
+}
+
+/// The words in assembly text that could be labels: codegen's labels all
+/// start with `.` or `_` (`.Lfor_3`, `_line_100`, `_proc_FOO`), which
+/// registers and mnemonics never do
+fn asm_labels(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')))
+        .filter(|word| word.starts_with(['.', '_']))
+}
+
+/// The labels an instruction refers to (as a jump, call or operand)
+fn labels_used(inst: &Inst) -> Vec<&str> {
+    match inst {
+        Inst::Jump(target) | Inst::Call(target) | Inst::Branch { label: target, .. } => {
+            vec![target.as_str()]
+        }
+        Inst::Asm(text) => asm_labels(text).collect(),
+        Inst::InlineAsm(lines) => lines.iter().flat_map(|line| asm_labels(line)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Drop the code after each unconditional jump or return, up to a label
+/// that something refers to. Dropped code may hold the last reference to a
+/// label, so this repeats until nothing changes, counting the references
+/// to each label once up front rather than rescanning the code every time.
+fn remove_unreachable(code: &mut Vec<Inst>) {
+    let mut refs: HashMap<&str, usize> = HashMap::new();
+    for label in code.iter().flat_map(labels_used) {
+        *refs.entry(label).or_default() += 1;
+    }
+    let mut keep = vec![true; code.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut reachable = true;
+        for (i, inst) in code.iter().enumerate() {
+            if !keep[i] {
+                continue;
+            }
+            match inst {
+                // An ASM block may have labels of its own to jump to
+                Inst::Enter { .. } | Inst::InlineAsm(_) => reachable = true,
+                Inst::Label(label) if !reachable => {
+                    reachable = refs.get(label.as_str()).is_some_and(|&n| n > 0);
+                }
+                _ => {}
+            }
+            // Source markers stay, so dropped lines still show in -S output
+            if !reachable && !matches!(inst, Inst::Source(_)) {
+                keep[i] = false;
+                changed = true;
+                for label in labels_used(inst) {
+                    *refs.get_mut(label).expect("counted above") -= 1;
+                }
+            }
+            if ends_block(inst) {
+                reachable = false;
+            }
+        }
+    }
+    let mut keep = keep.into_iter();
+    code.retain(|_| keep.next().unwrap());
+}
+
+/// Whether an instruction only computes a value in the accumulator, so it
+/// can be dropped along with the value. Division and `^` can stop the
+/// program or call the C library, and checked conversions can overflow.
+fn is_pure(inst: &Inst) -> bool {
+    match inst {
+        Inst::Const(_)
+        | Inst::Load { .. }
+        | Inst::ConstRight(_)
+        | Inst::LoadRight { .. }
+        | Inst::Push(_)
+        | Inst::PopRight(_)
+        | Inst::SaveTemp { .. }
+        | Inst::RestoreRight { .. }
+        | Inst::Neg(_)
+        | Inst::Not(_) => true,
+        Inst::Convert { checked, .. } => !checked,
+        Inst::Binary { op, .. } => !matches!(
+            op,
+            BinaryOp::Div | BinaryOp::IntDiv | BinaryOp::Mod | BinaryOp::Pow
+        ),
+        _ => false,
+    }
+}
+
+/// Where the side-effect-free computation of the value stored at `store`
+/// begins, if it is side-effect free
+fn value_start(code: &[Inst], store: usize) -> Option<usize> {
+    let mut depth = 0;
+    for i in (0..store).rev() {
+        match &code[i] {
+            Inst::PopRight(_) | Inst::RestoreRight { .. } => depth += 1,
+            Inst::Push(_) | Inst::SaveTemp { .. } if depth == 0 => return None,
+            Inst::Push(_) | Inst::SaveTemp { .. } => depth -= 1,
+            Inst::Const(_) | Inst::Load { .. } if depth == 0 => return Some(i),
+            inst if is_pure(inst) => {}
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Whether the instruction after a store leaves the accumulator unread
+fn discards_value(inst: Option<&Inst>) -> bool {
+    matches!(
+        inst,
+        Some(
+            Inst::Const(_)
+                | Inst::Load { .. }
+                | Inst::Label(_)
+                | Inst::Enter { .. }
+                | Inst::Jump(_)
+        )
+    )
+}
+
+/// The addresses a store of type `ty` to `addr` writes: a string's length
+/// is 8 bytes below its pointer. Addresses end in a displacement
+/// (`rbp + -16`, `rip + _var_A_str + 0`).
+fn words(ty: DataType, addr: &str) -> Vec<String> {
+    let mut words = vec![addr.to_string()];
+    if ty == DataType::String {
+        match addr
+            .rsplit_once(" + ")
+            .and_then(|(base, disp)| Some((base, disp.parse::<i32>().ok()?)))
+        {
+            Some((base, disp)) => words.push(format!("{} + {}", base, disp - 8)),
+            None => words.push(format!("{} - 8", addr)),
+        }
+    }
+    words
+}
+
+fn remove_dead_stores(code: &mut Vec<Inst>) {
+    let loads: HashSet<&str> = code
+        .iter()
+        .filter_map(|inst| match inst {
+            Inst::Load { addr, .. } | Inst::LoadRight { addr, .. } => Some(&**addr),
+            _ => None,
+        })
+        .collect();
+    // Memory operands in assembly text, without their brackets
+    let escaped: HashSet<&str> = code
+        .iter()
+        .flat_map(|inst| match inst {
+            Inst::Asm(text) => std::slice::from_ref(text),
+            Inst::InlineAsm(lines) => lines.as_slice(),
+            _ => &[],
+        })
+        .flat_map(|text| text.split('[').skip(1))
+        .filter_map(|operand| operand.split_once(']').map(|(addr, _)| addr))
+        .collect();
+
+    let mut dead = vec![false; code.len()];
+    for (i, inst) in code.iter().enumerate() {
+        let Inst::Store { ty, addr } = inst else {
+            continue;
+        };
+        let words = words(*ty, addr);
+        // A store through a register (an array element) isn't to a variable
+        let variable = addr.starts_with("rbp ") || addr.starts_with("rip ");
+        if !variable || words.iter().any(|word| escaped.contains(word.as_str())) {
+            continue;
+        }
+        let never_loaded = words.iter().all(|word| !loads.contains(word.as_str()));
+        if never_loaded || overwritten(&code[i + 1..], addr, &words) {
+            dead[i] = true;
+            let next = code[i + 1..]
+                .iter()
+                .find(|inst| !matches!(inst, Inst::Source(_)));
+            if discards_value(next) {
+                if let Some(start) = value_start(code, i) {
+                    dead[start..i].fill(true);
+                }
+            }
+        }
+    }
+    let mut dead = dead.into_iter();
+    code.retain(|_| !dead.next().unwrap());
+}
+
+/// Whether `addr` is stored again before any of its `words` could be
+/// loaded, in the rest of a basic block
+fn overwritten(rest: &[Inst], addr: &str, words: &[String]) -> bool {
+    for inst in rest {
+        match inst {
+            Inst::Store { addr: other, .. } if **other == *addr => return true,
+            Inst::Load { addr: other, .. } | Inst::LoadRight { addr: other, .. }
+                if words.iter().any(|word| **word == **other) =>
+            {
+                return false;
+            }
+            Inst::Label(_)
+            | Inst::Enter { .. }
+            | Inst::Return { .. }
+            | Inst::Jump(_)
+            | Inst::Branch { .. }
+            | Inst::Call(_)
+            | Inst::CallLibc(_)
+            | Inst::InlineAsm(_) => return false,
+            Inst::Asm(text) if text.starts_with('j') || text.starts_with("call") => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Const;
+
+    fn load(addr: &str) -> Inst {
+        Inst::Load {
+            ty: DataType::Double,
+            addr: addr.into(),
+        }
+    }
+
+    fn store(addr: &str) -> Inst {
+        Inst::Store {
+            ty: DataType::Double,
+            addr: addr.into(),
+        }
+    }
+
+    fn eliminated(code: Vec<Inst>) -> Vec<Inst> {
+        let mut module = Module {
+            code,
+            ..Default::default()
+        };
+        eliminate(&mut module);
+        module.code
+    }
+
+    // ===================
+    // Unreachable Code Tests
+    // ===================
+
+    #[test]
+    fn test_remove_unreachable() {
+        let code = vec![
+            Inst::Jump("_line_20".to_string()),
+            Inst::Const(Const::Long(3)),
+            Inst::Call("_rt_print_float".to_string()),
+            Inst::Label("_line_15".to_string()),
+            Inst::Call("_rt_print_newline".to_string()),
+            Inst::Label("_line_20".to_string()),
+            Inst::Return { frame: 0 },
+            Inst::Asm("xor eax, eax".to_string()),
+            Inst::Return { frame: 0 },
+        ];
+        // The unused label goes with the code after it
+        assert_eq!(
+            eliminated(code),
+            vec![
+                Inst::Jump("_line_20".to_string()),
+                Inst::Label("_line_20".to_string()),
+                Inst::Return { frame: 0 },
+            ]
+        );
+
+        // A label something refers to starts reachable code again
+        let code = vec![
+            Inst::Asm("jmp rax".to_string()),
+            Inst::Label(".Lgosub_ret_0".to_string()),
+            Inst::Return { frame: 0 },
+            Inst::Enter {
+                label: "main".to_string(),
+                frame: 0,
+            },
+            Inst::Asm("lea rax, [rip + .Lgosub_ret_0]".to_string()),
+        ];
+        assert_eq!(eliminated(code.clone()), code);
+
+        // So does an ASM block, which may hold labels of its own, and the
+        // variables it names are live
+        let code = vec![
+            Inst::Const(Const::Double(1.0)),
+            store("rbp + -8"),
+            Inst::Jump("_line_20".to_string()),
+            Inst::InlineAsm(vec![
+                "jmp .Lmine".to_string(),
+                "movsd xmm0, QWORD PTR [rbp + -8]".to_string(),
+                ".Lmine:".to_string(),
+            ]),
+            Inst::Label("_line_20".to_string()),
+            Inst::Call("_rt_print_newline".to_string()),
+        ];
+        assert_eq!(eliminated(code.clone()), code);
+    }
+
+    // ===================
+    // Dead Store Tests
+    // ===================
+
+    #[test]
+    fn test_remove_dead_stores() {
+        // Y is never loaded; the first store to X is overwritten
+        let code = vec![
+            Inst::Const(Const::Double(1.0)),
+            store("rbp + -8"),
+            load("rbp + -8"),
+            Inst::Push(DataType::Double),
+            Inst::Const(Const::Double(2.0)),
+            Inst::PopRight(DataType::Double),
+            Inst::Binary {
+                op: BinaryOp::Add,
+                ty: DataType::Double,
+            },
+            store("rbp + -16"),
+            Inst::Const(Const::Double(3.0)),
+            store("rbp + -8"),
+            Inst::Const(Const::Double(4.0)),
+            store("rbp + -8"),
+            load("rbp + -8"),
+            Inst::Call("_rt_print_float".to_string()),
+        ];
+        assert_eq!(
+            eliminated(code),
+            vec![
+                Inst::Const(Const::Double(1.0)),
+                store("rbp + -8"),
+                Inst::Const(Const::Double(4.0)),
+                store("rbp + -8"),
+                load("rbp + -8"),
+                Inst::Call("_rt_print_float".to_string()),
+            ]
+        );
+
+        // A value with side effects is still computed
+        let code = vec![
+            Inst::Call("_rt_rnd".to_string()),
+            store("rbp + -8"),
+            Inst::Const(Const::Double(1.0)),
+        ];
+        assert_eq!(
+            eliminated(code),
+            vec![
+                Inst::Call("_rt_rnd".to_string()),
+                Inst::Const(Const::Double(1.0)),
+            ]
+        );
+
+        // A variable the assembly refers to is kept
+        let code = vec![
+            Inst::Const(Const::Double(1.0)),
+            store("rbp + -8"),
+            Inst::Asm("lea rdi, [rbp + -8]".to_string()),
+        ];
+        assert_eq!(eliminated(code.clone()), code);
+
+        // So is a string whose length (8 bytes below) the assembly reads
+        let code = vec![
+            Inst::Const(Const::Str { index: 0, len: 2 }),
+            Inst::Store {
+                ty: DataType::String,
+                addr: "rip + _var_A_str + 0".into(),
+            },
+            Inst::Asm("mov rax, QWORD PTR [rip + _var_A_str + -8]".to_string()),
+        ];
+        assert_eq!(eliminated(code.clone()), code);
+
+        // A store through a register writes an array element
+        let code = vec![Inst::Const(Const::Double(1.0)), store("rcx")];
+        assert_eq!(eliminated(code.clone()), code);
+    }
+}
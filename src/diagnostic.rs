@@ -0,0 +1,370 @@
+//! Compiler diagnostics - errors tied to the source location they refer to
+//!
+//! The lexer, parser and checker report a `Diagnostic` with the span of the
+//! token or statement at fault. Rendering one shows the file, line and
+//! column, then the source line with a caret under the column:
+//!
+//! ```text
+//! prog.bas:3:13: Parse error: Expected RParen, got Newline
+//!     3 | PRINT (1 + 2
+//!       |             ^
+//! ```
+//!
+//! For editors and CI, `to_json` gives the same information as one JSON
+//! object per diagnostic, with a short code naming the kind of problem.
+//!
+//! The library's compiler hands back `Diagnostics`: each one `Located` in
+//! the file (main or included) it is about, with its source line, so it
+//! can be shown once the program's source is gone.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::include::Source;
+use crate::lexer::Span;
+use serde::Serialize;
+use std::fmt;
+
+/// Whether a diagnostic stops the build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A message and where in the source it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub span: Option<Span>, // None when the location isn't known
+    pub severity: Severity,
+    pub code: &'static str, // e.g. "parse-error", "unused-variable"
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// An error
+    pub fn new(span: Option<Span>, message: String) -> Self {
+        Diagnostic {
+            span,
+            severity: Severity::Error,
+            code: "error",
+            message,
+        }
+    }
+
+    /// A diagnostic at a span from the parser, which is all zeros when the
+    /// tokens came without positions
+    pub fn at(span: Span, message: String) -> Self {
+        Diagnostic::new((span.line > 0).then_some(span), message)
+    }
+
+    /// Set the code identifying what kind of problem this is
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// Make this a warning, which doesn't stop the build
+    pub fn as_warning(mut self) -> Self {
+        self.severity = Severity::Warning;
+        self
+    }
+
+    pub fn is_warning(&self) -> bool {
+        self.severity == Severity::Warning
+    }
+
+    /// What kind of problem this is, as the terminal shows it
+    pub fn kind(&self) -> &'static str {
+        match (self.severity, self.code) {
+            (Severity::Warning, _) => "Warning",
+            (_, "lex-error") => "Lexer error",
+            (_, "parse-error") => "Parse error",
+            (_, "assembler-error") => "Assembler error",
+            (_, "linker-error") => "Linker error",
+            _ => "Error",
+        }
+    }
+
+    /// Format for the terminal: `file:line:col: kind: message`, then the
+    /// source line with a caret under the column. With `color`, the kind
+    /// and caret are red for errors and yellow for warnings, as rustc does.
+    pub fn render(&self, filename: &str, source: &str, color: bool) -> String {
+        let line = self
+            .span
+            .and_then(|span| source.lines().nth((span.line as usize).saturating_sub(1)));
+        self.render_line(filename, line, color)
+    }
+
+    /// Format with the text of the line the span is on, if known. Without
+    /// a span or a file name, the message stands alone after the kind.
+    fn render_line(&self, filename: &str, line: Option<&str>, color: bool) -> String {
+        let paint = |code: &str, text: &str| {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", code, text)
+            } else {
+                text.to_string()
+            }
+        };
+        let kind = self.kind();
+        let severity = match self.severity {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+        };
+        let Some(span) = self.span else {
+            if filename.is_empty() {
+                return format!("{}: {}", paint(severity, kind), paint("1", &self.message));
+            }
+            return format!(
+                "{}: {}: {}",
+                paint("1", filename),
+                paint(severity, kind),
+                paint("1", &self.message)
+            );
+        };
+        let mut out = format!(
+            "{}: {}: {}",
+            paint("1", &format!("{}:{}:{}", filename, span.line, span.col)),
+            paint(severity, kind),
+            paint("1", &self.message)
+        );
+        if let Some(text) = line {
+            let text = text.trim_end_matches('\r');
+            // Keep tabs in the padding so the caret lines up under them
+            let pad: String = text
+                .chars()
+                .take((span.col as usize).saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let gutter = |n: &str| paint("1;34", &format!("{:>5} |", n));
+            out.push_str(&format!("\n{} {}", gutter(&span.line.to_string()), text));
+            out.push_str(&format!("\n{} {}{}", gutter(""), pad, paint(severity, "^")));
+        }
+        out
+    }
+
+    /// Format as a single-line JSON object with the file, line, column,
+    /// severity, code and message. Line and column are null when unknown.
+    pub fn to_json(&self, filename: &str) -> String {
+        let json = JsonDiagnostic {
+            file: filename,
+            line: self.span.map(|span| span.line),
+            column: self.span.map(|span| span.col),
+            severity: self.severity,
+            code: self.code,
+            message: &self.message,
+        };
+        serde_json::to_string(&json).expect("diagnostic serializes")
+    }
+}
+
+/// A diagnostic placed in the file it is about, with the line it points
+/// at, so it can be shown after the source is gone
+#[derive(Debug, Clone, PartialEq)]
+pub struct Located {
+    pub file: String,           // empty when it isn't about a source file
+    pub diagnostic: Diagnostic, // with the span's line counted in `file`
+    line: Option<String>,       // the text of that line
+}
+
+impl Located {
+    /// Place a diagnostic with a span in the combined text of `source` in
+    /// the file (main or included) its line came from
+    pub fn new(source: &Source, diagnostic: &Diagnostic) -> Self {
+        let Some(span) = diagnostic.span else {
+            return Located::in_file(source.name(), diagnostic.clone());
+        };
+        let (file, text, span) = source.locate(span);
+        let line = text.lines().nth((span.line as usize).saturating_sub(1));
+        Located {
+            file: file.to_string(),
+            diagnostic: Diagnostic {
+                span: Some(span),
+                ..diagnostic.clone()
+            },
+            line: line.map(str::to_string),
+        }
+    }
+
+    /// A diagnostic about a file as a whole (or, with an empty name, about
+    /// no file, as when the assembler or linker fails)
+    pub fn in_file(file: &str, diagnostic: Diagnostic) -> Self {
+        Located {
+            file: file.to_string(),
+            diagnostic: Diagnostic {
+                span: None,
+                ..diagnostic
+            },
+            line: None,
+        }
+    }
+
+    /// Format for the terminal, as `Diagnostic::render` does
+    pub fn render(&self, color: bool) -> String {
+        let line = self.line.as_deref();
+        self.diagnostic.render_line(&self.file, line, color)
+    }
+
+    /// Format as a single-line JSON object, as `Diagnostic::to_json` does
+    pub fn to_json(&self) -> String {
+        self.diagnostic.to_json(&self.file)
+    }
+}
+
+/// The diagnostics a compile gave: on failure, the errors that stopped it
+/// and any warnings found first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    list: Vec<Located>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics::default()
+    }
+
+    pub fn push(&mut self, located: Located) {
+        self.list.push(located);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Located> {
+        self.list.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// How many are errors rather than warnings
+    pub fn error_count(&self) -> usize {
+        self.list
+            .iter()
+            .filter(|d| !d.diagnostic.is_warning())
+            .count()
+    }
+
+    /// Format each for the terminal, one after another
+    pub fn render(&self, color: bool) -> String {
+        let list: Vec<String> = self.list.iter().map(|d| d.render(color)).collect();
+        list.join("\n")
+    }
+}
+
+impl From<Located> for Diagnostics {
+    fn from(located: Located) -> Self {
+        Diagnostics {
+            list: vec![located],
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Located;
+    type IntoIter = std::slice::Iter<'a, Located>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(false))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// The fields of a diagnostic, in the order they're written as JSON
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    file: &'a str,
+    line: Option<u32>,
+    column: Option<u32>,
+    severity: Severity,
+    code: &'a str,
+    message: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ===================
+    // Rendering Tests
+    // ===================
+
+    #[test]
+    fn test_render_caret() {
+        let diag = Diagnostic::new(Some(Span { line: 2, col: 7 }), "Oops".to_string())
+            .with_code("parse-error");
+        let out = diag.render("t.bas", "10 CLS\n20 X = ) + 1\n", false);
+        assert_eq!(
+            out,
+            "t.bas:2:7: Parse error: Oops\n    2 | 20 X = ) + 1\n      |       ^"
+        );
+    }
+
+    #[test]
+    fn test_render_tabs_and_no_span() {
+        let diag = Diagnostic::new(Some(Span { line: 1, col: 3 }), "Oops".to_string());
+        let out = diag.render("t.bas", "\tX)", false);
+        assert!(out.ends_with("| \tX)\n      | \t ^"), "{:?}", out);
+        let diag = Diagnostic::new(None, "Oops".to_string());
+        assert_eq!(diag.render("t.bas", "", false), "t.bas: Error: Oops");
+    }
+
+    #[test]
+    fn test_render_color() {
+        let diag = Diagnostic::new(Some(Span { line: 1, col: 2 }), "Oops".to_string());
+        let out = diag.clone().as_warning().render("t.bas", "X)", true);
+        assert_eq!(
+            out,
+            "\x1b[1mt.bas:1:2\x1b[0m: \x1b[1;33mWarning\x1b[0m: \x1b[1mOops\x1b[0m\n\
+             \x1b[1;34m    1 |\x1b[0m X)\n\
+             \x1b[1;34m      |\x1b[0m  \x1b[1;33m^\x1b[0m"
+        );
+        let out = diag.render("t.bas", "X)", true);
+        assert!(out.contains("\x1b[1;31mError\x1b[0m"), "{:?}", out);
+    }
+
+    #[test]
+    fn test_located() {
+        let mut source = Source::new("t.bas", "PRINT 1\nPRINT )\n".to_string());
+        source.expand_includes(None, &[]).unwrap();
+        let diag = Diagnostic::new(Some(Span { line: 2, col: 7 }), "Oops".to_string())
+            .with_code("parse-error");
+        let mut list = Diagnostics::from(Located::new(&source, &diag));
+        let diag = Diagnostic::new(None, "Boom".to_string()).with_code("linker-error");
+        list.push(Located::in_file("", diag));
+        assert_eq!(
+            list.to_string(),
+            "t.bas:2:7: Parse error: Oops\n    2 | PRINT )\n      |       ^\nLinker error: Boom"
+        );
+        assert_eq!(list.error_count(), 2);
+    }
+
+    // ===================
+    // JSON Tests
+    // ===================
+
+    #[test]
+    fn test_to_json() {
+        let diag = Diagnostic::new(Some(Span { line: 2, col: 7 }), "Expected \"X\"".to_string())
+            .with_code("parse-error");
+        assert_eq!(
+            diag.to_json("dir\\t.bas"),
+            r#"{"file":"dir\\t.bas","line":2,"column":7,"severity":"error","code":"parse-error","message":"Expected \"X\""}"#
+        );
+        let diag = Diagnostic::new(None, "a\tb\u{1}".to_string()).as_warning();
+        assert_eq!(
+            diag.to_json("t.bas"),
+            r#"{"file":"t.bas","line":null,"column":null,"severity":"warning","code":"error","message":"a\tb\u0001"}"#
+        );
+    }
+}
@@ -0,0 +1,408 @@
+//! ELF64 relocatable object writer - the `.o` files the built-in
+//! assembler produces for x86-64 Linux
+//!
+//! An object has up to four loadable sections (`.text`, `.data`, `.bss`,
+//! `.rodata`), a relocation section for each one that refers to addresses
+//! the linker must fill in, a symbol table, and an empty `.note.GNU-stack`
+//! marking the stack non-executable.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+/// 64-bit absolute address
+pub const R_X86_64_64: u32 = 1;
+/// 32-bit PC-relative address
+pub const R_X86_64_PC32: u32 = 2;
+/// 32-bit PC-relative call or jump, through the PLT if need be
+pub const R_X86_64_PLT32: u32 = 4;
+/// 32-bit absolute address, zero-extended
+pub const R_X86_64_32: u32 = 10;
+/// 32-bit absolute address, sign-extended
+pub const R_X86_64_32S: u32 = 11;
+/// 16-bit absolute address
+pub const R_X86_64_16: u32 = 12;
+/// 8-bit absolute address
+pub const R_X86_64_8: u32 = 14;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+
+const HEADER_SIZE: usize = 64;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+/// What a section holds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SectionKind {
+    /// Machine code
+    Text,
+    /// Initialized, writable data
+    Data,
+    /// Zero-initialized data, which takes no space in the file
+    Bss,
+    /// Initialized data that is never written: string constants
+    Rodata,
+}
+
+/// A loadable section
+#[derive(Debug)]
+pub struct Section {
+    pub name: &'static str,
+    pub kind: SectionKind,
+    /// Contents (empty for `.bss`)
+    pub data: Vec<u8>,
+    /// Size in bytes: the length of `data`, or of the zeroes in `.bss`
+    pub size: u64,
+    pub align: u64,
+    pub relocs: Vec<Reloc>,
+}
+
+impl Section {
+    pub fn new(name: &'static str, kind: SectionKind) -> Self {
+        Section {
+            name,
+            kind,
+            data: Vec::new(),
+            size: 0,
+            align: 1,
+            relocs: Vec::new(),
+        }
+    }
+}
+
+/// What a relocation refers to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// The start of a section in this object (by index into
+    /// `Object::sections`)
+    Section(usize),
+    /// A symbol (by index into `Object::symbols`)
+    Symbol(usize),
+}
+
+/// An address for the linker to fill in: `target + addend`, less the
+/// address being patched for the PC-relative kinds
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reloc {
+    pub offset: u64,
+    /// One of the `R_X86_64_*` constants
+    pub kind: u32,
+    pub target: Target,
+    pub addend: i64,
+}
+
+/// A named address: defined in one of the sections, or (with no section)
+/// left for the linker to find in another object or library
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Option<usize>,
+    pub value: u64,
+    pub global: bool,
+}
+
+/// A relocatable object
+#[derive(Debug, Default)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+/// A string table being built
+struct StringTable(Vec<u8>);
+
+impl StringTable {
+    fn new() -> Self {
+        StringTable(vec![0])
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        let offset = self.0.len() as u32;
+        self.0.extend_from_slice(s.as_bytes());
+        self.0.push(0);
+        offset
+    }
+}
+
+/// A section header, before it's written out
+#[derive(Default)]
+struct Header {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl Header {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.name.to_le_bytes());
+        out.extend_from_slice(&self.kind.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes()); // address
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.link.to_le_bytes());
+        out.extend_from_slice(&self.info.to_le_bytes());
+        out.extend_from_slice(&self.align.to_le_bytes());
+        out.extend_from_slice(&self.entsize.to_le_bytes());
+    }
+}
+
+/// Pad `out` with zeroes to a multiple of `align`
+fn align_to(out: &mut Vec<u8>, align: u64) {
+    while out.len() as u64 % align.max(1) != 0 {
+        out.push(0);
+    }
+}
+
+impl Object {
+    /// The object file's bytes
+    pub fn write(&self) -> Vec<u8> {
+        let mut shstrtab = StringTable::new();
+        let mut strtab = StringTable::new();
+        let mut headers = vec![Header::default()];
+        let mut out = vec![0; HEADER_SIZE];
+
+        // Loadable sections: section header index = 1 + index
+        for section in &self.sections {
+            let (kind, flags) = match section.kind {
+                SectionKind::Text => (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR),
+                SectionKind::Data => (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE),
+                SectionKind::Bss => (SHT_NOBITS, SHF_ALLOC | SHF_WRITE),
+                SectionKind::Rodata => (SHT_PROGBITS, SHF_ALLOC),
+            };
+            align_to(&mut out, section.align);
+            headers.push(Header {
+                name: shstrtab.add(section.name),
+                kind,
+                flags,
+                offset: out.len() as u64,
+                size: section.size,
+                align: section.align,
+                ..Header::default()
+            });
+            out.extend_from_slice(&section.data);
+        }
+
+        // Symbols: the null symbol, one per section, then the locals, then
+        // the globals
+        let mut order: Vec<usize> = (0..self.symbols.len()).collect();
+        order.sort_by_key(|&i| self.symbols[i].global);
+        let first_symbol = 1 + self.sections.len();
+        let mut symbol_index = vec![0; self.symbols.len()];
+        for (position, &i) in order.iter().enumerate() {
+            symbol_index[i] = first_symbol + position;
+        }
+        let first_global = first_symbol + self.symbols.iter().filter(|s| !s.global).count();
+
+        let mut symtab = vec![0; SYMBOL_SIZE];
+        let mut symbol = |name: u32, info: u8, shndx: u16, value: u64| {
+            symtab.extend_from_slice(&name.to_le_bytes());
+            symtab.push(info);
+            symtab.push(0); // default visibility
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&0u64.to_le_bytes()); // size
+        };
+        for i in 0..self.sections.len() {
+            symbol(0, STB_LOCAL << 4 | STT_SECTION, 1 + i as u16, 0);
+        }
+        for &i in &order {
+            let s = &self.symbols[i];
+            let bind = if s.global { STB_GLOBAL } else { STB_LOCAL };
+            let shndx = s.section.map_or(0, |section| 1 + section as u16);
+            symbol(strtab.add(&s.name), bind << 4 | STT_NOTYPE, shndx, s.value);
+        }
+
+        // Relocation sections go right after the loadable ones, so the
+        // symbol table's index is known before they're written
+        let with_relocs: Vec<usize> = (0..self.sections.len())
+            .filter(|&i| !self.sections[i].relocs.is_empty())
+            .collect();
+        let symtab_index = 1 + self.sections.len() + with_relocs.len();
+        for &i in &with_relocs {
+            let section = &self.sections[i];
+            align_to(&mut out, 8);
+            let offset = out.len() as u64;
+            for reloc in &section.relocs {
+                let symbol = match reloc.target {
+                    Target::Section(s) => 1 + s,
+                    Target::Symbol(s) => symbol_index[s],
+                };
+                out.extend_from_slice(&reloc.offset.to_le_bytes());
+                out.extend_from_slice(&((symbol as u64) << 32 | reloc.kind as u64).to_le_bytes());
+                out.extend_from_slice(&reloc.addend.to_le_bytes());
+            }
+            headers.push(Header {
+                name: shstrtab.add(&format!(".rela{}", section.name)),
+                kind: SHT_RELA,
+                flags: SHF_INFO_LINK,
+                offset,
+                size: (section.relocs.len() * RELA_SIZE) as u64,
+                link: symtab_index as u32,
+                info: 1 + i as u32,
+                align: 8,
+                entsize: RELA_SIZE as u64,
+            });
+        }
+
+        align_to(&mut out, 8);
+        headers.push(Header {
+            name: shstrtab.add(".symtab"),
+            kind: SHT_SYMTAB,
+            offset: out.len() as u64,
+            size: symtab.len() as u64,
+            link: symtab_index as u32 + 1,
+            info: first_global as u32,
+            align: 8,
+            entsize: SYMBOL_SIZE as u64,
+            ..Header::default()
+        });
+        out.extend_from_slice(&symtab);
+
+        headers.push(Header {
+            name: shstrtab.add(".strtab"),
+            kind: SHT_STRTAB,
+            offset: out.len() as u64,
+            size: strtab.0.len() as u64,
+            align: 1,
+            ..Header::default()
+        });
+        out.extend_from_slice(&strtab.0);
+
+        headers.push(Header {
+            name: shstrtab.add(".note.GNU-stack"),
+            kind: SHT_PROGBITS,
+            offset: out.len() as u64,
+            align: 1,
+            ..Header::default()
+        });
+
+        let shstrndx = headers.len();
+        let name = shstrtab.add(".shstrtab");
+        headers.push(Header {
+            name,
+            kind: SHT_STRTAB,
+            offset: out.len() as u64,
+            size: shstrtab.0.len() as u64,
+            align: 1,
+            ..Header::default()
+        });
+        out.extend_from_slice(&shstrtab.0);
+
+        align_to(&mut out, 8);
+        let shoff = out.len() as u64;
+        for header in &headers {
+            header.write(&mut out);
+        }
+
+        // The ELF header
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(b"\x7fELF");
+        header.extend_from_slice(&[2, 1, 1, 0]); // 64-bit, little-endian, version 1, System V
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&1u16.to_le_bytes()); // relocatable
+        header.extend_from_slice(&62u16.to_le_bytes()); // x86-64
+        header.extend_from_slice(&1u32.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes()); // entry point
+        header.extend_from_slice(&0u64.to_le_bytes()); // program headers
+        header.extend_from_slice(&shoff.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // flags
+        header.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // program header size
+        header.extend_from_slice(&0u16.to_le_bytes()); // program header count
+        header.extend_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&(headers.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(shstrndx as u16).to_le_bytes());
+        out[..HEADER_SIZE].copy_from_slice(&header);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    // ===================
+    // Object Layout Tests
+    // ===================
+
+    #[test]
+    fn test_object_layout() {
+        let mut text = Section::new(".text", SectionKind::Text);
+        text.data = vec![0xE8, 0, 0, 0, 0, 0xC3];
+        text.size = 6;
+        text.relocs.push(Reloc {
+            offset: 1,
+            kind: R_X86_64_PLT32,
+            target: Target::Symbol(1),
+            addend: -4,
+        });
+        let mut bss = Section::new(".bss", SectionKind::Bss);
+        bss.size = 4096;
+        let object = Object {
+            sections: vec![text, bss],
+            symbols: vec![
+                Symbol {
+                    name: "main".to_string(),
+                    section: Some(0),
+                    value: 0,
+                    global: true,
+                },
+                Symbol {
+                    name: "puts".to_string(),
+                    section: None,
+                    value: 0,
+                    global: true,
+                },
+            ],
+        };
+        let bytes = object.write();
+        assert_eq!(&bytes[..4], b"\x7fELF");
+        assert_eq!(u16_at(&bytes, 16), 1, "relocatable");
+        assert_eq!(u16_at(&bytes, 18), 62, "x86-64");
+
+        // null, .text, .bss, .rela.text, .symtab, .strtab, note, .shstrtab
+        let shoff = u64_at(&bytes, 40) as usize;
+        assert_eq!(u16_at(&bytes, 60), 8);
+        assert_eq!(u16_at(&bytes, 62), 7);
+
+        // .bss takes no room in the file
+        let bss = shoff + SECTION_HEADER_SIZE * 2;
+        assert_eq!(u64_at(&bytes, bss + 32), 4096);
+        assert!(bytes.len() < 4096);
+
+        // The call's relocation names puts, the last symbol
+        let rela = shoff + SECTION_HEADER_SIZE * 3;
+        let offset = u64_at(&bytes, rela + 24) as usize;
+        assert_eq!(u64_at(&bytes, offset), 1);
+        assert_eq!(u64_at(&bytes, offset + 8), 4 << 32 | R_X86_64_PLT32 as u64);
+    }
+}
@@ -0,0 +1,662 @@
+//! Backend - emits x86-64 assembly (Intel syntax) from the IR
+//!
+//! Each IR instruction expands to a short fixed sequence using the register
+//! conventions described in ir.rs; `Asm` instructions are copied through.
+//! The data sections (string literals, read-only; the DATA table, static
+//! variables and the GOSUB stack) are emitted after the code. Each string
+//! literal's length is a symbol, `_str_N_len`, defined ahead of the code so
+//! every assembler reads it as an immediate.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::abi::Target;
+use crate::include::Source;
+use crate::ir::{Const, Frame, GOSUB_STACK_SIZE, Inst, Module};
+use crate::lexer::Span;
+use crate::parser::{BinaryOp, DataType, Literal};
+use std::fmt::{self, Write};
+
+/// Stack space for temporary values (must be 16-byte aligned)
+const STACK_TEMP_SPACE: i32 = 16;
+
+/// Scratch registers for `SaveTemp`, by temp number: caller-saved on both
+/// ABIs and untouched by every other IR instruction
+pub const INT_TEMPS: [&str; 4] = ["r8d", "r9d", "r10d", "r11d"];
+pub const FLOAT_TEMPS: [&str; 3] = ["xmm3", "xmm4", "xmm5"];
+
+/// Assembly for a whole module, for `target`. Given the program's
+/// `source`, each source line is shown as a comment above its code.
+pub fn emit(module: &Module, target: Target, source: Option<&Source>) -> String {
+    let mut out = Emitter {
+        // Most instructions expand to a line or two of about this size
+        out: String::with_capacity(module.code.len() * 32),
+        frames: &module.frames,
+        target,
+        source: source.map(|source| (source, source.text.lines().collect())),
+    };
+    out.line(".intel_syntax noprefix");
+    for (i, s) in module.strings.iter().enumerate() {
+        out.line(format_args!(".equ _str_{}_len, {}", i, s.len()));
+    }
+    out.line(".text");
+    for label in &module.exports {
+        out.line(format_args!(".globl {}", label));
+    }
+    out.line("");
+    for inst in &module.code {
+        out.inst(inst);
+    }
+    out.data_section(module);
+    out.out
+}
+
+struct Emitter<'a> {
+    out: String,
+    frames: &'a [Frame],
+    target: Target,
+    source: Option<(&'a Source, Vec<&'a str>)>, // source and its lines, to annotate the code with
+}
+
+impl Emitter<'_> {
+    fn line(&mut self, s: impl fmt::Display) {
+        writeln!(self.out, "{}", s).expect("writing to a String");
+    }
+
+    /// An indented instruction
+    fn op(&mut self, s: impl fmt::Display) {
+        self.out.push_str("    ");
+        self.line(s);
+    }
+
+    /// The instruction for the work type: integer, SINGLE or DOUBLE
+    fn typed(&mut self, ty: DataType, int_instr: &str, single_instr: &str, double_instr: &str) {
+        match ty {
+            DataType::Integer | DataType::Long => self.op(int_instr),
+            DataType::Single => self.op(single_instr),
+            _ => self.op(double_instr),
+        }
+    }
+
+    fn inst(&mut self, inst: &Inst) {
+        match inst {
+            Inst::Label(label) => self.line(format_args!("{}:", label)),
+            Inst::Enter { label, frame } => {
+                self.line(format_args!("{}:", label));
+                self.op("push rbp");
+                self.op("mov rbp, rsp");
+                // System V AMD64 ABI stack alignment rules:
+                // - On function entry (after call pushed return addr): rsp % 16 == 8
+                // - After push rbp: rsp % 16 == 0
+                // - Before any call: rsp % 16 == 0
+                //
+                // Since we use 16-byte sub/add for all temporaries in expression
+                // evaluation, the frame size is a multiple of 16.
+                let frame = &self.frames[*frame];
+                if frame.size > 0 {
+                    self.op(format_args!("sub rsp, {}", frame.size));
+                }
+                for (reg, slot) in &frame.saved {
+                    self.op(format_args!("mov QWORD PTR [rbp + {}], {}", slot, reg));
+                }
+            }
+            Inst::Return { frame } => {
+                for (reg, slot) in &self.frames[*frame].saved {
+                    self.op(format_args!("mov {}, QWORD PTR [rbp + {}]", reg, slot));
+                }
+                self.op("leave");
+                self.op("ret");
+            }
+            Inst::Const(c) => self.constant(*c),
+            Inst::Load { ty, addr } => match ty {
+                DataType::Integer => self.op(format_args!("movsx eax, WORD PTR [{}]", addr)),
+                DataType::Long => self.op(format_args!("mov eax, DWORD PTR [{}]", addr)),
+                DataType::Single => self.op(format_args!("movss xmm0, DWORD PTR [{}]", addr)),
+                DataType::Double => self.op(format_args!("movsd xmm0, QWORD PTR [{}]", addr)),
+                DataType::String => {
+                    // The length is 8 bytes below the pointer
+                    self.op(format_args!("mov rax, QWORD PTR [{}]", addr));
+                    self.op(format_args!("mov rdx, QWORD PTR [{} - 8]", addr));
+                }
+            },
+            Inst::Store { ty, addr } => match ty {
+                DataType::Integer => self.op(format_args!("mov WORD PTR [{}], ax", addr)),
+                DataType::Long => self.op(format_args!("mov DWORD PTR [{}], eax", addr)),
+                DataType::Single => self.op(format_args!("movss DWORD PTR [{}], xmm0", addr)),
+                DataType::Double => self.op(format_args!("movsd QWORD PTR [{}], xmm0", addr)),
+                DataType::String => {
+                    self.op(format_args!("mov QWORD PTR [{}], rax", addr));
+                    self.op(format_args!("mov QWORD PTR [{} - 8], rdx", addr));
+                }
+            },
+            Inst::Convert { from, to, checked } => self.convert(*from, *to, *checked),
+            Inst::Push(ty) => {
+                // 16 bytes keep the stack aligned for calls in the right operand
+                self.op(format_args!("sub rsp, {}", STACK_TEMP_SPACE));
+                match ty {
+                    DataType::Integer | DataType::Long => self.op("mov QWORD PTR [rsp], rax"),
+                    DataType::Single => self.op("movss DWORD PTR [rsp], xmm0"),
+                    DataType::Double => self.op("movsd QWORD PTR [rsp], xmm0"),
+                    DataType::String => {
+                        self.op("mov QWORD PTR [rsp], rax");
+                        self.op("mov QWORD PTR [rsp + 8], rdx");
+                    }
+                }
+            }
+            Inst::PopRight(ty) => {
+                match ty {
+                    DataType::Integer | DataType::Long => {
+                        self.op("mov ecx, eax");
+                        self.op("mov rax, QWORD PTR [rsp]");
+                    }
+                    DataType::Single => {
+                        self.op("movss xmm1, xmm0");
+                        self.op("movss xmm0, DWORD PTR [rsp]");
+                    }
+                    DataType::Double => {
+                        self.op("movsd xmm1, xmm0");
+                        self.op("movsd xmm0, QWORD PTR [rsp]");
+                    }
+                    DataType::String => unreachable!("string operands go to the runtime"),
+                }
+                self.op(format_args!("add rsp, {}", STACK_TEMP_SPACE));
+            }
+            Inst::SaveTemp { ty, temp } => match ty {
+                DataType::Integer | DataType::Long => {
+                    self.op(format_args!("mov {}, eax", INT_TEMPS[*temp]))
+                }
+                DataType::Single | DataType::Double => {
+                    self.op(format_args!("movaps {}, xmm0", FLOAT_TEMPS[*temp]))
+                }
+                DataType::String => unreachable!("string operands go to the runtime"),
+            },
+            Inst::RestoreRight { ty, temp } => match ty {
+                DataType::Integer | DataType::Long => {
+                    self.op("mov ecx, eax");
+                    self.op(format_args!("mov eax, {}", INT_TEMPS[*temp]));
+                }
+                DataType::Single | DataType::Double => {
+                    self.op("movaps xmm1, xmm0");
+                    self.op(format_args!("movaps xmm0, {}", FLOAT_TEMPS[*temp]));
+                }
+                DataType::String => unreachable!("string operands go to the runtime"),
+            },
+            Inst::ConstRight(c) => match c {
+                Const::Long(n) => self.op(format_args!("mov ecx, {}", n)),
+                // The left operand is in xmm0, so rax is free
+                Const::Single(x) => {
+                    self.op(format_args!("mov eax, 0x{:X}", x.to_bits()));
+                    self.op("movd xmm1, eax");
+                }
+                Const::Double(x) => {
+                    self.op(format_args!("mov rax, 0x{:X}", x.to_bits()));
+                    self.op("movq xmm1, rax");
+                }
+                Const::Str { .. } => unreachable!("string operands go to the runtime"),
+            },
+            Inst::LoadRight { ty, addr } => match ty {
+                DataType::Integer => self.op(format_args!("movsx ecx, WORD PTR [{}]", addr)),
+                DataType::Long => self.op(format_args!("mov ecx, DWORD PTR [{}]", addr)),
+                DataType::Single => self.op(format_args!("movss xmm1, DWORD PTR [{}]", addr)),
+                DataType::Double => self.op(format_args!("movsd xmm1, QWORD PTR [{}]", addr)),
+                DataType::String => unreachable!("string operands go to the runtime"),
+            },
+            Inst::Binary { op, ty } => self.binary(*op, *ty),
+            Inst::Neg(ty) => match ty {
+                DataType::Integer | DataType::Long => self.op("neg eax"),
+                DataType::Single => {
+                    // Flip the sign bit
+                    self.op("mov eax, 0x80000000");
+                    self.op("movd xmm1, eax");
+                    self.op("xorps xmm0, xmm1");
+                }
+                _ => {
+                    self.op("mov rax, 0x8000000000000000");
+                    self.op("movq xmm1, rax");
+                    self.op("xorpd xmm0, xmm1");
+                }
+            },
+            Inst::Not(ty) => {
+                self.test_zero(*ty);
+                self.op("sete al");
+                self.op("movzx eax, al");
+                self.op("neg eax");
+            }
+            Inst::CheckOverflow(ty) => match ty {
+                DataType::Integer => {
+                    self.op("movsx edx, ax");
+                    self.op("cmp edx, eax");
+                    self.op("jne _rt_overflow");
+                }
+                DataType::Long => self.op("jo _rt_overflow"),
+                _ => {}
+            },
+            Inst::Jump(label) => self.op(format_args!("jmp {}", label)),
+            Inst::Branch { ty, if_zero, label } => {
+                self.test_zero(*ty);
+                let jcc = if *if_zero { "je" } else { "jne" };
+                self.op(format_args!("{} {}", jcc, label));
+            }
+            Inst::Call(func) => self.op(format_args!("call {}", func)),
+            Inst::CallLibc(func) => {
+                let shadow = self.target.shadow_space();
+                if shadow > 0 {
+                    self.op(format_args!("sub rsp, {}", shadow));
+                }
+                self.op(format_args!("call {}{}", self.target.symbol_prefix(), func));
+                if shadow > 0 {
+                    self.op(format_args!("add rsp, {}", shadow));
+                }
+            }
+            Inst::Asm(text) if text.is_empty() => self.line(""),
+            Inst::Asm(text) => self.op(text),
+            Inst::InlineAsm(lines) => {
+                for line in lines.iter().map(|line| line.trim()) {
+                    if line.ends_with(':') {
+                        self.line(line);
+                    } else if !line.is_empty() {
+                        self.op(line);
+                    }
+                }
+            }
+            Inst::Source(line) => {
+                let Some((source, lines)) = &self.source else {
+                    return;
+                };
+                let Some(text) = lines.get(*line as usize - 1) else {
+                    return;
+                };
+                let span = Span {
+                    line: *line,
+                    col: 1,
+                };
+                let (name, _, span) = source.locate(span);
+                let comment = if source.in_main_file(*line) {
+                    format!("# line {}: {}", span.line, text.trim())
+                } else {
+                    format!("# {}:{}: {}", name, span.line, text.trim())
+                };
+                self.line(comment);
+            }
+        }
+    }
+
+    fn constant(&mut self, c: Const) {
+        match c {
+            Const::Long(n) => self.op(format_args!("mov eax, {}", n)),
+            Const::Single(x) => {
+                self.op(format_args!("mov eax, 0x{:X}", x.to_bits()));
+                self.op("movd xmm0, eax");
+            }
+            Const::Double(x) => {
+                self.op(format_args!("mov rax, 0x{:X}", x.to_bits()));
+                self.op("movq xmm0, rax");
+            }
+            Const::Str { index, .. } => {
+                self.op(format_args!("lea rax, [rip + _str_{}]", index));
+                self.op(format_args!("mov rdx, _str_{}_len", index));
+            }
+        }
+    }
+
+    /// Set the flags from comparing the accumulator with zero
+    fn test_zero(&mut self, ty: DataType) {
+        match ty {
+            DataType::Integer | DataType::Long => self.op("test eax, eax"),
+            DataType::Single => {
+                self.op("xorps xmm1, xmm1");
+                self.op("ucomiss xmm0, xmm1");
+            }
+            _ => {
+                self.op("xorpd xmm1, xmm1");
+                self.op("ucomisd xmm0, xmm1");
+            }
+        }
+    }
+
+    fn convert(&mut self, from: DataType, to: DataType, checked: bool) {
+        match (from, to) {
+            _ if from == to => {}
+            // Sign-extend 16-bit to 32-bit
+            (DataType::Integer, DataType::Long) => self.op("movsx eax, ax"),
+            // The value is truncated to 16 bits when stored
+            (DataType::Long, DataType::Integer) => {
+                if checked {
+                    self.inst(&Inst::CheckOverflow(DataType::Integer));
+                }
+            }
+            (DataType::Integer | DataType::Long, DataType::Single) => self.op("cvtsi2ss xmm0, eax"),
+            (DataType::Integer | DataType::Long, DataType::Double) => self.op("cvtsi2sd xmm0, eax"),
+            (DataType::Single, DataType::Double) => self.op("cvtss2sd xmm0, xmm0"),
+            (DataType::Double, DataType::Single) => self.op("cvtsd2ss xmm0, xmm0"),
+            // Truncate toward zero
+            (DataType::Single | DataType::Double, DataType::Integer | DataType::Long) => {
+                let cvt = if from == DataType::Single {
+                    "cvttss2si"
+                } else {
+                    "cvttsd2si"
+                };
+                if checked {
+                    // Convert to 64 bits so out-of-range values can be detected
+                    self.op(format_args!("{} rax, xmm0", cvt));
+                    self.op("movsxd rdx, eax");
+                    self.op("cmp rdx, rax");
+                    self.op("jne _rt_overflow");
+                    self.inst(&Inst::CheckOverflow(to));
+                } else {
+                    self.op(format_args!("{} eax, xmm0", cvt));
+                }
+            }
+            _ => panic!("Cannot implicitly convert to/from String"),
+        }
+    }
+
+    /// Convert float operands to integers (truncate). Used for logical ops.
+    fn cvt_float_to_int(&mut self, ty: DataType) {
+        if !ty.is_integer() {
+            self.typed(ty, "", "cvttss2si eax, xmm0", "cvttsd2si eax, xmm0");
+            self.typed(ty, "", "cvttss2si ecx, xmm1", "cvttsd2si ecx, xmm1");
+        }
+    }
+
+    /// Convert float operands to integers, rounding half to even as CLNG
+    /// does (the default MXCSR mode). Used for IntDiv and Mod.
+    fn round_float_to_int(&mut self, ty: DataType) {
+        if !ty.is_integer() {
+            self.typed(ty, "", "cvtss2si eax, xmm0", "cvtsd2si eax, xmm0");
+            self.typed(ty, "", "cvtss2si ecx, xmm1", "cvtsd2si ecx, xmm1");
+        }
+    }
+
+    /// Convert integer/single operands to double. Used for Div, Pow.
+    fn cvt_to_double(&mut self, ty: DataType) {
+        match ty {
+            DataType::Integer | DataType::Long => {
+                self.op("cvtsi2sd xmm0, eax");
+                self.op("cvtsi2sd xmm1, ecx");
+            }
+            DataType::Single => {
+                self.op("cvtss2sd xmm0, xmm0");
+                self.op("cvtss2sd xmm1, xmm1");
+            }
+            _ => {}
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, ty: DataType) {
+        match op {
+            BinaryOp::Add => self.typed(ty, "add eax, ecx", "addss xmm0, xmm1", "addsd xmm0, xmm1"),
+            BinaryOp::Sub => self.typed(ty, "sub eax, ecx", "subss xmm0, xmm1", "subsd xmm0, xmm1"),
+            BinaryOp::Mul => {
+                self.typed(ty, "imul eax, ecx", "mulss xmm0, xmm1", "mulsd xmm0, xmm1")
+            }
+            BinaryOp::Div => {
+                self.cvt_to_double(ty);
+                self.op("xorpd xmm2, xmm2");
+                self.op("ucomisd xmm1, xmm2");
+                self.op("je _rt_div_zero");
+                self.op("divsd xmm0, xmm1");
+            }
+            BinaryOp::IntDiv | BinaryOp::Mod => {
+                self.round_float_to_int(ty);
+                self.op("test ecx, ecx");
+                self.op("jz _rt_div_zero");
+                self.op("cdq");
+                self.op("idiv ecx");
+                if op == BinaryOp::Mod {
+                    self.op("mov eax, edx");
+                }
+            }
+            BinaryOp::Pow => {
+                self.cvt_to_double(ty);
+                self.inst(&Inst::CallLibc("pow".to_string()));
+            }
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Gt
+            | BinaryOp::Le
+            | BinaryOp::Ge => self.comparison(op, ty),
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => {
+                self.cvt_float_to_int(ty);
+                let instr = match op {
+                    BinaryOp::And => "and",
+                    BinaryOp::Or => "or",
+                    _ => "xor",
+                };
+                self.op(format_args!("{} eax, ecx", instr));
+            }
+            BinaryOp::AndAlso | BinaryOp::OrElse => {
+                unreachable!("short-circuit operators are lowered to branches")
+            }
+        }
+    }
+
+    /// Compare eax/xmm0 with ecx/xmm1, leaving -1 (true) or 0 in eax
+    fn comparison(&mut self, op: BinaryOp, ty: DataType) {
+        // (signed_setcc, unsigned_setcc) - signed for integers, unsigned for floats
+        let (signed, unsigned) = match op {
+            BinaryOp::Eq => ("sete", "sete"),
+            BinaryOp::Ne => ("setne", "setne"),
+            BinaryOp::Lt => ("setl", "setb"),
+            BinaryOp::Gt => ("setg", "seta"),
+            BinaryOp::Le => ("setle", "setbe"),
+            BinaryOp::Ge => ("setge", "setae"),
+            _ => unreachable!(),
+        };
+        self.typed(
+            ty,
+            "cmp eax, ecx",
+            "ucomiss xmm0, xmm1",
+            "ucomisd xmm0, xmm1",
+        );
+        let setcc = if ty.is_integer() { signed } else { unsigned };
+        self.op(format_args!("{} al", setcc));
+        self.op("movzx eax, al");
+        self.op("neg eax");
+    }
+
+    fn data_section(&mut self, module: &Module) {
+        // String literals, which nothing writes to
+        if !module.strings.is_empty() {
+            self.out.push('\n');
+            self.line(self.target.rodata_section());
+        }
+        for (i, s) in module.strings.iter().enumerate() {
+            self.line(format_args!("_str_{}:", i));
+            self.op(format_args!(".ascii {}", quoted(s)));
+        }
+
+        self.out.push_str("\n.data\n");
+
+        // DATA table - always define it (even if empty) to avoid linker errors
+        self.line("_data_table:");
+        for (i, item) in module.data.iter().enumerate() {
+            match item {
+                Literal::Integer(n) => {
+                    self.op(".quad 0  # type int");
+                    self.op(format_args!(".quad {}", n));
+                }
+                Literal::Typed(n, data_type) if data_type.is_integer() => {
+                    self.op(".quad 0  # type int");
+                    self.op(format_args!(".quad {}", *n as i64));
+                }
+                Literal::Float(f) | Literal::Typed(f, _) => {
+                    self.op(".quad 1  # type float");
+                    self.op(format_args!(".quad 0x{:X}", f.to_bits()));
+                }
+                Literal::String(_) => {
+                    self.op(".quad 2  # type string");
+                    self.op(format_args!(".quad _data_str_{}", i));
+                }
+            }
+        }
+        self.line(format_args!("_data_count: .quad {}", module.data.len()));
+
+        // DATA strings follow their length, for _rt_read_string, and end in
+        // a NUL, for strtod when one is READ as a number
+        for (i, item) in module.data.iter().enumerate() {
+            if let Literal::String(s) = item {
+                self.op(format_args!(".quad {}", s.len()));
+                self.line(format_args!("_data_str_{}: .asciz {}", i, quoted(s)));
+            }
+        }
+
+        // DATA pointer
+        self.line("_data_ptr: .quad 0");
+
+        // GOSUB return stack pointer
+        if module.gosub_stack {
+            self.line("_gosub_sp: .quad 0");
+        }
+
+        self.line("");
+        self.line(".bss");
+        if module.gosub_stack {
+            self.line(format_args!(
+                "_gosub_stack: .skip {}  # GOSUB return stack (64K entries)",
+                GOSUB_STACK_SIZE
+            ));
+        }
+
+        // Main-program and SHARED variables, and array descriptors
+        for (label, (before, size)) in &module.statics {
+            self.op(".p2align 3");
+            if *before > 0 {
+                self.op(format_args!(".skip {}", before));
+            }
+            self.line(format_args!("{}: .skip {}", label, size));
+        }
+    }
+}
+
+/// A string as an assembler string literal holding exactly its bytes:
+/// printable ASCII as is, anything else (control characters, NULs, the
+/// bytes of non-ASCII characters) as an octal escape, which every
+/// assembler reads the same way
+fn quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            b' '..=b'~' => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\{:03o}", b);
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The instructions emitted for `code`, one per line, without the
+    /// header and data section
+    fn emit_code(code: Vec<Inst>) -> Vec<String> {
+        let module = Module {
+            code,
+            frames: vec![Frame {
+                size: 32,
+                saved: vec![("rbx", -24)],
+            }],
+            ..Default::default()
+        };
+        let asm = emit(&module, Target::Linux, None);
+        let text = asm.split("\n.data\n").next().unwrap();
+        text.lines()
+            .skip_while(|line| !line.is_empty())
+            .skip(1)
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    // ===================
+    // Emission Tests
+    // ===================
+
+    #[test]
+    fn test_emit_expression() {
+        let code = vec![
+            Inst::Const(Const::Long(2)),
+            Inst::Push(DataType::Long),
+            Inst::Load {
+                ty: DataType::Integer,
+                addr: "rbp + -8".into(),
+            },
+            Inst::Convert {
+                from: DataType::Integer,
+                to: DataType::Long,
+                checked: false,
+            },
+            Inst::PopRight(DataType::Long),
+            Inst::Binary {
+                op: BinaryOp::Lt,
+                ty: DataType::Long,
+            },
+            Inst::Branch {
+                ty: DataType::Long,
+                if_zero: true,
+                label: ".Lelse_0".to_string(),
+            },
+        ];
+        assert_eq!(
+            emit_code(code),
+            [
+                "mov eax, 2",
+                "sub rsp, 16",
+                "mov QWORD PTR [rsp], rax",
+                "movsx eax, WORD PTR [rbp + -8]",
+                "movsx eax, ax",
+                "mov ecx, eax",
+                "mov rax, QWORD PTR [rsp]",
+                "add rsp, 16",
+                "cmp eax, ecx",
+                "setl al",
+                "movzx eax, al",
+                "neg eax",
+                "test eax, eax",
+                "je .Lelse_0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quoted() {
+        assert_eq!(quoted("a \"b\" \\c"), r#""a \"b\" \\c""#);
+        assert_eq!(quoted("\t\n\0\x7f"), r#""\011\012\000\177""#);
+        assert_eq!(quoted("é#"), r#""\303\251#""#);
+    }
+
+    #[test]
+    fn test_emit_frame() {
+        let code = vec![
+            Inst::Enter {
+                label: "_proc_F".to_string(),
+                frame: 0,
+            },
+            Inst::Asm("nop".to_string()),
+            Inst::Return { frame: 0 },
+        ];
+        assert_eq!(
+            emit_code(code),
+            [
+                "_proc_F:",
+                "push rbp",
+                "mov rbp, rsp",
+                "sub rsp, 32",
+                "mov QWORD PTR [rbp + -24], rbx",
+                "nop",
+                "mov rbx, QWORD PTR [rbp + -24]",
+                "leave",
+                "ret",
+            ]
+        );
+    }
+}
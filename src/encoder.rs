@@ -0,0 +1,1015 @@
+//! x86-64 instruction encoder - machine code for the instructions the code
+//! generator and runtime use
+//!
+//! Every instruction has one fixed encoding: jumps and calls always take a
+//! 32-bit displacement, so an instruction's size never depends on where
+//! its labels end up and the assembler lays out a section in one pass.
+//! Where an instruction refers to a symbol, `encode` leaves zeroes and a
+//! `Fixup` saying what belongs there.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+const REX_W: u8 = 0x08;
+const REX_R: u8 = 0x04;
+const REX_X: u8 = 0x02;
+const REX_B: u8 = 0x01;
+
+const GP64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const GP32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const GP16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+const GP8: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+
+/// A register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reg {
+    /// General-purpose register: number (0-15) and size in bytes
+    Gp(u8, u8),
+    /// SSE register xmm0-xmm15
+    Xmm(u8),
+}
+
+impl Reg {
+    /// The register with this (lowercase) name
+    pub fn parse(name: &str) -> Option<Reg> {
+        for (size, names) in [(8, &GP64), (4, &GP32), (2, &GP16), (1, &GP8)] {
+            if let Some(num) = names.iter().position(|&n| n == name) {
+                return Some(Reg::Gp(num as u8, size));
+            }
+        }
+        let num: u8 = name.strip_prefix("xmm")?.parse().ok()?;
+        (num < 16).then_some(Reg::Xmm(num))
+    }
+
+    fn num(self) -> u8 {
+        match self {
+            Reg::Gp(num, _) | Reg::Xmm(num) => num,
+        }
+    }
+}
+
+/// A constant, or a symbol's address plus a constant, less another
+/// symbol's address for the distance between two labels
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Value {
+    pub symbol: Option<String>,
+    pub minus: Option<String>,
+    pub addend: i64,
+}
+
+impl Value {
+    pub fn constant(n: i64) -> Self {
+        Value {
+            addend: n,
+            ..Value::default()
+        }
+    }
+
+    /// The value, if it's known without knowing any addresses
+    pub fn as_constant(&self) -> Option<i64> {
+        (self.symbol.is_none() && self.minus.is_none()).then_some(self.addend)
+    }
+}
+
+/// A memory operand: `[base + index*scale + disp]`, or `[rip + disp]`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mem {
+    /// Size from a `BYTE PTR`-style prefix, if any
+    pub size: Option<u8>,
+    pub base: Option<u8>,
+    /// Index register and scale
+    pub index: Option<(u8, u8)>,
+    pub rip: bool,
+    pub disp: Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Reg(Reg),
+    Mem(Mem),
+    Imm(Value),
+}
+
+/// How a fixup's field is computed from its value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FixupKind {
+    /// 32 bits relative to the field: value - address of the field
+    Pc32,
+    /// Like `Pc32`, for the target of a call or jump
+    Branch32,
+    /// The value itself, in this many bytes; sign-extended by the CPU when
+    /// `signed`
+    Abs(u8, bool),
+}
+
+/// A field of an instruction that depends on a symbol's address.
+/// PC-relative addends already account for the field not being at the
+/// end of the instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixup {
+    /// Offset of the field within the instruction
+    pub offset: usize,
+    pub kind: FixupKind,
+    pub value: Value,
+}
+
+/// An encoded instruction
+#[derive(Debug, PartialEq)]
+pub struct Encoded {
+    pub bytes: Vec<u8>,
+    pub fixups: Vec<Fixup>,
+}
+
+/// An instruction being encoded, field by field
+#[derive(Default)]
+struct Builder {
+    prefixes: Vec<u8>,
+    rex: u8,
+    /// spl, bpl, sil and dil need a REX prefix even when it's empty
+    force_rex: bool,
+    opcode: Vec<u8>,
+    modrm: Option<u8>,
+    sib: Option<u8>,
+    /// Displacement, its size, and whether it's relative to rip
+    disp: Option<(Value, usize, bool)>,
+    /// Immediate, its size, and whether the CPU sign-extends it
+    imm: Option<(Value, usize, bool)>,
+    /// rel32 branch target
+    target: Option<Value>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Whether `n` fits in `width` bytes, read as signed, or also as
+/// unsigned when the operation is no wider than that
+fn fits(n: i64, width: usize, unsigned: bool) -> bool {
+    let bits = 8 * width as u32;
+    let min = -(1i64 << (bits - 1));
+    let max = if unsigned {
+        (1i64 << bits) - 1
+    } else {
+        (1i64 << (bits - 1)) - 1
+    };
+    (min..=max).contains(&n)
+}
+
+fn fits_i8(value: &Value) -> bool {
+    value.as_constant().is_some_and(|n| fits(n, 1, false))
+}
+
+impl Builder {
+    fn new(opcode: &[u8]) -> Self {
+        Builder {
+            opcode: opcode.to_vec(),
+            ..Builder::default()
+        }
+    }
+
+    /// Operand size prefix or REX.W for a general-purpose operation
+    fn size(&mut self, size: u8) -> &mut Self {
+        match size {
+            2 => self.prefixes.push(0x66),
+            8 => self.rex |= REX_W,
+            _ => {}
+        }
+        self
+    }
+
+    /// A mandatory SSE prefix (66, F2 or F3)
+    fn prefix(&mut self, prefix: u8) -> &mut Self {
+        self.prefixes.push(prefix);
+        self
+    }
+
+    /// A register's number, noting when it needs a REX prefix to be named
+    fn reg_num(&mut self, reg: Reg) -> u8 {
+        if let Reg::Gp(4..=7, 1) = reg {
+            self.force_rex = true;
+        }
+        reg.num()
+    }
+
+    /// ModRM with a register in the reg field and `rm` in the r/m field
+    fn reg_rm(&mut self, reg: Reg, rm: &Operand) -> Result<&mut Self> {
+        let num = self.reg_num(reg);
+        self.rm(num, rm)
+    }
+
+    /// ModRM (and SIB and displacement) with `field` - a register number or
+    /// an opcode extension - in the reg field and `rm` in the r/m field
+    fn rm(&mut self, field: u8, rm: &Operand) -> Result<&mut Self> {
+        if field & 8 != 0 {
+            self.rex |= REX_R;
+        }
+        let reg = (field & 7) << 3;
+        match rm {
+            Operand::Reg(r) => {
+                let num = self.reg_num(*r);
+                if num & 8 != 0 {
+                    self.rex |= REX_B;
+                }
+                self.modrm = Some(0xC0 | reg | (num & 7));
+            }
+            Operand::Mem(m) => self.mem(reg, m)?,
+            Operand::Imm(_) => return Err("expected a register or memory operand".to_string()),
+        }
+        Ok(self)
+    }
+
+    fn mem(&mut self, reg: u8, m: &Mem) -> Result<()> {
+        let scale = |index: u8, scale: u8| -> Result<u8> {
+            if index == 4 {
+                return Err("rsp can't be an index register".to_string());
+            }
+            let bits = match scale {
+                1 => 0,
+                2 => 1,
+                4 => 2,
+                8 => 3,
+                _ => return Err(format!("bad scale {}", scale)),
+            };
+            Ok(bits << 6 | (index & 7) << 3)
+        };
+        if m.index.is_some_and(|(index, _)| index & 8 != 0) {
+            self.rex |= REX_X;
+        }
+        if m.rip {
+            self.modrm = Some(reg | 0x05);
+            self.disp = Some((m.disp.clone(), 4, true));
+            return Ok(());
+        }
+        let Some(base) = m.base else {
+            // Absolute, with or without an index
+            self.modrm = Some(reg | 0x04);
+            self.sib = Some(match m.index {
+                Some((index, s)) => scale(index, s)? | 0x05,
+                None => 0x25,
+            });
+            self.disp = Some((m.disp.clone(), 4, false));
+            return Ok(());
+        };
+        if base & 8 != 0 {
+            self.rex |= REX_B;
+        }
+        // rbp and r13 as a base always take a displacement
+        let (mode, width) = match m.disp.as_constant() {
+            Some(0) if base & 7 != 5 => (0x00, 0),
+            Some(n) if fits(n, 1, false) => (0x40, 1),
+            _ => (0x80, 4),
+        };
+        if m.index.is_none() && base & 7 != 4 {
+            self.modrm = Some(mode | reg | (base & 7));
+        } else {
+            // rsp and r12 as a base need a SIB byte
+            self.modrm = Some(mode | reg | 0x04);
+            let (index, s) = m.index.unwrap_or((4, 1));
+            let sib = if index == 4 && m.index.is_none() {
+                0x20
+            } else {
+                scale(index, s)?
+            };
+            self.sib = Some(sib | (base & 7));
+        }
+        if width > 0 {
+            self.disp = Some((m.disp.clone(), width, false));
+        }
+        Ok(())
+    }
+
+    /// An immediate of `width` bytes for an operation of `size` bytes
+    fn imm(&mut self, value: &Value, width: usize, size: u8) -> Result<&mut Self> {
+        let signed = width < size as usize;
+        match value.as_constant() {
+            Some(n) if !fits(n, width, !signed) => {
+                return Err(format!("immediate {} out of range", n));
+            }
+            _ => {}
+        }
+        self.imm = Some((value.clone(), width, signed));
+        Ok(self)
+    }
+
+    fn finish(&self) -> Encoded {
+        let mut bytes = self.prefixes.clone();
+        if self.rex != 0 || self.force_rex {
+            bytes.push(0x40 | self.rex);
+        }
+        bytes.extend_from_slice(&self.opcode);
+        bytes.extend(self.modrm);
+        bytes.extend(self.sib);
+
+        let imm_width = self.imm.as_ref().map_or(0, |&(_, width, _)| width);
+        let mut fixups = Vec::new();
+        let mut field =
+            |bytes: &mut Vec<u8>, value: &Value, width: usize, kind: FixupKind| match value
+                .as_constant()
+            {
+                Some(n) => bytes.extend_from_slice(&n.to_le_bytes()[..width]),
+                None => {
+                    fixups.push(Fixup {
+                        offset: bytes.len(),
+                        kind,
+                        value: value.clone(),
+                    });
+                    bytes.extend(std::iter::repeat_n(0, width));
+                }
+            };
+        if let Some((value, width, rip)) = &self.disp {
+            if *rip {
+                // Relative to the end of the instruction
+                let mut value = value.clone();
+                value.addend -= (4 + imm_width) as i64;
+                field(&mut bytes, &value, 4, FixupKind::Pc32);
+            } else {
+                field(&mut bytes, value, *width, FixupKind::Abs(4, true));
+            }
+        }
+        if let Some((value, width, signed)) = &self.imm {
+            field(
+                &mut bytes,
+                value,
+                *width,
+                FixupKind::Abs(*width as u8, *signed),
+            );
+        }
+        if let Some(target) = &self.target {
+            let mut value = target.clone();
+            value.addend -= 4;
+            field(&mut bytes, &value, 4, FixupKind::Branch32);
+        }
+        Encoded { bytes, fixups }
+    }
+}
+
+/// The condition code of a jcc/setcc/cmovcc suffix
+fn condition(suffix: &str) -> Option<u8> {
+    Some(match suffix {
+        "o" => 0x0,
+        "no" => 0x1,
+        "b" | "c" | "nae" => 0x2,
+        "ae" | "nb" | "nc" => 0x3,
+        "e" | "z" => 0x4,
+        "ne" | "nz" => 0x5,
+        "be" | "na" => 0x6,
+        "a" | "nbe" => 0x7,
+        "s" => 0x8,
+        "ns" => 0x9,
+        "p" | "pe" => 0xA,
+        "np" | "po" => 0xB,
+        "l" | "nge" => 0xC,
+        "ge" | "nl" => 0xD,
+        "le" | "ng" => 0xE,
+        "g" | "nle" => 0xF,
+        _ => return None,
+    })
+}
+
+/// The size of a general-purpose operation: its first register's, or
+/// else its memory operand's
+fn operand_size(ops: &[Operand]) -> Result<u8> {
+    ops.iter()
+        .find_map(|op| match op {
+            Operand::Reg(Reg::Gp(_, size)) => Some(*size),
+            _ => None,
+        })
+        .or_else(|| {
+            ops.iter().find_map(|op| match op {
+                Operand::Mem(m) => m.size,
+                _ => None,
+            })
+        })
+        .ok_or_else(|| "operand size not specified".to_string())
+}
+
+/// The size of a memory operand or general-purpose register
+fn size_of(op: &Operand) -> Option<u8> {
+    match op {
+        Operand::Reg(Reg::Gp(_, size)) => Some(*size),
+        Operand::Mem(m) => m.size,
+        _ => None,
+    }
+}
+
+/// Opcode extension of the ALU operations (`add` = 0 ... `cmp` = 7)
+fn alu(mnemonic: &str) -> Option<u8> {
+    ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"]
+        .iter()
+        .position(|&m| m == mnemonic)
+        .map(|ext| ext as u8)
+}
+
+/// Opcode extension of the shifts and rotates
+fn shift(mnemonic: &str) -> Option<u8> {
+    Some(match mnemonic {
+        "rol" => 0,
+        "ror" => 1,
+        "shl" | "sal" => 4,
+        "shr" => 5,
+        "sar" => 7,
+        _ => return None,
+    })
+}
+
+/// Opcode extension of the one-operand group 3/5 operations
+fn unary(mnemonic: &str) -> Option<(u8, u8)> {
+    Some(match mnemonic {
+        "inc" => (0xFE, 0),
+        "dec" => (0xFE, 1),
+        "not" => (0xF6, 2),
+        "neg" => (0xF6, 3),
+        "mul" => (0xF6, 4),
+        "imul" => (0xF6, 5),
+        "div" => (0xF6, 6),
+        "idiv" => (0xF6, 7),
+        _ => return None,
+    })
+}
+
+/// SSE operations of the form `op xmm, xmm/mem`: mandatory prefix and
+/// opcode after 0F
+fn sse(mnemonic: &str) -> Option<(Option<u8>, u8)> {
+    Some(match mnemonic {
+        "addsd" => (Some(0xF2), 0x58),
+        "addss" => (Some(0xF3), 0x58),
+        "mulsd" => (Some(0xF2), 0x59),
+        "mulss" => (Some(0xF3), 0x59),
+        "subsd" => (Some(0xF2), 0x5C),
+        "subss" => (Some(0xF3), 0x5C),
+        "divsd" => (Some(0xF2), 0x5E),
+        "divss" => (Some(0xF3), 0x5E),
+        "sqrtsd" => (Some(0xF2), 0x51),
+        "sqrtss" => (Some(0xF3), 0x51),
+        "minsd" => (Some(0xF2), 0x5D),
+        "maxsd" => (Some(0xF2), 0x5F),
+        "cvtss2sd" => (Some(0xF3), 0x5A),
+        "cvtsd2ss" => (Some(0xF2), 0x5A),
+        "ucomisd" => (Some(0x66), 0x2E),
+        "ucomiss" => (None, 0x2E),
+        "comisd" => (Some(0x66), 0x2F),
+        "comiss" => (None, 0x2F),
+        "andpd" => (Some(0x66), 0x54),
+        "andps" => (None, 0x54),
+        "andnpd" => (Some(0x66), 0x55),
+        "orpd" => (Some(0x66), 0x56),
+        "xorpd" => (Some(0x66), 0x57),
+        "xorps" => (None, 0x57),
+        "pxor" => (Some(0x66), 0xEF),
+        _ => return None,
+    })
+}
+
+/// SSE moves: mandatory prefix, load opcode and store opcode
+fn sse_move(mnemonic: &str) -> Option<(Option<u8>, u8, u8)> {
+    Some(match mnemonic {
+        "movsd" => (Some(0xF2), 0x10, 0x11),
+        "movss" => (Some(0xF3), 0x10, 0x11),
+        "movups" => (None, 0x10, 0x11),
+        "movupd" => (Some(0x66), 0x10, 0x11),
+        "movaps" => (None, 0x28, 0x29),
+        "movapd" => (Some(0x66), 0x28, 0x29),
+        _ => return None,
+    })
+}
+
+/// Instructions without operands
+fn plain(mnemonic: &str) -> Option<&'static [u8]> {
+    Some(match mnemonic {
+        "ret" => &[0xC3],
+        "leave" => &[0xC9],
+        "nop" => &[0x90],
+        "hlt" => &[0xF4],
+        "int3" => &[0xCC],
+        "ud2" => &[0x0F, 0x0B],
+        "syscall" => &[0x0F, 0x05],
+        "cwde" => &[0x98],
+        "cdqe" => &[0x48, 0x98],
+        "cdq" => &[0x99],
+        "cqo" => &[0x48, 0x99],
+        "movsb" => &[0xA4],
+        "movsd" => &[0xA5],
+        "movsq" => &[0x48, 0xA5],
+        "stosb" => &[0xAA],
+        "stosd" => &[0xAB],
+        "stosq" => &[0x48, 0xAB],
+        "fsincos" => &[0xD9, 0xFB],
+        "fsin" => &[0xD9, 0xFE],
+        "fcos" => &[0xD9, 0xFF],
+        _ => return None,
+    })
+}
+
+/// Encode one instruction
+pub fn encode(mnemonic: &str, ops: &[Operand]) -> Result<Encoded> {
+    use Operand::{Imm, Mem as M, Reg as R};
+    let bad = || format!("invalid operands for {}", mnemonic);
+
+    if let (Some(bytes), []) = (plain(mnemonic), ops) {
+        return Ok(Builder::new(bytes).finish());
+    }
+
+    if let Some(ext) = alu(mnemonic) {
+        let [dst, src] = ops else { return Err(bad()) };
+        let size = operand_size(ops)?;
+        let byte = size == 1;
+        let mut b = match (dst, src) {
+            (R(_) | M(_), Imm(value)) => {
+                let (opcode, width) = if byte {
+                    (0x80, 1)
+                } else if fits_i8(value) {
+                    (0x83, 1)
+                } else {
+                    (0x81, if size == 2 { 2 } else { 4 })
+                };
+                let mut b = Builder::new(&[opcode]);
+                b.rm(ext, dst)?.imm(value, width, size)?;
+                b
+            }
+            (R(_) | M(_), R(r)) => {
+                let mut b = Builder::new(&[ext << 3 | if byte { 0x00 } else { 0x01 }]);
+                b.reg_rm(*r, dst)?;
+                b
+            }
+            (R(r), M(_)) => {
+                let mut b = Builder::new(&[ext << 3 | if byte { 0x02 } else { 0x03 }]);
+                b.reg_rm(*r, src)?;
+                b
+            }
+            _ => return Err(bad()),
+        };
+        return Ok(b.size(size).finish());
+    }
+
+    if let Some(ext) = shift(mnemonic) {
+        let [dst, count] = ops else { return Err(bad()) };
+        let size = operand_size(&ops[..1])?;
+        let byte = size == 1;
+        let mut b = match count {
+            Imm(value) => {
+                let mut b = Builder::new(&[if byte { 0xC0 } else { 0xC1 }]);
+                b.rm(ext, dst)?.imm(value, 1, 1)?;
+                b
+            }
+            R(Reg::Gp(1, 1)) => {
+                let mut b = Builder::new(&[if byte { 0xD2 } else { 0xD3 }]);
+                b.rm(ext, dst)?;
+                b
+            }
+            _ => return Err(bad()),
+        };
+        return Ok(b.size(size).finish());
+    }
+
+    if let (Some((opcode, ext)), [operand]) = (unary(mnemonic), ops) {
+        let size = operand_size(ops)?;
+        let opcode = if size == 1 { opcode } else { opcode + 1 };
+        return Ok(Builder::new(&[opcode])
+            .rm(ext, operand)?
+            .size(size)
+            .finish());
+    }
+
+    if let Some(cc) = mnemonic.strip_prefix('j').and_then(condition) {
+        let [Imm(target)] = ops else {
+            return Err(bad());
+        };
+        let mut b = Builder::new(&[0x0F, 0x80 | cc]);
+        b.target = Some(target.clone());
+        return Ok(b.finish());
+    }
+
+    if let Some(cc) = mnemonic.strip_prefix("set").and_then(condition) {
+        let [dst] = ops else { return Err(bad()) };
+        if size_of(dst) != Some(1) {
+            return Err(bad());
+        }
+        return Ok(Builder::new(&[0x0F, 0x90 | cc]).rm(0, dst)?.finish());
+    }
+
+    if let Some(cc) = mnemonic.strip_prefix("cmov").and_then(condition) {
+        let [R(dst @ Reg::Gp(_, size)), src] = ops else {
+            return Err(bad());
+        };
+        return Ok(Builder::new(&[0x0F, 0x40 | cc])
+            .reg_rm(*dst, src)?
+            .size(*size)
+            .finish());
+    }
+
+    if let Some((prefix, opcode)) = sse(mnemonic) {
+        let [R(dst @ Reg::Xmm(_)), src @ (R(Reg::Xmm(_)) | M(_))] = ops else {
+            return Err(bad());
+        };
+        let mut b = Builder::new(&[0x0F, opcode]);
+        if let Some(prefix) = prefix {
+            b.prefix(prefix);
+        }
+        return Ok(b.reg_rm(*dst, src)?.finish());
+    }
+
+    if let (Some((prefix, load, store)), false) = (sse_move(mnemonic), ops.is_empty()) {
+        let (opcode, reg, rm) = match ops {
+            [R(dst @ Reg::Xmm(_)), src @ (R(Reg::Xmm(_)) | M(_))] => (load, dst, src),
+            [dst @ M(_), R(src @ Reg::Xmm(_))] => (store, src, dst),
+            _ => return Err(bad()),
+        };
+        let mut b = Builder::new(&[0x0F, opcode]);
+        if let Some(prefix) = prefix {
+            b.prefix(prefix);
+        }
+        return Ok(b.reg_rm(*reg, rm)?.finish());
+    }
+
+    let b = match (mnemonic, ops) {
+        ("mov", [dst @ (R(Reg::Gp(..)) | M(_)), R(src @ Reg::Gp(..))]) => {
+            let size = operand_size(ops)?;
+            let mut b = Builder::new(&[if size == 1 { 0x88 } else { 0x89 }]);
+            b.reg_rm(*src, dst)?.size(size);
+            b
+        }
+        ("mov", [R(dst @ Reg::Gp(_, size)), src @ M(_)]) => {
+            let mut b = Builder::new(&[if *size == 1 { 0x8A } else { 0x8B }]);
+            b.reg_rm(*dst, src)?.size(*size);
+            b
+        }
+        ("mov", [R(dst @ Reg::Gp(num, 8)), Imm(value)])
+            if value.as_constant().is_some_and(|n| !fits(n, 4, false)) =>
+        {
+            // movabs
+            let mut b = Builder::new(&[0xB8 | (num & 7)]);
+            if num & 8 != 0 {
+                b.rex |= REX_B;
+            }
+            b.reg_num(*dst);
+            b.size(8).imm = Some((value.clone(), 8, false));
+            b
+        }
+        ("mov", [dst @ (R(Reg::Gp(..)) | M(_)), Imm(value)]) => {
+            let size = operand_size(ops)?;
+            let (opcode, width) = match size {
+                1 => (0xC6, 1),
+                2 => (0xC7, 2),
+                _ => (0xC7, 4),
+            };
+            let mut b = Builder::new(&[opcode]);
+            b.rm(0, dst)?.imm(value, width, size)?.size(size);
+            b
+        }
+        ("lea", [R(dst @ Reg::Gp(_, size)), src @ M(_)]) => {
+            let mut b = Builder::new(&[0x8D]);
+            b.reg_rm(*dst, src)?.size(*size);
+            b
+        }
+        ("movzx" | "movsx", [R(dst @ Reg::Gp(_, size)), src]) => {
+            let opcode = match (mnemonic, size_of(src)) {
+                ("movzx", Some(1)) => 0xB6,
+                ("movzx", Some(2)) => 0xB7,
+                ("movsx", Some(1)) => 0xBE,
+                ("movsx", Some(2)) => 0xBF,
+                _ => return Err(bad()),
+            };
+            let mut b = Builder::new(&[0x0F, opcode]);
+            b.reg_rm(*dst, src)?.size(*size);
+            b
+        }
+        ("movsxd", [R(dst @ Reg::Gp(_, 8)), src]) if size_of(src).is_none_or(|s| s == 4) => {
+            let mut b = Builder::new(&[0x63]);
+            b.reg_rm(*dst, src)?.size(8);
+            b
+        }
+        ("test", [dst @ (R(Reg::Gp(..)) | M(_)), R(src @ Reg::Gp(..))]) => {
+            let size = operand_size(ops)?;
+            let mut b = Builder::new(&[if size == 1 { 0x84 } else { 0x85 }]);
+            b.reg_rm(*src, dst)?.size(size);
+            b
+        }
+        ("test", [dst @ (R(Reg::Gp(..)) | M(_)), Imm(value)]) => {
+            let size = operand_size(ops)?;
+            let (opcode, width) = match size {
+                1 => (0xF6, 1),
+                2 => (0xF7, 2),
+                _ => (0xF7, 4),
+            };
+            let mut b = Builder::new(&[opcode]);
+            b.rm(0, dst)?.imm(value, width, size)?.size(size);
+            b
+        }
+        ("xchg", [R(reg @ Reg::Gp(..)), other @ (R(Reg::Gp(..)) | M(_))])
+        | ("xchg", [other @ M(_), R(reg @ Reg::Gp(..))]) => {
+            let size = operand_size(ops)?;
+            let mut b = Builder::new(&[if size == 1 { 0x86 } else { 0x87 }]);
+            b.reg_rm(*reg, other)?.size(size);
+            b
+        }
+        ("imul", [R(dst @ Reg::Gp(_, size)), src @ (R(Reg::Gp(..)) | M(_))]) => {
+            let mut b = Builder::new(&[0x0F, 0xAF]);
+            b.reg_rm(*dst, src)?.size(*size);
+            b
+        }
+        ("imul", [R(dst @ Reg::Gp(..)), Imm(value)]) => {
+            return encode("imul", &[R(*dst), R(*dst), Imm(value.clone())]);
+        }
+        (
+            "imul",
+            [
+                R(dst @ Reg::Gp(_, size)),
+                src @ (R(Reg::Gp(..)) | M(_)),
+                Imm(value),
+            ],
+        ) => {
+            let (opcode, width) = if fits_i8(value) {
+                (0x6B, 1)
+            } else {
+                (0x69, if *size == 2 { 2 } else { 4 })
+            };
+            let mut b = Builder::new(&[opcode]);
+            b.reg_rm(*dst, src)?.imm(value, width, *size)?.size(*size);
+            b
+        }
+        ("push", [R(reg @ Reg::Gp(num, 8))]) | ("pop", [R(reg @ Reg::Gp(num, 8))]) => {
+            let base = if mnemonic == "push" { 0x50 } else { 0x58 };
+            let mut b = Builder::new(&[base | (num & 7)]);
+            if num & 8 != 0 {
+                b.rex |= REX_B;
+            }
+            b.reg_num(*reg);
+            b
+        }
+        ("push", [Imm(value)]) => {
+            let mut b = Builder::new(&[0x68]);
+            b.imm(value, 4, 8)?;
+            b
+        }
+        ("push", [src @ M(_)]) => {
+            let mut b = Builder::new(&[0xFF]);
+            b.rm(6, src)?;
+            b
+        }
+        ("pop", [dst @ M(_)]) => {
+            let mut b = Builder::new(&[0x8F]);
+            b.rm(0, dst)?;
+            b
+        }
+        ("call" | "jmp", [Imm(target)]) => {
+            let mut b = Builder::new(&[if mnemonic == "call" { 0xE8 } else { 0xE9 }]);
+            b.target = Some(target.clone());
+            b
+        }
+        ("call" | "jmp", [target @ (R(Reg::Gp(_, 8)) | M(_))]) => {
+            let mut b = Builder::new(&[0xFF]);
+            b.rm(if mnemonic == "call" { 2 } else { 4 }, target)?;
+            b
+        }
+        ("movq", [R(dst @ Reg::Xmm(_)), src @ R(Reg::Gp(_, 8))]) => {
+            let mut b = Builder::new(&[0x0F, 0x6E]);
+            b.prefix(0x66).reg_rm(*dst, src)?.size(8);
+            b
+        }
+        ("movq", [dst @ R(Reg::Gp(_, 8)), R(src @ Reg::Xmm(_))]) => {
+            let mut b = Builder::new(&[0x0F, 0x7E]);
+            b.prefix(0x66).reg_rm(*src, dst)?.size(8);
+            b
+        }
+        ("movq", [R(dst @ Reg::Xmm(_)), src @ (R(Reg::Xmm(_)) | M(_))]) => {
+            let mut b = Builder::new(&[0x0F, 0x7E]);
+            b.prefix(0xF3).reg_rm(*dst, src)?;
+            b
+        }
+        ("movq", [dst @ M(_), R(src @ Reg::Xmm(_))]) => {
+            let mut b = Builder::new(&[0x0F, 0xD6]);
+            b.prefix(0x66).reg_rm(*src, dst)?;
+            b
+        }
+        ("movd", [R(dst @ Reg::Xmm(_)), src]) if size_of(src) == Some(4) => {
+            let mut b = Builder::new(&[0x0F, 0x6E]);
+            b.prefix(0x66).reg_rm(*dst, src)?;
+            b
+        }
+        ("movd", [dst, R(src @ Reg::Xmm(_))]) if size_of(dst) == Some(4) => {
+            let mut b = Builder::new(&[0x0F, 0x7E]);
+            b.prefix(0x66).reg_rm(*src, dst)?;
+            b
+        }
+        ("cvtsi2sd" | "cvtsi2ss", [R(dst @ Reg::Xmm(_)), src]) => {
+            let size = size_of(src).ok_or_else(bad)?;
+            let mut b = Builder::new(&[0x0F, 0x2A]);
+            b.prefix(if mnemonic == "cvtsi2sd" { 0xF2 } else { 0xF3 })
+                .reg_rm(*dst, src)?
+                .size(size);
+            b
+        }
+        (
+            "cvtsd2si" | "cvttsd2si" | "cvtss2si" | "cvttss2si",
+            [R(dst @ Reg::Gp(_, size)), src @ (R(Reg::Xmm(_)) | M(_))],
+        ) => {
+            let prefix = if mnemonic.ends_with("sd2si") {
+                0xF2
+            } else {
+                0xF3
+            };
+            let opcode = if mnemonic.starts_with("cvtt") {
+                0x2C
+            } else {
+                0x2D
+            };
+            let mut b = Builder::new(&[0x0F, opcode]);
+            b.prefix(prefix).reg_rm(*dst, src)?.size(*size);
+            b
+        }
+        ("roundsd" | "roundss", [R(dst @ Reg::Xmm(_)), src, Imm(mode)]) => {
+            let opcode = if mnemonic == "roundsd" { 0x0B } else { 0x0A };
+            let mut b = Builder::new(&[0x0F, 0x3A, opcode]);
+            b.prefix(0x66).reg_rm(*dst, src)?.imm(mode, 1, 1)?;
+            b
+        }
+        ("fld" | "fst" | "fstp" | "fild" | "fistp", [mem @ M(m)]) => {
+            let (opcode, ext) = match (mnemonic, m.size) {
+                ("fld", Some(8)) => (0xDD, 0),
+                ("fst", Some(8)) => (0xDD, 2),
+                ("fstp", Some(8)) => (0xDD, 3),
+                ("fld", Some(4)) => (0xD9, 0),
+                ("fst", Some(4)) => (0xD9, 2),
+                ("fstp", Some(4)) => (0xD9, 3),
+                ("fild", Some(8)) => (0xDF, 5),
+                ("fild", Some(4)) => (0xDB, 0),
+                ("fistp", Some(8)) => (0xDF, 7),
+                ("fistp", Some(4)) => (0xDB, 3),
+                _ => return Err(bad()),
+            };
+            let mut b = Builder::new(&[opcode]);
+            b.rm(ext, mem)?;
+            b
+        }
+        _ => {
+            return Err(format!(
+                "unknown instruction or invalid operands: {}",
+                mnemonic
+            ));
+        }
+    };
+    Ok(b.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reg(name: &str) -> Operand {
+        Operand::Reg(Reg::parse(name).unwrap())
+    }
+
+    fn imm(n: i64) -> Operand {
+        Operand::Imm(Value::constant(n))
+    }
+
+    fn mem(size: Option<u8>, base: &str, index: Option<(&str, u8)>, disp: i64) -> Operand {
+        Operand::Mem(Mem {
+            size,
+            base: Some(Reg::parse(base).unwrap().num()),
+            index: index.map(|(r, s)| (Reg::parse(r).unwrap().num(), s)),
+            rip: false,
+            disp: Value::constant(disp),
+        })
+    }
+
+    fn bytes(mnemonic: &str, ops: &[Operand]) -> Vec<u8> {
+        let encoded = encode(mnemonic, ops).unwrap();
+        assert!(encoded.fixups.is_empty());
+        encoded.bytes
+    }
+
+    // ===================
+    // Encoding Tests
+    // ===================
+
+    #[test]
+    fn test_general_purpose() {
+        assert_eq!(bytes("mov", &[reg("rax"), reg("rcx")]), [0x48, 0x89, 0xC8]);
+        assert_eq!(
+            bytes("mov", &[reg("r9d"), imm(5)]),
+            [0x41, 0xC7, 0xC1, 5, 0, 0, 0]
+        );
+        assert_eq!(
+            bytes("mov", &[reg("rax"), imm(0x3FF0_0000_0000_0000)]),
+            [0x48, 0xB8, 0, 0, 0, 0, 0, 0, 0xF0, 0x3F]
+        );
+        assert_eq!(
+            bytes("mov", &[reg("rdi"), mem(None, "rax", Some(("rbx", 8)), 0)]),
+            [0x48, 0x8B, 0x3C, 0xD8]
+        );
+        // rbp as a base needs a displacement, rsp and r12 a SIB byte
+        assert_eq!(
+            bytes("mov", &[reg("rax"), mem(Some(8), "rbp", None, -8)]),
+            [0x48, 0x8B, 0x45, 0xF8]
+        );
+        assert_eq!(
+            bytes("mov", &[reg("rax"), mem(Some(8), "r12", None, 0)]),
+            [0x49, 0x8B, 0x04, 0x24]
+        );
+        assert_eq!(bytes("add", &[reg("rsp"), imm(8)]), [0x48, 0x83, 0xC4, 8]);
+        assert_eq!(
+            bytes("sub", &[reg("rsp"), imm(4096)]),
+            [0x48, 0x81, 0xEC, 0, 0x10, 0, 0]
+        );
+        assert_eq!(
+            bytes("cmp", &[mem(Some(1), "rdx", None, 16), imm(255)]),
+            [0x80, 0x7A, 0x10, 0xFF]
+        );
+        assert_eq!(
+            bytes("movzx", &[reg("eax"), reg("sil")]),
+            [0x40, 0x0F, 0xB6, 0xC6]
+        );
+        assert_eq!(bytes("sete", &[reg("al")]), [0x0F, 0x94, 0xC0]);
+        assert_eq!(
+            bytes("shl", &[reg("r11w"), reg("cl")]),
+            [0x66, 0x41, 0xD3, 0xE3]
+        );
+        assert_eq!(bytes("push", &[reg("r12")]), [0x41, 0x54]);
+        assert_eq!(bytes("idiv", &[reg("rcx")]), [0x48, 0xF7, 0xF9]);
+        assert_eq!(
+            bytes("imul", &[reg("rax"), imm(10)]),
+            [0x48, 0x6B, 0xC0, 10]
+        );
+        assert_eq!(bytes("cqo", &[]), [0x48, 0x99]);
+    }
+
+    #[test]
+    fn test_sse() {
+        assert_eq!(
+            bytes("movsd", &[reg("xmm0"), mem(Some(8), "rbp", None, -16)]),
+            [0xF2, 0x0F, 0x10, 0x45, 0xF0]
+        );
+        assert_eq!(
+            bytes("movsd", &[mem(Some(8), "rbp", None, -16), reg("xmm9")]),
+            [0xF2, 0x44, 0x0F, 0x11, 0x4D, 0xF0]
+        );
+        assert_eq!(
+            bytes("cvtsi2sd", &[reg("xmm0"), reg("rax")]),
+            [0xF2, 0x48, 0x0F, 0x2A, 0xC0]
+        );
+        assert_eq!(
+            bytes("cvttsd2si", &[reg("eax"), reg("xmm1")]),
+            [0xF2, 0x0F, 0x2C, 0xC1]
+        );
+        assert_eq!(
+            bytes("movq", &[reg("xmm0"), reg("rax")]),
+            [0x66, 0x48, 0x0F, 0x6E, 0xC0]
+        );
+        assert_eq!(
+            bytes("roundsd", &[reg("xmm0"), reg("xmm0"), imm(3)]),
+            [0x66, 0x0F, 0x3A, 0x0B, 0xC0, 3]
+        );
+    }
+
+    #[test]
+    fn test_fixups() {
+        let label = Value {
+            symbol: Some("_x".to_string()),
+            ..Value::default()
+        };
+        // The displacement is relative to the end, past the immediate
+        let rip = Operand::Mem(Mem {
+            size: Some(8),
+            rip: true,
+            disp: label.clone(),
+            ..Mem::default()
+        });
+        let encoded = encode("mov", &[rip, imm(1)]).unwrap();
+        assert_eq!(encoded.bytes.len(), 11);
+        assert_eq!(
+            encoded.fixups,
+            [Fixup {
+                offset: 3,
+                kind: FixupKind::Pc32,
+                value: Value {
+                    addend: -8,
+                    ..label.clone()
+                },
+            }]
+        );
+
+        let encoded = encode("call", &[Operand::Imm(label.clone())]).unwrap();
+        assert_eq!(encoded.bytes, [0xE8, 0, 0, 0, 0]);
+        assert_eq!(encoded.fixups[0].kind, FixupKind::Branch32);
+        assert_eq!(encoded.fixups[0].value.addend, -4);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(encode("frobnicate", &[]).is_err());
+        assert!(encode("mov", &[reg("rax")]).is_err());
+        assert!(encode("add", &[reg("rax"), imm(1 << 40)]).is_err());
+        assert!(encode("mov", &[mem(None, "rax", None, 0), imm(1)]).is_err());
+    }
+}
@@ -0,0 +1,837 @@
+//! Constant folding and propagation - an AST pass run at -O1 and above
+//!
+//! An operator whose operands are constants is evaluated at compile time,
+//! with the types and arithmetic the generated code would use, so
+//! `X = 2 * 3.14159 * R` does one multiplication at run time. Anything the
+//! generated code would compute differently from plain arithmetic - an
+//! INTEGER or LONG overflow, a division by zero, a NaN - is left for run
+//! time, where it behaves (or fails) as usual. So are `^`, which the C
+//! library computes, and string comparisons, which the runtime does.
+//!
+//! A main-program variable that is assigned a constant once, and written
+//! nowhere else, becomes that constant in every statement after the
+//! assignment. The assignment must come before the first GOTO, GOSUB or
+//! event trap, so those statements can only run once it has. Variables a
+//! procedure can see (SHARED) or that are passed by name (VARPTR, UBOUND,
+//! procedure arguments, ASM blocks) are never propagated.
+//!
+//! CONST names are replaced by their values at every optimization level,
+//! before the program is checked (`substitute_consts`), so a CONST works
+//! anywhere a literal does, as in `DIM Buf(MAXROWS * 4)`.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Dialect;
+use crate::parser::{
+    BinaryOp, DataType, Expr, ExprKind, Literal, PrintItem, Program, Stmt, StmtKind, UnaryOp,
+};
+use crate::types;
+use crate::warnings;
+use std::collections::{HashMap, HashSet};
+
+/// A constant, held as the generated code would hold it
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Int(i32, DataType), // an INTEGER or LONG
+    Single(f32),
+    Double(f64),
+    Str(String),
+}
+
+/// An integer of type `ty`, if it is in range
+fn int(n: i64, ty: DataType) -> Option<Value> {
+    let fits = match ty {
+        DataType::Integer => i16::try_from(n).is_ok(),
+        _ => i32::try_from(n).is_ok(),
+    };
+    fits.then_some(Value::Int(n as i32, ty))
+}
+
+impl Value {
+    /// The value codegen loads for a literal
+    fn from_literal(lit: &Literal) -> Value {
+        match lit {
+            Literal::Integer(n) => match i32::try_from(*n) {
+                Ok(n) => Value::Int(n, DataType::Long),
+                Err(_) => Value::Double(*n as f64),
+            },
+            Literal::Float(x) => Value::Double(*x),
+            Literal::Typed(x, ty) => match ty {
+                DataType::Integer | DataType::Long => Value::Int(*x as i32, *ty),
+                DataType::Single => Value::Single(*x as f32),
+                _ => Value::Double(*x),
+            },
+            Literal::String(s) => Value::Str(s.clone()),
+        }
+    }
+
+    /// A literal with this value and type
+    fn into_literal(self) -> Literal {
+        match self {
+            Value::Int(n, DataType::Long) => Literal::Integer(n as i64),
+            Value::Int(n, ty) => Literal::Typed(n as f64, ty),
+            Value::Single(x) => Literal::Typed(x as f64, DataType::Single),
+            Value::Double(x) => Literal::Float(x),
+            Value::Str(s) => Literal::String(s),
+        }
+    }
+
+    /// Whether a number is nonzero (None for a NaN, which the generated
+    /// code doesn't treat consistently)
+    fn is_true(&self) -> Option<bool> {
+        match self {
+            Value::Int(n, _) => Some(*n != 0),
+            Value::Single(x) if !x.is_nan() => Some(*x != 0.0),
+            Value::Double(x) if !x.is_nan() => Some(*x != 0.0),
+            _ => None,
+        }
+    }
+
+    /// A float rounded to a whole number, half to even as cvtsd2si does
+    fn round_even(self) -> Value {
+        match self {
+            Value::Single(x) => Value::Single(x.round_ties_even()),
+            Value::Double(x) => Value::Double(x.round_ties_even()),
+            value => value,
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Value::Int(_, ty) => *ty,
+            Value::Single(_) => DataType::Single,
+            Value::Double(_) => DataType::Double,
+            Value::Str(_) => DataType::String,
+        }
+    }
+
+    /// Convert to another type as the generated code does: floats are
+    /// truncated toward zero when they become integers. None if the value
+    /// doesn't fit.
+    fn convert(self, to: DataType) -> Option<Value> {
+        match (self, to) {
+            (value, to) if value.data_type() == to => Some(value),
+            (Value::Int(n, _), DataType::Integer | DataType::Long) => int(n as i64, to),
+            (Value::Int(n, _), DataType::Single) => Some(Value::Single(n as f32)),
+            (Value::Int(n, _), DataType::Double) => Some(Value::Double(n as f64)),
+            (Value::Single(x), DataType::Double) => Some(Value::Double(x as f64)),
+            (Value::Double(x), DataType::Single) => Some(Value::Single(x as f32)),
+            (Value::Single(x), DataType::Integer | DataType::Long) => truncate(x as f64, to),
+            (Value::Double(x), DataType::Integer | DataType::Long) => truncate(x, to),
+            _ => None,
+        }
+    }
+}
+
+fn truncate(x: f64, to: DataType) -> Option<Value> {
+    let x = x.trunc();
+    if x.is_nan() || x < i32::MIN as f64 || x > i32::MAX as f64 {
+        return None;
+    }
+    int(x as i64, to)
+}
+
+/// Evaluate a unary operator on a constant
+fn unary(op: UnaryOp, value: Value) -> Option<Value> {
+    match (op, value) {
+        (_, Value::Str(_)) => None,
+        (UnaryOp::Neg, Value::Int(n, ty)) => int(-(n as i64), ty),
+        (UnaryOp::Neg, Value::Single(x)) => Some(Value::Single(-x)),
+        (UnaryOp::Neg, Value::Double(x)) => Some(Value::Double(-x)),
+        // NOT gives -1 for zero, else 0
+        (UnaryOp::Not, value) => Some(Value::Int(-(!value.is_true()? as i32), DataType::Long)),
+    }
+}
+
+/// Evaluate a binary operator on constants, in the work type codegen uses
+fn binary(op: BinaryOp, left: Value, right: Value) -> Option<Value> {
+    let (left_type, right_type) = (left.data_type(), right.data_type());
+    if left_type == DataType::String || right_type == DataType::String {
+        return match (op, left, right) {
+            (BinaryOp::Add, Value::Str(l), Value::Str(r)) => Some(Value::Str(l + &r)),
+            _ => None,
+        };
+    }
+
+    // ANDALSO and ORELSE give -1 or 0 by the truth of their operands
+    if matches!(op, BinaryOp::AndAlso | BinaryOp::OrElse) {
+        let (l, r) = (left.is_true()?, right.is_true()?);
+        let truth = if op == BinaryOp::AndAlso {
+            l && r
+        } else {
+            l || r
+        };
+        return Some(Value::Int(-(truth as i32), DataType::Long));
+    }
+
+    let result_type = types::promote(op, left_type, right_type);
+    let logical = matches!(op, BinaryOp::And | BinaryOp::Or | BinaryOp::Xor);
+    let work_type = if types::is_comparison(op) || logical {
+        types::widest(left_type, right_type)
+    } else {
+        result_type
+    };
+    // \ and MOD round their operands, as CLNG does, rather than truncate
+    let (left, right) = if matches!(op, BinaryOp::IntDiv | BinaryOp::Mod) {
+        (left.round_even(), right.round_even())
+    } else {
+        (left, right)
+    };
+    let (left, right) = (left.convert(work_type)?, right.convert(work_type)?);
+
+    if types::is_comparison(op) {
+        let ordering = match (left, right) {
+            (Value::Int(l, _), Value::Int(r, _)) => l.cmp(&r),
+            (Value::Single(l), Value::Single(r)) => l.partial_cmp(&r)?,
+            (Value::Double(l), Value::Double(r)) => l.partial_cmp(&r)?,
+            _ => return None,
+        };
+        let truth = match op {
+            BinaryOp::Eq => ordering.is_eq(),
+            BinaryOp::Ne => ordering.is_ne(),
+            BinaryOp::Lt => ordering.is_lt(),
+            BinaryOp::Gt => ordering.is_gt(),
+            BinaryOp::Le => ordering.is_le(),
+            _ => ordering.is_ge(),
+        };
+        return Some(Value::Int(-(truth as i32), DataType::Long));
+    }
+    if logical {
+        // Floats are truncated to integers first
+        let (Value::Int(l, _), Value::Int(r, _)) = (
+            left.convert(DataType::Long)?,
+            right.convert(DataType::Long)?,
+        ) else {
+            return None;
+        };
+        let n = match op {
+            BinaryOp::And => l & r,
+            BinaryOp::Or => l | r,
+            _ => l ^ r,
+        };
+        return Some(Value::Int(n, result_type));
+    }
+
+    match (op, left, right) {
+        (BinaryOp::Pow, _, _) => None,
+        (_, Value::Int(l, ty), Value::Int(r, _)) => {
+            let (l, r) = (l as i64, r as i64);
+            let n = match op {
+                BinaryOp::Add => l + r,
+                BinaryOp::Sub => l - r,
+                BinaryOp::Mul => l * r,
+                // idiv: truncating, and a remainder with the dividend's sign
+                BinaryOp::IntDiv if r != 0 => l / r,
+                BinaryOp::Mod if r != 0 => l % r,
+                _ => return None,
+            };
+            int(n, ty)
+        }
+        (_, Value::Single(l), Value::Single(r)) => match op {
+            BinaryOp::Add => Some(Value::Single(l + r)),
+            BinaryOp::Sub => Some(Value::Single(l - r)),
+            BinaryOp::Mul => Some(Value::Single(l * r)),
+            _ => None,
+        },
+        (_, Value::Double(l), Value::Double(r)) => match op {
+            BinaryOp::Add => Some(Value::Double(l + r)),
+            BinaryOp::Sub => Some(Value::Double(l - r)),
+            BinaryOp::Mul => Some(Value::Double(l * r)),
+            // A zero or NaN divisor stops with "Division by zero"
+            BinaryOp::Div if r != 0.0 && !r.is_nan() => Some(Value::Double(l / r)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Fold the constant parts of an expression, replacing variables in
+/// `consts` with their values
+fn fold_expr(expr: &mut Expr, consts: &HashMap<String, Value>) {
+    let folded = match &mut expr.kind {
+        ExprKind::Literal(_) => None,
+        ExprKind::Variable(name) => consts.get(name).cloned(),
+        ExprKind::ArrayAccess { indices: args, .. } | ExprKind::FnCall { args, .. } => {
+            args.iter_mut().for_each(|arg| fold_expr(arg, consts));
+            None
+        }
+        ExprKind::Unary { op, operand } => {
+            fold_expr(operand, consts);
+            match &operand.kind {
+                ExprKind::Literal(lit) => unary(*op, Value::from_literal(lit)),
+                _ => None,
+            }
+        }
+        ExprKind::Binary { op, left, right } => {
+            fold_expr(left, consts);
+            fold_expr(right, consts);
+            match (&left.kind, &right.kind) {
+                (ExprKind::Literal(l), ExprKind::Literal(r)) => {
+                    binary(*op, Value::from_literal(l), Value::from_literal(r))
+                }
+                _ => None,
+            }
+        }
+    };
+    if let Some(value) = folded {
+        expr.kind = ExprKind::Literal(value.into_literal());
+    }
+}
+
+/// An expression with its constant parts folded. DIM bounds are folded at
+/// every optimization level, so `DIM A(10 * 4)` gets static storage.
+pub(crate) fn folded(expr: &Expr) -> Expr {
+    let mut expr = expr.clone();
+    fold_expr(&mut expr, &HashMap::new());
+    expr
+}
+
+/// Every expression of a statement, not counting nested blocks
+fn stmt_exprs(kind: &mut StmtKind) -> Vec<&mut Expr> {
+    fn pair(p: &mut (Expr, Expr)) -> [&mut Expr; 2] {
+        [&mut p.0, &mut p.1]
+    }
+    match kind {
+        StmtKind::Let { indices, value, .. } => std::iter::once(value)
+            .chain(indices.iter_mut().flatten())
+            .collect(),
+        StmtKind::Print { items, .. } | StmtKind::PrintFile { items, .. } => items
+            .iter_mut()
+            .filter_map(|item| match item {
+                PrintItem::Expr(expr) => Some(expr),
+                _ => None,
+            })
+            .collect(),
+        StmtKind::PrintUsing { format, items, .. } => {
+            std::iter::once(format).chain(items.iter_mut()).collect()
+        }
+        StmtKind::Write { items, .. } => items.iter_mut().collect(),
+        StmtKind::Input { vars, .. } | StmtKind::Read(vars) | StmtKind::InputFile { vars, .. } => {
+            vars.iter_mut().collect()
+        }
+        StmtKind::LineInput { var, .. } => vec![var],
+        StmtKind::If { condition, .. } | StmtKind::While { condition, .. } => vec![condition],
+        StmtKind::For {
+            start, end, step, ..
+        } => [start, end].into_iter().chain(step).collect(),
+        StmtKind::DoLoop { condition, .. } => condition.iter_mut().collect(),
+        StmtKind::OnGoto { expr, .. } => vec![expr],
+        StmtKind::Dim { arrays, .. } => arrays
+            .iter_mut()
+            .flat_map(|a| a.dimensions.iter_mut())
+            .collect(),
+        StmtKind::Call { args, .. } => args.iter_mut().collect(),
+        StmtKind::Width { width, .. } => vec![width],
+        StmtKind::SelectCase { expr, cases } => std::iter::once(expr)
+            .chain(cases.iter_mut().filter_map(|(value, _)| value.as_mut()))
+            .collect(),
+        StmtKind::Open { filename, .. } => vec![filename],
+        StmtKind::Screen { mode } => vec![mode],
+        StmtKind::Palette { entry } => entry.iter_mut().flat_map(pair).collect(),
+        StmtKind::Pset { x, y, color, .. } => [x, y].into_iter().chain(color).collect(),
+        StmtKind::GraphicsLine {
+            from, to, color, ..
+        } => from
+            .iter_mut()
+            .flat_map(pair)
+            .chain(pair(to))
+            .chain(color)
+            .collect(),
+        StmtKind::Circle {
+            x,
+            y,
+            radius,
+            color,
+        } => [x, y, radius].into_iter().chain(color).collect(),
+        StmtKind::Paint {
+            x,
+            y,
+            color,
+            border,
+        } => [x, y].into_iter().chain(color).chain(border).collect(),
+        StmtKind::Draw { commands } => vec![commands],
+        StmtKind::GetImage {
+            from, to, indices, ..
+        } => pair(from)
+            .into_iter()
+            .chain(pair(to))
+            .chain(indices.iter_mut())
+            .collect(),
+        StmtKind::PutImage { at, indices, .. } => {
+            pair(at).into_iter().chain(indices.iter_mut()).collect()
+        }
+        StmtKind::DefSeg { segment } => segment.iter_mut().collect(),
+        StmtKind::Poke { address, value } => vec![address, value],
+        StmtKind::Bsave {
+            filename,
+            offset,
+            length,
+        } => vec![filename, offset, length],
+        StmtKind::Bload { filename, offset } => std::iter::once(filename).chain(offset).collect(),
+        StmtKind::Seek { position, .. } => vec![position],
+        StmtKind::Lock { first, last, .. } => first.iter_mut().chain(last).collect(),
+        StmtKind::DictSet { dict, key, value } => [dict, key].into_iter().chain(value).collect(),
+        StmtKind::OnKey { key, .. } | StmtKind::KeyTrap { key, .. } => vec![key],
+        StmtKind::OnTimer { interval, .. } => vec![interval],
+        _ => vec![],
+    }
+}
+
+/// Fold a statement and the blocks nested in it
+fn fold_stmt(stmt: &mut Stmt, consts: &HashMap<String, Value>) {
+    for expr in stmt_exprs(&mut stmt.kind) {
+        fold_expr(expr, consts);
+    }
+    for body in stmt.kind.bodies_mut() {
+        for stmt in body {
+            fold_stmt(stmt, consts);
+        }
+    }
+}
+
+/// Whether a statement, or one nested in it, can transfer control to a
+/// line number or start an event trap
+fn jumps(stmt: &Stmt) -> bool {
+    matches!(
+        stmt.kind,
+        StmtKind::Goto(_)
+            | StmtKind::Gosub(_)
+            | StmtKind::Return
+            | StmtKind::OnGoto { .. }
+            | StmtKind::OnKey { .. }
+            | StmtKind::OnTimer { .. }
+            | StmtKind::OnBreak { .. }
+            | StmtKind::OnMouse { .. }
+    ) || stmt.kind.bodies().into_iter().flatten().any(jumps)
+}
+
+/// Names that can't be propagated: those written more than once or in a
+/// way other than LET, visible to procedures, or passed by name
+fn pinned_names(program: &Program) -> HashSet<String> {
+    let procs: HashSet<&str> = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Sub { name, .. } | StmtKind::Function { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut lets = HashMap::new();
+    let mut pinned = HashSet::new();
+    collect_pinned(&program.statements, &procs, &mut lets, &mut pinned);
+    pinned.extend(
+        lets.into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(name, _)| name),
+    );
+    pinned
+}
+
+fn collect_pinned(
+    stmts: &[Stmt],
+    procs: &HashSet<&str>,
+    lets: &mut HashMap<String, usize>,
+    pinned: &mut HashSet<String>,
+) {
+    for stmt in stmts {
+        let mut pin = |name: &str| {
+            pinned.insert(name.to_string());
+        };
+        match &stmt.kind {
+            StmtKind::Let {
+                name,
+                indices: None,
+                ..
+            } => *lets.entry(name.clone()).or_default() += 1,
+            StmtKind::Input { vars, .. }
+            | StmtKind::Read(vars)
+            | StmtKind::InputFile { vars, .. } => vars.iter().filter_map(var_name).for_each(pin),
+            StmtKind::LineInput { var, .. } => var_name(var).into_iter().for_each(pin),
+            StmtKind::For { var, .. } => pin(var),
+            StmtKind::Dim { arrays, .. } => arrays.iter().for_each(|a| pin(&a.name)),
+            StmtKind::Shared(params) => params.iter().for_each(|p| pin(&p.name)),
+            StmtKind::Sub { params, .. } => params.iter().for_each(|p| pin(&p.name)),
+            StmtKind::Function { name, params, .. } => {
+                pin(name);
+                params.iter().for_each(|p| pin(&p.name));
+            }
+            StmtKind::Call { args, .. } => args.iter().filter_map(var_name).for_each(pin),
+            StmtKind::Asm { vars, .. } => vars.iter().filter_map(var_name).for_each(pin),
+            _ => {}
+        }
+        for expr in warnings::stmt_reads(&stmt.kind) {
+            pin_passed_by_name(expr, procs, pinned);
+        }
+        for body in stmt.kind.bodies() {
+            collect_pinned(body, procs, lets, pinned);
+        }
+    }
+}
+
+/// Pin variables passed to a procedure or to a built-in that takes a name
+fn pin_passed_by_name(expr: &Expr, procs: &HashSet<&str>, pinned: &mut HashSet<String>) {
+    match &expr.kind {
+        ExprKind::FnCall { name, args } => {
+            let by_name = procs.contains(name.as_str())
+                || matches!(name.as_str(), "LBOUND" | "UBOUND" | "VARPTR" | "VARSEG");
+            for arg in args {
+                match var_name(arg) {
+                    Some(var) if by_name => {
+                        pinned.insert(var.to_string());
+                    }
+                    _ => pin_passed_by_name(arg, procs, pinned),
+                }
+            }
+        }
+        ExprKind::ArrayAccess { indices: args, .. } => {
+            args.iter()
+                .for_each(|arg| pin_passed_by_name(arg, procs, pinned));
+        }
+        ExprKind::Unary { operand, .. } => pin_passed_by_name(operand, procs, pinned),
+        ExprKind::Binary { left, right, .. } => {
+            pin_passed_by_name(left, procs, pinned);
+            pin_passed_by_name(right, procs, pinned);
+        }
+        ExprKind::Literal(_) | ExprKind::Variable(_) => {}
+    }
+}
+
+fn var_name(expr: &Expr) -> Option<&str> {
+    match &expr.kind {
+        ExprKind::Variable(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Replace each CONST name with its value, folded, in the statements after
+/// its CONST. A CONST in the main program is seen in every procedure too;
+/// one in a procedure only there. Each value must be a constant, and a
+/// CONST name can't be defined again or assigned.
+pub fn substitute_consts(program: &mut Program) -> Result<(), Diagnostic> {
+    let is_proc =
+        |stmt: &Stmt| matches!(stmt.kind, StmtKind::Sub { .. } | StmtKind::Function { .. });
+    let mut consts = HashMap::new();
+    for stmt in program.statements.iter_mut().filter(|stmt| !is_proc(stmt)) {
+        const_stmt(stmt, &mut consts)?;
+    }
+    for stmt in program.statements.iter_mut().filter(|stmt| is_proc(stmt)) {
+        const_stmt(stmt, &mut consts.clone())?;
+    }
+    Ok(())
+}
+
+/// Substitute CONST names in a statement and the blocks nested in it,
+/// adding those it defines to `consts`
+fn const_stmt(stmt: &mut Stmt, consts: &mut HashMap<String, Expr>) -> Result<(), Diagnostic> {
+    let span = stmt.span;
+    let fail = |message: String| Err(Diagnostic::at(span, message).with_code("semantic-error"));
+    if let Some(name) = written_names(&stmt.kind)
+        .into_iter()
+        .find(|name| consts.contains_key(*name))
+    {
+        return fail(format!("Duplicate definition: {} is a CONST", name));
+    }
+    if let StmtKind::Const(defs) = &mut stmt.kind {
+        for (name, value) in defs {
+            substitute(value, consts);
+            fold_expr(value, &HashMap::new());
+            if !is_constant(value) {
+                return fail(format!("CONST {} needs a constant value", name));
+            }
+            if consts.contains_key(name) {
+                return fail(format!("CONST {} is already defined", name));
+            }
+            // A type suffix converts a number, rounding it to an integer
+            // as CLNG does
+            let to = DataType::from_suffix(name);
+            if let ExprKind::Literal(lit) = &value.kind {
+                let lit_value = Value::from_literal(lit);
+                let numbers = lit_value.data_type() != DataType::String && to != DataType::String;
+                if numbers && name.ends_with(['%', '&', '!', '#']) {
+                    let lit_value = if to.is_integer() {
+                        lit_value.round_even()
+                    } else {
+                        lit_value
+                    };
+                    match lit_value.convert(to) {
+                        Some(converted) => value.kind = ExprKind::Literal(converted.into_literal()),
+                        None => return fail(format!("Overflow in CONST {}", name)),
+                    }
+                }
+            }
+            consts.insert(name.clone(), value.clone());
+        }
+        return Ok(());
+    }
+    for expr in stmt_exprs(&mut stmt.kind) {
+        substitute(expr, consts);
+    }
+    for body in stmt.kind.bodies_mut() {
+        for stmt in body {
+            const_stmt(stmt, consts)?;
+        }
+    }
+    Ok(())
+}
+
+/// The variables, arrays and parameters a statement assigns or declares
+fn written_names(kind: &StmtKind) -> Vec<&str> {
+    fn target(expr: &Expr) -> Option<&str> {
+        match &expr.kind {
+            ExprKind::Variable(name)
+            | ExprKind::ArrayAccess { name, .. }
+            | ExprKind::FnCall { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+    match kind {
+        StmtKind::Let { name, .. } | StmtKind::For { var: name, .. } => vec![name],
+        StmtKind::Input { vars, .. } | StmtKind::Read(vars) | StmtKind::InputFile { vars, .. } => {
+            vars.iter().filter_map(target).collect()
+        }
+        StmtKind::LineInput { var, .. } => target(var).into_iter().collect(),
+        StmtKind::Dim { arrays, .. } => arrays.iter().map(|a| a.name.as_str()).collect(),
+        StmtKind::Shared(params) => params.iter().map(|p| p.name.as_str()).collect(),
+        StmtKind::Sub { name, params, .. } | StmtKind::Function { name, params, .. } => {
+            std::iter::once(name.as_str())
+                .chain(params.iter().map(|p| p.name.as_str()))
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+/// Replace the CONST names in an expression with their values
+fn substitute(expr: &mut Expr, consts: &HashMap<String, Expr>) {
+    match &mut expr.kind {
+        ExprKind::Literal(_) => {}
+        ExprKind::Variable(name) => {
+            // The value keeps the place of the name it replaces
+            if let Some(value) = consts.get(name) {
+                expr.kind = value.kind.clone();
+            }
+        }
+        ExprKind::ArrayAccess { indices: args, .. } | ExprKind::FnCall { args, .. } => {
+            args.iter_mut().for_each(|arg| substitute(arg, consts));
+        }
+        ExprKind::Unary { operand, .. } => substitute(operand, consts),
+        ExprKind::Binary { left, right, .. } => {
+            substitute(left, consts);
+            substitute(right, consts);
+        }
+    }
+}
+
+/// Whether an expression is made of literals and operators alone
+fn is_constant(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Literal(_) => true,
+        ExprKind::Unary { operand, .. } => is_constant(operand),
+        ExprKind::Binary { left, right, .. } => is_constant(left) && is_constant(right),
+        _ => false,
+    }
+}
+
+/// Fold constants throughout a checked program, and propagate the
+/// main-program variables that are assigned a constant once (converted to
+/// their type in `dialect`)
+pub fn fold(program: &mut Program, dialect: Dialect) {
+    let pinned = pinned_names(program);
+    let mut consts = HashMap::new();
+    let mut straight_line = true;
+    for stmt in &mut program.statements {
+        if let StmtKind::Sub { .. } | StmtKind::Function { .. } = stmt.kind {
+            fold_stmt(stmt, &HashMap::new());
+            continue;
+        }
+        fold_stmt(stmt, &consts);
+        straight_line &= !jumps(stmt);
+        if let StmtKind::Let {
+            name,
+            indices: None,
+            value:
+                Expr {
+                    kind: ExprKind::Literal(lit),
+                    ..
+                },
+        } = &stmt.kind
+        {
+            if straight_line && !pinned.contains(name) {
+                let value = Value::from_literal(lit).convert(DataType::of_name(name, dialect));
+                if let Some(value) = value {
+                    consts.insert(name.clone(), value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn folded(source: &str) -> Program {
+        let mut program = Parser::new(Lexer::new(source)).parse().unwrap();
+        fold(&mut program, Dialect::Modern);
+        program
+    }
+
+    /// The value of the statement at `index`, which must be a LET
+    fn let_value(program: &Program, index: usize) -> &Expr {
+        match &program.statements[index].kind {
+            StmtKind::Let { value, .. } => value,
+            other => panic!("expected LET, got {:?}", other),
+        }
+    }
+
+    fn literal(expr: &Expr) -> Option<&Literal> {
+        match &expr.kind {
+            ExprKind::Literal(lit) => Some(lit),
+            _ => None,
+        }
+    }
+
+    // ===================
+    // Folding Tests
+    // ===================
+
+    #[test]
+    fn test_fold_arithmetic() {
+        let program = folded("X = 2 * 3.5\nY% = 7 \\ 2 + 10 MOD 4\nZ! = 1.5! * 2\nW = -(3 - 5)\n");
+        assert!(matches!(literal(let_value(&program, 0)), Some(Literal::Float(x)) if *x == 7.0));
+        assert!(matches!(
+            literal(let_value(&program, 1)),
+            Some(Literal::Integer(5))
+        ));
+        assert!(matches!(
+            literal(let_value(&program, 2)),
+            Some(Literal::Typed(x, DataType::Single)) if *x == 3.0
+        ));
+        assert!(matches!(
+            literal(let_value(&program, 3)),
+            Some(Literal::Integer(2))
+        ));
+
+        // Comparisons and logical operators give -1 or 0
+        let program =
+            folded("A = 1 < 2\nB = NOT 0\nC = 6 AND 3\nD$ = \"AB\" + \"C\"\nE = 6 ANDALSO 3\n");
+        assert!(matches!(
+            literal(let_value(&program, 0)),
+            Some(Literal::Integer(-1))
+        ));
+        assert!(matches!(
+            literal(let_value(&program, 1)),
+            Some(Literal::Integer(-1))
+        ));
+        assert!(matches!(
+            literal(let_value(&program, 2)),
+            Some(Literal::Integer(2))
+        ));
+        assert!(matches!(
+            literal(let_value(&program, 3)),
+            Some(Literal::String(s)) if s == "ABC"
+        ));
+        assert!(matches!(
+            literal(let_value(&program, 4)),
+            Some(Literal::Integer(-1))
+        ));
+
+        // The constant prefix of a left-to-right product folds
+        let program = folded("X = 2 * 2.5 * R\n");
+        let ExprKind::Binary { left, .. } = &let_value(&program, 0).kind else {
+            panic!("expected a product");
+        };
+        assert!(matches!(literal(left), Some(Literal::Float(x)) if *x == 5.0));
+    }
+
+    #[test]
+    fn test_fold_leaves_runtime_behavior() {
+        // Overflow, division by zero and ^ are left for run time
+        let program =
+            folded("A = 2147483647 + 1\nB = 1 / 0\nC = 5 \\ 0\nD = 2 ^ 3\nE% = 200% * 200%\n");
+        for i in 0..5 {
+            assert!(literal(let_value(&program, i)).is_none(), "statement {}", i);
+        }
+    }
+
+    // ===================
+    // Propagation Tests
+    // ===================
+
+    #[test]
+    fn test_propagate_constants() {
+        let program = folded("N% = 10\nM = N% * 2\nPRINT M + 1\n");
+        assert!(matches!(
+            literal(let_value(&program, 1)),
+            Some(Literal::Integer(20))
+        ));
+        let StmtKind::Print { items, .. } = &program.statements[2].kind else {
+            panic!("expected PRINT");
+        };
+        let PrintItem::Expr(item) = &items[0] else {
+            panic!("expected an expression");
+        };
+        assert!(matches!(literal(item), Some(Literal::Float(x)) if *x == 21.0));
+
+        // The value takes the variable's type: 2.7 truncates into N%
+        let program = folded("N% = 2.7\nM = N% + 1\n");
+        assert!(matches!(
+            literal(let_value(&program, 1)),
+            Some(Literal::Integer(3))
+        ));
+
+        // An unsuffixed variable is SINGLE outside xbasic64
+        let mut program = Parser::new(Lexer::new("N = 0.1\nM# = N\n"))
+            .parse()
+            .unwrap();
+        fold(&mut program, Dialect::Qb45);
+        assert!(matches!(
+            literal(let_value(&program, 1)),
+            Some(Literal::Typed(x, DataType::Single)) if *x == 0.1f32 as f64
+        ));
+    }
+
+    #[test]
+    fn test_propagate_only_when_safe() {
+        let not_propagated = [
+            // Assigned twice
+            "N = 1\nN = 2\nM = N + 1\n",
+            // Written by INPUT or a FOR loop
+            "N = 1\nINPUT N\nM = N + 1\n",
+            "N = 1\nFOR N = 1 TO 2\nNEXT\nM = N + 1\n",
+            // After a GOSUB, the subroutine might run first
+            "GOSUB 100\nN = 1\nM = N + 1\nEND\n100 PRINT N\nRETURN\n",
+            // Visible to a procedure
+            "N = 1\nCALL S\nM = N + 1\nSUB S\nSHARED N\nN = 5\nEND SUB\n",
+            // Passed by name
+            "N = 1\nP = VARPTR(N)\nM = N + 1\n",
+        ];
+        for source in not_propagated {
+            let program = folded(source);
+            let index = program
+                .statements
+                .iter()
+                .position(|s| matches!(&s.kind, StmtKind::Let { name, .. } if name == "M"))
+                .unwrap();
+            assert!(literal(let_value(&program, index)).is_none(), "{}", source);
+        }
+
+        // Reads before the assignment keep the variable
+        let program = folded("M = N + 1\nN = 1\n");
+        assert!(literal(let_value(&program, 0)).is_none());
+        // Procedure locals aren't propagated
+        let program = folded("SUB S\nN = 1\nPRINT N\nEND SUB\n");
+        let StmtKind::Sub { body, .. } = &program.statements[0].kind else {
+            panic!("expected SUB");
+        };
+        assert!(matches!(
+            &body[1].kind,
+            StmtKind::Print { items, .. }
+                if matches!(&items[0], PrintItem::Expr(e) if matches!(e.kind, ExprKind::Variable(_)))
+        ));
+    }
+}
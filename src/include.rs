@@ -0,0 +1,329 @@
+//! $INCLUDE - splices other source files into a program before it is lexed
+//!
+//! A line holding just one of
+//!
+//! ```text
+//! '$INCLUDE: 'common.bi'
+//! REM $INCLUDE: "common.bi"
+//! INCLUDE "common.bas"
+//! ```
+//!
+//! is replaced by the lines of the file it names, which is looked for next
+//! to the file doing the including, then in each `-I` directory in turn.
+//! Included files may include others; a file that ends up including
+//! itself is an error.
+//!
+//! Each line of the combined text remembers the file and line it came
+//! from, so diagnostics and -S comments point at the right place.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A program's text with its includes spliced in
+pub struct Source {
+    pub text: String,
+    files: Vec<File>,         // the main file first, then each include
+    lines: Vec<(usize, u32)>, // file and line number of each line of `text`
+}
+
+struct File {
+    name: String,
+    text: String,
+}
+
+impl Source {
+    /// The source of a program read from `name`, includes not yet expanded
+    pub fn new(name: &str, text: String) -> Self {
+        let lines = (1..=text.lines().count() as u32).map(|n| (0, n)).collect();
+        Source {
+            files: vec![File {
+                name: name.to_string(),
+                text: text.clone(),
+            }],
+            text,
+            lines,
+        }
+    }
+
+    /// Replace the include lines with the files they name. `path` is the
+    /// main file (None for standard input: includes are found from the
+    /// current directory). On an error, the text is cut short at the
+    /// include line at fault, which the error points at.
+    pub fn expand_includes(
+        &mut self,
+        path: Option<&Path>,
+        include_dirs: &[String],
+    ) -> Result<(), Diagnostic> {
+        let main = path.and_then(|path| fs::canonicalize(path).ok());
+        let dir = path
+            .and_then(Path::parent)
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let mut stack = vec![(main, self.files[0].name.clone())];
+        let mut splice = Splice {
+            text: String::with_capacity(self.text.len()),
+            lines: Vec::with_capacity(self.lines.len()),
+            include_dirs,
+        };
+        let result = splice.file(&mut self.files, 0, &dir, &mut stack);
+        self.text = splice.text;
+        self.lines = splice.lines;
+        result
+    }
+
+    /// The main file's name
+    pub fn name(&self) -> &str {
+        &self.files[0].name
+    }
+
+    /// The files spliced in, in the order they were included
+    pub fn included(&self) -> impl Iterator<Item = &str> {
+        self.files[1..].iter().map(|file| file.name.as_str())
+    }
+
+    /// Where a position in the combined text is: the name and text of its
+    /// file, and the position there
+    pub fn locate(&self, span: Span) -> (&str, &str, Span) {
+        let (file, line) = match self.lines.get((span.line as usize).wrapping_sub(1)) {
+            Some(&(file, line)) => (&self.files[file], line),
+            None => (&self.files[0], span.line),
+        };
+        (&file.name, &file.text, Span { line, ..span })
+    }
+
+    /// Whether a line of the combined text is from the main file
+    pub fn in_main_file(&self, line: u32) -> bool {
+        self.lines
+            .get((line as usize).wrapping_sub(1))
+            .is_none_or(|&(file, _)| file == 0)
+    }
+}
+
+/// The combined text as it is built
+struct Splice<'a> {
+    text: String,
+    lines: Vec<(usize, u32)>,
+    include_dirs: &'a [String],
+}
+
+impl Splice<'_> {
+    /// Add the lines of `files[file]`, found in `dir`, expanding includes.
+    /// `stack` holds the files being included, outermost first.
+    fn file(
+        &mut self,
+        files: &mut Vec<File>,
+        file: usize,
+        dir: &Path,
+        stack: &mut Vec<(Option<PathBuf>, String)>,
+    ) -> Result<(), Diagnostic> {
+        let text = std::mem::take(&mut files[file].text);
+        let result = self.lines_of(files, file, &text, dir, stack);
+        files[file].text = text;
+        result
+    }
+
+    fn lines_of(
+        &mut self,
+        files: &mut Vec<File>,
+        file: usize,
+        text: &str,
+        dir: &Path,
+        stack: &mut Vec<(Option<PathBuf>, String)>,
+    ) -> Result<(), Diagnostic> {
+        for (n, line) in (1..).zip(text.lines()) {
+            self.lines.push((file, n));
+            let Some(target) = directive(line) else {
+                self.text.push_str(line);
+                self.text.push('\n');
+                continue;
+            };
+            // The include line itself is left blank
+            self.text.push('\n');
+            let span = Span {
+                line: self.lines.len() as u32,
+                col: (line.len() - line.trim_start().len()) as u32 + 1,
+            };
+            let fail = |message: String| Diagnostic::at(span, message).with_code("include-error");
+
+            let name = target.map_err(|e| fail(e.to_string()))?;
+            let path = self
+                .find(name, dir)
+                .ok_or_else(|| fail(format!("Include file not found: {}", name)))?;
+            let canonical = fs::canonicalize(&path).ok();
+            let shown = path.to_string_lossy().to_string();
+            if canonical.is_some() && stack.iter().any(|(p, _)| *p == canonical) {
+                let cycle: Vec<&str> = stack
+                    .iter()
+                    .skip_while(|(p, _)| *p != canonical)
+                    .map(|(_, name)| name.as_str())
+                    .chain([shown.as_str()])
+                    .collect();
+                return Err(fail(format!("Include cycle: {}", cycle.join(" -> "))));
+            }
+            let included = fs::read_to_string(&path)
+                .map_err(|e| fail(format!("Error reading {}: {}", shown, e)))?;
+
+            files.push(File {
+                name: shown.clone(),
+                text: included,
+            });
+            stack.push((canonical, shown));
+            let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+            self.file(files, files.len() - 1, &dir, stack)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+
+    /// The first of `dir` and the include directories holding `name`
+    fn find(&self, name: &str, dir: &Path) -> Option<PathBuf> {
+        std::iter::once(dir.to_path_buf())
+            .chain(self.include_dirs.iter().map(PathBuf::from))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+}
+
+/// The file an include line names, or what is wrong with it; None for any
+/// other line
+fn directive(line: &str) -> Option<Result<&str, &'static str>> {
+    let line = line.trim();
+    let comment = line
+        .strip_prefix('\'')
+        .or_else(|| strip_keyword(line, "REM"))
+        .map(str::trim_start);
+    if let Some(meta) = comment.and_then(|text| strip_keyword(text, "$INCLUDE")) {
+        let name = meta
+            .trim_start()
+            .strip_prefix(':')
+            .and_then(|rest| quoted(rest.trim()));
+        return Some(name.ok_or("Expected '$INCLUDE: 'file'"));
+    }
+    // INCLUDE "file" - anything else starting INCLUDE is an ordinary line
+    let rest = strip_keyword(line, "INCLUDE")?;
+    quoted(rest.trim())
+        .filter(|_| rest.starts_with([' ', '\t', '"']))
+        .map(Ok)
+}
+
+/// `text` after a leading keyword, matched without regard to case
+fn strip_keyword<'t>(text: &'t str, keyword: &str) -> Option<&'t str> {
+    let head = text.get(..keyword.len())?;
+    head.eq_ignore_ascii_case(keyword)
+        .then(|| &text[keyword.len()..])
+        .filter(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// The contents of a string in single or double quotes making up all of
+/// `text` (a comment may follow a double-quoted name)
+fn quoted(text: &str) -> Option<&str> {
+    let quote = text.chars().next().filter(|c| matches!(c, '\'' | '"'))?;
+    let (name, rest) = text[1..].split_once(quote)?;
+    let rest = rest.trim();
+    (!name.is_empty() && (rest.is_empty() || rest.starts_with('\''))).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ===================
+    // Directives
+    // ===================
+
+    #[test]
+    fn test_directive() {
+        assert_eq!(directive("'$INCLUDE: 'common.bi'"), Some(Ok("common.bi")));
+        assert_eq!(directive("  REM $include: \"a b.bi\""), Some(Ok("a b.bi")));
+        assert_eq!(directive("' $INCLUDE:'x.bi'"), Some(Ok("x.bi")));
+        assert_eq!(directive("INCLUDE \"lib.bas\""), Some(Ok("lib.bas")));
+        assert_eq!(
+            directive("include \"lib.bas\" ' shared"),
+            Some(Ok("lib.bas"))
+        );
+        assert_eq!(
+            directive("'$INCLUDE common.bi"),
+            Some(Err("Expected '$INCLUDE: 'file'"))
+        );
+        assert_eq!(directive("' include these"), None);
+        assert_eq!(directive("INCLUDED = 1"), None);
+        assert_eq!(directive("PRINT \"INCLUDE\""), None);
+        assert_eq!(directive("REMARK"), None);
+    }
+
+    // ===================
+    // Splicing
+    // ===================
+
+    #[test]
+    fn test_expand_includes() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let lib = tmp.path().join("lib");
+        fs::create_dir(&lib).unwrap();
+        fs::write(
+            tmp.path().join("a.bi"),
+            "A = 1\n'$INCLUDE: 'b.bi'\nA2 = 2\n",
+        )
+        .unwrap();
+        fs::write(lib.join("b.bi"), "B = 3\n").unwrap();
+        let main = tmp.path().join("main.bas");
+        let text = "PRINT 0\nINCLUDE \"a.bi\"\nPRINT A\n";
+        fs::write(&main, text).unwrap();
+
+        let dirs = [lib.to_string_lossy().to_string()];
+        let mut source = Source::new("main.bas", text.to_string());
+        source.expand_includes(Some(&main), &dirs).unwrap();
+        assert_eq!(source.text, "PRINT 0\n\nA = 1\n\nB = 3\nA2 = 2\nPRINT A\n");
+
+        let at = |line| {
+            let (name, _, span) = source.locate(Span { line, col: 2 });
+            let name = Path::new(name).file_name().unwrap().to_str().unwrap();
+            (name.to_string(), span.line, span.col)
+        };
+        assert_eq!(at(1), ("main.bas".to_string(), 1, 2));
+        assert_eq!(at(3), ("a.bi".to_string(), 1, 2));
+        assert_eq!(at(5), ("b.bi".to_string(), 1, 2));
+        assert_eq!(at(6), ("a.bi".to_string(), 3, 2));
+        assert_eq!(at(7), ("main.bas".to_string(), 3, 2));
+        assert!(source.in_main_file(7) && !source.in_main_file(5));
+        let included: Vec<&str> = source.included().collect();
+        assert!(
+            included.len() == 2 && included[0].ends_with("a.bi") && included[1].ends_with("b.bi"),
+            "{:?}",
+            included
+        );
+
+        // Without the include directory, b.bi isn't found; the error is at
+        // the include line in a.bi
+        let mut source = Source::new("main.bas", text.to_string());
+        let err = source.expand_includes(Some(&main), &[]).unwrap_err();
+        assert_eq!(err.message, "Include file not found: b.bi");
+        let (name, _, span) = source.locate(err.span.unwrap());
+        assert!(name.ends_with("a.bi"), "{}", name);
+        assert_eq!((span.line, span.col), (2, 1));
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        fs::write(tmp.path().join("a.bi"), "'$INCLUDE: 'b.bi'\n").unwrap();
+        fs::write(tmp.path().join("b.bi"), "  INCLUDE \"a.bi\"\n").unwrap();
+        let main = tmp.path().join("main.bas");
+        let text = "'$INCLUDE: 'a.bi'\n";
+        let mut source = Source::new("main.bas", text.to_string());
+        let err = source.expand_includes(Some(&main), &[]).unwrap_err();
+        assert!(
+            err.message.starts_with("Include cycle: ") && err.message.ends_with("a.bi"),
+            "{}",
+            err.message
+        );
+        let (name, _, span) = source.locate(err.span.unwrap());
+        assert!(name.ends_with("b.bi"), "{}", name);
+        assert_eq!(span.col, 3);
+    }
+}
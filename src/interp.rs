@@ -0,0 +1,3258 @@
+//! Direct interpreter - runs a checked program without assembling or linking
+//! it (`--run`)
+//!
+//! Each procedure's body, and the main program, is flattened into a list of
+//! operations where IF, FOR, WHILE, DO and SELECT CASE become jumps, so GOTO
+//! and GOSUB can land anywhere in their procedure just as they can in
+//! compiled code. Expressions are evaluated straight from the AST.
+//!
+//! Values follow the compiled program's rules rather than an idealized
+//! BASIC: INTEGER and LONG arithmetic is done in 32 bits and an INTEGER is cut
+//! to 16 when stored, conversions to integers truncate (CINT and CLNG round
+//! to even), numbers print through the same `%ld`/`%.*g` formats, and console
+//! and file output go through a port of the runtime's print engine
+//! (print.s), with its column tracking, print zones and WIDTH wrapping.
+//! INPUT splits and validates lines like input.s, and INPUT # reads files a
+//! line at a time like file.s.
+//!
+//! Graphics, the emulated memory space and event trapping live in the
+//! runtime only; programs using them are rejected before they start.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::codegen::const_int;
+use crate::diagnostic::Diagnostic;
+use crate::fold;
+use crate::lexer::Dialect;
+use crate::parser::{
+    BinaryOp, DataType, Expr, ExprKind, FileMode, GotoTarget, Literal, Param, PrintItem, Program,
+    Stmt, StmtKind, UnaryOp,
+};
+use crate::types::{self, is_comparison, promote, widest};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// GOSUB return addresses that fit on the compiled program's stack
+const GOSUB_DEPTH: usize = crate::ir::GOSUB_STACK_SIZE as usize / 8;
+
+/// Stack for the interpreter thread (see with_big_stack)
+const STACK_SIZE: usize = 256 << 20;
+
+/// Output channels: 0 is the console, 1-15 are files (as in print.s)
+const CHANNELS: usize = 16;
+
+/// A width that never wraps
+const WIDTH_INFINITE: i64 = 255;
+
+/// Columns per print zone (comma in PRINT)
+const ZONE_WIDTH: usize = 14;
+
+/// Longest line INPUT and LINE INPUT take, like the runtime's buffer
+const INPUT_MAX: usize = 1023;
+
+/// Largest array DIM will allocate, in elements
+const MAX_ELEMENTS: i64 = 1 << 28;
+
+/// Initial state of RND's generator (see math.s)
+const RNG_SEED: u64 = 0x1234_5678_DEAD_BEEF;
+
+/// One more than the most dictionaries DICTNEW makes (see dict.s)
+const DICT_MAX: usize = 256;
+
+/// Size of the text screen SCREEN(row, col) reads (see print.s)
+const SCREEN_ROWS: usize = 25;
+const SCREEN_COLS: usize = 80;
+
+/// Name of a statement the interpreter can't run
+fn unsupported(kind: &StmtKind) -> Option<&'static str> {
+    let name = match kind {
+        StmtKind::Screen { .. } => "SCREEN",
+        StmtKind::Pset { preset: false, .. } => "PSET",
+        StmtKind::Pset { preset: true, .. } => "PRESET",
+        StmtKind::GraphicsLine { .. } => "LINE",
+        StmtKind::Circle { .. } => "CIRCLE",
+        StmtKind::Paint { .. } => "PAINT",
+        StmtKind::Draw { .. } => "DRAW",
+        StmtKind::GetImage { .. } => "GET",
+        StmtKind::PutImage { .. } => "PUT",
+        StmtKind::Display => "DISPLAY",
+        StmtKind::Palette { .. } => "PALETTE",
+        StmtKind::DefSeg { .. } => "DEF SEG",
+        StmtKind::Poke { .. } => "POKE",
+        StmtKind::Bsave { .. } => "BSAVE",
+        StmtKind::Bload { .. } => "BLOAD",
+        StmtKind::Lock { unlock: false, .. } => "LOCK",
+        StmtKind::Lock { unlock: true, .. } => "UNLOCK",
+        StmtKind::OnKey { .. } => "ON KEY",
+        StmtKind::KeyTrap { .. } => "KEY",
+        StmtKind::OnTimer { .. } => "ON TIMER",
+        StmtKind::TimerTrap(_) => "TIMER",
+        StmtKind::OnBreak { .. } => "ON BREAK",
+        StmtKind::BreakTrap(_) => "BREAK",
+        StmtKind::OnMouse { .. } => "ON MOUSE",
+        StmtKind::MouseTrap(_) => "MOUSE",
+        StmtKind::Declare {
+            foreign: Some(_), ..
+        } => "DECLARE ... LIB",
+        StmtKind::Asm { .. } => "ASM",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Reject programs with statements only the compiled runtime supports
+pub fn check(program: &Program) -> Result<(), Diagnostic> {
+    check_stmts(&program.statements)
+}
+
+fn check_stmts(stmts: &[Stmt]) -> Result<(), Diagnostic> {
+    for stmt in stmts {
+        if let Some(name) = unsupported(&stmt.kind) {
+            let message = format!("{} is not supported by --run", name);
+            return Err(Diagnostic::at(stmt.span, message).with_code("unsupported"));
+        }
+        for body in stmt.kind.bodies() {
+            check_stmts(body)?;
+        }
+    }
+    Ok(())
+}
+
+/// The main program's variables and arrays, which interactive mode keeps
+/// from one statement or RUN to the next, and the console's print column
+#[derive(Default)]
+pub struct Globals {
+    frame: Frame,
+    column: usize,
+}
+
+impl Globals {
+    /// Whether the last run left the console part way along a line. The
+    /// caller is about to end it, so the next run starts at column 0.
+    pub fn end_line(&mut self) -> bool {
+        std::mem::take(&mut self.column) != 0
+    }
+}
+
+/// Run a checked program, written in `dialect`, on stdin and stdout.
+/// Returns the runtime error that stopped it, its message formatted like
+/// the compiled program's (`"... in <line>"`).
+pub fn run(program: &Program, overflow_check: bool, dialect: Dialect) -> Result<(), Diagnostic> {
+    with_big_stack(|| run_with(program, overflow_check, dialect, &mut Globals::default()))
+}
+
+/// Run a checked program starting with, and leaving its variables in,
+/// `globals`. Deeply recursive programs need `with_big_stack`.
+pub fn run_with(
+    program: &Program,
+    overflow_check: bool,
+    dialect: Dialect,
+    globals: &mut Globals,
+) -> Result<(), Diagnostic> {
+    let mut program = program.clone();
+    fold::substitute_consts(&mut program)?;
+    let input = Box::new(io::stdin().lock());
+    let output = Box::new(BufWriter::new(io::stdout().lock()));
+    let mut interp = Interpreter::new(&program, overflow_check, dialect, input, output);
+    interp.set_globals(std::mem::take(&mut globals.frame));
+    interp.cols[0] = globals.column;
+    let result = interp.run();
+    globals.frame = std::mem::take(&mut interp.frames[0]);
+    globals.column = interp.cols[0];
+    result.map_err(|message| Diagnostic::new(None, message).with_code("runtime-error"))
+}
+
+/// Call `f` on a thread with a stack big enough for recursive programs:
+/// every BASIC procedure call nests a few Rust calls
+pub fn with_big_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("interpreter thread starts")
+            .join()
+            .expect("interpreter thread finishes")
+    })
+}
+
+// ============================================================================
+// Values
+// ============================================================================
+
+/// What the console shows, as _scr_track in print.s records it: printable
+/// characters fill cells and wrap at the right edge, newline and carriage
+/// return move the cursor, and a newline on the last row scrolls
+struct TextScreen {
+    cells: Vec<u8>,
+    row: usize,
+    col: usize,
+}
+
+impl TextScreen {
+    fn new() -> Self {
+        TextScreen {
+            cells: vec![b' '; SCREEN_ROWS * SCREEN_COLS],
+            row: 0,
+            col: 0,
+        }
+    }
+
+    fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < SCREEN_ROWS {
+            self.row += 1;
+        } else {
+            self.cells.copy_within(SCREEN_COLS.., 0);
+            self.cells[(SCREEN_ROWS - 1) * SCREEN_COLS..].fill(b' ');
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            match b {
+                b'\n' => self.newline(),
+                b'\r' => self.col = 0,
+                0..=31 => {}
+                _ => {
+                    if self.col == SCREEN_COLS {
+                        self.newline();
+                    }
+                    self.cells[self.row * SCREEN_COLS + self.col] = b;
+                    self.col += 1;
+                }
+            }
+        }
+    }
+
+    /// The character code at a 1-based position, if it is on the screen
+    fn char_at(&self, row: i64, col: i64) -> Option<u8> {
+        let row = usize::try_from(row - 1).ok().filter(|&r| r < SCREEN_ROWS)?;
+        let col = usize::try_from(col - 1).ok().filter(|&c| c < SCREEN_COLS)?;
+        Some(self.cells[row * SCREEN_COLS + col])
+    }
+}
+
+/// A DICTNEW dictionary: its entries in the order their keys were first
+/// set, and where each key is among them
+#[derive(Default)]
+struct Dict {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    index: HashMap<Vec<u8>, usize>,
+}
+
+impl Dict {
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    /// Remove a key, keeping the others in order
+    fn delete(&mut self, key: &[u8]) {
+        if let Some(i) = self.index.remove(key) {
+            self.entries.remove(i);
+            for place in self.index.values_mut().filter(|place| **place > i) {
+                *place -= 1;
+            }
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let &i = self.index.get(key)?;
+        Some(&self.entries[i].1)
+    }
+}
+
+/// A value with its BASIC type. INTEGER values may hold more than 16 bits
+/// in the middle of an expression, as eax does in compiled code.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Integer(i32),
+    Long(i32),
+    Single(f32),
+    Double(f64),
+    Str(Vec<u8>),
+}
+
+impl Value {
+    fn zero(data_type: DataType) -> Value {
+        match data_type {
+            DataType::Integer => Value::Integer(0),
+            DataType::Long => Value::Long(0),
+            DataType::Single => Value::Single(0.0),
+            DataType::Double => Value::Double(0.0),
+            DataType::String => Value::Str(Vec::new()),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Value::Integer(_) => DataType::Integer,
+            Value::Long(_) => DataType::Long,
+            Value::Single(_) => DataType::Single,
+            Value::Double(_) => DataType::Double,
+            Value::Str(_) => DataType::String,
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        match self {
+            Value::Integer(n) | Value::Long(n) => *n as f64,
+            Value::Single(x) => *x as f64,
+            Value::Double(x) => *x,
+            Value::Str(_) => unreachable!("strings are checked before running"),
+        }
+    }
+
+    /// A float rounded to a whole number, half to even as CLNG rounds
+    fn round_even(self) -> Value {
+        match self {
+            Value::Single(x) => Value::Single(x.round_ties_even()),
+            Value::Double(x) => Value::Double(x.round_ties_even()),
+            value => value,
+        }
+    }
+
+    /// Integer value (after conversion to INTEGER or LONG)
+    fn to_i32(&self) -> i32 {
+        match self {
+            Value::Integer(n) | Value::Long(n) => *n,
+            _ => unreachable!("converted to an integer type first"),
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Value::Str(s) => s,
+            _ => unreachable!("strings are checked before running"),
+        }
+    }
+
+    /// Whether a condition is false. A NaN compares equal to zero, as it
+    /// does with ucomisd.
+    fn is_zero(&self) -> bool {
+        match self {
+            Value::Integer(n) | Value::Long(n) => *n == 0,
+            Value::Single(x) => *x == 0.0 || x.is_nan(),
+            Value::Double(x) => *x == 0.0 || x.is_nan(),
+            Value::Str(_) => unreachable!("strings are checked before running"),
+        }
+    }
+
+    /// The value as kept in a variable or array element: an INTEGER is cut
+    /// to 16 bits
+    fn stored(self) -> Value {
+        match self {
+            Value::Integer(n) => Value::Integer(n as i16 as i32),
+            other => other,
+        }
+    }
+}
+
+fn bool_value(b: bool) -> Value {
+    Value::Long(if b { -1 } else { 0 })
+}
+
+fn int_value(data_type: DataType, n: i32) -> Value {
+    if data_type == DataType::Integer {
+        Value::Integer(n)
+    } else {
+        Value::Long(n)
+    }
+}
+
+/// Value of a literal in an expression
+fn literal_value(lit: &Literal) -> Value {
+    match lit {
+        Literal::Integer(n) => match i32::try_from(*n) {
+            Ok(n) => Value::Long(n),
+            // Too big for a Long: a Double constant
+            Err(_) => Value::Double(*n as f64),
+        },
+        Literal::Float(x) => Value::Double(*x),
+        Literal::Typed(x, data_type) => match data_type {
+            DataType::Integer | DataType::Long => int_value(*data_type, *x as i32),
+            DataType::Single => Value::Single(*x as f32),
+            _ => Value::Double(*x),
+        },
+        Literal::String(s) => Value::Str(s.as_bytes().to_vec()),
+    }
+}
+
+/// cvttsd2si to 32 bits: truncate toward zero, with out-of-range values and
+/// NaN giving the "integer indefinite" i32::MIN
+fn trunc_i32(x: f64) -> i32 {
+    let t = x.trunc();
+    if (i32::MIN as f64..=i32::MAX as f64).contains(&t) {
+        t as i32
+    } else {
+        i32::MIN
+    }
+}
+
+/// 2^63, the first double past i64::MAX
+const TWO_63: f64 = 9_223_372_036_854_775_808.0;
+
+/// cvttsd2si to 64 bits
+fn trunc_i64(x: f64) -> i64 {
+    let t = x.trunc();
+    if (-TWO_63..TWO_63).contains(&t) {
+        t as i64
+    } else {
+        i64::MIN
+    }
+}
+
+/// cvtsd2si to 64 bits: round to nearest, ties to even
+fn round_i64(x: f64) -> i64 {
+    trunc_i64(x.round_ties_even())
+}
+
+fn fits_integer(n: i32) -> bool {
+    n as i16 as i32 == n
+}
+
+/// Outcome of a comparison as ucomisd reports it: an unordered (NaN)
+/// comparison looks both equal and less
+fn compare(op: BinaryOp, ord: Option<Ordering>) -> bool {
+    let (less, equal) = match ord {
+        Some(Ordering::Less) => (true, false),
+        Some(Ordering::Equal) => (false, true),
+        Some(Ordering::Greater) => (false, false),
+        None => (true, true),
+    };
+    match op {
+        BinaryOp::Eq => equal,
+        BinaryOp::Ne => !equal,
+        BinaryOp::Lt => less,
+        BinaryOp::Gt => !less && !equal,
+        BinaryOp::Le => less || equal,
+        BinaryOp::Ge => !less,
+        _ => unreachable!("not a comparison"),
+    }
+}
+
+// ============================================================================
+// Number formatting and parsing (libc's %g and strtod)
+// ============================================================================
+
+/// Format like printf's `%.<precision>g`
+fn format_g(x: f64, precision: usize) -> String {
+    if x.is_nan() {
+        return if x.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if x.is_infinite() {
+        return if x < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    if x == 0.0 {
+        return if x.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+    let precision = precision.max(1);
+    // The exponent after rounding to `precision` significant digits decides
+    // between %e and %f style
+    let sci = format!("{:.*e}", precision - 1, x);
+    let (mantissa, exp) = sci.split_once('e').expect("exponent format");
+    let exp: i32 = exp.parse().expect("exponent");
+    if exp < -4 || exp >= precision as i32 {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", strip_zeros(mantissa), sign, exp.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exp) as usize;
+        strip_zeros(&format!("{:.*}", decimals, x)).to_string()
+    }
+}
+
+/// Drop trailing zeros after a decimal point, and the point if nothing is left
+fn strip_zeros(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+/// A number as PRINT shows it: whole numbers as integers, others with %g
+/// to `precision` digits (7 for SINGLE), or for 0 (DOUBLE) the fewest of
+/// 15, 16 or 17 that read back as the same value
+fn format_number(x: f64, precision: usize) -> String {
+    let n = trunc_i64(x);
+    if n as f64 == x {
+        n.to_string()
+    } else if precision == 0 {
+        (15..17)
+            .map(|precision| format_g(x, precision))
+            .find(|text| strtod(text.as_bytes()).0 == x)
+            .unwrap_or_else(|| format_g(x, 17))
+    } else {
+        format_g(x, precision)
+    }
+}
+
+/// Parse a number at the start of `s` like strtod: returns the value and the
+/// bytes used (0 when there is no number)
+fn strtod(s: &[u8]) -> (f64, usize) {
+    let at = |i: usize| s.get(i).copied().unwrap_or(0);
+    let mut i = 0;
+    while matches!(at(i), b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r') {
+        i += 1;
+    }
+    let start = i;
+    if matches!(at(i), b'+' | b'-') {
+        i += 1;
+    }
+    let negative = at(start) == b'-';
+    let sign = if negative { -1.0 } else { 1.0 };
+
+    // inf, infinity, nan
+    let rest = &s[i..];
+    let lower = |n: usize| rest.get(..n).map(|w| w.to_ascii_lowercase());
+    if lower(8).as_deref() == Some(b"infinity") {
+        return (sign * f64::INFINITY, i + 8);
+    }
+    if lower(3).as_deref() == Some(b"inf") {
+        return (sign * f64::INFINITY, i + 3);
+    }
+    if lower(3).as_deref() == Some(b"nan") {
+        return (sign * f64::NAN, i + 3);
+    }
+
+    // Hexadecimal: 0x digits [. digits] [p exponent]
+    if at(i) == b'0' && matches!(at(i + 1), b'x' | b'X') {
+        let mut j = i + 2;
+        let mut value = 0.0;
+        let mut digits = 0;
+        while let Some(d) = (at(j) as char).to_digit(16) {
+            value = value * 16.0 + d as f64;
+            digits += 1;
+            j += 1;
+        }
+        if at(j) == b'.' {
+            j += 1;
+            let mut scale = 1.0 / 16.0;
+            while let Some(d) = (at(j) as char).to_digit(16) {
+                value += d as f64 * scale;
+                scale /= 16.0;
+                digits += 1;
+                j += 1;
+            }
+        }
+        if digits == 0 {
+            // Just the "0"
+            return (0.0 * sign, i + 1);
+        }
+        if matches!(at(j), b'p' | b'P') {
+            let (exp, used) = exponent(&s[j + 1..]);
+            if used > 0 {
+                value *= 2f64.powi(exp);
+                j += 1 + used;
+            }
+        }
+        return (sign * value, j);
+    }
+
+    // Decimal: digits [. digits] [e exponent]
+    let mut j = i;
+    let mut digits = 0;
+    while at(j).is_ascii_digit() {
+        j += 1;
+        digits += 1;
+    }
+    if at(j) == b'.' {
+        j += 1;
+        while at(j).is_ascii_digit() {
+            j += 1;
+            digits += 1;
+        }
+    }
+    if digits == 0 {
+        return (0.0, 0);
+    }
+    if matches!(at(j), b'e' | b'E') {
+        let (_, used) = exponent(&s[j + 1..]);
+        if used > 0 {
+            j += 1 + used;
+        }
+    }
+    let text = std::str::from_utf8(&s[start..j]).expect("ASCII number");
+    (text.parse().unwrap_or(0.0), j)
+}
+
+/// Parse a signed decimal exponent; returns it and the bytes used
+fn exponent(s: &[u8]) -> (i32, usize) {
+    let mut i = usize::from(matches!(s.first(), Some(b'+' | b'-')));
+    let digits_start = i;
+    let mut exp: i32 = 0;
+    while let Some(d) = s.get(i).filter(|c| c.is_ascii_digit()) {
+        exp = exp.saturating_mul(10).saturating_add((d - b'0') as i32);
+        i += 1;
+    }
+    if i == digits_start {
+        return (0, 0);
+    }
+    if s[0] == b'-' {
+        exp = -exp;
+    }
+    (exp, i)
+}
+
+/// Split the next comma-separated field off an input line, like
+/// _in_next_field in input.s: blanks around a field are dropped, and a field
+/// in double quotes is taken as it is. Returns the field, where the next one
+/// starts, and what ended this one: a comma, 0 at the end of the line, or
+/// anything else for junk after a quoted field.
+fn next_field(line: &[u8], mut pos: usize) -> (std::ops::Range<usize>, usize, u8) {
+    let at = |i: usize| line.get(i).copied().unwrap_or(0);
+    let is_blank = |c: u8| c == b' ' || c == b'\t';
+    while is_blank(at(pos)) {
+        pos += 1;
+    }
+    let field = if at(pos) == b'"' {
+        pos += 1;
+        let start = pos;
+        while !matches!(at(pos), 0 | b'"') {
+            pos += 1;
+        }
+        let field = start..pos;
+        if at(pos) != 0 {
+            pos += 1;
+            while is_blank(at(pos)) {
+                pos += 1;
+            }
+        }
+        field
+    } else {
+        let start = pos;
+        while !matches!(at(pos), 0 | b',') {
+            pos += 1;
+        }
+        let mut end = pos;
+        while end > start && is_blank(line[end - 1]) {
+            end -= 1;
+        }
+        start..end
+    };
+    let ended_by = at(pos);
+    if ended_by == b',' {
+        pos += 1;
+    }
+    (field, pos, ended_by)
+}
+
+/// Whether an INPUT line has one valid field per target (`true` for a
+/// string target): numeric fields must be a whole number or empty
+fn input_fits(line: &[u8], string_targets: &[bool]) -> bool {
+    let mut pos = 0;
+    for (i, &is_string) in string_targets.iter().enumerate() {
+        let (field, next, ended_by) = next_field(line, pos);
+        pos = next;
+        if !is_string && !field.is_empty() && strtod(&line[field.clone()]).1 != field.len() {
+            return false;
+        }
+        let last = i + 1 == string_targets.len();
+        if (last && ended_by != 0) || (!last && ended_by != b',') {
+            return false;
+        }
+    }
+    true
+}
+
+// ============================================================================
+// PRINT USING formats (using.s)
+// ============================================================================
+
+/// Most decimals (and exponent digits) PRINT USING writes
+const USING_MAX_DIGITS: usize = 100;
+
+/// A field of a PRINT USING format
+enum UsingField {
+    Number(NumberField),
+    /// Characters of the string written (0 = all of it)
+    String(usize),
+}
+
+#[derive(Default)]
+struct NumberField {
+    digits: usize,           // positions before the point
+    decimals: Option<usize>, // None = no point
+    plus: bool,              // leading +
+    trail_plus: bool,
+    trail_minus: bool,
+    comma: bool,
+    stars: bool,
+    dollar: bool,
+    exp: usize, // exponent digits (0 = none)
+}
+
+impl NumberField {
+    fn signed(&self) -> bool {
+        self.plus || self.trail_plus || self.trail_minus
+    }
+}
+
+/// The field at `pos` in a format, and how many characters it takes
+fn using_field(fmt: &[u8], pos: usize) -> Option<(UsingField, usize)> {
+    let at = |i: usize| fmt.get(i).copied().unwrap_or(0);
+    match at(pos) {
+        b'!' => return Some((UsingField::String(1), 1)),
+        b'&' => return Some((UsingField::String(0), 1)),
+        b'\\' => {
+            let end = pos + 1 + fmt[pos + 1..].iter().take_while(|&&c| c == b' ').count();
+            return (at(end) == b'\\').then(|| {
+                let width = end + 1 - pos;
+                (UsingField::String(width), width)
+            });
+        }
+        _ => {}
+    }
+
+    let mut field = NumberField::default();
+    let mut i = pos;
+    if at(i) == b'+' {
+        field.plus = true;
+        i += 1;
+    }
+    if at(i) == b'*' && at(i + 1) == b'*' {
+        field.stars = true;
+        field.digits = 2;
+        i += 2;
+        if at(i) == b'$' {
+            field.dollar = true;
+            i += 1;
+        }
+    } else if at(i) == b'$' && at(i + 1) == b'$' {
+        // One $ is a digit position, the other the sign
+        field.dollar = true;
+        field.digits = 1;
+        i += 2;
+    }
+    loop {
+        match at(i) {
+            b'#' => {}
+            // A comma belongs to the field between digit positions and the point
+            b',' if field.digits > 0 && matches!(at(i + 1), b'#' | b',' | b'.') => {
+                field.comma = true
+            }
+            _ => break,
+        }
+        field.digits += 1;
+        i += 1;
+    }
+    if at(i) == b'.' && (field.digits > 0 || at(i + 1) == b'#') {
+        i += 1;
+        let decimals = fmt[i..].iter().take_while(|&&c| c == b'#').count();
+        field.decimals = Some(decimals);
+        i += decimals;
+    }
+    // "+" or "." on its own is text
+    if field.digits == 0 && field.decimals.is_none_or(|d| d == 0) {
+        return None;
+    }
+    if fmt[i..].starts_with(b"^^^^") {
+        field.exp = 2;
+        i += 4;
+        if at(i) == b'^' {
+            field.exp = 3;
+            i += 1;
+        }
+    }
+    if !field.plus {
+        match at(i) {
+            b'+' => field.trail_plus = true,
+            b'-' => field.trail_minus = true,
+            _ => {}
+        }
+        if field.signed() {
+            i += 1;
+        }
+    }
+    Some((UsingField::Number(field), i - pos))
+}
+
+/// Where the next field at or after `pos` starts (the format's length if
+/// there is none); `_` makes the character after it text
+fn using_scan(fmt: &[u8], mut pos: usize) -> usize {
+    while pos < fmt.len() && using_field(fmt, pos).is_none() {
+        if fmt[pos] == b'_' && pos + 1 < fmt.len() {
+            pos += 1;
+        }
+        pos += 1;
+    }
+    pos
+}
+
+/// The text of a format from `pos` to `end`, without its `_` escapes
+fn using_text(fmt: &[u8], mut pos: usize, end: usize) -> Vec<u8> {
+    let mut text = Vec::new();
+    while pos < end {
+        if fmt[pos] == b'_' && pos + 1 < end {
+            pos += 1;
+        }
+        text.push(fmt[pos]);
+        pos += 1;
+    }
+    text
+}
+
+/// A number written through a field `width` characters wide
+fn using_number(field: &NumberField, width: usize, x: f64) -> Vec<u8> {
+    let neg = x < 0.0;
+    let x = x.abs();
+    let decimals = field.decimals.unwrap_or(0).min(USING_MAX_DIGITS);
+    let mut digits = field.digits;
+    let (text, exponent) = if field.exp == 0 {
+        (format!("{:.*}", decimals, x), String::new())
+    } else {
+        // The digits before the point fill the digit positions, less one
+        // kept for a minus sign unless the field has a sign of its own
+        if !field.signed() {
+            digits = digits.saturating_sub(1);
+        }
+        if digits == 0 && decimals == 0 {
+            digits = 1;
+        }
+        let precision = (digits + decimals).saturating_sub(1).min(USING_MAX_DIGITS);
+        let sci = format!("{:.*e}", precision, x);
+        let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+        let mantissa: String = mantissa.chars().filter(|&c| c != '.').collect();
+        let (whole, fraction) = mantissa.split_at(digits.min(mantissa.len()));
+        let mut text = if whole.is_empty() { "0" } else { whole }.to_string();
+        if !fraction.is_empty() {
+            text.push('.');
+            text.push_str(fraction);
+        }
+        let exp = if x == 0.0 {
+            0
+        } else {
+            exp.parse::<i64>().unwrap_or(0) - (digits as i64 - 1)
+        };
+        let sign = if exp < 0 { '-' } else { '+' };
+        (text, format!("E{}{:0w$}", sign, exp.abs(), w = field.exp))
+    };
+
+    let (mut whole, tail) = text.split_at(text.find('.').unwrap_or(text.len()));
+    // A field with no digit positions before the point drops a lone 0
+    if digits == 0 && whole == "0" {
+        whole = "";
+    }
+    let mut body = String::new();
+    let sign = if neg { '-' } else { '+' };
+    if field.plus || (neg && !field.trail_plus && !field.trail_minus) {
+        body.push(sign);
+    }
+    if field.dollar {
+        body.push('$');
+    }
+    for (i, c) in whole.chars().enumerate() {
+        body.push(c);
+        let left = whole.len() - 1 - i;
+        if field.comma && left > 0 && left % 3 == 0 {
+            body.push(',');
+        }
+    }
+    body.push_str(tail);
+    if field.decimals == Some(0) {
+        body.push('.');
+    }
+    body.push_str(&exponent);
+    if field.trail_plus {
+        body.push(sign);
+    } else if field.trail_minus {
+        body.push(if neg { '-' } else { ' ' });
+    }
+
+    let mut out = Vec::new();
+    match width.checked_sub(body.len()) {
+        Some(fill) => out.resize(fill, if field.stars { b'*' } else { b' ' }),
+        None => out.push(b'%'),
+    }
+    out.extend_from_slice(body.as_bytes());
+    out
+}
+
+// ============================================================================
+// Flattened code
+// ============================================================================
+
+/// One step of a flattened procedure or main program
+#[derive(Debug)]
+enum Op<'a> {
+    /// A statement with no control flow of its own
+    Stmt(&'a Stmt),
+    /// A line number label
+    Line(u32),
+    Jump(usize),
+    /// Jump to `target` if `cond` is zero (`if_zero`) or nonzero
+    Branch {
+        cond: &'a Expr,
+        if_zero: bool,
+        target: usize,
+    },
+    Goto(u32),
+    Gosub(u32),
+    Return,
+    OnGoto {
+        expr: &'a Expr,
+        targets: Vec<u32>,
+    },
+    /// Assign the start value and save the end and step in a slot
+    ForInit {
+        var: &'a str,
+        start: &'a Expr,
+        end: &'a Expr,
+        step: Option<&'a Expr>,
+        slot: usize,
+    },
+    /// Leave the loop (jump to `exit`) once the variable is past the end
+    ForTest {
+        var: &'a str,
+        slot: usize,
+        exit: usize,
+    },
+    /// Step the variable; go back to `test` (or straight to `body` for an
+    /// integer loop, which tests here)
+    ForNext {
+        var: &'a str,
+        slot: usize,
+        test: usize,
+        body: usize,
+    },
+    /// Evaluate a SELECT CASE expression into a slot
+    Select {
+        expr: &'a Expr,
+        slot: usize,
+    },
+    /// Go on to `next` unless the selected value equals `value`
+    Case {
+        value: &'a Expr,
+        slot: usize,
+        next: usize,
+    },
+    End,
+}
+
+/// The flattened body of a procedure or the main program
+#[derive(Debug, Default)]
+struct Code<'a> {
+    ops: Vec<Op<'a>>,
+    lines: HashMap<u32, usize>, // line number -> index of its Line op
+    slots: usize,               // FOR and SELECT CASE temporaries
+    shared: Vec<&'a Param>,     // names SHARED with the main program
+}
+
+impl<'a> Code<'a> {
+    fn new(stmts: &'a [Stmt]) -> Self {
+        let mut code = Code::default();
+        code.lower(stmts);
+        code
+    }
+
+    fn push(&mut self, op: Op<'a>) -> usize {
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    fn new_slot(&mut self) -> usize {
+        self.slots += 1;
+        self.slots - 1
+    }
+
+    /// Point the jump at `at` to the next op
+    fn patch(&mut self, at: usize) {
+        let here = self.ops.len();
+        match &mut self.ops[at] {
+            Op::Jump(target)
+            | Op::Branch { target, .. }
+            | Op::ForTest { exit: target, .. }
+            | Op::Case { next: target, .. } => *target = here,
+            op => unreachable!("{:?} has no jump to patch", op),
+        }
+    }
+
+    fn lower(&mut self, stmts: &'a [Stmt]) {
+        for stmt in stmts {
+            self.lower_stmt(stmt);
+        }
+    }
+
+    fn lower_stmt(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Label(n) => {
+                let at = self.push(Op::Line(*n));
+                self.lines.insert(*n, at);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let branch = self.push(Op::Branch {
+                    cond: condition,
+                    if_zero: true,
+                    target: 0,
+                });
+                self.lower(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let jump = self.push(Op::Jump(0));
+                        self.patch(branch);
+                        self.lower(else_branch);
+                        self.patch(jump);
+                    }
+                    None => self.patch(branch),
+                }
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let slot = self.new_slot();
+                self.push(Op::ForInit {
+                    var,
+                    start,
+                    end,
+                    step: step.as_ref(),
+                    slot,
+                });
+                let test = self.push(Op::ForTest { var, slot, exit: 0 });
+                let body_start = self.ops.len();
+                self.lower(body);
+                self.push(Op::ForNext {
+                    var,
+                    slot,
+                    test,
+                    body: body_start,
+                });
+                self.patch(test);
+            }
+            StmtKind::While { condition, body } => {
+                let start = self.ops.len();
+                let branch = self.push(Op::Branch {
+                    cond: condition,
+                    if_zero: true,
+                    target: 0,
+                });
+                self.lower(body);
+                self.push(Op::Jump(start));
+                self.patch(branch);
+            }
+            StmtKind::DoLoop {
+                condition,
+                cond_at_start,
+                is_until,
+                body,
+            } => {
+                // Leave when a WHILE condition is false or an UNTIL one true;
+                // repeat while a WHILE condition is true or an UNTIL one false
+                let start = self.ops.len();
+                let branch = condition.as_ref().filter(|_| *cond_at_start).map(|cond| {
+                    self.push(Op::Branch {
+                        cond,
+                        if_zero: !*is_until,
+                        target: 0,
+                    })
+                });
+                self.lower(body);
+                match condition.as_ref().filter(|_| !*cond_at_start) {
+                    Some(cond) => self.push(Op::Branch {
+                        cond,
+                        if_zero: *is_until,
+                        target: start,
+                    }),
+                    None => self.push(Op::Jump(start)),
+                };
+                if let Some(branch) = branch {
+                    self.patch(branch);
+                }
+            }
+            StmtKind::SelectCase { expr, cases } => {
+                let slot = self.new_slot();
+                self.push(Op::Select { expr, slot });
+                let mut ends = Vec::new();
+                for (value, body) in cases {
+                    let case = value.as_ref().map(|value| {
+                        self.push(Op::Case {
+                            value,
+                            slot,
+                            next: 0,
+                        })
+                    });
+                    self.lower(body);
+                    ends.push(self.push(Op::Jump(0)));
+                    if let Some(case) = case {
+                        self.patch(case);
+                    }
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            StmtKind::Goto(target) => self.push_jump(target, Op::Goto),
+            StmtKind::Gosub(target) => self.push_jump(target, Op::Gosub),
+            StmtKind::Return => {
+                self.push(Op::Return);
+            }
+            StmtKind::OnGoto { expr, targets } => {
+                let targets = targets.iter().map(line_of).collect();
+                self.push(Op::OnGoto { expr, targets });
+            }
+            StmtKind::End | StmtKind::Stop => {
+                self.push(Op::End);
+            }
+            StmtKind::Shared(names) => self.shared.extend(names),
+            StmtKind::Sub { .. } | StmtKind::Function { .. } => {
+                // Lowered on their own (see Interpreter::new)
+            }
+            _ => {
+                self.push(Op::Stmt(stmt));
+            }
+        }
+    }
+
+    fn push_jump(&mut self, target: &GotoTarget, op: fn(u32) -> Op<'a>) {
+        self.push(op(line_of(target)));
+    }
+}
+
+/// Line number of a jump target (the checker rejects named labels)
+fn line_of(target: &GotoTarget) -> u32 {
+    match target {
+        GotoTarget::Line(n) => *n,
+        GotoTarget::Label(name) => unreachable!("label {} is checked before running", name),
+    }
+}
+
+/// A SUB or FUNCTION
+struct Proc<'a> {
+    params: &'a [Param],
+    code: Code<'a>,
+    is_function: bool,
+}
+
+// ============================================================================
+// Run-time state
+// ============================================================================
+
+#[derive(Debug)]
+struct Array {
+    dims: Vec<usize>, // elements in each dimension
+    data: Vec<Value>,
+}
+
+/// FOR loop and SELECT CASE temporaries
+#[derive(Clone, Debug)]
+enum Slot {
+    Empty,
+    /// FOR with an INTEGER or LONG variable and a constant STEP
+    IntFor {
+        end: i32,
+        step: i32,
+    },
+    /// Any other FOR, run in DOUBLE
+    FloatFor {
+        end: f64,
+        step: f64,
+    },
+    Selected(Value),
+}
+
+/// Variables of the main program or of one procedure call
+#[derive(Default)]
+struct Frame {
+    vars: HashMap<String, Value>,
+    arrays: HashMap<String, Rc<RefCell<Array>>>,
+    shared: HashSet<String>,        // scalars that are the main program's
+    shared_arrays: HashSet<String>, // arrays that are the main program's
+    slots: Vec<Slot>,
+}
+
+impl Frame {
+    fn new(slots: usize) -> Self {
+        Frame {
+            slots: vec![Slot::Empty; slots],
+            ..Frame::default()
+        }
+    }
+}
+
+/// An open file
+enum Handle {
+    Input(BufReader<File>),
+    Output(BufWriter<File>),
+    /// SCRN:, KYBD: or CONS:, which read and write the console
+    Console,
+    /// STDERR:
+    Stderr,
+    /// PIPE: for INPUT, reading the command's output
+    PipeIn(Child, BufReader<ChildStdout>),
+    /// PIPE: for OUTPUT or APPEND, feeding the command's input
+    PipeOut(Child, BufWriter<ChildStdin>),
+    /// TCP: or TCPL:, a connection that reads and writes
+    Tcp(BufReader<TcpStream>),
+}
+
+/// Why a program stopped before running off its end
+enum Stop {
+    End,
+    Error(String),
+}
+
+type Exec<T> = Result<T, Stop>;
+
+struct Interpreter<'a> {
+    main: Rc<Code<'a>>,
+    procs: HashMap<&'a str, Rc<Proc<'a>>>,
+    dim_shared: Vec<Param>,
+    data: Vec<&'a Literal>,
+    data_lines: HashMap<u32, usize>, // line -> first DATA item at or after it
+    data_ptr: usize,
+    frames: Vec<Frame>, // the main program's, then one per active call
+    gosubs: Vec<(usize, Option<u32>)>, // return op and line
+    line: Option<u32>,  // last line number reached, for error messages
+    overflow_check: bool,
+    dialect: Dialect, // gives unsuffixed names their type
+    rng_state: u64,
+    cols: [usize; CHANNELS],
+    widths: [i64; CHANNELS],
+    written: [bool; CHANNELS], // opened FOR OUTPUT or APPEND, so INPUT # can't read it
+    screen: TextScreen,
+    files: Vec<Option<Handle>>,
+    file_lines: Vec<Option<(Vec<u8>, usize, usize)>>, // INPUT # line, next field, bytes read
+    dicts: Vec<Dict>,                                 // DICTNEW dictionaries, handle 1 first
+    input: Box<dyn BufRead + 'a>,
+    output: Box<dyn Write + 'a>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Prepare a checked program, written in `dialect`, to run with the
+    /// given console. With `overflow_check`, INTEGER and LONG results out
+    /// of range stop it.
+    fn new(
+        program: &'a Program,
+        overflow_check: bool,
+        dialect: Dialect,
+        input: Box<dyn BufRead + 'a>,
+        output: Box<dyn Write + 'a>,
+    ) -> Self {
+        let main = Rc::new(Code::new(&program.statements));
+        let mut procs = HashMap::new();
+        for stmt in &program.statements {
+            let (name, params, body, is_function) = match &stmt.kind {
+                StmtKind::Sub { name, params, body } => (name, params, body, false),
+                StmtKind::Function { name, params, body } => (name, params, body, true),
+                _ => continue,
+            };
+            let proc = Proc {
+                params,
+                code: Code::new(body),
+                is_function,
+            };
+            procs.insert(name.as_str(), Rc::new(proc));
+        }
+        let mut widths = [WIDTH_INFINITE; CHANNELS];
+        widths[0] = 80;
+        let mut interp = Interpreter {
+            frames: vec![Frame::new(main.slots)],
+            main,
+            procs,
+            dim_shared: Vec::new(),
+            data: Vec::new(),
+            data_lines: HashMap::new(),
+            data_ptr: 0,
+            gosubs: Vec::new(),
+            line: None,
+            overflow_check,
+            dialect,
+            rng_state: RNG_SEED,
+            cols: [0; CHANNELS],
+            widths,
+            screen: TextScreen::new(),
+            files: (0..CHANNELS).map(|_| None).collect(),
+            file_lines: vec![None; CHANNELS],
+            written: [false; CHANNELS],
+            dicts: Vec::new(),
+            input,
+            output,
+        };
+        for stmt in &program.statements {
+            interp.preprocess(stmt);
+        }
+        interp
+    }
+
+    /// Collect DATA items and DIM SHARED names, as codegen does
+    fn preprocess(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Data(values) => self.data.extend(values),
+            StmtKind::Label(n) => {
+                // RESTORE n continues from the first DATA at or after line n
+                self.data_lines.entry(*n).or_insert(self.data.len());
+            }
+            StmtKind::Dim {
+                arrays,
+                shared: true,
+            } => {
+                self.dim_shared.extend(arrays.iter().map(|arr| Param {
+                    name: arr.name.clone(),
+                    data_type: DataType::of_name(&arr.name, self.dialect),
+                    is_array: !arr.dimensions.is_empty(),
+                }));
+            }
+            _ => {}
+        }
+        for body in stmt.kind.bodies() {
+            for s in body {
+                self.preprocess(s);
+            }
+        }
+    }
+
+    /// Start with another run's main-program variables
+    fn set_globals(&mut self, mut globals: Frame) {
+        globals.slots = vec![Slot::Empty; self.main.slots];
+        self.frames[0] = globals;
+    }
+
+    /// Run the program to its end (or END), then flush its output
+    fn run(&mut self) -> Result<(), String> {
+        let main = Rc::clone(&self.main);
+        let result = self.exec(&main);
+        let _ = self.output.flush();
+        match result {
+            Ok(()) | Err(Stop::End) => Ok(()),
+            Err(Stop::Error(message)) => Err(message),
+        }
+    }
+
+    /// A runtime error, with the last line number reached
+    fn fail(&self, message: &str) -> Stop {
+        Stop::Error(match self.line {
+            Some(n) => format!("{} in {}", message, n),
+            None => message.to_string(),
+        })
+    }
+
+    fn overflow(&self) -> Stop {
+        self.fail("Overflow")
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("main frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("main frame")
+    }
+
+    // ------------------------------------------------------------------
+    // Control flow
+    // ------------------------------------------------------------------
+
+    /// Run flattened code. GOSUBs made here can't be RETURNed from by the
+    /// caller's code, and any left over are dropped at the end.
+    fn exec(&mut self, code: &Code<'a>) -> Exec<()> {
+        let base = self.gosubs.len();
+        let result = self.exec_ops(code, base);
+        self.gosubs.truncate(base);
+        result
+    }
+
+    fn exec_ops(&mut self, code: &Code<'a>, gosub_base: usize) -> Exec<()> {
+        let mut pc = 0;
+        while let Some(op) = code.ops.get(pc) {
+            pc += 1;
+            match op {
+                Op::Stmt(stmt) => self.stmt(stmt)?,
+                Op::Line(n) => self.line = Some(*n),
+                Op::Jump(target) => pc = *target,
+                Op::Branch {
+                    cond,
+                    if_zero,
+                    target,
+                } => {
+                    if self.eval(cond)?.is_zero() == *if_zero {
+                        pc = *target;
+                    }
+                }
+                Op::Goto(n) => pc = code.lines[n],
+                Op::Gosub(n) => {
+                    if self.gosubs.len() >= GOSUB_DEPTH {
+                        return Err(self.fail("GOSUB stack overflow"));
+                    }
+                    self.gosubs.push((pc, self.line));
+                    pc = code.lines[n];
+                }
+                Op::Return => {
+                    if self.gosubs.len() <= gosub_base {
+                        return Err(self.fail("RETURN without GOSUB"));
+                    }
+                    let (ret, line) = self.gosubs.pop().expect("GOSUB entry");
+                    pc = ret;
+                    self.line = line;
+                }
+                Op::OnGoto { expr, targets } => {
+                    // Out of range falls through to the next statement
+                    let n = self.eval_int(expr)?;
+                    if n >= 1 && n <= targets.len() as i64 {
+                        pc = code.lines[&targets[n as usize - 1]];
+                    }
+                }
+                Op::ForInit {
+                    var,
+                    start,
+                    end,
+                    step,
+                    slot,
+                } => self.for_init(var, start, end, *step, *slot)?,
+                Op::ForTest { var, slot, exit } => {
+                    if self.for_done(var, *slot) {
+                        pc = *exit;
+                    }
+                }
+                Op::ForNext {
+                    var,
+                    slot,
+                    test,
+                    body,
+                } => {
+                    if let Some(target) = self.for_next(var, *slot, *test, *body)? {
+                        pc = target;
+                    }
+                }
+                Op::Select { expr, slot } => {
+                    let value = self.eval(expr)?;
+                    self.frame_mut().slots[*slot] = Slot::Selected(value);
+                }
+                Op::Case { value, slot, next } => {
+                    let Slot::Selected(selected) = self.frame().slots[*slot].clone() else {
+                        unreachable!("SELECT CASE evaluated first");
+                    };
+                    let value = self.eval(value)?;
+                    let matched = match (&selected, &value) {
+                        (Value::Str(a), Value::Str(b)) => a == b,
+                        // Compared as DOUBLEs
+                        _ => compare(BinaryOp::Eq, selected.to_f64().partial_cmp(&value.to_f64())),
+                    };
+                    if !matched {
+                        pc = *next;
+                    }
+                }
+                Op::End => return Err(Stop::End),
+            }
+        }
+        Ok(())
+    }
+
+    /// Start a FOR loop. An INTEGER or LONG variable with a constant STEP
+    /// counts in 32 bits; any other loop runs in DOUBLE.
+    fn for_init(
+        &mut self,
+        var: &str,
+        start: &Expr,
+        end: &Expr,
+        step: Option<&Expr>,
+        slot: usize,
+    ) -> Exec<()> {
+        let int_step = step.map_or(Some(1), const_int);
+        let value = self.eval(start)?;
+        self.store_var(var, value)?;
+        let state = match int_step {
+            Some(step) if self.var_type(var).is_integer() => {
+                let end = self.eval(end)?;
+                let end = self.convert(end, DataType::Long)?.to_i32();
+                Slot::IntFor { end, step }
+            }
+            _ => {
+                let end = self.eval(end)?;
+                let end = self.convert(end, DataType::Double)?.to_f64();
+                let step = match step {
+                    Some(step) => {
+                        let value = self.eval(step)?;
+                        self.convert(value, DataType::Double)?.to_f64()
+                    }
+                    None => 1.0,
+                };
+                Slot::FloatFor { end, step }
+            }
+        };
+        self.frame_mut().slots[slot] = state;
+        Ok(())
+    }
+
+    /// Whether a FOR variable has passed the loop's end
+    fn for_done(&mut self, var: &str, slot: usize) -> bool {
+        let value = self.load_var(var);
+        match self.frame().slots[slot] {
+            Slot::IntFor { end, step } => {
+                let n = value.to_i32();
+                if step < 0 { n < end } else { n > end }
+            }
+            Slot::FloatFor { end, step } => {
+                let ord = value.to_f64().partial_cmp(&end);
+                // A NaN STEP counts as negative, as with ucomisd
+                if compare(BinaryOp::Lt, step.partial_cmp(&0.0)) {
+                    compare(BinaryOp::Lt, ord)
+                } else {
+                    compare(BinaryOp::Gt, ord)
+                }
+            }
+            _ => unreachable!("FOR started first"),
+        }
+    }
+
+    /// NEXT: step the variable. Returns where to go on, or `None` to leave
+    /// an integer loop, which tests the sum here rather than at the top.
+    fn for_next(
+        &mut self,
+        var: &str,
+        slot: usize,
+        test: usize,
+        body: usize,
+    ) -> Exec<Option<usize>> {
+        let value = self.load_var(var);
+        match self.frame().slots[slot] {
+            Slot::IntFor { end, step } => {
+                // The sum is compared before an INTEGER is cut to 16 bits
+                let n = value.to_i32();
+                let sum = n.wrapping_add(step);
+                if self.overflow_check {
+                    let overflowed = match value.data_type() {
+                        DataType::Integer => !fits_integer(sum),
+                        _ => n.checked_add(step).is_none(),
+                    };
+                    if overflowed {
+                        return Err(self.overflow());
+                    }
+                }
+                self.store_var(var, int_value(value.data_type(), sum))?;
+                let repeat = if step < 0 { sum >= end } else { sum <= end };
+                Ok(repeat.then_some(body))
+            }
+            Slot::FloatFor { step, .. } => {
+                self.store_var(var, Value::Double(value.to_f64() + step))?;
+                Ok(Some(test))
+            }
+            _ => unreachable!("FOR started first"),
+        }
+    }
+
+    /// Run a statement with no control flow of its own
+    fn stmt(&mut self, stmt: &Stmt) -> Exec<()> {
+        match &stmt.kind {
+            StmtKind::Let {
+                name,
+                indices,
+                value,
+            } => match indices {
+                Some(indices) => {
+                    let indices = self.eval_indices(indices)?;
+                    let value = self.eval(value)?;
+                    self.array_set(name, &indices, value)?;
+                }
+                None => {
+                    let value = self.eval(value)?;
+                    self.store_var(name, value)?;
+                }
+            },
+            StmtKind::Print { items, newline } => self.print(0, items, *newline)?,
+            StmtKind::PrintFile {
+                file_num,
+                items,
+                newline,
+            } => {
+                let ch = self.open_channel(*file_num as i64)?;
+                self.print(ch, items, *newline)?;
+            }
+            StmtKind::PrintUsing {
+                file_num,
+                format,
+                items,
+                newline,
+            } => {
+                let ch = match file_num {
+                    Some(n) => self.open_channel(*n as i64)?,
+                    None => 0,
+                };
+                self.print_using(ch, format, items, *newline)?;
+            }
+            StmtKind::Write { file_num, items } => {
+                let ch = match file_num {
+                    Some(n) => self.open_channel(*n as i64)?,
+                    None => 0,
+                };
+                self.write(ch, items)?;
+            }
+            StmtKind::Input {
+                prompt,
+                question,
+                vars,
+            } => self.input(prompt.as_deref(), *question, vars)?,
+            StmtKind::LineInput {
+                file_num: Some(file_num),
+                var,
+                ..
+            } => {
+                let line = self.file_line(*file_num as i64)?;
+                self.assign(var, |_| Ok(Value::Str(line)))?;
+            }
+            StmtKind::LineInput {
+                file_num: None,
+                prompt,
+                var,
+            } => {
+                if let Some(prompt) = prompt {
+                    self.out_write(0, prompt.as_bytes());
+                }
+                let (line, _) = self.read_console_line();
+                self.assign(var, |_| Ok(Value::Str(line)))?;
+            }
+            StmtKind::InputFile { file_num, vars } => {
+                for var in vars {
+                    let is_string = self.is_string_target(var);
+                    self.assign(var, |interp| {
+                        let field = interp.file_field(*file_num as i64)?;
+                        Ok(if is_string {
+                            Value::Str(field)
+                        } else {
+                            Value::Double(strtod(&field).0)
+                        })
+                    })?;
+                }
+            }
+            StmtKind::Dim { arrays, .. } => {
+                for arr in arrays.iter().filter(|arr| !arr.dimensions.is_empty()) {
+                    self.dim(&arr.name, &arr.dimensions)?;
+                }
+            }
+            StmtKind::Call { name, args } => {
+                self.call(name, args)?;
+            }
+            StmtKind::Read(vars) => {
+                for var in vars {
+                    let is_string = self.is_string_target(var);
+                    self.assign(var, |interp| interp.read_data(is_string))?;
+                }
+            }
+            StmtKind::Restore(target) => {
+                self.data_ptr = match target {
+                    Some(target) => self.data_lines.get(&line_of(target)).copied().unwrap_or(0),
+                    None => 0,
+                };
+            }
+            StmtKind::Cls => {
+                let _ = self.output.write_all(b"\x1b[2J\x1b[H");
+                self.cols[0] = 0;
+                self.screen = TextScreen::new();
+            }
+            StmtKind::Width { file_num, width } => {
+                let ch = match file_num {
+                    Some(n) => self.open_channel(*n as i64)?,
+                    None => 0,
+                };
+                let width = self.eval_rounded(width)?;
+                if !(1..=WIDTH_INFINITE).contains(&width) {
+                    return Err(self.fail("Illegal function call"));
+                }
+                self.widths[ch] = width;
+            }
+            StmtKind::Open {
+                filename,
+                mode,
+                file_num,
+            } => {
+                let filename = self.eval(filename)?.into_bytes();
+                let ch = self.channel(*file_num as i64)?;
+                // Device names go one way but CONS:, and only TCP
+                // connections go both ways (with no FOR)
+                let upper = filename.to_ascii_uppercase();
+                let output = !matches!(mode, FileMode::Input);
+                let tcp = upper.starts_with(b"TCP:") || upper.starts_with(b"TCPL:");
+                let bad_mode = match (upper.as_slice(), mode) {
+                    (_, FileMode::Both) => !tcp,
+                    (b"SCRN:" | b"STDERR:", _) => !output,
+                    (b"KYBD:", _) => output,
+                    _ => false,
+                };
+                if bad_mode {
+                    return Err(self.fail("Bad file mode"));
+                }
+                if self.files[ch].is_some() {
+                    return Err(self.fail("File already open"));
+                }
+                let path = String::from_utf8_lossy(&filename).into_owned();
+                self.files[ch] = match upper.as_slice() {
+                    b"SCRN:" | b"KYBD:" | b"CONS:" => Some(Handle::Console),
+                    b"STDERR:" => Some(Handle::Stderr),
+                    name if name.starts_with(b"PIPE:") => self.pipe(&path[5..], output),
+                    name if name.starts_with(b"TCP:") => tcp_connect(&path[4..]),
+                    name if name.starts_with(b"TCPL:") => tcp_listen(&path[5..]),
+                    _ => match mode {
+                        FileMode::Input => match File::open(path) {
+                            Ok(f) => Some(Handle::Input(BufReader::new(f))),
+                            Err(_) => return Err(self.fail("File not found")),
+                        },
+                        FileMode::Output => File::create(path)
+                            .ok()
+                            .map(|f| Handle::Output(BufWriter::new(f))),
+                        // At its end from the start, so SEEK() counts from there
+                        FileMode::Append => OpenOptions::new()
+                            .append(true)
+                            .create(true)
+                            .open(path)
+                            .ok()
+                            .map(|mut f| {
+                                let _ = f.seek(SeekFrom::End(0));
+                                Handle::Output(BufWriter::new(f))
+                            }),
+                        FileMode::Both => None, // refused above
+                    },
+                };
+                self.written[ch] = matches!(mode, FileMode::Output | FileMode::Append) && !tcp;
+                self.cols[ch] = 0;
+                self.widths[ch] = WIDTH_INFINITE;
+            }
+            StmtKind::Close { file_num } => {
+                let ch = self.open_channel(*file_num as i64)?;
+                self.close_channel(ch);
+            }
+            StmtKind::Flush { file_num } => {
+                let ch = self.open_channel(*file_num as i64)?;
+                let _ = match &mut self.files[ch] {
+                    Some(Handle::Output(file)) => file.flush(),
+                    Some(Handle::PipeOut(_, stdin)) => stdin.flush(),
+                    Some(Handle::Console) => self.output.flush(),
+                    _ => Ok(()),
+                };
+            }
+            StmtKind::Seek { file_num, position } => {
+                let ch = self.open_channel(*file_num as i64)?;
+                let position = self.eval_rounded(position)?;
+                if position < 1 {
+                    return Err(self.fail("Illegal function call"));
+                }
+                let to = SeekFrom::Start(position as u64 - 1);
+                let moved = match &mut self.files[ch] {
+                    Some(Handle::Input(file)) => file.seek(to).is_ok(),
+                    Some(Handle::Output(file)) => file.seek(to).is_ok(),
+                    _ => false,
+                };
+                if !moved {
+                    return Err(self.fail("Bad file mode"));
+                }
+                self.file_lines[ch] = None;
+            }
+            StmtKind::DictSet { dict, key, value } => {
+                let key = self.eval_bytes(key)?;
+                let value = match value {
+                    Some(value) => Some(self.eval_bytes(value)?),
+                    None => None,
+                };
+                let dict = self.dict(dict)?;
+                match value {
+                    Some(value) => self.dicts[dict].set(key, value),
+                    None => self.dicts[dict].delete(&key),
+                }
+            }
+            StmtKind::Data(_)
+            | StmtKind::Declare { .. }
+            | StmtKind::OptionExplicit
+            | StmtKind::Const(_)
+            | StmtKind::KeyDisplay(_) => {}
+            kind => unreachable!("{:?} is lowered or checked before running", kind),
+        }
+        Ok(())
+    }
+
+    // ------------------------------------------------------------------
+    // Variables and arrays
+    // ------------------------------------------------------------------
+
+    /// Frame holding a scalar: the main program's if the name is shared
+    fn var_frame(&self, name: &str) -> usize {
+        if self.frame().shared.contains(name) {
+            0
+        } else {
+            self.frames.len() - 1
+        }
+    }
+
+    /// Frame holding an array
+    fn array_frame(&self, name: &str) -> usize {
+        if self.frame().shared_arrays.contains(name) {
+            0
+        } else {
+            self.frames.len() - 1
+        }
+    }
+
+    /// Type of a scalar: a parameter's declared type, else its name's
+    fn var_type(&self, name: &str) -> DataType {
+        match self.frames[self.var_frame(name)].vars.get(name) {
+            Some(value) => value.data_type(),
+            None => DataType::of_name(name, self.dialect),
+        }
+    }
+
+    fn load_var(&self, name: &str) -> Value {
+        match self.frames[self.var_frame(name)].vars.get(name) {
+            Some(value) => value.clone(),
+            None => Value::zero(DataType::of_name(name, self.dialect)),
+        }
+    }
+
+    fn store_var(&mut self, name: &str, value: Value) -> Exec<()> {
+        let value = self.convert(value, self.var_type(name))?.stored();
+        let frame = self.var_frame(name);
+        self.frames[frame].vars.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    fn array(&self, name: &str) -> Option<Rc<RefCell<Array>>> {
+        self.frames[self.array_frame(name)]
+            .arrays
+            .get(name)
+            .cloned()
+    }
+
+    /// DIM an array: bounds are the last index of each dimension, and a
+    /// bound of -1 gives an empty one
+    fn dim(&mut self, name: &str, dimensions: &[Expr]) -> Exec<()> {
+        let mut dims = Vec::with_capacity(dimensions.len());
+        let mut total: i64 = 1;
+        for dim in dimensions {
+            let count = self.eval_int(dim)?.saturating_add(1);
+            if count < 0 {
+                return Err(self.fail("Illegal function call"));
+            }
+            total = total.saturating_mul(count);
+            dims.push(count as usize);
+        }
+        if total > MAX_ELEMENTS {
+            return Err(self.fail("Out of memory"));
+        }
+        let zero = Value::zero(DataType::of_name(name, self.dialect));
+        let array = Array {
+            data: vec![zero; total as usize],
+            dims,
+        };
+        // A parameter or shared array is redimensioned where it lives
+        match self.array(name) {
+            Some(existing) => *existing.borrow_mut() = array,
+            None => {
+                let frame = self.frame_mut();
+                frame
+                    .arrays
+                    .insert(name.to_string(), Rc::new(RefCell::new(array)));
+            }
+        }
+        Ok(())
+    }
+
+    fn eval_indices(&mut self, indices: &[Expr]) -> Exec<Vec<i64>> {
+        indices.iter().map(|index| self.eval_int(index)).collect()
+    }
+
+    /// Position of an element in an array's data
+    fn element(&self, array: &Array, indices: &[i64]) -> Exec<usize> {
+        if indices.len() != array.dims.len() {
+            return Err(self.fail("Subscript out of range"));
+        }
+        let mut offset = 0;
+        for (&index, &count) in indices.iter().zip(&array.dims) {
+            if index as u64 >= count as u64 {
+                return Err(self.fail("Subscript out of range"));
+            }
+            offset = offset * count + index as usize;
+        }
+        Ok(offset)
+    }
+
+    fn array_get(&mut self, name: &str, indices: &[Expr]) -> Exec<Value> {
+        let indices = self.eval_indices(indices)?;
+        let array = self
+            .array(name)
+            .ok_or_else(|| self.fail("Subscript out of range"))?;
+        let array = array.borrow();
+        let at = self.element(&array, &indices)?;
+        Ok(array.data[at].clone())
+    }
+
+    fn array_set(&mut self, name: &str, indices: &[i64], value: Value) -> Exec<()> {
+        let value = self
+            .convert(value, DataType::of_name(name, self.dialect))?
+            .stored();
+        let array = self
+            .array(name)
+            .ok_or_else(|| self.fail("Subscript out of range"))?;
+        let mut array = array.borrow_mut();
+        let at = self.element(&array, indices)?;
+        array.data[at] = value;
+        Ok(())
+    }
+
+    /// Store into a variable or array element. For an element the indices
+    /// are found before the value, as in compiled code.
+    fn assign(&mut self, target: &Expr, value: impl FnOnce(&mut Self) -> Exec<Value>) -> Exec<()> {
+        match &target.kind {
+            ExprKind::Variable(name) => {
+                let value = value(self)?;
+                self.store_var(name, value)
+            }
+            ExprKind::ArrayAccess { name, indices }
+            | ExprKind::FnCall {
+                name,
+                args: indices,
+            } => {
+                let indices = self.eval_indices(indices)?;
+                let value = value(self)?;
+                self.array_set(name, &indices, value)
+            }
+            _ => unreachable!("assignment targets are checked before running"),
+        }
+    }
+
+    fn is_string_target(&self, target: &Expr) -> bool {
+        let data_type = match &target.kind {
+            ExprKind::Variable(name) => self.var_type(name),
+            ExprKind::ArrayAccess { name, .. } | ExprKind::FnCall { name, .. } => {
+                DataType::of_name(name, self.dialect)
+            }
+            _ => unreachable!("assignment targets are checked before running"),
+        };
+        data_type == DataType::String
+    }
+
+    // ------------------------------------------------------------------
+    // Procedures
+    // ------------------------------------------------------------------
+
+    /// Call a SUB or FUNCTION; returns a FUNCTION's result
+    fn call(&mut self, name: &str, args: &[Expr]) -> Exec<Option<Value>> {
+        let proc = Rc::clone(&self.procs[name]);
+        let mut frame = Frame::new(proc.code.slots);
+        for (param, arg) in proc.params.iter().zip(args) {
+            if param.is_array {
+                // Passed by reference; an array never DIM'd is made empty so
+                // a DIM in the procedure reaches the caller
+                let array_name = crate::types::array_name(arg).expect("array argument");
+                let array = match self.array(array_name) {
+                    Some(array) => array,
+                    None => {
+                        let array = Rc::new(RefCell::new(Array {
+                            dims: Vec::new(),
+                            data: Vec::new(),
+                        }));
+                        let at = self.array_frame(array_name);
+                        self.frames[at]
+                            .arrays
+                            .insert(array_name.to_string(), Rc::clone(&array));
+                        array
+                    }
+                };
+                frame.arrays.insert(param.name.clone(), array);
+            } else {
+                let value = self.eval(arg)?;
+                let value = self.convert(value, param.data_type)?.stored();
+                frame.vars.insert(param.name.clone(), value);
+            }
+        }
+        let params: HashSet<&str> = proc.params.iter().map(|p| p.name.as_str()).collect();
+        let shared = self
+            .dim_shared
+            .iter()
+            .chain(proc.code.shared.iter().copied());
+        for shared in shared.filter(|p| !params.contains(p.name.as_str())) {
+            let names = if shared.is_array {
+                &mut frame.shared_arrays
+            } else {
+                &mut frame.shared
+            };
+            names.insert(shared.name.clone());
+        }
+        if proc.is_function {
+            frame.vars.insert(
+                name.to_string(),
+                Value::zero(DataType::of_name(name, self.dialect)),
+            );
+        }
+
+        let line = self.line;
+        self.frames.push(frame);
+        let result = self.exec(&proc.code);
+        let frame = self.frames.pop().expect("procedure frame");
+        result?;
+        self.line = line;
+        Ok(proc.is_function.then(|| frame.vars[name].clone()))
+    }
+
+    // ------------------------------------------------------------------
+    // Expressions
+    // ------------------------------------------------------------------
+
+    fn eval(&mut self, expr: &Expr) -> Exec<Value> {
+        match &expr.kind {
+            ExprKind::Literal(lit) => Ok(literal_value(lit)),
+            ExprKind::Variable(name) => Ok(self.load_var(name)),
+            ExprKind::ArrayAccess { name, indices } => self.array_get(name, indices),
+            ExprKind::FnCall { name, args } => {
+                if let Some(value) = self.builtin(name, args)? {
+                    Ok(value)
+                } else if self.array(name).is_none() && self.procs.contains_key(name.as_str()) {
+                    let value = self.call(name, args)?;
+                    Ok(value.expect("FUNCTION result"))
+                } else {
+                    self.array_get(name, args)
+                }
+            }
+            ExprKind::Unary { op, operand } => {
+                let value = self.eval(operand)?;
+                match op {
+                    UnaryOp::Neg => self.negate(value),
+                    UnaryOp::Not => Ok(bool_value(value.is_zero())),
+                }
+            }
+            ExprKind::Binary {
+                op: BinaryOp::AndAlso,
+                left,
+                right,
+            } => Ok(bool_value(
+                !self.eval(left)?.is_zero() && !self.eval(right)?.is_zero(),
+            )),
+            ExprKind::Binary {
+                op: BinaryOp::OrElse,
+                left,
+                right,
+            } => Ok(bool_value(
+                !self.eval(left)?.is_zero() || !self.eval(right)?.is_zero(),
+            )),
+            ExprKind::Binary { op, left, right } => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                self.binary(*op, left, right)
+            }
+        }
+    }
+
+    /// An integer argument or index: integers as they are, others truncated
+    fn eval_int(&mut self, expr: &Expr) -> Exec<i64> {
+        Ok(match self.eval(expr)? {
+            Value::Integer(n) | Value::Long(n) => n as i64,
+            value => trunc_i64(value.to_f64()),
+        })
+    }
+
+    /// An integer argument rounded to nearest, like the runtime's
+    fn eval_rounded(&mut self, expr: &Expr) -> Exec<i64> {
+        Ok(match self.eval(expr)? {
+            Value::Integer(n) | Value::Long(n) => n as i64,
+            value => round_i64(value.to_f64()),
+        })
+    }
+
+    fn eval_f64(&mut self, expr: &Expr) -> Exec<f64> {
+        Ok(self.eval(expr)?.to_f64())
+    }
+
+    fn eval_bytes(&mut self, expr: &Expr) -> Exec<Vec<u8>> {
+        Ok(self.eval(expr)?.into_bytes())
+    }
+
+    /// Convert a value to another type, checking integer conversions for
+    /// overflow when asked to
+    fn convert(&self, value: Value, to: DataType) -> Exec<Value> {
+        let check = self.overflow_check;
+        Ok(match (value, to) {
+            (value, to) if value.data_type() == to => value,
+            (Value::Integer(n), DataType::Long) => Value::Long(n as i16 as i32),
+            (Value::Long(n), DataType::Integer) => {
+                if check && !fits_integer(n) {
+                    return Err(self.overflow());
+                }
+                Value::Integer(n)
+            }
+            (value, DataType::Single) => Value::Single(value.to_f64() as f32),
+            (value, DataType::Double) => Value::Double(value.to_f64()),
+            (value, to) => {
+                let x = value.to_f64();
+                let n = if check {
+                    let n = trunc_i64(x);
+                    let fits = match to {
+                        DataType::Integer => i16::try_from(n).is_ok(),
+                        _ => i32::try_from(n).is_ok(),
+                    };
+                    if !fits {
+                        return Err(self.overflow());
+                    }
+                    n as i32
+                } else {
+                    trunc_i32(x)
+                };
+                int_value(to, n)
+            }
+        })
+    }
+
+    fn negate(&self, value: Value) -> Exec<Value> {
+        Ok(match value {
+            Value::Integer(n) => {
+                let n = n.wrapping_neg();
+                if self.overflow_check && !fits_integer(n) {
+                    return Err(self.overflow());
+                }
+                Value::Integer(n)
+            }
+            Value::Long(n) => match n.checked_neg() {
+                Some(n) => Value::Long(n),
+                None if self.overflow_check => return Err(self.overflow()),
+                None => Value::Long(n),
+            },
+            Value::Single(x) => Value::Single(-x),
+            Value::Double(x) => Value::Double(-x),
+            Value::Str(_) => unreachable!("strings are checked before running"),
+        })
+    }
+
+    fn binary(&self, op: BinaryOp, left: Value, right: Value) -> Exec<Value> {
+        if let (Value::Str(a), Value::Str(b)) = (&left, &right) {
+            return Ok(match op {
+                BinaryOp::Add => Value::Str([a.as_slice(), b].concat()),
+                _ => bool_value(compare(op, Some(a.cmp(b)))),
+            });
+        }
+        let (lt, rt) = (left.data_type(), right.data_type());
+        let result = promote(op, lt, rt);
+        let work =
+            if is_comparison(op) || matches!(op, BinaryOp::And | BinaryOp::Or | BinaryOp::Xor) {
+                widest(lt, rt)
+            } else {
+                result
+            };
+        // \ and MOD round their operands, as CLNG does, rather than truncate
+        let (left, right) = if matches!(op, BinaryOp::IntDiv | BinaryOp::Mod) {
+            (left.round_even(), right.round_even())
+        } else {
+            (left, right)
+        };
+        let left = self.convert(left, work)?;
+        let right = self.convert(right, work)?;
+
+        if is_comparison(op) {
+            let ord = match (&left, &right) {
+                (Value::Integer(a), Value::Integer(b)) | (Value::Long(a), Value::Long(b)) => {
+                    Some(a.cmp(b))
+                }
+                _ => left.to_f64().partial_cmp(&right.to_f64()),
+            };
+            return Ok(bool_value(compare(op, ord)));
+        }
+        if let BinaryOp::And | BinaryOp::Or | BinaryOp::Xor = op {
+            // Floats are truncated, without an overflow check
+            let int = |value: &Value| match value {
+                Value::Integer(n) | Value::Long(n) => *n,
+                value => trunc_i32(value.to_f64()),
+            };
+            let (a, b) = (int(&left), int(&right));
+            let n = match op {
+                BinaryOp::And => a & b,
+                BinaryOp::Or => a | b,
+                _ => a ^ b,
+            };
+            return Ok(int_value(result, n));
+        }
+
+        match work {
+            DataType::Integer | DataType::Long => {
+                let (a, b) = (left.to_i32(), right.to_i32());
+                let (n, overflowed) = match op {
+                    BinaryOp::Add => a.overflowing_add(b),
+                    BinaryOp::Sub => a.overflowing_sub(b),
+                    BinaryOp::Mul => a.overflowing_mul(b),
+                    BinaryOp::IntDiv | BinaryOp::Mod => {
+                        if b == 0 {
+                            return Err(self.fail("Division by zero"));
+                        }
+                        let n = if op == BinaryOp::IntDiv {
+                            a.wrapping_div(b)
+                        } else {
+                            a.wrapping_rem(b)
+                        };
+                        (n, false)
+                    }
+                    _ => unreachable!("{:?} is not an integer operation", op),
+                };
+                let overflowed = match work {
+                    DataType::Integer => !fits_integer(n),
+                    _ => overflowed,
+                };
+                if self.overflow_check && overflowed {
+                    return Err(self.overflow());
+                }
+                Ok(int_value(work, n))
+            }
+            DataType::Single => {
+                let (a, b) = (left.to_f64() as f32, right.to_f64() as f32);
+                Ok(Value::Single(match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    _ => unreachable!("{:?} is done in DOUBLE", op),
+                }))
+            }
+            _ => {
+                let (a, b) = (left.to_f64(), right.to_f64());
+                Ok(Value::Double(match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => {
+                        if b == 0.0 || b.is_nan() {
+                            return Err(self.fail("Division by zero"));
+                        }
+                        a / b
+                    }
+                    BinaryOp::Pow => a.powf(b),
+                    _ => unreachable!("{:?} is not a DOUBLE operation", op),
+                }))
+            }
+        }
+    }
+
+    /// A built-in function call, or `None` if `name` isn't one
+    fn builtin(&mut self, name: &str, args: &[Expr]) -> Exec<Option<Value>> {
+        let value = match name {
+            "SIN" | "COS" | "TAN" | "ATN" | "EXP" | "LOG" | "LOG10" | "SQR" | "INT" | "FIX"
+            | "ABS" | "SGN" | "CSNG" | "CDBL" => {
+                let arg = self.eval(&args[0])?;
+                let single = matches!(arg, Value::Single(_)) && types::keeps_single(name);
+                let x = arg.to_f64();
+                let result = match name {
+                    "SIN" => x.sin(),
+                    "COS" => x.cos(),
+                    "TAN" => x.tan(),
+                    "ATN" => x.atan(),
+                    "EXP" => x.exp(),
+                    "LOG" => x.ln(),
+                    "LOG10" => x.log10(),
+                    "SQR" => x.sqrt(),
+                    "INT" => x.floor(),
+                    "FIX" => x.trunc(),
+                    "ABS" => x.abs(),
+                    // A NaN compares as below zero
+                    "SGN" if x > 0.0 => 1.0,
+                    "SGN" if x < 0.0 || x.is_nan() => -1.0,
+                    "SGN" => 0.0,
+                    _ => x,
+                };
+                if single {
+                    Value::Single(result as f32)
+                } else {
+                    Value::Double(result)
+                }
+            }
+            "MIN" | "MAX" => {
+                let a = self.eval_f64(&args[0])?;
+                let b = self.eval_f64(&args[1])?;
+                // The second when either is a NaN, as minsd and maxsd do
+                let first = if name == "MIN" { a < b } else { a > b };
+                Value::Double(if first { a } else { b })
+            }
+            "ROUND" => {
+                let x = self.eval_f64(&args[0])?;
+                let digits = match args.get(1) {
+                    Some(digits) => self.eval_rounded(digits)?,
+                    None => 0,
+                };
+                Value::Double(round_to(x, digits))
+            }
+            "CINT" | "CLNG" => match self.eval(&args[0])? {
+                Value::Integer(n) | Value::Long(n) => Value::Long(n),
+                value => Value::Long(trunc_i32(value.to_f64().round_ties_even())),
+            },
+            "RND" => {
+                if let Some(arg) = args.first() {
+                    self.eval(arg)?;
+                }
+                let mut s = self.rng_state;
+                s ^= s << 13;
+                s ^= s >> 7;
+                s ^= s << 17;
+                self.rng_state = s;
+                Value::Double(f64::from_bits((s >> 12) | 0x3FF0_0000_0000_0000) - 1.0)
+            }
+            "TIMER" => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH);
+                let secs = now.map_or(0, |d| d.as_secs());
+                Value::Double((secs % 86400) as f64)
+            }
+            "LEN" => Value::Long(self.eval_bytes(&args[0])?.len() as i32),
+            "ASC" => {
+                let s = self.eval_bytes(&args[0])?;
+                let pos = match args.get(1) {
+                    Some(pos) => self.eval_int(pos)?,
+                    None => 1,
+                };
+                match usize::try_from(pos - 1).ok().and_then(|i| s.get(i)) {
+                    Some(&c) => Value::Long(c as i32),
+                    None => return Err(self.fail("Illegal function call")),
+                }
+            }
+            "VAL" => Value::Double(strtod(&self.eval_bytes(&args[0])?).0),
+            "CHR$" => match u8::try_from(self.eval_int(&args[0])?) {
+                Ok(c) => Value::Str(vec![c]),
+                Err(_) => return Err(self.fail("Illegal function call")),
+            },
+            "STR$" => {
+                let text = match self.eval(&args[0])? {
+                    Value::Single(x) => format_number(x as f64, 7),
+                    value => format_number(value.to_f64(), 0),
+                };
+                // A leading space where a minus sign would go
+                let space = if text.starts_with('-') { "" } else { " " };
+                Value::Str(format!("{}{}", space, text).into_bytes())
+            }
+            "LEFT$" | "RIGHT$" => {
+                let s = self.eval_bytes(&args[0])?;
+                // A negative count, as unsigned, takes the whole string
+                let count = (self.eval_int(&args[1])? as u64).min(s.len() as u64) as usize;
+                Value::Str(match name {
+                    "LEFT$" => s[..count].to_vec(),
+                    _ => s[s.len() - count..].to_vec(),
+                })
+            }
+            "MID$" => {
+                let s = self.eval_bytes(&args[0])?;
+                let start = self.eval_int(&args[1])?;
+                let count = match args.get(2) {
+                    Some(count) => self.eval_int(count)?,
+                    None => -1,
+                };
+                let skip = (start - 1) as u64;
+                Value::Str(if skip >= s.len() as u64 {
+                    Vec::new()
+                } else {
+                    let rest = &s[skip as usize..];
+                    let count = if count < 0 {
+                        rest.len()
+                    } else {
+                        (count as u64).min(rest.len() as u64) as usize
+                    };
+                    rest[..count].to_vec()
+                })
+            }
+            "INSTR" | "INSTRREV" => {
+                let (start, hay, needle) = match args {
+                    [start, hay, needle] => (Some(self.eval_int(start)?), hay, needle),
+                    [hay, needle] => (None, hay, needle),
+                    _ => unreachable!("INSTR arguments are checked before running"),
+                };
+                let hay = self.eval_bytes(hay)?;
+                let needle = self.eval_bytes(needle)?;
+                Value::Long(if name == "INSTR" {
+                    instr(start.unwrap_or(1), &hay, &needle) as i32
+                } else {
+                    let start = start.unwrap_or(hay.len() as i64);
+                    instrrev(start, &hay, &needle) as i32
+                })
+            }
+            "SHL" | "SHR" => {
+                let value = self.eval_rounded(&args[0])? as u64;
+                let count = self.eval_rounded(&args[1])? as u64;
+                let n = match (count > 63, name) {
+                    (true, _) => 0,
+                    (false, "SHL") => value << count,
+                    (false, _) => value >> count,
+                };
+                Value::Double(n as i64 as f64)
+            }
+            "LBOUND" => Value::Long(0),
+            "UBOUND" => {
+                let name = crate::types::array_name(&args[0]).expect("array argument");
+                let dim = match args.get(1) {
+                    Some(dim) => self.eval_rounded(dim)?,
+                    None => 1,
+                };
+                let dims = self.array(name).map(|a| a.borrow().dims.clone());
+                let dims = dims.unwrap_or_default();
+                if dim < 1 || dim > dims.len() as i64 {
+                    return Err(self.fail("Illegal function call"));
+                }
+                Value::Long(dims[dim as usize - 1] as i32 - 1)
+            }
+            "SEEK" => {
+                let file_num = self.eval_rounded(&args[0])?;
+                Value::Long(self.file_position(file_num)? as i32)
+            }
+            "DICTNEW" => {
+                if self.dicts.len() == DICT_MAX - 1 {
+                    return Err(self.fail("Out of memory"));
+                }
+                self.dicts.push(Dict::default());
+                Value::Long(self.dicts.len() as i32)
+            }
+            "DICTGET$" | "DICTHAS" => {
+                let key = self.eval_bytes(&args[1])?;
+                let dict = self.dict(&args[0])?;
+                let dict = &self.dicts[dict];
+                let value = dict.get(&key);
+                match name {
+                    "DICTHAS" => Value::Long(-(value.is_some() as i32)),
+                    _ => Value::Str(value.unwrap_or_default().to_vec()),
+                }
+            }
+            "DICTCOUNT" => {
+                let dict = self.dict(&args[0])?;
+                Value::Long(self.dicts[dict].entries.len() as i32)
+            }
+            "DICTKEY$" => {
+                let n = self.eval_rounded(&args[1])?;
+                let dict = self.dict(&args[0])?;
+                let dict = &self.dicts[dict];
+                match usize::try_from(n - 1)
+                    .ok()
+                    .and_then(|i| dict.entries.get(i))
+                {
+                    Some((key, _)) => Value::Str(key.clone()),
+                    None => return Err(self.fail("Illegal function call")),
+                }
+            }
+            "RGB" => {
+                let mut rgb = 0xFF;
+                for arg in &args[..3] {
+                    rgb = rgb << 8 | self.eval_rounded(arg)?.clamp(0, 255) as u32;
+                }
+                Value::Long(rgb as i32)
+            }
+            "SCREEN" => {
+                let row = self.eval_rounded(&args[0])?;
+                let col = self.eval_rounded(&args[1])?;
+                match self.screen.char_at(row, col) {
+                    Some(c) => Value::Long(c as i32),
+                    None => return Err(self.fail("Illegal function call")),
+                }
+            }
+            "PEEK" | "POINT" | "VARPTR" | "VARSEG" | "MOUSEX" | "MOUSEY" | "MOUSEB" | "STICK"
+            | "STRIG" => {
+                let message = format!("{} is not supported by --run", name);
+                return Err(self.fail(&message));
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(value))
+    }
+
+    // ------------------------------------------------------------------
+    // DATA
+    // ------------------------------------------------------------------
+
+    /// READ the next DATA item
+    fn read_data(&mut self, is_string: bool) -> Exec<Value> {
+        let Some(&item) = self.data.get(self.data_ptr) else {
+            return Err(self.fail("Out of DATA"));
+        };
+        self.data_ptr += 1;
+        Ok(match (item, is_string) {
+            (Literal::String(s), true) => Value::Str(s.as_bytes().to_vec()),
+            (Literal::String(s), false) => Value::Double(strtod(s.as_bytes()).0),
+            (Literal::Integer(n), false) => Value::Double(*n as f64),
+            (Literal::Integer(n), true) => Value::Str(n.to_string().into_bytes()),
+            (Literal::Float(x) | Literal::Typed(x, _), false) => Value::Double(*x),
+            (Literal::Float(x) | Literal::Typed(x, _), true) => {
+                Value::Str(format_number(*x, 6).into_bytes())
+            }
+        })
+    }
+
+    // ------------------------------------------------------------------
+    // Output (print.s)
+    // ------------------------------------------------------------------
+
+    /// Write bytes to a channel with no column tracking. Output to a file
+    /// opened for INPUT is dropped, as in the runtime.
+    fn raw_write(&mut self, ch: usize, bytes: &[u8]) {
+        // Write errors (a closed pipe, a full disk) are ignored like the
+        // runtime ignores them
+        let _ = match ch {
+            0 => self.console_write(bytes),
+            _ => match &mut self.files[ch] {
+                Some(Handle::Output(file)) => file.write_all(bytes),
+                // A command gets each line as it is finished
+                Some(Handle::PipeOut(_, stdin)) => stdin.write_all(bytes).and_then(|()| {
+                    if bytes.contains(&b'\n') {
+                        stdin.flush()
+                    } else {
+                        Ok(())
+                    }
+                }),
+                Some(Handle::Tcp(stream)) => stream.get_mut().write_all(bytes),
+                Some(Handle::Console) => self.console_write(bytes),
+                Some(Handle::Stderr) => std::io::stderr().write_all(bytes),
+                _ => Ok(()),
+            },
+        };
+    }
+
+    /// Write to the console, keeping the text screen up to date
+    fn console_write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.screen.write(bytes);
+        self.output.write_all(bytes)
+    }
+
+    /// End the current line
+    fn newline(&mut self, ch: usize) {
+        self.raw_write(ch, b"\n");
+        self.cols[ch] = 0;
+    }
+
+    /// Write text, starting a new line when the channel's width is reached
+    fn out_write(&mut self, ch: usize, mut bytes: &[u8]) {
+        let width = self.widths[ch];
+        while !bytes.is_empty() {
+            let mut room = usize::MAX;
+            if width != WIDTH_INFINITE {
+                if self.cols[ch] >= width as usize {
+                    self.newline(ch);
+                }
+                room = width as usize - self.cols[ch];
+            }
+            let mut len = bytes.len().min(room);
+            if let Some(newline) = bytes[..len].iter().position(|&b| b == b'\n') {
+                len = newline + 1;
+            }
+            let (chunk, rest) = bytes.split_at(len);
+            self.raw_write(ch, chunk);
+            if chunk.ends_with(b"\n") {
+                self.cols[ch] = 0;
+            } else {
+                self.cols[ch] += len;
+            }
+            bytes = rest;
+        }
+    }
+
+    /// PRINT or PRINT # items
+    fn print(&mut self, ch: usize, items: &[PrintItem], newline: bool) -> Exec<()> {
+        for item in items {
+            match item {
+                PrintItem::Expr(Expr {
+                    kind: ExprKind::FnCall { name, args },
+                    ..
+                }) if args.len() == 1 && (name == "TAB" || name == "SPC") => {
+                    let n = self.eval_rounded(&args[0])?;
+                    if name == "TAB" {
+                        self.print_tab(ch, n);
+                    } else {
+                        self.print_spc(ch, n);
+                    }
+                }
+                PrintItem::Expr(expr) => {
+                    let value = self.eval(expr)?;
+                    self.print_value(ch, value);
+                }
+                PrintItem::Tab => self.print_zone(ch),
+                PrintItem::Empty => {}
+            }
+        }
+        if newline {
+            self.newline(ch);
+        }
+        Ok(())
+    }
+
+    fn print_value(&mut self, ch: usize, value: Value) {
+        let text = match value {
+            Value::Str(s) => return self.out_write(ch, &s),
+            Value::Single(x) => format_number(x as f64, 7),
+            value => format_number(value.to_f64(), 0),
+        };
+        // A number that won't fit goes on the next line
+        let width = self.widths[ch];
+        if width != WIDTH_INFINITE
+            && self.cols[ch] != 0
+            && self.cols[ch] + text.len() > width as usize
+        {
+            self.newline(ch);
+        }
+        self.out_write(ch, text.as_bytes());
+    }
+
+    fn print_using(&mut self, ch: usize, format: &Expr, items: &[Expr], newline: bool) -> Exec<()> {
+        let fmt = self.eval_bytes(format)?;
+        if using_scan(&fmt, 0) == fmt.len() {
+            return Err(self.fail("Illegal function call"));
+        }
+        let mut pos = 0;
+        for item in items {
+            let value = self.eval(item)?;
+            // The text up to the next field, starting over once they run out
+            let mut end = using_scan(&fmt, pos);
+            self.out_write(ch, &using_text(&fmt, pos, end));
+            if end == fmt.len() {
+                end = using_scan(&fmt, 0);
+                self.out_write(ch, &using_text(&fmt, 0, end));
+            }
+            let (field, width) = using_field(&fmt, end).expect("a field");
+            pos = end + width;
+            let text = match (field, value) {
+                (UsingField::String(count), Value::Str(s)) => {
+                    let count = if count == 0 { s.len() } else { count };
+                    let mut text = s[..s.len().min(count)].to_vec();
+                    text.resize(count, b' ');
+                    text
+                }
+                (UsingField::Number(field), value) if !matches!(value, Value::Str(_)) => {
+                    using_number(&field, width, value.to_f64())
+                }
+                _ => return Err(self.fail("Type mismatch")),
+            };
+            self.out_write(ch, &text);
+        }
+        let end = using_scan(&fmt, pos);
+        self.out_write(ch, &using_text(&fmt, pos, end));
+        if newline {
+            self.newline(ch);
+        }
+        Ok(())
+    }
+
+    /// WRITE: items separated by commas, strings in quotes
+    fn write(&mut self, ch: usize, items: &[Expr]) -> Exec<()> {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.out_write(ch, b",");
+            }
+            match self.eval(item)? {
+                Value::Str(s) => {
+                    self.out_write(ch, b"\"");
+                    self.out_write(ch, &s);
+                    self.out_write(ch, b"\"");
+                }
+                value => self.print_value(ch, value),
+            }
+        }
+        self.newline(ch);
+        Ok(())
+    }
+
+    /// Comma in PRINT: move to the next print zone, or the next line if
+    /// there's no room for another zone
+    fn print_zone(&mut self, ch: usize) {
+        let next = (self.cols[ch] / ZONE_WIDTH + 1) * ZONE_WIDTH;
+        let width = self.widths[ch];
+        if width != WIDTH_INFINITE && (next + ZONE_WIDTH) as i64 > width {
+            self.newline(ch);
+        } else {
+            let pad = next - self.cols[ch];
+            self.out_write(ch, &b" ".repeat(pad));
+        }
+    }
+
+    /// TAB(n): move to column n (1-based), on the next line if it's passed
+    fn print_tab(&mut self, ch: usize, n: i64) {
+        let width = self.widths[ch];
+        let mut target = (n - 1).max(0);
+        if width != WIDTH_INFINITE {
+            target %= width;
+        }
+        let target = target as usize;
+        if self.cols[ch] > target {
+            self.newline(ch);
+        }
+        let pad = target - self.cols[ch];
+        self.out_write(ch, &b" ".repeat(pad));
+    }
+
+    /// SPC(n): write n spaces
+    fn print_spc(&mut self, ch: usize, n: i64) {
+        if n <= 0 {
+            return;
+        }
+        let width = self.widths[ch];
+        let n = if width != WIDTH_INFINITE {
+            n % width
+        } else {
+            n
+        };
+        self.out_write(ch, &b" ".repeat(n as usize));
+    }
+
+    // ------------------------------------------------------------------
+    // Console input (input.s)
+    // ------------------------------------------------------------------
+
+    /// Read a console line of up to INPUT_MAX bytes, like
+    /// scanf("%1023[^\n]") and the getchar after it. Also returns whether
+    /// input had already run out.
+    fn read_console_line(&mut self) -> (Vec<u8>, bool) {
+        let _ = self.output.flush();
+        let mut line = Vec::new();
+        let mut eof = false;
+        loop {
+            let next = match self.input.fill_buf() {
+                Ok(buf) => buf.first().copied(),
+                Err(_) => None,
+            };
+            match next {
+                None => {
+                    eof = line.is_empty();
+                    break;
+                }
+                Some(b'\n') => break,
+                Some(_) if line.len() == INPUT_MAX => break,
+                Some(b) => {
+                    line.push(b);
+                    self.input.consume(1);
+                }
+            }
+        }
+        // The newline (or the byte after a line that was too long)
+        if !eof && self.input.fill_buf().is_ok_and(|buf| !buf.is_empty()) {
+            self.input.consume(1);
+        }
+        // Enter moved the cursor to column 0
+        self.cols[0] = 0;
+        (line, eof)
+    }
+
+    /// INPUT: prompt until the line has a valid field for each target, then
+    /// assign them in order
+    fn input(&mut self, prompt: Option<&str>, question: bool, vars: &[Expr]) -> Exec<()> {
+        let mut text = prompt.unwrap_or_default().to_string();
+        if question {
+            text.push_str("? ");
+        }
+        let targets: Vec<bool> = vars.iter().map(|v| self.is_string_target(v)).collect();
+        let line = loop {
+            self.out_write(0, text.as_bytes());
+            let (line, eof) = self.read_console_line();
+            // At end of input the line is taken as it is
+            if eof || input_fits(&line, &targets) {
+                break line;
+            }
+            self.out_write(0, b"?Redo from start");
+            self.newline(0);
+        };
+        let mut pos = 0;
+        for (var, is_string) in vars.iter().zip(targets) {
+            let (field, next, _) = next_field(&line, pos);
+            pos = next;
+            let field = &line[field];
+            let value = if is_string {
+                Value::Str(field.to_vec())
+            } else {
+                Value::Double(strtod(field).0)
+            };
+            self.assign(var, |_| Ok(value))?;
+        }
+        Ok(())
+    }
+
+    // ------------------------------------------------------------------
+    // Files (file.s)
+    // ------------------------------------------------------------------
+
+    /// Channel for a file number, which must be 1-15
+    fn channel(&self, file_num: i64) -> Exec<usize> {
+        if (1..CHANNELS as i64).contains(&file_num) {
+            Ok(file_num as usize)
+        } else {
+            Err(self.fail("Bad file number"))
+        }
+    }
+
+    /// Channel for a file number that must be open
+    fn open_channel(&self, file_num: i64) -> Exec<usize> {
+        let ch = self.channel(file_num)?;
+        match self.files[ch] {
+            Some(_) => Ok(ch),
+            None => Err(self.fail("Bad file number")),
+        }
+    }
+
+    /// An open file number INPUT # can read: not one opened FOR OUTPUT or
+    /// APPEND
+    fn read_channel(&self, file_num: i64) -> Exec<usize> {
+        let ch = self.open_channel(file_num)?;
+        if self.written[ch] {
+            return Err(self.fail("Bad file mode"));
+        }
+        Ok(ch)
+    }
+
+    fn close_channel(&mut self, ch: usize) {
+        match self.files[ch].take() {
+            Some(Handle::Output(mut file)) => {
+                let _ = file.flush();
+            }
+            // Closing the pipe ends the command's input or output; then
+            // wait for it, as pclose does
+            Some(Handle::PipeIn(mut child, stdout)) => {
+                drop(stdout);
+                let _ = child.wait();
+            }
+            Some(Handle::PipeOut(mut child, stdin)) => {
+                drop(stdin);
+                self.flush_all();
+                let _ = child.wait();
+            }
+            _ => {}
+        }
+        self.file_lines[ch] = None;
+    }
+
+    /// Write out the console's and every file's buffered output
+    fn flush_all(&mut self) {
+        let _ = self.output.flush();
+        for file in self.files.iter_mut() {
+            if let Some(Handle::Output(file)) = file {
+                let _ = file.flush();
+            }
+        }
+    }
+
+    /// Start a PIPE: command in the shell, with a pipe to its input for
+    /// output, or from its output for input. The program's output so far
+    /// is flushed first, so the command's comes after it and it finds the
+    /// files complete.
+    fn pipe(&mut self, command: &str, output: bool) -> Option<Handle> {
+        self.flush_all();
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        shell.arg(command);
+        if output {
+            let mut child = shell.stdin(Stdio::piped()).spawn().ok()?;
+            let stdin = child.stdin.take()?;
+            Some(Handle::PipeOut(child, BufWriter::new(stdin)))
+        } else {
+            let mut child = shell.stdout(Stdio::piped()).spawn().ok()?;
+            let stdout = child.stdout.take()?;
+            Some(Handle::PipeIn(child, BufReader::new(stdout)))
+        }
+    }
+
+    /// The dictionary (its place in `dicts`) a DICTNEW handle refers to
+    fn dict(&mut self, handle: &Expr) -> Exec<usize> {
+        let handle = self.eval_rounded(handle)?;
+        match usize::try_from(handle - 1) {
+            Ok(i) if i < self.dicts.len() => Ok(i),
+            _ => Err(self.fail("Illegal function call")),
+        }
+    }
+
+    /// SEEK(): the byte position (1 = the first) of a file's next read or
+    /// write, before what INPUT # hasn't used of the current line
+    fn file_position(&mut self, file_num: i64) -> Exec<u64> {
+        let ch = self.open_channel(file_num)?;
+        let position = match &mut self.files[ch] {
+            Some(Handle::Input(file)) => file.stream_position().ok(),
+            Some(Handle::Output(file)) => file.stream_position().ok(),
+            _ => None,
+        };
+        let Some(mut position) = position else {
+            return Err(self.fail("Bad file mode"));
+        };
+        if let Some((_, next, read)) = &self.file_lines[ch] {
+            position -= (read - next) as u64;
+        }
+        Ok(position + 1)
+    }
+
+    /// The next comma-separated field for INPUT #, reading a new line when
+    /// the last one is used up. A file at its end gives empty lines.
+    fn file_field(&mut self, file_num: i64) -> Exec<Vec<u8>> {
+        let ch = self.read_channel(file_num)?;
+        let (line, pos, read) = match self.file_lines[ch].take() {
+            Some(pending) => pending,
+            None => {
+                let (line, read) = self.read_file_line(ch);
+                (line, 0, read)
+            }
+        };
+        let (field, next, ended_by) = next_field(&line, pos);
+        let field = line[field].to_vec();
+        if ended_by != 0 {
+            self.file_lines[ch] = Some((line, next, read));
+        }
+        Ok(field)
+    }
+
+    /// LINE INPUT #: the rest of the line INPUT # is partway through, else
+    /// the next line
+    fn file_line(&mut self, file_num: i64) -> Exec<Vec<u8>> {
+        let ch = self.read_channel(file_num)?;
+        Ok(match self.file_lines[ch].take() {
+            Some((line, pos, _)) => line[pos..].to_vec(),
+            None => self.read_file_line(ch).0,
+        })
+    }
+
+    /// Read a channel's next line, without its line ending, and how many
+    /// bytes that took
+    fn read_file_line(&mut self, ch: usize) -> (Vec<u8>, usize) {
+        let mut line = Vec::new();
+        match &mut self.files[ch] {
+            Some(Handle::Input(file)) => {
+                let _ = file.read_until(b'\n', &mut line);
+            }
+            Some(Handle::PipeIn(_, stdout)) => {
+                let _ = stdout.read_until(b'\n', &mut line);
+            }
+            Some(Handle::Tcp(stream)) => {
+                let _ = stream.read_until(b'\n', &mut line);
+            }
+            Some(Handle::Console) => {
+                let _ = self.input.read_until(b'\n', &mut line);
+                self.cols[0] = 0; // Enter moved the cursor to column 0
+            }
+            _ => {}
+        }
+        let read = line.len();
+        while line.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+            line.pop();
+        }
+        (line, read)
+    }
+}
+
+/// Connect a TCP: file to `host:port`
+fn tcp_connect(address: &str) -> Option<Handle> {
+    let (host, port) = address.rsplit_once(':')?;
+    let stream = TcpStream::connect((host, port.parse::<u16>().ok()?)).ok()?;
+    Some(Handle::Tcp(BufReader::new(stream)))
+}
+
+/// Wait for a client to connect to a TCPL: file's port
+fn tcp_listen(port: &str) -> Option<Handle> {
+    let listener = TcpListener::bind(("0.0.0.0", port.parse::<u16>().ok()?)).ok()?;
+    let (stream, _) = listener.accept().ok()?;
+    Some(Handle::Tcp(BufReader::new(stream)))
+}
+
+/// INSTR: 1-based position of `needle` in `hay` from `start`, or 0
+fn instr(start: i64, hay: &[u8], needle: &[u8]) -> i64 {
+    let start = start.max(1);
+    if start as u64 > hay.len() as u64 {
+        return if needle.is_empty() { start } else { 0 };
+    }
+    if needle.is_empty() {
+        return start;
+    }
+    let from = start as usize - 1;
+    hay[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map_or(0, |at| (from + at + 1) as i64)
+}
+
+/// ROUND: `x` rounded half away from zero to `digits` decimal places (tens,
+/// hundreds... when negative), as the runtime's _rt_round does it
+fn round_to(x: f64, digits: i64) -> f64 {
+    let scale = 10f64.powf(digits.unsigned_abs() as f64);
+    if digits < 0 {
+        let n = (x / scale).round();
+        if n == 0.0 { n } else { n * scale }
+    } else {
+        // Past 2^52 every double is whole already
+        let scaled = x * scale;
+        if scaled.abs() < 2f64.powi(52) {
+            scaled.round() / scale
+        } else {
+            x
+        }
+    }
+}
+
+/// INSTRREV: 1-based position of the last `needle` in `hay` starting at
+/// or before `start`, or 0
+fn instrrev(start: i64, hay: &[u8], needle: &[u8]) -> i64 {
+    let last = start.min(hay.len() as i64);
+    if last < 1 {
+        return 0;
+    }
+    if needle.is_empty() {
+        return last;
+    }
+    hay.windows(needle.len())
+        .take(last as usize)
+        .rposition(|w| w == needle)
+        .map_or(0, |at| at as i64 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Run a program with the given console input; returns its output, or
+    /// its output and the error that stopped it
+    fn interpret(source: &str, input: &str) -> Result<String, (String, String)> {
+        let program = Parser::new(Lexer::new(source)).parse().unwrap();
+        let mut output = Vec::new();
+        let result = Interpreter::new(
+            &program,
+            false,
+            Dialect::Modern,
+            Box::new(input.as_bytes()),
+            Box::new(&mut output),
+        )
+        .run();
+        let output = String::from_utf8(output).unwrap();
+        match result {
+            Ok(()) => Ok(output),
+            Err(message) => Err((output, message)),
+        }
+    }
+
+    fn run_ok(source: &str) -> String {
+        interpret(source, "").unwrap()
+    }
+
+    // ===================
+    // Formatting Tests
+    // ===================
+
+    #[test]
+    fn test_format_g() {
+        assert_eq!(format_g(0.1, 6), "0.1");
+        assert_eq!(format_g(1.0 / 3.0, 6), "0.333333");
+        assert_eq!(format_g(1.0 / 3.0, 7), "0.3333333");
+        assert_eq!(format_g(123456.7, 6), "123457");
+        assert_eq!(format_g(1234567.0, 6), "1.23457e+06");
+        assert_eq!(format_g(1e20, 6), "1e+20");
+        assert_eq!(format_g(0.0001, 6), "0.0001");
+        assert_eq!(format_g(0.00001234, 6), "1.234e-05");
+        assert_eq!(format_g(-2.5, 6), "-2.5");
+        assert_eq!(format_g(999999.5, 6), "1e+06");
+        assert_eq!(format_g(f64::INFINITY, 6), "inf");
+        assert_eq!(format_g(f64::NAN, 6), "nan");
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(42.0, 6), "42");
+        assert_eq!(format_number(-7.0, 6), "-7");
+        assert_eq!(format_number(1e15, 6), "1000000000000000");
+        assert_eq!(format_number(2.5, 6), "2.5");
+        assert_eq!(format_number((1.0f32 / 3.0) as f64, 7), "0.3333333");
+        // A DOUBLE with the fewest digits that read back as the same value
+        assert_eq!(format_number(0.1, 0), "0.1");
+        assert_eq!(format_number(0.1 + 0.2, 0), "0.30000000000000004");
+        assert_eq!(format_number(1.0 / 3.0, 0), "0.3333333333333333");
+        assert_eq!(format_number(1e300 / 7.0, 0), "1.4285714285714286e+299");
+        for x in [std::f64::consts::PI, 2.0 / 3.0, 1e-7 / 3.0, 123456.789e10] {
+            assert_eq!(strtod(format_number(x, 0).as_bytes()).0, x);
+        }
+    }
+
+    #[test]
+    fn test_strtod() {
+        assert_eq!(strtod(b"12.5"), (12.5, 4));
+        assert_eq!(strtod(b"  -3e2xyz"), (-300.0, 6));
+        assert_eq!(strtod(b"1e"), (1.0, 1));
+        assert_eq!(strtod(b".5"), (0.5, 2));
+        assert_eq!(strtod(b"0x1A"), (26.0, 4));
+        assert_eq!(strtod(b"abc"), (0.0, 0));
+        assert_eq!(strtod(b"-"), (0.0, 0));
+        assert_eq!(strtod(b"Infinity"), (f64::INFINITY, 8));
+        assert!(strtod(b"nan").0.is_nan());
+    }
+
+    // ===================
+    // Input Field Tests
+    // ===================
+
+    #[test]
+    fn test_next_field() {
+        let line = b"  a b  , \"x, y\"  ,3";
+        let (field, pos, ended_by) = next_field(line, 0);
+        assert_eq!((&line[field], ended_by), (&b"a b"[..], b','));
+        let (field, pos, ended_by) = next_field(line, pos);
+        assert_eq!((&line[field], ended_by), (&b"x, y"[..], b','));
+        let (field, _, ended_by) = next_field(line, pos);
+        assert_eq!((&line[field], ended_by), (&b"3"[..], 0));
+
+        // Junk after a quoted field
+        let (_, _, ended_by) = next_field(b"\"a\" b", 0);
+        assert_eq!(ended_by, b'b');
+    }
+
+    #[test]
+    fn test_input_fits() {
+        assert!(input_fits(b"1, 2", &[false, false]));
+        assert!(input_fits(b", x", &[false, true]));
+        assert!(!input_fits(b"1", &[false, false]));
+        assert!(!input_fits(b"1, 2, 3", &[false, false]));
+        assert!(!input_fits(b"1x", &[false]));
+        assert!(!input_fits(b"\"a\" b", &[true]));
+    }
+
+    // ===================
+    // Execution Tests
+    // ===================
+
+    #[test]
+    fn test_run_print() {
+        assert_eq!(
+            run_ok("PRINT 1; 2.5; \"x\"\nPRINT 1, 2"),
+            "12.5x\n1             2\n"
+        );
+        assert_eq!(
+            run_ok("PRINT 1 / 3\nS! = 1 / 3\nPRINT S!"),
+            "0.3333333333333333\n0.3333333\n"
+        );
+        assert_eq!(
+            run_ok("PRINT \"a\"; TAB(4); \"b\"; SPC(2); \"c\""),
+            "a  b  c\n"
+        );
+        assert_eq!(
+            run_ok("WIDTH 4\nPRINT \"abcdef\"; 12345"),
+            "abcd\nef\n1234\n5\n"
+        );
+    }
+
+    #[test]
+    fn test_run_integer_types() {
+        // INTEGER variables keep 16 bits, LONG arithmetic wraps in 32
+        assert_eq!(run_ok("A% = 32767\nA% = A% + 1\nPRINT A%"), "-32768\n");
+        assert_eq!(
+            run_ok("B& = 2147483647\nB& = B& + 1\nPRINT B&"),
+            "-2147483648\n"
+        );
+        assert_eq!(run_ok("A% = 2.7\nPRINT A%; CINT(2.5); CINT(3.5)"), "224\n");
+        assert_eq!(
+            run_ok("PRINT -7 \\ 2; -7 MOD 3; 5 AND 3; NOT 0"),
+            "-3-11-1\n"
+        );
+    }
+
+    #[test]
+    fn test_run_control_flow() {
+        let source = r#"
+FOR I% = 1 TO 10 STEP 4: PRINT I%;: NEXT: PRINT I%
+FOR X = 1 TO 2 STEP 0.5: PRINT X;: NEXT: PRINT X
+GOSUB 100
+DO: N = N + 1: LOOP UNTIL N = 3
+PRINT N
+SELECT CASE N
+CASE 2: PRINT "two"
+CASE 3: PRINT "three"
+END SELECT
+END
+100 PRINT "sub": RETURN
+"#;
+        assert_eq!(run_ok(source), "15913\n11.522.5\nsub\n3\nthree\n");
+    }
+
+    #[test]
+    fn test_run_procedures() {
+        let source = r#"
+FUNCTION FACT(N)
+    IF N <= 1 THEN FACT = 1 ELSE FACT = N * FACT(N - 1)
+END FUNCTION
+SUB FILL(A(), V)
+    SHARED COUNT
+    FOR I = 0 TO UBOUND(A): A(I) = V: NEXT
+    COUNT = COUNT + 1
+END SUB
+DIM B(2)
+FILL B(), 7
+PRINT FACT(10); B(2); COUNT
+"#;
+        assert_eq!(run_ok(source), "362880071\n");
+    }
+
+    #[test]
+    fn test_run_input() {
+        let out = interpret("INPUT \"N\"; A, B$\nPRINT A + 1; B$", "x\n41, hi\n").unwrap();
+        assert_eq!(out, "N? ?Redo from start\nN? 42hi\n");
+        let out = interpret("LINE INPUT L$\nINPUT A\nPRINT L$; A", " a, b \n").unwrap();
+        assert_eq!(out, "?  a, b 0\n");
+        // The console devices share the console's input and output
+        let source = "OPEN \"kybd:\" FOR INPUT AS #1\nINPUT #1, A\nOPEN \"SCRN:\" FOR OUTPUT AS #2\nPRINT #2, A * 2";
+        assert_eq!(interpret(source, "21\n").unwrap(), "42\n");
+        // PIPE: reads a command's output
+        let source =
+            "OPEN \"PIPE:echo 6, 7\" FOR INPUT AS #1\nINPUT #1, A, B\nCLOSE #1\nPRINT A * B";
+        assert_eq!(interpret(source, "").unwrap(), "42\n");
+    }
+
+    #[test]
+    fn test_run_tcp() {
+        use std::io::Read;
+        use std::net::TcpListener;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"6, 7\n").unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).unwrap();
+            reply
+        });
+        let source = format!(
+            "OPEN \"TCP:127.0.0.1:{}\" AS #1\nINPUT #1, A, B\nPRINT #1, A * B\nCLOSE #1",
+            port
+        );
+        assert_eq!(interpret(&source, "").unwrap(), "");
+        assert_eq!(server.join().unwrap(), "42\n");
+        let (_, message) = interpret("OPEN \"x\" AS #1", "").unwrap_err();
+        assert_eq!(message, "Bad file mode");
+    }
+
+    #[test]
+    fn test_run_data() {
+        let source = "READ A, B$\nRESTORE 20\nREAD C\nPRINT A; B$; C\n10 DATA 1, \"x\"\n20 DATA 3";
+        assert_eq!(run_ok(source), "1x3\n");
+        let (_, message) = interpret("READ A, B", "").unwrap_err();
+        assert_eq!(message, "Out of DATA");
+    }
+
+    #[test]
+    fn test_run_errors() {
+        let (output, message) = interpret("10 PRINT \"a\"\n20 PRINT 1 / 0", "").unwrap_err();
+        assert_eq!(
+            (output.as_str(), message.as_str()),
+            ("a\n", "Division by zero in 20")
+        );
+        let (_, message) = interpret("DIM A(3)\nA(4) = 1", "").unwrap_err();
+        assert_eq!(message, "Subscript out of range");
+        let (_, message) = interpret("RETURN", "").unwrap_err();
+        assert_eq!(message, "RETURN without GOSUB");
+        let (_, message) = interpret("10 GOSUB 10", "").unwrap_err();
+        assert_eq!(message, "GOSUB stack overflow in 10");
+        let (_, message) = interpret("PRINT #3, 1", "").unwrap_err();
+        assert_eq!(message, "Bad file number");
+        let (_, message) = interpret("OPEN \"SCRN:\" FOR INPUT AS #1", "").unwrap_err();
+        assert_eq!(message, "Bad file mode");
+    }
+
+    #[test]
+    fn test_check_unsupported() {
+        let program = Parser::new(Lexer::new("PRINT 1\nIF 1 THEN PSET (1, 2)")).parse();
+        let err = check(&program.unwrap()).unwrap_err();
+        assert_eq!(err.message, "PSET is not supported by --run");
+        let program = Parser::new(Lexer::new("KEY OFF\nPRINT 1")).parse();
+        assert!(check(&program.unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,257 @@
+//! Intermediate representation - typed instructions between the AST and assembly
+//!
+//! The code generator lowers the AST to a flat list of `Inst`s (see
+//! codegen.rs) and the backend turns them into x86-64 assembly (see emit.rs).
+//!
+//! The IR is an accumulator machine, matching the register conventions the
+//! backend uses. Each value-producing instruction leaves its result in the
+//! accumulator for its type:
+//!
+//! | Type             | Accumulator    | Secondary      |
+//! |------------------|----------------|----------------|
+//! | Integer, Long    | `eax`          | `ecx`          |
+//! | Single, Double   | `xmm0`         | `xmm1`         |
+//! | String           | `rax` + `rdx`  |                |
+//!
+//! A binary operation evaluates its left operand, `Push`es it, evaluates the
+//! right operand, then `PopRight` moves the right operand to the secondary
+//! register and restores the left one, and `Binary` combines them.
+//!
+//! Instructions carry their DataType, so passes over the IR can reason about
+//! values without looking at assembly text. Operations the IR doesn't model
+//! yet (runtime calling sequences, array addressing and the like) are kept
+//! as `Asm` text and passed through unchanged.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::parser::{BinaryOp, DataType, Literal};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+/// An interned operand: codegen computes each variable's address once and
+/// every load and store of it shares the text
+pub type Symbol = Rc<str>;
+
+/// GOSUB stack size in bytes (64K entries * 8 bytes = 512KB)
+pub const GOSUB_STACK_SIZE: i32 = 524288;
+
+/// A constant loaded into the accumulator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Const {
+    Long(i32), // also INTEGER constants: integers are computed as 32 bits
+    Single(f32),
+    Double(f64),
+    Str { index: usize, len: usize }, // string literal `_str_{index}`
+}
+
+impl Const {
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Const::Long(_) => DataType::Long,
+            Const::Single(_) => DataType::Single,
+            Const::Double(_) => DataType::Double,
+            Const::Str { .. } => DataType::String,
+        }
+    }
+
+    /// Whether this constant loads the accumulator for type `ty`
+    pub fn holds(&self, ty: DataType) -> bool {
+        match self {
+            Const::Long(_) => ty.is_integer(),
+            c => c.data_type() == ty,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    /// A code label
+    Label(String),
+    /// Function entry: label, frame setup, and saves of the callee-saved
+    /// registers the function uses (from `frames[frame]`)
+    Enter { label: String, frame: usize },
+    /// Restore the saved registers, tear down the frame and return
+    Return { frame: usize },
+
+    /// Load a constant into the accumulator
+    Const(Const),
+    /// Load a variable from memory (`addr` is an operand without brackets)
+    Load { ty: DataType, addr: Symbol },
+    /// Store the accumulator to memory
+    Store { ty: DataType, addr: Symbol },
+    /// Convert the accumulator between numeric types; `checked` stops with
+    /// "Overflow" when a value doesn't fit an integer type
+    Convert {
+        from: DataType,
+        to: DataType,
+        checked: bool,
+    },
+    /// Save the accumulator (a binary operation's left operand) in a
+    /// 16-byte stack temporary
+    Push(DataType),
+    /// Move the accumulator to the secondary register and restore the
+    /// pushed left operand
+    PopRight(DataType),
+    /// Save the accumulator (a binary operation's left operand) in scratch
+    /// register `temp` instead of on the stack (from the register allocator)
+    SaveTemp { ty: DataType, temp: usize },
+    /// Move the accumulator to the secondary register and restore the left
+    /// operand from scratch register `temp`
+    RestoreRight { ty: DataType, temp: usize },
+    /// Load a constant right operand straight into the secondary register
+    /// (what `Push`, `Const`, `PopRight` does, from the peephole pass)
+    ConstRight(Const),
+    /// Load a variable right operand straight into the secondary register
+    LoadRight { ty: DataType, addr: Symbol },
+    /// Combine the accumulator and secondary register, in the work type
+    /// `ty`. Comparisons and logical operators leave a Long; `/` and `^`
+    /// leave a Double.
+    Binary { op: BinaryOp, ty: DataType },
+    /// Negate the accumulator
+    Neg(DataType),
+    /// Logical NOT: -1 if the accumulator is zero, else 0 (a Long)
+    Not(DataType),
+    /// Stop with "Overflow" unless the integer accumulator fits `ty`. For
+    /// Long, it must directly follow the arithmetic that set the flags.
+    CheckOverflow(DataType),
+
+    /// Unconditional jump
+    Jump(String),
+    /// Jump if the accumulator is zero (`if_zero`) or nonzero
+    Branch {
+        ty: DataType,
+        if_zero: bool,
+        label: String,
+    },
+    /// Call a runtime routine or a SUB/FUNCTION
+    Call(String),
+    /// Call a C library function (with Win64 shadow space)
+    CallLibc(String),
+
+    /// An instruction or directive the IR doesn't model, as assembly text
+    Asm(String),
+    /// An ASM ... END ASM block, one line each; the passes don't look
+    /// inside it, so it keeps what it might read, write or jump to
+    InlineAsm(Vec<String>),
+    /// Where the code for a line of the source file (counting from 1, not a
+    /// BASIC line number) starts; -S shows the line as a comment there
+    Source(u32),
+}
+
+/// The frame of a function, known once its body has been lowered
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    pub size: i32,                       // bytes below rbp, a multiple of 16
+    pub saved: Vec<(&'static str, i32)>, // callee-saved register, frame slot
+}
+
+/// A lowered program: its code and the data it refers to
+#[derive(Debug, Default)]
+pub struct Module {
+    pub code: Vec<Inst>,
+    pub frames: Vec<Frame>,
+    pub strings: Vec<String>,                  // string literals, `_str_N`
+    pub data: Vec<Literal>,                    // DATA values, in order
+    pub statics: BTreeMap<String, (i32, i32)>, // static label -> (bytes before, bytes from label)
+    pub gosub_stack: bool,                     // whether the GOSUB return stack is needed
+    pub exports: Vec<String>, // labels other modules can refer to: main, procedures
+}
+
+/// Suffix naming a type in the IR listing
+fn type_suffix(ty: DataType) -> &'static str {
+    match ty {
+        DataType::Integer => "int",
+        DataType::Long => "lng",
+        DataType::Single => "sng",
+        DataType::Double => "dbl",
+        DataType::String => "str",
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inst::Label(label) => write!(f, "{}:", label),
+            Inst::Enter { label, frame } => write!(f, "{}:\n    enter frame{}", label, frame),
+            Inst::Return { frame } => write!(f, "    return frame{}", frame),
+            Inst::Const(Const::Long(n)) => write!(f, "    const.lng {}", n),
+            Inst::Const(Const::Single(x)) => write!(f, "    const.sng {:?}", x),
+            Inst::Const(Const::Double(x)) => write!(f, "    const.dbl {:?}", x),
+            Inst::Const(Const::Str { index, len }) => {
+                write!(f, "    const.str _str_{} ({} bytes)", index, len)
+            }
+            Inst::Load { ty, addr } => write!(f, "    load.{} [{}]", type_suffix(*ty), addr),
+            Inst::Store { ty, addr } => write!(f, "    store.{} [{}]", type_suffix(*ty), addr),
+            Inst::Convert { from, to, checked } => write!(
+                f,
+                "    convert.{}.{}{}",
+                type_suffix(*from),
+                type_suffix(*to),
+                if *checked { " checked" } else { "" }
+            ),
+            Inst::Push(ty) => write!(f, "    push.{}", type_suffix(*ty)),
+            Inst::PopRight(ty) => write!(f, "    pop_right.{}", type_suffix(*ty)),
+            Inst::SaveTemp { ty, temp } => write!(f, "    save.{} t{}", type_suffix(*ty), temp),
+            Inst::RestoreRight { ty, temp } => {
+                write!(f, "    restore_right.{} t{}", type_suffix(*ty), temp)
+            }
+            Inst::ConstRight(Const::Long(n)) => write!(f, "    const_right.lng {}", n),
+            Inst::ConstRight(Const::Single(x)) => write!(f, "    const_right.sng {:?}", x),
+            Inst::ConstRight(Const::Double(x)) => write!(f, "    const_right.dbl {:?}", x),
+            Inst::ConstRight(Const::Str { index, len }) => {
+                write!(f, "    const_right.str _str_{} ({} bytes)", index, len)
+            }
+            Inst::LoadRight { ty, addr } => {
+                write!(f, "    load_right.{} [{}]", type_suffix(*ty), addr)
+            }
+            Inst::Binary { op, ty } => {
+                write!(
+                    f,
+                    "    {}.{}",
+                    format!("{:?}", op).to_lowercase(),
+                    type_suffix(*ty)
+                )
+            }
+            Inst::Neg(ty) => write!(f, "    neg.{}", type_suffix(*ty)),
+            Inst::Not(ty) => write!(f, "    not.{}", type_suffix(*ty)),
+            Inst::CheckOverflow(ty) => write!(f, "    check_overflow.{}", type_suffix(*ty)),
+            Inst::Jump(label) => write!(f, "    jump {}", label),
+            Inst::Branch { ty, if_zero, label } => write!(
+                f,
+                "    {}.{} {}",
+                if *if_zero {
+                    "branch_zero"
+                } else {
+                    "branch_nonzero"
+                },
+                type_suffix(*ty),
+                label
+            ),
+            Inst::Call(func) => write!(f, "    call {}", func),
+            Inst::CallLibc(func) => write!(f, "    call_libc {}", func),
+            Inst::Asm(text) if text.is_empty() => Ok(()),
+            Inst::Asm(text) => write!(f, "    asm {}", text),
+            Inst::InlineAsm(lines) => {
+                write!(f, "    inline_asm")?;
+                for line in lines {
+                    write!(f, "\n        {}", line.trim())?;
+                }
+                Ok(())
+            }
+            Inst::Source(line) => write!(f, "# line {}", line),
+        }
+    }
+}
+
+impl Module {
+    /// The code as a readable listing (for --emit-ir)
+    pub fn listing(&self) -> String {
+        let mut out = String::with_capacity(self.code.len() * 24);
+        for inst in &self.code {
+            writeln!(out, "{}", inst).expect("writing to a String");
+        }
+        out
+    }
+}
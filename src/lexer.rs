@@ -1,8 +1,18 @@
 //! BASIC lexer - tokenizes source into tokens
+//!
+//! Each token's span (where it starts) is kept alongside it, so later passes
+//! can point their error messages at the source.
+//!
+//! The lexer is an iterator over `(Token, Span)` pairs, ending with `Eof`
+//! (or the first error), so the parser can pull tokens as it goes instead
+//! of holding the whole token stream of a large source in memory.
 
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
+use crate::diagnostic::Diagnostic;
+use clap::ValueEnum;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::iter::Peekable;
 use std::str::Chars;
@@ -12,6 +22,7 @@ use std::sync::LazyLock;
 static KEYWORDS: LazyLock<HashMap<&'static str, Token>> = LazyLock::new(|| {
     HashMap::from([
         ("PRINT", Token::Print),
+        ("WRITE", Token::Write),
         ("INPUT", Token::Input),
         ("LINE", Token::Line),
         ("LET", Token::Let),
@@ -48,6 +59,23 @@ static KEYWORDS: LazyLock<HashMap<&'static str, Token>> = LazyLock::new(|| {
         ("READ", Token::Read),
         ("RESTORE", Token::Restore),
         ("CLS", Token::Cls),
+        ("WIDTH", Token::Width),
+        ("SCREEN", Token::Screen),
+        ("PSET", Token::Pset),
+        ("PRESET", Token::Preset),
+        ("CIRCLE", Token::Circle),
+        ("PAINT", Token::Paint),
+        ("DRAW", Token::Draw),
+        ("GET", Token::Get),
+        ("PUT", Token::Put),
+        ("DISPLAY", Token::Display),
+        ("PALETTE", Token::Palette),
+        ("DEF", Token::Def),
+        ("POKE", Token::Poke),
+        ("BSAVE", Token::Bsave),
+        ("BLOAD", Token::Bload),
+        ("KEY", Token::Key),
+        ("OFF", Token::Off),
         ("OPEN", Token::Open),
         ("CLOSE", Token::Close),
         ("AS", Token::As),
@@ -55,17 +83,69 @@ static KEYWORDS: LazyLock<HashMap<&'static str, Token>> = LazyLock::new(|| {
         ("APPEND", Token::Append),
         ("AND", Token::And),
         ("OR", Token::Or),
+        ("ANDALSO", Token::AndAlso),
+        ("ORELSE", Token::OrElse),
         ("NOT", Token::Not),
         ("XOR", Token::Xor),
         ("MOD", Token::Mod),
     ])
 });
 
-#[derive(Debug, Clone, PartialEq)]
+/// The BASIC dialect programs are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[non_exhaustive]
+pub enum Dialect {
+    /// GW-BASIC: every line numbered, and QuickBASIC's block keywords (DO,
+    /// SUB, FUNCTION, SELECT CASE, ELSEIF...) are ordinary names
+    Gw,
+    /// QuickBASIC 4.5: block syntax and optional line numbers, without the
+    /// compiler's own extensions
+    Qb45,
+    /// Line numbers and structured blocks alike, with every extension the
+    /// compiler knows
+    #[default]
+    Modern,
+}
+
+impl Dialect {
+    /// The dialect's name in messages
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Gw => "GW-BASIC",
+            Dialect::Qb45 => "QuickBASIC 4.5",
+            Dialect::Modern => "xbasic64",
+        }
+    }
+
+    /// Whether `word`, in the keyword table, is a keyword of this dialect
+    /// rather than a name programs may use for their variables
+    fn has_keyword(self, word: &str) -> bool {
+        let qb45 = || {
+            !matches!(
+                word,
+                "ENDIF" | "ENDSUB" | "ENDFUNCTION" | "ENDSELECT" | "ANDALSO" | "ORELSE"
+            )
+        };
+        match self {
+            Dialect::Gw => {
+                qb45()
+                    && !matches!(
+                        word,
+                        "ELSEIF" | "DO" | "LOOP" | "UNTIL" | "SUB" | "FUNCTION" | "SELECT" | "CASE"
+                    )
+            }
+            Dialect::Qb45 => qb45(),
+            Dialect::Modern => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Token {
     // Literals
     Integer(i64),
     Float(f64),
+    TypedNumber(f64, char), // number with a type suffix: 10%, 1.5!, 1D0#
     String(String),
 
     // Identifier with optional type suffix
@@ -73,6 +153,7 @@ pub enum Token {
 
     // Keywords
     Print,
+    Write,
     Input,
     Line,
     Let,
@@ -109,6 +190,23 @@ pub enum Token {
     Read,
     Restore,
     Cls,
+    Width,
+    Screen,
+    Pset,
+    Preset,
+    Circle,
+    Paint,
+    Draw,
+    Get,
+    Put,
+    Display,
+    Palette,
+    Def,
+    Poke,
+    Bsave,
+    Bload,
+    Key,
+    Off,
     Open,
     Close,
     As,
@@ -118,6 +216,8 @@ pub enum Token {
     Or,
     Not,
     Xor,
+    AndAlso,
+    OrElse,
     Mod,
 
     // Operators
@@ -142,18 +242,40 @@ pub enum Token {
     Colon,
     Hash,
 
+    // ASM ... END ASM: the lines in between, as written
+    Asm(Vec<String>),
+
     // Special
     Newline,
     LineNumber(u32),
     Eof,
 }
 
+/// Where a token or statement starts: 1-based line and column (in characters)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Span {
+    pub line: u32,
+    pub col: u32,
+}
+
+/// A comment (' or REM to the end of the line) and where it starts. The
+/// tokens have none, but tools that write the source back out need them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub span: Span,
+    pub text: String, // from the ' or REM, trailing blanks removed
+}
+
 pub struct Lexer<'a> {
     input: &'a str,
     chars: Peekable<Chars<'a>>,
     pos: usize,
     line: u32,
+    line_start: usize, // byte offset where the current line begins
     at_line_start: bool,
+    done: bool, // Eof or an error has been returned
+    comments: Vec<Comment>,
+    dialect: Dialect,
 }
 
 impl<'a> Lexer<'a> {
@@ -163,10 +285,31 @@ impl<'a> Lexer<'a> {
             chars: input.chars().peekable(),
             pos: 0,
             line: 1,
+            line_start: 0,
             at_line_start: true,
+            done: false,
+            comments: Vec::new(),
+            dialect: Dialect::default(),
         }
     }
 
+    /// Read `dialect`'s keywords, and for GW-BASIC require a line number
+    /// on every line
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// The dialect being read
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// The comments passed so far, in order
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
     fn advance(&mut self) -> Option<char> {
         let c = self.chars.next();
         if let Some(ch) = c {
@@ -179,6 +322,15 @@ impl<'a> Lexer<'a> {
         self.chars.peek().copied()
     }
 
+    /// Where the next character is
+    fn span(&self) -> Span {
+        let col = self.input[self.line_start..self.pos].chars().count() as u32 + 1;
+        Span {
+            line: self.line,
+            col,
+        }
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.peek() {
             if c == ' ' || c == '\t' || c == '\r' {
@@ -189,7 +341,9 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn skip_comment(&mut self) {
+    /// Skip to the end of the line, keeping the comment that began at byte
+    /// offset `start`
+    fn skip_comment(&mut self, start: usize) {
         // Skip until newline
         while let Some(c) = self.peek() {
             if c == '\n' {
@@ -197,6 +351,14 @@ impl<'a> Lexer<'a> {
             }
             self.advance();
         }
+        let col = self.input[self.line_start..start].chars().count() as u32 + 1;
+        self.comments.push(Comment {
+            span: Span {
+                line: self.line,
+                col,
+            },
+            text: self.input[start..self.pos].trim_end().to_string(),
+        });
     }
 
     fn read_string(&mut self) -> Result<String, String> {
@@ -222,7 +384,7 @@ impl<'a> Lexer<'a> {
         Ok(s)
     }
 
-    fn read_number(&mut self, first: char) -> Token {
+    fn read_number(&mut self, first: char) -> Result<Token, String> {
         let mut s = String::new();
         s.push(first);
 
@@ -253,24 +415,31 @@ impl<'a> Lexer<'a> {
         // Replace D with E for parsing
         let s = s.replace(['d', 'D'], "e");
 
-        if is_float {
-            Token::Float(s.parse().unwrap_or(0.0))
-        } else {
-            Token::Integer(s.parse().unwrap_or(0))
+        let value: f64 = s.parse().unwrap_or(0.0);
+        match self.peek() {
+            Some(suffix @ ('%' | '&' | '!' | '#')) => {
+                self.advance();
+                typed_number(value, suffix)
+                    .ok_or_else(|| format!("Overflow in literal {}{}", s, suffix))
+            }
+            _ if is_float => Ok(Token::Float(value)),
+            _ => Ok(Token::Integer(s.parse().unwrap_or(0))),
         }
     }
 
-    fn read_hex(&mut self) -> Token {
+    /// Read the digits of a &H (hex), &O (octal) or &B (binary) literal
+    fn read_radix(&mut self, prefix: char, radix: u32) -> Result<Token, String> {
         let mut s = String::new();
         while let Some(c) = self.peek() {
-            if c.is_ascii_hexdigit() {
+            if c.is_ascii_alphanumeric() {
                 s.push(self.advance().unwrap());
             } else {
                 break;
             }
         }
-        let val = i64::from_str_radix(&s, 16).unwrap_or(0);
-        Token::Integer(val)
+        i64::from_str_radix(&s, radix)
+            .map(Token::Integer)
+            .map_err(|_| format!("Invalid &{} literal: &{}{}", prefix, prefix, s))
     }
 
     fn read_identifier(&mut self, first: char) -> String {
@@ -295,15 +464,42 @@ impl<'a> Lexer<'a> {
         s
     }
 
-    fn keyword_or_ident(&self, s: &str) -> Token {
+    /// The lines after ASM up to END ASM, which is left for the newline
+    /// after it to end the statement. Nothing in them is tokenized.
+    fn read_asm_block(&mut self) -> Result<Token, String> {
+        let mut lines = Vec::new();
+        while self.advance() == Some('\n') {
+            self.line += 1;
+            self.line_start = self.pos;
+            let mut line = String::new();
+            while let Some(c) = self.peek() {
+                if c == '\n' {
+                    break;
+                }
+                line.push(c);
+                self.advance();
+            }
+            let words: Vec<String> = line
+                .split_whitespace()
+                .map(str::to_ascii_uppercase)
+                .collect();
+            if words == ["END", "ASM"] || words == ["ENDASM"] {
+                return Ok(Token::Asm(lines));
+            }
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+        Err("ASM without END ASM".to_string())
+    }
+
+    fn keyword_or_ident(&self, s: String) -> Token {
         let base = s.trim_end_matches(['%', '&', '!', '#', '$']);
-        KEYWORDS
-            .get(base)
-            .cloned()
-            .unwrap_or_else(|| Token::Ident(s.to_string()))
+        match KEYWORDS.get(base) {
+            Some(keyword) if self.dialect.has_keyword(base) => keyword.clone(),
+            _ => Token::Ident(s),
+        }
     }
 
-    pub fn next_token(&mut self) -> Result<Token, String> {
+    fn next_token(&mut self) -> Result<Token, String> {
         self.skip_whitespace();
 
         // Check for line number at start of line
@@ -323,6 +519,10 @@ impl<'a> Lexer<'a> {
                     return Ok(Token::LineNumber(num.parse().unwrap_or(0)));
                 }
             }
+            // GW-BASIC's own message for a line it can't store
+            if self.dialect == Dialect::Gw && !matches!(self.peek(), Some('\n') | None) {
+                return Err("Direct statement in file: GW-BASIC lines need line numbers".into());
+            }
         }
         self.at_line_start = false;
 
@@ -334,6 +534,7 @@ impl<'a> Lexer<'a> {
         match c {
             '\n' => {
                 self.line += 1;
+                self.line_start = self.pos;
                 self.at_line_start = true;
                 Ok(Token::Newline)
             }
@@ -346,10 +547,11 @@ impl<'a> Lexer<'a> {
             }
 
             '\'' => {
-                self.skip_comment();
+                self.skip_comment(self.pos - 1);
                 Ok(Token::Newline) // Treat comment as end of statement
             }
 
+            '?' => Ok(Token::Print), // PRINT shorthand
             '+' => Ok(Token::Plus),
             '-' => Ok(Token::Minus),
             '*' => Ok(Token::Star),
@@ -384,48 +586,94 @@ impl<'a> Lexer<'a> {
                 }
             }
 
-            '&' => {
-                if self.peek() == Some('H') || self.peek() == Some('h') {
+            '&' => match self.peek().map(|c| c.to_ascii_uppercase()) {
+                Some('H') => {
                     self.advance();
-                    Ok(self.read_hex())
-                } else {
-                    // & alone could be long suffix but we handle that in identifiers
-                    Ok(Token::Ident("&".to_string()))
+                    self.read_radix('H', 16)
                 }
-            }
+                Some('O') => {
+                    self.advance();
+                    self.read_radix('O', 8)
+                }
+                Some('B') => {
+                    self.advance();
+                    self.read_radix('B', 2)
+                }
+                Some(d) if d.is_ascii_digit() => Err(format!(
+                    "Invalid number &{}: use &H, &O or &B for hex, octal or binary",
+                    d
+                )),
+                // & alone could be long suffix but we handle that in identifiers
+                _ => Ok(Token::Ident("&".to_string())),
+            },
 
-            _ if c.is_ascii_digit() => Ok(self.read_number(c)),
+            _ if c.is_ascii_digit() => self.read_number(c),
 
             _ if c.is_ascii_alphabetic() => {
                 let ident = self.read_identifier(c);
 
                 // Handle REM as comment
                 if ident == "REM" {
-                    self.skip_comment();
+                    self.skip_comment(self.pos - 3);
                     return Ok(Token::Newline);
                 }
 
-                Ok(self.keyword_or_ident(&ident))
+                // ASM alone on its line starts a block of assembly
+                if ident == "ASM" {
+                    self.skip_whitespace();
+                    if self.peek() == Some('\'') {
+                        self.skip_comment(self.pos);
+                    }
+                    if matches!(self.peek(), Some('\n') | None) {
+                        return self.read_asm_block();
+                    }
+                }
+
+                Ok(self.keyword_or_ident(ident))
             }
 
             _ => Err(format!("Unexpected character: {}", c)),
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
-        let mut tokens = Vec::new();
-        loop {
-            let tok = self.next_token()?;
-            let is_eof = tok == Token::Eof;
-            tokens.push(tok);
-            if is_eof {
-                break;
-            }
+    /// Tokenize the whole input, returning the tokens and where each starts
+    pub fn tokenize(&mut self) -> Result<(Vec<Token>, Vec<Span>), Diagnostic> {
+        let pairs: Vec<(Token, Span)> = self.by_ref().collect::<Result<_, _>>()?;
+        Ok(pairs.into_iter().unzip())
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<(Token, Span), Diagnostic>;
+
+    /// The next token and where it starts
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
-        Ok(tokens)
+        self.skip_whitespace();
+        let span = self.span();
+        let result = self
+            .next_token()
+            .map(|tok| (tok, span))
+            .map_err(|message| Diagnostic::new(Some(span), message).with_code("lex-error"));
+        self.done = !matches!(result, Ok((ref tok, _)) if *tok != Token::Eof);
+        Some(result)
     }
 }
 
+/// A number with a type suffix, if it fits the type: INTEGER and LONG
+/// literals must be whole numbers in range, and SINGLE ones are rounded
+fn typed_number(value: f64, suffix: char) -> Option<Token> {
+    let range = match suffix {
+        '%' => i16::MIN as f64..=i16::MAX as f64,
+        '&' => i32::MIN as f64..=i32::MAX as f64,
+        '!' => return Some(Token::TypedNumber(value as f32 as f64, suffix)),
+        _ => return Some(Token::TypedNumber(value, suffix)),
+    };
+    (value.fract() == 0.0 && range.contains(&value)).then_some(Token::TypedNumber(value, suffix))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -437,7 +685,7 @@ mod tests {
     #[test]
     fn test_integer_literal() {
         let mut lexer = Lexer::new("X = 42");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[1], Token::Eq);
         assert_eq!(tokens[2], Token::Integer(42));
     }
@@ -445,18 +693,18 @@ mod tests {
     #[test]
     fn test_float_literal_decimal() {
         let mut lexer = Lexer::new("X = 1.23456");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[2], Token::Float(1.23456));
     }
 
     #[test]
     fn test_float_literal_exponent() {
         let mut lexer = Lexer::new("X = 1E5");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[2], Token::Float(100000.0));
 
         let mut lexer = Lexer::new("X = 2e-3");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[2], Token::Float(0.002));
     }
 
@@ -464,29 +712,67 @@ mod tests {
     fn test_float_literal_d_exponent() {
         // BASIC uses D for double-precision exponent
         let mut lexer = Lexer::new("X = 1D5");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[2], Token::Float(100000.0));
 
         let mut lexer = Lexer::new("X = 2d+3");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[2], Token::Float(2000.0));
     }
 
     #[test]
     fn test_hex_literal() {
         let mut lexer = Lexer::new("X = &HFF");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[2], Token::Integer(255));
 
         let mut lexer = Lexer::new("X = &h10");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[2], Token::Integer(16));
     }
 
+    #[test]
+    fn test_octal_binary_literals() {
+        let mut lexer = Lexer::new("X = &O777 + &o10");
+        let (tokens, _) = lexer.tokenize().unwrap();
+        assert_eq!(tokens[2], Token::Integer(511));
+        assert_eq!(tokens[4], Token::Integer(8));
+
+        let mut lexer = Lexer::new("X = &B1010 + &b0");
+        let (tokens, _) = lexer.tokenize().unwrap();
+        assert_eq!(tokens[2], Token::Integer(10));
+        assert_eq!(tokens[4], Token::Integer(0));
+    }
+
+    #[test]
+    fn test_bad_radix_literals() {
+        // Digits outside the base, no digits, and & followed by a digit
+        for source in ["X = &B102", "X = &O8", "X = &HFG", "X = &H", "X = &12"] {
+            let mut lexer = Lexer::new(source);
+            assert!(lexer.tokenize().is_err(), "{} should not lex", source);
+        }
+    }
+
+    #[test]
+    fn test_typed_number_literals() {
+        let mut lexer = Lexer::new("X = 32000% + 100000& + 1.5! + 1D0#");
+        let (tokens, _) = lexer.tokenize().unwrap();
+        assert_eq!(tokens[2], Token::TypedNumber(32000.0, '%'));
+        assert_eq!(tokens[4], Token::TypedNumber(100000.0, '&'));
+        assert_eq!(tokens[6], Token::TypedNumber(1.5, '!'));
+        assert_eq!(tokens[8], Token::TypedNumber(1.0, '#'));
+
+        // Out of range or not a whole number
+        for source in ["X = 40000%", "X = 3000000000&", "X = 1.5%"] {
+            let mut lexer = Lexer::new(source);
+            assert!(lexer.tokenize().is_err(), "{} should not lex", source);
+        }
+    }
+
     #[test]
     fn test_string_literal() {
         let mut lexer = Lexer::new("PRINT \"Hello, World!\"");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Print);
         assert_eq!(tokens[1], Token::String("Hello, World!".to_string()));
     }
@@ -494,7 +780,7 @@ mod tests {
     #[test]
     fn test_string_escaped_quote() {
         let mut lexer = Lexer::new("X$ = \"He said \"\"Hi\"\"\"");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[2], Token::String("He said \"Hi\"".to_string()));
     }
 
@@ -503,7 +789,7 @@ mod tests {
         let mut lexer = Lexer::new("X$ = \"unterminated");
         let result = lexer.tokenize();
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Unterminated"));
+        assert!(result.unwrap_err().message.contains("Unterminated"));
     }
 
     // ===================
@@ -513,7 +799,7 @@ mod tests {
     #[test]
     fn test_identifier() {
         let mut lexer = Lexer::new("MyVar COUNTER foo123");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Ident("MYVAR".to_string()));
         assert_eq!(tokens[1], Token::Ident("COUNTER".to_string()));
         assert_eq!(tokens[2], Token::Ident("FOO123".to_string()));
@@ -522,7 +808,7 @@ mod tests {
     #[test]
     fn test_type_suffix_all() {
         let mut lexer = Lexer::new("A% B& C! D# E$");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Ident("A%".to_string())); // integer
         assert_eq!(tokens[1], Token::Ident("B&".to_string())); // long
         assert_eq!(tokens[2], Token::Ident("C!".to_string())); // single
@@ -537,16 +823,20 @@ mod tests {
     #[test]
     fn test_keywords_print_input() {
         let mut lexer = Lexer::new("PRINT INPUT LINE");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Print);
         assert_eq!(tokens[1], Token::Input);
         assert_eq!(tokens[2], Token::Line);
+
+        let mut lexer = Lexer::new("? X");
+        let (tokens, _) = lexer.tokenize().unwrap();
+        assert_eq!(tokens[0], Token::Print);
     }
 
     #[test]
     fn test_keywords_assignment() {
         let mut lexer = Lexer::new("LET DIM");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Let);
         assert_eq!(tokens[1], Token::Dim);
     }
@@ -554,7 +844,7 @@ mod tests {
     #[test]
     fn test_keywords_conditionals() {
         let mut lexer = Lexer::new("IF THEN ELSE ELSEIF ENDIF");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::If);
         assert_eq!(tokens[1], Token::Then);
         assert_eq!(tokens[2], Token::Else);
@@ -565,7 +855,7 @@ mod tests {
     #[test]
     fn test_keywords_for_loop() {
         let mut lexer = Lexer::new("FOR TO STEP NEXT");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::For);
         assert_eq!(tokens[1], Token::To);
         assert_eq!(tokens[2], Token::Step);
@@ -575,7 +865,7 @@ mod tests {
     #[test]
     fn test_keywords_while_loop() {
         let mut lexer = Lexer::new("WHILE WEND");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::While);
         assert_eq!(tokens[1], Token::Wend);
     }
@@ -583,7 +873,7 @@ mod tests {
     #[test]
     fn test_keywords_do_loop() {
         let mut lexer = Lexer::new("DO LOOP UNTIL");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Do);
         assert_eq!(tokens[1], Token::Loop);
         assert_eq!(tokens[2], Token::Until);
@@ -592,7 +882,7 @@ mod tests {
     #[test]
     fn test_keywords_control_flow() {
         let mut lexer = Lexer::new("GOTO GOSUB RETURN ON");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Goto);
         assert_eq!(tokens[1], Token::Gosub);
         assert_eq!(tokens[2], Token::Return);
@@ -602,7 +892,7 @@ mod tests {
     #[test]
     fn test_keywords_procedures() {
         let mut lexer = Lexer::new("SUB ENDSUB FUNCTION ENDFUNCTION");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Sub);
         assert_eq!(tokens[1], Token::EndSub);
         assert_eq!(tokens[2], Token::Function);
@@ -612,7 +902,7 @@ mod tests {
     #[test]
     fn test_keywords_select_case() {
         let mut lexer = Lexer::new("SELECT CASE ENDSELECT");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Select);
         assert_eq!(tokens[1], Token::Case);
         assert_eq!(tokens[2], Token::EndSelect);
@@ -621,7 +911,7 @@ mod tests {
     #[test]
     fn test_keywords_program_control() {
         let mut lexer = Lexer::new("END STOP CLS");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::End);
         assert_eq!(tokens[1], Token::Stop);
         assert_eq!(tokens[2], Token::Cls);
@@ -630,7 +920,7 @@ mod tests {
     #[test]
     fn test_keywords_data() {
         let mut lexer = Lexer::new("DATA READ RESTORE");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Data);
         assert_eq!(tokens[1], Token::Read);
         assert_eq!(tokens[2], Token::Restore);
@@ -638,25 +928,87 @@ mod tests {
 
     #[test]
     fn test_keywords_logical() {
-        let mut lexer = Lexer::new("AND OR NOT XOR MOD");
-        let tokens = lexer.tokenize().unwrap();
+        let mut lexer = Lexer::new("AND OR NOT XOR MOD ANDALSO OrElse");
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::And);
         assert_eq!(tokens[1], Token::Or);
         assert_eq!(tokens[2], Token::Not);
         assert_eq!(tokens[3], Token::Xor);
         assert_eq!(tokens[4], Token::Mod);
+        assert_eq!(tokens[5], Token::AndAlso);
+        assert_eq!(tokens[6], Token::OrElse);
+    }
+
+    #[test]
+    fn test_keywords_graphics() {
+        let mut lexer = Lexer::new("SCREEN PSET PRESET CIRCLE PAINT DRAW GET PUT DISPLAY PALETTE");
+        let (tokens, _) = lexer.tokenize().unwrap();
+        assert_eq!(tokens[0], Token::Screen);
+        assert_eq!(tokens[1], Token::Pset);
+        assert_eq!(tokens[2], Token::Preset);
+        assert_eq!(tokens[3], Token::Circle);
+        assert_eq!(tokens[4], Token::Paint);
+        assert_eq!(tokens[5], Token::Draw);
+        assert_eq!(tokens[6], Token::Get);
+        assert_eq!(tokens[7], Token::Put);
+        assert_eq!(tokens[8], Token::Display);
+        assert_eq!(tokens[9], Token::Palette);
+    }
+
+    #[test]
+    fn test_keywords_events() {
+        let mut lexer = Lexer::new("ON KEY(1) GOSUB 100: KEY(1) OFF");
+        let (tokens, _) = lexer.tokenize().unwrap();
+        assert_eq!(tokens[0], Token::On);
+        assert_eq!(tokens[1], Token::Key);
+        assert_eq!(tokens[8], Token::Key);
+        assert_eq!(tokens[12], Token::Off);
     }
 
     #[test]
     fn test_keywords_case_insensitive() {
         let mut lexer = Lexer::new("print Print PRINT PrInT");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Print);
         assert_eq!(tokens[1], Token::Print);
         assert_eq!(tokens[2], Token::Print);
         assert_eq!(tokens[3], Token::Print);
     }
 
+    #[test]
+    fn test_dialect_keywords() {
+        let source = "10 DO LOOP SELECT ENDIF ANDALSO WHILE";
+        let tokens = |dialect| {
+            let mut lexer = Lexer::new(source).with_dialect(dialect);
+            lexer.tokenize().unwrap().0
+        };
+        let modern = tokens(Dialect::Modern);
+        assert_eq!(modern[1..4], [Token::Do, Token::Loop, Token::Select]);
+        assert_eq!(modern[4..6], [Token::EndIf, Token::AndAlso]);
+        let qb45 = tokens(Dialect::Qb45);
+        assert_eq!(qb45[1..4], [Token::Do, Token::Loop, Token::Select]);
+        assert_eq!(qb45[4], Token::Ident("ENDIF".to_string()));
+        assert_eq!(qb45[5], Token::Ident("ANDALSO".to_string()));
+        let gw = tokens(Dialect::Gw);
+        assert_eq!(gw[1], Token::Ident("DO".to_string()));
+        assert_eq!(gw[3], Token::Ident("SELECT".to_string()));
+        assert_eq!(gw[6], Token::While);
+    }
+
+    #[test]
+    fn test_gw_line_numbers() {
+        let mut lexer = Lexer::new("10 PRINT 1\n\n20 END\n").with_dialect(Dialect::Gw);
+        assert!(lexer.tokenize().is_ok());
+        let mut lexer = Lexer::new("10 PRINT 1\n  PRINT 2\n").with_dialect(Dialect::Gw);
+        let err = lexer.tokenize().unwrap_err();
+        assert!(
+            err.message.starts_with("Direct statement in file"),
+            "{}",
+            err.message
+        );
+        assert_eq!(err.span.map(|span| (span.line, span.col)), Some((2, 3)));
+    }
+
     // ===================
     // Operator Tests
     // ===================
@@ -664,7 +1016,7 @@ mod tests {
     #[test]
     fn test_arithmetic_operators() {
         let mut lexer = Lexer::new("+ - * / \\ ^");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Plus);
         assert_eq!(tokens[1], Token::Minus);
         assert_eq!(tokens[2], Token::Star);
@@ -676,7 +1028,7 @@ mod tests {
     #[test]
     fn test_comparison_operators() {
         let mut lexer = Lexer::new("= <> < > <= >=");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Eq);
         assert_eq!(tokens[1], Token::Ne);
         assert_eq!(tokens[2], Token::Lt);
@@ -692,7 +1044,7 @@ mod tests {
     #[test]
     fn test_punctuation() {
         let mut lexer = Lexer::new("( ) , ; :");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::LParen);
         assert_eq!(tokens[1], Token::RParen);
         assert_eq!(tokens[2], Token::Comma);
@@ -707,7 +1059,7 @@ mod tests {
     #[test]
     fn test_line_numbers() {
         let mut lexer = Lexer::new("10 PRINT\n20 END");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::LineNumber(10));
         assert_eq!(tokens[1], Token::Print);
         assert_eq!(tokens[2], Token::Newline);
@@ -718,7 +1070,7 @@ mod tests {
     #[test]
     fn test_newline() {
         let mut lexer = Lexer::new("A\nB\nC");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Ident("A".to_string()));
         assert_eq!(tokens[1], Token::Newline);
         assert_eq!(tokens[2], Token::Ident("B".to_string()));
@@ -730,7 +1082,7 @@ mod tests {
     #[test]
     fn test_eof() {
         let mut lexer = Lexer::new("");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens.len(), 1);
         assert_eq!(tokens[0], Token::Eof);
     }
@@ -742,7 +1094,7 @@ mod tests {
     #[test]
     fn test_rem_comment() {
         let mut lexer = Lexer::new("X = 1 REM this is a comment\nY = 2");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Ident("X".to_string()));
         assert_eq!(tokens[1], Token::Eq);
         assert_eq!(tokens[2], Token::Integer(1));
@@ -751,10 +1103,34 @@ mod tests {
         assert_eq!(tokens[5], Token::Ident("Y".to_string()));
     }
 
+    #[test]
+    fn test_comments_kept() {
+        let mut lexer = Lexer::new("' top\r\nX = 1 rem  note  \nPRINT \"'\"\n");
+        lexer.tokenize().unwrap();
+        let comments: Vec<(u32, u32, &str)> = lexer
+            .comments()
+            .iter()
+            .map(|c| (c.span.line, c.span.col, c.text.as_str()))
+            .collect();
+        assert_eq!(comments, [(1, 1, "' top"), (2, 7, "rem  note")]);
+    }
+
+    #[test]
+    fn test_asm_block() {
+        let mut lexer = Lexer::new("ASM ' raw\n  mov eax, {X%} ' keep\n\n end  asm\nASM = 1");
+        let (tokens, spans) = lexer.tokenize().unwrap();
+        let lines = vec!["  mov eax, {X%} ' keep".to_string(), String::new()];
+        assert_eq!(tokens[0], Token::Asm(lines));
+        assert_eq!(tokens[1], Token::Newline);
+        assert_eq!(tokens[2], Token::Ident("ASM".to_string()));
+        assert_eq!(spans[2].line, 5);
+        assert!(Lexer::new("ASM\nnop\n").tokenize().is_err());
+    }
+
     #[test]
     fn test_apostrophe_comment() {
         let mut lexer = Lexer::new("X = 1 ' this is a comment\nY = 2");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Ident("X".to_string()));
         assert_eq!(tokens[1], Token::Eq);
         assert_eq!(tokens[2], Token::Integer(1));
@@ -770,7 +1146,7 @@ mod tests {
     #[test]
     fn test_for_loop_statement() {
         let mut lexer = Lexer::new("FOR I = 1 TO 10 STEP 2");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::For);
         assert_eq!(tokens[1], Token::Ident("I".to_string()));
         assert_eq!(tokens[2], Token::Eq);
@@ -784,7 +1160,7 @@ mod tests {
     #[test]
     fn test_function_call() {
         let mut lexer = Lexer::new("X = SIN(1.23) + COS(0)");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Ident("X".to_string()));
         assert_eq!(tokens[1], Token::Eq);
         assert_eq!(tokens[2], Token::Ident("SIN".to_string()));
@@ -801,7 +1177,7 @@ mod tests {
     #[test]
     fn test_if_statement() {
         let mut lexer = Lexer::new("IF X > 10 AND Y < 5 THEN PRINT X");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::If);
         assert_eq!(tokens[1], Token::Ident("X".to_string()));
         assert_eq!(tokens[2], Token::Gt);
@@ -818,7 +1194,7 @@ mod tests {
     #[test]
     fn test_print_with_separators() {
         let mut lexer = Lexer::new("PRINT A; B, C");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Print);
         assert_eq!(tokens[1], Token::Ident("A".to_string()));
         assert_eq!(tokens[2], Token::Semicolon);
@@ -830,7 +1206,7 @@ mod tests {
     #[test]
     fn test_dim_array() {
         let mut lexer = Lexer::new("DIM A(10), B$(100)");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Dim);
         assert_eq!(tokens[1], Token::Ident("A".to_string()));
         assert_eq!(tokens[2], Token::LParen);
@@ -846,7 +1222,7 @@ mod tests {
     #[test]
     fn test_colon_statement_separator() {
         let mut lexer = Lexer::new("X = 1 : Y = 2 : PRINT X");
-        let tokens = lexer.tokenize().unwrap();
+        let (tokens, _) = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Ident("X".to_string()));
         assert_eq!(tokens[1], Token::Eq);
         assert_eq!(tokens[2], Token::Integer(1));
@@ -863,6 +1239,40 @@ mod tests {
         let mut lexer = Lexer::new("X = @");
         let result = lexer.tokenize();
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Unexpected character"));
+        assert!(result.unwrap_err().message.contains("Unexpected character"));
+    }
+
+    #[test]
+    fn test_token_stream() {
+        // The iterator ends after Eof, or after the first error
+        let tokens: Vec<_> = Lexer::new("X").map(|r| r.unwrap().0).collect();
+        assert_eq!(tokens, vec![Token::Ident("X".to_string()), Token::Eof]);
+        let results: Vec<_> = Lexer::new("X = @ 1").collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[2].is_err());
+    }
+
+    // ===================
+    // Span Tests
+    // ===================
+
+    #[test]
+    fn test_token_spans() {
+        let (tokens, spans) = Lexer::new("10 PRINT X\n  Y = \"a\"").tokenize().unwrap();
+        assert_eq!(tokens.len(), spans.len());
+        let at = |line, col| Span { line, col };
+        assert_eq!(spans[0], at(1, 1)); // 10
+        assert_eq!(spans[1], at(1, 4)); // PRINT
+        assert_eq!(spans[2], at(1, 10)); // X
+        assert_eq!(spans[3], at(1, 11)); // newline
+        assert_eq!(spans[4], at(2, 3)); // Y
+        assert_eq!(spans[6], at(2, 7)); // "a"
+        assert_eq!(spans[7], at(2, 10)); // end of input
+    }
+
+    #[test]
+    fn test_error_span() {
+        let err = Lexer::new("X = 1\nPRINT \"abc").tokenize().unwrap_err();
+        assert_eq!(err.span, Some(Span { line: 2, col: 7 }));
     }
 }
@@ -0,0 +1,63 @@
+//! BASIC-to-x86_64 Compiler
+//!
+//! Compiles 1980s-era BASIC programs to x86-64 executables.
+//! Supports Linux, macOS, and Windows (MinGW).
+//!
+//! The xbasic64 command is a thin layer over this library. To compile from
+//! another program, make a `Compiler` with the `CompilerOptions` wanted:
+//!
+//! ```no_run
+//! use xbasic64::{Compiler, CompilerOptions};
+//!
+//! let mut compiler = Compiler::new(CompilerOptions::default());
+//! match compiler.compile_to_executable("hello.bas") {
+//!     Ok(exe) => println!("Built {}", exe.display()),
+//!     Err(diagnostics) => eprintln!("{}", diagnostics),
+//! }
+//! ```
+//!
+//! Errors and warnings come back as `Diagnostics`, each with its file, span,
+//! severity, code and message, to print as the command does or to show
+//! some other way. The lexer, parser and code generator are public too, for
+//! tools that work on the tokens, the syntax tree or the generated code.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+pub mod abi;
+mod assembler;
+pub mod codegen;
+pub mod compiler;
+mod dce;
+pub mod diagnostic;
+mod elf;
+mod emit;
+mod encoder;
+mod fold;
+pub mod include;
+pub mod interp;
+pub mod ir;
+pub mod lexer;
+mod linker;
+pub mod lsp;
+mod modules;
+pub mod parser;
+mod peephole;
+pub mod printer;
+mod regalloc;
+pub mod renum;
+pub mod repl;
+mod runtime;
+mod semantic;
+pub mod spec;
+mod types;
+mod warnings;
+pub mod xref;
+
+pub use abi::Target;
+pub use codegen::CodeGen;
+pub use compiler::{Checks, Compiler, CompilerOptions, Dialect, Output, Runtime};
+pub use diagnostic::{Diagnostic, Diagnostics, Located, Severity};
+pub use include::Source;
+pub use lexer::{Lexer, Span, Token};
+pub use parser::{Expr, Parser, Program, Stmt, StmtKind};
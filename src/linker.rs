@@ -0,0 +1,636 @@
+//! Built-in linker - turns the assembler's object into a runnable Linux
+//! executable, so compiling needs neither `as` nor `cc`
+//!
+//! The runtime is built on the C library, and the part of it every glibc
+//! system has is the shared library itself. So the executable is laid out
+//! the way `cc -no-pie` lays it out - at a fixed address, with the
+//! object's own relocations applied here - and each C library function it
+//! calls goes through a PLT stub and a GOT slot that the dynamic loader
+//! fills in at startup from libc.so.6 or libm.so.6. `START` takes the
+//! place of crt1.o.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::elf::{self, Object, Reloc, Section, SectionKind, Symbol, Target};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// The glibc dynamic loader, named in the executable
+const INTERP: &str = "/lib64/ld-linux-x86-64.so.2";
+/// Shared libraries the executable needs
+const NEEDED: [&str; 2] = ["libc.so.6", "libm.so.6"];
+/// Where the dynamic loader finds them
+const LIB_DIRS: [&str; 6] = [
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib64",
+    "/usr/lib64",
+    "/lib",
+    "/usr/lib",
+];
+
+const BASE: u64 = 0x400000;
+const PAGE: u64 = 0x1000;
+
+const HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const PROGRAM_HEADERS: u64 = 7;
+const SYMBOL_SIZE: u64 = 24;
+const RELA_SIZE: u64 = 24;
+/// `jmp QWORD PTR [rip + slot]` and two bytes of nop
+const PLT_ENTRY_SIZE: u64 = 8;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+const PT_GNU_STACK: u32 = 0x6474_E551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_STRSZ: u64 = 10;
+const DT_SYMENT: u64 = 11;
+const DT_DEBUG: u64 = 21;
+
+const SHT_DYNSYM: u32 = 11;
+const R_X86_64_GLOB_DAT: u64 = 6;
+const STB_GLOBAL: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// The entry point, appended to the program before it's assembled: hands
+/// `main` to the C library, as crt1.o does, and supplies the one function
+/// the runtime calls that the shared library doesn't export
+pub const START: &str = "
+.text
+.globl _start
+_start:
+    xor ebp, ebp
+    mov r9, rdx                 # the dynamic loader's exit hook
+    pop rsi                     # argc
+    mov rdx, rsp                # argv
+    and rsp, -16
+    push rax
+    push rsp
+    xor r8d, r8d
+    xor ecx, ecx
+    mov rdi, main
+    call __libc_start_main
+    hlt
+
+# atexit comes from libc_nonshared.a, not the shared library
+atexit:
+    xor esi, esi
+    xor edx, edx
+    jmp __cxa_atexit
+";
+
+/// Whether executables linked here can run on this system
+pub fn available() -> bool {
+    Path::new(INTERP).exists()
+}
+
+fn align(n: u64, to: u64) -> u64 {
+    n.next_multiple_of(to.max(1))
+}
+
+fn put(image: &mut [u8], at: u64, bytes: &[u8]) {
+    image[at as usize..at as usize + bytes.len()].copy_from_slice(bytes);
+}
+
+/// The names a shared library defines, from its dynamic symbol table;
+/// None if it can't be found or read
+fn exports(lib: &str) -> Option<HashSet<String>> {
+    let bytes = LIB_DIRS
+        .iter()
+        .find_map(|dir| std::fs::read(Path::new(dir).join(lib)).ok())?;
+    let u16_at = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+    let u32_at = |at: usize| Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+    let u64_at = |at: usize| Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?));
+
+    // Section headers: type at 4, offset at 24, size at 32, link at 40
+    let headers = u64_at(0x28)? as usize;
+    let header_size = u16_at(0x3A)? as usize;
+    let header = |i: usize| headers + i * header_size;
+    let dynsym = (0..u16_at(0x3C)? as usize)
+        .map(header)
+        .find(|&h| u32_at(h + 4) == Some(SHT_DYNSYM))?;
+    let strings = u64_at(header(u32_at(dynsym + 40)? as usize) + 24)? as usize;
+    let symbols = u64_at(dynsym + 24)? as usize;
+    let end = symbols + u64_at(dynsym + 32)? as usize;
+
+    let mut names = HashSet::new();
+    for symbol in (symbols..end).step_by(SYMBOL_SIZE as usize) {
+        if u16_at(symbol + 6)? == 0 {
+            continue; // the library's own import
+        }
+        let name = bytes.get(strings + u32_at(symbol)? as usize..)?;
+        let len = name.iter().position(|&b| b == 0)?;
+        names.insert(String::from_utf8_lossy(&name[..len]).into_owned());
+    }
+    Some(names)
+}
+
+/// Fail on a symbol that neither the objects nor the libraries define, as
+/// cc would, rather than leave it for the dynamic loader to stop the
+/// program over. If a library can't be read, the loader has the last word.
+fn check_imports(imports: &[&str]) -> Result<(), String> {
+    let mut defined = HashSet::new();
+    for lib in NEEDED {
+        match exports(lib) {
+            Some(names) => defined.extend(names),
+            None => return Ok(()),
+        }
+    }
+    let missing: Vec<&str> = imports
+        .iter()
+        .copied()
+        .filter(|name| !defined.contains(*name))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("undefined reference to {}", missing.join(", ")))
+    }
+}
+
+/// Put objects together as one: each reference to a symbol another object
+/// defines as global goes to that definition, and the references left
+/// over share one undefined symbol per name
+fn combine(objects: &[Object]) -> Result<Object, String> {
+    let mut combined = Object::default();
+    let mut section_base = Vec::with_capacity(objects.len());
+    for object in objects {
+        section_base.push(combined.sections.len());
+        for section in &object.sections {
+            combined.sections.push(Section {
+                name: section.name,
+                kind: section.kind,
+                data: section.data.clone(),
+                size: section.size,
+                align: section.align,
+                relocs: Vec::new(),
+            });
+        }
+    }
+
+    // Definitions first, so a reference in any object can find them
+    let mut index: Vec<Vec<usize>> = objects
+        .iter()
+        .map(|object| vec![0; object.symbols.len()])
+        .collect();
+    let mut by_name: HashMap<&str, usize> = HashMap::new();
+    for (o, object) in objects.iter().enumerate() {
+        for (s, symbol) in object.symbols.iter().enumerate() {
+            let Some(section) = symbol.section else {
+                continue;
+            };
+            if symbol.global
+                && by_name
+                    .insert(&symbol.name, combined.symbols.len())
+                    .is_some()
+            {
+                return Err(format!("{} is defined more than once", symbol.name));
+            }
+            index[o][s] = combined.symbols.len();
+            combined.symbols.push(Symbol {
+                section: Some(section_base[o] + section),
+                ..symbol.clone()
+            });
+        }
+    }
+    for (o, object) in objects.iter().enumerate() {
+        for (s, symbol) in object.symbols.iter().enumerate() {
+            if symbol.section.is_none() {
+                index[o][s] = *by_name.entry(&symbol.name).or_insert_with(|| {
+                    combined.symbols.push(symbol.clone());
+                    combined.symbols.len() - 1
+                });
+            }
+        }
+    }
+
+    for (o, object) in objects.iter().enumerate() {
+        for (i, section) in object.sections.iter().enumerate() {
+            combined.sections[section_base[o] + i].relocs = section
+                .relocs
+                .iter()
+                .map(|reloc| Reloc {
+                    target: match reloc.target {
+                        Target::Section(s) => Target::Section(section_base[o] + s),
+                        Target::Symbol(s) => Target::Symbol(index[o][s]),
+                    },
+                    ..reloc.clone()
+                })
+                .collect();
+        }
+    }
+    Ok(combined)
+}
+
+/// Link objects (the first assembled with `START`) into the bytes of an
+/// executable
+pub fn link(objects: &[Object]) -> Result<Vec<u8>, String> {
+    let combined;
+    let object = match objects {
+        [object] => object,
+        _ => {
+            combined = combine(objects)?;
+            &combined
+        }
+    };
+
+    // Every undefined symbol is a function imported from a library, which
+    // must define it
+    let mut imports: Vec<&str> = Vec::new();
+    let mut import_index = vec![None; object.symbols.len()];
+    for (i, symbol) in object.symbols.iter().enumerate() {
+        if symbol.section.is_none() {
+            import_index[i] = Some(imports.len() as u64);
+            imports.push(&symbol.name);
+        }
+    }
+    check_imports(&imports)?;
+    let count = imports.len() as u64;
+
+    // Dynamic symbols, their names, and a one-bucket hash table over them
+    let mut dynstr = vec![0u8];
+    let mut add_string = |s: &str| {
+        let offset = dynstr.len() as u64;
+        dynstr.extend_from_slice(s.as_bytes());
+        dynstr.push(0);
+        offset
+    };
+    let needed: Vec<u64> = NEEDED.iter().map(|lib| add_string(lib)).collect();
+    let mut dynsym = vec![0u8; SYMBOL_SIZE as usize];
+    for name in &imports {
+        dynsym.extend_from_slice(&(add_string(name) as u32).to_le_bytes());
+        dynsym.push(STB_GLOBAL << 4 | STT_FUNC);
+        dynsym.push(0);
+        dynsym.extend_from_slice(&[0; 2 + 8 + 8]); // undefined, no value or size
+    }
+    let mut hash: Vec<u32> = vec![1, count as u32 + 1, if count > 0 { 1 } else { 0 }, 0];
+    hash.extend((1..=count as u32).map(|i| if i < count as u32 { i + 1 } else { 0 }));
+    let hash: Vec<u8> = hash.iter().flat_map(|w| w.to_le_bytes()).collect();
+
+    // Read-only segment: headers, then what the dynamic loader reads
+    let interp_offset = HEADER_SIZE + PROGRAM_HEADER_SIZE * PROGRAM_HEADERS;
+    let hash_offset = align(interp_offset + INTERP.len() as u64 + 1, 8);
+    let dynsym_offset = align(hash_offset + hash.len() as u64, 8);
+    let dynstr_offset = dynsym_offset + dynsym.len() as u64;
+    let rela_offset = align(dynstr_offset + dynstr.len() as u64, 8);
+    let rela_size = RELA_SIZE * count;
+
+    // ... then the read-only data sections
+    let mut addresses = vec![0; object.sections.len()];
+    let mut offset = rela_offset + rela_size;
+    for (i, section) in object.sections.iter().enumerate() {
+        if section.kind == SectionKind::Rodata {
+            offset = align(offset, section.align);
+            addresses[i] = BASE + offset;
+            offset += section.size;
+        }
+    }
+    let readonly_end = offset;
+
+    // Code segment: the code sections, then the PLT
+    offset = align(readonly_end, PAGE);
+    let code_offset = offset;
+    for (i, section) in object.sections.iter().enumerate() {
+        if section.kind == SectionKind::Text {
+            offset = align(offset, section.align);
+            addresses[i] = BASE + offset;
+            offset += section.size;
+        }
+    }
+    let plt_offset = align(offset, PLT_ENTRY_SIZE);
+    let code_end = plt_offset + PLT_ENTRY_SIZE * count;
+
+    // Data segment: the dynamic section and GOT, data, then the zeroes
+    let dynamic_offset = align(code_end, PAGE);
+    let dynamic_entries = NEEDED.len() as u64 + 10;
+    let got_offset = dynamic_offset + 16 * dynamic_entries;
+    offset = got_offset + 8 * count;
+    for (i, section) in object.sections.iter().enumerate() {
+        if section.kind == SectionKind::Data {
+            offset = align(offset, section.align);
+            addresses[i] = BASE + offset;
+            offset += section.size;
+        }
+    }
+    let data_end = offset;
+    for (i, section) in object.sections.iter().enumerate() {
+        if section.kind == SectionKind::Bss {
+            offset = align(offset, section.align);
+            addresses[i] = BASE + offset;
+            offset += section.size;
+        }
+    }
+    let memory_end = offset;
+    let plt = BASE + plt_offset;
+    let got = BASE + got_offset;
+
+    let mut image = vec![0u8; data_end as usize];
+
+    // Apply the object's relocations to each section's contents
+    for (i, section) in object.sections.iter().enumerate() {
+        if section.kind == SectionKind::Bss {
+            continue;
+        }
+        let mut data = section.data.clone();
+        for reloc in &section.relocs {
+            let target = match reloc.target {
+                Target::Section(s) => addresses[s],
+                Target::Symbol(s) => match (object.symbols[s].section, import_index[s]) {
+                    (Some(section), _) => addresses[section] + object.symbols[s].value,
+                    (None, Some(import)) => plt + PLT_ENTRY_SIZE * import,
+                    (None, None) => unreachable!("undefined symbols are imports"),
+                },
+            };
+            let place = addresses[i] + reloc.offset;
+            let value = (target as i64).wrapping_add(reloc.addend);
+            let (value, width, fits) = match reloc.kind {
+                elf::R_X86_64_PC32 | elf::R_X86_64_PLT32 => {
+                    let value = value.wrapping_sub(place as i64);
+                    (value, 4, i32::try_from(value).is_ok())
+                }
+                elf::R_X86_64_64 => (value, 8, true),
+                elf::R_X86_64_32S => (value, 4, i32::try_from(value).is_ok()),
+                elf::R_X86_64_32 => (value, 4, u32::try_from(value).is_ok()),
+                elf::R_X86_64_16 => (value, 2, u16::try_from(value).is_ok()),
+                elf::R_X86_64_8 => (value, 1, u8::try_from(value).is_ok()),
+                kind => return Err(format!("unsupported relocation type {}", kind)),
+            };
+            if !fits {
+                return Err(format!(
+                    "relocation in {} at {:#x} out of range",
+                    section.name, reloc.offset
+                ));
+            }
+            let at = reloc.offset as usize;
+            data[at..at + width].copy_from_slice(&value.to_le_bytes()[..width]);
+        }
+        put(&mut image, addresses[i] - BASE, &data);
+    }
+
+    // PLT stubs jump through their GOT slots, which the loader fills in
+    let mut rela = Vec::new();
+    for i in 0..count {
+        let stub = plt_offset + PLT_ENTRY_SIZE * i;
+        let slot = got + 8 * i;
+        let displacement = slot as i64 - (BASE + stub + 6) as i64;
+        let mut entry = vec![0xFF, 0x25];
+        entry.extend_from_slice(&(displacement as i32).to_le_bytes());
+        entry.extend_from_slice(&[0x66, 0x90]);
+        put(&mut image, stub, &entry);
+        rela.extend_from_slice(&slot.to_le_bytes());
+        rela.extend_from_slice(&((i + 1) << 32 | R_X86_64_GLOB_DAT).to_le_bytes());
+        rela.extend_from_slice(&0i64.to_le_bytes());
+    }
+
+    let mut interp = INTERP.as_bytes().to_vec();
+    interp.push(0);
+    put(&mut image, interp_offset, &interp);
+    put(&mut image, hash_offset, &hash);
+    put(&mut image, dynsym_offset, &dynsym);
+    put(&mut image, dynstr_offset, &dynstr);
+    put(&mut image, rela_offset, &rela);
+
+    let mut dynamic: Vec<(u64, u64)> = needed.iter().map(|&name| (DT_NEEDED, name)).collect();
+    dynamic.extend([
+        (DT_HASH, BASE + hash_offset),
+        (DT_STRTAB, BASE + dynstr_offset),
+        (DT_SYMTAB, BASE + dynsym_offset),
+        (DT_STRSZ, dynstr.len() as u64),
+        (DT_SYMENT, SYMBOL_SIZE),
+        (DT_RELA, BASE + rela_offset),
+        (DT_RELASZ, rela_size),
+        (DT_RELAENT, RELA_SIZE),
+        (DT_DEBUG, 0),
+        (DT_NULL, 0),
+    ]);
+    assert_eq!(dynamic.len() as u64, dynamic_entries);
+    let dynamic: Vec<u8> = dynamic
+        .iter()
+        .flat_map(|&(tag, value)| [tag.to_le_bytes(), value.to_le_bytes()])
+        .flatten()
+        .collect();
+    put(&mut image, dynamic_offset, &dynamic);
+
+    // Program headers: (type, flags, offset, file size, memory size, alignment)
+    let headers = [
+        (
+            PT_PHDR,
+            PF_R,
+            HEADER_SIZE,
+            PROGRAM_HEADER_SIZE * PROGRAM_HEADERS,
+            PROGRAM_HEADER_SIZE * PROGRAM_HEADERS,
+            8,
+        ),
+        (
+            PT_INTERP,
+            PF_R,
+            interp_offset,
+            interp.len() as u64,
+            interp.len() as u64,
+            1,
+        ),
+        (PT_LOAD, PF_R, 0, readonly_end, readonly_end, PAGE),
+        (
+            PT_LOAD,
+            PF_R | PF_X,
+            code_offset,
+            code_end - code_offset,
+            code_end - code_offset,
+            PAGE,
+        ),
+        (
+            PT_LOAD,
+            PF_R | PF_W,
+            dynamic_offset,
+            data_end - dynamic_offset,
+            memory_end - dynamic_offset,
+            PAGE,
+        ),
+        (
+            PT_DYNAMIC,
+            PF_R | PF_W,
+            dynamic_offset,
+            dynamic.len() as u64,
+            dynamic.len() as u64,
+            8,
+        ),
+        (PT_GNU_STACK, PF_R | PF_W, 0, 0, 0, 16),
+    ];
+    let mut program_headers = Vec::new();
+    for (kind, flags, offset, file_size, memory_size, alignment) in headers {
+        let address = if kind == PT_GNU_STACK {
+            0
+        } else {
+            BASE + offset
+        };
+        program_headers.extend_from_slice(&kind.to_le_bytes());
+        program_headers.extend_from_slice(&flags.to_le_bytes());
+        program_headers.extend_from_slice(&offset.to_le_bytes());
+        program_headers.extend_from_slice(&address.to_le_bytes()); // virtual
+        program_headers.extend_from_slice(&address.to_le_bytes()); // physical
+        program_headers.extend_from_slice(&file_size.to_le_bytes());
+        program_headers.extend_from_slice(&memory_size.to_le_bytes());
+        program_headers.extend_from_slice(&alignment.to_le_bytes());
+    }
+    put(&mut image, HEADER_SIZE, &program_headers);
+
+    let entry = object
+        .symbols
+        .iter()
+        .find(|s| s.name == "_start")
+        .and_then(|s| Some(addresses[s.section?] + s.value))
+        .ok_or("no _start entry point")?;
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(b"\x7fELF");
+    header.extend_from_slice(&[2, 1, 1, 0]); // 64-bit, little-endian, version 1, System V
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&2u16.to_le_bytes()); // executable
+    header.extend_from_slice(&62u16.to_le_bytes()); // x86-64
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.extend_from_slice(&entry.to_le_bytes());
+    header.extend_from_slice(&HEADER_SIZE.to_le_bytes()); // program headers
+    header.extend_from_slice(&0u64.to_le_bytes()); // no section headers
+    header.extend_from_slice(&0u32.to_le_bytes()); // flags
+    header.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(PROGRAM_HEADERS as u16).to_le_bytes());
+    header.extend_from_slice(&[0; 6]); // section header size, count, names
+    put(&mut image, 0, &header);
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn u64_at(bytes: &[u8], at: u64) -> u64 {
+        u64::from_le_bytes(bytes[at as usize..at as usize + 8].try_into().unwrap())
+    }
+
+    // ===================
+    // Executable Layout Tests
+    // ===================
+
+    #[test]
+    fn test_executable_layout() {
+        let text = format!(
+            ".intel_syntax noprefix\n.globl main\nmain:\n    call exit\n    \
+             lea rax, [rip + _n]\n.data\n_n: .quad main\n{}",
+            START
+        );
+        let image = link(&[assemble(&text).unwrap()]).unwrap();
+        assert_eq!(&image[..4], b"\x7fELF");
+        assert_eq!(image[16], 2, "executable");
+
+        // The loader is named, main starts the code segment with _start
+        // after it, and .data holds main's address
+        let interp = HEADER_SIZE + PROGRAM_HEADER_SIZE * PROGRAM_HEADERS;
+        assert_eq!(
+            &image[interp as usize..interp as usize + INTERP.len()],
+            INTERP.as_bytes()
+        );
+        let main = BASE + PAGE;
+        assert!(u64_at(&image, 24) > main);
+        assert_eq!(u64_at(&image, image.len() as u64 - 8), main);
+
+        // The call to exit goes to a PLT stub: jmp [rip + slot]
+        let call = (main - BASE) as usize;
+        assert_eq!(image[call], 0xE8);
+        let displacement = i32::from_le_bytes(image[call + 1..call + 5].try_into().unwrap());
+        let stub = (call as i64 + 5 + displacement as i64) as usize;
+        assert_eq!(&image[stub..stub + 2], [0xFF, 0x25]);
+    }
+
+    #[test]
+    fn test_link_objects() {
+        // main calls twice, which another object defines; both call exit
+        let main = format!(
+            ".intel_syntax noprefix\n.globl main\nmain:\n    call twice\n    call exit\n{}",
+            START
+        );
+        let twice = ".intel_syntax noprefix\n.globl twice\ntwice:\n    \
+                     lea rax, [rip + _n]\n    call exit\n.data\n_n: .quad twice\n";
+        let objects = [assemble(&main).unwrap(), assemble(twice).unwrap()];
+        let combined = combine(&objects).unwrap();
+        let names: Vec<&str> = combined.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names.iter().filter(|&&n| n == "twice").count(), 1);
+        assert_eq!(names.iter().filter(|&&n| n == "exit").count(), 1);
+        assert!(
+            combined
+                .symbols
+                .iter()
+                .all(|s| s.section.is_some() || s.name != "twice")
+        );
+        let image = link(&objects).unwrap();
+        assert_eq!(&image[..4], b"\x7fELF");
+
+        // The data pointing at twice is in the second object's .data,
+        // after the code
+        let twice_at = u64_at(&image, image.len() as u64 - 8);
+        assert!(twice_at > BASE + PAGE);
+
+        let again = assemble(twice).unwrap();
+        let objects = [assemble(&main).unwrap(), assemble(twice).unwrap(), again];
+        assert_eq!(
+            link(&objects).unwrap_err(),
+            "twice is defined more than once"
+        );
+    }
+
+    #[test]
+    fn test_rodata_layout() {
+        // .rodata goes in the read-only segment, ahead of the code
+        let text = format!(
+            ".intel_syntax noprefix\n.globl main\nmain:\n    lea rax, [rip + _s]\n    \
+             call exit\n.section .rodata\n_s: .ascii \"hi\"\n{}",
+            START
+        );
+        let image = link(&[assemble(&text).unwrap()]).unwrap();
+        let main = PAGE as usize; // file offsets are addresses less BASE
+        assert_eq!(&image[main..main + 3], [0x48, 0x8D, 0x05]);
+        let displacement = i32::from_le_bytes(image[main + 3..main + 7].try_into().unwrap());
+        let s = (main as i64 + 7 + displacement as i64) as usize;
+        assert!(s < PAGE as usize);
+        assert_eq!(&image[s..s + 2], b"hi");
+    }
+
+    #[test]
+    fn test_undefined_reference() {
+        // A call to a function no library has fails here, not at run time
+        if exports("libc.so.6").is_none() {
+            return;
+        }
+        assert!(exports("libm.so.6").unwrap().contains("sin"));
+        let text = format!(
+            ".intel_syntax noprefix\n.globl main\nmain:\n    call _proc_FOO\n    \
+             call exit\n{}",
+            START
+        );
+        assert_eq!(
+            link(&[assemble(&text).unwrap()]).unwrap_err(),
+            "undefined reference to _proc_FOO"
+        );
+    }
+
+    #[test]
+    fn test_missing_entry() {
+        let object = assemble(".intel_syntax noprefix\nmain:\n    ret\n").unwrap();
+        assert!(link(&[object]).is_err());
+    }
+}
@@ -0,0 +1,567 @@
+//! Language server - `xbasic64 lsp`
+//!
+//! Speaks the Language Server Protocol over standard input and output, so
+//! an editor can show the compiler's errors as the program is typed and
+//! find its way around it:
+//!
+//! - Diagnostics: each time a document is opened or changed it is parsed
+//!   and checked, as `--check` would, and its errors and warnings are sent.
+//! - Go to definition: on a procedure name, its SUB or FUNCTION (else its
+//!   DECLARE); on a line number, the line. Line numbers are looked up in the
+//!   procedure the cursor is in, as jumps are.
+//! - Document symbols: the SUBs and FUNCTIONs, and the line numbers.
+//!
+//! Documents are synced whole. Messages are JSON-RPC, each after a
+//! `Content-Length` header.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::compiler::{Compiler, CompilerOptions};
+use crate::diagnostic::{Diagnostics, Severity};
+use crate::lexer::{Lexer, Span};
+use crate::parser::{Parser, Program, Stmt, StmtKind};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+/// LSP SymbolKind values
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_NUMBER: u32 = 16;
+
+/// JSON-RPC error code for a request the server doesn't handle
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Serve one client until it sends `exit` or closes the input
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        out: output,
+        documents: HashMap::new(),
+    };
+    while let Some(message) = read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        server.handle(&message)?;
+    }
+    Ok(())
+}
+
+/// Read one message, or None at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+struct Server<W> {
+    out: W,
+    documents: HashMap<String, String>, // text of each open document by URI
+}
+
+impl<W: Write> Server<W> {
+    fn handle(&mut self, message: &Value) -> io::Result<()> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let method = message["method"].as_str().unwrap_or_default();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1, // full text on each change
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": {
+                    "name": "xbasic64",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+                return self.publish(uri);
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()?["text"].as_str()) {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                return self.publish(uri);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return self.publish(uri);
+            }
+            "textDocument/definition" => {
+                let text = self.documents.get(uri).map(String::as_str);
+                let line = params["position"]["line"].as_u64().unwrap_or_default();
+                let character = params["position"]["character"].as_u64().unwrap_or_default();
+                text.and_then(|text| definition(uri, text, line as usize, character as usize))
+                    .unwrap_or(Value::Null)
+            }
+            "textDocument/documentSymbol" => match self.documents.get(uri) {
+                Some(text) => symbols(uri, text),
+                None => Value::Null,
+            },
+            _ => {
+                // Notifications the server doesn't use need no answer
+                if message.get("id").is_none() {
+                    return Ok(());
+                }
+                let error = json!({
+                    "code": METHOD_NOT_FOUND,
+                    "message": format!("Unknown method {}", method),
+                });
+                return self.send(&json!({"jsonrpc": "2.0", "id": message["id"], "error": error}));
+            }
+        };
+        self.send(&json!({"jsonrpc": "2.0", "id": message["id"], "result": result}))
+    }
+
+    /// Send a document's diagnostics (none once it is closed)
+    fn publish(&mut self, uri: &str) -> io::Result<()> {
+        let list = match self.documents.get(uri) {
+            Some(text) => diagnostics(uri, text),
+            None => Vec::new(),
+        };
+        self.send(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": list},
+        }))
+    }
+
+    fn send(&mut self, message: &Value) -> io::Result<()> {
+        let body = message.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+}
+
+/// The file a `file:` URI names
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+/// Check a document as `--check` does, for its errors and warnings
+fn diagnostics(uri: &str, text: &str) -> Vec<Value> {
+    let path = uri_path(uri);
+    let name = path
+        .as_ref()
+        .map_or(uri.to_string(), |p| p.display().to_string());
+    let mut compiler = Compiler::new(CompilerOptions::default());
+    let found = compiler
+        .load(&name, text.to_string(), path.as_deref())
+        .and_then(|source| {
+            let programs = [compiler.parse(&source)?];
+            compiler.check(&[source], &programs, false)
+        });
+    let found = match found {
+        Ok(()) => compiler.warnings().clone(),
+        Err(list) => list,
+    };
+    lsp_diagnostics(&name, text, &found)
+}
+
+fn lsp_diagnostics(name: &str, text: &str, found: &Diagnostics) -> Vec<Value> {
+    found
+        .iter()
+        .map(|located| {
+            let diag = &located.diagnostic;
+            // Problems in an included file, or in no file, go on the first line
+            let (span, message) = match diag.span {
+                Some(span) if located.file == name => (span, diag.message.clone()),
+                _ if located.file.is_empty() || located.file == name => {
+                    (Span { line: 1, col: 1 }, diag.message.clone())
+                }
+                _ => (
+                    Span { line: 1, col: 1 },
+                    format!("{}: {}", located.file, diag.message),
+                ),
+            };
+            let severity = match diag.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            };
+            json!({
+                "range": word_range(text, span),
+                "severity": severity,
+                "code": diag.code,
+                "source": "xbasic64",
+                "message": message,
+            })
+        })
+        .collect()
+}
+
+/// The procedures and line numbers a parsed document defines
+struct Outline<'a> {
+    procs: Vec<(&'a str, Span, u32)>, // name, header, line the next top-level statement is on
+    declares: Vec<(&'a str, Span)>,
+    lines: Vec<(u32, Option<&'a str>, Span)>, // number, procedure it is in
+}
+
+fn outline(program: &Program) -> Outline<'_> {
+    let mut outline = Outline {
+        procs: Vec::new(),
+        declares: Vec::new(),
+        lines: Vec::new(),
+    };
+    let stmts = &program.statements;
+    for (i, stmt) in stmts.iter().enumerate() {
+        match &stmt.kind {
+            StmtKind::Sub { name, body, .. } | StmtKind::Function { name, body, .. } => {
+                let end = stmts.get(i + 1).map_or(u32::MAX, |next| next.span.line);
+                outline.procs.push((name, stmt.span, end));
+                collect_lines(body, Some(name), &mut outline.lines);
+            }
+            StmtKind::Declare { name, .. } => outline.declares.push((name, stmt.span)),
+            _ => collect_lines(std::slice::from_ref(stmt), None, &mut outline.lines),
+        }
+    }
+    outline
+}
+
+fn collect_lines<'a>(
+    stmts: &'a [Stmt],
+    scope: Option<&'a str>,
+    lines: &mut Vec<(u32, Option<&'a str>, Span)>,
+) {
+    for stmt in stmts {
+        if let StmtKind::Label(n) = stmt.kind {
+            lines.push((n, scope, stmt.span));
+        }
+        for body in stmt.kind.bodies() {
+            collect_lines(body, scope, lines);
+        }
+    }
+}
+
+fn parse(text: &str) -> Option<Program> {
+    Parser::new(Lexer::new(text)).parse().ok()
+}
+
+/// Where the word at a position is defined
+fn definition(uri: &str, text: &str, line: usize, character: usize) -> Option<Value> {
+    let line_text = text.lines().nth(line)?;
+    let col = char_col(line_text, character);
+    let word = word_at(line_text, col)?.to_ascii_uppercase();
+    let program = parse(text)?;
+    let outline = outline(&program);
+    let span = if let Ok(n) = word.parse::<u32>() {
+        // Jumps stay in the procedure they are in
+        let here = line as u32 + 1;
+        let scope = outline
+            .procs
+            .iter()
+            .find(|(_, start, end)| (start.line..*end).contains(&here))
+            .map(|(name, ..)| *name);
+        outline
+            .lines
+            .iter()
+            .find(|(number, inside, _)| *number == n && *inside == scope)
+            .map(|(.., span)| *span)?
+    } else {
+        let procs = outline.procs.iter().map(|(name, span, _)| (*name, *span));
+        procs
+            .chain(outline.declares.iter().copied())
+            .find(|(name, _)| *name == word)
+            .map(|(_, span)| span)?
+    };
+    Some(json!({"uri": uri, "range": word_range(text, span)}))
+}
+
+/// The SUBs, FUNCTIONs and line numbers of a document
+fn symbols(uri: &str, text: &str) -> Value {
+    let Some(program) = parse(text) else {
+        return json!([]);
+    };
+    let outline = outline(&program);
+    let procs = outline.procs.iter().map(|(name, span, _)| {
+        json!({
+            "name": name,
+            "kind": SYMBOL_FUNCTION,
+            "location": {"uri": uri, "range": line_range(text, *span)},
+        })
+    });
+    let lines = outline.lines.iter().map(|(n, scope, span)| {
+        let mut symbol = json!({
+            "name": n.to_string(),
+            "kind": SYMBOL_NUMBER,
+            "location": {"uri": uri, "range": line_range(text, *span)},
+        });
+        if let Some(scope) = scope {
+            symbol["containerName"] = json!(scope);
+        }
+        symbol
+    });
+    Value::Array(procs.chain(lines).collect())
+}
+
+/// Whether a character can be part of a name or number
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | '%' | '&' | '!' | '#')
+}
+
+/// The name or number around a character column (0-based) of a line
+fn word_at(line: &str, col: usize) -> Option<&str> {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut start = col.min(chars.len());
+    while start > 0 && is_word(chars[start - 1].1) {
+        start -= 1;
+    }
+    let mut end = col.min(chars.len());
+    while end < chars.len() && is_word(chars[end].1) {
+        end += 1;
+    }
+    let byte = |i: usize| chars.get(i).map_or(line.len(), |&(b, _)| b);
+    (start < end).then(|| &line[byte(start)..byte(end)])
+}
+
+/// The character column of an LSP position, which counts UTF-16 units
+fn char_col(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.chars().enumerate() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.chars().count()
+}
+
+/// An LSP position for a character column (0-based) of a line
+fn position(text: &str, line: u32, col: usize) -> Value {
+    let line_text = text.lines().nth(line as usize).unwrap_or_default();
+    let character: usize = line_text.chars().take(col).map(char::len_utf16).sum();
+    json!({"line": line, "character": character})
+}
+
+/// The range of the word starting at a span (at least one character)
+fn word_range(text: &str, span: Span) -> Value {
+    let line = span.line.saturating_sub(1);
+    let col = span.col.saturating_sub(1) as usize;
+    let line_text = text.lines().nth(line as usize).unwrap_or_default();
+    let rest = line_text.chars().skip(col);
+    let len = rest.take_while(|&c| is_word(c)).count().max(1);
+    json!({"start": position(text, line, col), "end": position(text, line, col + len)})
+}
+
+/// The range from a span to the end of its line
+fn line_range(text: &str, span: Span) -> Value {
+    let line = span.line.saturating_sub(1);
+    let col = span.col.saturating_sub(1) as usize;
+    let line_text = text.lines().nth(line as usize).unwrap_or_default();
+    let end = line_text.trim_end().chars().count().max(col);
+    json!({"start": position(text, line, col), "end": position(text, line, end)})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///tmp/prog%20one.bas";
+
+    /// Run a session of messages, returning what the server sent
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for message in messages {
+            let body = message.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(input.as_slice(), &mut output).unwrap();
+        let mut reader = output.as_slice();
+        std::iter::from_fn(|| read_message(&mut reader).unwrap()).collect()
+    }
+
+    fn open(text: &str) -> Value {
+        json!({"jsonrpc": "2.0", "method": "textDocument/didOpen",
+               "params": {"textDocument": {"uri": URI, "languageId": "basic", "version": 1, "text": text}}})
+    }
+
+    fn request(id: u32, method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    fn definition_at(text: &str, line: u32, character: u32) -> Value {
+        let params = json!({"textDocument": {"uri": URI},
+                            "position": {"line": line, "character": character}});
+        let replies = session(&[open(text), request(2, "textDocument/definition", params)]);
+        replies[1]["result"].clone()
+    }
+
+    // ===================
+    // Protocol Tests
+    // ===================
+
+    #[test]
+    fn test_initialize_and_shutdown() {
+        let replies = session(&[
+            request(1, "initialize", json!({"capabilities": {}})),
+            json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+            request(2, "workspace/symbol", json!({"query": ""})),
+            request(3, "shutdown", Value::Null),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+            request(4, "shutdown", Value::Null),
+        ]);
+        // Nothing answers the notification, or anything after exit
+        assert_eq!(replies.len(), 3);
+        assert_eq!(replies[0]["id"], 1);
+        assert_eq!(
+            replies[0]["result"]["capabilities"]["definitionProvider"],
+            true
+        );
+        assert_eq!(replies[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(
+            replies[2],
+            json!({"jsonrpc": "2.0", "id": 3, "result": null})
+        );
+    }
+
+    #[test]
+    fn test_uri_path() {
+        assert_eq!(uri_path(URI), Some(PathBuf::from("/tmp/prog one.bas")));
+        assert_eq!(uri_path("untitled:1"), None);
+    }
+
+    // ===================
+    // Diagnostics Tests
+    // ===================
+
+    #[test]
+    fn test_diagnostics_on_change() {
+        let change = json!({"jsonrpc": "2.0", "method": "textDocument/didChange",
+                            "params": {"textDocument": {"uri": URI, "version": 2},
+                                       "contentChanges": [{"text": "PRINT 1\n"}]}});
+        let close = json!({"jsonrpc": "2.0", "method": "textDocument/didClose",
+                           "params": {"textDocument": {"uri": URI}}});
+        let replies = session(&[open("PRINT 1\nX = \"a\" + 1\n"), change, close]);
+        let lists: Vec<&Value> = replies
+            .iter()
+            .map(|r| &r["params"]["diagnostics"])
+            .collect();
+        assert_eq!(replies[0]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(replies[0]["params"]["uri"], URI);
+        let error = &lists[0][0];
+        assert_eq!(error["severity"], 1);
+        assert_eq!(
+            error["range"],
+            json!({"start": {"line": 1, "character": 8}, "end": {"line": 1, "character": 9}})
+        );
+        assert!(error["message"].as_str().unwrap().contains("Type mismatch"));
+        assert_eq!(lists[1], &json!([]));
+        assert_eq!(lists[2], &json!([]));
+    }
+
+    #[test]
+    fn test_warnings_and_parse_errors() {
+        let replies = session(&[open("Y = 2\n")]);
+        let warning = &replies[0]["params"]["diagnostics"][0];
+        assert_eq!(warning["severity"], 2);
+        assert_eq!(warning["code"], "unused-variable");
+
+        let replies = session(&[open("PRINT 1\nPRINT (2\n")]);
+        let error = &replies[0]["params"]["diagnostics"][0];
+        assert_eq!(error["code"], "parse-error");
+        assert_eq!(error["range"]["start"]["line"], 1);
+    }
+
+    // ===================
+    // Navigation Tests
+    // ===================
+
+    const PROGRAM: &str = "10 GOSUB 100\nCALL SHOW(1)\nEND\n100 RETURN\n\
+                           SUB SHOW(N)\n100 PRINT N\nGOTO 100\nEND SUB\n";
+
+    #[test]
+    fn test_definition() {
+        let at = |line, character| {
+            let location = definition_at(PROGRAM, line, character);
+            assert_eq!(location["uri"], URI);
+            location["range"]["start"].clone()
+        };
+        // GOSUB 100 finds the main program's line, GOTO 100 the SUB's
+        assert_eq!(at(0, 10), json!({"line": 3, "character": 0}));
+        assert_eq!(at(6, 6), json!({"line": 5, "character": 0}));
+        // A procedure name finds its SUB, even lowercase
+        assert_eq!(at(1, 7), json!({"line": 4, "character": 0}));
+        assert_eq!(
+            definition_at("declare sub s\ncall s\n", 1, 5)["range"]["start"],
+            json!({"line": 0, "character": 0})
+        );
+        // Nothing to find
+        assert_eq!(definition_at(PROGRAM, 2, 1), Value::Null);
+        assert_eq!(definition_at(PROGRAM, 0, 7), Value::Null);
+    }
+
+    #[test]
+    fn test_document_symbols() {
+        let params = json!({"textDocument": {"uri": URI}});
+        let replies = session(&[
+            open(PROGRAM),
+            request(2, "textDocument/documentSymbol", params),
+        ]);
+        let symbols = replies[1]["result"].as_array().unwrap();
+        let names: Vec<(&str, Option<&str>)> = symbols
+            .iter()
+            .map(|s| (s["name"].as_str().unwrap(), s["containerName"].as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("SHOW", None),
+                ("10", None),
+                ("100", None),
+                ("100", Some("SHOW"))
+            ]
+        );
+        assert_eq!(symbols[0]["kind"], SYMBOL_FUNCTION);
+        assert_eq!(
+            symbols[0]["location"]["range"],
+            json!({"start": {"line": 4, "character": 0}, "end": {"line": 4, "character": 11}})
+        );
+    }
+}
@@ -6,190 +6,953 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-mod abi;
-mod codegen;
-mod lexer;
-mod parser;
-mod runtime;
-
-use clap::Parser;
-use std::fs;
-use std::io::Write;
+use clap::error::ErrorKind;
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant, SystemTime};
+use xbasic64::compiler::{self, Checks, Compiler, CompilerOptions, Dialect, Output, Runtime};
+use xbasic64::{
+    Diagnostic, Diagnostics, Lexer, Located, Program, Source, Span, Target, Token, interp, lsp,
+    printer, renum, repl, spec, xref,
+};
 
 /// BASIC-to-x86_64 compiler
 #[derive(Parser)]
-#[command(name = "xbasic64")]
+#[command(name = "xbasic64", version)]
 #[command(about = "Compiles 1980s-era BASIC programs to x86-64 executables")]
+#[command(group(
+    // Everything that builds or dumps a program needs one to work on
+    ArgGroup::new("needs_input")
+        .multiple(true)
+        .requires("inputs")
+        .args(["output", "asm_only", "object_only", "keep_temps", "run",
+               "runtime", "check", "emit_tokens", "emit_ast", "emit_ir", "xref", "watch"])
+))]
+#[command(group(
+    // The dumps print one stage of compilation instead of building
+    ArgGroup::new("dump")
+        .args(["emit_tokens", "emit_ast", "emit_ir", "xref"])
+        .conflicts_with_all(["output", "asm_only", "object_only", "keep_temps", "run", "check",
+                             "timings", "watch"])
+))]
 struct Args {
-    /// Input BASIC source file
-    input: String,
+    /// Input BASIC source files, or - for standard input. The first holds
+    /// the main program, any others SUBs and FUNCTIONs it DECLAREs (none:
+    /// start an interactive session)
+    inputs: Vec<String>,
 
-    /// Output file name
+    /// Output file name (- with -S: standard output)
     #[arg(short, long)]
     output: Option<String>,
 
     /// Emit assembly only (don't assemble or link)
     #[arg(short = 'S')]
     asm_only: bool,
+
+    /// Compile to an object file only (don't link)
+    #[arg(short = 'c', long = "emit-obj", conflicts_with = "asm_only")]
+    object_only: bool,
+
+    /// Keep the intermediate assembly and object files next to the output
+    #[arg(long, visible_alias = "save-temps")]
+    keep_temps: bool,
+
+    /// Interpret the program right away instead of compiling it
+    #[arg(long, conflicts_with_all = ["output", "asm_only", "object_only"])]
+    run: bool,
+
+    /// Only look for errors and warnings: generate no code and run no
+    /// assembler or linker. Each file is checked as -c would compile it.
+    #[arg(long, conflicts_with_all = ["output", "asm_only", "object_only", "keep_temps", "run"])]
+    check: bool,
+
+    /// System to compile for: linux, macos (or x86_64-apple) or windows
+    /// (default: the one the compiler runs on)
+    #[arg(long, value_name = "SYSTEM", value_parser = parse_target)]
+    target: Option<Target>,
+
+    /// Assembler command (default: built in for Linux, clang for macOS and
+    /// on Windows, x86_64-w64-mingw32-as for Windows elsewhere)
+    #[arg(long = "as", value_name = "CMD")]
+    assembler: Option<String>,
+
+    /// Linker command, run like cc (default: built in for Linux, clang for
+    /// macOS, link.exe on Windows, x86_64-w64-mingw32-gcc for Windows
+    /// elsewhere)
+    #[arg(long, value_name = "CMD")]
+    cc: Option<String>,
+
+    /// Add a directory to the linker's library search path
+    #[arg(short = 'L', value_name = "DIR")]
+    lib_dirs: Vec<String>,
+
+    /// Link with a library
+    #[arg(short = 'l', value_name = "LIB")]
+    libs: Vec<String>,
+
+    /// Runtime routines to link with: builtin, none (only refer to them) or
+    /// an object file or static library that provides them
+    #[arg(
+        long,
+        value_name = "RUNTIME",
+        default_value = "builtin",
+        conflicts_with = "run"
+    )]
+    runtime: Runtime,
+
+    /// Add a directory to search for $INCLUDE files
+    #[arg(short = 'I', value_name = "DIR")]
+    include_dirs: Vec<String>,
+
+    /// BASIC dialect the programs are written in: which words are keywords
+    /// and which extensions they may use
+    #[arg(long, value_enum, value_name = "DIALECT", default_value_t = Dialect::Modern)]
+    dialect: Dialect,
+
+    /// Require variables to be assigned or DIM'd before use (OPTION EXPLICIT)
+    #[arg(long)]
+    explicit: bool,
+
+    /// Stop with "Overflow" when an INTEGER or LONG result is out of range
+    /// (default: wrap around)
+    #[arg(long)]
+    overflow_check: bool,
+
+    /// Give numbers on the console, and STR$ and VAL, the decimal point of
+    /// the locale the program runs in (default: always '.')
+    #[arg(long, conflicts_with = "run")]
+    locale: bool,
+
+    /// Optimize (same as --opt-level=1)
+    #[arg(short = 'O')]
+    optimize: bool,
+
+    /// Optimization level: 0 (none) or 1 (fold constants, remove dead code,
+    /// peephole, register allocation)
+    #[arg(long, value_name = "LEVEL", default_value_t = 0,
+          value_parser = clap::value_parser!(u8).range(0..=1))]
+    opt_level: u8,
+
+    /// Treat warnings (unused variables, unreachable code, jumps into FOR
+    /// loops) as errors
+    #[arg(short = 'W', long)]
+    deny_warnings: bool,
+
+    /// How to print errors and warnings
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Human, global = true)]
+    error_format: ErrorFormat,
+
+    /// When to color errors and warnings
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, global = true)]
+    color: ColorChoice,
+
+    /// Print the token stream and stop (pretty or json)
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, require_equals = true,
+          default_missing_value = "pretty")]
+    emit_tokens: Option<DumpFormat>,
+
+    /// Print the parsed program and stop (pretty or json)
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, require_equals = true,
+          default_missing_value = "pretty")]
+    emit_ast: Option<DumpFormat>,
+
+    /// Print the intermediate representation and stop
+    #[arg(long)]
+    emit_ir: bool,
+
+    /// Print where each variable, array, procedure and line number is
+    /// defined and used, and stop
+    #[arg(long)]
+    xref: bool,
+
+    /// Print how long each phase of the build took, with counts of what
+    /// it handled (tokens, statements, instructions), to stderr
+    #[arg(long)]
+    timings: bool,
+
+    /// Build again whenever an input file, or a file it includes, changes,
+    /// until interrupted (build, or run: also start each executable built)
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, require_equals = true,
+          default_missing_value = "build")]
+    watch: Option<WatchMode>,
+
+    #[command(subcommand)]
+    tool: Option<Tool>,
+}
+
+/// Tools that work on BASIC source instead of compiling it
+#[derive(Subcommand)]
+enum Tool {
+    /// Print BASIC source in one layout: keywords in capitals, blocks
+    /// indented and line numbers lined up
+    Fmt(FmtArgs),
+    /// Number the lines from START by STEP and change the GOTOs, GOSUBs,
+    /// RESTOREs and other jumps to match, printing the program formatted
+    Renum(RenumArgs),
+    /// Serve the Language Server Protocol on standard input and output:
+    /// diagnostics as the program changes, go to definition and symbols
+    Lsp,
+    /// Compile and run each program with 'EXPECT: comments in the files
+    /// and directories given, checking it prints what they say
+    Test(TestArgs),
+}
+
+#[derive(clap::Args)]
+struct FmtArgs {
+    /// BASIC source files, or - for standard input
+    #[arg(required = true)]
+    files: Vec<String>,
+
+    /// Rewrite the files instead of printing them
+    #[arg(short, long)]
+    write: bool,
+
+    /// Change nothing, but name the files that aren't formatted and fail
+    /// if there are any
+    #[arg(long, conflicts_with = "write")]
+    check: bool,
+}
+
+#[derive(clap::Args)]
+struct RenumArgs {
+    /// BASIC source files, or - for standard input
+    #[arg(required = true)]
+    files: Vec<String>,
+
+    /// Number for the first line
+    #[arg(long, default_value_t = 10)]
+    start: u32,
+
+    /// Gap between line numbers
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    step: u32,
+
+    /// Rewrite the files instead of printing them
+    #[arg(short, long)]
+    write: bool,
+}
+
+#[derive(clap::Args)]
+struct TestArgs {
+    /// BASIC source files, and directories to search for them
+    #[arg(default_value = ".")]
+    paths: Vec<String>,
+
+    /// Seconds a program may run before it fails
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    timeout: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum WatchMode {
+    /// Rebuild (or with --run, interpret the program again)
+    Build,
+    /// Rebuild, then run the executable
+    Run,
+}
+
+/// How often --watch looks at the files, and how long it lets an editor
+/// finish saving before it rebuilds
+const WATCH_POLL: Duration = Duration::from_millis(200);
+const WATCH_SETTLE: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum DumpFormat {
+    /// Human-readable listing
+    Pretty,
+    /// JSON
+    Json,
+}
+
+/// A --target name. Only x86-64 code is generated, so an arm64 target is
+/// refused rather than quietly built for Rosetta 2.
+fn parse_target(name: &str) -> Result<Target, String> {
+    if name.eq_ignore_ascii_case("aarch64-apple") {
+        return Err(
+            "unsupported target: no arm64 code generation (--target macos builds x86-64 \
+             code, which Apple Silicon runs through Rosetta 2)"
+                .to_string(),
+        );
+    }
+    Target::from_str(name, true)
+        .map_err(|_| "unknown target (expected linux, macos, x86_64-apple or windows)".to_string())
+}
+
+/// A token and where it starts, for --emit-tokens
+#[derive(Serialize)]
+struct SpannedToken<'a> {
+    span: Span,
+    token: &'a Token,
+}
+
+/// Print the tokens for --emit-tokens
+fn dump_tokens(tokens: &[Token], spans: &[Span], format: DumpFormat) {
+    match format {
+        DumpFormat::Pretty => {
+            for (token, span) in tokens.iter().zip(spans) {
+                println!("{}:{}\t{:?}", span.line, span.col, token);
+            }
+        }
+        DumpFormat::Json => {
+            let list: Vec<SpannedToken> = tokens
+                .iter()
+                .zip(spans)
+                .map(|(token, &span)| SpannedToken { span, token })
+                .collect();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&list).expect("tokens serialize")
+            );
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ErrorFormat {
+    /// Messages with the source line and a caret under the column
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ColorChoice {
+    /// Color when stderr is a terminal and NO_COLOR isn't set
+    Auto,
+    /// Always color, even when piped
+    Always,
+    /// Never color
+    Never,
+}
+
+/// Whether human-readable diagnostics should be colored
+fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+        }
+    }
+}
+
+/// Print diagnostics to stderr in the chosen format
+fn report(args: &Args, diagnostics: &Diagnostics) {
+    for located in diagnostics {
+        match args.error_format {
+            ErrorFormat::Human => eprintln!("{}", located.render(use_color(args.color))),
+            ErrorFormat::Json => eprintln!("{}", located.to_json()),
+        }
+    }
+}
+
+/// Print the diagnostics that stopped the build (none when the failure
+/// was already reported) and exit
+fn fail(args: &Args, diagnostics: &Diagnostics) -> ! {
+    report(args, diagnostics);
+    std::process::exit(1)
+}
+
+/// Stop with a usage error for options that don't go together
+fn usage_error(message: &str) -> ! {
+    Args::command()
+        .error(ErrorKind::ArgumentConflict, message)
+        .exit()
 }
 
 fn main() {
     let args = Args::parse();
 
-    let input_file = &args.input;
+    match &args.tool {
+        Some(Tool::Fmt(options)) => {
+            fmt(&args, options);
+            return;
+        }
+        Some(Tool::Renum(options)) => {
+            renum(&args, options);
+            return;
+        }
+        Some(Tool::Test(options)) => {
+            run_tests(&args, options);
+            return;
+        }
+        Some(Tool::Lsp) => {
+            if let Err(e) = lsp::serve(std::io::stdin().lock(), std::io::stdout().lock()) {
+                eprintln!("xbasic64 lsp: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
+    if args.inputs.is_empty() {
+        repl::run(args.overflow_check, use_color(args.color));
+        return;
+    }
+    let from_stdin = args.inputs[0] == "-";
+    let several = args.inputs.len() > 1;
+    let to_stdout = args.output.as_deref() == Some("-");
+    if to_stdout && !args.asm_only {
+        usage_error("only assembly (-S) can be written to standard output (-o -)");
+    }
+    if several && args.inputs.iter().any(|input| input == "-") {
+        usage_error("standard input (-) can't be compiled with other files");
+    }
+    let dump = args.emit_tokens.is_some() || args.emit_ast.is_some() || args.emit_ir || args.xref;
+    if several && dump {
+        usage_error("--emit-tokens, --emit-ast, --emit-ir and --xref take one input file");
+    }
+    if several && args.output.is_some() && (args.asm_only || args.object_only) {
+        usage_error("-o can't name the output of -S or -c for several input files");
+    }
+    if args.watch.is_some() && from_stdin {
+        usage_error("--watch needs input files, not standard input (-)");
+    }
+    let no_exe = args.asm_only || args.object_only || args.run || args.check;
+    if args.watch == Some(WatchMode::Run) && no_exe {
+        usage_error("--watch=run needs an executable to run (not -S, -c, --run or --check)");
+    }
+
+    let mut compiler = Compiler::new(compiler_options(&args));
 
-    // Read source file
-    let source = match fs::read_to_string(input_file) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Error reading {}: {}", input_file, e);
-            std::process::exit(1);
+    match args.watch {
+        Some(mode) => watch(&args, &mut compiler, mode),
+        None => {
+            let mut timings = Timings::new(args.timings);
+            if let Err(e) = build(&args, &mut compiler, &mut Vec::new(), &mut timings) {
+                fail(&args, &e);
+            }
+            timings.print();
         }
+    }
+}
+
+/// The compiler options the command line asks for
+fn compiler_options(args: &Args) -> CompilerOptions {
+    let opt_level = if args.optimize {
+        args.opt_level.max(1)
+    } else {
+        args.opt_level
     };
+    CompilerOptions {
+        target: args.target.unwrap_or_else(Target::host),
+        opt_level,
+        dialect: args.dialect,
+        checks: Checks {
+            explicit: args.explicit,
+            overflow: args.overflow_check,
+            deny_warnings: args.deny_warnings,
+        },
+        include_dirs: args.include_dirs.clone(),
+        // With -S, show each source line above the code it became
+        annotate: args.asm_only,
+        assembler: args.assembler.clone(),
+        cc: args.cc.clone(),
+        lib_dirs: args.lib_dirs.clone(),
+        libs: args.libs.clone(),
+        keep_temps: args.keep_temps,
+        runtime: args.runtime.clone(),
+        locale: args.locale,
+        ..CompilerOptions::default()
+    }
+}
 
-    // Tokenize
-    let mut lexer = lexer::Lexer::new(&source);
-    let tokens = match lexer.tokenize() {
-        Ok(t) => t,
-        Err(e) => {
-            eprintln!("Lexer error: {}", e);
-            std::process::exit(1);
+/// How long each phase of a build took, for --timings
+struct Timings {
+    enabled: bool,
+    last: Instant,
+    phases: Vec<(&'static str, Duration, String)>, // phase, time, what it handled
+}
+
+impl Timings {
+    fn new(enabled: bool) -> Self {
+        Timings {
+            enabled,
+            last: Instant::now(),
+            phases: Vec::new(),
         }
-    };
+    }
 
-    // Parse
-    let mut parser = parser::Parser::new(tokens);
-    let program = match parser.parse() {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("Parse error: {}", e);
-            std::process::exit(1);
+    /// End a phase that started when the last one ended
+    fn lap(&mut self, phase: &'static str, counts: impl FnOnce() -> String) {
+        if self.enabled {
+            let elapsed = self.last.elapsed();
+            self.phases.push((phase, elapsed, counts()));
+            self.last = Instant::now();
         }
-    };
+    }
 
-    // Generate code
-    let mut codegen = codegen::CodeGen::default();
-    let asm = codegen.generate(&program);
+    /// The steps timed inside `Compiler::build`: assembling and linking
+    fn steps(&mut self, steps: &[(&'static str, Duration)]) {
+        if self.enabled {
+            let steps = steps
+                .iter()
+                .map(|&(step, time)| (step, time, String::new()));
+            self.phases.extend(steps);
+        }
+    }
+
+    fn print(&self) {
+        if !self.enabled {
+            return;
+        }
+        let total: Duration = self.phases.iter().map(|phase| phase.1).sum();
+        let rows = self
+            .phases
+            .iter()
+            .map(|(phase, time, counts)| (*phase, *time, counts.as_str()));
+        for (phase, time, counts) in rows.chain([("total", total, "")]) {
+            let ms = time.as_secs_f64() * 1000.0;
+            let row = format!("{:<10} {:>10.3} ms  {}", phase, ms, counts);
+            eprintln!("{}", row.trim_end());
+        }
+    }
+}
 
-    // Add runtime
-    let runtime_asm = runtime::generate_runtime();
+/// Statements in a block and the blocks inside it
+fn count_statements(stmts: &[xbasic64::Stmt]) -> usize {
+    stmts
+        .iter()
+        .map(|stmt| {
+            1 + stmt
+                .kind
+                .bodies()
+                .into_iter()
+                .map(count_statements)
+                .sum::<usize>()
+        })
+        .sum()
+}
 
-    let full_asm = format!("{}\n{}", asm, runtime_asm);
+/// Compile the inputs as the options say (or dump a stage, or interpret
+/// them), printing what was written. The files the inputs include are
+/// added to `included`, and each phase's time to `timings`. Returns the
+/// executable built, if one was.
+fn build(
+    args: &Args,
+    compiler: &mut Compiler,
+    included: &mut Vec<String>,
+    timings: &mut Timings,
+) -> Result<Option<String>, Diagnostics> {
+    let from_stdin = args.inputs[0] == "-";
+    let to_stdout = args.output.as_deref() == Some("-");
 
-    // Determine output file names - put temp files next to output
-    let input_path = Path::new(&input_file);
-    let stem = input_path.file_stem().unwrap().to_str().unwrap();
-    let input_dir = input_path.parent().unwrap_or(Path::new("."));
+    // Read source files
+    let sources = args
+        .inputs
+        .iter()
+        .map(|input| compiler.read(input))
+        .collect::<Result<Vec<_>, _>>()?;
+    included.extend(sources.iter().flat_map(Source::included).map(String::from));
+    timings.lap("read", || {
+        let lines: usize = sources.iter().map(|s| s.text.lines().count()).sum();
+        format!("{} lines", lines)
+    });
+    if timings.enabled {
+        // The parser lexes as it goes; lex once more on its own to time it
+        let dialect = compiler.options().dialect;
+        let tokens: usize = sources
+            .iter()
+            .map(|s| {
+                let lexer = Lexer::new(&s.text).with_dialect(dialect);
+                lexer.take_while(Result::is_ok).count()
+            })
+            .sum();
+        timings.lap("lex", || format!("{} tokens", tokens));
+    }
 
-    let exe_file = args.output.unwrap_or_else(|| {
-        if cfg!(windows) {
-            input_dir
-                .join(format!("{}.exe", stem))
-                .to_string_lossy()
-                .to_string()
-        } else {
-            input_dir.join(stem).to_string_lossy().to_string()
+    // Tokenize
+    if let Some(format) = args.emit_tokens {
+        let mut lexer = Lexer::new(&sources[0].text).with_dialect(compiler.options().dialect);
+        match lexer.tokenize() {
+            Ok((tokens, spans)) => dump_tokens(&tokens, &spans, format),
+            Err(e) => return Err(Located::new(&sources[0], &e).into()),
         }
+        return Ok(None);
+    }
+
+    // Parse
+    let programs = sources
+        .iter()
+        .map(|source| compiler.parse(source))
+        .collect::<Result<Vec<_>, _>>()?;
+    timings.lap("parse", || {
+        let statements: usize = programs
+            .iter()
+            .map(|p| count_statements(&p.statements))
+            .sum();
+        format!("{} statements", statements)
+    });
+    match args.emit_ast {
+        Some(DumpFormat::Pretty) => {
+            println!("{:#?}", programs[0]);
+            return Ok(None);
+        }
+        Some(DumpFormat::Json) => {
+            let json = serde_json::to_string_pretty(&programs[0]).expect("AST serializes");
+            println!("{}", json);
+            return Ok(None);
+        }
+        None => {}
+    }
+    if args.xref {
+        print!("{}", xref::build(&programs[0]).listing(&sources[0]));
+        return Ok(None);
+    }
+
+    // Check - an executable needs every procedure called to be defined in
+    // one of the modules
+    let linking = !(args.asm_only || args.object_only || args.emit_ir || args.check);
+    compiler.check(&sources, &programs, linking)?;
+    timings.lap("check", || {
+        let names = programs.iter().map(xref::build);
+        let (variables, arrays) = names.fold((0, 0), |(v, a), names| {
+            (
+                v + names.count(xref::Kind::Variable),
+                a + names.count(xref::Kind::Array),
+            )
+        });
+        format!("{} variables, {} arrays", variables, arrays)
     });
+    report(args, compiler.warnings());
+    if args.check {
+        return Ok(None);
+    }
 
-    // Put temp files next to the executable
-    let exe_path = Path::new(&exe_file);
-    let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
-    let exe_stem = exe_path.file_stem().unwrap().to_str().unwrap();
-    let asm_file = exe_dir
-        .join(format!("{}.s", exe_stem))
-        .to_string_lossy()
-        .to_string();
-    let obj_file = exe_dir
-        .join(format!("{}.o", exe_stem))
-        .to_string_lossy()
-        .to_string();
-
-    // Write assembly
-    match fs::File::create(&asm_file) {
-        Ok(mut f) => {
-            if let Err(e) = f.write_all(full_asm.as_bytes()) {
-                eprintln!("Error writing assembly: {}", e);
-                std::process::exit(1);
+    // Interpret, with every module's procedures in the one program
+    if args.run {
+        for (source, program) in sources.iter().zip(&programs) {
+            if let Err(e) = interp::check(program) {
+                return Err(Located::new(source, &e).into());
             }
         }
-        Err(e) => {
-            eprintln!("Error creating assembly file: {}", e);
-            std::process::exit(1);
+        let program = Program {
+            statements: programs.into_iter().flat_map(|p| p.statements).collect(),
+        };
+        if let Err(e) = interp::run(&program, args.overflow_check, args.dialect) {
+            // As a compiled program reports it, without a location
+            eprintln!("{}", e.message);
+            return Err(Diagnostics::new());
         }
+        return Ok(None);
     }
 
-    if args.asm_only {
-        println!("Assembly written to {}", asm_file);
-        return;
+    let modules = compiler.generate(programs);
+    timings.lap("generate", || {
+        let code: usize = modules.iter().map(|m| m.code.len()).sum();
+        format!("{} IR instructions", code)
+    });
+    if args.emit_ir {
+        print!("{}", modules[0].listing());
+        return Ok(None);
     }
+    let asms = compiler.emit(&sources, &modules);
+    timings.lap("emit", || {
+        let lines: usize = asms.iter().map(|asm| asm.lines().count()).sum();
+        format!("{} lines of assembly", lines)
+    });
 
-    // Assemble - use clang on Windows, GNU as elsewhere
-    #[cfg(windows)]
-    let as_status = Command::new("clang")
-        .args(["-c", "-o", &obj_file, &asm_file])
-        .status();
+    // Assembly from standard input goes to standard output unless -o names
+    // a file, as with cc -S
+    if args.asm_only && (to_stdout || from_stdin && args.output.is_none()) {
+        print!("{}", asms[0]);
+        return Ok(None);
+    }
 
-    #[cfg(not(windows))]
-    let as_status = Command::new("as")
-        .args(["-o", &obj_file, &asm_file])
-        .status();
+    let output = if args.asm_only {
+        Output::Assembly
+    } else if args.object_only {
+        Output::Object
+    } else {
+        Output::Executable
+    };
+    let path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| compiler.output_file(&args.inputs[0], output));
+    let written = compiler.build(&sources, asms, output, &path)?;
+    timings.steps(&compiler.build_steps());
+    match output {
+        Output::Assembly => {
+            for file in written {
+                println!("Assembly written to {}", file);
+            }
+            Ok(None)
+        }
+        Output::Object => {
+            for file in written {
+                println!("Object written to {}", file);
+            }
+            Ok(None)
+        }
+        Output::Executable => {
+            let compiled: Vec<&str> = args
+                .inputs
+                .iter()
+                .map(|s| compiler::source_name(s))
+                .collect();
+            println!("Compiled {} -> {}", compiled.join(" "), path);
+            Ok(Some(path))
+        }
+    }
+}
 
-    match as_status {
-        Ok(status) if status.success() => {}
-        Ok(status) => {
-            eprintln!("Assembler failed with status: {}", status);
-            std::process::exit(1);
+/// Build, then build again whenever an input file or a file it includes
+/// changes, until interrupted. The files are polled, as there's no portable
+/// way to be told of a change. Errors are reported and the watch goes on;
+/// with `WatchMode::Run`, each executable built is started, and stopped if
+/// it is still running when the next change comes in.
+fn watch(args: &Args, compiler: &mut Compiler, mode: WatchMode) -> ! {
+    let mut running: Option<Child> = None;
+    let mut files = args.inputs.clone();
+    loop {
+        // Stamped before the build reads them, so a change saved while it
+        // runs (or once the program has printed something) is never taken
+        // for the version just built. Includes it finds for the first time
+        // are stamped after it.
+        let mut before: HashMap<String, Stamp> =
+            files.iter().cloned().zip(file_stamps(&files)).collect();
+        files = args.inputs.clone();
+        let mut timings = Timings::new(args.timings);
+        let built = build(args, compiler, &mut files, &mut timings);
+        let stamps: Vec<Stamp> = files
+            .iter()
+            .map(|file| before.remove(file).unwrap_or_else(|| file_stamp(file)))
+            .collect();
+        match built {
+            Ok(exe) => {
+                timings.print();
+                if let (Some(exe), WatchMode::Run) = (exe, mode) {
+                    running = start(&exe);
+                }
+            }
+            Err(e) => report(args, &e),
         }
-        Err(e) => {
-            eprintln!("Failed to run assembler: {}", e);
-            std::process::exit(1);
-        }
-    }
-
-    // Link - Windows uses link.exe with UCRT, others use cc
-    // msvcrt.lib provides CRT startup (mainCRTStartup) and imports CRT DLL
-    #[cfg(windows)]
-    let cc_status = Command::new("link.exe")
-        .args([
-            &format!("/OUT:{}", exe_file),
-            &obj_file,
-            "/SUBSYSTEM:CONSOLE",
-            "/DEFAULTLIB:msvcrt.lib",
-            "/DEFAULTLIB:ucrt.lib",
-            "/DEFAULTLIB:kernel32.lib",
-            "/DEFAULTLIB:legacy_stdio_definitions.lib",
-        ])
-        .status();
-
-    #[cfg(not(windows))]
-    let cc_status = {
-        #[allow(unused_mut)]
-        let mut cc_args = vec!["-o", &exe_file, &obj_file, "-lm"];
-
-        #[cfg(target_os = "linux")]
-        cc_args.push("-no-pie");
-
-        Command::new("cc").args(&cc_args).status()
-    };
+        eprintln!("Watching {} for changes (Ctrl-C to stop)", files.join(", "));
 
-    match cc_status {
-        Ok(status) if status.success() => {}
-        Ok(status) => {
-            eprintln!("Linker failed with status: {}", status);
-            std::process::exit(1);
+        while file_stamps(&files) == stamps {
+            std::thread::sleep(WATCH_POLL);
+            if let Some(child) = &mut running {
+                if let Ok(Some(status)) = child.try_wait() {
+                    if !status.success() {
+                        eprintln!("Program exited with {}", status);
+                    }
+                    running = None;
+                }
+            }
+        }
+        if let Some(mut child) = running.take() {
+            let _ = child.kill();
+            let _ = child.wait();
         }
+        std::thread::sleep(WATCH_SETTLE);
+        eprintln!("Change detected, rebuilding");
+    }
+}
+
+/// Start an executable just built, found by its path rather than on PATH
+fn start(exe: &str) -> Option<Child> {
+    let path = Path::new(exe);
+    let path = if path.components().count() == 1 {
+        Path::new(".").join(path)
+    } else {
+        path.to_path_buf()
+    };
+    match Command::new(&path).spawn() {
+        Ok(child) => Some(child),
         Err(e) => {
-            eprintln!("Failed to run linker: {}", e);
-            std::process::exit(1);
+            eprintln!("Can't run {}: {}", exe, e);
+            None
         }
     }
+}
+
+/// When a file was last modified, and its size (as a second sign of a
+/// change where timestamps are coarse); None for a file that is missing
+type Stamp = Option<(Option<SystemTime>, u64)>;
+
+fn file_stamp(file: &str) -> Stamp {
+    let meta = std::fs::metadata(file).ok()?;
+    Some((meta.modified().ok(), meta.len()))
+}
+
+fn file_stamps(files: &[String]) -> Vec<Stamp> {
+    files.iter().map(|file| file_stamp(file)).collect()
+}
 
-    // Clean up temporary files
-    let _ = fs::remove_file(&asm_file);
-    let _ = fs::remove_file(&obj_file);
+/// Format each file: print it, rewrite it or, with --check, name it if it
+/// isn't formatted already. Exits with status 1 if any can't be formatted
+/// (or with --check, aren't).
+fn fmt(args: &Args, options: &FmtArgs) {
+    if options.files.len() > 1 && !(options.write || options.check) {
+        usage_error("fmt prints one file; use --write or --check for several");
+    }
+    if options.write && options.files.iter().any(|file| file == "-") {
+        usage_error("fmt --write can't rewrite standard input (-)");
+    }
+    let failed = rewrite(
+        args,
+        &options.files,
+        options.write,
+        options.check,
+        "Formatted",
+        printer::format_source,
+    );
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-    println!("Compiled {} -> {}", input_file, exe_file);
+/// Run the spec tests in the files and directories given, printing PASS
+/// or FAIL for each and a count at the end. Exits with status 1 if any
+/// fail, or if there are none.
+fn run_tests(args: &Args, options: &TestArgs) {
+    let mut files = Vec::new();
+    for path in &options.paths {
+        if let Err(e) = find_programs(Path::new(path), &mut files) {
+            let diag = Diagnostic::new(None, format!("Can't read {}: {}", path, e));
+            fail(args, &Located::in_file("", diag).into());
+        }
+    }
+    let mut compiler = Compiler::new(compiler_options(args));
+    let timeout = Duration::from_secs(options.timeout);
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let file = file.to_string_lossy();
+        let Some(spec) = std::fs::read_to_string(&*file)
+            .ok()
+            .and_then(|text| spec::parse(&text))
+        else {
+            continue;
+        };
+        match spec::run(&mut compiler, &file, &spec, timeout) {
+            spec::Outcome::Pass => {
+                println!("PASS {}", file);
+                passed += 1;
+                continue;
+            }
+            spec::Outcome::Fail(why) => println!("FAIL {}\n     {}", file, why),
+            spec::Outcome::Error(e) => {
+                println!("FAIL {}", file);
+                report(args, &e);
+            }
+        }
+        failed += 1;
+    }
+    if passed + failed == 0 {
+        eprintln!("No programs with 'EXPECT: comments found");
+        std::process::exit(1);
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// The .bas files at `path`: the file itself, or those in the directory
+/// and below, in name order
+fn find_programs(path: &Path, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        std::fs::metadata(path)?;
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<_> = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for entry in entries {
+        let basic = entry
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("bas"));
+        if entry.is_dir() || basic {
+            find_programs(&entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Renumber each file: print it or rewrite it. Exits with status 1 if any
+/// can't be renumbered.
+fn renum(args: &Args, options: &RenumArgs) {
+    if options.files.len() > 1 && !options.write {
+        usage_error("renum prints one file; use --write for several");
+    }
+    if options.write && options.files.iter().any(|file| file == "-") {
+        usage_error("renum --write can't rewrite standard input (-)");
+    }
+    let failed = rewrite(
+        args,
+        &options.files,
+        options.write,
+        false,
+        "Renumbered",
+        |text| renum::renumber_source(text, options.start, options.step),
+    );
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Put each file through `change`, then print the result, write it back
+/// (saying `done` and the file's name) or, to `check`, name the files it
+/// would change. Returns whether anything failed.
+fn rewrite(
+    args: &Args,
+    files: &[String],
+    write: bool,
+    check: bool,
+    done: &str,
+    change: impl Fn(&str) -> Result<String, Diagnostic>,
+) -> bool {
+    let mut failed = false;
+    for file in files {
+        let name = compiler::source_name(file);
+        let text = if file == "-" {
+            std::io::read_to_string(std::io::stdin())
+        } else {
+            std::fs::read_to_string(file)
+        };
+        let text = match text {
+            Ok(text) => text,
+            Err(e) => {
+                let diag = Diagnostic::new(None, format!("Can't read the file: {}", e));
+                report(args, &Located::in_file(name, diag).into());
+                failed = true;
+                continue;
+            }
+        };
+        let changed = match change(&text) {
+            Ok(changed) => changed,
+            Err(e) => {
+                let source = Source::new(name, text);
+                report(args, &Located::new(&source, &e).into());
+                failed = true;
+                continue;
+            }
+        };
+        if check {
+            if changed != text {
+                println!("{} needs formatting", name);
+                failed = true;
+            }
+        } else if write {
+            if changed != text {
+                if let Err(e) = std::fs::write(file, changed) {
+                    let diag = Diagnostic::new(None, format!("Can't write the file: {}", e));
+                    report(args, &Located::in_file(name, diag).into());
+                    failed = true;
+                    continue;
+                }
+                println!("{} {}", done, name);
+            }
+        } else {
+            print!("{}", changed);
+        }
+    }
+    failed
 }
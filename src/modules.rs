@@ -0,0 +1,346 @@
+//! Separate compilation - checks that the modules of a program fit together
+//!
+//! A program may be built from several source files. The first is the main
+//! module, holding the main program; each of the others is a library
+//! module, holding only SUBs and FUNCTIONs. A module calls a procedure
+//! defined in another after declaring it:
+//!
+//! ```text
+//! DECLARE SUB Greet(N$)
+//! DECLARE FUNCTION Twice#(X#)
+//! ```
+//!
+//! Each module is compiled to its own object. Procedures are exported under
+//! the same symbols in every module (`_proc_NAME`, with a tag for a type
+//! suffix), and the main module carries the runtime all of them call.
+//!
+//! The main module owns the DATA table and the GOSUB stack, so DATA,
+//! RESTORE, GOSUB/RETURN and event traps are only allowed there.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+use crate::parser::{Expr, ExprKind, Param, Program, Stmt, StmtKind};
+use crate::warnings;
+use std::collections::HashMap;
+
+/// A SUB or FUNCTION definition or declaration
+#[derive(Clone, Copy)]
+struct Proc<'a> {
+    module: usize,
+    is_function: bool,
+    params: &'a [Param],
+    foreign: bool, // DECLARE ... LIB: a C function, defined in no module
+}
+
+impl Proc<'_> {
+    /// Whether calls made with `other`'s parameters suit this procedure
+    fn matches(&self, other: &Proc) -> bool {
+        self.is_function == other.is_function
+            && self.params.len() == other.params.len()
+            && self
+                .params
+                .iter()
+                .zip(other.params)
+                .all(|(a, b)| a.data_type == b.data_type && a.is_array == b.is_array)
+    }
+}
+
+fn describe(name: &str, is_function: bool) -> String {
+    let kind = if is_function { "FUNCTION" } else { "SUB" };
+    format!("{} {}", kind, name)
+}
+
+/// Check modules (name and program, the main module first) against each
+/// other: library modules hold only procedures, each procedure is defined
+/// once, every DECLARE matches its definition, and every call is to a
+/// procedure its module defines or declares. When `linking`, every
+/// procedure called must also be defined in one of the modules. An error
+/// comes with the index of the module it is in.
+pub fn check(modules: &[(&str, &Program)], linking: bool) -> Result<(), (usize, Diagnostic)> {
+    let fail = |module: usize, span: Span, message: String| {
+        Err((
+            module,
+            Diagnostic::at(span, message).with_code("module-error"),
+        ))
+    };
+
+    let mut defined: HashMap<&str, Proc> = HashMap::new();
+    for (module, (_, program)) in modules.iter().enumerate() {
+        for stmt in &program.statements {
+            let (name, params, is_function) = match &stmt.kind {
+                StmtKind::Sub { name, params, .. } => (name, params, false),
+                StmtKind::Function { name, params, .. } => (name, params, true),
+                StmtKind::Declare { .. } | StmtKind::OptionExplicit | StmtKind::Label(_) => {
+                    continue;
+                }
+                _ if module > 0 => {
+                    return fail(
+                        module,
+                        stmt.span,
+                        "Only SUB, FUNCTION and DECLARE can be outside procedures in a \
+                         module after the first, which holds the main program"
+                            .to_string(),
+                    );
+                }
+                _ => continue,
+            };
+            let proc = Proc {
+                module,
+                is_function,
+                params,
+                foreign: false,
+            };
+            if let Some(first) = defined.insert(name, proc) {
+                let message = format!(
+                    "{} is already defined in {}",
+                    describe(name, is_function),
+                    modules[first.module].0
+                );
+                return fail(module, stmt.span, message);
+            }
+            if module > 0 {
+                if let Some((span, what)) = main_only(std::slice::from_ref(stmt)) {
+                    let message = format!("{} can only be used in the main module", what);
+                    return fail(module, span, message);
+                }
+            }
+        }
+    }
+
+    for (module, (file, program)) in modules.iter().enumerate() {
+        // What this module can call: its own procedures and its DECLAREs
+        let mut known: HashMap<&str, Proc> = HashMap::new();
+        for stmt in &program.statements {
+            let StmtKind::Declare {
+                name,
+                params,
+                is_function,
+                foreign,
+            } = &stmt.kind
+            else {
+                continue;
+            };
+            let declared = Proc {
+                module,
+                is_function: *is_function,
+                params,
+                foreign: foreign.is_some(),
+            };
+            if let Some(definition) = defined.get(name.as_str()) {
+                if declared.foreign {
+                    let message = format!(
+                        "{} is defined in {}, so it can't be DECLAREd from a library",
+                        describe(name, *is_function),
+                        modules[definition.module].0
+                    );
+                    return fail(module, stmt.span, message);
+                }
+                if !definition.matches(&declared) {
+                    let message = format!(
+                        "DECLARE {} doesn't match the definition in {}",
+                        describe(name, *is_function),
+                        modules[definition.module].0
+                    );
+                    return fail(module, stmt.span, message);
+                }
+            }
+            known.insert(name, declared);
+        }
+        for (name, proc) in &defined {
+            if proc.module == module {
+                known.entry(name).or_insert(*proc);
+            }
+        }
+
+        let mut calls = Vec::new();
+        collect_calls(&program.statements, &mut calls);
+        for (name, span, is_function) in calls {
+            let message = match (known.get(name), defined.get(name)) {
+                (Some(_), Some(_)) => continue,
+                (Some(declared), None) if linking && !declared.foreign => format!(
+                    "{} is declared but not defined in any module",
+                    describe(name, declared.is_function)
+                ),
+                (Some(_), None) => continue,
+                (None, Some(definition)) => format!(
+                    "{} is defined in {}; DECLARE it to call it from {}",
+                    describe(name, definition.is_function),
+                    modules[definition.module].0,
+                    file
+                ),
+                // A FUNCTION call that isn't one parses as an array
+                (None, None) if is_function => continue,
+                (None, None) => format!("{} is not defined", describe(name, false)),
+            };
+            return fail(module, span, message);
+        }
+    }
+    Ok(())
+}
+
+/// The first statement in `stmts` (or nested in them) that only the main
+/// module can hold, and what it is
+fn main_only(stmts: &[Stmt]) -> Option<(Span, &'static str)> {
+    stmts.iter().find_map(|stmt| {
+        let what = match &stmt.kind {
+            StmtKind::Data(_) => Some("DATA"),
+            StmtKind::Restore(_) => Some("RESTORE"),
+            StmtKind::Gosub(_) | StmtKind::Return => Some("GOSUB and RETURN"),
+            StmtKind::OnKey { .. }
+            | StmtKind::OnTimer { .. }
+            | StmtKind::OnBreak { .. }
+            | StmtKind::OnMouse { .. } => Some("Event trapping"),
+            _ => None,
+        };
+        match what {
+            Some(what) => Some((stmt.span, what)),
+            None => stmt.kind.bodies().into_iter().find_map(main_only),
+        }
+    })
+}
+
+/// Every procedure call in `stmts`: the name, where, and whether it is a
+/// FUNCTION call (which might be an array instead)
+fn collect_calls<'a>(stmts: &'a [Stmt], calls: &mut Vec<(&'a str, Span, bool)>) {
+    for stmt in stmts {
+        if let StmtKind::Call { name, .. } = &stmt.kind {
+            calls.push((name, stmt.span, false));
+        }
+        for expr in warnings::stmt_reads(&stmt.kind) {
+            expr_calls(expr, calls);
+        }
+        for body in stmt.kind.bodies() {
+            collect_calls(body, calls);
+        }
+    }
+}
+
+fn expr_calls<'a>(expr: &'a Expr, calls: &mut Vec<(&'a str, Span, bool)>) {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Variable(_) => {}
+        ExprKind::FnCall { name, args } => {
+            calls.push((name, expr.span, true));
+            args.iter().for_each(|arg| expr_calls(arg, calls));
+        }
+        ExprKind::ArrayAccess { indices, .. } => {
+            indices.iter().for_each(|arg| expr_calls(arg, calls));
+        }
+        ExprKind::Unary { operand, .. } => expr_calls(operand, calls),
+        ExprKind::Binary { left, right, .. } => {
+            expr_calls(left, calls);
+            expr_calls(right, calls);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source)).parse().unwrap()
+    }
+
+    /// The error checking the modules gives, as module index, line and
+    /// message
+    fn error(sources: &[&str], linking: bool) -> Option<(usize, u32, String)> {
+        let programs: Vec<Program> = sources.iter().map(|s| parse(s)).collect();
+        let names = ["main.bas", "lib.bas", "more.bas"];
+        let modules: Vec<(&str, &Program)> = names.into_iter().zip(&programs).collect();
+        check(&modules, linking)
+            .err()
+            .map(|(module, diag)| (module, diag.span.unwrap().line, diag.message))
+    }
+
+    // ===================
+    // Module Checks
+    // ===================
+
+    #[test]
+    fn test_modules_fit() {
+        let main =
+            "DECLARE SUB Greet(N$)\nDECLARE FUNCTION Twice#(X#)\nGreet \"A\"\nPRINT Twice#(2)\n";
+        let lib = "DECLARE FUNCTION Twice#(X#)\nSUB Greet(N$)\nPRINT Twice#(1); N$\nEND SUB\n";
+        let more = "FUNCTION Twice#(X#)\nTwice# = X# * 2\nEND FUNCTION\n";
+        assert_eq!(error(&[main, lib, more], true), None);
+
+        // Without the module defining Twice#, it's only an error to link
+        assert_eq!(error(&[main, lib], false), None);
+        assert_eq!(
+            error(&[main, lib], true),
+            Some((
+                0,
+                4,
+                "FUNCTION TWICE# is declared but not defined in any module".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_module_errors() {
+        let lib = "SUB Greet(N$)\nPRINT N$\nEND SUB\n";
+        assert_eq!(
+            error(&["Greet \"A\"\n", lib], true),
+            Some((
+                0,
+                1,
+                "SUB GREET is defined in lib.bas; DECLARE it to call it from main.bas".to_string()
+            ))
+        );
+        assert_eq!(
+            error(&["DECLARE SUB Greet(N)\nGreet 1\n", lib], true),
+            Some((
+                0,
+                1,
+                "DECLARE SUB GREET doesn't match the definition in lib.bas".to_string()
+            ))
+        );
+        assert_eq!(
+            error(&["SUB Greet(N$)\nEND SUB\n", lib], false),
+            Some((1, 1, "SUB GREET is already defined in main.bas".to_string()))
+        );
+        assert_eq!(
+            error(&["DECLARE SUB Greet LIB \"c\" (N$)\n", lib], true),
+            Some((
+                0,
+                1,
+                "SUB GREET is defined in lib.bas, so it can't be DECLAREd from a library"
+                    .to_string()
+            ))
+        );
+        assert_eq!(
+            error(&["DECLARE SUB Nap LIB \"c\" (BYVAL N&)\nNap 5\n"], true),
+            None
+        );
+        assert_eq!(
+            error(&["CALL Missing(1)\n"], false),
+            Some((0, 1, "SUB MISSING is not defined".to_string()))
+        );
+        assert_eq!(
+            error(&["PRINT 1\n", "PRINT 2\n"], false),
+            Some((
+                1,
+                1,
+                "Only SUB, FUNCTION and DECLARE can be outside procedures in a module after \
+                 the first, which holds the main program"
+                    .to_string()
+            ))
+        );
+        assert_eq!(
+            error(
+                &["PRINT 1\n", "SUB S\nIF 1 THEN GOSUB 10\nEND SUB\n"],
+                false
+            ),
+            Some((
+                1,
+                2,
+                "GOSUB and RETURN can only be used in the main module".to_string()
+            ))
+        );
+    }
+}
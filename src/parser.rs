@@ -3,8 +3,11 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-use crate::lexer::Token;
-use std::collections::HashSet;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Dialect, Lexer, Span, Token};
+use crate::types;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
 
 /// Binary operator precedence levels (higher = tighter binding)
 /// Returns (precedence, BinaryOp) or None if not a binary operator
@@ -12,8 +15,10 @@ fn binary_op_info(token: &Token) -> Option<(u8, BinaryOp)> {
     match token {
         // Precedence 1: logical OR (lowest)
         Token::Or => Some((1, BinaryOp::Or)),
+        Token::OrElse => Some((1, BinaryOp::OrElse)),
         // Precedence 2: logical AND
         Token::And => Some((2, BinaryOp::And)),
+        Token::AndAlso => Some((2, BinaryOp::AndAlso)),
         // Precedence 3: logical XOR
         Token::Xor => Some((3, BinaryOp::Xor)),
         // Precedence 4: comparison
@@ -41,13 +46,20 @@ fn binary_op_info(token: &Token) -> Option<(u8, BinaryOp)> {
 // AST Definitions
 // ============================================================================
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Program {
     pub statements: Vec<Stmt>,
 }
 
-#[derive(Debug, Clone)]
-pub enum Stmt {
+/// A statement and where it starts in the source
+#[derive(Debug, Clone, Serialize)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum StmtKind {
     Label(u32), // Line number label
     Let {
         name: String,
@@ -60,11 +72,13 @@ pub enum Stmt {
     },
     Input {
         prompt: Option<String>,
-        vars: Vec<String>,
+        question: bool, // show "? " after the prompt (no prompt, or prompt followed by ;)
+        vars: Vec<Expr>, // variables or array elements
     },
     LineInput {
+        file_num: Option<i32>, // None = console
         prompt: Option<String>,
-        var: String,
+        var: Expr,
     },
     If {
         condition: Expr,
@@ -97,31 +111,45 @@ pub enum Stmt {
     },
     Dim {
         arrays: Vec<ArrayDecl>,
+        shared: bool, // DIM SHARED: also visible in every SUB and FUNCTION
     },
+    Shared(Vec<Param>), // SHARED X, A() - main-program names used in a procedure
+    Const(Vec<(String, Expr)>), // CONST N = 10, G$ = "hi" - names for constant values
     Sub {
         name: String,
-        params: Vec<String>,
+        params: Vec<Param>,
         body: Vec<Stmt>,
     },
     Function {
-        name: String,
-        params: Vec<String>,
+        name: String, // suffix gives the return type
+        params: Vec<Param>,
         body: Vec<Stmt>,
     },
+    Declare {
+        name: String, // a SUB or FUNCTION defined here or in another module
+        params: Vec<Param>,
+        is_function: bool,
+        foreign: Option<Foreign>, // LIB: a C function instead
+    },
     Call {
         name: String,
         args: Vec<Expr>,
     },
     Data(Vec<Literal>),
-    Read(Vec<String>),
+    Read(Vec<Expr>),
     Restore(Option<GotoTarget>),
     Cls,
+    Width {
+        file_num: Option<i32>, // None = console
+        width: Expr,
+    },
     SelectCase {
         expr: Expr,
         cases: Vec<(Option<Expr>, Vec<Stmt>)>, // (None = ELSE, Some = value)
     },
     End,
     Stop,
+    OptionExplicit, // variables must be assigned or DIM'd before use
     // File I/O
     Open {
         filename: Expr,
@@ -131,6 +159,26 @@ pub enum Stmt {
     Close {
         file_num: i32,
     },
+    Flush {
+        file_num: i32,
+    },
+    Seek {
+        file_num: i32,
+        position: Expr, // byte position, 1 = the first
+    },
+    // LOCK/UNLOCK #n [, record | [first] TO last]
+    Lock {
+        file_num: i32,
+        first: Option<Expr>, // alone: one record
+        last: Option<Expr>,  // alone: records 1 TO last
+        unlock: bool,
+    },
+    // DICTSET dict, key$, value$ / DICTDEL dict, key$
+    DictSet {
+        dict: Expr,
+        key: Expr,
+        value: Option<Expr>, // None = DICTDEL
+    },
     PrintFile {
         file_num: i32,
         items: Vec<PrintItem>,
@@ -138,38 +186,239 @@ pub enum Stmt {
     },
     InputFile {
         file_num: i32,
-        vars: Vec<String>,
+        vars: Vec<Expr>,
+    },
+    PrintUsing {
+        file_num: Option<i32>, // None = console
+        format: Expr,
+        items: Vec<Expr>,
+        newline: bool,
+    },
+    Write {
+        file_num: Option<i32>, // None = console
+        items: Vec<Expr>,
+    },
+    // Graphics
+    Screen {
+        mode: Expr,
+    },
+    Pset {
+        x: Expr,
+        y: Expr,
+        color: Option<Expr>,
+        preset: bool, // PRESET: default color is the background
+    },
+    GraphicsLine {
+        from: Option<(Expr, Expr)>, // None = start at the last point
+        to: (Expr, Expr),
+        color: Option<Expr>,
+        style: LineStyle,
+    },
+    Circle {
+        x: Expr,
+        y: Expr,
+        radius: Expr,
+        color: Option<Expr>,
+    },
+    Paint {
+        x: Expr,
+        y: Expr,
+        color: Option<Expr>,
+        border: Option<Expr>,
+    },
+    Draw {
+        commands: Expr,
+    },
+    GetImage {
+        from: (Expr, Expr),
+        to: (Expr, Expr),
+        array: String,
+        indices: Vec<Expr>, // starting element (empty = start of array)
+    },
+    PutImage {
+        at: (Expr, Expr),
+        array: String,
+        indices: Vec<Expr>,
+        mode: PutMode,
+    },
+    Display,
+    Palette {
+        entry: Option<(Expr, Expr)>, // attribute and color; None = defaults
+    },
+    // Emulated memory
+    DefSeg {
+        segment: Option<Expr>, // None = default segment
+    },
+    Poke {
+        address: Expr,
+        value: Expr,
+    },
+    Bsave {
+        filename: Expr,
+        offset: Expr,
+        length: Expr,
+    },
+    Bload {
+        filename: Expr,
+        offset: Option<Expr>, // None = where the file was saved from
+    },
+    // Event trapping
+    OnKey {
+        key: Expr,
+        target: GotoTarget,
+    },
+    KeyTrap {
+        key: Expr, // 0 = all keys
+        state: TrapState,
+    },
+    KeyDisplay(bool), // KEY ON/OFF: show or hide the function key line
+    OnTimer {
+        interval: Expr, // seconds
+        target: GotoTarget,
     },
+    TimerTrap(TrapState),
+    OnBreak {
+        target: GotoTarget,
+    },
+    BreakTrap(TrapState),
+    OnMouse {
+        target: GotoTarget,
+    },
+    MouseTrap(TrapState),
+    // Inline assembly
+    Asm {
+        lines: Vec<String>, // passed through, `{NAME}` naming a variable
+        vars: Vec<Expr>,    // the variables named, once each
+    },
+}
+
+impl StmtKind {
+    /// The statement blocks nested directly inside this statement
+    pub fn bodies(&self) -> Vec<&[Stmt]> {
+        match self {
+            StmtKind::If {
+                then_branch,
+                else_branch,
+                ..
+            } => std::iter::once(then_branch)
+                .chain(else_branch)
+                .map(Vec::as_slice)
+                .collect(),
+            StmtKind::For { body, .. }
+            | StmtKind::While { body, .. }
+            | StmtKind::DoLoop { body, .. }
+            | StmtKind::Sub { body, .. }
+            | StmtKind::Function { body, .. } => vec![body.as_slice()],
+            StmtKind::SelectCase { cases, .. } => {
+                cases.iter().map(|(_, body)| body.as_slice()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The same blocks as `bodies`, for passes that rewrite them
+    pub fn bodies_mut(&mut self) -> Vec<&mut Vec<Stmt>> {
+        match self {
+            StmtKind::If {
+                then_branch,
+                else_branch,
+                ..
+            } => std::iter::once(then_branch).chain(else_branch).collect(),
+            StmtKind::For { body, .. }
+            | StmtKind::While { body, .. }
+            | StmtKind::DoLoop { body, .. }
+            | StmtKind::Sub { body, .. }
+            | StmtKind::Function { body, .. } => vec![body],
+            StmtKind::SelectCase { cases, .. } => cases.iter_mut().map(|(_, body)| body).collect(),
+            _ => vec![],
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum FileMode {
     Input,
     Output,
     Append,
+    /// No FOR clause: read and write, which only devices do
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum LineStyle {
+    Line,
+    Box,       // B
+    FilledBox, // BF
+}
+
+/// How PUT combines sprite pixels with the screen
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum PutMode {
+    Pset,
+    Preset,
+    And,
+    Or,
+    Xor,
+}
+
+/// Trap state set by KEY(n) ON/OFF/STOP and TIMER ON/OFF/STOP
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TrapState {
+    Off,
+    On,
+    Stop, // events are remembered until the trap is ON again
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum PrintItem {
     Expr(Expr),
     Tab,   // comma = tab to next zone
     Empty, // semicolon = no separator
 }
 
-#[derive(Debug, Clone)]
+/// A SUB or FUNCTION parameter
+#[derive(Debug, Clone, Serialize)]
+pub struct Param {
+    pub name: String,
+    pub data_type: DataType, // from the AS clause, else the name's suffix
+    pub is_array: bool,      // A() - the caller's array is passed
+}
+
+/// The C function a DECLARE ... LIB names
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Foreign {
+    pub lib: String,    // library to link with: "c", "m", "sqlite3"
+    pub symbol: String, // the ALIAS, else the name in lowercase
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct ArrayDecl {
     pub name: String,
-    pub dimensions: Vec<Expr>,
+    pub dimensions: Vec<Expr>, // empty for a scalar (DIM X)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum GotoTarget {
     Line(u32),
     Label(String),
 }
 
-#[derive(Debug, Clone)]
-pub enum Expr {
+/// An expression and where it is: a name, literal or call at its first
+/// character, an operator at the operator
+#[derive(Debug, Clone, Serialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum ExprKind {
     Literal(Literal),
     Variable(String),
     ArrayAccess {
@@ -191,20 +440,21 @@ pub enum Expr {
     },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Literal {
     Integer(i64),
     Float(f64),
+    Typed(f64, DataType), // a number with a type suffix
     String(String),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum UnaryOp {
     Neg,
     Not,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum BinaryOp {
     Add,
     Sub,
@@ -222,10 +472,12 @@ pub enum BinaryOp {
     And,
     Or,
     Xor,
+    AndAlso, // short-circuit: the right operand only if the left is true
+    OrElse,  // short-circuit: the right operand only if the left is false
 }
 
 /// BASIC data types following GW-BASIC/QuickBASIC conventions
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum DataType {
     Integer, // % - 16-bit signed (i16)
     Long,    // & - 32-bit signed (i32)
@@ -234,6 +486,11 @@ pub enum DataType {
     String,  // $ - heap-allocated string
 }
 
+/// Literal for a number token with a type suffix
+fn typed_literal(value: f64, suffix: char) -> Literal {
+    Literal::Typed(value, DataType::from_suffix(&suffix.to_string()))
+}
+
 impl DataType {
     /// Determine type from variable name suffix
     pub fn from_suffix(name: &str) -> DataType {
@@ -247,6 +504,17 @@ impl DataType {
         }
     }
 
+    /// Type of a variable, array or FUNCTION name in `dialect`: an
+    /// unsuffixed name is SINGLE in GW-BASIC and QuickBASIC, as they
+    /// have it, and DOUBLE in xbasic64
+    pub fn of_name(name: &str, dialect: Dialect) -> DataType {
+        match name.chars().last() {
+            Some('%' | '&' | '!' | '#' | '$') => DataType::from_suffix(name),
+            _ if dialect == Dialect::Modern => DataType::Double,
+            _ => DataType::Single,
+        }
+    }
+
     /// Check if this is an integer type (Integer or Long)
     pub fn is_integer(&self) -> bool {
         matches!(self, DataType::Integer | DataType::Long)
@@ -257,10 +525,19 @@ impl DataType {
 // Parser
 // ============================================================================
 
-#[derive(Default)]
-pub struct Parser {
-    tokens: Vec<Token>,
-    pos: usize,
+/// Tokens the parser can look at before consuming them: the next one and
+/// the one after (see parse_line)
+const LOOKAHEAD: usize = 2;
+
+pub struct Parser<'a> {
+    /// Where tokens come from, pulled as they are needed
+    lexer: Lexer<'a>,
+    /// The next LOOKAHEAD tokens and where they start
+    lookahead: VecDeque<(Token, Span)>,
+    /// The first lexer error; the token stream ends there
+    lex_error: Option<Diagnostic>,
+    /// Where the last consumed token starts
+    last_span: Option<Span>,
     /// Stores condition from LOOP WHILE/UNTIL for DO loops
     last_loop_condition: Option<Expr>,
     last_loop_is_until: bool,
@@ -268,26 +545,82 @@ pub struct Parser {
     last_elseif_condition: Option<Expr>,
     /// Tracks declared array names for distinguishing array access from function calls
     declared_arrays: HashSet<String>,
+    /// Variable named by the last NEXT, checked against its FOR
+    last_next_var: Option<String>,
+    /// Where the statement being parsed starts
+    stmt_start: Span,
+    /// Where an error is, when it isn't at the last token consumed
+    error_at: Option<Span>,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser {
-            tokens,
-            ..Default::default()
+impl<'a> Parser<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        let mut parser = Parser {
+            lexer,
+            lookahead: VecDeque::with_capacity(LOOKAHEAD),
+            lex_error: None,
+            last_span: None,
+            last_loop_condition: None,
+            last_loop_is_until: false,
+            last_elseif_condition: None,
+            declared_arrays: HashSet::new(),
+            last_next_var: None,
+            stmt_start: Span::default(),
+            error_at: None,
+        };
+        parser.fill();
+        parser
+    }
+
+    /// Pull tokens from the lexer until LOOKAHEAD are waiting. After the
+    /// end of input, or a lexer error, the stream is `Eof` from there on.
+    fn fill(&mut self) {
+        while self.lookahead.len() < LOOKAHEAD {
+            let next = match self.lexer.next() {
+                Some(Ok(next)) => next,
+                Some(Err(e)) => {
+                    let span = e.span.unwrap_or_default();
+                    self.lex_error = Some(e);
+                    (Token::Eof, span)
+                }
+                None => {
+                    let end = self
+                        .lookahead
+                        .back()
+                        .map_or_else(Span::default, |&(_, span)| span);
+                    (Token::Eof, end)
+                }
+            };
+            self.lookahead.push_back(next);
         }
     }
 
     fn peek(&self) -> &Token {
-        self.tokens.get(self.pos).unwrap_or(&Token::Eof)
+        &self.lookahead[0].0
+    }
+
+    /// The token after the next one
+    fn peek_second(&self) -> &Token {
+        &self.lookahead[1].0
+    }
+
+    /// Where the next token starts
+    fn peek_span(&self) -> Span {
+        self.lookahead[0].1
     }
 
     fn advance(&mut self) -> Token {
-        let tok = self.tokens.get(self.pos).cloned().unwrap_or(Token::Eof);
-        self.pos += 1;
+        let (tok, span) = self.lookahead.pop_front().expect("lookahead is kept full");
+        self.last_span = Some(span);
+        self.fill();
         tok
     }
 
+    /// Consume the next token if `pred` accepts it
+    fn next_if(&mut self, pred: impl FnOnce(&Token) -> bool) -> Option<Token> {
+        pred(self.peek()).then(|| self.advance())
+    }
+
     fn expect(&mut self, expected: Token) -> Result<(), String> {
         let tok = self.advance();
         if std::mem::discriminant(&tok) == std::mem::discriminant(&expected) {
@@ -303,36 +636,95 @@ impl Parser {
         }
     }
 
-    pub fn parse(&mut self) -> Result<Program, String> {
+    pub fn parse(&mut self) -> Result<Program, Diagnostic> {
         let mut statements = Vec::new();
         self.skip_newlines();
 
         while !matches!(self.peek(), Token::Eof) {
-            let stmt = self.parse_statement()?;
-            statements.push(stmt);
+            match self.parse_statement() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => {
+                    // A lexer error ends the tokens early, so it comes first
+                    if let Some(e) = self.lex_error.take() {
+                        return Err(e);
+                    }
+                    let message = self.stray(e);
+                    // Most errors are found just after consuming the bad token
+                    let span = self.error_at.take().or(self.last_span);
+                    let span = span.unwrap_or_else(|| self.peek_span());
+                    return Err(Diagnostic::at(span, message).with_code("parse-error"));
+                }
+            }
+            self.skip_newlines();
+        }
+
+        match self.lex_error.take() {
+            Some(e) => Err(e),
+            None => Ok(Program { statements }),
+        }
+    }
+
+    /// Parse statements up to one of the terminators in `ends`, returning them
+    /// and the terminator that closed the block. `block` names the statement
+    /// that opened it, for the error when the source ends first.
+    fn parse_block(&mut self, block: &str, ends: &[&str]) -> Result<(Vec<Stmt>, String), String> {
+        let start = self.stmt_start;
+        let mut body = Vec::new();
+        loop {
             self.skip_newlines();
+            if matches!(self.peek(), Token::Eof) {
+                self.error_at = Some(start);
+                return Err(format!("{} without {}", block, ends[0]));
+            }
+            match self.parse_statement() {
+                Ok(stmt) => body.push(stmt),
+                Err(e) if ends.contains(&e.as_str()) => return Ok((body, e)),
+                Err(e) => return Err(self.stray(e)),
+            }
         }
+    }
 
-        Ok(Program { statements })
+    /// Turn a block terminator that reached the wrong block (or none) into
+    /// an error; other errors pass through unchanged
+    fn stray(&mut self, e: String) -> String {
+        let (found, opener) = match e.as_str() {
+            "NEXT" => ("NEXT", "FOR"),
+            "WEND" => ("WEND", "WHILE"),
+            "LOOP" | "LOOP WHILE" | "LOOP UNTIL" => ("LOOP", "DO"),
+            "END IF" | "ELSE" | "ELSEIF" => (e.as_str(), "IF"),
+            "END SUB" => ("END SUB", "SUB"),
+            "END FUNCTION" => ("END FUNCTION", "FUNCTION"),
+            "END SELECT" | "CASE ELSE" => (e.as_str(), "SELECT CASE"),
+            _ if e.starts_with("CASE:") => ("CASE", "SELECT CASE"),
+            _ => return e,
+        };
+        self.error_at = Some(self.stmt_start);
+        format!("{} without {}", found, opener)
     }
 
     fn parse_statement(&mut self) -> Result<Stmt, String> {
-        // Handle line numbers as labels
-        if let Token::LineNumber(n) = self.peek().clone() {
+        // Colons separate statements; blank lines between them don't matter
+        while matches!(self.peek(), Token::Colon | Token::Newline) {
             self.advance();
-            return Ok(Stmt::Label(n));
         }
+        self.stmt_start = self.peek_span();
+        let span = self.stmt_start;
+        let kind = self.parse_statement_kind()?;
+        Ok(Stmt { kind, span })
+    }
 
-        // Handle colon as statement separator
-        if matches!(self.peek(), Token::Colon) {
+    fn parse_statement_kind(&mut self) -> Result<StmtKind, String> {
+        // Handle line numbers as labels
+        if let Token::LineNumber(n) = *self.peek() {
             self.advance();
-            return self.parse_statement();
+            return Ok(StmtKind::Label(n));
         }
 
-        match self.peek().clone() {
+        match self.peek() {
             Token::Print => self.parse_print(),
+            Token::Write => self.parse_write(),
             Token::Input => self.parse_input(),
-            Token::Line => self.parse_line_input(),
+            Token::Line => self.parse_line(),
             Token::Let => self.parse_let(),
             Token::If => self.parse_if(),
             Token::For => self.parse_for(),
@@ -342,21 +734,90 @@ impl Parser {
             Token::Gosub => self.parse_gosub(),
             Token::Return => {
                 self.advance();
-                Ok(Stmt::Return)
+                Ok(StmtKind::Return)
             }
             Token::On => self.parse_on_goto(),
+            Token::Key => self.parse_key(),
             Token::Dim => self.parse_dim(),
             Token::Sub => self.parse_sub(),
             Token::Function => self.parse_function(),
             Token::Data => self.parse_data(),
+            Token::Asm(_) => self.parse_asm(),
             Token::Read => self.parse_read(),
             Token::Restore => self.parse_restore(),
             Token::Cls => {
                 self.advance();
-                Ok(Stmt::Cls)
+                Ok(StmtKind::Cls)
             }
             Token::Open => self.parse_open(),
             Token::Close => self.parse_close(),
+            Token::Width => self.parse_width(),
+            Token::Screen => {
+                self.advance();
+                let mode = self.parse_expression()?;
+                Ok(StmtKind::Screen { mode })
+            }
+            Token::Pset => self.parse_pset(false),
+            Token::Preset => self.parse_pset(true),
+            Token::Circle => self.parse_circle(),
+            Token::Paint => self.parse_paint(),
+            Token::Get => self.parse_get_image(),
+            Token::Put => self.parse_put_image(),
+            Token::Draw => {
+                self.advance();
+                let commands = self.parse_expression()?;
+                Ok(StmtKind::Draw { commands })
+            }
+            Token::Display => {
+                self.advance();
+                Ok(StmtKind::Display)
+            }
+            Token::Palette => {
+                self.advance();
+                let entry = if matches!(
+                    self.peek(),
+                    Token::Newline | Token::Colon | Token::Eof | Token::Else
+                ) {
+                    None
+                } else {
+                    let attribute = self.parse_expression()?;
+                    self.expect(Token::Comma)?;
+                    Some((attribute, self.parse_expression()?))
+                };
+                Ok(StmtKind::Palette { entry })
+            }
+            Token::Def => self.parse_def(),
+            Token::Poke => {
+                self.advance();
+                let address = self.parse_expression()?;
+                self.expect(Token::Comma)?;
+                let value = self.parse_expression()?;
+                Ok(StmtKind::Poke { address, value })
+            }
+            Token::Bsave => {
+                self.advance();
+                let filename = self.parse_expression()?;
+                self.expect(Token::Comma)?;
+                let offset = self.parse_expression()?;
+                self.expect(Token::Comma)?;
+                let length = self.parse_expression()?;
+                Ok(StmtKind::Bsave {
+                    filename,
+                    offset,
+                    length,
+                })
+            }
+            Token::Bload => {
+                self.advance();
+                let filename = self.parse_expression()?;
+                let offset = if matches!(self.peek(), Token::Comma) {
+                    self.advance();
+                    Some(self.parse_expression()?)
+                } else {
+                    None
+                };
+                Ok(StmtKind::Bload { filename, offset })
+            }
             Token::End => {
                 self.advance();
                 // Check for END IF, END SUB, END FUNCTION, END SELECT
@@ -378,7 +839,7 @@ impl Parser {
                         self.advance();
                         Err("END SELECT".to_string())
                     }
-                    _ => Ok(Stmt::End),
+                    _ => Ok(StmtKind::End),
                 }
             }
             Token::EndIf => {
@@ -399,14 +860,15 @@ impl Parser {
             }
             Token::Stop => {
                 self.advance();
-                Ok(Stmt::Stop)
+                Ok(StmtKind::Stop)
             }
             Token::Next => {
                 self.advance();
-                // Skip optional variable name
-                if let Token::Ident(_) = self.peek() {
-                    self.advance();
-                }
+                // Remember the optional variable name for parse_for to check
+                self.last_next_var = match self.next_if(|tok| matches!(tok, Token::Ident(_))) {
+                    Some(Token::Ident(name)) => Some(name),
+                    _ => None,
+                };
                 Err("NEXT".to_string())
             }
             Token::Wend => {
@@ -460,16 +922,85 @@ impl Parser {
                     Err(format!("CASE:{:?}", value))
                 }
             }
-            Token::Ident(_) => self.parse_assignment_or_call(),
-            Token::Newline => {
+            Token::Ident(s) if s == "TIMER" => {
+                self.advance();
+                Ok(StmtKind::TimerTrap(self.parse_trap_state()?))
+            }
+            // BREAK is not a keyword, so BREAK ON is a statement only when
+            // ON, OFF or STOP follows
+            Token::Ident(s)
+                if s == "BREAK"
+                    && matches!(self.peek_second(), Token::On | Token::Off | Token::Stop) =>
+            {
+                self.advance();
+                Ok(StmtKind::BreakTrap(self.parse_trap_state()?))
+            }
+            // Likewise MOUSE
+            Token::Ident(s)
+                if s == "MOUSE"
+                    && matches!(self.peek_second(), Token::On | Token::Off | Token::Stop) =>
+            {
+                self.advance();
+                Ok(StmtKind::MouseTrap(self.parse_trap_state()?))
+            }
+            // And FLUSH, only with a file number
+            Token::Ident(s) if s == "FLUSH" && matches!(self.peek_second(), Token::Hash) => {
+                self.parse_flush()
+            }
+            // And SEEK #, LOCK and UNLOCK
+            Token::Ident(s) if s == "SEEK" && matches!(self.peek_second(), Token::Hash) => {
+                self.parse_seek()
+            }
+            Token::Ident(s)
+                if (s == "LOCK" || s == "UNLOCK") && matches!(self.peek_second(), Token::Hash) =>
+            {
+                let unlock = s == "UNLOCK";
+                self.parse_lock(unlock)
+            }
+            // DICTSET and DICTDEL, unless assigned to as variables
+            Token::Ident(s)
+                if (s == "DICTSET" || s == "DICTDEL")
+                    && !matches!(self.peek_second(), Token::Eq) =>
+            {
+                let delete = s == "DICTDEL";
+                self.parse_dict_set(delete)
+            }
+            Token::Ident(s) if s == "CALL" => self.parse_call(),
+            // CONST, unless assigned to as a variable
+            Token::Ident(s) if s == "CONST" && !matches!(self.peek_second(), Token::Eq) => {
+                self.parse_const()
+            }
+            Token::Ident(s) if s == "DECLARE" => self.parse_declare(),
+            Token::Ident(s) if s == "SHARED" => {
+                self.advance();
+                let names = self.parse_param_list(false)?;
+                if names.is_empty() {
+                    return Err("Expected variable name after SHARED".to_string());
+                }
+                if let Some(p) = names
+                    .iter()
+                    .find(|p| p.data_type != DataType::of_name(&p.name, self.lexer.dialect()))
+                {
+                    return Err(format!(
+                        "SHARED {} can't have an AS type; it comes from the main program",
+                        p.name
+                    ));
+                }
+                Ok(StmtKind::Shared(names))
+            }
+            Token::Ident(s) if s == "OPTION" => {
                 self.advance();
-                self.parse_statement()
+                match self.advance() {
+                    Token::Ident(s) if s == "EXPLICIT" => Ok(StmtKind::OptionExplicit),
+                    tok => Err(format!("Expected EXPLICIT after OPTION, got {:?}", tok)),
+                }
             }
-            _ => Err(format!("Unexpected token: {:?}", self.peek())),
+            Token::Ident(_) => self.parse_assignment_or_call(),
+            _ => Err(format!("Unexpected token: {:?}", self.advance())),
         }
     }
 
-    fn parse_print(&mut self) -> Result<Stmt, String> {
+    fn parse_print(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume PRINT
 
         // Check for PRINT #n (file output)
@@ -487,6 +1018,11 @@ impl Parser {
             None
         };
 
+        if matches!(self.peek(), Token::Ident(s) if s == "USING") {
+            self.advance();
+            return self.parse_print_using(file_num);
+        }
+
         let mut items = Vec::new();
         let mut newline = true;
 
@@ -510,17 +1046,82 @@ impl Parser {
         }
 
         if let Some(file_num) = file_num {
-            Ok(Stmt::PrintFile {
+            Ok(StmtKind::PrintFile {
                 file_num,
                 items,
                 newline,
             })
         } else {
-            Ok(Stmt::Print { items, newline })
+            Ok(StmtKind::Print { items, newline })
+        }
+    }
+
+    /// The rest of PRINT [#n,] USING: the format, then items separated by
+    /// ; or , (which only separate here)
+    fn parse_print_using(&mut self, file_num: Option<i32>) -> Result<StmtKind, String> {
+        let format = self.parse_expression()?;
+        match self.advance() {
+            Token::Semicolon => {}
+            tok => return Err(format!("Expected ; after USING format, got {:?}", tok)),
+        }
+
+        let mut items = Vec::new();
+        let mut newline = true;
+        while !matches!(
+            self.peek(),
+            Token::Newline | Token::Colon | Token::Eof | Token::Else
+        ) {
+            items.push(self.parse_expression()?);
+            newline = true;
+            if matches!(self.peek(), Token::Semicolon | Token::Comma) {
+                self.advance();
+                newline = false;
+            }
+        }
+        if items.is_empty() {
+            return Err("Expected an expression after USING format".to_string());
+        }
+
+        Ok(StmtKind::PrintUsing {
+            file_num,
+            format,
+            items,
+            newline,
+        })
+    }
+
+    fn parse_write(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume WRITE
+
+        let file_num = if matches!(self.peek(), Token::Hash) {
+            self.advance(); // consume #
+            let num = match self.advance() {
+                Token::Integer(n) => n as i32,
+                tok => return Err(format!("Expected file number after #, got {:?}", tok)),
+            };
+            if matches!(self.peek(), Token::Comma) {
+                self.advance();
+            }
+            Some(num)
+        } else {
+            None
+        };
+
+        let mut items = Vec::new();
+        if !matches!(
+            self.peek(),
+            Token::Newline | Token::Colon | Token::Eof | Token::Else
+        ) {
+            items.push(self.parse_expression()?);
+            while matches!(self.peek(), Token::Comma) {
+                self.advance();
+                items.push(self.parse_expression()?);
+            }
         }
+        Ok(StmtKind::Write { file_num, items })
     }
 
-    fn parse_input(&mut self) -> Result<Stmt, String> {
+    fn parse_input(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume INPUT
 
         // Check for INPUT #n (file input)
@@ -534,77 +1135,119 @@ impl Parser {
                 self.advance(); // consume comma after file number
             }
 
-            let mut vars = Vec::new();
-            while let Token::Ident(name) = self.peek().clone() {
-                self.advance();
-                vars.push(name);
-                if matches!(self.peek(), Token::Comma) {
-                    self.advance();
-                } else {
-                    break;
-                }
-            }
-
-            return Ok(Stmt::InputFile { file_num, vars });
+            let vars = self.parse_targets()?;
+            return Ok(StmtKind::InputFile { file_num, vars });
         }
 
         let mut prompt = None;
-        let mut vars = Vec::new();
+        let mut question = true;
 
-        // Check for prompt string
-        if let Token::String(s) = self.peek().clone() {
-            self.advance();
+        // Check for prompt string: a comma after it leaves out the "? "
+        if let Some(Token::String(s)) = self.next_if(|tok| matches!(tok, Token::String(_))) {
             prompt = Some(s);
-            // Expect comma or semicolon after prompt
             if matches!(self.peek(), Token::Comma | Token::Semicolon) {
-                self.advance();
+                question = matches!(self.advance(), Token::Semicolon);
             }
         }
 
-        // Read variable names
-        while let Token::Ident(name) = self.peek().clone() {
-            self.advance();
-            vars.push(name);
+        let vars = self.parse_targets()?;
+        Ok(StmtKind::Input {
+            prompt,
+            question,
+            vars,
+        })
+    }
+
+    /// Parse the comma-separated variables or array elements that INPUT and
+    /// READ assign
+    fn parse_targets(&mut self) -> Result<Vec<Expr>, String> {
+        let mut targets = Vec::new();
+        while let Token::Ident(_) = self.peek() {
+            targets.push(self.parse_target()?);
             if matches!(self.peek(), Token::Comma) {
                 self.advance();
             } else {
                 break;
             }
         }
+        Ok(targets)
+    }
+
+    /// Parse a variable or array element to be assigned
+    fn parse_target(&mut self) -> Result<Expr, String> {
+        let span = self.peek_span();
+        let name = match self.advance() {
+            Token::Ident(name) => name,
+            tok => return Err(format!("Expected variable name, got {:?}", tok)),
+        };
+        let kind = if matches!(self.peek(), Token::LParen) {
+            self.advance();
+            let indices = self.parse_expr_list()?;
+            self.expect(Token::RParen)?;
+            ExprKind::ArrayAccess { name, indices }
+        } else {
+            ExprKind::Variable(name)
+        };
+        Ok(Expr::new(kind, span))
+    }
 
-        Ok(Stmt::Input { prompt, vars })
+    fn parse_line(&mut self) -> Result<StmtKind, String> {
+        // LINE INPUT and the graphics LINE statement share the LINE keyword
+        if matches!(self.peek_second(), Token::Input) {
+            self.parse_line_input()
+        } else {
+            self.parse_graphics_line()
+        }
     }
 
-    fn parse_line_input(&mut self) -> Result<Stmt, String> {
+    fn parse_line_input(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume LINE
         self.expect(Token::Input)?;
 
+        // LINE INPUT #n, var$ reads a line from a file
+        if matches!(self.peek(), Token::Hash) {
+            self.advance(); // consume #
+            let file_num = match self.advance() {
+                Token::Integer(n) => n as i32,
+                tok => return Err(format!("Expected file number after #, got {:?}", tok)),
+            };
+            self.expect(Token::Comma)?;
+            let var = self.parse_target()?;
+            return Ok(StmtKind::LineInput {
+                file_num: Some(file_num),
+                prompt: None,
+                var,
+            });
+        }
+
         let mut prompt = None;
 
         // Check for prompt string
-        if let Token::String(s) = self.peek().clone() {
-            self.advance();
+        if let Some(Token::String(s)) = self.next_if(|tok| matches!(tok, Token::String(_))) {
             prompt = Some(s);
             if matches!(self.peek(), Token::Comma | Token::Semicolon) {
                 self.advance();
             }
         }
 
-        let var = if let Token::Ident(name) = self.advance() {
-            name
-        } else {
+        if !matches!(self.peek(), Token::Ident(_)) {
             return Err("Expected variable name after LINE INPUT".to_string());
-        };
+        }
+        let var = self.parse_target()?;
 
-        Ok(Stmt::LineInput { prompt, var })
+        Ok(StmtKind::LineInput {
+            file_num: None,
+            prompt,
+            var,
+        })
     }
 
-    fn parse_let(&mut self) -> Result<Stmt, String> {
+    fn parse_let(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume LET
         self.parse_assignment()
     }
 
-    fn parse_assignment(&mut self) -> Result<Stmt, String> {
+    fn parse_assignment(&mut self) -> Result<StmtKind, String> {
         let name = if let Token::Ident(n) = self.advance() {
             n
         } else {
@@ -624,14 +1267,14 @@ impl Parser {
         self.expect(Token::Eq)?;
         let value = self.parse_expression()?;
 
-        Ok(Stmt::Let {
+        Ok(StmtKind::Let {
             name,
             indices,
             value,
         })
     }
 
-    fn parse_assignment_or_call(&mut self) -> Result<Stmt, String> {
+    fn parse_assignment_or_call(&mut self) -> Result<StmtKind, String> {
         let name = if let Token::Ident(n) = self.advance() {
             n
         } else {
@@ -651,20 +1294,20 @@ impl Parser {
                 // Array assignment
                 self.advance();
                 let value = self.parse_expression()?;
-                Ok(Stmt::Let {
+                Ok(StmtKind::Let {
                     name,
                     indices: Some(args),
                     value,
                 })
             } else {
                 // Subroutine call
-                Ok(Stmt::Call { name, args })
+                Ok(StmtKind::Call { name, args })
             }
         } else if matches!(self.peek(), Token::Eq) {
             // Simple assignment
             self.advance();
             let value = self.parse_expression()?;
-            Ok(Stmt::Let {
+            Ok(StmtKind::Let {
                 name,
                 indices: None,
                 value,
@@ -683,11 +1326,30 @@ impl Parser {
                     break;
                 }
             }
-            Ok(Stmt::Call { name, args })
+            Ok(StmtKind::Call { name, args })
         }
     }
 
-    fn parse_if(&mut self) -> Result<Stmt, String> {
+    /// CALL name [(args)]
+    fn parse_call(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume CALL
+        let name = if let Token::Ident(n) = self.advance() {
+            n
+        } else {
+            return Err("Expected subroutine name after CALL".to_string());
+        };
+        let args = if matches!(self.peek(), Token::LParen) {
+            self.advance();
+            let args = self.parse_expr_list()?;
+            self.expect(Token::RParen)?;
+            args
+        } else {
+            Vec::new()
+        };
+        Ok(StmtKind::Call { name, args })
+    }
+
+    fn parse_if(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume IF
         let condition = self.parse_expression()?;
         self.expect(Token::Then)?;
@@ -695,16 +1357,16 @@ impl Parser {
         // Check for single-line IF
         if !matches!(self.peek(), Token::Newline | Token::Eof) {
             // Single-line IF
-            let then_branch = vec![self.parse_statement()?];
+            let then_branch = self.parse_if_clause()?;
 
             let else_branch = if matches!(self.peek(), Token::Else) {
                 self.advance();
-                Some(vec![self.parse_statement()?])
+                Some(self.parse_if_clause()?)
             } else {
                 None
             };
 
-            return Ok(Stmt::If {
+            return Ok(StmtKind::If {
                 condition,
                 then_branch,
                 else_branch,
@@ -715,66 +1377,74 @@ impl Parser {
         self.skip_newlines();
         let (then_branch, else_branch) = self.parse_if_body()?;
 
-        Ok(Stmt::If {
+        Ok(StmtKind::If {
             condition,
             then_branch,
             else_branch,
         })
     }
 
-    /// Parse the body of an IF block, returning (then_branch, else_branch)
-    /// Handles ELSEIF by constructing nested IF statements in else_branch
-    fn parse_if_body(&mut self) -> Result<(Vec<Stmt>, Option<Vec<Stmt>>), String> {
-        let mut body = Vec::new();
-
-        loop {
-            match self.parse_statement() {
-                Ok(stmt) => {
-                    body.push(stmt);
-                }
-                Err(e) if e == "END IF" => {
-                    return Ok((body, None));
-                }
-                Err(e) if e == "ELSE" => {
-                    // Parse ELSE body until END IF
-                    self.skip_newlines();
-                    let mut else_body = Vec::new();
-                    loop {
-                        match self.parse_statement() {
-                            Ok(stmt) => else_body.push(stmt),
-                            Err(e) if e == "END IF" => break,
-                            Err(e) => return Err(e),
-                        }
-                        self.skip_newlines();
-                    }
-                    return Ok((body, Some(else_body)));
-                }
-                Err(e) if e == "ELSEIF" => {
-                    // Get the stored condition
-                    let elseif_condition = self
-                        .last_elseif_condition
-                        .take()
-                        .ok_or_else(|| "Internal error: ELSEIF condition not stored".to_string())?;
-
-                    // Recursively parse the rest as a nested IF
-                    self.skip_newlines();
-                    let (nested_then, nested_else) = self.parse_if_body()?;
-
-                    let nested_if = Stmt::If {
+    /// Parse one branch of a single-line IF: a line number (an implied GOTO),
+    /// or statements separated by colons up to ELSE or the end of the line.
+    /// NEXT, WEND and LOOP end the branch and close the enclosing loop.
+    fn parse_if_clause(&mut self) -> Result<Vec<Stmt>, String> {
+        if let Token::Integer(n) = *self.peek() {
+            let span = self.peek_span();
+            self.advance();
+            let kind = StmtKind::Goto(GotoTarget::Line(n as u32));
+            return Ok(vec![Stmt { kind, span }]);
+        }
+        let mut stmts = vec![self.parse_statement()?];
+        while matches!(self.peek(), Token::Colon) {
+            self.advance();
+            if matches!(
+                self.peek(),
+                Token::Newline | Token::Eof | Token::Else | Token::Next | Token::Wend | Token::Loop
+            ) {
+                break;
+            }
+            stmts.push(self.parse_statement()?);
+        }
+        Ok(stmts)
+    }
+
+    /// Parse the body of an IF block, returning (then_branch, else_branch)
+    /// Handles ELSEIF by constructing nested IF statements in else_branch
+    fn parse_if_body(&mut self) -> Result<(Vec<Stmt>, Option<Vec<Stmt>>), String> {
+        let (body, end) = self.parse_block("IF", &["END IF", "ELSE", "ELSEIF"])?;
+        match end.as_str() {
+            "ELSE" => {
+                // Parse ELSE body until END IF
+                let (else_body, _) = self.parse_block("IF", &["END IF"])?;
+                Ok((body, Some(else_body)))
+            }
+            "ELSEIF" => {
+                let span = self.stmt_start;
+                // Get the stored condition
+                let elseif_condition = self
+                    .last_elseif_condition
+                    .take()
+                    .ok_or_else(|| "Internal error: ELSEIF condition not stored".to_string())?;
+
+                // Recursively parse the rest as a nested IF
+                let (nested_then, nested_else) = self.parse_if_body()?;
+
+                let nested_if = Stmt {
+                    kind: StmtKind::If {
                         condition: elseif_condition,
                         then_branch: nested_then,
                         else_branch: nested_else,
-                    };
+                    },
+                    span,
+                };
 
-                    return Ok((body, Some(vec![nested_if])));
-                }
-                Err(e) => return Err(e),
+                Ok((body, Some(vec![nested_if])))
             }
-            self.skip_newlines();
+            _ => Ok((body, None)),
         }
     }
 
-    fn parse_for(&mut self) -> Result<Stmt, String> {
+    fn parse_for(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume FOR
         let var = if let Token::Ident(n) = self.advance() {
             n
@@ -794,19 +1464,15 @@ impl Parser {
             None
         };
 
-        self.skip_newlines();
-
-        let mut body = Vec::new();
-        loop {
-            match self.parse_statement() {
-                Ok(stmt) => body.push(stmt),
-                Err(e) if e == "NEXT" => break,
-                Err(e) => return Err(e),
+        let (body, _) = self.parse_block("FOR", &["NEXT"])?;
+        if let Some(next_var) = self.last_next_var.take() {
+            if next_var != var {
+                self.error_at = Some(self.stmt_start);
+                return Err(format!("NEXT {} doesn't match FOR {}", next_var, var));
             }
-            self.skip_newlines();
         }
 
-        Ok(Stmt::For {
+        Ok(StmtKind::For {
             var,
             start,
             end,
@@ -815,25 +1481,15 @@ impl Parser {
         })
     }
 
-    fn parse_while(&mut self) -> Result<Stmt, String> {
+    fn parse_while(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume WHILE
         let condition = self.parse_expression()?;
-        self.skip_newlines();
+        let (body, _) = self.parse_block("WHILE", &["WEND"])?;
 
-        let mut body = Vec::new();
-        loop {
-            match self.parse_statement() {
-                Ok(stmt) => body.push(stmt),
-                Err(e) if e == "WEND" => break,
-                Err(e) => return Err(e),
-            }
-            self.skip_newlines();
-        }
-
-        Ok(Stmt::While { condition, body })
+        Ok(StmtKind::While { condition, body })
     }
 
-    fn parse_do_loop(&mut self) -> Result<Stmt, String> {
+    fn parse_do_loop(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume DO
 
         // Check for DO WHILE/UNTIL at start
@@ -849,40 +1505,18 @@ impl Parser {
             _ => (false, false, None),
         };
 
-        self.skip_newlines();
-
-        let mut body = Vec::new();
-        let mut end_condition: Option<Expr> = None;
-        let mut end_is_until = false;
-
         // Clear any previous loop condition
         self.last_loop_condition = None;
 
-        loop {
-            match self.parse_statement() {
-                Ok(stmt) => body.push(stmt),
-                Err(e) if e == "LOOP" => break,
-                Err(e) if e == "LOOP WHILE" => {
-                    // Retrieve condition stored by parse_statement
-                    end_condition = self.last_loop_condition.take();
-                    end_is_until = false;
-                    break;
-                }
-                Err(e) if e == "LOOP UNTIL" => {
-                    // Retrieve condition stored by parse_statement
-                    end_condition = self.last_loop_condition.take();
-                    end_is_until = true;
-                    break;
-                }
-                Err(e) => return Err(e),
-            }
-            self.skip_newlines();
-        }
+        let (body, end) = self.parse_block("DO", &["LOOP", "LOOP WHILE", "LOOP UNTIL"])?;
+        // Retrieve the condition stored by parse_statement
+        let end_condition = self.last_loop_condition.take();
+        let end_is_until = end == "LOOP UNTIL";
 
         // Use end condition if no start condition, or start condition takes precedence
         let final_condition = condition.or(end_condition);
 
-        Ok(Stmt::DoLoop {
+        Ok(StmtKind::DoLoop {
             condition: final_condition,
             cond_at_start,
             is_until: if cond_at_start {
@@ -894,9 +1528,10 @@ impl Parser {
         })
     }
 
-    fn parse_select_case(&mut self) -> Result<Stmt, String> {
+    fn parse_select_case(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume SELECT
         self.expect(Token::Case)?;
+        let start = self.stmt_start;
         let expr = self.parse_expression()?;
         self.skip_newlines();
 
@@ -904,6 +1539,11 @@ impl Parser {
 
         // Parse CASE blocks until END SELECT
         loop {
+            if matches!(self.peek(), Token::Eof) {
+                self.error_at = Some(start);
+                return Err("SELECT CASE without END SELECT".to_string());
+            }
+
             // Check for END SELECT
             if matches!(self.peek(), Token::End | Token::EndSelect) {
                 // Consume END SELECT
@@ -941,7 +1581,7 @@ impl Parser {
 
                 match self.parse_statement() {
                     Ok(stmt) => body.push(stmt),
-                    Err(e) => return Err(e),
+                    Err(e) => return Err(self.stray(e)),
                 }
                 self.skip_newlines();
             }
@@ -949,19 +1589,19 @@ impl Parser {
             cases.push((case_value, body));
         }
 
-        Ok(Stmt::SelectCase { expr, cases })
+        Ok(StmtKind::SelectCase { expr, cases })
     }
 
-    fn parse_goto(&mut self) -> Result<Stmt, String> {
+    fn parse_goto(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume GOTO
         let target = self.parse_goto_target()?;
-        Ok(Stmt::Goto(target))
+        Ok(StmtKind::Goto(target))
     }
 
-    fn parse_gosub(&mut self) -> Result<Stmt, String> {
+    fn parse_gosub(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume GOSUB
         let target = self.parse_goto_target()?;
-        Ok(Stmt::Gosub(target))
+        Ok(StmtKind::Gosub(target))
     }
 
     fn parse_goto_target(&mut self) -> Result<GotoTarget, String> {
@@ -973,8 +1613,37 @@ impl Parser {
         }
     }
 
-    fn parse_on_goto(&mut self) -> Result<Stmt, String> {
+    fn parse_on_goto(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume ON
+
+        // ON KEY(n) GOSUB target, ON TIMER(n) GOSUB target, ON BREAK GOSUB
+        // target, ON MOUSE GOSUB target
+        match self.peek() {
+            Token::Key => {
+                self.advance();
+                let (key, target) = self.parse_event_handler()?;
+                return Ok(StmtKind::OnKey { key, target });
+            }
+            Token::Ident(s) if s == "TIMER" => {
+                self.advance();
+                let (interval, target) = self.parse_event_handler()?;
+                return Ok(StmtKind::OnTimer { interval, target });
+            }
+            Token::Ident(s) if s == "BREAK" && matches!(self.peek_second(), Token::Gosub) => {
+                self.advance();
+                self.advance(); // consume GOSUB
+                let target = self.parse_goto_target()?;
+                return Ok(StmtKind::OnBreak { target });
+            }
+            Token::Ident(s) if s == "MOUSE" && matches!(self.peek_second(), Token::Gosub) => {
+                self.advance();
+                self.advance(); // consume GOSUB
+                let target = self.parse_goto_target()?;
+                return Ok(StmtKind::OnMouse { target });
+            }
+            _ => {}
+        }
+
         let expr = self.parse_expression()?;
         self.expect(Token::Goto)?;
 
@@ -988,12 +1657,59 @@ impl Parser {
             }
         }
 
-        Ok(Stmt::OnGoto { expr, targets })
+        Ok(StmtKind::OnGoto { expr, targets })
+    }
+
+    /// The (n) GOSUB target part of ON KEY / ON TIMER
+    fn parse_event_handler(&mut self) -> Result<(Expr, GotoTarget), String> {
+        self.expect(Token::LParen)?;
+        let n = self.parse_expression()?;
+        self.expect(Token::RParen)?;
+        self.expect(Token::Gosub)?;
+        let target = self.parse_goto_target()?;
+        Ok((n, target))
+    }
+
+    /// ON, OFF or STOP after KEY(n) / TIMER / BREAK
+    fn parse_trap_state(&mut self) -> Result<TrapState, String> {
+        match self.advance() {
+            Token::On => Ok(TrapState::On),
+            Token::Off => Ok(TrapState::Off),
+            Token::Stop => Ok(TrapState::Stop),
+            tok => Err(format!("Expected ON, OFF or STOP, got {:?}", tok)),
+        }
+    }
+
+    /// KEY(n) ON|OFF|STOP, or KEY ON|OFF for the function key line
+    fn parse_key(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume KEY
+
+        match self.peek() {
+            Token::On | Token::Off => {
+                let on = matches!(self.advance(), Token::On);
+                return Ok(StmtKind::KeyDisplay(on));
+            }
+            Token::LParen => {}
+            _ => {
+                let tok = self.advance();
+                return Err(format!("Expected ( or ON/OFF after KEY, got {:?}", tok));
+            }
+        }
+
+        self.advance(); // consume (
+        let key = self.parse_expression()?;
+        self.expect(Token::RParen)?;
+        let state = self.parse_trap_state()?;
+        Ok(StmtKind::KeyTrap { key, state })
     }
 
-    fn parse_dim(&mut self) -> Result<Stmt, String> {
+    fn parse_dim(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume DIM
         let mut arrays = Vec::new();
+        let shared = matches!(self.peek(), Token::Ident(s) if s == "SHARED");
+        if shared {
+            self.advance();
+        }
 
         loop {
             let name = if let Token::Ident(n) = self.advance() {
@@ -1002,12 +1718,19 @@ impl Parser {
                 return Err("Expected array name after DIM".to_string());
             };
 
-            self.expect(Token::LParen)?;
-            let dimensions = self.parse_expr_list()?;
-            self.expect(Token::RParen)?;
+            // DIM X (no bounds) declares a scalar
+            let mut dimensions = Vec::new();
+            if matches!(self.peek(), Token::LParen) {
+                self.advance();
+                dimensions = self.parse_expr_list()?;
+                self.expect(Token::RParen)?;
+                if dimensions.is_empty() {
+                    return Err(format!("Expected bounds for array {}", name));
+                }
 
-            // Track this array name for later use in parse_primary
-            self.declared_arrays.insert(name.to_uppercase());
+                // Track this array name for later use in parse_primary
+                self.declared_arrays.insert(name.to_uppercase());
+            }
 
             arrays.push(ArrayDecl { name, dimensions });
 
@@ -1018,10 +1741,10 @@ impl Parser {
             }
         }
 
-        Ok(Stmt::Dim { arrays })
+        Ok(StmtKind::Dim { arrays, shared })
     }
 
-    fn parse_sub(&mut self) -> Result<Stmt, String> {
+    fn parse_sub(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume SUB
         let name = if let Token::Ident(n) = self.advance() {
             n
@@ -1031,29 +1754,19 @@ impl Parser {
 
         let params = if matches!(self.peek(), Token::LParen) {
             self.advance();
-            let params = self.parse_param_list()?;
+            let params = self.parse_param_list(false)?;
             self.expect(Token::RParen)?;
             params
         } else {
             Vec::new()
         };
 
-        self.skip_newlines();
+        let (body, _) = self.parse_block("SUB", &["END SUB"])?;
 
-        let mut body = Vec::new();
-        loop {
-            match self.parse_statement() {
-                Ok(stmt) => body.push(stmt),
-                Err(e) if e == "END SUB" => break,
-                Err(e) => return Err(e),
-            }
-            self.skip_newlines();
-        }
-
-        Ok(Stmt::Sub { name, params, body })
+        Ok(StmtKind::Sub { name, params, body })
     }
 
-    fn parse_function(&mut self) -> Result<Stmt, String> {
+    fn parse_function(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume FUNCTION
         let name = if let Token::Ident(n) = self.advance() {
             n
@@ -1063,33 +1776,110 @@ impl Parser {
 
         let params = if matches!(self.peek(), Token::LParen) {
             self.advance();
-            let params = self.parse_param_list()?;
+            let params = self.parse_param_list(false)?;
             self.expect(Token::RParen)?;
             params
         } else {
             Vec::new()
         };
 
-        self.skip_newlines();
+        let (body, _) = self.parse_block("FUNCTION", &["END FUNCTION"])?;
 
-        let mut body = Vec::new();
-        loop {
-            match self.parse_statement() {
-                Ok(stmt) => body.push(stmt),
-                Err(e) if e == "END FUNCTION" => break,
-                Err(e) => return Err(e),
+        Ok(StmtKind::Function { name, params, body })
+    }
+
+    /// DECLARE SUB name(params) or DECLARE FUNCTION name(params)
+    fn parse_declare(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume DECLARE
+        let is_function = match self.advance() {
+            Token::Sub => false,
+            Token::Function => true,
+            tok => {
+                return Err(format!(
+                    "Expected SUB or FUNCTION after DECLARE, got {:?}",
+                    tok
+                ));
             }
-            self.skip_newlines();
-        }
+        };
+        let name = if let Token::Ident(n) = self.advance() {
+            n
+        } else {
+            return Err("Expected procedure name after DECLARE".to_string());
+        };
+
+        // LIB "lib" [ALIAS "symbol"]: a C function
+        let foreign = if matches!(self.peek(), Token::Ident(s) if s == "LIB") {
+            self.advance();
+            let Token::String(lib) = self.advance() else {
+                return Err("Expected library name in quotes after LIB".to_string());
+            };
+            let symbol = if matches!(self.peek(), Token::Ident(s) if s == "ALIAS") {
+                self.advance();
+                let Token::String(symbol) = self.advance() else {
+                    return Err("Expected function name in quotes after ALIAS".to_string());
+                };
+                symbol
+            } else {
+                name.trim_end_matches(['%', '&', '!', '#', '$'])
+                    .to_lowercase()
+            };
+            Some(Foreign { lib, symbol })
+        } else {
+            None
+        };
+
+        let params = if matches!(self.peek(), Token::LParen) {
+            self.advance();
+            let params = self.parse_param_list(foreign.is_some())?;
+            self.expect(Token::RParen)?;
+            params
+        } else {
+            Vec::new()
+        };
 
-        Ok(Stmt::Function { name, params, body })
+        Ok(StmtKind::Declare {
+            name,
+            params,
+            is_function,
+            foreign,
+        })
     }
 
-    fn parse_param_list(&mut self) -> Result<Vec<String>, String> {
+    /// Parameter names, each with an optional AS type; with `by_val`, each
+    /// may start with BYVAL (how C functions take their arguments anyway)
+    fn parse_param_list(&mut self, by_val: bool) -> Result<Vec<Param>, String> {
         let mut params = Vec::new();
-        while let Token::Ident(name) = self.peek().clone() {
-            self.advance();
-            params.push(name);
+        while let Some(Token::Ident(mut name)) = self.next_if(|tok| matches!(tok, Token::Ident(_)))
+        {
+            if name == "BYVAL" && matches!(self.peek(), Token::Ident(_)) {
+                if !by_val {
+                    return Err("BYVAL is only allowed in DECLARE ... LIB".to_string());
+                }
+                let Token::Ident(next) = self.advance() else {
+                    unreachable!("peeked an identifier")
+                };
+                name = next;
+            }
+            let is_array = matches!(self.peek(), Token::LParen);
+            if is_array {
+                self.advance();
+                self.expect(Token::RParen)?;
+                self.declared_arrays.insert(name.to_uppercase());
+            }
+            let data_type = if matches!(self.peek(), Token::As) {
+                self.advance();
+                if name.ends_with(['%', '&', '!', '#', '$']) {
+                    return Err(format!("Parameter {} has both a type suffix and AS", name));
+                }
+                self.parse_type_name()?
+            } else {
+                DataType::of_name(&name, self.lexer.dialect())
+            };
+            params.push(Param {
+                name,
+                data_type,
+                is_array,
+            });
             if matches!(self.peek(), Token::Comma) {
                 self.advance();
             } else {
@@ -1099,33 +1889,48 @@ impl Parser {
         Ok(params)
     }
 
-    fn parse_data(&mut self) -> Result<Stmt, String> {
+    /// Type name after AS: INTEGER, LONG, SINGLE, DOUBLE or STRING
+    fn parse_type_name(&mut self) -> Result<DataType, String> {
+        match self.advance() {
+            Token::Ident(s) => match s.as_str() {
+                "INTEGER" => Ok(DataType::Integer),
+                "LONG" => Ok(DataType::Long),
+                "SINGLE" => Ok(DataType::Single),
+                "DOUBLE" => Ok(DataType::Double),
+                "STRING" => Ok(DataType::String),
+                _ => Err(format!("Unknown type {}", s)),
+            },
+            tok => Err(format!("Expected type name after AS, got {:?}", tok)),
+        }
+    }
+
+    fn parse_data(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume DATA
         let mut values = Vec::new();
 
         loop {
-            match self.peek().clone() {
-                Token::Integer(n) => {
-                    self.advance();
-                    values.push(Literal::Integer(n));
-                }
-                Token::Float(f) => {
-                    self.advance();
-                    values.push(Literal::Float(f));
-                }
-                Token::String(s) => {
-                    self.advance();
-                    values.push(Literal::String(s));
-                }
-                Token::Minus => {
-                    self.advance();
-                    match self.advance() {
-                        Token::Integer(n) => values.push(Literal::Integer(-n)),
-                        Token::Float(f) => values.push(Literal::Float(-f)),
-                        _ => return Err("Expected number after minus in DATA".to_string()),
-                    }
-                }
-                _ => break,
+            let value = self.next_if(|tok| {
+                matches!(
+                    tok,
+                    Token::Integer(_)
+                        | Token::Float(_)
+                        | Token::TypedNumber(..)
+                        | Token::String(_)
+                        | Token::Minus
+                )
+            });
+            match value {
+                Some(Token::Integer(n)) => values.push(Literal::Integer(n)),
+                Some(Token::Float(f)) => values.push(Literal::Float(f)),
+                Some(Token::TypedNumber(f, suffix)) => values.push(typed_literal(f, suffix)),
+                Some(Token::String(s)) => values.push(Literal::String(s)),
+                Some(_) => match self.advance() {
+                    Token::Integer(n) => values.push(Literal::Integer(-n)),
+                    Token::Float(f) => values.push(Literal::Float(-f)),
+                    Token::TypedNumber(f, suffix) => values.push(typed_literal(-f, suffix)),
+                    _ => return Err("Expected number after minus in DATA".to_string()),
+                },
+                None => break,
             }
             if matches!(self.peek(), Token::Comma) {
                 self.advance();
@@ -1134,60 +1939,94 @@ impl Parser {
             }
         }
 
-        Ok(Stmt::Data(values))
+        Ok(StmtKind::Data(values))
     }
 
-    fn parse_read(&mut self) -> Result<Stmt, String> {
-        self.advance(); // consume READ
-        let mut vars = Vec::new();
-
-        while let Token::Ident(name) = self.peek().clone() {
-            self.advance();
-            vars.push(name);
-            if matches!(self.peek(), Token::Comma) {
-                self.advance();
-            } else {
-                break;
+    /// ASM ... END ASM. Each `{name}` in a line must name a variable, and
+    /// is written back in upper case for the code generator to replace.
+    fn parse_asm(&mut self) -> Result<StmtKind, String> {
+        let span = self.peek_span();
+        let Token::Asm(raw) = self.advance() else {
+            unreachable!("parse_asm called on an ASM block");
+        };
+        let mut lines = Vec::with_capacity(raw.len());
+        let mut vars: Vec<Expr> = Vec::new();
+        for text in raw {
+            let mut line = String::with_capacity(text.len());
+            let mut rest = text.as_str();
+            while let Some((before, after)) = rest.split_once('{') {
+                let Some((inner, after)) = after.split_once('}') else {
+                    return Err(format!("Missing }} in ASM line: {}", text.trim()));
+                };
+                let tokens = Lexer::new(inner.trim())
+                    .tokenize()
+                    .map(|(tokens, _)| tokens);
+                let Ok([Token::Ident(name), Token::Eof]) = tokens.as_deref() else {
+                    return Err(format!("{{{}}} in ASM isn't a variable name", inner));
+                };
+                if !vars
+                    .iter()
+                    .any(|v| matches!(&v.kind, ExprKind::Variable(v) if v == name))
+                {
+                    vars.push(Expr::new(ExprKind::Variable(name.clone()), span));
+                }
+                line.push_str(before);
+                line.push('{');
+                line.push_str(name);
+                line.push('}');
+                rest = after;
             }
+            line.push_str(rest);
+            lines.push(line);
         }
+        Ok(StmtKind::Asm { lines, vars })
+    }
 
-        Ok(Stmt::Read(vars))
+    fn parse_read(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume READ
+        let vars = self.parse_targets()?;
+        Ok(StmtKind::Read(vars))
     }
 
-    fn parse_restore(&mut self) -> Result<Stmt, String> {
+    fn parse_restore(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume RESTORE
         let target = if matches!(self.peek(), Token::Integer(_) | Token::Ident(_)) {
             Some(self.parse_goto_target()?)
         } else {
             None
         };
-        Ok(Stmt::Restore(target))
+        Ok(StmtKind::Restore(target))
     }
 
-    fn parse_open(&mut self) -> Result<Stmt, String> {
+    fn parse_open(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume OPEN
 
         // Parse filename expression
         let filename = self.parse_expression()?;
 
-        // Expect FOR
-        self.expect(Token::For)?;
-
-        // Parse mode (INPUT, OUTPUT, APPEND)
-        let mode = match self.peek() {
-            Token::Input => {
-                self.advance();
-                FileMode::Input
-            }
-            Token::Output => {
-                self.advance();
-                FileMode::Output
-            }
-            Token::Append => {
-                self.advance();
-                FileMode::Append
+        // Parse mode (FOR INPUT, OUTPUT, APPEND), or none for both ways
+        let mode = if matches!(self.peek(), Token::As) {
+            FileMode::Both
+        } else {
+            self.expect(Token::For)?;
+            match self.peek() {
+                Token::Input => {
+                    self.advance();
+                    FileMode::Input
+                }
+                Token::Output => {
+                    self.advance();
+                    FileMode::Output
+                }
+                Token::Append => {
+                    self.advance();
+                    FileMode::Append
+                }
+                _ => {
+                    let tok = self.advance();
+                    return Err(format!("Expected INPUT, OUTPUT, or APPEND, got {:?}", tok));
+                }
             }
-            tok => return Err(format!("Expected INPUT, OUTPUT, or APPEND, got {:?}", tok)),
         };
 
         // Expect AS
@@ -1200,14 +2039,14 @@ impl Parser {
             tok => return Err(format!("Expected file number after #, got {:?}", tok)),
         };
 
-        Ok(Stmt::Open {
+        Ok(StmtKind::Open {
             filename,
             mode,
             file_num,
         })
     }
 
-    fn parse_close(&mut self) -> Result<Stmt, String> {
+    fn parse_close(&mut self) -> Result<StmtKind, String> {
         self.advance(); // consume CLOSE
 
         // Expect #n
@@ -1217,7 +2056,286 @@ impl Parser {
             tok => return Err(format!("Expected file number after #, got {:?}", tok)),
         };
 
-        Ok(Stmt::Close { file_num })
+        Ok(StmtKind::Close { file_num })
+    }
+
+    /// FLUSH #n - write out what is waiting in the file's buffer
+    fn parse_flush(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume FLUSH
+        self.expect(Token::Hash)?;
+        let file_num = match self.advance() {
+            Token::Integer(n) => n as i32,
+            tok => return Err(format!("Expected file number after #, got {:?}", tok)),
+        };
+        Ok(StmtKind::Flush { file_num })
+    }
+
+    /// SEEK #n, position
+    fn parse_seek(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume SEEK
+        self.expect(Token::Hash)?;
+        let file_num = match self.advance() {
+            Token::Integer(n) => n as i32,
+            tok => return Err(format!("Expected file number after #, got {:?}", tok)),
+        };
+        self.expect(Token::Comma)?;
+        let position = self.parse_expression()?;
+        Ok(StmtKind::Seek { file_num, position })
+    }
+
+    /// LOCK/UNLOCK #n [, record | [first] TO last]
+    fn parse_lock(&mut self, unlock: bool) -> Result<StmtKind, String> {
+        self.advance(); // consume LOCK/UNLOCK
+        self.expect(Token::Hash)?;
+        let file_num = match self.advance() {
+            Token::Integer(n) => n as i32,
+            tok => return Err(format!("Expected file number after #, got {:?}", tok)),
+        };
+        let (mut first, mut last) = (None, None);
+        if self.peek() == &Token::Comma {
+            self.advance();
+            if self.peek() != &Token::To {
+                first = Some(self.parse_expression()?);
+            }
+            if self.peek() == &Token::To {
+                self.advance();
+                last = Some(self.parse_expression()?);
+            }
+        }
+        Ok(StmtKind::Lock {
+            file_num,
+            first,
+            last,
+            unlock,
+        })
+    }
+
+    /// DICTSET dict, key$, value$ or DICTDEL dict, key$
+    fn parse_dict_set(&mut self, delete: bool) -> Result<StmtKind, String> {
+        self.advance(); // consume DICTSET/DICTDEL
+        let dict = self.parse_expression()?;
+        self.expect(Token::Comma)?;
+        let key = self.parse_expression()?;
+        let value = if delete {
+            None
+        } else {
+            self.expect(Token::Comma)?;
+            Some(self.parse_expression()?)
+        };
+        Ok(StmtKind::DictSet { dict, key, value })
+    }
+
+    /// CONST name = value [, name = value]...
+    fn parse_const(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume CONST
+        let mut consts = Vec::new();
+        loop {
+            let name = match self.advance() {
+                Token::Ident(name) => name,
+                tok => return Err(format!("Expected a name after CONST, got {:?}", tok)),
+            };
+            self.expect(Token::Eq)?;
+            consts.push((name, self.parse_expression()?));
+            if !matches!(self.peek(), Token::Comma) {
+                break;
+            }
+            self.advance(); // consume ,
+        }
+        Ok(StmtKind::Const(consts))
+    }
+
+    /// WIDTH [#n,] columns [, lines] - the line count is accepted and ignored
+    fn parse_width(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume WIDTH
+
+        let file_num = if matches!(self.peek(), Token::Hash) {
+            self.advance(); // consume #
+            let num = match self.advance() {
+                Token::Integer(n) => n as i32,
+                tok => return Err(format!("Expected file number after #, got {:?}", tok)),
+            };
+            self.expect(Token::Comma)?;
+            Some(num)
+        } else {
+            None
+        };
+
+        let width = self.parse_expression()?;
+        if file_num.is_none() && matches!(self.peek(), Token::Comma) {
+            self.advance();
+            self.parse_expression()?;
+        }
+        Ok(StmtKind::Width { file_num, width })
+    }
+
+    /// Parse a graphics coordinate pair: (x, y)
+    fn parse_coord(&mut self) -> Result<(Expr, Expr), String> {
+        self.expect(Token::LParen)?;
+        let x = self.parse_expression()?;
+        self.expect(Token::Comma)?;
+        let y = self.parse_expression()?;
+        self.expect(Token::RParen)?;
+        Ok((x, y))
+    }
+
+    /// Parse an optional ", color" suffix of a graphics statement
+    fn parse_optional_color(&mut self) -> Result<Option<Expr>, String> {
+        if !matches!(self.peek(), Token::Comma) {
+            return Ok(None);
+        }
+        self.advance();
+        if matches!(
+            self.peek(),
+            Token::Comma | Token::Newline | Token::Colon | Token::Eof
+        ) {
+            return Ok(None);
+        }
+        Ok(Some(self.parse_expression()?))
+    }
+
+    fn parse_pset(&mut self, preset: bool) -> Result<StmtKind, String> {
+        self.advance(); // consume PSET/PRESET
+        let (x, y) = self.parse_coord()?;
+        let color = self.parse_optional_color()?;
+        Ok(StmtKind::Pset {
+            x,
+            y,
+            color,
+            preset,
+        })
+    }
+
+    fn parse_graphics_line(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume LINE
+        let from = if matches!(self.peek(), Token::LParen) {
+            Some(self.parse_coord()?)
+        } else {
+            None
+        };
+        self.expect(Token::Minus)?;
+        let to = self.parse_coord()?;
+        let color = self.parse_optional_color()?;
+
+        // Optional ", B" or ", BF"
+        let mut style = LineStyle::Line;
+        if matches!(self.peek(), Token::Comma) {
+            self.advance();
+            style = match self.advance() {
+                Token::Ident(s) if s == "B" => LineStyle::Box,
+                Token::Ident(s) if s == "BF" => LineStyle::FilledBox,
+                tok => return Err(format!("Expected B or BF in LINE, got {:?}", tok)),
+            };
+        }
+
+        Ok(StmtKind::GraphicsLine {
+            from,
+            to,
+            color,
+            style,
+        })
+    }
+
+    fn parse_circle(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume CIRCLE
+        let (x, y) = self.parse_coord()?;
+        self.expect(Token::Comma)?;
+        let radius = self.parse_expression()?;
+        let color = self.parse_optional_color()?;
+        Ok(StmtKind::Circle {
+            x,
+            y,
+            radius,
+            color,
+        })
+    }
+
+    fn parse_paint(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume PAINT
+        let (x, y) = self.parse_coord()?;
+        let color = self.parse_optional_color()?;
+        let border = self.parse_optional_color()?;
+        Ok(StmtKind::Paint {
+            x,
+            y,
+            color,
+            border,
+        })
+    }
+
+    /// Parse the array operand of GET/PUT: A or A(start)
+    fn parse_image_array(&mut self) -> Result<(String, Vec<Expr>), String> {
+        let name = match self.advance() {
+            Token::Ident(name) => name,
+            tok => return Err(format!("Expected array name, got {:?}", tok)),
+        };
+        let indices = if matches!(self.peek(), Token::LParen) {
+            self.advance();
+            let indices = self.parse_expr_list()?;
+            self.expect(Token::RParen)?;
+            indices
+        } else {
+            Vec::new()
+        };
+        Ok((name, indices))
+    }
+
+    fn parse_get_image(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume GET
+        let from = self.parse_coord()?;
+        self.expect(Token::Minus)?;
+        let to = self.parse_coord()?;
+        self.expect(Token::Comma)?;
+        let (array, indices) = self.parse_image_array()?;
+        Ok(StmtKind::GetImage {
+            from,
+            to,
+            array,
+            indices,
+        })
+    }
+
+    fn parse_put_image(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume PUT
+        let at = self.parse_coord()?;
+        self.expect(Token::Comma)?;
+        let (array, indices) = self.parse_image_array()?;
+        let mut mode = PutMode::Xor;
+        if matches!(self.peek(), Token::Comma) {
+            self.advance();
+            mode = match self.advance() {
+                Token::Pset => PutMode::Pset,
+                Token::Preset => PutMode::Preset,
+                Token::And => PutMode::And,
+                Token::Or => PutMode::Or,
+                Token::Xor => PutMode::Xor,
+                tok => return Err(format!("Expected PUT mode, got {:?}", tok)),
+            };
+        }
+        Ok(StmtKind::PutImage {
+            at,
+            array,
+            indices,
+            mode,
+        })
+    }
+
+    /// DEF SEG [= segment]
+    fn parse_def(&mut self) -> Result<StmtKind, String> {
+        self.advance(); // consume DEF
+        match self.advance() {
+            Token::Ident(s) if s == "SEG" => {}
+            Token::Ident(s) if s.starts_with("FN") => {
+                return Err("DEF FN is not supported, use FUNCTION instead".to_string());
+            }
+            tok => return Err(format!("Expected SEG after DEF, got {:?}", tok)),
+        }
+        let segment = if matches!(self.peek(), Token::Eq) {
+            self.advance();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        Ok(StmtKind::DefSeg { segment })
     }
 
     // Expression parsing with precedence climbing
@@ -1230,12 +2348,14 @@ impl Parser {
     fn parse_prec(&mut self, min_prec: u8) -> Result<Expr, String> {
         // Handle NOT prefix operator (binds tighter than binary ops)
         let mut left = if matches!(self.peek(), Token::Not) {
+            let span = self.peek_span();
             self.advance();
             let operand = self.parse_prec(min_prec)?; // NOT is right-associative
-            Expr::Unary {
+            let kind = ExprKind::Unary {
                 op: UnaryOp::Not,
                 operand: Box::new(operand),
-            }
+            };
+            Expr::new(kind, span)
         } else {
             self.parse_unary()?
         };
@@ -1245,15 +2365,17 @@ impl Parser {
             if prec < min_prec {
                 break;
             }
+            let span = self.peek_span();
             self.advance();
             // Power is right-associative; others are left-associative
             let next_min = if op == BinaryOp::Pow { prec } else { prec + 1 };
             let right = self.parse_prec(next_min)?;
-            left = Expr::Binary {
+            let kind = ExprKind::Binary {
                 op,
                 left: Box::new(left),
                 right: Box::new(right),
             };
+            left = Expr::new(kind, span);
         }
         Ok(left)
     }
@@ -1261,12 +2383,14 @@ impl Parser {
     fn parse_unary(&mut self) -> Result<Expr, String> {
         match self.peek() {
             Token::Minus => {
+                let span = self.peek_span();
                 self.advance();
                 let operand = self.parse_unary()?;
-                Ok(Expr::Unary {
+                let kind = ExprKind::Unary {
                     op: UnaryOp::Neg,
                     operand: Box::new(operand),
-                })
+                };
+                Ok(Expr::new(kind, span))
             }
             Token::Plus => {
                 self.advance();
@@ -1277,47 +2401,70 @@ impl Parser {
     }
 
     fn parse_primary(&mut self) -> Result<Expr, String> {
-        match self.peek().clone() {
-            Token::Integer(n) => {
-                self.advance();
-                Ok(Expr::Literal(Literal::Integer(n)))
-            }
-            Token::Float(f) => {
-                self.advance();
-                Ok(Expr::Literal(Literal::Float(f)))
-            }
-            Token::String(s) => {
-                self.advance();
-                Ok(Expr::Literal(Literal::String(s)))
-            }
+        let span = self.peek_span();
+        let kind = match self.advance() {
+            Token::Integer(n) => ExprKind::Literal(Literal::Integer(n)),
+            Token::Float(f) => ExprKind::Literal(Literal::Float(f)),
+            Token::TypedNumber(f, suffix) => ExprKind::Literal(typed_literal(f, suffix)),
+            Token::String(s) => ExprKind::Literal(Literal::String(s)),
             Token::Ident(name) => {
-                self.advance();
                 if matches!(self.peek(), Token::LParen) {
                     self.advance();
+                    // SEEK(#n) is SEEK(n)
+                    if name == "SEEK" && matches!(self.peek(), Token::Hash) {
+                        self.advance();
+                    }
                     let args = self.parse_expr_list()?;
                     self.expect(Token::RParen)?;
 
                     // Distinguish array access from function call based on DIM declarations
                     if self.declared_arrays.contains(&name.to_uppercase()) {
-                        Ok(Expr::ArrayAccess {
+                        ExprKind::ArrayAccess {
                             name,
                             indices: args,
-                        })
+                        }
                     } else {
-                        Ok(Expr::FnCall { name, args })
+                        if matches!(name.to_uppercase().as_str(), "VARPTR" | "VARSEG")
+                            && !matches!(
+                                args.as_slice(),
+                                [Expr {
+                                    kind: ExprKind::Variable(_)
+                                        | ExprKind::ArrayAccess { .. }
+                                        | ExprKind::FnCall { .. },
+                                    ..
+                                }]
+                            )
+                        {
+                            return Err(format!("{} requires a variable or array element", name));
+                        }
+                        ExprKind::FnCall { name, args }
                     }
+                } else if types::builtin_signature(&name).is_some_and(|b| b.required == 0) {
+                    // TIMER, RND, DICTNEW and the like need no parentheses
+                    ExprKind::FnCall { name, args: vec![] }
                 } else {
-                    Ok(Expr::Variable(name))
+                    ExprKind::Variable(name)
                 }
             }
-            Token::LParen => {
+            // SCREEN(row, col) reads the text screen; SCREEN alone is the
+            // statement
+            Token::Screen if matches!(self.peek(), Token::LParen) => {
                 self.advance();
+                let args = self.parse_expr_list()?;
+                self.expect(Token::RParen)?;
+                ExprKind::FnCall {
+                    name: "SCREEN".to_string(),
+                    args,
+                }
+            }
+            Token::LParen => {
                 let expr = self.parse_expression()?;
                 self.expect(Token::RParen)?;
-                Ok(expr)
+                return Ok(expr);
             }
-            tok => Err(format!("Unexpected token in expression: {:?}", tok)),
-        }
+            tok => return Err(format!("Unexpected token in expression: {:?}", tok)),
+        };
+        Ok(Expr::new(kind, span))
     }
 
     fn parse_expr_list(&mut self) -> Result<Vec<Expr>, String> {
@@ -1337,13 +2484,19 @@ impl Parser {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lexer::Lexer;
 
     fn parse(input: &str) -> Result<Program, String> {
-        let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-        let mut parser = Parser::new(tokens);
-        parser.parse()
+        parse_with_spans(input).map_err(|e| e.message)
+    }
+
+    fn parse_with_spans(input: &str) -> Result<Program, Diagnostic> {
+        Parser::new(Lexer::new(input)).parse()
+    }
+
+    /// The line and column a parse error points at
+    fn error_at(input: &str) -> (u32, u32) {
+        let span = parse_with_spans(input).unwrap_err().span.unwrap();
+        (span.line, span.col)
     }
 
     // ===================
@@ -1354,7 +2507,7 @@ mod tests {
     fn test_label() {
         let prog = parse("10 PRINT X").unwrap();
         assert_eq!(prog.statements.len(), 2);
-        if let Stmt::Label(n) = &prog.statements[0] {
+        if let StmtKind::Label(n) = &prog.statements[0].kind {
             assert_eq!(*n, 10);
         } else {
             panic!("Expected Label");
@@ -1365,9 +2518,9 @@ mod tests {
     fn test_multiple_labels() {
         let prog = parse("10 X = 1\n20 Y = 2\n30 END").unwrap();
         assert_eq!(prog.statements.len(), 6); // 3 labels + 3 statements
-        assert!(matches!(&prog.statements[0], Stmt::Label(10)));
-        assert!(matches!(&prog.statements[2], Stmt::Label(20)));
-        assert!(matches!(&prog.statements[4], Stmt::Label(30)));
+        assert!(matches!(&prog.statements[0].kind, StmtKind::Label(10)));
+        assert!(matches!(&prog.statements[2].kind, StmtKind::Label(20)));
+        assert!(matches!(&prog.statements[4].kind, StmtKind::Label(30)));
     }
 
     // ===================
@@ -1378,15 +2531,18 @@ mod tests {
     fn test_let_simple() {
         let prog = parse("X = 42").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Let {
+        if let StmtKind::Let {
             name,
             indices,
             value,
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert_eq!(name, "X");
             assert!(indices.is_none());
-            assert!(matches!(value, Expr::Literal(Literal::Integer(42))));
+            assert!(matches!(
+                &value.kind,
+                ExprKind::Literal(Literal::Integer(42))
+            ));
         } else {
             panic!("Expected Let");
         }
@@ -1396,7 +2552,7 @@ mod tests {
     fn test_let_with_keyword() {
         let prog = parse("LET X = 42").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Let { name, .. } = &prog.statements[0] {
+        if let StmtKind::Let { name, .. } = &prog.statements[0].kind {
             assert_eq!(name, "X");
         } else {
             panic!("Expected Let");
@@ -1407,17 +2563,20 @@ mod tests {
     fn test_let_array_assignment() {
         let prog = parse("A(5) = 100").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Let {
+        if let StmtKind::Let {
             name,
             indices,
             value,
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert_eq!(name, "A");
             assert!(indices.is_some());
             let idx = indices.as_ref().unwrap();
             assert_eq!(idx.len(), 1);
-            assert!(matches!(value, Expr::Literal(Literal::Integer(100))));
+            assert!(matches!(
+                &value.kind,
+                ExprKind::Literal(Literal::Integer(100))
+            ));
         } else {
             panic!("Expected Let with array");
         }
@@ -1427,9 +2586,9 @@ mod tests {
     fn test_let_expression() {
         let prog = parse("X = 1 + 2 * 3").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
             // Should be 1 + (2 * 3) due to precedence
-            if let Expr::Binary { op, .. } = value {
+            if let ExprKind::Binary { op, .. } = &value.kind {
                 assert_eq!(*op, BinaryOp::Add);
             } else {
                 panic!("Expected binary expression");
@@ -1447,7 +2606,7 @@ mod tests {
     fn test_print_string() {
         let prog = parse(r#"PRINT "Hello""#).unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Print { items, newline } = &prog.statements[0] {
+        if let StmtKind::Print { items, newline } = &prog.statements[0].kind {
             assert_eq!(items.len(), 1);
             assert!(*newline);
         } else {
@@ -1458,7 +2617,7 @@ mod tests {
     #[test]
     fn test_print_multiple_items() {
         let prog = parse(r#"PRINT "A"; B; C"#).unwrap();
-        if let Stmt::Print { items, .. } = &prog.statements[0] {
+        if let StmtKind::Print { items, .. } = &prog.statements[0].kind {
             assert_eq!(items.len(), 5); // "A", Empty, B, Empty, C
         } else {
             panic!("Expected Print");
@@ -1468,7 +2627,7 @@ mod tests {
     #[test]
     fn test_print_with_tab() {
         let prog = parse(r#"PRINT A, B"#).unwrap();
-        if let Stmt::Print { items, .. } = &prog.statements[0] {
+        if let StmtKind::Print { items, .. } = &prog.statements[0].kind {
             assert!(items.iter().any(|i| matches!(i, PrintItem::Tab)));
         } else {
             panic!("Expected Print");
@@ -1478,13 +2637,140 @@ mod tests {
     #[test]
     fn test_print_no_newline() {
         let prog = parse(r#"PRINT X;"#).unwrap();
-        if let Stmt::Print { newline, .. } = &prog.statements[0] {
+        if let StmtKind::Print { newline, .. } = &prog.statements[0].kind {
             assert!(!*newline);
         } else {
             panic!("Expected Print");
         }
     }
 
+    #[test]
+    fn test_flush() {
+        let prog = parse("FLUSH #3\nFLUSH = 1").unwrap();
+        assert!(matches!(
+            prog.statements[0].kind,
+            StmtKind::Flush { file_num: 3 }
+        ));
+        // Without a file number FLUSH is an ordinary name
+        assert!(matches!(prog.statements[1].kind, StmtKind::Let { .. }));
+    }
+
+    #[test]
+    fn test_seek() {
+        let prog = parse("SEEK #2, P + 1\nP = SEEK(#2) + SEEK(2)\nSEEK = 1").unwrap();
+        assert!(matches!(
+            prog.statements[0].kind,
+            StmtKind::Seek {
+                file_num: 2,
+                position: Expr {
+                    kind: ExprKind::Binary { .. },
+                    ..
+                }
+            }
+        ));
+        if let StmtKind::Let { value, .. } = &prog.statements[1].kind {
+            let ExprKind::Binary { left, right, .. } = &value.kind else {
+                panic!("Expected a sum");
+            };
+            for call in [left, right] {
+                assert!(matches!(&call.kind,
+                    ExprKind::FnCall { name, args } if name == "SEEK" && args.len() == 1
+                ));
+            }
+        } else {
+            panic!("Expected Let");
+        }
+        // Without a file number SEEK is an ordinary name
+        assert!(matches!(prog.statements[2].kind, StmtKind::Let { .. }));
+    }
+
+    #[test]
+    fn test_lock() {
+        let prog =
+            parse("LOCK #1\nLOCK #1, 5\nUNLOCK #2, TO 9\nLOCK #1, 2 TO 4\nLOCK = 1").unwrap();
+        assert!(matches!(
+            prog.statements[0].kind,
+            StmtKind::Lock {
+                file_num: 1,
+                first: None,
+                last: None,
+                unlock: false
+            }
+        ));
+        assert!(matches!(
+            prog.statements[1].kind,
+            StmtKind::Lock {
+                first: Some(_),
+                last: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            prog.statements[2].kind,
+            StmtKind::Lock {
+                file_num: 2,
+                first: None,
+                last: Some(_),
+                unlock: true
+            }
+        ));
+        assert!(matches!(
+            prog.statements[3].kind,
+            StmtKind::Lock {
+                first: Some(_),
+                last: Some(_),
+                ..
+            }
+        ));
+        // Without a file number LOCK is an ordinary name
+        assert!(matches!(prog.statements[4].kind, StmtKind::Let { .. }));
+    }
+
+    #[test]
+    fn test_dict_set() {
+        let prog = parse("DICTSET D, K$, \"v\"\nDICTDEL D, K$\nDICTSET = 1").unwrap();
+        assert!(matches!(
+            prog.statements[0].kind,
+            StmtKind::DictSet { value: Some(_), .. }
+        ));
+        assert!(matches!(
+            prog.statements[1].kind,
+            StmtKind::DictSet { value: None, .. }
+        ));
+        // Assigned to, DICTSET is an ordinary name
+        assert!(matches!(prog.statements[2].kind, StmtKind::Let { .. }));
+    }
+
+    #[test]
+    fn test_print_using() {
+        let prog = parse("PRINT #2, USING F$; A, B;").unwrap();
+        if let StmtKind::PrintUsing {
+            file_num,
+            items,
+            newline,
+            ..
+        } = &prog.statements[0].kind
+        {
+            assert_eq!(*file_num, Some(2));
+            assert_eq!(items.len(), 2);
+            assert!(!*newline);
+        } else {
+            panic!("Expected PrintUsing");
+        }
+        assert!(parse("PRINT USING F$").is_err());
+    }
+
+    #[test]
+    fn test_write() {
+        let prog = parse(r#"WRITE #1, "a", 2"#).unwrap();
+        if let StmtKind::Write { file_num, items } = &prog.statements[0].kind {
+            assert_eq!(*file_num, Some(1));
+            assert_eq!(items.len(), 2);
+        } else {
+            panic!("Expected Write");
+        }
+    }
+
     // ===================
     // Input Tests
     // ===================
@@ -1493,10 +2779,16 @@ mod tests {
     fn test_input_simple() {
         let prog = parse("INPUT X").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Input { prompt, vars } = &prog.statements[0] {
+        if let StmtKind::Input {
+            prompt,
+            question,
+            vars,
+        } = &prog.statements[0].kind
+        {
             assert!(prompt.is_none());
+            assert!(question);
             assert_eq!(vars.len(), 1);
-            assert_eq!(vars[0], "X");
+            assert!(matches!(&vars[0].kind, ExprKind::Variable(name) if name == "X"));
         } else {
             panic!("Expected Input");
         }
@@ -1505,9 +2797,32 @@ mod tests {
     #[test]
     fn test_input_with_prompt() {
         let prog = parse(r#"INPUT "Enter value: ", X"#).unwrap();
-        if let Stmt::Input { prompt, vars } = &prog.statements[0] {
+        if let StmtKind::Input {
+            prompt,
+            question,
+            vars,
+        } = &prog.statements[0].kind
+        {
             assert_eq!(prompt.as_ref().unwrap(), "Enter value: ");
-            assert_eq!(vars[0], "X");
+            assert!(!question);
+            assert!(matches!(&vars[0].kind, ExprKind::Variable(name) if name == "X"));
+        } else {
+            panic!("Expected Input");
+        }
+    }
+
+    #[test]
+    fn test_input_prompt_semicolon() {
+        let prog = parse(r#"INPUT "Coords"; X, Y"#).unwrap();
+        if let StmtKind::Input {
+            prompt,
+            question,
+            vars,
+        } = &prog.statements[0].kind
+        {
+            assert_eq!(prompt.as_deref(), Some("Coords"));
+            assert!(question);
+            assert_eq!(vars.len(), 2);
         } else {
             panic!("Expected Input");
         }
@@ -1516,7 +2831,7 @@ mod tests {
     #[test]
     fn test_input_multiple_vars() {
         let prog = parse("INPUT A, B, C").unwrap();
-        if let Stmt::Input { vars, .. } = &prog.statements[0] {
+        if let StmtKind::Input { vars, .. } = &prog.statements[0].kind {
             assert_eq!(vars.len(), 3);
         } else {
             panic!("Expected Input");
@@ -1531,20 +2846,35 @@ mod tests {
     fn test_line_input_simple() {
         let prog = parse("LINE INPUT X$").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::LineInput { prompt, var } = &prog.statements[0] {
+        if let StmtKind::LineInput { prompt, var, .. } = &prog.statements[0].kind {
+            assert!(prompt.is_none());
+            assert!(matches!(&var.kind, ExprKind::Variable(name) if name == "X$"));
+        } else {
+            panic!("Expected LineInput");
+        }
+    }
+
+    #[test]
+    fn test_line_input_file() {
+        let prog = parse("LINE INPUT #2, L$(1)").unwrap();
+        if let StmtKind::LineInput {
+            file_num, prompt, ..
+        } = &prog.statements[0].kind
+        {
+            assert_eq!(*file_num, Some(2));
             assert!(prompt.is_none());
-            assert_eq!(var, "X$");
         } else {
             panic!("Expected LineInput");
         }
+        assert!(parse("LINE INPUT #2 L$").is_err());
     }
 
     #[test]
     fn test_line_input_with_prompt() {
         let prog = parse(r#"LINE INPUT "Name: ", NAME$"#).unwrap();
-        if let Stmt::LineInput { prompt, var } = &prog.statements[0] {
+        if let StmtKind::LineInput { prompt, var, .. } = &prog.statements[0].kind {
             assert_eq!(prompt.as_ref().unwrap(), "Name: ");
-            assert_eq!(var, "NAME$");
+            assert!(matches!(&var.kind, ExprKind::Variable(name) if name == "NAME$"));
         } else {
             panic!("Expected LineInput");
         }
@@ -1558,38 +2888,59 @@ mod tests {
     fn test_if_single_line() {
         let prog = parse("IF X > 0 THEN PRINT X").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::If {
+        if let StmtKind::If {
             condition,
             then_branch,
             else_branch,
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert!(matches!(
-                condition,
-                Expr::Binary {
+                &condition.kind,
+                ExprKind::Binary {
                     op: BinaryOp::Gt,
                     ..
                 }
             ));
             assert_eq!(then_branch.len(), 1);
-            assert!(else_branch.is_none());
+            assert!(else_branch.is_none());
+        } else {
+            panic!("Expected If");
+        }
+    }
+
+    #[test]
+    fn test_if_single_line_with_else() {
+        let prog = parse("IF X > 0 THEN PRINT X ELSE PRINT Y").unwrap();
+        if let StmtKind::If {
+            then_branch,
+            else_branch,
+            ..
+        } = &prog.statements[0].kind
+        {
+            assert_eq!(then_branch.len(), 1);
+            assert!(else_branch.is_some());
+            assert_eq!(else_branch.as_ref().unwrap().len(), 1);
         } else {
             panic!("Expected If");
         }
     }
 
     #[test]
-    fn test_if_single_line_with_else() {
-        let prog = parse("IF X > 0 THEN PRINT X ELSE PRINT Y").unwrap();
-        if let Stmt::If {
+    fn test_if_single_line_clauses() {
+        let prog = parse("IF X THEN 100 ELSE A = 1: B = 2: PRINT").unwrap();
+        assert_eq!(prog.statements.len(), 1);
+        if let StmtKind::If {
             then_branch,
             else_branch,
             ..
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert_eq!(then_branch.len(), 1);
-            assert!(else_branch.is_some());
-            assert_eq!(else_branch.as_ref().unwrap().len(), 1);
+            assert!(matches!(
+                then_branch[0].kind,
+                StmtKind::Goto(GotoTarget::Line(100))
+            ));
+            assert_eq!(else_branch.as_ref().unwrap().len(), 3);
         } else {
             panic!("Expected If");
         }
@@ -1598,11 +2949,11 @@ mod tests {
     #[test]
     fn test_if_block() {
         let prog = parse("IF X > 0 THEN\nPRINT X\nEND IF").unwrap();
-        if let Stmt::If {
+        if let StmtKind::If {
             then_branch,
             else_branch,
             ..
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert_eq!(then_branch.len(), 1);
             assert!(else_branch.is_none());
@@ -1614,11 +2965,11 @@ mod tests {
     #[test]
     fn test_if_block_with_else() {
         let prog = parse("IF X > 0 THEN\nPRINT X\nELSE\nPRINT Y\nEND IF").unwrap();
-        if let Stmt::If {
+        if let StmtKind::If {
             then_branch,
             else_branch,
             ..
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert_eq!(then_branch.len(), 1);
             assert!(else_branch.is_some());
@@ -1635,17 +2986,20 @@ mod tests {
     fn test_for_simple() {
         let prog = parse("FOR I = 1 TO 10\nPRINT I\nNEXT I").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::For {
+        if let StmtKind::For {
             var,
             start,
             end,
             step,
             body,
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert_eq!(var, "I");
-            assert!(matches!(start, Expr::Literal(Literal::Integer(1))));
-            assert!(matches!(end, Expr::Literal(Literal::Integer(10))));
+            assert!(matches!(
+                &start.kind,
+                ExprKind::Literal(Literal::Integer(1))
+            ));
+            assert!(matches!(&end.kind, ExprKind::Literal(Literal::Integer(10))));
             assert!(step.is_none());
             assert_eq!(body.len(), 1);
         } else {
@@ -1656,11 +3010,11 @@ mod tests {
     #[test]
     fn test_for_with_step() {
         let prog = parse("FOR I = 0 TO 100 STEP 10\nNEXT").unwrap();
-        if let Stmt::For { step, .. } = &prog.statements[0] {
+        if let StmtKind::For { step, .. } = &prog.statements[0].kind {
             assert!(step.is_some());
             assert!(matches!(
-                step.as_ref().unwrap(),
-                Expr::Literal(Literal::Integer(10))
+                &step.as_ref().unwrap().kind,
+                ExprKind::Literal(Literal::Integer(10))
             ));
         } else {
             panic!("Expected For");
@@ -1670,7 +3024,7 @@ mod tests {
     #[test]
     fn test_for_negative_step() {
         let prog = parse("FOR I = 10 TO 1 STEP -1\nNEXT").unwrap();
-        if let Stmt::For { step, .. } = &prog.statements[0] {
+        if let StmtKind::For { step, .. } = &prog.statements[0].kind {
             assert!(step.is_some());
         } else {
             panic!("Expected For");
@@ -1685,10 +3039,10 @@ mod tests {
     fn test_while_simple() {
         let prog = parse("WHILE X < 10\nX = X + 1\nWEND").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::While { condition, body } = &prog.statements[0] {
+        if let StmtKind::While { condition, body } = &prog.statements[0].kind {
             assert!(matches!(
-                condition,
-                Expr::Binary {
+                &condition.kind,
+                ExprKind::Binary {
                     op: BinaryOp::Lt,
                     ..
                 }
@@ -1707,12 +3061,12 @@ mod tests {
     fn test_do_loop_simple() {
         let prog = parse("DO\nX = X + 1\nLOOP").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::DoLoop {
+        if let StmtKind::DoLoop {
             condition,
             cond_at_start,
             body,
             ..
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert!(condition.is_none());
             assert!(!*cond_at_start);
@@ -1725,12 +3079,12 @@ mod tests {
     #[test]
     fn test_do_while() {
         let prog = parse("DO WHILE X < 10\nX = X + 1\nLOOP").unwrap();
-        if let Stmt::DoLoop {
+        if let StmtKind::DoLoop {
             condition,
             cond_at_start,
             is_until,
             ..
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert!(condition.is_some());
             assert!(*cond_at_start);
@@ -1743,12 +3097,12 @@ mod tests {
     #[test]
     fn test_do_until() {
         let prog = parse("DO UNTIL X >= 10\nX = X + 1\nLOOP").unwrap();
-        if let Stmt::DoLoop {
+        if let StmtKind::DoLoop {
             condition,
             cond_at_start,
             is_until,
             ..
-        } = &prog.statements[0]
+        } = &prog.statements[0].kind
         {
             assert!(condition.is_some());
             assert!(*cond_at_start);
@@ -1766,8 +3120,8 @@ mod tests {
     fn test_select_case_simple() {
         let prog = parse("SELECT CASE X\nCASE 1\nPRINT 1\nEND SELECT").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::SelectCase { expr, cases } = &prog.statements[0] {
-            assert!(matches!(expr, Expr::Variable(_)));
+        if let StmtKind::SelectCase { expr, cases } = &prog.statements[0].kind {
+            assert!(matches!(&expr.kind, ExprKind::Variable(_)));
             assert_eq!(cases.len(), 1);
             assert!(cases[0].0.is_some()); // Has a value
             assert_eq!(cases[0].1.len(), 1); // One statement in body
@@ -1779,7 +3133,7 @@ mod tests {
     #[test]
     fn test_select_case_multiple() {
         let prog = parse("SELECT CASE X\nCASE 1\nPRINT 1\nCASE 2\nPRINT 2\nEND SELECT").unwrap();
-        if let Stmt::SelectCase { cases, .. } = &prog.statements[0] {
+        if let StmtKind::SelectCase { cases, .. } = &prog.statements[0].kind {
             assert_eq!(cases.len(), 2);
         } else {
             panic!("Expected SelectCase");
@@ -1789,7 +3143,7 @@ mod tests {
     #[test]
     fn test_select_case_with_else() {
         let prog = parse("SELECT CASE X\nCASE 1\nPRINT 1\nCASE ELSE\nPRINT 0\nEND SELECT").unwrap();
-        if let Stmt::SelectCase { cases, .. } = &prog.statements[0] {
+        if let StmtKind::SelectCase { cases, .. } = &prog.statements[0].kind {
             assert_eq!(cases.len(), 2);
             assert!(cases[0].0.is_some()); // CASE 1
             assert!(cases[1].0.is_none()); // CASE ELSE
@@ -1801,10 +3155,12 @@ mod tests {
     #[test]
     fn test_select_case_string() {
         let prog = parse("SELECT CASE A$\nCASE \"yes\"\nPRINT 1\nEND SELECT").unwrap();
-        if let Stmt::SelectCase { expr, cases } = &prog.statements[0] {
-            assert!(matches!(expr, Expr::Variable(_)));
+        if let StmtKind::SelectCase { expr, cases } = &prog.statements[0].kind {
+            assert!(matches!(&expr.kind, ExprKind::Variable(_)));
             assert_eq!(cases.len(), 1);
-            if let Some(Expr::Literal(Literal::String(s))) = &cases[0].0 {
+            if let Some(ExprKind::Literal(Literal::String(s))) =
+                cases[0].0.as_ref().map(|e| &e.kind)
+            {
                 assert_eq!(s, "yes");
             } else {
                 panic!("Expected string literal in CASE");
@@ -1822,7 +3178,7 @@ mod tests {
     fn test_goto_line_number() {
         let prog = parse("GOTO 100").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Goto(target) = &prog.statements[0] {
+        if let StmtKind::Goto(target) = &prog.statements[0].kind {
             assert!(matches!(target, GotoTarget::Line(100)));
         } else {
             panic!("Expected Goto");
@@ -1832,7 +3188,7 @@ mod tests {
     #[test]
     fn test_goto_label() {
         let prog = parse("GOTO MYLOOP").unwrap();
-        if let Stmt::Goto(target) = &prog.statements[0] {
+        if let StmtKind::Goto(target) = &prog.statements[0].kind {
             if let GotoTarget::Label(name) = target {
                 assert_eq!(name, "MYLOOP");
             } else {
@@ -1851,7 +3207,7 @@ mod tests {
     fn test_gosub_line_number() {
         let prog = parse("GOSUB 1000").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Gosub(target) = &prog.statements[0] {
+        if let StmtKind::Gosub(target) = &prog.statements[0].kind {
             assert!(matches!(target, GotoTarget::Line(1000)));
         } else {
             panic!("Expected Gosub");
@@ -1861,7 +3217,7 @@ mod tests {
     #[test]
     fn test_gosub_label() {
         let prog = parse("GOSUB MYSUB").unwrap();
-        if let Stmt::Gosub(target) = &prog.statements[0] {
+        if let StmtKind::Gosub(target) = &prog.statements[0].kind {
             assert!(matches!(target, GotoTarget::Label(_)));
         } else {
             panic!("Expected Gosub");
@@ -1876,7 +3232,7 @@ mod tests {
     fn test_return() {
         let prog = parse("RETURN").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        assert!(matches!(&prog.statements[0], Stmt::Return));
+        assert!(matches!(&prog.statements[0].kind, StmtKind::Return));
     }
 
     // ===================
@@ -1887,14 +3243,157 @@ mod tests {
     fn test_on_goto() {
         let prog = parse("ON X GOTO 10, 20, 30").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::OnGoto { expr, targets } = &prog.statements[0] {
-            assert!(matches!(expr, Expr::Variable(_)));
+        if let StmtKind::OnGoto { expr, targets } = &prog.statements[0].kind {
+            assert!(matches!(&expr.kind, ExprKind::Variable(_)));
             assert_eq!(targets.len(), 3);
         } else {
             panic!("Expected OnGoto");
         }
     }
 
+    // ===================
+    // Event Tests
+    // ===================
+
+    #[test]
+    fn test_on_key_gosub() {
+        let prog = parse("ON KEY(1) GOSUB 100").unwrap();
+        assert_eq!(prog.statements.len(), 1);
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::OnKey {
+                target: GotoTarget::Line(100),
+                ..
+            }
+        ));
+        assert!(parse("ON KEY(1) GOTO 100").is_err());
+    }
+
+    #[test]
+    fn test_key_trap() {
+        let prog = parse("KEY(1) ON\nKEY(2) OFF\nKEY(11) STOP\nKEY OFF").unwrap();
+        assert_eq!(prog.statements.len(), 4);
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::KeyTrap {
+                state: TrapState::On,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &prog.statements[1].kind,
+            StmtKind::KeyTrap {
+                state: TrapState::Off,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &prog.statements[2].kind,
+            StmtKind::KeyTrap {
+                state: TrapState::Stop,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &prog.statements[3].kind,
+            StmtKind::KeyDisplay(false)
+        ));
+        assert!(parse("KEY(1) LIST").is_err());
+    }
+
+    #[test]
+    fn test_on_timer() {
+        let prog = parse("ON TIMER(5) GOSUB Tick: TIMER ON\nTIMER STOP\nTIMER OFF").unwrap();
+        assert_eq!(prog.statements.len(), 4);
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::OnTimer {
+                target: GotoTarget::Label(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            &prog.statements[1].kind,
+            StmtKind::TimerTrap(TrapState::On)
+        ));
+        assert!(matches!(
+            &prog.statements[2].kind,
+            StmtKind::TimerTrap(TrapState::Stop)
+        ));
+        assert!(matches!(
+            &prog.statements[3].kind,
+            StmtKind::TimerTrap(TrapState::Off)
+        ));
+        // TIMER is still a function in expressions
+        assert!(parse("T = TIMER").is_ok());
+        assert!(parse("TIMER LIST").is_err());
+    }
+
+    #[test]
+    fn test_on_break() {
+        let prog = parse("ON BREAK GOSUB 100: BREAK ON\nBREAK STOP\nBREAK OFF").unwrap();
+        assert_eq!(prog.statements.len(), 4);
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::OnBreak {
+                target: GotoTarget::Line(100)
+            }
+        ));
+        assert!(matches!(
+            &prog.statements[1].kind,
+            StmtKind::BreakTrap(TrapState::On)
+        ));
+        assert!(matches!(
+            &prog.statements[2].kind,
+            StmtKind::BreakTrap(TrapState::Stop)
+        ));
+        assert!(matches!(
+            &prog.statements[3].kind,
+            StmtKind::BreakTrap(TrapState::Off)
+        ));
+        // BREAK is still a variable name
+        let prog = parse("BREAK = 2\nON BREAK GOTO 10, 20").unwrap();
+        assert!(matches!(&prog.statements[1].kind, StmtKind::OnGoto { .. }));
+    }
+
+    #[test]
+    fn test_on_mouse() {
+        let prog = parse("ON MOUSE GOSUB 100: MOUSE ON\nMOUSE STOP\nMOUSE OFF").unwrap();
+        assert_eq!(prog.statements.len(), 4);
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::OnMouse {
+                target: GotoTarget::Line(100)
+            }
+        ));
+        assert!(matches!(
+            &prog.statements[1].kind,
+            StmtKind::MouseTrap(TrapState::On)
+        ));
+        assert!(matches!(
+            &prog.statements[2].kind,
+            StmtKind::MouseTrap(TrapState::Stop)
+        ));
+        assert!(matches!(
+            &prog.statements[3].kind,
+            StmtKind::MouseTrap(TrapState::Off)
+        ));
+        // MOUSEX() and friends are functions, MOUSE still a variable
+        let prog = parse("MOUSE = MOUSEX() + MOUSEB()").unwrap();
+        assert!(matches!(&prog.statements[0].kind, StmtKind::Let { .. }));
+    }
+
+    // ===================
+    // Option Tests
+    // ===================
+
+    #[test]
+    fn test_option_explicit() {
+        let prog = parse("OPTION EXPLICIT\nX = 1").unwrap();
+        assert!(matches!(&prog.statements[0].kind, StmtKind::OptionExplicit));
+        assert!(parse("OPTION BASE 1").is_err());
+    }
+
     // ===================
     // Dim Tests
     // ===================
@@ -1903,7 +3402,7 @@ mod tests {
     fn test_dim_single() {
         let prog = parse("DIM A(10)").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Dim { arrays } = &prog.statements[0] {
+        if let StmtKind::Dim { arrays, .. } = &prog.statements[0].kind {
             assert_eq!(arrays.len(), 1);
             assert_eq!(arrays[0].name, "A");
             assert_eq!(arrays[0].dimensions.len(), 1);
@@ -1915,7 +3414,7 @@ mod tests {
     #[test]
     fn test_dim_multiple() {
         let prog = parse("DIM A(10), B$(100), C(50)").unwrap();
-        if let Stmt::Dim { arrays } = &prog.statements[0] {
+        if let StmtKind::Dim { arrays, .. } = &prog.statements[0].kind {
             assert_eq!(arrays.len(), 3);
             assert_eq!(arrays[0].name, "A");
             assert_eq!(arrays[1].name, "B$");
@@ -1928,7 +3427,7 @@ mod tests {
     #[test]
     fn test_dim_2d() {
         let prog = parse("DIM A(10, 20)").unwrap();
-        if let Stmt::Dim { arrays } = &prog.statements[0] {
+        if let StmtKind::Dim { arrays, .. } = &prog.statements[0].kind {
             assert_eq!(arrays.len(), 1);
             assert_eq!(arrays[0].name, "A");
             assert_eq!(arrays[0].dimensions.len(), 2);
@@ -1940,7 +3439,7 @@ mod tests {
     #[test]
     fn test_dim_3d() {
         let prog = parse("DIM Matrix(5, 10, 15)").unwrap();
-        if let Stmt::Dim { arrays } = &prog.statements[0] {
+        if let StmtKind::Dim { arrays, .. } = &prog.statements[0].kind {
             assert_eq!(arrays.len(), 1);
             assert_eq!(arrays[0].name, "MATRIX");
             assert_eq!(arrays[0].dimensions.len(), 3);
@@ -1952,8 +3451,8 @@ mod tests {
     #[test]
     fn test_array_access_2d() {
         let prog = parse("X = A(1, 2)").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
-            if let Expr::FnCall { name, args } = value {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+            if let ExprKind::FnCall { name, args } = &value.kind {
                 assert_eq!(name, "A");
                 assert_eq!(args.len(), 2);
             } else {
@@ -1967,7 +3466,7 @@ mod tests {
     #[test]
     fn test_array_assign_2d() {
         let prog = parse("A(1, 2) = 42").unwrap();
-        if let Stmt::Let { name, indices, .. } = &prog.statements[0] {
+        if let StmtKind::Let { name, indices, .. } = &prog.statements[0].kind {
             assert_eq!(name, "A");
             assert!(indices.is_some());
             assert_eq!(indices.as_ref().unwrap().len(), 2);
@@ -1984,7 +3483,7 @@ mod tests {
     fn test_sub_no_params() {
         let prog = parse("SUB MySub\nPRINT X\nEND SUB").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Sub { name, params, body } = &prog.statements[0] {
+        if let StmtKind::Sub { name, params, body } = &prog.statements[0].kind {
             assert_eq!(name, "MYSUB");
             assert!(params.is_empty());
             assert_eq!(body.len(), 1);
@@ -1996,13 +3495,62 @@ mod tests {
     #[test]
     fn test_sub_with_params() {
         let prog = parse("SUB MySub(A, B, C)\nPRINT A + B + C\nEND SUB").unwrap();
-        if let Stmt::Sub { params, .. } = &prog.statements[0] {
+        if let StmtKind::Sub { params, .. } = &prog.statements[0].kind {
             assert_eq!(params.len(), 3);
         } else {
             panic!("Expected Sub");
         }
     }
 
+    #[test]
+    fn test_sub_typed_params() {
+        let prog = parse("SUB Foo(N AS INTEGER, S AS STRING, X, Y&)\nEND SUB").unwrap();
+        if let StmtKind::Sub { params, .. } = &prog.statements[0].kind {
+            let types: Vec<DataType> = params.iter().map(|p| p.data_type).collect();
+            assert_eq!(
+                types,
+                [
+                    DataType::Integer,
+                    DataType::String,
+                    DataType::Double,
+                    DataType::Long
+                ]
+            );
+            assert_eq!(params[0].name, "N");
+        } else {
+            panic!("Expected Sub");
+        }
+        assert!(parse("SUB Foo(N% AS INTEGER)\nEND SUB").is_err());
+        let prog = parse("SUB Sort(A(), N AS INTEGER)\nA(0) = N\nEND SUB").unwrap();
+        if let StmtKind::Sub { params, body, .. } = &prog.statements[0].kind {
+            assert!(params[0].is_array && !params[1].is_array);
+            assert!(matches!(
+                &body[0].kind,
+                StmtKind::Let {
+                    indices: Some(_),
+                    ..
+                }
+            ));
+        } else {
+            panic!("Expected Sub");
+        }
+        assert!(parse("SUB Foo(N AS BYTE)\nEND SUB").is_err());
+    }
+
+    #[test]
+    fn test_params_take_dialect_default() {
+        // An unsuffixed parameter is SINGLE in QuickBASIC, and a plain
+        // SHARED name isn't mistaken for one with an AS type
+        let source = "SUB Foo(X, Y#)\nSHARED Z\nEND SUB";
+        let lexer = Lexer::new(source).with_dialect(Dialect::Qb45);
+        let prog = Parser::new(lexer).parse().unwrap();
+        let StmtKind::Sub { params, .. } = &prog.statements[0].kind else {
+            panic!("Expected Sub");
+        };
+        let types: Vec<DataType> = params.iter().map(|p| p.data_type).collect();
+        assert_eq!(types, [DataType::Single, DataType::Double]);
+    }
+
     // ===================
     // Function Tests
     // ===================
@@ -2011,7 +3559,7 @@ mod tests {
     fn test_function_no_params() {
         let prog = parse("FUNCTION GetValue\nGetValue = 42\nEND FUNCTION").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Function { name, params, body } = &prog.statements[0] {
+        if let StmtKind::Function { name, params, body } = &prog.statements[0].kind {
             assert_eq!(name, "GETVALUE");
             assert!(params.is_empty());
             assert_eq!(body.len(), 1);
@@ -2023,7 +3571,7 @@ mod tests {
     #[test]
     fn test_function_with_params() {
         let prog = parse("FUNCTION Add(A, B)\nAdd = A + B\nEND FUNCTION").unwrap();
-        if let Stmt::Function { name, params, .. } = &prog.statements[0] {
+        if let StmtKind::Function { name, params, .. } = &prog.statements[0].kind {
             assert_eq!(name, "ADD");
             assert_eq!(params.len(), 2);
         } else {
@@ -2031,6 +3579,111 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_declare() {
+        let prog =
+            parse("DECLARE SUB Show(A(), N AS INTEGER)\nDECLARE FUNCTION Twice#(X#)").unwrap();
+        if let StmtKind::Declare {
+            name,
+            params,
+            is_function,
+            foreign,
+        } = &prog.statements[0].kind
+        {
+            assert_eq!(name, "SHOW");
+            assert!(!is_function && params[0].is_array && foreign.is_none());
+            assert_eq!(params[1].data_type, DataType::Integer);
+        } else {
+            panic!("Expected Declare");
+        }
+        assert!(matches!(
+            &prog.statements[1].kind,
+            StmtKind::Declare { name, is_function: true, foreign: None, .. } if name == "TWICE#"
+        ));
+        assert!(parse("DECLARE X").is_err());
+
+        let prog = parse(
+            "DECLARE FUNCTION GetPid& LIB \"c\" ()\n\
+             DECLARE SUB Nap LIB \"c\" ALIAS \"usleep\" (BYVAL Us&)",
+        )
+        .unwrap();
+        let foreign = |i: usize| match &prog.statements[i].kind {
+            StmtKind::Declare {
+                foreign: Some(f),
+                params,
+                ..
+            } => (f.lib.clone(), f.symbol.clone(), params.len()),
+            _ => panic!("Expected DECLARE ... LIB"),
+        };
+        assert_eq!(foreign(0), ("c".to_string(), "getpid".to_string(), 0));
+        assert_eq!(foreign(1), ("c".to_string(), "usleep".to_string(), 1));
+        assert!(parse("SUB S(BYVAL X)\nEND SUB").is_err());
+    }
+
+    #[test]
+    fn test_asm() {
+        let prog = parse("ASM\n  mov eax, { n& }\n  add {N&}, {y}\nEND ASM").unwrap();
+        let StmtKind::Asm { lines, vars } = &prog.statements[0].kind else {
+            panic!("Expected Asm");
+        };
+        assert_eq!(lines, &["  mov eax, {N&}", "  add {N&}, {Y}"]);
+        let names: Vec<&ExprKind> = vars.iter().map(|var| &var.kind).collect();
+        assert!(
+            matches!(names[..], [ExprKind::Variable(n), ExprKind::Variable(y)] if n == "N&" && y == "Y")
+        );
+        assert!(parse("ASM\nmov eax, {PRINT}\nEND ASM").is_err());
+        assert!(parse("ASM\nmov eax, {X\nEND ASM").is_err());
+    }
+
+    #[test]
+    fn test_dim_shared() {
+        let prog = parse("DIM SHARED X, A(5)\nDIM Y").unwrap();
+        if let StmtKind::Dim { arrays, shared } = &prog.statements[0].kind {
+            assert!(*shared);
+            assert!(arrays[0].dimensions.is_empty());
+            assert_eq!(arrays[1].dimensions.len(), 1);
+        } else {
+            panic!("Expected Dim");
+        }
+        assert!(matches!(
+            &prog.statements[1].kind,
+            StmtKind::Dim { shared: false, .. }
+        ));
+        assert!(parse("DIM A()").is_err());
+    }
+
+    #[test]
+    fn test_shared() {
+        let prog = parse("SUB S\nSHARED X, A(), N$\nEND SUB").unwrap();
+        if let StmtKind::Sub { body, .. } = &prog.statements[0].kind {
+            if let StmtKind::Shared(names) = &body[0].kind {
+                assert_eq!(names.len(), 3);
+                assert!(names[1].is_array && !names[2].is_array);
+            } else {
+                panic!("Expected Shared");
+            }
+        } else {
+            panic!("Expected Sub");
+        }
+        assert!(parse("SHARED").is_err());
+        assert!(parse("SHARED X AS INTEGER").is_err());
+    }
+
+    #[test]
+    fn test_const() {
+        let prog = parse("CONST N = 10, T$ = \"hi\"\nCONST = 5").unwrap();
+        if let StmtKind::Const(consts) = &prog.statements[0].kind {
+            assert_eq!(consts[0].0, "N");
+            assert_eq!(consts[1].0, "T$");
+        } else {
+            panic!("Expected Const");
+        }
+        // Without a name, CONST is a variable
+        assert!(matches!(&prog.statements[1].kind, StmtKind::Let { .. }));
+        assert!(parse("CONST 5 = 1").is_err());
+        assert!(parse("CONST N").is_err());
+    }
+
     // ===================
     // Call Tests
     // ===================
@@ -2039,7 +3692,7 @@ mod tests {
     fn test_call_no_args() {
         let prog = parse("MySub").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Call { name, args } = &prog.statements[0] {
+        if let StmtKind::Call { name, args } = &prog.statements[0].kind {
             assert_eq!(name, "MYSUB");
             assert!(args.is_empty());
         } else {
@@ -2050,7 +3703,7 @@ mod tests {
     #[test]
     fn test_call_with_parens() {
         let prog = parse("MySub(1, 2, 3)").unwrap();
-        if let Stmt::Call { name, args } = &prog.statements[0] {
+        if let StmtKind::Call { name, args } = &prog.statements[0].kind {
             assert_eq!(name, "MYSUB");
             assert_eq!(args.len(), 3);
         } else {
@@ -2061,13 +3714,25 @@ mod tests {
     #[test]
     fn test_call_without_parens() {
         let prog = parse("MySub 1, 2, 3").unwrap();
-        if let Stmt::Call { args, .. } = &prog.statements[0] {
+        if let StmtKind::Call { args, .. } = &prog.statements[0].kind {
             assert_eq!(args.len(), 3);
         } else {
             panic!("Expected Call");
         }
     }
 
+    #[test]
+    fn test_call_keyword() {
+        let prog = parse("DIM A(5)\nCALL Sort(A(), 5)\nCALL Done").unwrap();
+        if let StmtKind::Call { name, args } = &prog.statements[1].kind {
+            assert_eq!(name, "SORT");
+            assert_eq!(args.len(), 2);
+        } else {
+            panic!("Expected Call");
+        }
+        assert!(matches!(&prog.statements[2].kind, StmtKind::Call { args, .. } if args.is_empty()));
+    }
+
     // ===================
     // Data Tests
     // ===================
@@ -2076,7 +3741,7 @@ mod tests {
     fn test_data_integers() {
         let prog = parse("DATA 1, 2, 3, 4, 5").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Data(values) = &prog.statements[0] {
+        if let StmtKind::Data(values) = &prog.statements[0].kind {
             assert_eq!(values.len(), 5);
             assert!(matches!(values[0], Literal::Integer(1)));
         } else {
@@ -2087,7 +3752,7 @@ mod tests {
     #[test]
     fn test_data_mixed() {
         let prog = parse(r#"DATA 1, 3.14, "hello""#).unwrap();
-        if let Stmt::Data(values) = &prog.statements[0] {
+        if let StmtKind::Data(values) = &prog.statements[0].kind {
             assert_eq!(values.len(), 3);
             assert!(matches!(values[0], Literal::Integer(1)));
             assert!(matches!(values[1], Literal::Float(_)));
@@ -2100,7 +3765,7 @@ mod tests {
     #[test]
     fn test_data_negative() {
         let prog = parse("DATA -5, -3.14").unwrap();
-        if let Stmt::Data(values) = &prog.statements[0] {
+        if let StmtKind::Data(values) = &prog.statements[0].kind {
             assert!(matches!(values[0], Literal::Integer(-5)));
         } else {
             panic!("Expected Data");
@@ -2115,18 +3780,47 @@ mod tests {
     fn test_read_single() {
         let prog = parse("READ X").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Read(vars) = &prog.statements[0] {
+        if let StmtKind::Read(vars) = &prog.statements[0].kind {
             assert_eq!(vars.len(), 1);
-            assert_eq!(vars[0], "X");
+            assert!(matches!(&vars[0].kind, ExprKind::Variable(name) if name == "X"));
         } else {
             panic!("Expected Read");
         }
     }
 
+    #[test]
+    fn test_read_input_array_elements() {
+        let prog = parse("READ A(I), B$(1, 2), C\nINPUT X(3)\nLINE INPUT L$(N)").unwrap();
+        if let StmtKind::Read(vars) = &prog.statements[0].kind {
+            assert!(
+                matches!(&vars[0].kind, ExprKind::ArrayAccess { name, indices } if name == "A" && indices.len() == 1)
+            );
+            assert!(
+                matches!(&vars[1].kind, ExprKind::ArrayAccess { indices, .. } if indices.len() == 2)
+            );
+            assert!(matches!(&vars[2].kind, ExprKind::Variable(_)));
+        } else {
+            panic!("Expected Read");
+        }
+        assert!(
+            matches!(&prog.statements[1].kind, StmtKind::Input { vars, .. } if matches!(&vars[0].kind, ExprKind::ArrayAccess { .. }))
+        );
+        assert!(matches!(
+            &prog.statements[2].kind,
+            StmtKind::LineInput {
+                var: Expr {
+                    kind: ExprKind::ArrayAccess { .. },
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
     #[test]
     fn test_read_multiple() {
         let prog = parse("READ A, B, C$").unwrap();
-        if let Stmt::Read(vars) = &prog.statements[0] {
+        if let StmtKind::Read(vars) = &prog.statements[0].kind {
             assert_eq!(vars.len(), 3);
         } else {
             panic!("Expected Read");
@@ -2141,7 +3835,7 @@ mod tests {
     fn test_restore_simple() {
         let prog = parse("RESTORE").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        if let Stmt::Restore(target) = &prog.statements[0] {
+        if let StmtKind::Restore(target) = &prog.statements[0].kind {
             assert!(target.is_none());
         } else {
             panic!("Expected Restore");
@@ -2151,7 +3845,7 @@ mod tests {
     #[test]
     fn test_restore_with_target() {
         let prog = parse("RESTORE 100").unwrap();
-        if let Stmt::Restore(target) = &prog.statements[0] {
+        if let StmtKind::Restore(target) = &prog.statements[0].kind {
             assert!(target.is_some());
         } else {
             panic!("Expected Restore");
@@ -2166,7 +3860,38 @@ mod tests {
     fn test_cls() {
         let prog = parse("CLS").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        assert!(matches!(&prog.statements[0], Stmt::Cls));
+        assert!(matches!(&prog.statements[0].kind, StmtKind::Cls));
+    }
+
+    // ===================
+    // Width Tests
+    // ===================
+
+    #[test]
+    fn test_width() {
+        let prog = parse("WIDTH 40\nWIDTH 80, 25\nWIDTH #2, 10").unwrap();
+        assert_eq!(prog.statements.len(), 3);
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::Width {
+                file_num: None,
+                width: Expr {
+                    kind: ExprKind::Literal(Literal::Integer(40)),
+                    ..
+                }
+            }
+        ));
+        assert!(matches!(
+            &prog.statements[1].kind,
+            StmtKind::Width { file_num: None, .. }
+        ));
+        assert!(matches!(
+            &prog.statements[2].kind,
+            StmtKind::Width {
+                file_num: Some(2),
+                ..
+            }
+        ));
     }
 
     // ===================
@@ -2177,7 +3902,7 @@ mod tests {
     fn test_end() {
         let prog = parse("END").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        assert!(matches!(&prog.statements[0], Stmt::End));
+        assert!(matches!(&prog.statements[0].kind, StmtKind::End));
     }
 
     // ===================
@@ -2188,7 +3913,234 @@ mod tests {
     fn test_stop() {
         let prog = parse("STOP").unwrap();
         assert_eq!(prog.statements.len(), 1);
-        assert!(matches!(&prog.statements[0], Stmt::Stop));
+        assert!(matches!(&prog.statements[0].kind, StmtKind::Stop));
+    }
+
+    // ===================
+    // Graphics Tests
+    // ===================
+
+    #[test]
+    fn test_screen() {
+        let prog = parse("SCREEN 13").unwrap();
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::Screen {
+                mode: Expr {
+                    kind: ExprKind::Literal(Literal::Integer(13)),
+                    ..
+                }
+            }
+        ));
+    }
+
+    #[test]
+    fn test_screen_function() {
+        // SCREEN followed by parentheses in an expression is the function
+        let prog = parse("C = SCREEN(2, 3)\nSCREEN (1)").unwrap();
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+            assert!(
+                matches!(&value.kind, ExprKind::FnCall { name, args } if name == "SCREEN" && args.len() == 2)
+            );
+        } else {
+            panic!("Expected Let");
+        }
+        assert!(matches!(prog.statements[1].kind, StmtKind::Screen { .. }));
+    }
+
+    #[test]
+    fn test_pset_and_preset() {
+        let prog = parse("PSET (10, 20), 4\nPRESET (1, 2)").unwrap();
+        if let StmtKind::Pset { color, preset, .. } = &prog.statements[0].kind {
+            assert!(color.is_some());
+            assert!(!preset);
+        } else {
+            panic!("Expected Pset");
+        }
+        if let StmtKind::Pset { color, preset, .. } = &prog.statements[1].kind {
+            assert!(color.is_none());
+            assert!(preset);
+        } else {
+            panic!("Expected Pset");
+        }
+    }
+
+    #[test]
+    fn test_graphics_line() {
+        let prog = parse("LINE (0, 0)-(10, 10), 2, BF").unwrap();
+        if let StmtKind::GraphicsLine {
+            from, color, style, ..
+        } = &prog.statements[0].kind
+        {
+            assert!(from.is_some());
+            assert!(color.is_some());
+            assert_eq!(*style, LineStyle::FilledBox);
+        } else {
+            panic!("Expected GraphicsLine");
+        }
+    }
+
+    #[test]
+    fn test_graphics_line_from_last_point() {
+        let prog = parse("LINE -(5, 5), , B").unwrap();
+        if let StmtKind::GraphicsLine {
+            from, color, style, ..
+        } = &prog.statements[0].kind
+        {
+            assert!(from.is_none());
+            assert!(color.is_none());
+            assert_eq!(*style, LineStyle::Box);
+        } else {
+            panic!("Expected GraphicsLine");
+        }
+    }
+
+    #[test]
+    fn test_line_input_still_parses() {
+        let prog = parse("LINE INPUT A$").unwrap();
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::LineInput { .. }
+        ));
+    }
+
+    #[test]
+    fn test_circle() {
+        let prog = parse("CIRCLE (160, 100), 50, 14").unwrap();
+        if let StmtKind::Circle { color, .. } = &prog.statements[0].kind {
+            assert!(color.is_some());
+        } else {
+            panic!("Expected Circle");
+        }
+    }
+
+    #[test]
+    fn test_paint() {
+        let prog = parse("PAINT (5, 5), 2, 1\nPAINT (5, 5)").unwrap();
+        if let StmtKind::Paint { color, border, .. } = &prog.statements[0].kind {
+            assert!(color.is_some());
+            assert!(border.is_some());
+        } else {
+            panic!("Expected Paint");
+        }
+        if let StmtKind::Paint { color, border, .. } = &prog.statements[1].kind {
+            assert!(color.is_none());
+            assert!(border.is_none());
+        } else {
+            panic!("Expected Paint");
+        }
+    }
+
+    #[test]
+    fn test_get_and_put_image() {
+        let prog = parse("GET (0, 0)-(7, 7), S\nPUT (10, 10), S(2), PSET\nPUT (1, 1), S").unwrap();
+        if let StmtKind::GetImage { array, indices, .. } = &prog.statements[0].kind {
+            assert_eq!(array, "S");
+            assert!(indices.is_empty());
+        } else {
+            panic!("Expected GetImage");
+        }
+        if let StmtKind::PutImage { indices, mode, .. } = &prog.statements[1].kind {
+            assert_eq!(indices.len(), 1);
+            assert_eq!(*mode, PutMode::Pset);
+        } else {
+            panic!("Expected PutImage");
+        }
+        if let StmtKind::PutImage { mode, .. } = &prog.statements[2].kind {
+            assert_eq!(*mode, PutMode::Xor);
+        } else {
+            panic!("Expected PutImage");
+        }
+    }
+
+    #[test]
+    fn test_draw() {
+        let prog = parse("DRAW \"U10 R10\" + M$").unwrap();
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::Draw {
+                commands: Expr {
+                    kind: ExprKind::Binary { .. },
+                    ..
+                }
+            }
+        ));
+    }
+
+    #[test]
+    fn test_palette() {
+        let prog = parse("PALETTE 1, RGB(1, 2, 3)\nPALETTE: PALETTE").unwrap();
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::Palette {
+                entry: Some((
+                    Expr {
+                        kind: ExprKind::Literal(Literal::Integer(1)),
+                        ..
+                    },
+                    Expr {
+                        kind: ExprKind::FnCall { .. },
+                        ..
+                    }
+                ))
+            }
+        ));
+        assert!(matches!(
+            prog.statements[1].kind,
+            StmtKind::Palette { entry: None }
+        ));
+        assert!(matches!(
+            prog.statements[2].kind,
+            StmtKind::Palette { entry: None }
+        ));
+    }
+
+    // ===================
+    // Memory Tests
+    // ===================
+
+    #[test]
+    fn test_def_seg_and_poke() {
+        let prog = parse("DEF SEG = &HB800\nPOKE 10, 65\nDEF SEG\nX = PEEK(10)").unwrap();
+        assert!(matches!(
+            &prog.statements[0].kind,
+            StmtKind::DefSeg {
+                segment: Some(Expr {
+                    kind: ExprKind::Literal(Literal::Integer(0xB800)),
+                    ..
+                })
+            }
+        ));
+        assert!(matches!(&prog.statements[1].kind, StmtKind::Poke { .. }));
+        assert!(matches!(
+            &prog.statements[2].kind,
+            StmtKind::DefSeg { segment: None }
+        ));
+        assert!(parse("DEF FNA(X) = X * 2").is_err());
+    }
+
+    #[test]
+    fn test_bsave_and_bload() {
+        let prog = parse("BSAVE \"A.BIN\", 0, 100\nBLOAD \"A.BIN\"\nBLOAD F$, 50").unwrap();
+        assert!(matches!(&prog.statements[0].kind, StmtKind::Bsave { .. }));
+        assert!(matches!(
+            &prog.statements[1].kind,
+            StmtKind::Bload { offset: None, .. }
+        ));
+        assert!(matches!(
+            &prog.statements[2].kind,
+            StmtKind::Bload {
+                offset: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_varptr_requires_variable() {
+        assert!(parse("DIM A(10)\nX = VARPTR(A(3)) + VARSEG(B)").is_ok());
+        assert!(parse("X = VARPTR(5)").is_err());
+        assert!(parse("X = VARSEG(A + 1)").is_err());
     }
 
     // ===================
@@ -2199,12 +4151,12 @@ mod tests {
     fn test_expr_precedence() {
         // 2 + 3 * 4 should be 2 + (3 * 4) = 14, not (2 + 3) * 4 = 20
         let prog = parse("X = 2 + 3 * 4").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
-            if let Expr::Binary { op, right, .. } = value {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+            if let ExprKind::Binary { op, right, .. } = &value.kind {
                 assert_eq!(*op, BinaryOp::Add);
                 assert!(matches!(
-                    right.as_ref(),
-                    Expr::Binary {
+                    &right.as_ref().kind,
+                    ExprKind::Binary {
                         op: BinaryOp::Mul,
                         ..
                     }
@@ -2217,16 +4169,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_expr_short_circuit_precedence() {
+        // ANDALSO binds like AND, tighter than ORELSE
+        let prog = parse("X = A ORELSE B ANDALSO C < 1").unwrap();
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+            if let ExprKind::Binary { op, right, .. } = &value.kind {
+                assert_eq!(*op, BinaryOp::OrElse);
+                assert!(matches!(
+                    &right.as_ref().kind,
+                    ExprKind::Binary {
+                        op: BinaryOp::AndAlso,
+                        ..
+                    }
+                ));
+            } else {
+                panic!("Expected binary expression");
+            }
+        } else {
+            panic!("Expected Let");
+        }
+    }
+
     #[test]
     fn test_expr_power_right_associative() {
         // 2 ^ 3 ^ 2 should be 2 ^ (3 ^ 2) = 512, not (2 ^ 3) ^ 2 = 64
         let prog = parse("X = 2 ^ 3 ^ 2").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
-            if let Expr::Binary { op, right, .. } = value {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+            if let ExprKind::Binary { op, right, .. } = &value.kind {
                 assert_eq!(*op, BinaryOp::Pow);
                 assert!(matches!(
-                    right.as_ref(),
-                    Expr::Binary {
+                    &right.as_ref().kind,
+                    ExprKind::Binary {
                         op: BinaryOp::Pow,
                         ..
                     }
@@ -2242,12 +4216,12 @@ mod tests {
     #[test]
     fn test_expr_parentheses() {
         let prog = parse("X = (2 + 3) * 4").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
-            if let Expr::Binary { op, left, .. } = value {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+            if let ExprKind::Binary { op, left, .. } = &value.kind {
                 assert_eq!(*op, BinaryOp::Mul);
                 assert!(matches!(
-                    left.as_ref(),
-                    Expr::Binary {
+                    &left.as_ref().kind,
+                    ExprKind::Binary {
                         op: BinaryOp::Add,
                         ..
                     }
@@ -2263,10 +4237,10 @@ mod tests {
     #[test]
     fn test_expr_unary_neg() {
         let prog = parse("X = -5").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
             assert!(matches!(
-                value,
-                Expr::Unary {
+                &value.kind,
+                ExprKind::Unary {
                     op: UnaryOp::Neg,
                     ..
                 }
@@ -2279,10 +4253,10 @@ mod tests {
     #[test]
     fn test_expr_unary_not() {
         let prog = parse("X = NOT Y").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
             assert!(matches!(
-                value,
-                Expr::Unary {
+                &value.kind,
+                ExprKind::Unary {
                     op: UnaryOp::Not,
                     ..
                 }
@@ -2295,11 +4269,11 @@ mod tests {
     #[test]
     fn test_expr_logical_operators() {
         let prog = parse("X = A AND B OR C XOR D").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
             // OR has lowest precedence, then XOR, then AND
             assert!(matches!(
-                value,
-                Expr::Binary {
+                &value.kind,
+                ExprKind::Binary {
                     op: BinaryOp::Or,
                     ..
                 }
@@ -2312,10 +4286,10 @@ mod tests {
     #[test]
     fn test_expr_comparison() {
         let prog = parse("X = A < B").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
             assert!(matches!(
-                value,
-                Expr::Binary {
+                &value.kind,
+                ExprKind::Binary {
                     op: BinaryOp::Lt,
                     ..
                 }
@@ -2336,8 +4310,8 @@ mod tests {
             ("X = A >= B", BinaryOp::Ge),
         ] {
             let prog = parse(input).unwrap();
-            if let Stmt::Let { value, .. } = &prog.statements[0] {
-                if let Expr::Binary { op, .. } = value {
+            if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+                if let ExprKind::Binary { op, .. } = &value.kind {
                     assert_eq!(*op, expected_op, "Failed for input: {}", input);
                 } else {
                     panic!("Expected binary for: {}", input);
@@ -2360,8 +4334,8 @@ mod tests {
             ("X = A ^ B", BinaryOp::Pow),
         ] {
             let prog = parse(input).unwrap();
-            if let Stmt::Let { value, .. } = &prog.statements[0] {
-                if let Expr::Binary { op, .. } = value {
+            if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+                if let ExprKind::Binary { op, .. } = &value.kind {
                     assert_eq!(*op, expected_op, "Failed for input: {}", input);
                 } else {
                     panic!("Expected binary for: {}", input);
@@ -2375,8 +4349,8 @@ mod tests {
     #[test]
     fn test_expr_function_call() {
         let prog = parse("X = SIN(3.14)").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
-            if let Expr::FnCall { name, args } = value {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+            if let ExprKind::FnCall { name, args } = &value.kind {
                 assert_eq!(name, "SIN");
                 assert_eq!(args.len(), 1);
             } else {
@@ -2390,8 +4364,8 @@ mod tests {
     #[test]
     fn test_expr_function_multiple_args() {
         let prog = parse("X = MID$(A$, 1, 5)").unwrap();
-        if let Stmt::Let { value, .. } = &prog.statements[0] {
-            if let Expr::FnCall { name, args } = value {
+        if let StmtKind::Let { value, .. } = &prog.statements[0].kind {
+            if let ExprKind::FnCall { name, args } = &value.kind {
                 assert_eq!(name, "MID$");
                 assert_eq!(args.len(), 3);
             } else {
@@ -2402,6 +4376,102 @@ mod tests {
         }
     }
 
+    // ===================
+    // Block Mismatch Tests
+    // ===================
+
+    #[test]
+    fn test_unclosed_blocks() {
+        let cases = [
+            ("10 FOR I = 1 TO 3\n20 PRINT I", "FOR without NEXT"),
+            ("IF X THEN\nPRINT 1", "IF without END IF"),
+            ("IF X THEN\nELSE\nPRINT 1", "IF without END IF"),
+            ("WHILE X\nPRINT 1", "WHILE without WEND"),
+            ("DO\nPRINT 1", "DO without LOOP"),
+            (
+                "SELECT CASE X\nCASE 1\nPRINT 1",
+                "SELECT CASE without END SELECT",
+            ),
+            ("SUB S\nPRINT 1", "SUB without END SUB"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse(source).unwrap_err(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_stray_terminators() {
+        let cases = [
+            ("10 PRINT 1\n20 NEXT", "NEXT without FOR"),
+            ("END IF", "END IF without IF"),
+            ("ELSE", "ELSE without IF"),
+            ("WEND", "WEND without WHILE"),
+            ("LOOP UNTIL X", "LOOP without DO"),
+            ("CASE 1", "CASE without SELECT CASE"),
+            ("DO\nPRINT 1\nWEND", "WEND without WHILE"),
+            ("FOR I = 1 TO 2\nIF I THEN LOOP\nNEXT", "LOOP without DO"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse(source).unwrap_err(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_next_variable_mismatch() {
+        assert!(parse("FOR I = 1 TO 2\nFOR J = 1 TO 2\nNEXT J\nNEXT I").is_ok());
+        assert!(parse("FOR I = 1 TO 2\nNEXT").is_ok());
+        let source = "10 FOR I = 1 TO 2\n20 FOR J = 1 TO 2\n30 NEXT I\n40 NEXT J";
+        assert_eq!(parse(source).unwrap_err(), "NEXT I doesn't match FOR J");
+        assert_eq!(error_at(source), (3, 4));
+    }
+
+    // ===================
+    // Error Position Tests
+    // ===================
+
+    #[test]
+    fn test_error_positions() {
+        // The token at fault, whether it was consumed or only looked at
+        assert_eq!(error_at("X = 1\nPRINT (1 + 2\n"), (2, 13));
+        assert_eq!(error_at("X = 1\n  Y = )"), (2, 7));
+        assert_eq!(error_at("FOR I = 1 10"), (1, 11));
+        // Block errors point at the statement that opened or closed the block
+        assert_eq!(error_at("PRINT 1\n  WHILE X\nPRINT 2"), (2, 3));
+        assert_eq!(error_at("PRINT 1\nX = 2: NEXT"), (2, 8));
+    }
+
+    #[test]
+    fn test_lexer_errors() {
+        // Tokens are pulled as the parser goes: a bad character ends them,
+        // and the lexer's error is reported rather than the parser's
+        let e = parse_with_spans("PRINT 1\nX = @").unwrap_err();
+        assert_eq!(e.code, "lex-error");
+        assert_eq!(e.message, "Unexpected character: @");
+        assert_eq!(error_at("PRINT 1\nX = @"), (2, 5));
+        let e = parse_with_spans("PRINT 1\nPRINT @").unwrap_err();
+        assert_eq!(e.code, "lex-error");
+
+        // ... unless the parser finds an error before reaching it
+        let e = parse_with_spans("PRINT (1\nX = 2\nY = @").unwrap_err();
+        assert_eq!(e.code, "parse-error");
+    }
+
+    #[test]
+    fn test_statement_spans() {
+        let prog = parse_with_spans("10 PRINT 1: X = 2\n  IF X THEN\n    CLS\n  END IF").unwrap();
+        let spans: Vec<_> = prog
+            .statements
+            .iter()
+            .map(|s| (s.span.line, s.span.col))
+            .collect();
+        assert_eq!(spans, vec![(1, 1), (1, 4), (1, 13), (2, 3)]);
+        if let StmtKind::If { then_branch, .. } = &prog.statements[3].kind {
+            assert_eq!((then_branch[0].span.line, then_branch[0].span.col), (3, 5));
+        } else {
+            panic!("Expected If");
+        }
+    }
+
     // ===================
     // Integration Tests
     // ===================
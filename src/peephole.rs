@@ -0,0 +1,316 @@
+//! Peephole optimizer - an IR pass run at -O1 and above
+//!
+//! Rewrites short instruction sequences into cheaper equivalents, until
+//! none are left:
+//!
+//! | Sequence                              | Becomes                    |
+//! |---------------------------------------|----------------------------|
+//! | `Const`, `Convert`                    | the converted `Const`      |
+//! | `Push`, `Const`, `PopRight`           | `ConstRight`               |
+//! | `Push`, `Load`, `PopRight`            | `LoadRight`                |
+//! | `Convert` a to b, `Convert` b to a    | nothing (exact round trip) |
+//! | `Store` to X, `Load` from X           | `Store` (value still held) |
+//! | `Jump` to L, `Label` L                | `Label` L                  |
+//!
+//! The first three remove the stack traffic a binary operation normally
+//! costs when its right operand is a constant or a variable.
+//!
+//! `Source` markers don't get in the way of a rewrite: one inside a
+//! rewritten sequence is moved after its replacement.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::ir::{Const, Inst};
+use crate::parser::DataType;
+
+/// Apply the rewrites until the code stops changing
+pub fn optimize(code: &mut Vec<Inst>) {
+    while rewrite(code) {}
+}
+
+/// One pass over the code. Returns whether anything changed.
+fn rewrite(code: &mut Vec<Inst>) -> bool {
+    // Set the Source markers aside, each with the index of the instruction
+    // it came before
+    let mut marks = Vec::new();
+    let mut insts = Vec::with_capacity(code.len());
+    for inst in code.drain(..) {
+        match inst {
+            Inst::Source(_) => marks.push((insts.len(), inst)),
+            inst => insts.push(inst),
+        }
+    }
+    let mut marks = marks.into_iter().peekable();
+
+    let mut out = Vec::with_capacity(insts.len() + marks.len());
+    let mut changed = false;
+    let mut i = 0;
+    while i < insts.len() {
+        while let Some((_, mark)) = marks.next_if(|&(at, _)| at <= i) {
+            out.push(mark);
+        }
+        match rewrite_at(&insts[i..]) {
+            Some((consumed, replacement)) => {
+                out.extend(replacement);
+                i += consumed;
+                changed = true;
+                while let Some((_, mark)) = marks.next_if(|&(at, _)| at < i) {
+                    out.push(mark);
+                }
+            }
+            None => {
+                // Move rather than clone: rewrite_at only looks ahead
+                out.push(std::mem::replace(&mut insts[i], Inst::Asm(String::new())));
+                i += 1;
+            }
+        }
+    }
+    out.extend(marks.map(|(_, mark)| mark));
+    *code = out;
+    changed
+}
+
+/// A rewrite of the instructions at the start of `code`: how many it
+/// replaces and what with
+fn rewrite_at(code: &[Inst]) -> Option<(usize, Vec<Inst>)> {
+    match code {
+        [Inst::Const(c), Inst::Convert { from, to, .. }, ..] => {
+            Some((2, vec![Inst::Const(convert(*c, *from, *to)?)]))
+        }
+        [Inst::Push(ty), Inst::Const(c), Inst::PopRight(pop), ..] if ty == pop && c.holds(*ty) => {
+            Some((3, vec![Inst::ConstRight(*c)]))
+        }
+        [
+            Inst::Push(ty),
+            Inst::Load { ty: load, addr },
+            Inst::PopRight(pop),
+            ..,
+        ] if ty == load && ty == pop && *ty != DataType::String => {
+            let addr = addr.clone();
+            Some((3, vec![Inst::LoadRight { ty: *ty, addr }]))
+        }
+        [
+            Inst::Convert { from, to, .. },
+            Inst::Convert {
+                from: back,
+                to: orig,
+                ..
+            },
+            ..,
+        ] if to == back && from == orig && round_trips(*from, *to) => Some((2, vec![])),
+        // An INTEGER store keeps only the low 16 bits, so the load matters
+        [
+            Inst::Store { ty, addr },
+            Inst::Load {
+                ty: load,
+                addr: from,
+            },
+            ..,
+        ] if ty == load && addr == from && *ty != DataType::Integer => {
+            Some((2, vec![code[0].clone()]))
+        }
+        [Inst::Jump(target), Inst::Label(label), ..] if target == label => Some((1, vec![])),
+        _ => None,
+    }
+}
+
+/// Whether converting `from` to `to` and back gives the original value
+fn round_trips(from: DataType, to: DataType) -> bool {
+    matches!(
+        (from, to),
+        (DataType::Integer | DataType::Long, DataType::Double)
+            | (DataType::Single, DataType::Double)
+    )
+}
+
+/// A constant converted at compile time, or None where the conversion
+/// could overflow
+fn convert(c: Const, from: DataType, to: DataType) -> Option<Const> {
+    // An INTEGER on either side is sign-extended from 16 bits
+    let int_range = |n: f64| match (from, to) {
+        (DataType::Integer, _) | (_, DataType::Integer) => {
+            (i16::MIN as f64..=i16::MAX as f64).contains(&n)
+        }
+        _ => (i32::MIN as f64..=i32::MAX as f64).contains(&n),
+    };
+    match (c, to) {
+        (Const::Long(n), DataType::Integer | DataType::Long) => int_range(n as f64).then_some(c),
+        (Const::Long(n), DataType::Single) => Some(Const::Single(n as f32)),
+        (Const::Long(n), DataType::Double) => Some(Const::Double(n as f64)),
+        (Const::Single(x), DataType::Double) => Some(Const::Double(x as f64)),
+        (Const::Double(x), DataType::Single) => Some(Const::Single(x as f32)),
+        // Truncate toward zero
+        (Const::Single(_) | Const::Double(_), DataType::Integer | DataType::Long) => {
+            let x = match c {
+                Const::Single(x) => (x as f64).trunc(),
+                Const::Double(x) => x.trunc(),
+                _ => unreachable!(),
+            };
+            int_range(x).then_some(Const::Long(x as i32))
+        }
+        _ if from == to => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BinaryOp;
+
+    fn optimized(mut code: Vec<Inst>) -> Vec<Inst> {
+        optimize(&mut code);
+        code
+    }
+
+    fn convert_inst(from: DataType, to: DataType) -> Inst {
+        Inst::Convert {
+            from,
+            to,
+            checked: false,
+        }
+    }
+
+    // ===================
+    // Rewrite Tests
+    // ===================
+
+    #[test]
+    fn test_constant_right_operand() {
+        // X# + 1: the 1 is converted at compile time and goes straight to xmm1
+        let code = vec![
+            Inst::Load {
+                ty: DataType::Double,
+                addr: "rbp + -8".into(),
+            },
+            Inst::Push(DataType::Double),
+            Inst::Const(Const::Long(1)),
+            convert_inst(DataType::Long, DataType::Double),
+            Inst::PopRight(DataType::Double),
+            Inst::Binary {
+                op: BinaryOp::Add,
+                ty: DataType::Double,
+            },
+        ];
+        assert_eq!(
+            optimized(code),
+            vec![
+                Inst::Load {
+                    ty: DataType::Double,
+                    addr: "rbp + -8".into(),
+                },
+                Inst::ConstRight(Const::Double(1.0)),
+                Inst::Binary {
+                    op: BinaryOp::Add,
+                    ty: DataType::Double,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_variable_right_operand() {
+        let load = |addr: &str| Inst::Load {
+            ty: DataType::Long,
+            addr: addr.into(),
+        };
+        let code = vec![
+            load("rbp + -8"),
+            Inst::Push(DataType::Long),
+            load("rbp + -16"),
+            Inst::PopRight(DataType::Long),
+            Inst::Binary {
+                op: BinaryOp::Mul,
+                ty: DataType::Long,
+            },
+        ];
+        assert_eq!(
+            optimized(code),
+            vec![
+                load("rbp + -8"),
+                Inst::LoadRight {
+                    ty: DataType::Long,
+                    addr: "rbp + -16".into(),
+                },
+                Inst::Binary {
+                    op: BinaryOp::Mul,
+                    ty: DataType::Long,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redundant_sequences() {
+        let store = |ty| Inst::Store {
+            ty,
+            addr: "rbp + -8".into(),
+        };
+        let load = |ty| Inst::Load {
+            ty,
+            addr: "rbp + -8".into(),
+        };
+        let code = vec![
+            Inst::Call("_rt_rnd".to_string()),
+            convert_inst(DataType::Single, DataType::Double),
+            convert_inst(DataType::Double, DataType::Single),
+            store(DataType::Single),
+            load(DataType::Single),
+            Inst::Jump(".Lnext".to_string()),
+            Inst::Label(".Lnext".to_string()),
+        ];
+        assert_eq!(
+            optimized(code),
+            vec![
+                Inst::Call("_rt_rnd".to_string()),
+                store(DataType::Single),
+                Inst::Label(".Lnext".to_string()),
+            ]
+        );
+
+        // Not every round trip or reload is a no-op
+        let code = vec![
+            convert_inst(DataType::Long, DataType::Single),
+            convert_inst(DataType::Single, DataType::Long),
+            store(DataType::Integer),
+            load(DataType::Integer),
+            Inst::Const(Const::Double(1e10)),
+            convert_inst(DataType::Double, DataType::Long),
+        ];
+        assert_eq!(optimized(code.clone()), code);
+    }
+
+    #[test]
+    fn test_source_markers() {
+        // A marker between two lines doesn't stop a rewrite, and moves
+        // after the replacement
+        let store = Inst::Store {
+            ty: DataType::Long,
+            addr: "rbp + -8".into(),
+        };
+        let code = vec![
+            Inst::Source(1),
+            Inst::Const(Const::Long(1)),
+            store.clone(),
+            Inst::Source(2),
+            Inst::Load {
+                ty: DataType::Long,
+                addr: "rbp + -8".into(),
+            },
+            Inst::Call("_rt_print_int".to_string()),
+            Inst::Source(3),
+        ];
+        assert_eq!(
+            optimized(code),
+            vec![
+                Inst::Source(1),
+                Inst::Const(Const::Long(1)),
+                store,
+                Inst::Source(2),
+                Inst::Call("_rt_print_int".to_string()),
+                Inst::Source(3),
+            ]
+        );
+    }
+}
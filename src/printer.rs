@@ -0,0 +1,1386 @@
+//! BASIC printer - writes a parsed program back out as source
+//!
+//! `xbasic64 fmt` parses a program and prints it in one layout: keywords
+//! in capitals, each block indented a level, and line numbers padded to
+//! the same width so the code after them lines up:
+//!
+//! ```text
+//! 10  FOR I = 1 TO 3
+//! 20      PRINT I
+//! 100 NEXT I
+//! ```
+//!
+//! Statements written on one line with colons stay on one line, as do
+//! single-line IFs, and blank lines between statements are kept (one for
+//! several).
+//!
+//! The syntax tree has no comments, so they come from the lexer and are
+//! put back by position: a comment on a line of its own goes before the
+//! statement after it, one after a statement stays at the end of its line.
+//! Block keywords (NEXT, ELSE, END IF...) aren't statements either, so the
+//! lexer's tokens say which lines they were on.
+//!
+//! Names and numbers are kept as written. The syntax tree has names in
+//! capitals and numbers as values (`&HFF00` is 65280), so each printed
+//! line is lexed again and its names and numbers are matched with those
+//! on the source line it came from.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Comment, Lexer, Span, Token};
+use crate::parser::{
+    BinaryOp, DataType, Expr, ExprKind, FileMode, GotoTarget, LineStyle, Literal, Param, Parser,
+    PrintItem, Program, PutMode, Stmt, StmtKind, TrapState, UnaryOp,
+};
+use std::collections::{HashMap, VecDeque};
+
+/// Spaces for each level of indentation
+const INDENT: usize = 4;
+
+/// Parse `source` and print it formatted
+pub fn format_source(source: &str) -> Result<String, Diagnostic> {
+    let program = Parser::new(Lexer::new(source)).parse()?;
+    Ok(format(&program, source))
+}
+
+/// Print a program, with the comments of the `source` it was parsed from
+pub fn format(program: &Program, source: &str) -> String {
+    let mut lexer = Lexer::new(source);
+    let tokens: Vec<(Token, Span)> = lexer.by_ref().map_while(Result::ok).collect();
+    let mut printer = Printer {
+        out: String::with_capacity(source.len()),
+        gutter: label_width(&program.statements),
+        comments: lexer.comments().iter().cloned().collect(),
+        marks: block_keywords(&tokens),
+        last: Span::default(),
+        last_line: 0,
+        label: None,
+        lines: Vec::new(),
+    };
+    printer.block(&program.statements, 0);
+    printer.finish();
+    respell(&printer.out, &printer.lines, source)
+}
+
+/// Digits in the largest line number, or 0 for a program without any
+fn label_width(stmts: &[Stmt]) -> usize {
+    stmts
+        .iter()
+        .map(|stmt| match stmt.kind {
+            StmtKind::Label(n) => n.to_string().len(),
+            ref kind => kind
+                .bodies()
+                .into_iter()
+                .map(label_width)
+                .max()
+                .unwrap_or(0),
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Where the keywords that end or divide a block start: NEXT, WEND, LOOP,
+/// ELSE, ELSEIF, CASE and the END IF/SUB/FUNCTION/SELECT forms
+fn block_keywords(tokens: &[(Token, Span)]) -> Vec<Span> {
+    let mut marks = Vec::new();
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        let at_start = i == 0
+            || matches!(
+                tokens[i - 1].0,
+                Token::Newline | Token::Colon | Token::LineNumber(_)
+            );
+        let keyword = match token {
+            Token::Next
+            | Token::Wend
+            | Token::Loop
+            | Token::Else
+            | Token::ElseIf
+            | Token::Case
+            | Token::EndIf
+            | Token::EndSub
+            | Token::EndFunction
+            | Token::EndSelect => true,
+            Token::End => matches!(
+                tokens.get(i + 1),
+                Some((Token::If | Token::Sub | Token::Function | Token::Select, _))
+            ),
+            _ => false,
+        };
+        if at_start && keyword {
+            marks.push(*span);
+        }
+    }
+    marks
+}
+
+/// Whether `a` is before `b` in the source
+fn before(a: Span, b: Span) -> bool {
+    (a.line, a.col) < (b.line, b.col)
+}
+
+/// Where the last statement inside `stmt` (or `stmt` itself) starts
+fn last_span(stmt: &Stmt) -> Span {
+    let inner = stmt.kind.bodies().into_iter().flatten().map(last_span);
+    inner.fold(stmt.span, |a, b| if before(a, b) { b } else { a })
+}
+
+/// The loops inside `stmt`, counting itself, each closed by a keyword
+fn loops(stmt: &Stmt) -> usize {
+    let own = matches!(
+        stmt.kind,
+        StmtKind::For { .. } | StmtKind::While { .. } | StmtKind::DoLoop { .. }
+    );
+    let inner: usize = stmt.kind.bodies().into_iter().flatten().map(loops).sum();
+    inner + own as usize
+}
+
+/// Whether a statement can be written on `line`, joined to others there by
+/// colons: block IFs, SELECT CASE, procedures and ASM take several lines
+fn fits_on(stmt: &Stmt, line: u32) -> bool {
+    let on = |body: &[Stmt]| body.iter().all(|stmt| fits_on(stmt, line));
+    stmt.span.line == line
+        && match &stmt.kind {
+            StmtKind::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                !then_branch.is_empty() && on(then_branch) && else_branch.as_deref().is_none_or(on)
+            }
+            StmtKind::For { body, .. }
+            | StmtKind::While { body, .. }
+            | StmtKind::DoLoop { body, .. } => on(body),
+            StmtKind::SelectCase { .. }
+            | StmtKind::Sub { .. }
+            | StmtKind::Function { .. }
+            | StmtKind::Asm { .. }
+            | StmtKind::Label(_) => false,
+            _ => true,
+        }
+}
+
+/// Whether nothing can follow a statement on its line: a single-line IF
+/// takes the rest of it, unless its last branch is just a line number
+fn ends_line(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::If {
+            then_branch,
+            else_branch,
+            ..
+        } => !matches!(
+            else_branch.as_ref().unwrap_or(then_branch).as_slice(),
+            [Stmt {
+                kind: StmtKind::Goto(GotoTarget::Line(_)),
+                ..
+            }]
+        ),
+        _ => false,
+    }
+}
+
+struct Printer {
+    out: String,
+    gutter: usize,               // width of the widest line number (0: none)
+    comments: VecDeque<Comment>, // those not printed yet
+    marks: Vec<Span>,            // where the block keywords are
+    last: Span,                  // the last statement or keyword printed
+    last_line: u32,              // the last source line printed
+    label: Option<(u32, u32)>,   // line number (and its source line) to print next
+    lines: Vec<u32>,             // source line of each line printed (0: none)
+}
+
+impl Printer {
+    /// Print one line: the line number waiting or padding, the indentation,
+    /// `text`, and the comments at the end of source line `line` (0 when
+    /// not known). A blank line goes first if there was one before it.
+    fn emit(&mut self, indent: usize, text: &str, line: u32) {
+        if line > 0 {
+            if !self.out.is_empty() && line > self.last_line + 1 {
+                self.out.push('\n');
+                self.lines.push(0);
+            }
+            self.last_line = self.last_line.max(line);
+        }
+        let mut text = text.to_string();
+        while let Some(comment) = self.comments.front() {
+            if line == 0 || comment.span.line != line {
+                break;
+            }
+            let comment = comment_text(comment);
+            if !text.is_empty() {
+                // REM is a statement, so it needs the separator after another
+                let separator = if comment.starts_with("REM") {
+                    ": "
+                } else {
+                    " "
+                };
+                text.push_str(separator);
+            }
+            text.push_str(&comment);
+            self.comments.pop_front();
+        }
+        let mut out = String::new();
+        if self.gutter > 0 {
+            let number = self
+                .label
+                .take()
+                .map_or(String::new(), |(n, _)| n.to_string());
+            out = format!("{:<width$} ", number, width = self.gutter);
+        }
+        out.push_str(&" ".repeat(indent * INDENT));
+        out.push_str(&text);
+        self.out.push_str(out.trim_end());
+        self.out.push('\n');
+        self.lines.push(line);
+    }
+
+    /// Print the comments that come before `span`, each on its own line
+    fn flush(&mut self, span: Span, indent: usize) {
+        while let Some(comment) = self.comments.front() {
+            if !before(comment.span, span) {
+                break;
+            }
+            let line = comment.span.line;
+            self.emit(indent, "", line);
+        }
+    }
+
+    /// The next block keyword after the last thing printed
+    fn next_mark(&self) -> Option<Span> {
+        self.marks
+            .iter()
+            .copied()
+            .find(|&mark| before(self.last, mark))
+    }
+
+    /// Print a block keyword, on the line of the next one in the source
+    fn keyword(&mut self, indent: usize, text: &str) {
+        let line = match self.next_mark() {
+            Some(mark) => {
+                // Comments at the end of the block stay in it
+                self.flush(mark, indent + 1);
+                self.last = mark;
+                mark.line
+            }
+            None => 0,
+        };
+        self.emit(indent, text, line);
+    }
+
+    /// Print whatever is left over at the end
+    fn finish(&mut self) {
+        if let Some((_, line)) = self.label {
+            self.emit(0, "", line);
+        }
+        let end = Span {
+            line: u32::MAX,
+            col: u32::MAX,
+        };
+        self.flush(end, 0);
+    }
+
+    /// Print a block of statements, joining those that were on one line
+    fn block(&mut self, stmts: &[Stmt], indent: usize) {
+        let mut i = 0;
+        while i < stmts.len() {
+            let stmt = &stmts[i];
+            self.flush(stmt.span, indent);
+            self.last = stmt.span;
+            if let StmtKind::Label(n) = stmt.kind {
+                // The line number goes on the line of what follows it; at
+                // the end of a block that is the keyword closing it
+                self.label = Some((n, stmt.span.line));
+                match stmts.get(i + 1) {
+                    Some(next) if next.span.line != stmt.span.line => {
+                        self.emit(indent, "", stmt.span.line)
+                    }
+                    _ => {}
+                }
+                i += 1;
+                continue;
+            }
+            if !self.fits(stmt) {
+                self.compound(stmt, indent);
+                i += 1;
+                continue;
+            }
+            let line = stmt.span.line;
+            let mut end = i + 1;
+            while end < stmts.len()
+                && !ends_line(&stmts[end - 1])
+                && self.fits(&stmts[end])
+                && stmts[end].span.line == line
+            {
+                end += 1;
+            }
+            let group = &stmts[i..end];
+            let text: Vec<String> = group.iter().map(inline).collect();
+            self.emit(indent, &text.join(": "), line);
+            // Past the keywords of loops written on the line
+            for stmt in group {
+                self.last = last_span(stmt);
+            }
+            for _ in 0..group.iter().map(loops).sum() {
+                match self.next_mark() {
+                    Some(mark) if mark.line == line => self.last = mark,
+                    _ => break,
+                }
+            }
+            i = end;
+        }
+    }
+
+    /// Whether a statement goes on one line: a loop only if it was written
+    /// on one, keyword and all
+    fn fits(&self, stmt: &Stmt) -> bool {
+        let line = stmt.span.line;
+        if !fits_on(stmt, line) {
+            return false;
+        }
+        match &stmt.kind {
+            StmtKind::For { .. } | StmtKind::While { .. } | StmtKind::DoLoop { .. } => {
+                let after = last_span(stmt);
+                self.marks
+                    .iter()
+                    .any(|&mark| mark.line == line && before(after, mark))
+            }
+            _ => true,
+        }
+    }
+
+    /// Print a statement that takes several lines
+    fn compound(&mut self, stmt: &Stmt, indent: usize) {
+        let line = stmt.span.line;
+        match &stmt.kind {
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.emit(indent, &format!("IF {} THEN", expr(condition)), line);
+                self.if_body(then_branch, else_branch.as_deref(), indent);
+            }
+            StmtKind::For { var, body, .. } => {
+                self.emit(indent, &for_head(&stmt.kind), line);
+                self.block(body, indent + 1);
+                self.keyword(indent, &format!("NEXT {}", var));
+            }
+            StmtKind::While { condition, body } => {
+                self.emit(indent, &format!("WHILE {}", expr(condition)), line);
+                self.block(body, indent + 1);
+                self.keyword(indent, "WEND");
+            }
+            StmtKind::DoLoop { body, .. } => {
+                let (head, tail) = do_ends(&stmt.kind);
+                self.emit(indent, &head, line);
+                self.block(body, indent + 1);
+                self.keyword(indent, &tail);
+            }
+            StmtKind::Sub { name, params, body } => {
+                self.emit(
+                    indent,
+                    &format!("SUB {}{}", name, params_text(params)),
+                    line,
+                );
+                self.block(body, indent + 1);
+                self.keyword(indent, "END SUB");
+            }
+            StmtKind::Function { name, params, body } => {
+                let head = format!("FUNCTION {}{}", name, params_text(params));
+                self.emit(indent, &head, line);
+                self.block(body, indent + 1);
+                self.keyword(indent, "END FUNCTION");
+            }
+            StmtKind::SelectCase { expr: value, cases } => {
+                self.emit(indent, &format!("SELECT CASE {}", expr(value)), line);
+                for (case, body) in cases {
+                    let head = match case {
+                        Some(case) => format!("CASE {}", expr(case)),
+                        None => "CASE ELSE".to_string(),
+                    };
+                    self.keyword(indent + 1, &head);
+                    self.block(body, indent + 2);
+                }
+                self.keyword(indent, "END SELECT");
+            }
+            StmtKind::Asm { lines, .. } => {
+                // The assembly is printed as written
+                self.emit(indent, "ASM", line);
+                for text in lines {
+                    self.out.push_str(text);
+                    self.out.push('\n');
+                    self.lines.push(0);
+                }
+                let end = line + lines.len() as u32 + 1;
+                self.last_line = end;
+                self.last = Span { line: end, col: 1 };
+                self.emit(indent, "END ASM", end);
+            }
+            kind => unreachable!("{:?} fits on a line", kind),
+        }
+    }
+
+    /// The branches of a block IF, an IF alone in the ELSE branch as ELSEIF
+    fn if_body(&mut self, then_branch: &[Stmt], else_branch: Option<&[Stmt]>, indent: usize) {
+        self.block(then_branch, indent + 1);
+        match else_branch {
+            Some([stmt]) if !self.fits(stmt) && matches!(stmt.kind, StmtKind::If { .. }) => {
+                let StmtKind::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                } = &stmt.kind
+                else {
+                    unreachable!("matched an IF")
+                };
+                self.keyword(indent, &format!("ELSEIF {} THEN", expr(condition)));
+                self.if_body(then_branch, else_branch.as_deref(), indent);
+            }
+            Some(branch) => {
+                self.keyword(indent, "ELSE");
+                self.block(branch, indent + 1);
+                self.keyword(indent, "END IF");
+            }
+            None => self.keyword(indent, "END IF"),
+        }
+    }
+}
+
+/// A name or a number as the syntax tree keeps it: a name in capitals, a
+/// number by value
+#[derive(PartialEq)]
+enum Word {
+    Name(String),
+    Number(u64),
+}
+
+/// The names and numbers in `text`, where each starts and how it's
+/// spelled. A line number after GOTO, GOSUB, THEN, ELSE or RESTORE is
+/// left out, since renumbering changes it, and so is the type after AS,
+/// which is printed in capitals like a keyword.
+fn words(text: &str) -> Vec<(Span, Word, String)> {
+    let lines: Vec<Vec<char>> = text.lines().map(|line| line.chars().collect()).collect();
+    let tokens: Vec<(Token, Span)> = Lexer::new(text).map_while(Result::ok).collect();
+    let mut words = Vec::new();
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        let word = match token {
+            Token::Integer(_) | Token::Float(_) | Token::TypedNumber(..)
+                if i > 0
+                    && matches!(
+                        tokens[i - 1].0,
+                        Token::Goto | Token::Gosub | Token::Then | Token::Else | Token::Restore
+                    ) =>
+            {
+                continue;
+            }
+            Token::Ident(_) if i > 0 && tokens[i - 1].0 == Token::As => continue,
+            Token::Ident(name) => Word::Name(name.to_ascii_uppercase()),
+            Token::Integer(n) => Word::Number((*n as f64).to_bits()),
+            Token::Float(x) | Token::TypedNumber(x, _) => Word::Number(x.to_bits()),
+            _ => continue,
+        };
+        let Some(line) = lines.get(span.line as usize - 1) else {
+            continue;
+        };
+        // It runs to the next token, or the end of the line
+        let start = (span.col as usize - 1).min(line.len());
+        let end = match tokens.get(i + 1) {
+            Some((_, next)) if next.line == span.line => next.col as usize - 1,
+            _ => line.len(),
+        };
+        let spelling: String = line[start..end.clamp(start, line.len())]
+            .iter()
+            .take_while(|c| !c.is_whitespace() && **c != '\'')
+            .collect();
+        words.push((*span, word, spelling));
+    }
+    words
+}
+
+/// The printed text with each name and number spelled as on the source
+/// line it came from (`lines` has that line for each printed one): the
+/// first there, not already used, with the same value. A name that isn't
+/// there (the variable of a bare NEXT) is spelled as it first was.
+fn respell(printed: &str, lines: &[u32], source: &str) -> String {
+    let mut source_words: HashMap<u32, Vec<(Word, String)>> = HashMap::new();
+    let mut first_spelling: HashMap<String, String> = HashMap::new();
+    for (span, word, spelling) in words(source) {
+        if let Word::Name(name) = &word {
+            first_spelling
+                .entry(name.clone())
+                .or_insert_with(|| spelling.clone());
+        }
+        source_words
+            .entry(span.line)
+            .or_default()
+            .push((word, spelling));
+    }
+    let mut out = String::with_capacity(printed.len());
+    for (i, text) in printed.lines().enumerate() {
+        let mut chars: Vec<char> = text.chars().collect();
+        let mut spelled = lines.get(i).and_then(|line| source_words.get_mut(line));
+        // Replaced from the end of the line, so the columns before stay put
+        let mut changes = Vec::new();
+        for (span, word, printed) in words(text) {
+            let on_line = spelled.as_mut().and_then(|spelled| {
+                let k = spelled.iter().position(|(w, _)| *w == word)?;
+                Some(spelled.remove(k).1)
+            });
+            let spelling = match (on_line, &word) {
+                (Some(spelling), _) => spelling,
+                (None, Word::Name(name)) => match first_spelling.get(name) {
+                    Some(spelling) => spelling.clone(),
+                    None => continue,
+                },
+                (None, Word::Number(_)) => continue,
+            };
+            if spelling != printed {
+                changes.push((span.col as usize - 1, printed.chars().count(), spelling));
+            }
+        }
+        for (start, len, spelling) in changes.into_iter().rev() {
+            chars.splice(start..start + len, spelling.chars());
+        }
+        out.extend(chars);
+        out.push('\n');
+    }
+    out
+}
+
+/// A comment as it's printed: REM in capitals like the keywords
+fn comment_text(comment: &Comment) -> String {
+    match comment.text.get(..3) {
+        Some(rem) if rem.eq_ignore_ascii_case("REM") => format!("REM{}", &comment.text[3..]),
+        _ => comment.text.clone(),
+    }
+}
+
+/// A statement on one line; blocks have their statements and closing
+/// keyword joined by colons
+fn inline(stmt: &Stmt) -> String {
+    let body = |head: String, body: &[Stmt], tail: String| {
+        let mut parts = vec![head];
+        parts.extend(body.iter().map(inline));
+        parts.push(tail);
+        parts.join(": ")
+    };
+    match &stmt.kind {
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let mut text = format!("IF {} THEN {}", expr(condition), clause(then_branch));
+            if let Some(branch) = else_branch {
+                text.push_str(" ELSE ");
+                text.push_str(&clause(branch));
+            }
+            text
+        }
+        StmtKind::For {
+            var, body: stmts, ..
+        } => body(for_head(&stmt.kind), stmts, format!("NEXT {}", var)),
+        StmtKind::While {
+            condition,
+            body: stmts,
+        } => body(
+            format!("WHILE {}", expr(condition)),
+            stmts,
+            "WEND".to_string(),
+        ),
+        StmtKind::DoLoop { body: stmts, .. } => {
+            let (head, tail) = do_ends(&stmt.kind);
+            body(head, stmts, tail)
+        }
+        kind => simple(kind),
+    }
+}
+
+/// A branch of a single-line IF: a line number alone, for a GOTO to it
+fn clause(stmts: &[Stmt]) -> String {
+    if let [
+        Stmt {
+            kind: StmtKind::Goto(GotoTarget::Line(n)),
+            ..
+        },
+    ] = stmts
+    {
+        return n.to_string();
+    }
+    let parts: Vec<String> = stmts.iter().map(inline).collect();
+    parts.join(": ")
+}
+
+/// FOR var = start TO end [STEP step]
+fn for_head(kind: &StmtKind) -> String {
+    let StmtKind::For {
+        var,
+        start,
+        end,
+        step,
+        ..
+    } = kind
+    else {
+        unreachable!("for_head of a FOR")
+    };
+    let mut text = format!("FOR {} = {} TO {}", var, expr(start), expr(end));
+    if let Some(step) = step {
+        text.push_str(&format!(" STEP {}", expr(step)));
+    }
+    text
+}
+
+/// The DO and LOOP lines of a DO loop, one of them with the condition
+fn do_ends(kind: &StmtKind) -> (String, String) {
+    let StmtKind::DoLoop {
+        condition,
+        cond_at_start,
+        is_until,
+        ..
+    } = kind
+    else {
+        unreachable!("do_ends of a DO loop")
+    };
+    let Some(condition) = condition else {
+        return ("DO".to_string(), "LOOP".to_string());
+    };
+    let test = format!(
+        "{} {}",
+        if *is_until { "UNTIL" } else { "WHILE" },
+        expr(condition)
+    );
+    if *cond_at_start {
+        (format!("DO {}", test), "LOOP".to_string())
+    } else {
+        ("DO".to_string(), format!("LOOP {}", test))
+    }
+}
+
+/// A statement that has no block in it
+fn simple(kind: &StmtKind) -> String {
+    match kind {
+        StmtKind::Let {
+            name,
+            indices,
+            value,
+        } => match indices {
+            Some(indices) => format!("{}({}) = {}", name, exprs(indices), expr(value)),
+            None => format!("{} = {}", name, expr(value)),
+        },
+        StmtKind::Print { items, .. } => print_items("PRINT".to_string(), items),
+        StmtKind::PrintFile {
+            file_num, items, ..
+        } => {
+            if items.is_empty() {
+                format!("PRINT #{}", file_num)
+            } else {
+                print_items(format!("PRINT #{},", file_num), items)
+            }
+        }
+        StmtKind::Input {
+            prompt,
+            question,
+            vars,
+        } => {
+            let mut text = "INPUT ".to_string();
+            if let Some(prompt) = prompt {
+                text.push_str(&string(prompt));
+                text.push_str(if *question { "; " } else { ", " });
+            }
+            text.push_str(&exprs(vars));
+            text.trim_end().to_string()
+        }
+        StmtKind::PrintUsing {
+            file_num,
+            format,
+            items,
+            newline,
+        } => {
+            let mut text = match file_num {
+                Some(file_num) => format!("PRINT #{}, USING ", file_num),
+                None => "PRINT USING ".to_string(),
+            };
+            text.push_str(&expr(format));
+            text.push_str("; ");
+            let items: Vec<String> = items.iter().map(expr).collect();
+            text.push_str(&items.join("; "));
+            if !newline {
+                text.push(';');
+            }
+            text
+        }
+        StmtKind::Write { file_num, items } => match file_num {
+            Some(file_num) if items.is_empty() => format!("WRITE #{}", file_num),
+            Some(file_num) => format!("WRITE #{}, {}", file_num, exprs(items)),
+            None if items.is_empty() => "WRITE".to_string(),
+            None => format!("WRITE {}", exprs(items)),
+        },
+        StmtKind::InputFile { file_num, vars } => {
+            format!("INPUT #{}, {}", file_num, exprs(vars))
+        }
+        StmtKind::LineInput {
+            file_num,
+            prompt,
+            var,
+        } => match (file_num, prompt) {
+            (Some(file_num), _) => format!("LINE INPUT #{}, {}", file_num, expr(var)),
+            (None, Some(prompt)) => format!("LINE INPUT {}; {}", string(prompt), expr(var)),
+            (None, None) => format!("LINE INPUT {}", expr(var)),
+        },
+        StmtKind::Goto(target) => format!("GOTO {}", target_text(target)),
+        StmtKind::Gosub(target) => format!("GOSUB {}", target_text(target)),
+        StmtKind::Return => "RETURN".to_string(),
+        StmtKind::OnGoto {
+            expr: value,
+            targets,
+        } => {
+            let targets: Vec<String> = targets.iter().map(target_text).collect();
+            format!("ON {} GOTO {}", expr(value), targets.join(", "))
+        }
+        StmtKind::Dim { arrays, shared } => {
+            let decls: Vec<String> = arrays
+                .iter()
+                .map(|decl| {
+                    if decl.dimensions.is_empty() {
+                        decl.name.clone()
+                    } else {
+                        format!("{}({})", decl.name, exprs(&decl.dimensions))
+                    }
+                })
+                .collect();
+            let shared = if *shared { "SHARED " } else { "" };
+            format!("DIM {}{}", shared, decls.join(", "))
+        }
+        StmtKind::Shared(params) => {
+            let names: Vec<String> = params.iter().map(param).collect();
+            format!("SHARED {}", names.join(", "))
+        }
+        StmtKind::Const(consts) => {
+            let consts: Vec<String> = consts
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, expr(value)))
+                .collect();
+            format!("CONST {}", consts.join(", "))
+        }
+        StmtKind::Declare {
+            name,
+            params,
+            is_function,
+            foreign,
+        } => {
+            let what = if *is_function { "FUNCTION" } else { "SUB" };
+            let mut text = format!("DECLARE {} {}", what, name);
+            if let Some(foreign) = foreign {
+                text.push_str(&format!(" LIB {}", string(&foreign.lib)));
+                let default = name
+                    .trim_end_matches(['%', '&', '!', '#', '$'])
+                    .to_lowercase();
+                if foreign.symbol != default {
+                    text.push_str(&format!(" ALIAS {}", string(&foreign.symbol)));
+                }
+                if !params.is_empty() {
+                    text.push(' ');
+                }
+            }
+            text.push_str(&params_text(params));
+            text
+        }
+        StmtKind::Call { name, args } => match args.first() {
+            None => name.clone(),
+            // Without CALL, a parenthesis after the name would take in
+            // only the first argument
+            Some(first) if expr(first).starts_with('(') => {
+                format!("CALL {}({})", name, exprs(args))
+            }
+            Some(_) => format!("{} {}", name, exprs(args)),
+        },
+        StmtKind::Data(values) => {
+            let values: Vec<String> = values.iter().map(literal).collect();
+            format!("DATA {}", values.join(", "))
+        }
+        StmtKind::Read(vars) => format!("READ {}", exprs(vars)),
+        StmtKind::Restore(target) => match target {
+            Some(target) => format!("RESTORE {}", target_text(target)),
+            None => "RESTORE".to_string(),
+        },
+        StmtKind::Cls => "CLS".to_string(),
+        StmtKind::Width { file_num, width } => match file_num {
+            Some(n) => format!("WIDTH #{}, {}", n, expr(width)),
+            None => format!("WIDTH {}", expr(width)),
+        },
+        StmtKind::End => "END".to_string(),
+        StmtKind::Stop => "STOP".to_string(),
+        StmtKind::OptionExplicit => "OPTION EXPLICIT".to_string(),
+        StmtKind::Open {
+            filename,
+            mode,
+            file_num,
+        } => {
+            let mode = match mode {
+                FileMode::Input => " FOR INPUT",
+                FileMode::Output => " FOR OUTPUT",
+                FileMode::Append => " FOR APPEND",
+                FileMode::Both => "",
+            };
+            format!("OPEN {}{} AS #{}", expr(filename), mode, file_num)
+        }
+        StmtKind::Close { file_num } => format!("CLOSE #{}", file_num),
+        StmtKind::Flush { file_num } => format!("FLUSH #{}", file_num),
+        StmtKind::Seek { file_num, position } => {
+            format!("SEEK #{}, {}", file_num, expr(position))
+        }
+        StmtKind::Lock {
+            file_num,
+            first,
+            last,
+            unlock,
+        } => {
+            let mut text = format!("{} #{}", if *unlock { "UNLOCK" } else { "LOCK" }, file_num);
+            if first.is_some() || last.is_some() {
+                text.push(',');
+            }
+            if let Some(first) = first {
+                text.push_str(&format!(" {}", expr(first)));
+            }
+            if let Some(last) = last {
+                text.push_str(&format!(" TO {}", expr(last)));
+            }
+            text
+        }
+        StmtKind::DictSet { dict, key, value } => match value {
+            Some(value) => format!("DICTSET {}, {}, {}", expr(dict), expr(key), expr(value)),
+            None => format!("DICTDEL {}, {}", expr(dict), expr(key)),
+        },
+        StmtKind::Screen { mode } => format!("SCREEN {}", expr(mode)),
+        StmtKind::Pset {
+            x,
+            y,
+            color,
+            preset,
+        } => {
+            let name = if *preset { "PRESET" } else { "PSET" };
+            format!("{} {}{}", name, coord(x, y), optional(&[color]))
+        }
+        StmtKind::GraphicsLine {
+            from,
+            to,
+            color,
+            style,
+        } => {
+            let mut text = "LINE ".to_string();
+            if let Some((x, y)) = from {
+                text.push_str(&coord(x, y));
+            }
+            text.push('-');
+            text.push_str(&coord(&to.0, &to.1));
+            let style = match style {
+                LineStyle::Line => None,
+                LineStyle::Box => Some("B"),
+                LineStyle::FilledBox => Some("BF"),
+            };
+            match (color, style) {
+                (_, Some(style)) => {
+                    let color = color
+                        .as_ref()
+                        .map_or(String::new(), |c| format!(" {}", expr(c)));
+                    text.push_str(&format!(",{}, {}", color, style));
+                }
+                (Some(color), None) => text.push_str(&format!(", {}", expr(color))),
+                (None, None) => {}
+            }
+            text
+        }
+        StmtKind::Circle {
+            x,
+            y,
+            radius,
+            color,
+        } => format!(
+            "CIRCLE {}, {}{}",
+            coord(x, y),
+            expr(radius),
+            optional(&[color])
+        ),
+        StmtKind::Paint {
+            x,
+            y,
+            color,
+            border,
+        } => format!("PAINT {}{}", coord(x, y), optional(&[color, border])),
+        StmtKind::Draw { commands } => format!("DRAW {}", expr(commands)),
+        StmtKind::GetImage {
+            from,
+            to,
+            array,
+            indices,
+        } => format!(
+            "GET {}-{}, {}",
+            coord(&from.0, &from.1),
+            coord(&to.0, &to.1),
+            element(array, indices)
+        ),
+        StmtKind::PutImage {
+            at,
+            array,
+            indices,
+            mode,
+        } => {
+            let mode = match mode {
+                PutMode::Pset => ", PSET",
+                PutMode::Preset => ", PRESET",
+                PutMode::And => ", AND",
+                PutMode::Or => ", OR",
+                PutMode::Xor => "",
+            };
+            format!(
+                "PUT {}, {}{}",
+                coord(&at.0, &at.1),
+                element(array, indices),
+                mode
+            )
+        }
+        StmtKind::Display => "DISPLAY".to_string(),
+        StmtKind::Palette { entry: None } => "PALETTE".to_string(),
+        StmtKind::Palette {
+            entry: Some((attribute, color)),
+        } => format!("PALETTE {}, {}", expr(attribute), expr(color)),
+        StmtKind::DefSeg { segment } => match segment {
+            Some(segment) => format!("DEF SEG = {}", expr(segment)),
+            None => "DEF SEG".to_string(),
+        },
+        StmtKind::Poke { address, value } => format!("POKE {}, {}", expr(address), expr(value)),
+        StmtKind::Bsave {
+            filename,
+            offset,
+            length,
+        } => format!(
+            "BSAVE {}, {}, {}",
+            expr(filename),
+            expr(offset),
+            expr(length)
+        ),
+        StmtKind::Bload { filename, offset } => match offset {
+            Some(offset) => format!("BLOAD {}, {}", expr(filename), expr(offset)),
+            None => format!("BLOAD {}", expr(filename)),
+        },
+        StmtKind::OnKey { key, target } => {
+            format!("ON KEY({}) GOSUB {}", expr(key), target_text(target))
+        }
+        StmtKind::KeyTrap { key, state } => format!("KEY({}) {}", expr(key), trap(*state)),
+        StmtKind::KeyDisplay(on) => format!("KEY {}", if *on { "ON" } else { "OFF" }),
+        StmtKind::OnTimer { interval, target } => {
+            format!("ON TIMER({}) GOSUB {}", expr(interval), target_text(target))
+        }
+        StmtKind::TimerTrap(state) => format!("TIMER {}", trap(*state)),
+        StmtKind::OnBreak { target } => format!("ON BREAK GOSUB {}", target_text(target)),
+        StmtKind::BreakTrap(state) => format!("BREAK {}", trap(*state)),
+        StmtKind::OnMouse { target } => format!("ON MOUSE GOSUB {}", target_text(target)),
+        StmtKind::MouseTrap(state) => format!("MOUSE {}", trap(*state)),
+        kind => unreachable!("{:?} has a block", kind),
+    }
+}
+
+/// PRINT's items after `head`: semicolons and commas right after what
+/// they follow
+fn print_items(mut text: String, items: &[PrintItem]) -> String {
+    for item in items {
+        match item {
+            PrintItem::Expr(e) => {
+                text.push(' ');
+                text.push_str(&expr(e));
+            }
+            PrintItem::Empty => text.push(';'),
+            PrintItem::Tab => text.push(','),
+        }
+    }
+    text
+}
+
+fn trap(state: TrapState) -> &'static str {
+    match state {
+        TrapState::On => "ON",
+        TrapState::Off => "OFF",
+        TrapState::Stop => "STOP",
+    }
+}
+
+/// The parameter list of a procedure, if it has any
+fn params_text(params: &[Param]) -> String {
+    if params.is_empty() {
+        return String::new();
+    }
+    let params: Vec<String> = params.iter().map(param).collect();
+    format!("({})", params.join(", "))
+}
+
+/// A parameter, with AS when its type isn't the one its name gives
+fn param(param: &Param) -> String {
+    let mut text = param.name.clone();
+    if param.is_array {
+        text.push_str("()");
+    }
+    if param.data_type != DataType::from_suffix(&param.name) {
+        let name = match param.data_type {
+            DataType::Integer => "INTEGER",
+            DataType::Long => "LONG",
+            DataType::Single => "SINGLE",
+            DataType::Double => "DOUBLE",
+            DataType::String => "STRING",
+        };
+        text.push_str(" AS ");
+        text.push_str(name);
+    }
+    text
+}
+
+fn target_text(target: &GotoTarget) -> String {
+    match target {
+        GotoTarget::Line(n) => n.to_string(),
+        GotoTarget::Label(name) => name.clone(),
+    }
+}
+
+fn coord(x: &Expr, y: &Expr) -> String {
+    format!("({}, {})", expr(x), expr(y))
+}
+
+/// The optional trailing arguments of a graphics statement, an empty
+/// place left for each one missing before one given
+fn optional(args: &[&Option<Expr>]) -> String {
+    let given = args
+        .iter()
+        .rposition(|arg| arg.is_some())
+        .map_or(0, |i| i + 1);
+    args[..given]
+        .iter()
+        .map(|arg| match arg {
+            Some(e) => format!(", {}", expr(e)),
+            None => ",".to_string(),
+        })
+        .collect()
+}
+
+/// An array for GET and PUT, with the element to start at if given
+fn element(array: &str, indices: &[Expr]) -> String {
+    if indices.is_empty() {
+        array.to_string()
+    } else {
+        format!("{}({})", array, exprs(indices))
+    }
+}
+
+fn exprs(list: &[Expr]) -> String {
+    let list: Vec<String> = list.iter().map(expr).collect();
+    list.join(", ")
+}
+
+/// An expression, with only the parentheses its operators need
+pub fn expr(e: &Expr) -> String {
+    expr_at(e, 0)
+}
+
+/// An expression where an operator must bind at least as tightly as
+/// `min` (as the parser's binary_op_info ranks them) to go without
+/// parentheses
+fn expr_at(e: &Expr, min: u8) -> String {
+    match &e.kind {
+        ExprKind::Literal(lit) => literal(lit),
+        ExprKind::Variable(name) => name.clone(),
+        ExprKind::ArrayAccess {
+            name,
+            indices: args,
+        }
+        | ExprKind::FnCall { name, args } => {
+            format!("{}({})", name, exprs(args))
+        }
+        ExprKind::Unary {
+            op: UnaryOp::Neg,
+            operand,
+        } => match operand.kind {
+            ExprKind::Binary { .. }
+            | ExprKind::Unary {
+                op: UnaryOp::Not, ..
+            } => format!("-({})", expr(operand)),
+            _ => format!("-{}", expr(operand)),
+        },
+        // NOT takes in everything after it that binds at least as tightly
+        // as where it is
+        ExprKind::Unary {
+            op: UnaryOp::Not,
+            operand,
+        } => format!("NOT {}", expr_at(operand, min.max(1))),
+        ExprKind::Binary { op, left, right } => {
+            let prec = precedence(*op);
+            // ^ groups to the right, the others to the left
+            let (left_min, right_min) = if *op == BinaryOp::Pow {
+                (prec + 1, prec)
+            } else {
+                (prec, prec + 1)
+            };
+            let left = match left.kind {
+                ExprKind::Unary {
+                    op: UnaryOp::Not, ..
+                } => format!("({})", expr(left)),
+                _ => expr_at(left, left_min),
+            };
+            let text = format!("{} {} {}", left, operator(*op), expr_at(right, right_min));
+            if prec < min {
+                format!("({})", text)
+            } else {
+                text
+            }
+        }
+    }
+}
+
+/// How tightly a binary operator binds, higher binding tighter
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or | BinaryOp::OrElse => 1,
+        BinaryOp::And | BinaryOp::AndAlso => 2,
+        BinaryOp::Xor => 3,
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge => {
+            4
+        }
+        BinaryOp::Add | BinaryOp::Sub => 5,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::IntDiv | BinaryOp::Mod => 6,
+        BinaryOp::Pow => 7,
+    }
+}
+
+fn operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::IntDiv => "\\",
+        BinaryOp::Mod => "MOD",
+        BinaryOp::Pow => "^",
+        BinaryOp::Eq => "=",
+        BinaryOp::Ne => "<>",
+        BinaryOp::Lt => "<",
+        BinaryOp::Gt => ">",
+        BinaryOp::Le => "<=",
+        BinaryOp::Ge => ">=",
+        BinaryOp::And => "AND",
+        BinaryOp::Or => "OR",
+        BinaryOp::Xor => "XOR",
+        BinaryOp::AndAlso => "ANDALSO",
+        BinaryOp::OrElse => "ORELSE",
+    }
+}
+
+/// A literal as the lexer reads it back: floats keep a decimal point or
+/// exponent, and quotes in strings are doubled
+fn literal(lit: &Literal) -> String {
+    match lit {
+        Literal::Integer(n) => n.to_string(),
+        Literal::Float(f) => format!("{:?}", f),
+        Literal::Typed(f, data_type) => {
+            let suffix = match data_type {
+                DataType::Integer => '%',
+                DataType::Long => '&',
+                DataType::Single => '!',
+                _ => '#',
+            };
+            if f.fract() == 0.0 && f.abs() < 1e15 {
+                format!("{}{}", *f as i64, suffix)
+            } else if *data_type == DataType::Single {
+                format!("{:?}{}", *f as f32, suffix)
+            } else {
+                format!("{:?}{}", f, suffix)
+            }
+        }
+        Literal::String(s) => string(s),
+    }
+}
+
+fn string(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The parsed program as JSON, without the spans the layout changes
+    fn ast(source: &str) -> serde_json::Value {
+        fn strip(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    map.remove("span");
+                    map.values_mut().for_each(strip);
+                }
+                serde_json::Value::Array(list) => list.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let program = Parser::new(Lexer::new(source)).parse().unwrap();
+        let mut value = serde_json::to_value(&program).unwrap();
+        strip(&mut value);
+        value
+    }
+
+    /// Format, checking the result parses to the same program and formats
+    /// to itself
+    fn fmt(source: &str) -> String {
+        let out = format_source(source).unwrap();
+        assert_eq!(ast(&out), ast(source), "{}", out);
+        assert_eq!(format_source(&out).unwrap(), out);
+        out
+    }
+
+    // ===================
+    // Layout Tests
+    // ===================
+
+    #[test]
+    fn test_line_numbers_and_blocks() {
+        let out = fmt("10 for i=1 to 3\n20 print i;: if i=2 then 100\n30 next\n100 end\n");
+        assert_eq!(
+            out,
+            "10  FOR i = 1 TO 3\n\
+             20      PRINT i;: IF i = 2 THEN 100\n\
+             30  NEXT i\n\
+             100 END\n"
+        );
+    }
+
+    #[test]
+    fn test_structured_blocks() {
+        let src = "sub s(a(),n as integer)\nif n then\nprint 1\nelseif n>1 then\nprint 2\n\
+                   else\nprint 3\nend if\nend sub\nselect case x\ncase 1\ndo\nloop until x\n\
+                   case else\nwhile 0: wend\nend select\n";
+        assert_eq!(
+            fmt(src),
+            "SUB s(a(), n AS INTEGER)\n    IF n THEN\n        PRINT 1\n    ELSEIF n > 1 THEN\n\
+             \x20       PRINT 2\n    ELSE\n        PRINT 3\n    END IF\nEND SUB\n\
+             SELECT CASE x\n    CASE 1\n        DO\n        LOOP UNTIL x\n    CASE ELSE\n\
+             \x20       WHILE 0: WEND\nEND SELECT\n"
+        );
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        assert_eq!(fmt("a=1\n\n\n\nb=2\nc=3\n"), "a = 1\n\nb = 2\nc = 3\n");
+    }
+
+    // ===================
+    // Comment Tests
+    // ===================
+
+    #[test]
+    fn test_comments() {
+        let src = "' top\n10 rem line ten\n20 for i=1 to 2 ' loop\n  ' inside\n\
+                   30 next ' after\n";
+        assert_eq!(
+            fmt(src),
+            "   ' top\n10 REM line ten\n20 FOR i = 1 TO 2 ' loop\n       ' inside\n\
+             30 NEXT i ' after\n"
+        );
+        assert_eq!(fmt("x = 1\n' the end\n"), "x = 1\n' the end\n");
+
+        // A REM after a statement keeps its colon, and formats the same again
+        let once = fmt("x = 1: rem note\nif x then print x: REM done\n");
+        assert_eq!(once, "x = 1: REM note\nIF x THEN PRINT x: REM done\n");
+        assert_eq!(fmt(&once), once);
+    }
+
+    // ===================
+    // Expression Tests
+    // ===================
+
+    #[test]
+    fn test_parentheses() {
+        let cases = [
+            ("(a+b)*c-(d-e)", "(a + b) * c - (d - e)"),
+            ("a-(b+c)", "a - (b + c)"),
+            ("2^(3^4)+(2^3)^4", "2 ^ 3 ^ 4 + (2 ^ 3) ^ 4"),
+            ("-(a+b)+-c", "-(a + b) + -c"),
+            ("(not a) and b", "(NOT a) AND b"),
+            ("a = not (b or c)", "a = NOT (b OR c)"),
+            ("(a mod b) \\ c", "a MOD b \\ c"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                fmt(&format!("x = {}\n", input)),
+                format!("x = {}\n", expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(
+            fmt("x = 1.5 + 2.0 + 1e-7 + 3% + 70000& + 0.1! + 2.5#: y$ = \"say \"\"hi\"\"\"\n"),
+            "x = 1.5 + 2.0 + 1e-7 + 3% + 70000& + 0.1! + 2.5#: y$ = \"say \"\"hi\"\"\"\n"
+        );
+        assert_eq!(fmt("data 1, -2.5, \"a,b\"\n"), "DATA 1, -2.5, \"a,b\"\n");
+    }
+
+    #[test]
+    fn test_source_spelling() {
+        assert_eq!(
+            fmt("x=&HFF00+&b1010+&O17+1D3+1E-7\n"),
+            "x = &HFF00 + &b1010 + &O17 + 1D3 + 1E-7\n"
+        );
+        assert_eq!(
+            fmt("sub Greet(Name$)\nprint \"hi \";NAME$\nend sub\nfor K=1 to 2:next\n"),
+            "SUB Greet(Name$)\n    PRINT \"hi \"; NAME$\nEND SUB\nFOR K = 1 TO 2: NEXT K\n"
+        );
+        assert_eq!(
+            fmt("10 data &H10, 2\n20 goto 10\n"),
+            "10 DATA &H10, 2\n20 GOTO 10\n"
+        );
+    }
+
+    // ===================
+    // Round Trip Tests
+    // ===================
+
+    #[test]
+    fn test_every_statement() {
+        let src = r#"
+option explicit
+declare sub s(x%)
+declare function getpid& lib "c"
+declare function strlen& lib "c" alias "strlen"(byval s$)
+dim shared a(10), b(2, 3), z
+const n = 2, t$ = "a"
+let x = 1: a(1) = 2
+print "a"; x, : print #1, "b"; : print
+input "name"; n$: input "age", g: input v
+input #1, v, a(2): line input "l"; l$: line input l$
+goto 10: gosub 10: return
+10 on x goto 10, 20
+20 restore: restore 20: read x, a(1): data 1
+cls: width 40: width #1, 80: end: stop
+open "f" for output as #1: open "f" for append as #2: open "f" for input as #3
+open "tcp:h:1" as #4
+close #1
+screen 1: pset (1, 2): preset (1, 2), 3
+line (0, 0)-(5, 5), 2: line -(7, 7), 1, bf
+circle (5, 5), 3, 2: paint (5, 5), 1, 2: paint (1, 1)
+draw "U4": get (0, 0)-(3, 3), a: put (1, 1), a(2), pset: put (1, 1), a
+display: def seg = 1: def seg: poke 1, 2
+bsave "f", 0, 10: bload "f": bload "f", 5
+on key(1) gosub 10: key(1) on: key(2) stop: key off: key on
+on timer(1) gosub 20: timer on: timer off
+on break gosub 20: break on: break stop
+s 1, 2: call s(3): s
+if x then 10 else x = 2: y = 3
+do: x = x + 1: loop while x < 5
+do until x: loop
+asm
+    mov rax, {X}
+end asm
+sub s(p(), q$)
+    shared z
+end sub
+"#;
+        fmt(src);
+    }
+}
@@ -0,0 +1,331 @@
+//! Register allocation for expression temporaries - an IR pass run at -O1
+//! and above
+//!
+//! A binary operation saves its left operand while the right one is
+//! evaluated. Codegen saves it on the stack (`Push` / `PopRight`); this
+//! pass keeps it in a scratch register instead (`SaveTemp` /
+//! `RestoreRight`) whenever the right operand is plain IR arithmetic: no
+//! calls, which would clobber the scratch registers, and no assembly the
+//! pass can't see into. Nested operations take the next register, so
+//! `A * B + C * D` needs one, and a save nested deeper than there are
+//! registers (see emit.rs) stays on the stack.
+//!
+//! First, Sethi-Ullman style, a commutative operation whose left operand
+//! is a constant or variable and whose right operand is an expression is
+//! turned around: the expression is evaluated first and the leaf loaded
+//! straight into the secondary register, so `1 + A * B` is computed as
+//! `A * B + 1` and needs no temporary at all.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::emit::{FLOAT_TEMPS, INT_TEMPS};
+use crate::ir::Inst;
+use crate::parser::{BinaryOp, DataType};
+
+/// Reorder commutative operations, then move saves into registers
+pub fn allocate(code: &mut Vec<Inst>) {
+    while swap_leaf_operand(code) {}
+    assign_temps(code);
+}
+
+/// Each `Push` and its matching `PopRight`, in order of the push
+fn pairs(code: &[Inst]) -> Vec<(usize, usize)> {
+    let mut open = Vec::new();
+    let mut pairs = Vec::new();
+    for (i, inst) in code.iter().enumerate() {
+        match inst {
+            Inst::Push(_) => open.push(i),
+            Inst::PopRight(_) => pairs.extend(open.pop().map(|push| (push, i))),
+            _ => {}
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Whether a scratch register survives an instruction: true for
+/// everything but calls and assembly text (`^` calls the C library)
+fn keeps_temps(inst: &Inst) -> bool {
+    match inst {
+        Inst::Const(_)
+        | Inst::Load { .. }
+        | Inst::Convert { .. }
+        | Inst::Neg(_)
+        | Inst::Not(_)
+        | Inst::CheckOverflow(_)
+        | Inst::Push(_)
+        | Inst::PopRight(_)
+        | Inst::SaveTemp { .. }
+        | Inst::RestoreRight { .. }
+        | Inst::ConstRight(_)
+        | Inst::LoadRight { .. } => true,
+        Inst::Binary { op, .. } => *op != BinaryOp::Pow,
+        _ => false,
+    }
+}
+
+fn commutes(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Add
+            | BinaryOp::Mul
+            | BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::And
+            | BinaryOp::Or
+            | BinaryOp::Xor
+    )
+}
+
+/// The right-operand form of a constant or variable of type `ty`
+fn as_right_operand(leaf: &Inst, ty: DataType) -> Option<Inst> {
+    match leaf {
+        Inst::Const(c) if c.holds(ty) => Some(Inst::ConstRight(*c)),
+        Inst::Load { ty: load, addr } if *load == ty => Some(Inst::LoadRight {
+            ty,
+            addr: addr.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// Turn around each commutative operation with a leaf on the left and a
+/// side-effect-free expression on the right, except those nested inside
+/// another one being turned around this time. Returns whether any were.
+fn swap_leaf_operand(code: &mut Vec<Inst>) -> bool {
+    // (leaf, pop, the leaf as a right operand), in code order
+    let mut swaps: Vec<(usize, usize, Inst)> = Vec::new();
+    for (push, pop) in pairs(code) {
+        let (Some(leaf), Inst::Push(ty), Some(Inst::Binary { op, .. })) =
+            (push.checked_sub(1), &code[push], code.get(pop + 1))
+        else {
+            continue;
+        };
+        // The right operand doesn't store, so the leaf reads the same
+        // value after it as before
+        if !commutes(*op)
+            || !code[push + 1..pop].iter().all(keeps_temps)
+            || swaps.last().is_some_and(|&(_, end, _)| leaf <= end)
+        {
+            continue;
+        }
+        if let Some(leaf_right) = as_right_operand(&code[leaf], *ty) {
+            swaps.push((leaf, pop, leaf_right));
+        }
+    }
+    if swaps.is_empty() {
+        return false;
+    }
+    let mut old = std::mem::take(code).into_iter().enumerate();
+    for (leaf, pop, leaf_right) in swaps {
+        code.extend(
+            old.by_ref()
+                .take_while(|&(i, _)| i < leaf)
+                .map(|(_, inst)| inst),
+        );
+        // Skip the Push, keep the right operand, drop the PopRight
+        code.extend(
+            old.by_ref()
+                .take_while(|&(i, _)| i < pop)
+                .skip(1)
+                .map(|(_, inst)| inst),
+        );
+        code.push(leaf_right);
+    }
+    code.extend(old.map(|(_, inst)| inst));
+    true
+}
+
+/// Replace each `Push`/`PopRight` pair whose right operand keeps the
+/// scratch registers with `SaveTemp`/`RestoreRight`, numbering temps by
+/// nesting depth
+fn assign_temps(code: &mut [Inst]) {
+    // Register-allocated pairs enclosing the current one: (pop, is_float)
+    let mut active: Vec<(usize, bool)> = Vec::new();
+    for (push, pop) in pairs(code) {
+        active.retain(|&(end, _)| end > push);
+        let Inst::Push(ty) = code[push] else {
+            unreachable!("pairs start with a Push");
+        };
+        if !code[push + 1..pop].iter().all(keeps_temps) {
+            continue;
+        }
+        let float = !ty.is_integer();
+        let temp = active.iter().filter(|&&(_, f)| f == float).count();
+        let available = if float {
+            FLOAT_TEMPS.len()
+        } else {
+            INT_TEMPS.len()
+        };
+        if temp < available {
+            code[push] = Inst::SaveTemp { ty, temp };
+            code[pop] = Inst::RestoreRight { ty, temp };
+            active.push((pop, float));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Const;
+
+    fn load(addr: &str) -> Inst {
+        Inst::Load {
+            ty: DataType::Double,
+            addr: addr.into(),
+        }
+    }
+
+    fn load_right(addr: &str) -> Inst {
+        Inst::LoadRight {
+            ty: DataType::Double,
+            addr: addr.into(),
+        }
+    }
+
+    fn binary(op: BinaryOp) -> Inst {
+        Inst::Binary {
+            op,
+            ty: DataType::Double,
+        }
+    }
+
+    fn allocated(mut code: Vec<Inst>) -> Vec<Inst> {
+        allocate(&mut code);
+        code
+    }
+
+    // ===================
+    // Allocation Tests
+    // ===================
+
+    #[test]
+    fn test_temps_by_depth() {
+        // A * B - C * D: one temp for the left product
+        let code = vec![
+            load("a"),
+            load_right("b"),
+            binary(BinaryOp::Mul),
+            Inst::Push(DataType::Double),
+            load("c"),
+            load_right("d"),
+            binary(BinaryOp::Mul),
+            Inst::PopRight(DataType::Double),
+            binary(BinaryOp::Sub),
+        ];
+        let code = allocated(code);
+        assert_eq!(
+            code[3],
+            Inst::SaveTemp {
+                ty: DataType::Double,
+                temp: 0
+            }
+        );
+        assert_eq!(
+            code[7],
+            Inst::RestoreRight {
+                ty: DataType::Double,
+                temp: 0
+            }
+        );
+
+        // Nested saves take the next temp; a call keeps the stack
+        let code = vec![
+            load("a"),
+            Inst::Push(DataType::Double),
+            load("b"),
+            Inst::Push(DataType::Double),
+            load("c"),
+            Inst::Neg(DataType::Double),
+            Inst::PopRight(DataType::Double),
+            binary(BinaryOp::Sub),
+            Inst::PopRight(DataType::Double),
+            binary(BinaryOp::Sub),
+            Inst::Push(DataType::Double),
+            Inst::CallLibc("sin".to_string()),
+            Inst::PopRight(DataType::Double),
+            binary(BinaryOp::Div),
+        ];
+        let code = allocated(code);
+        let temps: Vec<_> = code
+            .iter()
+            .filter_map(|inst| match inst {
+                Inst::SaveTemp { temp, .. } => Some(*temp),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(temps, [0, 1]);
+        assert!(code.contains(&Inst::Push(DataType::Double)));
+    }
+
+    #[test]
+    fn test_swap_leaf_operand() {
+        // 1 + A * B becomes A * B + 1
+        let code = vec![
+            Inst::Const(Const::Double(1.0)),
+            Inst::Push(DataType::Double),
+            load("a"),
+            load_right("b"),
+            binary(BinaryOp::Mul),
+            Inst::PopRight(DataType::Double),
+            binary(BinaryOp::Add),
+        ];
+        assert_eq!(
+            allocated(code),
+            vec![
+                load("a"),
+                load_right("b"),
+                binary(BinaryOp::Mul),
+                Inst::ConstRight(Const::Double(1.0)),
+                binary(BinaryOp::Add),
+            ]
+        );
+
+        // Nested swaps: 1 + (2 + A * B) becomes A * B + 2 + 1
+        let code = vec![
+            Inst::Const(Const::Double(1.0)),
+            Inst::Push(DataType::Double),
+            Inst::Const(Const::Double(2.0)),
+            Inst::Push(DataType::Double),
+            load("a"),
+            load_right("b"),
+            binary(BinaryOp::Mul),
+            Inst::PopRight(DataType::Double),
+            binary(BinaryOp::Add),
+            Inst::PopRight(DataType::Double),
+            binary(BinaryOp::Add),
+        ];
+        assert_eq!(
+            allocated(code),
+            vec![
+                load("a"),
+                load_right("b"),
+                binary(BinaryOp::Mul),
+                Inst::ConstRight(Const::Double(2.0)),
+                binary(BinaryOp::Add),
+                Inst::ConstRight(Const::Double(1.0)),
+                binary(BinaryOp::Add),
+            ]
+        );
+
+        // Subtraction doesn't commute; it gets a temp instead
+        let code = vec![
+            load("x"),
+            Inst::Push(DataType::Double),
+            load("a"),
+            load_right("b"),
+            binary(BinaryOp::Mul),
+            Inst::PopRight(DataType::Double),
+            binary(BinaryOp::Sub),
+        ];
+        assert_eq!(
+            allocated(code)[1],
+            Inst::SaveTemp {
+                ty: DataType::Double,
+                temp: 0
+            }
+        );
+    }
+}
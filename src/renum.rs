@@ -0,0 +1,246 @@
+//! RENUM - gives a program's numbered lines new numbers
+//!
+//! `xbasic64 renum` numbers the lines START, START+STEP and so on in the
+//! order they're written, then changes every reference to them to match:
+//! GOTO, GOSUB, ON ... GOTO, IF ... THEN n, RESTORE, ON KEY, ON TIMER and
+//! ON BREAK. The printer writes the program back out.
+//!
+//! A jump can't leave the SUB or FUNCTION it's in, so each procedure's line
+//! numbers are its own, as they are to the checker: GOTO 10 in a SUB means
+//! the SUB's line 10 even if the main program has one too. RESTORE can
+//! name a line anywhere, its own procedure's first.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Lexer, Span};
+use crate::parser::{GotoTarget, Parser, Program, Stmt, StmtKind};
+use crate::printer;
+use std::collections::HashMap;
+
+/// Old line numbers and their new ones, for one scope: the main program
+/// (0) or the Nth procedure
+type Numbers = HashMap<(usize, u32), u32>;
+
+/// Parse `source`, renumber it and print it formatted
+pub fn renumber_source(source: &str, start: u32, step: u32) -> Result<String, Diagnostic> {
+    let mut program = Parser::new(Lexer::new(source)).parse()?;
+    renumber(&mut program, start, step)?;
+    Ok(printer::format(&program, source))
+}
+
+/// Number the program's lines from `start` by `step` and rewrite the
+/// references to them
+pub fn renumber(program: &mut Program, start: u32, step: u32) -> Result<(), Diagnostic> {
+    let mut numbers = Numbers::new();
+    let mut next = Some(start);
+    let mut procs = 0;
+    assign(
+        &program.statements,
+        0,
+        &mut procs,
+        &mut numbers,
+        &mut next,
+        step,
+    )?;
+    let mut procs = 0;
+    rewrite(&mut program.statements, 0, &mut procs, &numbers)
+}
+
+/// Give each line number in `stmts` (in scope `scope`) the next number
+fn assign(
+    stmts: &[Stmt],
+    scope: usize,
+    procs: &mut usize,
+    numbers: &mut Numbers,
+    next: &mut Option<u32>,
+    step: u32,
+) -> Result<(), Diagnostic> {
+    for stmt in stmts {
+        let inner = match &stmt.kind {
+            StmtKind::Label(n) => {
+                let Some(new) = *next else {
+                    let message = format!("Line {} would be numbered past {}", n, u32::MAX);
+                    return Err(Diagnostic::at(stmt.span, message));
+                };
+                if numbers.insert((scope, *n), new).is_some() {
+                    let message = format!("Duplicate line number {}", n);
+                    return Err(Diagnostic::at(stmt.span, message));
+                }
+                *next = new.checked_add(step);
+                continue;
+            }
+            StmtKind::Sub { .. } | StmtKind::Function { .. } => {
+                *procs += 1;
+                *procs
+            }
+            _ => scope,
+        };
+        for body in stmt.kind.bodies() {
+            assign(body, inner, procs, numbers, next, step)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replace the line numbers in `stmts` and in the jumps to them
+fn rewrite(
+    stmts: &mut [Stmt],
+    scope: usize,
+    procs: &mut usize,
+    numbers: &Numbers,
+) -> Result<(), Diagnostic> {
+    for stmt in stmts {
+        let span = stmt.span;
+        let jump =
+            |verb: &str, target: &mut GotoTarget| retarget(verb, target, scope, numbers, span);
+        let inner = match &mut stmt.kind {
+            StmtKind::Label(n) => {
+                *n = numbers[&(scope, *n)];
+                scope
+            }
+            StmtKind::Goto(target) => {
+                jump("GOTO", target)?;
+                scope
+            }
+            StmtKind::Gosub(target) => {
+                jump("GOSUB", target)?;
+                scope
+            }
+            StmtKind::OnGoto { targets, .. } => {
+                for target in targets {
+                    jump("ON ... GOTO", target)?;
+                }
+                scope
+            }
+            StmtKind::OnKey { target, .. } => {
+                jump("ON KEY", target)?;
+                scope
+            }
+            StmtKind::OnTimer { target, .. } => {
+                jump("ON TIMER", target)?;
+                scope
+            }
+            StmtKind::OnBreak { target } => {
+                jump("ON BREAK", target)?;
+                scope
+            }
+            StmtKind::OnMouse { target } => {
+                jump("ON MOUSE", target)?;
+                scope
+            }
+            StmtKind::Restore(Some(GotoTarget::Line(n))) => {
+                // The procedure's own line, else the main program's, else
+                // any procedure's
+                let new = numbers
+                    .get(&(scope, *n))
+                    .or_else(|| numbers.get(&(0, *n)))
+                    .or_else(|| {
+                        let any = numbers.iter().filter(|((_, old), _)| old == n);
+                        any.min_by_key(|((scope, _), _)| *scope).map(|(_, new)| new)
+                    });
+                *n = *new.ok_or_else(|| undefined("RESTORE", *n, span))?;
+                scope
+            }
+            StmtKind::Sub { .. } | StmtKind::Function { .. } => {
+                *procs += 1;
+                *procs
+            }
+            _ => scope,
+        };
+        for body in stmt.kind.bodies_mut() {
+            rewrite(body, inner, procs, numbers)?;
+        }
+    }
+    Ok(())
+}
+
+/// Point a jump in scope `scope` at its line's new number
+fn retarget(
+    verb: &str,
+    target: &mut GotoTarget,
+    scope: usize,
+    numbers: &Numbers,
+    span: Span,
+) -> Result<(), Diagnostic> {
+    if let GotoTarget::Line(n) = target {
+        *n = *numbers
+            .get(&(scope, *n))
+            .ok_or_else(|| undefined(verb, *n, span))?;
+    }
+    Ok(())
+}
+
+fn undefined(verb: &str, n: u32, span: Span) -> Diagnostic {
+    Diagnostic::at(span, format!("{} {}: undefined line number", verb, n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renum(source: &str, start: u32, step: u32) -> Result<String, String> {
+        renumber_source(source, start, step).map_err(|e| e.message)
+    }
+
+    // ===================
+    // Renumbering
+    // ===================
+
+    #[test]
+    fn test_renumber_jumps() {
+        let source = "5 RESTORE 90\n7 GOSUB 90\n8 IF X THEN 7 ELSE 5\n\
+                      9 ON X GOTO 5, 7: ON TIMER(1) GOSUB 90: ON KEY(1) GOSUB 7\n\
+                      90 DATA 1: RETURN\n";
+        assert_eq!(
+            renum(source, 100, 5).unwrap(),
+            "100 RESTORE 120\n105 GOSUB 120\n110 IF X THEN 105 ELSE 100\n\
+             115 ON X GOTO 100, 105: ON TIMER(1) GOSUB 120: ON KEY(1) GOSUB 105\n\
+             120 DATA 1: RETURN\n"
+        );
+    }
+
+    #[test]
+    fn test_renumber_nested_and_unnumbered() {
+        let source =
+            "' count\nFOR I = 1 TO 2\n3 PRINT I\nIF I = 1 THEN\n1 GOTO 3\nEND IF\nNEXT I\n";
+        assert_eq!(
+            renum(source, 10, 10).unwrap(),
+            "   ' count\n   FOR I = 1 TO 2\n10     PRINT I\n       IF I = 1 THEN\n\
+             20         GOTO 10\n       END IF\n   NEXT I\n"
+        );
+    }
+
+    #[test]
+    fn test_renumber_procedures() {
+        // Each SUB has its own line 10; RESTORE may name the main program's
+        let source = "10 GOTO 20\n20 END\nSUB S\n10 RESTORE 20\n30 GOTO 10\nEND SUB\n";
+        assert_eq!(
+            renum(source, 100, 100).unwrap(),
+            "100 GOTO 200\n200 END\n    SUB S\n300     RESTORE 200\n400     GOTO 300\n    END SUB\n"
+        );
+    }
+
+    #[test]
+    fn test_renumber_errors() {
+        assert_eq!(
+            renum("10 GOTO 30\n", 10, 10).unwrap_err(),
+            "GOTO 30: undefined line number"
+        );
+        assert_eq!(
+            renum("10 END\n10 END\n", 10, 10).unwrap_err(),
+            "Duplicate line number 10"
+        );
+        assert_eq!(
+            renum("1 END\n2 END\n", u32::MAX, 1).unwrap_err(),
+            format!("Line 2 would be numbered past {}", u32::MAX)
+        );
+        // A procedure can't jump to the main program's lines
+        let source = "10 END\nSUB S\nGOTO 10\nEND SUB\n";
+        assert_eq!(
+            renum(source, 10, 10).unwrap_err(),
+            "GOTO 10: undefined line number"
+        );
+    }
+}
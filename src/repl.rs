@@ -0,0 +1,333 @@
+//! Interactive mode - a classic BASIC prompt backed by the interpreter
+//!
+//! Started when xbasic64 is given no source file. Each line typed is one of:
+//!
+//! - A program line: `10 PRINT "HI"` adds or replaces line 10, and a bare
+//!   `10` deletes it. Lines are kept in line-number order.
+//! - A command: RUN, LIST [n | n-m | n- | -m], NEW, SAVE ["file"],
+//!   LOAD "file" or SYSTEM (quit; end of input quits too).
+//! - Anything else is run straight away as an immediate statement, e.g.
+//!   `PRINT 2 + 2`. Immediate statements see the variables the last RUN
+//!   left behind, and keep theirs for the next one.
+//!
+//! Errors are reported on stderr and the session carries on.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::interp::{self, Globals};
+use crate::parser::Program;
+use crate::{lexer, modules, parser, semantic};
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+/// Run an interactive session on stdin and stdout until SYSTEM or end of
+/// input
+pub fn run(overflow_check: bool, color: bool) {
+    interp::with_big_stack(|| {
+        let mut session = Session::new(overflow_check, color);
+        println!("xbasic64 {}", env!("CARGO_PKG_VERSION"));
+        println!("Ok");
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match io::stdin().lock().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            let text = line.trim_end_matches(['\n', '\r']);
+            if text.trim().is_empty() {
+                continue;
+            }
+            if !session.execute(text) {
+                break;
+            }
+            if session.globals.end_line() {
+                println!();
+            }
+            println!("Ok");
+            let _ = io::stdout().flush();
+        }
+    })
+}
+
+/// The program being edited and the state immediate statements share
+struct Session {
+    lines: Vec<String>,
+    name: Option<String>, // file last loaded or saved
+    globals: Globals,
+    overflow_check: bool,
+    color: bool,
+}
+
+impl Session {
+    fn new(overflow_check: bool, color: bool) -> Self {
+        Session {
+            lines: Vec::new(),
+            name: None,
+            globals: Globals::default(),
+            overflow_check,
+            color,
+        }
+    }
+
+    /// Handle one line of input; false when the session should end
+    fn execute(&mut self, text: &str) -> bool {
+        if let Some((number, rest)) = line_number(text) {
+            self.edit(number, rest.trim().is_empty(), text.trim());
+            return true;
+        }
+        let (word, arg) = match text.trim().split_once(char::is_whitespace) {
+            Some((word, arg)) => (word, arg.trim()),
+            None => (text.trim(), ""),
+        };
+        match word.to_ascii_uppercase().as_str() {
+            "SYSTEM" if arg.is_empty() => return false,
+            "RUN" if arg.is_empty() => self.run_program(),
+            "NEW" if arg.is_empty() => {
+                self.lines.clear();
+                self.name = None;
+                self.globals = Globals::default();
+            }
+            "LIST" => match parse_range(arg) {
+                Some(range) => {
+                    for line in self.list(range) {
+                        println!("{}", line);
+                    }
+                }
+                None => eprintln!("Syntax error"),
+            },
+            "SAVE" => self.save(arg),
+            "LOAD" => self.load(arg),
+            _ => self.immediate(text),
+        }
+        true
+    }
+
+    /// Add, replace or (when `delete`) remove a numbered program line
+    fn edit(&mut self, number: u32, delete: bool, text: &str) {
+        let found = self
+            .lines
+            .iter()
+            .position(|line| line_number(line).map(|(n, _)| n) == Some(number));
+        match found {
+            Some(i) if delete => {
+                self.lines.remove(i);
+            }
+            Some(i) => self.lines[i] = text.to_string(),
+            None if delete => {}
+            None => {
+                let at = self
+                    .lines
+                    .iter()
+                    .position(|line| line_number(line).is_some_and(|(n, _)| n > number))
+                    .unwrap_or(self.lines.len());
+                self.lines.insert(at, text.to_string());
+            }
+        }
+    }
+
+    /// The lines LIST shows: all of them, or the numbered ones in `range`
+    fn list(&self, range: Option<(u32, u32)>) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| match (range, line_number(line)) {
+                (None, _) => true,
+                (Some((lo, hi)), Some((n, _))) => lo <= n && n <= hi,
+                (Some(_), None) => false,
+            })
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Run the stored program from the start with fresh variables
+    fn run_program(&mut self) {
+        let source = self.source();
+        let name = self.name.clone().unwrap_or_else(|| "program".to_string());
+        let Some(program) = self.compile(&name, &source) else {
+            return;
+        };
+        self.globals = Globals::default();
+        self.interpret(&program);
+    }
+
+    /// Run a statement typed without a line number
+    fn immediate(&mut self, text: &str) {
+        let source = format!("{}\n", text);
+        if let Some(program) = self.compile("immediate", &source) {
+            self.interpret(&program);
+        }
+    }
+
+    fn interpret(&mut self, program: &Program) {
+        let result = interp::run_with(
+            program,
+            self.overflow_check,
+            lexer::Dialect::Modern,
+            &mut self.globals,
+        );
+        if let Err(e) = result {
+            if self.globals.end_line() {
+                println!();
+            }
+            eprintln!("{}", e.message);
+        }
+    }
+
+    /// Parse and check source for the interpreter, reporting any error
+    fn compile(&self, name: &str, source: &str) -> Option<Program> {
+        let mut parser = parser::Parser::new(lexer::Lexer::new(source));
+        let result = parser.parse().map_err(|e| vec![e]).and_then(|program| {
+            modules::check(&[(name, &program)], true)
+                .map_err(|(_, e)| vec![e])
+                .and_then(|()| {
+                    semantic::Checker::new(false, lexer::Dialect::Modern).check(&program)
+                })
+                .and_then(|()| interp::check(&program).map_err(|e| vec![e]))
+                .map(|()| program)
+        });
+        result
+            .map_err(|errors| {
+                for e in errors {
+                    eprintln!("{}", e.render(name, source, self.color));
+                }
+            })
+            .ok()
+    }
+
+    fn source(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+
+    /// SAVE "file", or SAVE alone to the file last loaded or saved
+    fn save(&mut self, arg: &str) {
+        let name = match (unquote(arg), &self.name) {
+            (Some(name), _) => name.to_string(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => {
+                eprintln!("SAVE needs a file name");
+                return;
+            }
+        };
+        match fs::write(&name, self.source()) {
+            Ok(()) => self.name = Some(name),
+            Err(e) => eprintln!("Error writing {}: {}", name, e),
+        }
+    }
+
+    /// LOAD "file", replacing the program and clearing the variables
+    fn load(&mut self, arg: &str) {
+        let Some(name) = unquote(arg) else {
+            eprintln!("LOAD needs a file name");
+            return;
+        };
+        match fs::read_to_string(name) {
+            Ok(text) => {
+                self.lines = text
+                    .lines()
+                    .map(|line| line.trim_end().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect();
+                self.name = Some(name.to_string());
+                self.globals = Globals::default();
+            }
+            Err(e) => eprintln!("Error reading {}: {}", name, e),
+        }
+    }
+}
+
+/// The line number a program line starts with, and the text after it
+fn line_number(text: &str) -> Option<(u32, &str)> {
+    let text = text.trim_start();
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let number = text[..digits].parse().ok()?;
+    Some((number, &text[digits..]))
+}
+
+/// A LIST argument: none (everything), `n`, `n-m`, `n-` or `-m`
+fn parse_range(arg: &str) -> Option<Option<(u32, u32)>> {
+    if arg.is_empty() {
+        return Some(None);
+    }
+    let bound = |s: &str, default: u32| match s.trim() {
+        "" => Some(default),
+        s => s.parse().ok(),
+    };
+    let range = match arg.split_once('-') {
+        Some((lo, hi)) if !(lo.trim().is_empty() && hi.trim().is_empty()) => {
+            (bound(lo, 0)?, bound(hi, u32::MAX)?)
+        }
+        Some(_) => return None,
+        None => {
+            let n = arg.trim().parse().ok()?;
+            (n, n)
+        }
+    };
+    Some(Some(range))
+}
+
+/// A file name, with or without the quotes around it
+fn unquote(arg: &str) -> Option<&str> {
+    let arg = arg.trim();
+    let name = arg
+        .strip_prefix('"')
+        .map(|s| s.strip_suffix('"').unwrap_or(s))
+        .unwrap_or(arg);
+    (!name.is_empty()).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(lines: &[&str]) -> Session {
+        let mut session = Session::new(false, false);
+        for line in lines {
+            assert!(session.execute(line));
+        }
+        session
+    }
+
+    // ===================
+    // Program editing
+    // ===================
+
+    #[test]
+    fn test_edit_lines() {
+        let edited = session(&["20 PRINT 2", "10 PRINT 1", "30 PRINT 3", "20 PRINT \"two\""]);
+        assert_eq!(
+            edited.lines,
+            ["10 PRINT 1", "20 PRINT \"two\"", "30 PRINT 3"]
+        );
+
+        // A bare line number deletes the line, if there is one
+        let deleted = session(&["10 A = 1", "20 B = 2", "30 C = 3", "20", "40"]);
+        assert_eq!(deleted.lines, ["10 A = 1", "30 C = 3"]);
+    }
+
+    #[test]
+    fn test_list_ranges() {
+        let listed = session(&["10 A = 1", "20 B = 2", "30 C = 3"]);
+        let list = |arg: &str| listed.list(parse_range(arg).unwrap());
+        assert_eq!(list(""), ["10 A = 1", "20 B = 2", "30 C = 3"]);
+        assert_eq!(list("20"), ["20 B = 2"]);
+        assert_eq!(list("15-30"), ["20 B = 2", "30 C = 3"]);
+        assert_eq!(list("20-"), ["20 B = 2", "30 C = 3"]);
+        assert_eq!(list("-10"), ["10 A = 1"]);
+        assert_eq!(parse_range("-"), None);
+        assert_eq!(parse_range("x"), None);
+    }
+
+    #[test]
+    fn test_line_number() {
+        assert_eq!(line_number("10 PRINT"), Some((10, " PRINT")));
+        assert_eq!(line_number("  20"), Some((20, "")));
+        assert_eq!(line_number("PRINT 10"), None);
+        assert_eq!(unquote("\"a.bas\""), Some("a.bas"));
+        assert_eq!(unquote("a.bas"), Some("a.bas"));
+        assert_eq!(unquote(""), None);
+    }
+}
@@ -5,77 +5,562 @@
 //! Runtime is split into separate assembly files for maintainability:
 //! - data_defs.s: Data section definitions (format strings, buffers)
 //! - print.s: Print functions
+//! - using.s: PRINT USING formatting
 //! - input.s: Input functions
 //! - string.s: String manipulation functions
 //! - math.s: Math and utility functions
 //! - data.s: DATA/READ support functions
 //! - file.s: File I/O functions (OPEN, CLOSE, PRINT#, INPUT#)
+//! - graphics.s: Pixel graphics (SCREEN, PSET, LINE, CIRCLE, PAINT, DRAW, GET, PUT)
+//! - memory.s: Emulated memory (PEEK, POKE, DEF SEG, VARSEG, BSAVE, BLOAD)
+//! - event.s: Event trapping (ON KEY, KEY(n) ON/OFF/STOP, ON TIMER)
+//! - dict.s: Dictionaries (DICTNEW, DICTSET, DICTGET$)
 //!
 //! Platform-specific runtimes:
 //! - sysv/: System V AMD64 ABI (Linux, macOS, BSD)
-//! - win64/: Windows x64 ABI
+//! - win64-native/: Windows x64 ABI (Win32 API)
+//!
+//! Only the parts of the runtime a program uses are emitted: the runtime is
+//! split into chunks at each global label (a function or a data item), and
+//! a chunk is kept if the program or a kept chunk refers to one of its
+//! labels, or if a kept chunk falls through into it. Executables built
+//! with an external assembler link the runtime as a static library instead,
+//! with the same chunks as its members, assembled once and then cached.
 
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
 // System V ABI runtime (Linux, macOS, BSD)
-#[cfg(not(windows))]
-mod runtime_files {
+mod sysv {
     pub const DATA_DEFS: &str = include_str!("runtime/sysv/data_defs.s");
-    pub const PRINT_FUNCS: &str = include_str!("runtime/sysv/print.s");
-    pub const INPUT_FUNCS: &str = include_str!("runtime/sysv/input.s");
-    pub const STRING_FUNCS: &str = include_str!("runtime/sysv/string.s");
-    pub const MATH_FUNCS: &str = include_str!("runtime/sysv/math.s");
-    pub const DATA_FUNCS: &str = include_str!("runtime/sysv/data.s");
-    pub const FILE_FUNCS: &str = include_str!("runtime/sysv/file.s");
+    pub const FUNCS: [&str; 11] = [
+        include_str!("runtime/sysv/print.s"),
+        include_str!("runtime/sysv/using.s"),
+        include_str!("runtime/sysv/input.s"),
+        include_str!("runtime/sysv/string.s"),
+        include_str!("runtime/sysv/math.s"),
+        include_str!("runtime/sysv/data.s"),
+        include_str!("runtime/sysv/file.s"),
+        include_str!("runtime/sysv/graphics.s"),
+        include_str!("runtime/sysv/memory.s"),
+        include_str!("runtime/sysv/event.s"),
+        include_str!("runtime/sysv/dict.s"),
+    ];
 }
 
 // Windows x64 Native runtime (pure Win32 API, no MinGW)
-#[cfg(windows)]
-mod runtime_files {
+mod win64 {
     pub const DATA_DEFS: &str = include_str!("runtime/win64-native/data_defs.s");
-    pub const PRINT_FUNCS: &str = include_str!("runtime/win64-native/print.s");
-    pub const INPUT_FUNCS: &str = include_str!("runtime/win64-native/input.s");
-    pub const STRING_FUNCS: &str = include_str!("runtime/win64-native/string.s");
-    pub const MATH_FUNCS: &str = include_str!("runtime/win64-native/math.s");
-    pub const DATA_FUNCS: &str = include_str!("runtime/win64-native/data.s");
-    pub const FILE_FUNCS: &str = include_str!("runtime/win64-native/file.s");
+    pub const FUNCS: [&str; 11] = [
+        include_str!("runtime/win64-native/print.s"),
+        include_str!("runtime/win64-native/using.s"),
+        include_str!("runtime/win64-native/input.s"),
+        include_str!("runtime/win64-native/string.s"),
+        include_str!("runtime/win64-native/math.s"),
+        include_str!("runtime/win64-native/data.s"),
+        include_str!("runtime/win64-native/file.s"),
+        include_str!("runtime/win64-native/graphics.s"),
+        include_str!("runtime/win64-native/memory.s"),
+        include_str!("runtime/win64-native/event.s"),
+        include_str!("runtime/win64-native/dict.s"),
+    ];
+}
+
+use crate::abi::Target;
+use std::collections::HashMap;
+
+/// The runtime routines and data `program` (its assembly) needs on `target`
+pub fn generate_runtime(program: &str, target: Target) -> String {
+    shake(&full_runtime(target), program)
 }
 
-use runtime_files::*;
+/// The runtime's first line, where it starts in a main module's assembly
+const BANNER: &str = "# BASIC Runtime Library";
 
-pub fn generate_runtime() -> String {
+/// The symbols the runtime uses from the program: its DATA table
+const PROGRAM_SYMBOLS: [&str; 3] = ["_data_table", "_data_count", "_data_ptr"];
+
+/// A main module's assembly without the runtime `emit` added to it, for
+/// linking with the runtime library; the symbols the runtime uses from the
+/// program are made global for it instead
+pub fn without_runtime(asm: &str) -> String {
+    let banner = format!("\n{}\n", BANNER);
+    let mut text = match asm.rfind(&banner) {
+        Some(end) => asm[..end + 1].to_string(),
+        None => asm.to_string(),
+    };
+    for symbol in PROGRAM_SYMBOLS {
+        text.push_str(&format!(".globl {}\n", symbol));
+    }
+    text
+}
+
+/// The whole runtime for `target`
+fn full_runtime(target: Target) -> String {
+    let (data_defs, funcs) = match target {
+        Target::Windows => (win64::DATA_DEFS, win64::FUNCS),
+        Target::Linux | Target::Macos => (sysv::DATA_DEFS, sysv::FUNCS),
+    };
     // On macOS, C library functions need underscore prefix
     // On Linux and Windows, no prefix
-    #[cfg(target_os = "macos")]
-    let libc_prefix = "_";
-    #[cfg(not(target_os = "macos"))]
-    let libc_prefix = "";
+    let libc_prefix = target.symbol_prefix();
 
     // Assemble all runtime components
     let mut output = String::new();
 
-    output.push_str("# BASIC Runtime Library\n");
+    output.push_str(BANNER);
+    output.push('\n');
     output.push_str("# Uses libc for cross-platform compatibility\n");
     output.push_str(".intel_syntax noprefix\n\n");
 
+    // struct termios layout used by event.s: offset of c_lflag, ICANON | ECHO;
+    // the clock_gettime clock id for the event timer; and for TCP files in
+    // file.s, the offset of ai_addr in struct addrinfo and the socket option
+    // for reusing a listening address; for LOCK, fcntl's command and lock
+    // types and the offset of l_type in struct flock; and setlocale's
+    // category for the decimal point
+    match target {
+        Target::Macos => output.push_str(
+            ".equ TERMIOS_LFLAG, 24\n.equ TERMIOS_RAW_BITS, 0x108\n.equ CLOCK_MONOTONIC, 6\n\
+             .equ ADDRINFO_ADDR, 32\n.equ SOL_SOCKET, 0xFFFF\n.equ SO_REUSEADDR, 4\n\
+             .equ F_SETLK, 8\n.equ F_RDLCK, 1\n.equ F_WRLCK, 3\n.equ FLOCK_TYPE, 20\n\
+             .equ LC_NUMERIC, 4\n\n",
+        ),
+        Target::Linux => output.push_str(
+            ".equ TERMIOS_LFLAG, 12\n.equ TERMIOS_RAW_BITS, 0xA\n.equ CLOCK_MONOTONIC, 1\n\
+             .equ ADDRINFO_ADDR, 24\n.equ SOL_SOCKET, 1\n.equ SO_REUSEADDR, 2\n\
+             .equ F_SETLK, 6\n.equ F_RDLCK, 0\n.equ F_WRLCK, 1\n.equ FLOCK_TYPE, 0\n\
+             .equ LC_NUMERIC, 1\n\n",
+        ),
+        Target::Windows => {}
+    }
+
     // Data section
-    output.push_str(DATA_DEFS);
+    output.push_str(data_defs);
     output.push_str("\n.text\n\n");
 
     // Functions - replace {libc} with appropriate prefix
-    output.push_str(&PRINT_FUNCS.replace("{libc}", libc_prefix));
-    output.push('\n');
-    output.push_str(&INPUT_FUNCS.replace("{libc}", libc_prefix));
-    output.push('\n');
-    output.push_str(&STRING_FUNCS.replace("{libc}", libc_prefix));
-    output.push('\n');
-    output.push_str(&MATH_FUNCS.replace("{libc}", libc_prefix));
-    output.push('\n');
-    output.push_str(&DATA_FUNCS.replace("{libc}", libc_prefix));
-    output.push('\n');
-    output.push_str(&FILE_FUNCS.replace("{libc}", libc_prefix));
-    output.push('\n');
+    for funcs in funcs {
+        output.push_str(&funcs.replace("{libc}", libc_prefix));
+        output.push('\n');
+    }
 
     output
 }
+
+/// A piece of the runtime: a global label and what follows it, up to the
+/// comments and directives that introduce the next one
+struct Chunk<'a> {
+    section: &'a str,
+    lines: Vec<&'a str>,
+}
+
+/// The label a line defines, if it starts with one
+fn label_def(line: &str) -> Option<&str> {
+    let (label, _) = line.split_once(':')?;
+    let ident = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$');
+    (!label.is_empty()
+        && label.chars().all(ident)
+        && !label.starts_with(|c: char| c.is_ascii_digit()))
+    .then_some(label)
+}
+
+/// A line without its comment (a `#` outside a string)
+fn code(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// The identifiers a line mentions
+fn words(line: &str) -> impl Iterator<Item = &str> {
+    code(line)
+        .split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')))
+        .filter(|word| !word.is_empty())
+}
+
+/// The identifiers starting with `_` in program text, comments and
+/// strings included. Runtime entry points all start with `_`, so this
+/// finds every one a program uses without splitting all of its words.
+fn underscore_words(text: &str) -> impl Iterator<Item = &str> {
+    let bytes = text.as_bytes();
+    let ident = |b: u8| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'$');
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        while let Some(offset) = bytes[pos..].iter().position(|&b| b == b'_') {
+            let start = pos + offset;
+            pos = bytes[start..]
+                .iter()
+                .position(|&b| !ident(b))
+                .map_or(bytes.len(), |len| start + len);
+            if start == 0 || !ident(bytes[start - 1]) {
+                return Some(&text[start..pos]);
+            }
+        }
+        None
+    })
+}
+
+/// Whether a line only introduces what follows it (so it goes with the
+/// next label)
+fn is_preamble(line: &str) -> bool {
+    let line = code(line).trim();
+    line.is_empty()
+        || [".globl", ".p2align", ".balign", ".align"]
+            .iter()
+            .any(|directive| line.starts_with(directive))
+}
+
+impl Chunk<'_> {
+    /// Whether control (or data) continues into the next chunk
+    fn falls_through(&self) -> bool {
+        let mut body = self
+            .lines
+            .iter()
+            .map(|line| match label_def(line) {
+                Some(label) => &line[label.len() + 1..],
+                None => line,
+            })
+            .map(|line| code(line).trim())
+            .filter(|line| !line.is_empty() && !line.starts_with(".globl"));
+        if self.section != ".text" {
+            return body.next().is_none();
+        }
+        !body
+            .next_back()
+            .is_some_and(|last| ["ret", "jmp ", "ud2"].iter().any(|op| last.starts_with(op)))
+    }
+}
+
+/// A runtime split up: the lines before its first global label, its
+/// `.equ`s, and its chunks
+struct Pieces<'a> {
+    header: Vec<&'a str>,
+    equs: Vec<&'a str>,
+    chunks: Vec<Chunk<'a>>,
+}
+
+fn split(runtime: &str) -> Pieces<'_> {
+    let mut header = Vec::new();
+    let mut equs = Vec::new();
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut pending = Vec::new();
+    let mut section = ".text";
+    for line in runtime.lines() {
+        let trimmed = code(line).trim();
+        if trimmed.starts_with(".equ") {
+            equs.push(line);
+            continue;
+        }
+        let target = match chunks.last_mut() {
+            Some(chunk) => &mut chunk.lines,
+            None => &mut header,
+        };
+        if matches!(trimmed, ".data" | ".text" | ".bss") {
+            target.append(&mut pending);
+            section = trimmed;
+            continue;
+        }
+        match label_def(line) {
+            Some(label) if !label.starts_with(".L") => {
+                pending.push(line);
+                let lines = std::mem::take(&mut pending);
+                chunks.push(Chunk { section, lines });
+            }
+            _ if is_preamble(line) => pending.push(line),
+            _ => {
+                target.append(&mut pending);
+                target.push(line);
+            }
+        }
+    }
+    match chunks.last_mut() {
+        Some(chunk) => chunk.lines.append(&mut pending),
+        None => header.append(&mut pending),
+    }
+    Pieces {
+        header,
+        equs,
+        chunks,
+    }
+}
+
+/// Which chunk defines each label, local ones included
+fn definitions<'a>(chunks: &[Chunk<'a>]) -> HashMap<&'a str, usize> {
+    let mut defined = HashMap::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        for label in chunk.lines.iter().filter_map(|line| label_def(line)) {
+            defined.insert(label, i);
+        }
+    }
+    defined
+}
+
+/// Keep the chunks of `runtime` that `program` needs
+fn shake(runtime: &str, program: &str) -> String {
+    let Pieces {
+        header,
+        equs,
+        chunks,
+    } = split(runtime);
+    let defined = definitions(&chunks);
+
+    let mut keep = vec![false; chunks.len()];
+    let mut work: Vec<usize> = underscore_words(program)
+        .filter_map(|word| defined.get(word).copied())
+        .collect();
+    while let Some(i) = work.pop() {
+        if std::mem::replace(&mut keep[i], true) {
+            continue;
+        }
+        let chunk = &chunks[i];
+        work.extend(
+            chunk
+                .lines
+                .iter()
+                .flat_map(|line| words(line))
+                .filter_map(|word| defined.get(word).copied()),
+        );
+        if chunk.falls_through() && i + 1 < chunks.len() {
+            work.push(i + 1);
+        }
+    }
+
+    let mut output: Vec<&str> = header;
+    output.extend(equs);
+    let mut current = "";
+    for (chunk, _) in chunks.iter().zip(&keep).filter(|(_, keep)| **keep) {
+        if chunk.section != current {
+            current = chunk.section;
+            output.push(current);
+        }
+        output.extend(&chunk.lines);
+    }
+    let mut text = output.join("\n");
+    text.push('\n');
+    text
+}
+
+/// The whole runtime for `target` as the members of a static library,
+/// for linking programs against instead of adding the parts they use
+pub fn library_members(target: Target) -> Vec<String> {
+    members(&full_runtime(target))
+}
+
+/// Split `runtime` into one assembly file per library member. Chunks that
+/// fall through into each other or share local labels stay together, so a
+/// member holds what `shake` would keep for it, and every label is made
+/// global for programs and the other members to link to.
+fn members(runtime: &str) -> Vec<String> {
+    let Pieces {
+        header,
+        equs,
+        chunks,
+    } = split(runtime);
+    let defined = definitions(&chunks);
+
+    // Each chunk's group is the first chunk of it
+    let mut group: Vec<usize> = (0..chunks.len()).collect();
+    fn root(group: &mut [usize], mut i: usize) -> usize {
+        while group[i] != i {
+            i = group[i];
+        }
+        i
+    }
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut joined: Vec<usize> = chunk
+            .lines
+            .iter()
+            .flat_map(|line| words(line))
+            .filter(|word| word.starts_with(".L"))
+            .filter_map(|word| defined.get(word).copied())
+            .collect();
+        if chunk.falls_through() && i + 1 < chunks.len() {
+            joined.push(i + 1);
+        }
+        for j in joined {
+            let (a, b) = (root(&mut group, i), root(&mut group, j));
+            group[a.max(b)] = a.min(b);
+        }
+    }
+
+    let mut prelude: Vec<&str> = header;
+    prelude.extend(equs);
+    let prelude = prelude.join("\n") + "\n";
+    let mut members: Vec<(String, &str)> = Vec::new();
+    let mut member_of = HashMap::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let first = root(&mut group, i);
+        let m = *member_of.entry(first).or_insert_with(|| {
+            members.push((prelude.clone(), ""));
+            members.len() - 1
+        });
+        let (text, section) = &mut members[m];
+        if chunk.section != *section {
+            *section = chunk.section;
+            text.push_str(chunk.section);
+            text.push('\n');
+        }
+        for line in &chunk.lines {
+            if let Some(label) = label_def(line).filter(|label| !label.starts_with(".L")) {
+                text.push_str(&format!(".globl {}\n", label));
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    members.into_iter().map(|(text, _)| text).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNTIME: &str = "\
+.intel_syntax noprefix
+.data
+_used_msg: .asciz \"used # not a comment\"
+_unused_msg: .asciz \"unused\"
+.text
+# Prints the message
+.globl _rt_used
+_rt_used:
+    lea rdi, [rip + _used_msg]
+    jmp .Lhelper_tail
+.globl _rt_unused
+_rt_unused:
+    lea rdi, [rip + _unused_msg]
+    ret
+_rt_entry:
+    xor eax, eax
+_rt_helper:
+    nop
+.Lhelper_tail:
+    ret
+";
+
+    // ===================
+    // Tree Shaking Tests
+    // ===================
+
+    #[test]
+    fn test_shake_keeps_what_is_used() {
+        let asm = shake(RUNTIME, "    call _rt_used\n");
+        assert!(
+            asm.starts_with(".intel_syntax noprefix\n.data\n_used_msg:"),
+            "{}",
+            asm
+        );
+        assert!(asm.contains("_rt_used:"), "{}", asm);
+        // A jump to a local label keeps the chunk it is in
+        assert!(asm.contains(".Lhelper_tail:"), "{}", asm);
+        assert!(!asm.contains("_rt_unused"), "{}", asm);
+        assert!(!asm.contains("_unused_msg"), "{}", asm);
+        assert!(!asm.contains("_rt_entry"), "{}", asm);
+
+        // Falling through keeps the next chunk
+        let asm = shake(RUNTIME, "    call _rt_entry\n");
+        assert!(
+            asm.contains("_rt_entry:\n    xor eax, eax\n_rt_helper:"),
+            "{}",
+            asm
+        );
+        assert!(!asm.contains(".data"), "{}", asm);
+    }
+
+    #[test]
+    fn test_shake_full_runtime() {
+        // Hello world needs printing but no file, graphics or event support
+        let program = "    call _rt_print_string\n    call _rt_print_newline\n";
+        let asm = generate_runtime(program, Target::Linux);
+        assert!(asm.contains("_rt_print_string:"), "{}", asm);
+        assert!(!asm.contains("_rt_file_open:"), "{}", asm);
+        assert!(!asm.contains("_rt_pset:"), "{}", asm);
+        assert!(asm.len() < full_runtime(Target::Linux).len() / 2);
+
+        // Each target gets its own runtime
+        let asm = generate_runtime(program, Target::Windows);
+        assert!(asm.contains("WriteFile"), "{}", asm);
+        let asm = generate_runtime(program, Target::Macos);
+        assert!(
+            asm.contains("_printf") || asm.contains("_fwrite"),
+            "{}",
+            asm
+        );
+    }
+
+    // ===================
+    // Runtime Library Tests
+    // ===================
+
+    #[test]
+    fn test_library_members() {
+        let members = members(RUNTIME);
+        let holding = |label: &str| {
+            let def = format!("\n{}:", label);
+            let found: Vec<&String> = members.iter().filter(|m| m.contains(&def)).collect();
+            assert_eq!(found.len(), 1, "{}: {:?}", label, members);
+            found[0]
+        };
+        // Each data item and routine is a member of its own, unless it
+        // falls through or jumps to a local label in another
+        assert_eq!(members.len(), 4, "{:?}", members);
+        let member = holding("_rt_used");
+        assert!(member.contains("_rt_entry:") && member.contains(".Lhelper_tail:"));
+        assert!(member.starts_with(".intel_syntax noprefix\n"), "{}", member);
+        assert!(!member.contains(".data"), "{}", member);
+        // Every label is global, once
+        assert_eq!(member.matches(".globl _rt_used\n").count(), 2);
+        assert!(member.contains(".globl _rt_helper\n"), "{}", member);
+        assert!(!member.contains(".globl .L"), "{}", member);
+        let member = holding("_used_msg");
+        assert!(
+            member
+                .ends_with(".data\n.globl _used_msg\n_used_msg: .asciz \"used # not a comment\"\n"),
+            "{}",
+            member
+        );
+        holding("_unused_msg");
+        holding("_rt_unused");
+
+        // The whole runtime, every label in one member
+        let members = library_members(Target::Linux);
+        let full = full_runtime(Target::Linux);
+        let labels = |text: &str| {
+            let mut labels: Vec<String> = text
+                .lines()
+                .filter_map(label_def)
+                .filter(|label| !label.starts_with(".L"))
+                .map(String::from)
+                .collect();
+            labels.sort();
+            labels
+        };
+        assert_eq!(labels(&members.concat()), labels(&full));
+        assert!(members.len() > 50, "{}", members.len());
+    }
+
+    #[test]
+    fn test_without_runtime() {
+        let asm = format!(
+            "main:\n    ret\n\n{}",
+            generate_runtime("call _rt_print_newline", Target::Linux)
+        );
+        let program = without_runtime(&asm);
+        assert!(
+            program.starts_with("main:\n    ret\n\n.globl _data_table\n"),
+            "{}",
+            program
+        );
+        assert!(!program.contains("_rt_print_newline"), "{}", program);
+    }
+}
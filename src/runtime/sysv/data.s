@@ -17,6 +17,9 @@
 #   0       8     Type tag: 0=integer, 1=float, 2=string
 #   8       8     Value: integer, double bits, or string pointer
 #
+#   A string's length is the quad just before its bytes, so it may hold
+#   any byte, NULs included; a NUL follows the bytes for strtod.
+#
 #   Example table for DATA 42, 3.14, "hello":
 #     Entry 0: [type=0] [value=42]           (integer)
 #     Entry 1: [type=1] [value=0x40091EB...]  (double bits for 3.14)
@@ -110,16 +113,7 @@ _rt_read_string:
     add rcx, rax                        # rcx = entry address
     # Load string pointer (assumes type is string)
     mov rax, QWORD PTR [rcx + 8]        # rax = string pointer
-    # Calculate length using strlen (DATA strings are null-terminated)
-    mov rdi, rax                        # string pointer for strlen
-    call {libc}strlen                   # returns length in rax
-    mov rdx, rax                        # length → rdx
-    # Reload string pointer (strlen clobbered rax)
-    mov rax, QWORD PTR [rip + _data_ptr]
-    shl rax, 4
-    lea rcx, [rip + _data_table]
-    add rcx, rax
-    mov rax, QWORD PTR [rcx + 8]        # rax = string pointer
+    mov rdx, QWORD PTR [rax - 8]        # rdx = length, stored before it
     # Advance to next entry
     inc QWORD PTR [rip + _data_ptr]
     leave
@@ -2,14 +2,38 @@
 .data
 _fmt_str: .asciz "%.*s"
 _fmt_int: .asciz "%ld"
-_fmt_float: .asciz "%g"
+_fmt_digits: .asciz "%.*g"
 _fmt_char: .asciz "%c"
 _fmt_newline: .asciz "\n"
-_fmt_input: .asciz "%lf"
 _fmt_input_str: .asciz "%1023[^\n]"
 _input_buf: .skip 1024
 _chr_buf: .skip 2
 _str_buf: .skip 64
 _rng_state: .quad 0x12345678DEADBEEF
 _cls_seq: .asciz "\033[2J\033[H"
-_gosub_overflow_msg: .asciz "Error: GOSUB stack overflow\n"
+# Runtime errors (see _rt_error in print.s): the last numbered line reached
+.globl _rt_cur_line
+_rt_cur_line: .quad 0
+_error_fmt: .asciz "%s\n"
+_error_line_fmt: .asciz "%s in %ld\n"
+_gosub_overflow_msg: .asciz "GOSUB stack overflow"
+_gosub_underflow_msg: .asciz "RETURN without GOSUB"
+_overflow_msg: .asciz "Overflow"
+_subscript_msg: .asciz "Subscript out of range"
+_div_zero_msg: .asciz "Division by zero"
+_bad_file_msg: .asciz "Bad file number"
+_type_mismatch_msg: .asciz "Type mismatch"
+# Output channels: 0 = console, 1-15 = files (see print.s)
+_out_col: .skip 128
+_out_width: .quad 80
+    .rept 15
+    .quad 255
+    .endr
+_out_blanks: .ascii "                "
+_out_decimal_point: .ascii "."  # the console's (see _rt_init_locale)
+_locale_env: .asciz ""          # setlocale: the environment's locale
+_locale_c: .asciz "C"
+# Text screen for SCREEN(row, col) (see print.s)
+_scr_text: .skip 2000, 32
+_scr_row: .quad 0
+_scr_col: .quad 0
@@ -0,0 +1,526 @@
+# ==============================================================================
+# BASIC Runtime: Dictionaries
+# ==============================================================================
+#
+# DICTNEW, DICTSET, DICTDEL, DICTGET$, DICTHAS, DICTCOUNT and DICTKEY$:
+# string-keyed associative arrays, an xbasic64 extension.
+#
+#     D = DICTNEW()
+#     DICTSET D, "apple", "red"
+#     PRINT DICTGET$(D, "apple")
+#
+# Handles:
+#   DICTNEW returns a handle, 1 to DICT_MAX - 1: the dictionary's index in
+#   _dict_table. Dictionaries last until the program ends. Any other number
+#   given as a handle is an illegal function call.
+#
+# Layout:
+#   A dictionary (DICT_SIZE bytes) keeps its entries in the order their keys
+#   were first set, each a copy of the key and of the value, since the
+#   strings a program passes may live in a static buffer. Like other strings
+#   they are never freed: a value DICTGET$ returned stays valid after the key
+#   is set again or deleted. A hash index of 2 * DICT_CAP 32-bit slots,
+#   each 0 (empty) or an entry number + 1, finds keys by FNV-1a hash with
+#   linear probing; it is never more than half full. The entries grow by
+#   doubling, and the index is rebuilt when they do and after a DICTDEL,
+#   which closes the gap so the other keys keep their order.
+# ==============================================================================
+
+.equ DICT_MAX, 256          # handles 1 to DICT_MAX - 1
+.equ DICT_ENTRIES, 0        # dictionary: the entry array
+.equ DICT_COUNT, 8          #   entries in use
+.equ DICT_CAP, 16           #   entries there is room for
+.equ DICT_INDEX, 24         #   the hash index
+.equ DICT_SIZE, 32
+.equ DICT_FIRST_CAP, 8
+.equ ENTRY_KEY, 0           # entry: key pointer and length
+.equ ENTRY_KEY_LEN, 8
+.equ ENTRY_VALUE, 16        #   value pointer and length
+.equ ENTRY_VALUE_LEN, 24
+.equ ENTRY_SHIFT, 5         # 32 bytes per entry
+
+.data
+_dict_table: .skip DICT_MAX * 8
+_dict_next: .quad 1         # the handle the next DICTNEW returns
+_dict_empty: .byte 0        # DICTGET$ of a missing key
+_dict_full_msg: .asciz "Out of memory"
+
+.text
+
+# ------------------------------------------------------------------------------
+# _rt_dict_new - Create an empty dictionary (DICTNEW function)
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   rax = handle ("Out of memory" after DICT_MAX - 1 of them)
+# ------------------------------------------------------------------------------
+.globl _rt_dict_new
+_rt_dict_new:
+    mov rax, QWORD PTR [rip + _dict_next]
+    cmp rax, DICT_MAX
+    jae .Ldict_full
+    push rbp
+    mov rbp, rsp
+    # calloc(1, DICT_SIZE): no entries and no index yet
+    mov edi, 1
+    mov esi, DICT_SIZE
+    call {libc}calloc
+    mov rcx, QWORD PTR [rip + _dict_next]
+    lea rdx, [rip + _dict_table]
+    mov QWORD PTR [rdx + rcx*8], rax
+    lea rax, [rcx + 1]
+    mov QWORD PTR [rip + _dict_next], rax
+    mov rax, rcx
+    leave
+    ret
+.Ldict_full:
+    lea rdi, [rip + _dict_full_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_dict_set - Set a key's value (DICTSET statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = handle
+#   rsi = key pointer
+#   rdx = key length
+#   rcx = value pointer
+#   r8  = value length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_dict_set
+_rt_dict_set:
+    call _dict_check
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    sub rsp, 8
+    mov rbx, rax            # rbx = dictionary
+    mov r12, rsi            # r12 = key
+    mov r13, rdx            # r13 = key length
+    mov r15, r8             # r15 = value length
+    mov rdi, rcx
+    mov rsi, r8
+    call _dict_copy
+    mov r14, rax            # r14 = copy of the value
+
+    mov rdi, rbx
+    mov rsi, r12
+    mov rdx, r13
+    call _dict_slot
+    test rax, rax
+    jz .Ldict_set_new
+    mov ecx, DWORD PTR [rax]
+    test ecx, ecx
+    jz .Ldict_set_new
+    # A key already there gets the new value
+    dec ecx
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rbx + DICT_ENTRIES]
+    mov QWORD PTR [rcx + ENTRY_VALUE], r14
+    mov QWORD PTR [rcx + ENTRY_VALUE_LEN], r15
+    jmp .Ldict_set_done
+
+.Ldict_set_new:
+    # Full: double the entries (realloc(NULL) allocates the first ones)
+    mov rax, QWORD PTR [rbx + DICT_COUNT]
+    cmp rax, QWORD PTR [rbx + DICT_CAP]
+    jb .Ldict_set_add
+    mov rax, QWORD PTR [rbx + DICT_CAP]
+    add rax, rax
+    mov ecx, DICT_FIRST_CAP
+    cmovz rax, rcx
+    mov QWORD PTR [rbx + DICT_CAP], rax
+    mov rdi, QWORD PTR [rbx + DICT_ENTRIES]
+    mov rsi, rax
+    shl rsi, ENTRY_SHIFT
+    call {libc}realloc
+    mov QWORD PTR [rbx + DICT_ENTRIES], rax
+    mov rdi, rbx
+    call _dict_reindex
+
+.Ldict_set_add:
+    # New entry at the end: copies of the key and value
+    mov rdi, r12
+    mov rsi, r13
+    call _dict_copy
+    mov rcx, QWORD PTR [rbx + DICT_COUNT]
+    mov rdx, rcx
+    shl rdx, ENTRY_SHIFT
+    add rdx, QWORD PTR [rbx + DICT_ENTRIES]
+    mov QWORD PTR [rdx + ENTRY_KEY], rax
+    mov QWORD PTR [rdx + ENTRY_KEY_LEN], r13
+    mov QWORD PTR [rdx + ENTRY_VALUE], r14
+    mov QWORD PTR [rdx + ENTRY_VALUE_LEN], r15
+    inc rcx
+    mov QWORD PTR [rbx + DICT_COUNT], rcx
+    # and in the index, where the search for the key stopped
+    mov rdi, rbx
+    mov rsi, r12
+    mov rdx, r13
+    call _dict_slot
+    mov rcx, QWORD PTR [rbx + DICT_COUNT]
+    mov DWORD PTR [rax], ecx
+
+.Ldict_set_done:
+    add rsp, 8
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_del - Delete a key (DICTDEL statement)
+# ------------------------------------------------------------------------------
+# Deleting a key that isn't there does nothing.
+#
+# Arguments:
+#   rdi = handle
+#   rsi = key pointer
+#   rdx = key length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_dict_del
+_rt_dict_del:
+    call _dict_check
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 8
+    mov rbx, rax            # rbx = dictionary
+    mov rdi, rax
+    call _dict_slot
+    test rax, rax
+    jz .Ldict_del_done
+    mov ecx, DWORD PTR [rax]
+    test ecx, ecx
+    jz .Ldict_del_done
+    # Move the later entries down over it, a quadword at a time
+    dec ecx
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rbx + DICT_ENTRIES]     # rcx = the entry
+    mov rdx, QWORD PTR [rbx + DICT_COUNT]
+    dec rdx
+    mov QWORD PTR [rbx + DICT_COUNT], rdx
+    shl rdx, ENTRY_SHIFT
+    add rdx, QWORD PTR [rbx + DICT_ENTRIES]     # rdx = the new end
+.Ldict_del_move:
+    cmp rcx, rdx
+    jae .Ldict_del_moved
+    mov rax, QWORD PTR [rcx + 32]
+    mov QWORD PTR [rcx], rax
+    add rcx, 8
+    jmp .Ldict_del_move
+.Ldict_del_moved:
+    mov rdi, rbx
+    call _dict_reindex
+.Ldict_del_done:
+    add rsp, 8
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_get - A key's value (DICTGET$ function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = handle
+#   rsi = key pointer
+#   rdx = key length
+#
+# Returns:
+#   rax = value pointer
+#   rdx = value length; empty if the key isn't there
+# ------------------------------------------------------------------------------
+.globl _rt_dict_get
+_rt_dict_get:
+    call _dict_check
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 8
+    mov rbx, rax            # rbx = dictionary
+    mov rdi, rax
+    call _dict_slot
+    test rax, rax
+    jz .Ldict_get_missing
+    mov ecx, DWORD PTR [rax]
+    test ecx, ecx
+    jz .Ldict_get_missing
+    dec ecx
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rbx + DICT_ENTRIES]
+    mov rax, QWORD PTR [rcx + ENTRY_VALUE]
+    mov rdx, QWORD PTR [rcx + ENTRY_VALUE_LEN]
+    jmp .Ldict_get_done
+.Ldict_get_missing:
+    lea rax, [rip + _dict_empty]
+    xor edx, edx
+.Ldict_get_done:
+    add rsp, 8
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_has - Whether a dictionary has a key (DICTHAS function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = handle
+#   rsi = key pointer
+#   rdx = key length
+#
+# Returns:
+#   rax = -1 if the key is there, else 0
+# ------------------------------------------------------------------------------
+.globl _rt_dict_has
+_rt_dict_has:
+    call _dict_check
+    push rbp
+    mov rbp, rsp
+    mov rdi, rax
+    call _dict_slot
+    test rax, rax
+    jz .Ldict_has_done
+    mov eax, DWORD PTR [rax]
+    neg eax                 # CF = entry number not 0
+    sbb rax, rax
+.Ldict_has_done:
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_count - Number of keys in a dictionary (DICTCOUNT function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = handle
+#
+# Returns:
+#   rax = number of keys
+# ------------------------------------------------------------------------------
+.globl _rt_dict_count
+_rt_dict_count:
+    call _dict_check
+    mov rax, QWORD PTR [rax + DICT_COUNT]
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_key - A dictionary's nth key (DICTKEY$ function)
+# ------------------------------------------------------------------------------
+# Keys are numbered from 1 in the order they were first set.
+#
+# Arguments:
+#   rdi = handle
+#   rsi = key number (1 to DICTCOUNT, else an illegal function call)
+#
+# Returns:
+#   rax = key pointer
+#   rdx = key length
+# ------------------------------------------------------------------------------
+.globl _rt_dict_key
+_rt_dict_key:
+    call _dict_check
+    lea rcx, [rsi - 1]
+    cmp rcx, QWORD PTR [rax + DICT_COUNT]
+    jae _rt_illegal_call    # unsigned compare also rejects 0 and negatives
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rax + DICT_ENTRIES]
+    mov rax, QWORD PTR [rcx + ENTRY_KEY]
+    mov rdx, QWORD PTR [rcx + ENTRY_KEY_LEN]
+    ret
+
+# ------------------------------------------------------------------------------
+# _dict_check - The dictionary a handle refers to (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = handle
+#
+# Returns: rax = the dictionary; an illegal function call for a handle
+# DICTNEW didn't return. Uses only rax and r11, so the caller's arguments
+# are kept.
+# ------------------------------------------------------------------------------
+_dict_check:
+    lea rax, [rdi - 1]
+    cmp rax, DICT_MAX - 2
+    ja _rt_illegal_call     # unsigned compare also rejects 0 and negatives
+    lea r11, [rip + _dict_table]
+    mov rax, QWORD PTR [r11 + rdi*8]
+    test rax, rax
+    jz _rt_illegal_call
+    ret
+
+# ------------------------------------------------------------------------------
+# _dict_slot - Find a key's slot in a dictionary's index (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = dictionary
+#   rsi = key pointer
+#   rdx = key length
+#
+# Returns:
+#   rax = the key's slot (a DWORD holding its entry number + 1), or the
+#         empty slot where the search stopped if it isn't there; 0 if the
+#         dictionary has never had an entry
+# ------------------------------------------------------------------------------
+_dict_slot:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    sub rsp, 8
+    mov rbx, rdi            # rbx = dictionary
+    mov r12, rsi            # r12 = key
+    mov r13, rdx            # r13 = key length
+    xor eax, eax
+    cmp QWORD PTR [rbx + DICT_INDEX], 0
+    je .Ldict_slot_done
+
+    # FNV-1a hash of the key
+    mov rax, 0xCBF29CE484222325
+    mov r8, 0x100000001B3
+    xor ecx, ecx
+.Ldict_hash:
+    cmp rcx, r13
+    jae .Ldict_hashed
+    movzx edx, BYTE PTR [r12 + rcx]
+    xor rax, rdx
+    imul rax, r8
+    inc rcx
+    jmp .Ldict_hash
+.Ldict_hashed:
+    mov r15, QWORD PTR [rbx + DICT_CAP]
+    lea r15, [r15 + r15 - 1]    # r15 = slot mask (2 * DICT_CAP slots)
+    mov r14, rax
+    and r14, r15            # r14 = slot number
+
+.Ldict_probe:
+    mov rax, QWORD PTR [rbx + DICT_INDEX]
+    lea rax, [rax + r14*4]
+    mov ecx, DWORD PTR [rax]
+    test ecx, ecx
+    jz .Ldict_slot_done     # an empty slot ends the search
+    dec ecx
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rbx + DICT_ENTRIES]
+    cmp QWORD PTR [rcx + ENTRY_KEY_LEN], r13
+    jne .Ldict_probe_next
+    # memcmp(entry key, key, length)
+    mov QWORD PTR [rsp], rax
+    mov rdi, QWORD PTR [rcx + ENTRY_KEY]
+    mov rsi, r12
+    mov rdx, r13
+    call {libc}memcmp
+    test eax, eax
+    mov rax, QWORD PTR [rsp]
+    jz .Ldict_slot_done
+.Ldict_probe_next:
+    inc r14
+    and r14, r15
+    jmp .Ldict_probe
+
+.Ldict_slot_done:
+    add rsp, 8
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _dict_reindex - Rebuild a dictionary's index (internal)
+# ------------------------------------------------------------------------------
+# Makes a new, empty index of 2 * DICT_CAP slots and adds every entry to it.
+#
+# Arguments:
+#   rdi = dictionary
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_dict_reindex:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    sub rsp, 8
+    mov rbx, rdi            # rbx = dictionary
+    mov rdi, QWORD PTR [rbx + DICT_INDEX]
+    call {libc}free         # free(NULL) is a no-op
+    # calloc(2 * DICT_CAP, 4)
+    mov rdi, QWORD PTR [rbx + DICT_CAP]
+    add rdi, rdi
+    mov esi, 4
+    call {libc}calloc
+    mov QWORD PTR [rbx + DICT_INDEX], rax
+
+    xor r12d, r12d          # r12 = entry number
+.Ldict_reindex_loop:
+    cmp r12, QWORD PTR [rbx + DICT_COUNT]
+    jae .Ldict_reindex_done
+    mov r13, r12
+    shl r13, ENTRY_SHIFT
+    add r13, QWORD PTR [rbx + DICT_ENTRIES]
+    # Keys are distinct, so the search stops at an empty slot
+    mov rdi, rbx
+    mov rsi, QWORD PTR [r13 + ENTRY_KEY]
+    mov rdx, QWORD PTR [r13 + ENTRY_KEY_LEN]
+    call _dict_slot
+    inc r12
+    mov DWORD PTR [rax], r12d
+    jmp .Ldict_reindex_loop
+.Ldict_reindex_done:
+    add rsp, 8
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _dict_copy - Copy a string for a dictionary to keep (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = pointer
+#   rsi = length
+#
+# Returns:
+#   rax = the copy (malloc'd)
+# ------------------------------------------------------------------------------
+_dict_copy:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    sub rsp, 8
+    mov rbx, rdi
+    mov r12, rsi
+    lea rdi, [rsi + 1]      # never malloc(0)
+    call {libc}malloc
+    mov r13, rax
+    mov rdi, rax
+    mov rsi, rbx
+    mov rdx, r12
+    call {libc}memcpy
+    mov rax, r13
+    add rsp, 8
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
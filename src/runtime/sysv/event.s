@@ -0,0 +1,1154 @@
+# ==============================================================================
+# BASIC Runtime: Event Trapping
+# ==============================================================================
+#
+# ON KEY(n) GOSUB, KEY(n) ON/OFF/STOP, ON TIMER(n) GOSUB, TIMER ON/OFF/STOP,
+# ON BREAK GOSUB, BREAK ON/OFF/STOP, ON MOUSE GOSUB, MOUSE ON/OFF/STOP, the
+# MOUSEX, MOUSEY and MOUSEB functions, and the STICK and STRIG joystick
+# functions.
+#
+# Each event source is a numbered slot with a handler address (the GOSUB
+# target), a trap state and a pending flag. Slot 0 is the timer; slots 1-31
+# are keys, numbered as in GW-BASIC and QuickBASIC:
+#   1-10 = F1-F10, 11 = Up, 12 = Left, 13 = Right, 14 = Down,
+#   30 = F11, 31 = F12
+# slot 32 is Ctrl-C and slot 33 is a mouse button press.
+#
+# Trap states:
+#   OFF  - events are ignored
+#   ON   - events run the handler
+#   STOP - events are remembered and run the handler once the trap is ON
+#
+# When a program uses event trapping, the compiled code calls _rt_event_poll
+# at every statement boundary and at the top of every loop. If an event is
+# pending, the poll returns the handler address and the compiled code runs
+# it as a GOSUB. While a handler runs, its event is held as if stopped, and
+# _rt_event_return releases it when the handler RETURNs.
+#
+# The timer event occurs every n seconds of CLOCK_MONOTONIC time while the
+# timer trap is not OFF, checked when the program polls. Turning the trap ON
+# from OFF starts the count again.
+#
+# Keys are read from stdin without blocking. While any key is trapped and
+# stdin is a terminal, the terminal is switched to non-canonical mode
+# without echo so keys arrive without Enter; the original mode is restored
+# when trapping ends or the program exits. Function and arrow keys are
+# recognized from their ANSI/xterm escape sequences; other input read while
+# keys are trapped is discarded.
+#
+# While the mouse trap is not OFF and stdout is a terminal, xterm mouse
+# reporting (any-motion tracking, SGR encoding) is turned on, and the
+# reports "ESC [ < b ; x ; y M" (press or motion) and "... m" (release) are
+# read from stdin with the keys. They update the pointer position (1-based
+# column and row) and the buttons held down (1 = left, 2 = right,
+# 4 = middle), which MOUSEX, MOUSEY and MOUSEB return; a button going down
+# is the mouse event. Reporting is turned off again by MOUSE OFF or when the
+# program exits.
+#
+# STICK and STRIG read a joystick device of the Linux joystick API, named by
+# XBASIC64_JOYSTICK or else /dev/input/js0. It is opened on first use and
+# read without blocking on every call. Joystick A is its first two axes and
+# joystick B the next two (the right stick of many gamepads); axes are
+# scaled to 1-200 with 100 at the centre. The four buttons A1, B1, A2 and B2
+# are its buttons 0-3. Without a joystick the sticks stay centred and no
+# button is pressed.
+#
+# Every program catches SIGINT (Ctrl-C) from the start (codegen calls
+# _rt_init_break). While BREAK is trapped, the signal only marks the break
+# event pending; otherwise the program ends through _rt_error with "Break"
+# ("Break in <line>"), which flushes the output and every file and, through
+# atexit, restores the terminal mode.
+#
+# TERMIOS_LFLAG and TERMIOS_RAW_BITS (offset of c_lflag in struct termios
+# and its ICANON | ECHO bits) and CLOCK_MONOTONIC are defined per platform
+# by runtime.rs.
+#
+# Global state:
+#   _evt_handler = handler address for each slot (0 = none)
+#   _evt_state   = trap state for each slot
+#   _evt_pending = 1 if the slot's event occurred and has not run yet
+#   _evt_busy    = 1 while the slot's handler is running
+#   _evt_nest    = slots of the running handlers, innermost last
+#   _evt_timer_interval = timer period in milliseconds
+#   _evt_timer_next     = clock reading (ms) at which the timer fires next
+#   _evt_mouse_x, _evt_mouse_y, _evt_mouse_b = last reported pointer state
+#   _joy_axes    = STICK value of each axis
+#   _joy_down    = joystick buttons held down (bit n = button n)
+#   _joy_pressed = buttons pressed since STRIG last reported them
+# ==============================================================================
+
+.equ EVT_SLOTS, 34
+.equ EVT_OFF, 0
+.equ EVT_ON, 1
+.equ EVT_STOP, 2
+.equ EVT_TIMER, 0           # timer slot
+.equ TIMER_MAX, 86400       # longest ON TIMER interval, in seconds
+.equ MS_PER_SEC, 1000
+.equ NS_PER_MS, 1000000
+.equ EVT_KEY_FIRST, 1       # F1
+.equ EVT_KEY_ARROWS, 14     # last of F1-F10 and the arrow keys
+.equ EVT_KEY_F11, 30
+.equ EVT_KEY_LAST, 31       # F12
+.equ EVT_BREAK, 32          # Ctrl-C slot
+.equ EVT_MOUSE, 33          # mouse slot
+.equ SGR_FIELDS, 3          # button, column and row in a mouse report
+.equ SGR_MAX, 9999          # largest field value we accept
+.equ SGR_NOT_BUTTON, 96     # motion (32) and wheel (64) reports
+.equ SGR_BUTTON_BITS, 3     # 0 = left, 1 = middle, 2 = right, 3 = none
+.equ SIGINT, 2
+.equ EVT_TILDE_MAX, 24      # highest n in "ESC [ n ~" we recognize
+.equ KEY_BUF_SIZE, 256      # room for a burst of mouse reports
+.equ TERMIOS_SIZE, 128      # larger than struct termios on every platform
+.equ POLLIN, 1
+.equ TCSANOW, 0
+.equ CHAR_ESC, 27
+.equ JOY_AXES, 4            # joystick A x and y, joystick B x and y
+.equ JOY_BUTTONS, 4         # A1, B1, A2, B2
+.equ JOY_STRIG_LAST, 7      # highest STRIG number
+.equ JOY_CLOSED, -2         # _joy_fd before the device is opened
+.equ JOY_CENTER, 100
+.equ JOY_RANGE, 199         # STICK values run 1 to 1 + JOY_RANGE
+.equ JS_EVENT_SIZE, 8       # struct js_event: time, value, type, number
+.equ JS_VALUE, 4
+.equ JS_TYPE, 6
+.equ JS_NUMBER, 7
+.equ JS_EVENT_BUTTON, 1
+.equ JS_EVENT_AXIS, 2
+.equ JS_EVENT_INIT, 0x80    # state reported when the device is opened
+
+.data
+_evt_handler: .skip EVT_SLOTS * 8
+_evt_state: .skip EVT_SLOTS
+_evt_pending: .skip EVT_SLOTS
+_evt_busy: .skip EVT_SLOTS
+_evt_nest: .skip EVT_SLOTS
+_evt_depth: .quad 0
+_evt_armed: .quad 0         # slots whose trap is not OFF
+_evt_keys_armed: .quad 0    # key and mouse slots whose trap is not OFF
+_evt_stdin_eof: .quad 0
+_evt_timer_interval: .quad 0    # ms
+_evt_timer_next: .quad 0        # ms, on the monotonic clock
+_evt_tty_raw: .quad 0       # 1 = terminal switched, _evt_termios holds the original
+_evt_atexit_done: .quad 0
+_evt_mouse_x: .quad 0
+_evt_mouse_y: .quad 0
+_evt_mouse_b: .quad 0
+_evt_mouse_on: .quad 0      # 1 = mouse reporting turned on
+_evt_mouse_atexit: .quad 0
+_evt_mouse_on_seq: .asciz "\033[?1003h\033[?1006h"
+_evt_mouse_off_seq: .asciz "\033[?1006l\033[?1003l"
+_evt_break_msg: .asciz "Break"
+_evt_termios: .skip TERMIOS_SIZE
+_evt_raw_termios: .skip TERMIOS_SIZE
+_evt_key_buf: .skip KEY_BUF_SIZE
+_evt_pollfd: .long 0        # fd = stdin
+    .short POLLIN           # events
+    .short 0                # revents
+# Key numbers for "ESC [ n ~", indexed by n (F1-F4 also arrive as ESC O P-S)
+_evt_tilde_keys: .byte 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+    .byte 1, 2, 3, 4, 5, 0, 6, 7, 8, 9, 10, 0, 30, 31
+# Key numbers for "ESC [ A" (Up), B (Down), C (Right), D (Left)
+_evt_arrow_keys: .byte 11, 14, 13, 12
+_joy_fd: .quad JOY_CLOSED   # joystick device, -1 if there is none
+_joy_axes: .quad JOY_CENTER, JOY_CENTER, JOY_CENTER, JOY_CENTER
+_joy_down: .quad 0
+_joy_pressed: .quad 0
+_joy_event: .skip JS_EVENT_SIZE
+_joy_pollfd: .long 0        # fd
+    .short POLLIN           # events
+    .short 0                # revents
+_joy_env_name: .asciz "XBASIC64_JOYSTICK"
+_joy_default_path: .asciz "/dev/input/js0"
+# MOUSEB bits for SGR buttons 0 (left), 1 (middle) and 2 (right)
+_evt_sgr_buttons: .byte 1, 4, 2
+
+.text
+
+# ------------------------------------------------------------------------------
+# _rt_on_key - Set the handler for a key (ON KEY(n) GOSUB)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = key number (1-14, 30, 31)
+#   rsi = handler address
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_on_key
+_rt_on_key:
+    call _evt_check_key
+    lea rax, [rip + _evt_handler]
+    mov QWORD PTR [rax + rdi*8], rsi
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_key_trap - Turn trapping of a key on, off or stop it (KEY(n) ON/OFF/STOP)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = key number (1-14, 30, 31; 0 = all keys)
+#   rsi = state (0 = OFF, 1 = ON, 2 = STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_key_trap
+_rt_key_trap:
+    test rdi, rdi
+    jz .Lkey_trap_all
+    call _evt_check_key
+    jmp _evt_set_state
+
+.Lkey_trap_all:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    mov r12, rsi            # r12 = state
+    mov ebx, EVT_KEY_FIRST  # rbx = key
+.Lkey_trap_loop:
+    mov rdi, rbx
+    mov rsi, r12
+    call _evt_set_state
+    inc rbx
+    cmp rbx, EVT_KEY_ARROWS
+    jbe .Lkey_trap_loop
+    cmp rbx, EVT_KEY_F11
+    jae .Lkey_trap_high
+    mov ebx, EVT_KEY_F11    # skip the unused key numbers
+.Lkey_trap_high:
+    cmp rbx, EVT_KEY_LAST
+    jbe .Lkey_trap_loop
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_on_timer - Set the timer interval and handler (ON TIMER(n) GOSUB)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = interval in seconds (1-86400)
+#   rsi = handler address
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_on_timer
+_rt_on_timer:
+    cmp rdi, 1
+    jl _rt_illegal_call
+    cmp rdi, TIMER_MAX
+    jg _rt_illegal_call
+    imul rdi, rdi, MS_PER_SEC
+    mov QWORD PTR [rip + _evt_timer_interval], rdi
+    mov QWORD PTR [rip + _evt_handler + EVT_TIMER * 8], rsi
+    jmp _evt_timer_restart
+
+# ------------------------------------------------------------------------------
+# _rt_timer_trap - Turn the timer on, off or stop it (TIMER ON/OFF/STOP)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = state (0 = OFF, 1 = ON, 2 = STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_timer_trap
+_rt_timer_trap:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push rbx                # alignment
+    mov rbx, rdi            # rbx = state
+    cmp ebx, EVT_OFF
+    je .Ltimer_trap_set
+    cmp BYTE PTR [rip + _evt_state + EVT_TIMER], EVT_OFF
+    jne .Ltimer_trap_set    # already counting
+    call _evt_timer_restart
+.Ltimer_trap_set:
+    mov edi, EVT_TIMER
+    mov rsi, rbx
+    call _evt_set_state
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_on_break - Set the Ctrl-C handler (ON BREAK GOSUB)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = handler address
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_on_break
+_rt_on_break:
+    mov QWORD PTR [rip + _evt_handler + EVT_BREAK * 8], rdi
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_break_trap - Turn Ctrl-C trapping on, off or stop it (BREAK ON/OFF/STOP)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = state (0 = OFF, 1 = ON, 2 = STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_break_trap
+_rt_break_trap:
+    mov rsi, rdi
+    mov edi, EVT_BREAK
+    jmp _evt_set_state
+
+# ------------------------------------------------------------------------------
+# _rt_on_mouse - Set the mouse button handler (ON MOUSE GOSUB)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = handler address
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_on_mouse
+_rt_on_mouse:
+    mov QWORD PTR [rip + _evt_handler + EVT_MOUSE * 8], rdi
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_mouse_trap - Turn mouse trapping on, off or stop it (MOUSE ON/OFF/STOP)
+# ------------------------------------------------------------------------------
+# Mouse reporting stays on while the trap is ON or STOP, so a program can
+# read MOUSEX, MOUSEY and MOUSEB after MOUSE STOP without a handler.
+#
+# Arguments:
+#   rdi = state (0 = OFF, 1 = ON, 2 = STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_mouse_trap
+_rt_mouse_trap:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push rbx                # alignment
+    mov rbx, rdi            # rbx = state
+    mov rsi, rdi
+    mov edi, EVT_MOUSE
+    call _evt_set_state
+    xor edi, edi
+    cmp ebx, EVT_OFF
+    setne dil
+    call _evt_mouse_report
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_mouse_x - Pointer column (MOUSEX)
+# _rt_mouse_y - Pointer row (MOUSEY)
+# _rt_mouse_b - Buttons held down (MOUSEB: 1 = left, 2 = right, 4 = middle)
+# ------------------------------------------------------------------------------
+# Each reads any pending input first. The position is 1-based, and 0 before
+# the first mouse report.
+#
+# Arguments: none
+#
+# Returns:
+#   rax = the value
+# ------------------------------------------------------------------------------
+.globl _rt_mouse_x
+_rt_mouse_x:
+    push rbp
+    mov rbp, rsp
+    call _evt_mouse_update
+    mov rax, QWORD PTR [rip + _evt_mouse_x]
+    leave
+    ret
+
+.globl _rt_mouse_y
+_rt_mouse_y:
+    push rbp
+    mov rbp, rsp
+    call _evt_mouse_update
+    mov rax, QWORD PTR [rip + _evt_mouse_y]
+    leave
+    ret
+
+.globl _rt_mouse_b
+_rt_mouse_b:
+    push rbp
+    mov rbp, rsp
+    call _evt_mouse_update
+    mov rax, QWORD PTR [rip + _evt_mouse_b]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_init_break - Catch Ctrl-C (called at program start)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_init_break
+_rt_init_break:
+    push rbp
+    mov rbp, rsp
+    mov edi, SIGINT         # signal(SIGINT, _evt_break_signal)
+    lea rsi, [rip + _evt_break_signal]
+    call {libc}signal
+    cmp rax, SIG_IGN        # a program started ignoring it (in the
+    jne .Linit_break_done   # background) keeps ignoring it
+    mov edi, SIGINT
+    mov esi, SIG_IGN
+    call {libc}signal
+.Linit_break_done:
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_break_signal - SIGINT handler (internal)
+# ------------------------------------------------------------------------------
+# Marks the break event pending while BREAK is trapped with a handler set,
+# and otherwise ends the program with "Break".
+#
+# Arguments:
+#   rdi = signal number
+#
+# Returns: only when the break is trapped
+# ------------------------------------------------------------------------------
+_evt_break_signal:
+    cmp BYTE PTR [rip + _evt_state + EVT_BREAK], EVT_OFF
+    je .Lbreak_signal_end
+    cmp QWORD PTR [rip + _evt_handler + EVT_BREAK * 8], 0
+    je .Lbreak_signal_end
+    mov BYTE PTR [rip + _evt_pending + EVT_BREAK], 1
+    ret
+.Lbreak_signal_end:
+    lea rdi, [rip + _evt_break_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_event_poll - Check for a trapped event (called at statement boundaries)
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   rax = handler address to run as a GOSUB, or 0 if no event is ready.
+#         The event is marked running until _rt_event_return.
+# ------------------------------------------------------------------------------
+.globl _rt_event_poll
+_rt_event_poll:
+    cmp QWORD PTR [rip + _evt_armed], 0
+    jne .Lpoll_armed
+    xor eax, eax            # fast path: nothing is trapped
+    ret
+
+.Lpoll_armed:
+    push rbp
+    mov rbp, rsp
+    cmp QWORD PTR [rip + _evt_keys_armed], 0
+    je .Lpoll_timer
+    call _evt_read_keys
+
+.Lpoll_timer:
+    cmp BYTE PTR [rip + _evt_state + EVT_TIMER], EVT_OFF
+    je .Lpoll_scan_start
+    call _evt_now_ms
+    cmp rax, QWORD PTR [rip + _evt_timer_next]
+    jl .Lpoll_scan_start
+    mov BYTE PTR [rip + _evt_pending + EVT_TIMER], 1
+    add rax, QWORD PTR [rip + _evt_timer_interval]
+    mov QWORD PTR [rip + _evt_timer_next], rax
+
+.Lpoll_scan_start:
+    # Run the lowest-numbered pending event that is ON and not already running
+    xor ecx, ecx
+.Lpoll_scan:
+    lea rax, [rip + _evt_pending]
+    cmp BYTE PTR [rax + rcx], 0
+    je .Lpoll_next
+    lea rax, [rip + _evt_state]
+    cmp BYTE PTR [rax + rcx], EVT_ON
+    jne .Lpoll_next
+    lea rax, [rip + _evt_busy]
+    cmp BYTE PTR [rax + rcx], 0
+    jne .Lpoll_next
+    lea rax, [rip + _evt_handler]
+    mov rax, QWORD PTR [rax + rcx*8]
+    test rax, rax
+    jnz .Lpoll_dispatch
+.Lpoll_next:
+    inc ecx
+    cmp ecx, EVT_SLOTS
+    jb .Lpoll_scan
+    xor eax, eax
+    leave
+    ret
+
+.Lpoll_dispatch:
+    lea rdx, [rip + _evt_pending]
+    mov BYTE PTR [rdx + rcx], 0
+    lea rdx, [rip + _evt_busy]
+    mov BYTE PTR [rdx + rcx], 1
+    mov rdx, QWORD PTR [rip + _evt_depth]
+    lea rsi, [rip + _evt_nest]
+    mov BYTE PTR [rsi + rdx], cl
+    inc rdx
+    mov QWORD PTR [rip + _evt_depth], rdx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_event_return - Release the event whose handler just RETURNed
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_event_return
+_rt_event_return:
+    mov rax, QWORD PTR [rip + _evt_depth]
+    test rax, rax
+    jz .Levent_return_done
+    dec rax
+    mov QWORD PTR [rip + _evt_depth], rax
+    lea rcx, [rip + _evt_nest]
+    movzx ecx, BYTE PTR [rcx + rax]
+    lea rax, [rip + _evt_busy]
+    mov BYTE PTR [rax + rcx], 0
+.Levent_return_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_check_key - Validate a key number (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = key number
+#
+# Returns: nothing (invalid numbers are an illegal function call)
+# ------------------------------------------------------------------------------
+_evt_check_key:
+    cmp rdi, EVT_KEY_FIRST
+    jl _rt_illegal_call
+    cmp rdi, EVT_KEY_ARROWS
+    jle .Lcheck_key_ok
+    cmp rdi, EVT_KEY_F11
+    jl _rt_illegal_call
+    cmp rdi, EVT_KEY_LAST
+    jg _rt_illegal_call
+.Lcheck_key_ok:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_set_state - Set the trap state of a slot (internal)
+# ------------------------------------------------------------------------------
+# Keeps the armed counts up to date and switches the terminal mode when key
+# trapping starts or ends.
+#
+# Arguments:
+#   rdi = slot
+#   rsi = state (EVT_OFF, EVT_ON or EVT_STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_set_state:
+    lea rax, [rip + _evt_state]
+    movzx ecx, BYTE PTR [rax + rdi]     # ecx = old state
+    mov BYTE PTR [rax + rdi], sil
+    cmp esi, EVT_OFF
+    jne .Lset_state_count
+    lea rax, [rip + _evt_pending]       # OFF forgets events seen while stopped
+    mov BYTE PTR [rax + rdi], 0
+
+.Lset_state_count:
+    # rax = (new state armed) - (old state armed)
+    xor edx, edx
+    test ecx, ecx
+    setnz dl
+    xor eax, eax
+    test esi, esi
+    setnz al
+    sub rax, rdx
+    jz .Lset_state_done
+    add QWORD PTR [rip + _evt_armed], rax
+    test rdi, rdi           # the timer and Ctrl-C are not input
+    jz .Lset_state_done
+    cmp rdi, EVT_BREAK
+    je .Lset_state_done
+    add QWORD PTR [rip + _evt_keys_armed], rax
+    cmp QWORD PTR [rip + _evt_keys_armed], 0
+    je _evt_restore_tty
+    cmp QWORD PTR [rip + _evt_tty_raw], 0
+    je _evt_raw_tty
+.Lset_state_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_timer_restart - Start a new timer interval from now (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_timer_restart:
+    push rbp
+    mov rbp, rsp
+    call _evt_now_ms
+    add rax, QWORD PTR [rip + _evt_timer_interval]
+    mov QWORD PTR [rip + _evt_timer_next], rax
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_now_ms - Read the monotonic clock (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   rax = milliseconds on CLOCK_MONOTONIC
+# ------------------------------------------------------------------------------
+_evt_now_ms:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 16             # struct timespec
+    mov edi, CLOCK_MONOTONIC
+    mov rsi, rsp
+    call {libc}clock_gettime
+    imul rcx, QWORD PTR [rsp], MS_PER_SEC
+    mov rax, QWORD PTR [rsp + 8]
+    xor edx, edx
+    mov r8d, NS_PER_MS
+    div r8
+    add rax, rcx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_raw_tty - Let keys arrive without Enter and without echo (internal)
+# ------------------------------------------------------------------------------
+# Does nothing if stdin is not a terminal.
+#
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_raw_tty:
+    push rbp
+    mov rbp, rsp
+    xor edi, edi            # isatty(0)
+    call {libc}isatty
+    test eax, eax
+    jz .Lraw_tty_done
+    xor edi, edi            # tcgetattr(0, &saved)
+    lea rsi, [rip + _evt_termios]
+    call {libc}tcgetattr
+    test eax, eax
+    jnz .Lraw_tty_done
+
+    lea rdi, [rip + _evt_raw_termios]
+    lea rsi, [rip + _evt_termios]
+    mov edx, TERMIOS_SIZE
+    call {libc}memcpy
+    lea rax, [rip + _evt_raw_termios]
+    and DWORD PTR [rax + TERMIOS_LFLAG], ~TERMIOS_RAW_BITS
+    xor edi, edi            # tcsetattr(0, TCSANOW, &raw)
+    mov esi, TCSANOW
+    lea rdx, [rip + _evt_raw_termios]
+    call {libc}tcsetattr
+    mov QWORD PTR [rip + _evt_tty_raw], 1
+
+    # Put the terminal back however the program exits
+    cmp QWORD PTR [rip + _evt_atexit_done], 0
+    jne .Lraw_tty_done
+    mov QWORD PTR [rip + _evt_atexit_done], 1
+    lea rdi, [rip + _evt_restore_tty]
+    call {libc}atexit
+.Lraw_tty_done:
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_restore_tty - Restore the terminal mode saved by _evt_raw_tty (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_restore_tty:
+    cmp QWORD PTR [rip + _evt_tty_raw], 0
+    je .Lrestore_tty_done
+    push rbp
+    mov rbp, rsp
+    mov QWORD PTR [rip + _evt_tty_raw], 0
+    xor edi, edi            # tcsetattr(0, TCSANOW, &saved)
+    mov esi, TCSANOW
+    lea rdx, [rip + _evt_termios]
+    call {libc}tcsetattr
+    leave
+.Lrestore_tty_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_read_keys - Read pending stdin input and record key events (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_read_keys:
+    cmp QWORD PTR [rip + _evt_stdin_eof], 0
+    jne .Lread_keys_ret
+    push rbp
+    mov rbp, rsp
+    lea rdi, [rip + _evt_pollfd]    # poll(&pollfd, 1, 0) - don't wait
+    mov esi, 1
+    xor edx, edx
+    call {libc}poll
+    test eax, eax
+    jle .Lread_keys_done
+    xor edi, edi            # read(0, buf, KEY_BUF_SIZE)
+    lea rsi, [rip + _evt_key_buf]
+    mov edx, KEY_BUF_SIZE
+    call {libc}read
+    test rax, rax
+    jg .Lread_keys_decode
+    mov QWORD PTR [rip + _evt_stdin_eof], 1
+    jmp .Lread_keys_done
+.Lread_keys_decode:
+    lea rdi, [rip + _evt_key_buf]
+    mov rsi, rax
+    call _evt_decode_keys
+.Lread_keys_done:
+    leave
+.Lread_keys_ret:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_decode_keys - Record the keys in a block of terminal input (internal)
+# ------------------------------------------------------------------------------
+# Recognizes ESC O P-S (F1-F4), ESC [ A-D (arrows) and ESC [ n ~ (F1-F12).
+#
+# Arguments:
+#   rdi = pointer to input
+#   rsi = length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_decode_keys:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    sub rsp, 24             # mouse report fields (dwords) + alignment
+    mov rbx, rdi            # rbx = next byte
+    lea r12, [rdi + rsi]    # r12 = end of input
+
+.Ldecode_loop:
+    cmp rbx, r12
+    jae .Ldecode_done
+    movzx eax, BYTE PTR [rbx]
+    inc rbx
+    cmp eax, CHAR_ESC
+    jne .Ldecode_loop
+    lea rax, [rbx + 1]      # need two more bytes
+    cmp rax, r12
+    jae .Ldecode_done
+    movzx eax, BYTE PTR [rbx]
+    movzx ecx, BYTE PTR [rbx + 1]
+    add rbx, 2
+    cmp eax, 'O'
+    je .Ldecode_ss3
+    cmp eax, '['
+    jne .Ldecode_loop
+    cmp ecx, '<'
+    je .Ldecode_sgr
+
+    # ESC [ A-D: arrow keys
+    sub ecx, 'A'
+    cmp ecx, 3
+    ja .Ldecode_number
+    lea rax, [rip + _evt_arrow_keys]
+    movzx edi, BYTE PTR [rax + rcx]
+    call _evt_key_hit
+    jmp .Ldecode_loop
+
+.Ldecode_number:
+    # ESC [ n ~: function keys
+    add ecx, 'A' - '0'
+    cmp ecx, 9
+    ja .Ldecode_loop
+    mov edx, ecx            # edx = n
+.Ldecode_digits:
+    cmp rbx, r12
+    jae .Ldecode_done
+    movzx ecx, BYTE PTR [rbx]
+    inc rbx
+    cmp ecx, '~'
+    je .Ldecode_tilde
+    sub ecx, '0'
+    cmp ecx, 9
+    ja .Ldecode_loop
+    imul edx, edx, 10
+    add edx, ecx
+    cmp edx, EVT_TILDE_MAX
+    jbe .Ldecode_digits
+    jmp .Ldecode_loop
+.Ldecode_tilde:
+    cmp edx, EVT_TILDE_MAX
+    ja .Ldecode_loop
+    lea rax, [rip + _evt_tilde_keys]
+    movzx edi, BYTE PTR [rax + rdx]
+    call _evt_key_hit
+    jmp .Ldecode_loop
+
+.Ldecode_ss3:
+    # ESC O P-S: F1-F4
+    sub ecx, 'P'
+    cmp ecx, 3
+    ja .Ldecode_loop
+    lea edi, [rcx + 1]
+    call _evt_key_hit
+    jmp .Ldecode_loop
+
+.Ldecode_sgr:
+    # ESC [ < b ; x ; y M/m: mouse report
+    xor r13d, r13d          # r13 = field number
+.Ldecode_sgr_field:
+    xor edx, edx            # edx = field value
+.Ldecode_sgr_digit:
+    cmp rbx, r12
+    jae .Ldecode_done
+    movzx eax, BYTE PTR [rbx]
+    inc rbx
+    lea ecx, [rax - '0']
+    cmp ecx, 9
+    ja .Ldecode_sgr_end
+    imul edx, edx, 10
+    add edx, ecx
+    cmp edx, SGR_MAX
+    jbe .Ldecode_sgr_digit
+    jmp .Ldecode_loop
+.Ldecode_sgr_end:
+    mov DWORD PTR [rbp + r13*4 - 40], edx
+    inc r13
+    cmp r13, SGR_FIELDS
+    jae .Ldecode_sgr_final
+    cmp eax, ';'
+    je .Ldecode_sgr_field
+    jmp .Ldecode_loop
+.Ldecode_sgr_final:
+    mov ecx, 1
+    cmp eax, 'M'
+    je .Ldecode_sgr_hit
+    xor ecx, ecx
+    cmp eax, 'm'
+    jne .Ldecode_loop
+.Ldecode_sgr_hit:
+    mov edi, DWORD PTR [rbp - 40]
+    mov esi, DWORD PTR [rbp - 36]
+    mov edx, DWORD PTR [rbp - 32]
+    call _evt_mouse_sgr
+    jmp .Ldecode_loop
+
+.Ldecode_done:
+    mov r13, QWORD PTR [rbp - 24]
+    mov r12, QWORD PTR [rbp - 16]
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_key_hit - Record a key press (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = key number (0 = not a trappable key)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_key_hit:
+    test edi, edi
+    jz .Lkey_hit_done
+    lea rax, [rip + _evt_state]
+    cmp BYTE PTR [rax + rdi], EVT_OFF
+    je .Lkey_hit_done
+    lea rax, [rip + _evt_pending]
+    mov BYTE PTR [rax + rdi], 1
+.Lkey_hit_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_sgr - Record an SGR mouse report (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = button code (b)
+#   rsi = column
+#   rdx = row
+#   rcx = 1 for a press or motion (M), 0 for a release (m)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_sgr:
+    mov rax, QWORD PTR [rip + _evt_mouse_b]     # rax = new buttons
+    test edi, SGR_NOT_BUTTON
+    jnz .Lmouse_sgr_hit
+    and edi, SGR_BUTTON_BITS
+    cmp edi, SGR_BUTTON_BITS
+    je .Lmouse_sgr_hit
+    lea r8, [rip + _evt_sgr_buttons]
+    movzx r8d, BYTE PTR [r8 + rdi]
+    or rax, r8
+    test ecx, ecx
+    jnz .Lmouse_sgr_hit
+    xor rax, r8             # released
+.Lmouse_sgr_hit:
+    mov rdi, rax
+    jmp _evt_mouse_hit
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_hit - Record the pointer state (internal)
+# ------------------------------------------------------------------------------
+# A button going down is a mouse event.
+#
+# Arguments:
+#   rdi = buttons held down
+#   rsi = column
+#   rdx = row
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_hit:
+    mov QWORD PTR [rip + _evt_mouse_x], rsi
+    mov QWORD PTR [rip + _evt_mouse_y], rdx
+    mov rax, QWORD PTR [rip + _evt_mouse_b]
+    mov QWORD PTR [rip + _evt_mouse_b], rdi
+    not rax
+    test rax, rdi
+    jz .Lmouse_hit_done
+    cmp BYTE PTR [rip + _evt_state + EVT_MOUSE], EVT_OFF
+    je .Lmouse_hit_done
+    mov BYTE PTR [rip + _evt_pending + EVT_MOUSE], 1
+.Lmouse_hit_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_update - Read pending input for the mouse functions (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_update:
+    cmp QWORD PTR [rip + _evt_keys_armed], 0
+    jne _evt_read_keys
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_report - Turn terminal mouse reporting on or off (internal)
+# ------------------------------------------------------------------------------
+# Does nothing if stdout is not a terminal. Turning it on the first time
+# registers _evt_mouse_off to run at exit.
+#
+# Arguments:
+#   rdi = 1 to turn reporting on, 0 to turn it off
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_report:
+    cmp rdi, QWORD PTR [rip + _evt_mouse_on]
+    je .Lmouse_report_ret
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push rbx                # alignment
+    mov rbx, rdi            # rbx = on
+    mov edi, 1              # isatty(1)
+    call {libc}isatty
+    test eax, eax
+    jz .Lmouse_report_done
+    mov QWORD PTR [rip + _evt_mouse_on], rbx
+    lea rdi, [rip + _evt_mouse_off_seq]
+    test rbx, rbx
+    jz .Lmouse_report_write
+    lea rdi, [rip + _evt_mouse_on_seq]
+.Lmouse_report_write:
+    xor eax, eax
+    call {libc}printf
+    xor edi, edi            # fflush(NULL)
+    call {libc}fflush
+    cmp QWORD PTR [rip + _evt_mouse_atexit], 0
+    jne .Lmouse_report_done
+    mov QWORD PTR [rip + _evt_mouse_atexit], 1
+    lea rdi, [rip + _evt_mouse_off]
+    call {libc}atexit
+.Lmouse_report_done:
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+.Lmouse_report_ret:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_off - Turn mouse reporting off (internal, run at exit)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_off:
+    xor edi, edi
+    jmp _evt_mouse_report
+
+# ------------------------------------------------------------------------------
+# _rt_stick - Joystick position (STICK)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = 0 (A x), 1 (A y), 2 (B x) or 3 (B y)
+#
+# Returns:
+#   rax = position, 1-200
+# ------------------------------------------------------------------------------
+.globl _rt_stick
+_rt_stick:
+    cmp rdi, JOY_AXES - 1
+    ja _rt_illegal_call     # (unsigned, so negative numbers fail too)
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push rbx                # alignment
+    mov rbx, rdi            # rbx = axis
+    call _joy_update
+    lea rax, [rip + _joy_axes]
+    mov rax, QWORD PTR [rax + rbx*8]
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_strig - Joystick button (STRIG)
+# ------------------------------------------------------------------------------
+# Even numbers ask whether the button was pressed since the last time they
+# were asked, odd numbers whether it is down now:
+#   0, 1 = A1   2, 3 = B1   4, 5 = A2   6, 7 = B2
+#
+# Arguments:
+#   rdi = 0-7
+#
+# Returns:
+#   rax = -1 if so, 0 if not
+# ------------------------------------------------------------------------------
+.globl _rt_strig
+_rt_strig:
+    cmp rdi, JOY_STRIG_LAST
+    ja _rt_illegal_call
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push rbx                # alignment
+    mov rbx, rdi            # rbx = STRIG number
+    call _joy_update
+    mov ecx, ebx
+    shr ecx, 1
+    mov edx, 1
+    shl edx, cl             # rdx = button bit
+    xor eax, eax
+    test ebx, 1
+    jnz .Lstrig_down
+    mov rcx, QWORD PTR [rip + _joy_pressed]
+    test rcx, rdx
+    jz .Lstrig_done
+    not rdx                 # reported: forget the press
+    and QWORD PTR [rip + _joy_pressed], rdx
+    dec rax
+    jmp .Lstrig_done
+.Lstrig_down:
+    mov rcx, QWORD PTR [rip + _joy_down]
+    test rcx, rdx
+    jz .Lstrig_done
+    dec rax
+.Lstrig_done:
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _joy_update - Read pending joystick events (internal)
+# ------------------------------------------------------------------------------
+# Opens the device on first use. A device that fails to read (unplugged) is
+# closed and treated as missing from then on.
+#
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_joy_update:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push rbx                # alignment
+    mov rbx, QWORD PTR [rip + _joy_fd]
+    cmp rbx, JOY_CLOSED
+    jne .Ljoy_opened
+    # Device: $XBASIC64_JOYSTICK or the default
+    lea rdi, [rip + _joy_env_name]
+    call {libc}getenv
+    test rax, rax
+    jnz .Ljoy_open
+    lea rax, [rip + _joy_default_path]
+.Ljoy_open:
+    mov rdi, rax            # open(path, O_RDONLY)
+    xor esi, esi
+    xor eax, eax
+    call {libc}open
+    movsxd rbx, eax
+    mov QWORD PTR [rip + _joy_fd], rbx
+    mov DWORD PTR [rip + _joy_pollfd], ebx
+.Ljoy_opened:
+    test rbx, rbx
+    js .Ljoy_done
+
+.Ljoy_read:
+    lea rdi, [rip + _joy_pollfd]    # poll(&pollfd, 1, 0) - don't wait
+    mov esi, 1
+    xor edx, edx
+    call {libc}poll
+    test eax, eax
+    jle .Ljoy_done
+    mov rdi, rbx            # read(fd, &event, JS_EVENT_SIZE)
+    lea rsi, [rip + _joy_event]
+    mov edx, JS_EVENT_SIZE
+    call {libc}read
+    cmp rax, JS_EVENT_SIZE
+    jne .Ljoy_lost
+
+    lea rax, [rip + _joy_event]
+    movsx edx, WORD PTR [rax + JS_VALUE]
+    movzx ecx, BYTE PTR [rax + JS_NUMBER]
+    movzx eax, BYTE PTR [rax + JS_TYPE]
+    and eax, ~JS_EVENT_INIT
+    cmp eax, JS_EVENT_AXIS
+    je .Ljoy_axis
+    cmp eax, JS_EVENT_BUTTON
+    jne .Ljoy_read
+    cmp ecx, JOY_BUTTONS
+    jae .Ljoy_read
+    mov eax, 1
+    shl eax, cl             # rax = button bit
+    test edx, edx
+    jz .Ljoy_release
+    or QWORD PTR [rip + _joy_down], rax
+    or QWORD PTR [rip + _joy_pressed], rax
+    jmp .Ljoy_read
+.Ljoy_release:
+    not rax
+    and QWORD PTR [rip + _joy_down], rax
+    jmp .Ljoy_read
+
+.Ljoy_axis:
+    # -32768..32767 -> 1..200
+    cmp ecx, JOY_AXES
+    jae .Ljoy_read
+    lea eax, [rdx + 32768]
+    imul eax, eax, JOY_RANGE
+    xor edx, edx
+    mov r8d, 65535
+    div r8d
+    inc eax
+    lea rdx, [rip + _joy_axes]
+    mov QWORD PTR [rdx + rcx*8], rax
+    jmp .Ljoy_read
+
+.Ljoy_lost:
+    mov rdi, rbx
+    call {libc}close
+    mov QWORD PTR [rip + _joy_fd], -1
+.Ljoy_done:
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
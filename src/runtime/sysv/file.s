@@ -3,7 +3,7 @@
 # ==============================================================================
 #
 # File input/output functions implementing BASIC's OPEN, CLOSE, PRINT#, INPUT#
-# statements. Uses libc file operations (fopen, fclose, fprintf, fscanf, fgets).
+# statements. Uses libc file operations (fopen, fclose, fwrite, fgets).
 #
 # BASIC File I/O Model:
 #   Files are referenced by number (1-15). The OPEN statement associates a
@@ -16,24 +16,112 @@
 # File Handle Table:
 #   _file_handles is an array of 16 FILE* pointers (128 bytes).
 #   Index 0 is unused (BASIC file numbers start at 1).
-#   Handles 1-15 are available for user files.
+#   Handles 1-15 are available for user files. A NULL entry is a number
+#   with no open file (never opened, closed, or one whose fopen for OUTPUT
+#   or APPEND, or connection, failed):
+#   OPEN raises "File already open" unless the entry is NULL, and every
+#   other statement, CLOSE included, raises "Bad file number" if it is.
 #
 # File Modes:
-#   0 = INPUT  - read existing file (fopen "r")
+#   0 = INPUT  - read existing file (fopen "r"); "File not found" if it
+#                isn't there
 #   1 = OUTPUT - create/truncate file (fopen "w")
 #   2 = APPEND - append to file (fopen "a")
+#   3 = both ways (OPEN with no FOR) - only for TCP connections
+#   _file_written[n] marks file n as opened FOR OUTPUT or APPEND (and not a
+#   TCP connection, which reads either way), so INPUT # and LINE INPUT # on
+#   it raise "Bad file mode".
+#
+# Device Names:
+#   "SCRN:" (output), "KYBD:" (input) and "CONS:" (either), in any case,
+#   open the console instead of a file. Their handle is FILE_CONSOLE, which
+#   the output engine writes like PRINT and INPUT # reads like INPUT, from
+#   the same stdio buffers. "STDERR:" (output) opens a duplicate of file
+#   descriptor 2, unbuffered. Opening a device the wrong way raises "Bad
+#   file mode".
+#
+# Pipes:
+#   "PIPE:command" runs the command with popen: FOR INPUT reads its output,
+#   FOR OUTPUT or APPEND feeds its input. _file_pipes[n] marks file n as a
+#   pipe, so CLOSE waits for the command with pclose. All stdio output is
+#   flushed before the command starts and before it is waited for, so its
+#   output stays in order with the program's.
+#
+# TCP Connections:
+#   "TCP:host:port" connects to a server, trying each address getaddrinfo
+#   finds, and "TCPL:port" listens on the port (IPv4) and waits in OPEN for
+#   one client to connect. Either way the socket becomes an unbuffered
+#   FILE* (fdopen "r+"), read and written like a file in any mode, which
+#   CLOSE closes. SIGPIPE is ignored once a connection is open, so writing
+#   to one the other end closed does nothing.
+#
+# Buffering:
+#   Files opened for OUTPUT or APPEND get a FILE_BUF_SIZE stdio buffer,
+#   written out when it fills, by FLUSH # and CLOSE, before a PIPE: command
+#   starts or is waited for, and when the program ends (exit, which runtime
+#   errors end with too, flushes every stream). Pipes a program writes to
+#   are line buffered and connections unbuffered, so whoever is on the
+#   other end sees every line as soon as it is finished.
+#
+# Locking:
+#   LOCK # and UNLOCK # take an fcntl advisory lock on the whole file (the
+#   files are sequential, so a record range is only checked), shared for a
+#   file opened FOR INPUT and exclusive otherwise. A lock another process
+#   holds raises "Permission denied"; UNLOCK writes out the file's buffer
+#   first. On the console devices and pipes they do nothing.
+#
+# Positions:
+#   SEEK # moves a disk file to a byte position (1 is the first byte) with
+#   fseek, which writes out what is buffered, and drops any partly read
+#   INPUT # line. SEEK() is the position the next read or write uses:
+#   ftell, less the part of the current line INPUT # hasn't used, which is
+#   why _file_line_len[n] keeps how many bytes the line took in the file.
+#   Devices, pipes and connections have no position ("Bad file mode").
 #
 # String Handling:
 #   BASIC strings are (ptr, len) pairs but libc expects null-terminated strings.
 #   For filenames, we copy to _file_name_buf and null-terminate.
-#   For string output, we use fprintf with "%.*s" (precision = length).
-#   For string input, fgets reads into _file_input_buf.
+#   Output (PRINT#) goes through the output channel engine in print.s, which
+#   writes with fwrite and tracks the column for WIDTH #.
+#   INPUT # reads a line at a time with fgets into the file's slot in
+#   _file_lines and splits it into comma-separated fields with _in_next_field
+#   (input.s), just like INPUT. _file_line_pos[n] points at the next field of
+#   file n's line, or is 0 when the next read needs a new line.
 #
 # Error Handling:
-#   Currently minimal - fopen failure results in NULL handle, which will
-#   cause subsequent operations to fail silently or crash.
+#   A file number outside 1-15, or one with no open file, raises "Bad file
+#   number" (_rt_bad_file). An fopen failure leaves the handle NULL, so the
+#   file's first use raises it.
 # ==============================================================================
 
+# Devices, as _file_device finds them
+.equ DEV_SCRN, 1
+.equ DEV_KYBD, 2
+.equ DEV_CONS, 3
+.equ DEV_STDERR, 4
+.equ IOFBF, 0               # setvbuf modes (glibc and macOS): buffered,
+.equ IOLBF, 1               # line buffered
+.equ IONBF, 2               # and unbuffered
+.equ FILE_BUF_SIZE, 8192    # stdio buffer of each file written to
+
+# fcntl locks (the same on Linux and macOS; F_SETLK, F_RDLCK, F_WRLCK and
+# FLOCK_TYPE differ and come from the runtime prelude)
+.equ F_GETFL, 3
+.equ O_ACCMODE, 3
+.equ O_RDONLY, 0
+.equ F_UNLCK, 2
+.equ FLOCK_SIZE, 32         # struct flock, rounded up (24 bytes on macOS)
+.equ MODE_BOTH, 3           # OPEN with no FOR
+
+# Sockets (the same on Linux and macOS; ADDRINFO_ADDR, SOL_SOCKET and
+# SO_REUSEADDR differ and come from the runtime prelude)
+.equ AF_INET, 2
+.equ SOCK_STREAM, 1
+.equ AI_PASSIVE, 1
+.equ ADDRINFO_NEXT, 40      # offset of ai_next in struct addrinfo
+.equ SIGPIPE, 13
+.equ SIG_IGN, 1
+
 # ------------------------------------------------------------------------------
 # Data Section: File handle table and buffers
 # ------------------------------------------------------------------------------
@@ -47,19 +135,36 @@ _mode_read:   .asciz "r"        # FOR INPUT
 _mode_write:  .asciz "w"        # FOR OUTPUT
 _mode_append: .asciz "a"        # FOR APPEND
 
+# Pipe files: "PIPE:" prefix, and a flag byte per file number
+_file_pipe_prefix: .asciz "PIPE:"
+.equ PIPE_PREFIX_LEN, 5
+_file_pipes: .skip 16
+
+# A flag byte per file number: opened FOR OUTPUT or APPEND, so not readable
+_file_written: .skip 16
+
+# TCP files: "TCP:" and "TCPL:" prefixes, and fdopen's mode for sockets
+_file_tcp_prefix: .asciz "TCP:"
+.equ TCP_PREFIX_LEN, 4
+_file_tcpl_prefix: .asciz "TCPL:"
+.equ TCPL_PREFIX_LEN, 5
+_mode_update: .asciz "r+"
+
 # Temp buffer for null-terminated filename (BASIC strings aren't null-terminated)
 _file_name_buf: .skip 1024
 
-# Format strings for fprintf/fscanf (same as console I/O)
-_file_fmt_str:     .asciz "%.*s"    # String with precision (ptr, len)
-_file_fmt_int:     .asciz "%ld"     # Long integer
-_file_fmt_float:   .asciz "%g"      # Floating point (compact)
-_file_fmt_char:    .asciz "%c"      # Single character
-_file_fmt_newline: .asciz "\n"      # Newline
-_file_fmt_input:   .asciz "%lf"     # Read double
+# Device names, in DEV_* order, ending with an empty one
+_file_devices: .asciz "SCRN:", "KYBD:", "CONS:", "STDERR:", ""
+_file_bad_mode_msg: .asciz "Bad file mode"
+_file_not_found_msg: .asciz "File not found"
+_file_open_msg: .asciz "File already open"
+_file_locked_msg: .asciz "Permission denied"
 
-# Buffer for string input from files
-_file_input_buf: .skip 1024
+# INPUT # line buffers, 1024 bytes per file, the next field in each, and
+# the bytes each line took in the file, newline included
+_file_lines: .skip 16 * 1024
+_file_line_pos: .skip 128
+_file_line_len: .skip 128
 
 .text
 
@@ -78,12 +183,21 @@ _file_input_buf: .skip 1024
 #
 # Implementation:
 #   1. Copy filename to _file_name_buf and null-terminate
-#   2. Select mode string based on mode argument
-#   3. Call fopen(filename, mode)
-#   4. Store resulting FILE* in handle table
+#   2. Open a device name as the console or standard error, run a "PIPE:"
+#      command with popen, and open a "TCP:" or "TCPL:" connection
+#   3. Otherwise select mode string based on mode argument
+#   4. Call fopen(filename, mode)
+#   5. Store resulting FILE* in handle table
 # ------------------------------------------------------------------------------
 .globl _rt_file_open
 _rt_file_open:
+    lea eax, [rcx - 1]      # file numbers are 1-15
+    cmp eax, 14
+    ja _rt_bad_file
+    mov eax, ecx            # and must be CLOSEd before they are reused
+    lea r11, [rip + _file_handles]
+    cmp QWORD PTR [r11 + rax*8], 0
+    jne _file_already_open
     push rbp
     mov rbp, rsp
     push rbx
@@ -106,6 +220,40 @@ _rt_file_open:
     # Null-terminate
     lea rax, [rip + _file_name_buf]
     mov BYTE PTR [rax + r13], 0
+    lea rax, [rip + _file_pipes]
+    mov BYTE PTR [rax + rbx], 0
+    # Mode 1 or 2: written to, not read
+    lea eax, [r14 - 1]
+    cmp eax, 1
+    setbe cl
+    lea rax, [rip + _file_written]
+    mov BYTE PTR [rax + rbx], cl
+
+    call _file_device
+    test eax, eax
+    jnz .Lopen_device
+
+    # strncasecmp(name, prefix, length) for "TCP:", "TCPL:" and "PIPE:"
+    lea rdi, [rip + _file_name_buf]
+    lea rsi, [rip + _file_tcp_prefix]
+    mov edx, TCP_PREFIX_LEN
+    call {libc}strncasecmp
+    test eax, eax
+    jz .Lopen_tcp
+    lea rdi, [rip + _file_name_buf]
+    lea rsi, [rip + _file_tcpl_prefix]
+    mov edx, TCPL_PREFIX_LEN
+    call {libc}strncasecmp
+    test eax, eax
+    jz .Lopen_tcpl
+    cmp r14d, MODE_BOTH     # only TCP connections go both ways
+    je .Lopen_bad_mode
+    lea rdi, [rip + _file_name_buf]
+    lea rsi, [rip + _file_pipe_prefix]
+    mov edx, PIPE_PREFIX_LEN
+    call {libc}strncasecmp
+    test eax, eax
+    jz .Lopen_pipe
 
     # Select mode string based on mode argument
     cmp r14d, 0
@@ -125,11 +273,39 @@ _rt_file_open:
     # fopen(filename, mode)
     lea rdi, [rip + _file_name_buf]
     call {libc}fopen        # returns FILE* in rax (or NULL on error)
+    test r14d, r14d
+    jnz .Lopen_buffered
+    # A file to read must be there
+    test rax, rax
+    jnz .Lopen_store
+    lea rdi, [rip + _file_not_found_msg]
+    jmp _rt_error
+.Lopen_buffered:
+    # setvbuf(file, NULL, IOFBF, FILE_BUF_SIZE) for a file written to
+    mov edx, IOFBF
+    mov ecx, FILE_BUF_SIZE
+.Lopen_setvbuf:
+    test rax, rax
+    jz .Lopen_store
+    mov r12, rax
+    mov rdi, rax
+    xor esi, esi
+    call {libc}setvbuf
+    mov rax, r12
 
+.Lopen_store:
     # Store FILE* in handle table: _file_handles[file_number] = rax
     lea rcx, [rip + _file_handles]
     mov [rcx + rbx*8], rax
 
+    # New files start at column 0 with no line width limit, and no line read
+    lea rcx, [rip + _file_line_pos]
+    mov QWORD PTR [rcx + rbx*8], 0
+    lea rcx, [rip + _out_col]
+    mov QWORD PTR [rcx + rbx*8], 0
+    lea rcx, [rip + _out_width]
+    mov QWORD PTR [rcx + rbx*8], OUT_WIDTH_INFINITE
+
     pop r14
     pop r13
     pop r12
@@ -137,6 +313,286 @@ _rt_file_open:
     leave
     ret
 
+.Lopen_device:
+    # KYBD: only reads, SCRN: and STDERR: only write, CONS: does either
+    cmp r14d, MODE_BOTH
+    je .Lopen_bad_mode
+    cmp eax, DEV_CONS
+    je .Lopen_console
+    cmp eax, DEV_KYBD
+    sete cl
+    test r14d, r14d         # mode 0 = INPUT
+    sete dl
+    cmp cl, dl
+    jne .Lopen_bad_mode
+    cmp eax, DEV_STDERR
+    je .Lopen_stderr
+.Lopen_console:
+    mov eax, FILE_CONSOLE
+    jmp .Lopen_store
+.Lopen_stderr:
+    # fdopen(dup(2), "w"), unbuffered so it keeps pace with the console;
+    # CLOSE closes the duplicate
+    mov edi, 2
+    call {libc}dup
+    mov edi, eax
+    lea rsi, [rip + _mode_write]
+.Lopen_fdopen:
+    # fdopen(fd, mode), unbuffered
+    call {libc}fdopen
+    mov edx, IONBF
+    xor ecx, ecx
+    jmp .Lopen_setvbuf
+.Lopen_bad_mode:
+    lea rdi, [rip + _file_bad_mode_msg]
+    jmp _rt_error
+.Lopen_pipe:
+    # popen(command, "r") for INPUT, "w" for OUTPUT and APPEND, after
+    # flushing everything written so far
+    xor edi, edi
+    call {libc}fflush
+    lea rsi, [rip + _mode_read]
+    test r14d, r14d
+    jz .Lopen_popen
+    lea rsi, [rip + _mode_write]
+.Lopen_popen:
+    lea rdi, [rip + _file_name_buf]
+    add rdi, PIPE_PREFIX_LEN
+    call {libc}popen
+    test rax, rax
+    jz .Lopen_store
+    lea rcx, [rip + _file_pipes]
+    mov BYTE PTR [rcx + rbx], 1
+    test r14d, r14d
+    jz .Lopen_store
+    # setvbuf(pipe, NULL, IOLBF, 0) for a command's input
+    mov edx, IOLBF
+    xor ecx, ecx
+    jmp .Lopen_setvbuf
+.Lopen_tcp:
+    call _file_tcp_connect
+    jmp .Lopen_socket
+.Lopen_tcpl:
+    call _file_tcp_listen
+.Lopen_socket:
+    # Connections read in any mode
+    lea rcx, [rip + _file_written]
+    mov BYTE PTR [rcx + rbx], 0
+    # A failed connection leaves the handle NULL, like a failed fopen
+    test eax, eax
+    js .Lopen_no_socket
+    mov r12d, eax
+    mov edi, SIGPIPE
+    mov esi, SIG_IGN
+    call {libc}signal
+    mov edi, r12d
+    lea rsi, [rip + _mode_update]
+    jmp .Lopen_fdopen
+.Lopen_no_socket:
+    xor eax, eax
+    jmp .Lopen_store
+
+# ------------------------------------------------------------------------------
+# _file_tcp_connect - Connect to "TCP:host:port" (internal)
+# ------------------------------------------------------------------------------
+# Splits the name in _file_name_buf at its last colon and tries each
+# address getaddrinfo finds for the host and port until one connects.
+#
+# Arguments: none (the name is in _file_name_buf)
+#
+# Returns:
+#   eax = the connected socket, or -1
+# ------------------------------------------------------------------------------
+_file_tcp_connect:
+    push rbx
+    push r12
+    push r13
+    sub rsp, 64             # hints (48 bytes), result list, alignment
+    mov r13d, -1            # r13 = socket
+
+    # strrchr(name, ':') ends the host; the port follows
+    lea rdi, [rip + _file_name_buf]
+    mov esi, ':'
+    call {libc}strrchr
+    mov BYTE PTR [rax], 0
+    lea r12, [rax + 1]
+
+    # getaddrinfo(host, port, &hints, &list), hints = any family, stream
+    mov QWORD PTR [rsp], 0
+    mov QWORD PTR [rsp + 8], 0
+    mov QWORD PTR [rsp + 16], 0
+    mov QWORD PTR [rsp + 24], 0
+    mov QWORD PTR [rsp + 32], 0
+    mov QWORD PTR [rsp + 40], 0
+    mov DWORD PTR [rsp + 8], SOCK_STREAM
+    lea rdi, [rip + _file_name_buf]
+    add rdi, TCP_PREFIX_LEN
+    mov rsi, r12
+    mov rdx, rsp
+    lea rcx, [rsp + 48]
+    call {libc}getaddrinfo
+    test eax, eax
+    jnz .Ltcp_connect_done
+    mov rbx, [rsp + 48]     # rbx = address to try
+.Ltcp_connect_next:
+    test rbx, rbx
+    jz .Ltcp_connect_free
+    # socket(ai_family, ai_socktype, ai_protocol)
+    mov edi, DWORD PTR [rbx + 4]
+    mov esi, DWORD PTR [rbx + 8]
+    mov edx, DWORD PTR [rbx + 12]
+    call {libc}socket
+    mov r13d, eax
+    test eax, eax
+    js .Ltcp_connect_skip
+    # connect(socket, ai_addr, ai_addrlen)
+    mov edi, eax
+    mov rsi, [rbx + ADDRINFO_ADDR]
+    mov edx, DWORD PTR [rbx + 16]
+    call {libc}connect
+    test eax, eax
+    jz .Ltcp_connect_free
+    mov edi, r13d
+    call {libc}close
+    mov r13d, -1
+.Ltcp_connect_skip:
+    mov rbx, [rbx + ADDRINFO_NEXT]
+    jmp .Ltcp_connect_next
+.Ltcp_connect_free:
+    mov rdi, [rsp + 48]
+    call {libc}freeaddrinfo
+.Ltcp_connect_done:
+    mov eax, r13d
+    add rsp, 64
+    pop r13
+    pop r12
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _file_tcp_listen - Wait for a connection on "TCPL:port" (internal)
+# ------------------------------------------------------------------------------
+# Listens on the port on every IPv4 address, accepts one connection and
+# closes the listening socket.
+#
+# Arguments: none (the name is in _file_name_buf)
+#
+# Returns:
+#   eax = the connected socket, or -1
+# ------------------------------------------------------------------------------
+_file_tcp_listen:
+    push rbx
+    push r12
+    push r13
+    sub rsp, 64             # hints (48 bytes), result list, option value
+    mov r13d, -1            # r13 = connected socket
+
+    # getaddrinfo(NULL, port, &hints, &list), hints = passive IPv4 stream
+    mov QWORD PTR [rsp], 0
+    mov QWORD PTR [rsp + 8], 0
+    mov QWORD PTR [rsp + 16], 0
+    mov QWORD PTR [rsp + 24], 0
+    mov QWORD PTR [rsp + 32], 0
+    mov QWORD PTR [rsp + 40], 0
+    mov DWORD PTR [rsp], AI_PASSIVE
+    mov DWORD PTR [rsp + 4], AF_INET
+    mov DWORD PTR [rsp + 8], SOCK_STREAM
+    xor edi, edi
+    lea rsi, [rip + _file_name_buf]
+    add rsi, TCPL_PREFIX_LEN
+    mov rdx, rsp
+    lea rcx, [rsp + 48]
+    call {libc}getaddrinfo
+    test eax, eax
+    jnz .Ltcp_listen_done
+    mov rbx, [rsp + 48]
+
+    # socket(ai_family, ai_socktype, ai_protocol)
+    mov edi, DWORD PTR [rbx + 4]
+    mov esi, DWORD PTR [rbx + 8]
+    mov edx, DWORD PTR [rbx + 12]
+    call {libc}socket
+    mov r12d, eax           # r12 = listening socket
+    test eax, eax
+    js .Ltcp_listen_free
+    # setsockopt(socket, SOL_SOCKET, SO_REUSEADDR, &1, 4), so a program
+    # can listen again right after a connection closes
+    mov DWORD PTR [rsp + 56], 1
+    mov edi, r12d
+    mov esi, SOL_SOCKET
+    mov edx, SO_REUSEADDR
+    lea rcx, [rsp + 56]
+    mov r8d, 4
+    call {libc}setsockopt
+    # bind(socket, ai_addr, ai_addrlen), listen(socket, 1)
+    mov edi, r12d
+    mov rsi, [rbx + ADDRINFO_ADDR]
+    mov edx, DWORD PTR [rbx + 16]
+    call {libc}bind
+    test eax, eax
+    jnz .Ltcp_listen_close
+    mov edi, r12d
+    mov esi, 1
+    call {libc}listen
+    test eax, eax
+    jnz .Ltcp_listen_close
+    # accept(socket, NULL, NULL)
+    mov edi, r12d
+    xor esi, esi
+    xor edx, edx
+    call {libc}accept
+    mov r13d, eax
+.Ltcp_listen_close:
+    mov edi, r12d
+    call {libc}close
+.Ltcp_listen_free:
+    mov rdi, [rsp + 48]
+    call {libc}freeaddrinfo
+.Ltcp_listen_done:
+    mov eax, r13d
+    add rsp, 64
+    pop r13
+    pop r12
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _file_device - Which device a file name names (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none (the name is in _file_name_buf)
+#
+# Returns:
+#   eax = DEV_SCRN, DEV_KYBD, DEV_CONS or DEV_STDERR, or 0 for a file
+# ------------------------------------------------------------------------------
+_file_device:
+    push rbx
+    push r12
+    sub rsp, 8              # Alignment
+    lea rbx, [rip + _file_devices]
+    mov r12d, 1             # r12 = device of the name at rbx
+.Lfile_device_next:
+    cmp BYTE PTR [rbx], 0
+    je .Lfile_device_none
+    # strcasecmp(name, device)
+    lea rdi, [rip + _file_name_buf]
+    mov rsi, rbx
+    call {libc}strcasecmp
+    test eax, eax
+    jz .Lfile_device_found
+    mov rdi, rbx
+    call {libc}strlen
+    lea rbx, [rbx + rax + 1]
+    inc r12d
+    jmp .Lfile_device_next
+.Lfile_device_none:
+    xor r12d, r12d
+.Lfile_device_found:
+    mov eax, r12d
+    add rsp, 8
+    pop r12
+    pop rbx
+    ret
+
 # ------------------------------------------------------------------------------
 # _rt_file_close - Close a file (CLOSE statement)
 # ------------------------------------------------------------------------------
@@ -145,10 +601,11 @@ _rt_file_open:
 # Arguments:
 #   rdi = file number (1-15)
 #
-# Returns: nothing
+# Returns: nothing ("Bad file number" if no file is open on the number)
 # ------------------------------------------------------------------------------
 .globl _rt_file_close
 _rt_file_close:
+    call _file_check
     push rbp
     mov rbp, rsp
     push rbx
@@ -159,8 +616,11 @@ _rt_file_close:
     # Get FILE* from handle table
     lea rax, [rip + _file_handles]
     mov rdi, [rax + rbx*8]  # rdi = FILE*
-    test rdi, rdi           # Check for NULL (already closed or never opened)
-    jz .Lclose_done
+    cmp rdi, FILE_CONSOLE   # the console stays open
+    je .Lclose_clear
+    lea rax, [rip + _file_pipes]
+    cmp BYTE PTR [rax + rbx], 0
+    jne .Lclose_pipe
 
     # Flush before close
     call {libc}fflush
@@ -169,134 +629,306 @@ _rt_file_close:
     lea rax, [rip + _file_handles]
     mov rdi, [rax + rbx*8]  # rdi = FILE*
     call {libc}fclose
+    jmp .Lclose_clear
 
-    # Clear handle from table
+.Lclose_pipe:
+    # Flush everything, so the command sees all its input and the program's
+    # output comes before what the command prints; then wait for it
+    xor edi, edi
+    call {libc}fflush
+    lea rax, [rip + _file_handles]
+    mov rdi, [rax + rbx*8]
+    call {libc}pclose
+    lea rax, [rip + _file_pipes]
+    mov BYTE PTR [rax + rbx], 0
+
+.Lclose_clear:
+    # Clear handle from table, dropping any partly read line
     lea rax, [rip + _file_handles]
     mov QWORD PTR [rax + rbx*8], 0
+    lea rax, [rip + _file_line_pos]
+    mov QWORD PTR [rax + rbx*8], 0
 
-.Lclose_done:
     add rsp, 8
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_print_string - Write string to file (PRINT# with string)
+# _rt_file_flush - Write out what is waiting in a file's buffer (FLUSH #)
 # ------------------------------------------------------------------------------
 # Arguments:
 #   rdi = file number
-#   rsi = string pointer
-#   rdx = string length
 #
 # Returns: nothing
 # ------------------------------------------------------------------------------
-.globl _rt_file_print_string
-_rt_file_print_string:
+.globl _rt_file_flush
+_rt_file_flush:
+    call _file_check
     push rbp
     mov rbp, rsp
-    push rbx
-    sub rsp, 8              # Align stack to 16 bytes
-
-    mov ebx, edi            # save file number
-    mov rcx, rsi            # string ptr → 4th arg (for %.*s format)
-    mov r8, rdx             # string len → will become 3rd arg
-
-    # Get FILE* from handle table
     lea rax, [rip + _file_handles]
-    mov rdi, [rax + rbx*8]  # FILE* → 1st arg
-
-    # fprintf(file, "%.*s", len, ptr)
-    lea rsi, [rip + _file_fmt_str]  # format → 2nd arg
-    mov rdx, r8             # len (precision for %.*s) → 3rd arg
-    # rcx already has ptr    → 4th arg
-    xor eax, eax            # no vector args
-    call {libc}fprintf
-
-    add rsp, 8
-    pop rbx
+    mov rdi, QWORD PTR [rax + rdi*8]
+    cmp rdi, FILE_CONSOLE
+    jne .Lflush_file
+    xor edi, edi            # the console devices share stdout: fflush(NULL)
+.Lflush_file:
+    call {libc}fflush
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_print_float - Write number to file (PRINT# with number)
+# _rt_file_seek - Move a file to a byte position (SEEK # statement)
 # ------------------------------------------------------------------------------
-# Like _rt_print_float, prints whole numbers as integers for clean output.
-#
 # Arguments:
 #   rdi = file number
-#   xmm0 = value to write (double)
+#   rsi = position (1 = the first byte)
 #
 # Returns: nothing
 # ------------------------------------------------------------------------------
-.globl _rt_file_print_float
-_rt_file_print_float:
+.globl _rt_file_seek
+_rt_file_seek:
+    call _file_check
+    cmp rsi, 1
+    jl _rt_illegal_call
     push rbp
     mov rbp, rsp
     push rbx
-    sub rsp, 8
-
-    mov ebx, edi            # save file number
-
-    # Check if value is a whole number
-    cvttsd2si rax, xmm0     # truncate to integer
-    cvtsi2sd xmm1, rax      # convert back
-    ucomisd xmm0, xmm1      # compare
-    jne .Lfile_print_as_float
-
-    # Print as integer (cleaner output)
+    push r12
+    mov ebx, edi
     lea rax, [rip + _file_handles]
-    mov rdi, [rax + rbx*8]  # FILE*
-    lea rsi, [rip + _file_fmt_int]
-    cvttsd2si rdx, xmm0     # integer value
-    xor eax, eax
-    call {libc}fprintf
-    jmp .Lfile_print_float_done
+    mov rdi, QWORD PTR [rax + rbx*8]
+    cmp rdi, FILE_CONSOLE
+    je _file_bad_mode
+    # fseek(file, position - 1, SEEK_SET); pipes and sockets fail
+    lea rsi, [rsi - 1]
+    xor edx, edx
+    call {libc}fseek
+    test eax, eax
+    jnz _file_bad_mode
+    # The next INPUT # reads from the new position
+    lea rax, [rip + _file_line_pos]
+    mov QWORD PTR [rax + rbx*8], 0
+    pop r12
+    pop rbx
+    leave
+    ret
 
-.Lfile_print_as_float:
-    # Print as floating point
+# ------------------------------------------------------------------------------
+# _rt_file_seek_pos - Position of a file's next read or write (SEEK function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#
+# Returns:
+#   rax = position (1 = the first byte)
+# ------------------------------------------------------------------------------
+.globl _rt_file_seek_pos
+_rt_file_seek_pos:
+    call _file_check
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    mov ebx, edi
     lea rax, [rip + _file_handles]
-    mov rdi, [rax + rbx*8]  # FILE*
-    lea rsi, [rip + _file_fmt_float]
-    mov eax, 1              # 1 vector register arg
-    call {libc}fprintf
-
-.Lfile_print_float_done:
-    add rsp, 8
+    mov rdi, QWORD PTR [rax + rbx*8]
+    cmp rdi, FILE_CONSOLE
+    je _file_bad_mode
+    call {libc}ftell
+    test rax, rax
+    js _file_bad_mode
+    # Back up over what INPUT # hasn't used of the current line:
+    # position - line length + (next field - line start)
+    lea rcx, [rip + _file_line_pos]
+    mov rdx, QWORD PTR [rcx + rbx*8]
+    test rdx, rdx
+    jz .Lseek_pos_done
+    lea rcx, [rip + _file_line_len]
+    sub rax, QWORD PTR [rcx + rbx*8]
+    add rax, rdx
+    mov rcx, rbx
+    shl rcx, 10
+    lea rdx, [rip + _file_lines]
+    add rcx, rdx
+    sub rax, rcx
+.Lseek_pos_done:
+    inc rax
+    pop r12
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_print_char - Write single character to file
+# _rt_file_lock - Lock a file against other processes (LOCK # statement)
 # ------------------------------------------------------------------------------
 # Arguments:
 #   rdi = file number
-#   rsi = character code
+#   rsi = first record
+#   rdx = last record
+#   rcx = 1 if a record range was given, else 0
 #
+# Returns: nothing ("Permission denied" if another process holds a lock)
+# ------------------------------------------------------------------------------
+.globl _rt_file_lock
+_rt_file_lock:
+    mov r8d, 1
+    jmp _file_lock
+
+# ------------------------------------------------------------------------------
+# _rt_file_unlock - Release a file's lock (UNLOCK # statement)
+# ------------------------------------------------------------------------------
+# Arguments: as _rt_file_lock
 # Returns: nothing
 # ------------------------------------------------------------------------------
-.globl _rt_file_print_char
-_rt_file_print_char:
+.globl _rt_file_unlock
+_rt_file_unlock:
+    xor r8d, r8d
+    jmp _file_lock
+
+# ------------------------------------------------------------------------------
+# _file_lock - Lock or unlock a file (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi-rcx = as _rt_file_lock
+#   r8 = 1 to lock, 0 to unlock
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_file_lock:
+    call _file_check
+    # Records are numbered from 1, and a range can't end before it starts
+    test rcx, rcx
+    jz .Llock_file
+    cmp rsi, 1
+    jl _rt_illegal_call
+    cmp rdx, rsi
+    jl _rt_illegal_call
+.Llock_file:
     push rbp
     mov rbp, rsp
     push rbx
     push r12
-
-    mov ebx, edi            # save file number
-    mov r12d, esi           # save char
-
+    sub rsp, FLOCK_SIZE
+    mov r12d, r8d           # r12 = 1 to lock
+    lea rax, [rip + _file_pipes]
+    cmp BYTE PTR [rax + rdi], 0
+    jne .Llock_done
     lea rax, [rip + _file_handles]
-    mov rdi, [rax + rbx*8]  # FILE*
-    lea rsi, [rip + _file_fmt_char]
-    mov rdx, r12            # char
+    mov rbx, QWORD PTR [rax + rdi*8]    # rbx = FILE*
+    cmp rbx, FILE_CONSOLE
+    je .Llock_done
+    test r12d, r12d
+    jnz .Llock_fd
+    mov rdi, rbx            # others see everything written before UNLOCK
+    call {libc}fflush
+.Llock_fd:
+    mov rdi, rbx
+    call {libc}fileno
+    mov ebx, eax            # rbx = file descriptor
+
+    # struct flock: the whole file (start 0, length 0, from SEEK_SET)
+    mov QWORD PTR [rsp], 0
+    mov QWORD PTR [rsp + 8], 0
+    mov QWORD PTR [rsp + 16], 0
+    mov QWORD PTR [rsp + 24], 0
+    mov eax, F_UNLCK
+    test r12d, r12d
+    jz .Llock_type
+    # fcntl(fd, F_GETFL): shared for a file only read, else exclusive
+    mov edi, ebx
+    mov esi, F_GETFL
     xor eax, eax
-    call {libc}fprintf
+    call {libc}fcntl
+    and eax, O_ACCMODE
+    cmp eax, O_RDONLY
+    mov eax, F_RDLCK
+    je .Llock_type
+    mov eax, F_WRLCK
+.Llock_type:
+    mov WORD PTR [rsp + FLOCK_TYPE], ax
 
+    # fcntl(fd, F_SETLK, &lock), which fails if another process holds one
+    mov edi, ebx
+    mov esi, F_SETLK
+    mov rdx, rsp
+    xor eax, eax
+    call {libc}fcntl
+    test eax, eax
+    jns .Llock_done
+    lea rdi, [rip + _file_locked_msg]
+    jmp _rt_error
+.Llock_done:
+    add rsp, FLOCK_SIZE
     pop r12
     pop rbx
     leave
     ret
 
+# ------------------------------------------------------------------------------
+# _rt_file_print_string - Write string to file (PRINT# with string)
+# ------------------------------------------------------------------------------
+# File output goes through the output channel engine in print.s, which
+# tracks the column for WIDTH #.
+#
+# Arguments:
+#   rdi = file number
+#   rsi = string pointer
+#   rdx = string length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_string
+_rt_file_print_string:
+    call _file_check
+    jmp _out_write          # channel = file number
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_float - Write number to file (PRINT# with number)
+# ------------------------------------------------------------------------------
+# Like _rt_print_float, prints whole numbers as integers for clean output.
+#
+# Arguments:
+#   rdi = file number
+#   xmm0 = value to write (double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_float
+_rt_file_print_float:
+    call _file_check
+    xor esi, esi            # as many digits as it takes
+    jmp _out_number
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_single - Write a SINGLE value to file (PRINT# with number)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#   xmm0 = value to write (a SINGLE widened to double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_single
+_rt_file_print_single:
+    call _file_check
+    mov esi, 7              # 7 significant digits
+    jmp _out_number
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_char - Write single character to file
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#   rsi = character code
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_char
+_rt_file_print_char:
+    call _file_check
+    jmp _out_char
+
 # ------------------------------------------------------------------------------
 # _rt_file_print_newline - Write newline to file
 # ------------------------------------------------------------------------------
@@ -309,119 +941,368 @@ _rt_file_print_char:
 # ------------------------------------------------------------------------------
 .globl _rt_file_print_newline
 _rt_file_print_newline:
+    call _file_check
+    jmp _out_newline
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_zone - Advance to the next print zone (comma in PRINT #)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_zone
+_rt_file_print_zone:
+    call _file_check
+    jmp _out_zone
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_tab - Move to a column (TAB function in PRINT #)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#   rsi = column (1-based)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_tab
+_rt_file_print_tab:
+    call _file_check
+    jmp _out_tab
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_spc - Write spaces (SPC function in PRINT #)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#   rsi = number of spaces
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_spc
+_rt_file_print_spc:
+    call _file_check
+    jmp _out_spc
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_using - Start a PRINT # USING statement
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#   rsi = format pointer
+#   rdx = format length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_using
+_rt_file_print_using:
+    call _file_check
+    mov rdi, rsi
+    mov rsi, rdx
+    jmp _rt_print_using
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_using_number - Write a number through the next USING field
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#   xmm0 = value (double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_using_number
+_rt_file_print_using_number:
+    call _file_check
+    jmp _using_number
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_using_string - Write a string through the next USING field
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#   rsi = pointer to string data
+#   rdx = string length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_using_string
+_rt_file_print_using_string:
+    call _file_check
+    jmp _using_string
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_using_end - Write the USING format's text after the last
+# field used
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_using_end
+_rt_file_print_using_end:
+    call _file_check
+    jmp _using_text
+
+# ------------------------------------------------------------------------------
+# _rt_file_width - Set the line width of a file (WIDTH # statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#   rsi = width in columns (1-255; 255 = never wrap, the default)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_width
+_rt_file_width:
+    call _file_check
+    jmp _out_set_width
+
+# ------------------------------------------------------------------------------
+# _rt_file_input_number - Read number from file (INPUT# with number)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#
+# Returns:
+#   xmm0 = value read (double); 0 for an empty field or at end of file
+# ------------------------------------------------------------------------------
+.globl _rt_file_input_number
+_rt_file_input_number:
+    push rbp
+    mov rbp, rsp
+    call _file_next_field
+    # strtod(field, NULL) stops at the comma or quote that ends the field
+    mov rdi, rax
+    xor esi, esi
+    call {libc}strtod
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_file_input_string - Read string from file (INPUT# with string)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#
+# Returns:
+#   rax = pointer to a copy of the field (malloc'd, via _rt_strcat)
+#   rdx = string length; empty at end of file
+# ------------------------------------------------------------------------------
+.globl _rt_file_input_string
+_rt_file_input_string:
+    push rbp
+    mov rbp, rsp
+    call _file_next_field
+    # Copy the field out of the line buffer: field + ""
+    mov rdi, rax
+    mov rsi, rdx
+    xor ecx, ecx
+    call _rt_strcat
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_file_line_input - Read a line from a file (LINE INPUT #)
+# ------------------------------------------------------------------------------
+# Takes the rest of the file's current line when INPUT # has read part of it,
+# else its next line.
+#
+# Arguments:
+#   rdi = file number
+#
+# Returns:
+#   rax = pointer to a copy of the line (malloc'd, via _rt_strcat)
+#   rdx = string length; empty at end of file
+# ------------------------------------------------------------------------------
+.globl _rt_file_line_input
+_rt_file_line_input:
+    call _file_check_read
     push rbp
     mov rbp, rsp
     push rbx
-    sub rsp, 8              # align stack
+    push r12
 
     mov ebx, edi            # save file number
+    lea rax, [rip + _file_line_pos]
+    mov r12, [rax + rbx*8]
+    test r12, r12
+    jnz .Lfile_line_rest
+    call _file_read_line
+    mov r12, rax
 
-    # Use fputc('\n', file) - simpler than fprintf
-    lea rax, [rip + _file_handles]
-    mov rsi, [rax + rbx*8]  # FILE* → rsi (2nd arg)
-    mov edi, 10             # '\n' → edi (1st arg)
-    call {libc}fputc
+.Lfile_line_rest:
+    # The whole line is used up
+    lea rax, [rip + _file_line_pos]
+    mov QWORD PTR [rax + rbx*8], 0
+    # Copy it out of the line buffer: line + ""
+    mov rdi, r12
+    call {libc}strlen
+    mov rdi, r12
+    mov rsi, rax
+    xor ecx, ecx
+    call _rt_strcat
 
-    add rsp, 8
+    pop r12
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_input_number - Read number from file (INPUT# with number)
+# _file_next_field - Next INPUT # field of a file
 # ------------------------------------------------------------------------------
+# Takes the next comma-separated field of the file's current line, reading a
+# new line first when the last one is used up.
+#
 # Arguments:
 #   rdi = file number
 #
 # Returns:
-#   xmm0 = value read (double)
+#   rax = field start (in _file_lines)
+#   rdx = field length
 # ------------------------------------------------------------------------------
-.globl _rt_file_input_number
-_rt_file_input_number:
+_file_next_field:
+    call _file_check_read
     push rbp
     mov rbp, rsp
     push rbx
-    sub rsp, 8
+    push r12
 
     mov ebx, edi            # save file number
+    lea rax, [rip + _file_line_pos]
+    mov r8, [rax + rbx*8]
+    test r8, r8
+    jnz .Lfile_field_split
+    call _file_read_line
+    mov r8, rax
 
-    # fscanf(file, "%lf", &result)
-    lea rax, [rip + _file_handles]
-    mov rdi, [rax + rbx*8]  # FILE*
-    lea rsi, [rip + _file_fmt_input]  # format "%lf"
-    lea rdx, [rbp - 16]     # pointer to local variable for result
-    xor eax, eax
-    call {libc}fscanf
+.Lfile_field_split:
+    call _in_next_field
+    # Keep reading this line after a comma; a new line is needed at its end
+    test r9d, r9d
+    jnz .Lfile_field_save
+    xor r8d, r8d
+.Lfile_field_save:
+    lea rcx, [rip + _file_line_pos]
+    mov [rcx + rbx*8], r8
 
-    # Load result into xmm0
-    movsd xmm0, QWORD PTR [rbp - 16]
-    add rsp, 8
+    pop r12
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_input_string - Read string from file (INPUT# with string, or LINE INPUT#)
+# _file_read_line - Read a file's next line into its line buffer
 # ------------------------------------------------------------------------------
-# Reads a line from file, stripping trailing newline.
-#
 # Arguments:
-#   rdi = file number
+#   rdi = file number (already checked)
 #
 # Returns:
-#   rax = pointer to string data (_file_input_buf)
-#   rdx = string length
-#
-# Note: Uses static buffer - result only valid until next file string read.
+#   rax = the line (in _file_lines), its newline stripped; empty at end of
+#         file. Its length as read goes in _file_line_len.
 # ------------------------------------------------------------------------------
-.globl _rt_file_input_string
-_rt_file_input_string:
+_file_read_line:
     push rbp
     mov rbp, rsp
     push rbx
-    sub rsp, 8              # align stack
+    push r12
 
     mov ebx, edi            # save file number
-
-    # fgets(buffer, size, file)
-    lea rdi, [rip + _file_input_buf]    # buffer
-    mov rsi, 1023                        # max chars (leave room for null)
+    # fgets(_file_lines + n*1024, 1024, file)
+    mov r12, rbx
+    shl r12, 10
+    lea rax, [rip + _file_lines]
+    add r12, rax
+    mov BYTE PTR [r12], 0   # empty line at end of file
     lea rax, [rip + _file_handles]
-    mov rdx, [rax + rbx*8]              # FILE*
+    mov rdx, [rax + rbx*8]
+    cmp rdx, FILE_CONSOLE
+    je .Lfile_read_console
+    mov rdi, r12
+    mov esi, 1024
     call {libc}fgets
+    jmp .Lfile_read_done
 
-    # Check for EOF/error (fgets returns NULL)
-    test rax, rax
-    jz .Lfile_input_string_empty
+.Lfile_read_console:
+    # The console reads like INPUT: scanf("%1023[^\n]", line), getchar()
+    lea rdi, [rip + _fmt_input_str]
+    mov rsi, r12
+    xor eax, eax
+    call {libc}scanf
+    call {libc}getchar
+    mov QWORD PTR [rip + _out_col], 0   # Enter moved the cursor to column 0
 
-    # Calculate length using strlen
-    lea rdi, [rip + _file_input_buf]
+.Lfile_read_done:
+    # Strip the trailing newline (and a CR before it)
+    mov rdi, r12
     call {libc}strlen
-    mov rdx, rax            # length → rdx
+    lea rcx, [rip + _file_line_len]
+    mov [rcx + rbx*8], rax
+.Lfile_read_strip:
+    test rax, rax
+    jz .Lfile_read_stripped
+    cmp BYTE PTR [r12 + rax - 1], 10
+    je .Lfile_read_strip_next
+    cmp BYTE PTR [r12 + rax - 1], 13
+    jne .Lfile_read_stripped
+.Lfile_read_strip_next:
+    dec rax
+    mov BYTE PTR [r12 + rax], 0
+    jmp .Lfile_read_strip
+.Lfile_read_stripped:
+    mov rax, r12
 
-    # Strip trailing newline if present
-    test rdx, rdx
-    jz .Lfile_input_string_done
-    lea rax, [rip + _file_input_buf]
-    mov cl, BYTE PTR [rax + rdx - 1]    # last character
-    cmp cl, 10              # newline?
-    jne .Lfile_input_string_done
-    dec rdx                 # reduce length
-    mov BYTE PTR [rax + rdx], 0         # remove newline
-
-.Lfile_input_string_done:
-    lea rax, [rip + _file_input_buf]
-    add rsp, 8
+    pop r12
     pop rbx
     leave
     ret
 
-.Lfile_input_string_empty:
-    # EOF or error - return empty string
-    lea rax, [rip + _file_input_buf]
-    mov BYTE PTR [rax], 0   # empty string
-    xor edx, edx            # length = 0
-    add rsp, 8
-    pop rbx
-    leave
+# ------------------------------------------------------------------------------
+# _file_check - Raise "Bad file number" unless the file number is open
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#
+# Returns: only if file number rdi (1-15) has an open file. Uses only rax and
+# r11, so the caller's arguments are kept.
+# ------------------------------------------------------------------------------
+_file_check:
+    lea eax, [rdi - 1]      # file numbers are 1-15
+    cmp eax, 14
+    ja _rt_bad_file
+    mov r11d, edi
+    lea rax, [rip + _file_handles]
+    cmp QWORD PTR [rax + r11*8], 0
+    je _rt_bad_file
+    ret
+
+# ------------------------------------------------------------------------------
+# _file_check_read - _file_check for INPUT #, also raising "Bad file mode"
+# for a file opened FOR OUTPUT or APPEND
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = file number
+#
+# Returns: only if the file is open to read. Uses only rax and r11.
+# ------------------------------------------------------------------------------
+_file_check_read:
+    call _file_check
+    lea rax, [rip + _file_written]
+    cmp BYTE PTR [rax + r11], 0
+    jne _file_bad_mode
     ret
+
+# ------------------------------------------------------------------------------
+# _file_bad_mode - Raise "Bad file mode" (jumped to; never returns)
+# ------------------------------------------------------------------------------
+_file_bad_mode:
+    lea rdi, [rip + _file_bad_mode_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _file_already_open - Raise "File already open" (jumped to; never returns)
+# ------------------------------------------------------------------------------
+_file_already_open:
+    lea rdi, [rip + _file_open_msg]
+    jmp _rt_error
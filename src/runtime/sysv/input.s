@@ -2,14 +2,18 @@
 # BASIC Runtime: Input Functions
 # ==============================================================================
 #
-# Keyboard input functions for the BASIC INPUT statement. These read from stdin
-# using libc scanf.
+# Keyboard input functions for the BASIC INPUT and LINE INPUT statements.
+# These read from stdin using libc scanf. INPUT reads a whole line and splits
+# it into comma-separated fields with _in_next_field, which INPUT # (file.s)
+# shares.
 #
 # Buffer and format strings (from data_defs.s):
 #   _input_buf      = 1024 bytes for string input
-#   _fmt_input      = "%lf"           - read double
 #   _fmt_input_str  = "%1023[^\n]"    - read up to 1023 chars, stop at newline
 #
+# The line an INPUT statement is taking apart stays in _input_buf, with
+# _input_pos pointing at its next field.
+#
 # Note: scanf with %[^\n] reads until newline but does NOT consume the newline.
 # We call getchar() after scanf to consume the trailing newline, preventing it
 # from being read by the next INPUT statement.
@@ -20,6 +24,12 @@
 #   - rdx = length in bytes
 # ==============================================================================
 
+.data
+_input_pos: .quad 0
+_redo_msg: .ascii "?Redo from start"
+.equ _redo_msg_len, 16
+.text
+
 # ------------------------------------------------------------------------------
 # _rt_input_string - Read a line of text from stdin
 # ------------------------------------------------------------------------------
@@ -54,6 +64,7 @@ _rt_input_string:
     call {libc}scanf
     # Consume trailing newline that scanf left behind
     call {libc}getchar
+    mov QWORD PTR [rip + _out_col], 0   # Enter moved the cursor to column 0
     # Calculate string length (scan for null terminator)
     lea rax, [rip + _input_buf]     # rax = start of string
     xor rdx, rdx                    # rdx = length counter
@@ -68,34 +79,244 @@ _rt_input_string:
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_input_number - Read a numeric value from stdin
+# _rt_input_line - Prompt for and read the line an INPUT statement takes apart
 # ------------------------------------------------------------------------------
-# Reads a double-precision floating point number. BASIC's INPUT statement
-# converts this to the appropriate type (Integer, Long, Single, Double).
+# Prints the prompt and reads a line, which must hold one comma-separated field
+# per INPUT target. A numeric target needs a number (or an empty field, which
+# is 0). Otherwise "?Redo from start" is printed and the line asked for again.
+# The fields are then handed out by _rt_input_next_number and
+# _rt_input_next_string, one per target. At end of input the line is taken as
+# it is, so a program fed too little input doesn't loop forever.
 #
-# Arguments: none
+# Arguments:
+#   rdi = prompt pointer (already ending in "? " unless the program used ",")
+#   rsi = prompt length
+#   rdx = kinds pointer: 'N' (number) or 'S' (string) for each target
+#   rcx = number of targets
 #
-# Returns:
-#   xmm0 = the number read (double)
+# Returns: nothing (the line is kept in _input_buf)
 #
-# Implementation:
-#   1. scanf("%lf", &local_var) - read double into stack
-#   2. getchar() - consume trailing newline
-#   3. Load result into xmm0
+# Register usage:
+#   rbx = field index, r12/r13 = prompt, r14/r15 = kinds
 # ------------------------------------------------------------------------------
-.globl _rt_input_number
-_rt_input_number:
+.globl _rt_input_line
+_rt_input_line:
     push rbp
     mov rbp, rsp
-    sub rsp, 16                     # Space for local double + alignment
-    # Read double: scanf("%lf", &result)
-    lea rsi, [rbp - 8]              # address of local variable (2nd arg)
-    lea rdi, [rip + _fmt_input]     # format string "%lf" (1st arg)
-    xor eax, eax                    # no vector args
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    sub rsp, 40                     # saved r8/r9, field end, strtod end
+
+    mov r12, rdi
+    mov r13, rsi
+    mov r14, rdx
+    mov r15, rcx
+
+.Linput_line_prompt:
+    mov rdi, r12
+    mov rsi, r13
+    call _rt_print_string
+
+    # Read the line: scanf("%1023[^\n]", buffer), then consume the newline
+    lea rdi, [rip + _input_buf]
+    mov BYTE PTR [rdi], 0
+    lea rsi, [rip + _input_buf]
+    lea rdi, [rip + _fmt_input_str]
+    xor eax, eax
     call {libc}scanf
-    # Consume trailing newline
+    mov ebx, eax                    # EOF (-1) when input has run out
     call {libc}getchar
-    # Load result into xmm0
-    movsd xmm0, QWORD PTR [rbp - 8]
+    mov QWORD PTR [rip + _out_col], 0   # Enter moved the cursor to column 0
+    cmp ebx, -1
+    je .Linput_line_accept
+    test r15, r15
+    jz .Linput_line_accept
+
+    # Check each field against its target
+    lea r8, [rip + _input_buf]
+    xor ebx, ebx
+.Linput_line_field:
+    call _in_next_field
+    cmp BYTE PTR [r14 + rbx], 'N'
+    jne .Linput_line_next
+    test rdx, rdx                   # an empty field reads as 0
+    jz .Linput_line_next
+    # The whole field must be a number: strtod(field, &end) stops at its end
+    mov [rbp - 48], r8
+    mov [rbp - 56], r9
+    add rdx, rax
+    mov [rbp - 64], rdx             # where the field ends
+    mov rdi, rax
+    lea rsi, [rbp - 72]
+    call {libc}strtod
+    mov r8, [rbp - 48]
+    mov r9, [rbp - 56]
+    mov rax, [rbp - 72]
+    cmp rax, [rbp - 64]
+    jne .Linput_line_redo
+.Linput_line_next:
+    inc rbx
+    cmp rbx, r15
+    je .Linput_line_last
+    cmp r9d, ','                    # too few fields, or junk after a quote
+    jne .Linput_line_redo
+    jmp .Linput_line_field
+.Linput_line_last:
+    test r9d, r9d                   # too many fields
+    jnz .Linput_line_redo
+
+.Linput_line_accept:
+    lea rax, [rip + _input_buf]
+    mov [rip + _input_pos], rax
+    add rsp, 40
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+.Linput_line_redo:
+    lea rdi, [rip + _redo_msg]
+    mov rsi, _redo_msg_len
+    call _rt_print_string
+    call _rt_print_newline
+    jmp .Linput_line_prompt
+
+# ------------------------------------------------------------------------------
+# _rt_input_next_number - Take the next field of the INPUT line as a number
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   xmm0 = the number (double); 0 for an empty field
+# ------------------------------------------------------------------------------
+.globl _rt_input_next_number
+_rt_input_next_number:
+    push rbp
+    mov rbp, rsp
+    mov r8, [rip + _input_pos]
+    call _in_next_field
+    mov [rip + _input_pos], r8
+    # strtod(field, NULL) stops at the comma or quote that ends the field
+    mov rdi, rax
+    xor esi, esi
+    call {libc}strtod
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_input_next_string - Take the next field of the INPUT line as a string
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   rax = pointer to a copy of the field (malloc'd, via _rt_strcat)
+#   rdx = length
+# ------------------------------------------------------------------------------
+.globl _rt_input_next_string
+_rt_input_next_string:
+    push rbp
+    mov rbp, rsp
+    mov r8, [rip + _input_pos]
+    call _in_next_field
+    mov [rip + _input_pos], r8
+    # Copy the field out of the line buffer: field + ""
+    mov rdi, rax
+    mov rsi, rdx
+    xor ecx, ecx
+    call _rt_strcat
     leave
     ret
+
+# ------------------------------------------------------------------------------
+# _in_next_field - Split the next field off an input line (INPUT and INPUT #)
+# ------------------------------------------------------------------------------
+# Fields are separated by commas. Blanks around a field are dropped, and a
+# field in double quotes is taken as it is, commas and all.
+#
+# Arguments:
+#   r8 = next character of a NUL-terminated line
+#
+# Returns:
+#   rax = field start
+#   rdx = field length
+#   r8  = character after the field's comma (or the NUL ending the line)
+#   r9  = what ended the field: ',' or 0 at the end of the line; anything else
+#         is junk after a quoted field
+#
+# Uses only rax, rdx, r8 and r9, so callers keep their other registers.
+# ------------------------------------------------------------------------------
+_in_next_field:
+    movzx r9d, BYTE PTR [r8]        # skip leading blanks
+    cmp r9d, ' '
+    je .Lfield_blank
+    cmp r9d, 9
+    jne .Lfield_start
+.Lfield_blank:
+    inc r8
+    jmp _in_next_field
+.Lfield_start:
+    cmp r9d, '"'
+    je .Lfield_quoted
+    mov rax, r8                     # unquoted: up to a comma or the line end
+.Lfield_scan:
+    movzx r9d, BYTE PTR [r8]
+    test r9d, r9d
+    jz .Lfield_trim
+    cmp r9d, ','
+    je .Lfield_trim
+    inc r8
+    jmp .Lfield_scan
+.Lfield_trim:
+    mov rdx, r8                     # drop trailing blanks
+.Lfield_trim_loop:
+    cmp rdx, rax
+    je .Lfield_length
+    cmp BYTE PTR [rdx - 1], ' '
+    je .Lfield_trim_next
+    cmp BYTE PTR [rdx - 1], 9
+    jne .Lfield_length
+.Lfield_trim_next:
+    dec rdx
+    jmp .Lfield_trim_loop
+.Lfield_length:
+    sub rdx, rax
+    jmp .Lfield_end
+.Lfield_quoted:
+    inc r8
+    mov rax, r8                     # quoted: up to the closing quote
+.Lfield_quote_scan:
+    movzx r9d, BYTE PTR [r8]
+    test r9d, r9d
+    jz .Lfield_quote_end            # unclosed: runs to the end of the line
+    cmp r9d, '"'
+    je .Lfield_quote_end
+    inc r8
+    jmp .Lfield_quote_scan
+.Lfield_quote_end:
+    mov rdx, r8
+    sub rdx, rax
+    test r9d, r9d
+    jz .Lfield_end
+    inc r8                          # skip the quote and any blanks after it
+.Lfield_quote_blank:
+    movzx r9d, BYTE PTR [r8]
+    cmp r9d, ' '
+    je .Lfield_quote_skip
+    cmp r9d, 9
+    jne .Lfield_end
+.Lfield_quote_skip:
+    inc r8
+    jmp .Lfield_quote_blank
+.Lfield_end:
+    movzx r9d, BYTE PTR [r8]
+    cmp r9d, ','
+    jne .Lfield_done
+    inc r8
+.Lfield_done:
+    ret
@@ -0,0 +1,377 @@
+# ==============================================================================
+# BASIC Runtime: Emulated Memory
+# ==============================================================================
+#
+# PEEK, POKE, DEF SEG, VARSEG, BSAVE and BLOAD against an emulated real-mode
+# address space.
+#
+# Old BASIC programs read and write memory through a 16-bit segment and a
+# 16-bit offset. The runtime emulates that with a zero-filled block covering
+# the full segment:offset range (segment * 16 + offset, up to 0x10FFEF), so
+# tables stored with POKE can be read back with PEEK. Nothing is mapped into
+# the block: POKEs to video memory or BIOS areas are stored but have no
+# other effect.
+#
+# The block is allocated on first use.
+#
+# VARSEG maps the 64KB-aligned host region holding a variable onto one of a
+# small set of reserved segments (D000, D100, ... EF00), and VARPTR gives the
+# variable's offset within that region. PEEK and POKE through those segments
+# read and write the program's real variables.
+#
+# BSAVE files use the GW-BASIC layout: a 0xFD marker byte, then the segment,
+# offset and length as 16-bit little-endian values, then the data.
+#
+# Global state:
+#   _mem_base = pointer to the emulated address space (NULL until first use)
+#   _mem_seg  = current segment set by DEF SEG (default 0)
+#   _mem_maps = host region base for each reserved segment (0 = unused)
+# ==============================================================================
+
+.equ MEM_SIZE, 0x110000     # 1MB plus the 64KB reachable above segment FFFF
+.equ MEM_MAP_SEG, 0xD000    # first segment handed out by VARSEG
+.equ MEM_MAP_SLOTS, 32      # reserved segments, 0x100 apart
+.equ BSAVE_MARKER, 0xFD
+.equ BSAVE_HEADER_SIZE, 7
+
+.data
+_mem_base: .quad 0
+_mem_seg: .quad 0
+_mem_maps: .skip MEM_MAP_SLOTS * 8
+_mem_header: .skip BSAVE_HEADER_SIZE
+_mem_mode_read: .asciz "rb"
+_mem_mode_write: .asciz "wb"
+_mem_not_found_msg: .asciz "File not found"
+_mem_bad_mode_msg: .asciz "Bad file mode"
+_mem_access_msg: .asciz "Path/File access error"
+
+.text
+
+# ------------------------------------------------------------------------------
+# _rt_def_seg - Select the segment used by PEEK and POKE (DEF SEG)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = segment (-32768 to 65535; negative values wrap like 16-bit integers)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_def_seg
+_rt_def_seg:
+    cmp rdi, -32768
+    jl _rt_illegal_call
+    cmp rdi, 65535
+    jg _rt_illegal_call
+    movzx edi, di
+    mov QWORD PTR [rip + _mem_seg], rdi
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_peek - Read a byte of emulated memory (PEEK function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = offset within the current segment
+#
+# Returns:
+#   rax = byte value (0-255)
+# ------------------------------------------------------------------------------
+.globl _rt_peek
+_rt_peek:
+    push rbp
+    mov rbp, rsp
+    call _mem_addr
+    movzx eax, BYTE PTR [rax]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_poke - Write a byte of emulated memory (POKE statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = offset within the current segment
+#   rsi = value (0-255)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_poke
+_rt_poke:
+    push rbp
+    mov rbp, rsp
+    cmp rsi, 255
+    ja _rt_illegal_call     # unsigned compare also rejects negatives
+    push rsi
+    sub rsp, 8
+    call _mem_addr
+    add rsp, 8
+    pop rsi
+    mov BYTE PTR [rax], sil
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_varseg - Map the memory holding a variable (VARSEG function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = host address of the variable
+#
+# Returns:
+#   rax = reserved segment mapped onto the variable's 64KB host region.
+#         VARPTR gives the offset (the low 16 bits of the address).
+# ------------------------------------------------------------------------------
+.globl _rt_varseg
+_rt_varseg:
+    and rdi, -65536         # region base
+    lea rcx, [rip + _mem_maps]
+    xor eax, eax
+.Lvarseg_scan:
+    mov rdx, QWORD PTR [rcx + rax*8]
+    cmp rdx, rdi
+    je .Lvarseg_found
+    test rdx, rdx
+    jz .Lvarseg_claim
+    inc rax
+    cmp rax, MEM_MAP_SLOTS
+    jb .Lvarseg_scan
+    jmp _rt_illegal_call    # all reserved segments in use
+.Lvarseg_claim:
+    mov QWORD PTR [rcx + rax*8], rdi
+.Lvarseg_found:
+    shl rax, 8
+    add rax, MEM_MAP_SEG
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_bsave - Save a block of memory to a file (BSAVE statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = filename pointer (BASIC string, not null-terminated)
+#   rsi = filename length
+#   rdx = offset within the current segment
+#   rcx = length in bytes (the block must not run past the segment's end)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_bsave
+_rt_bsave:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+
+    mov rbx, rcx            # rbx = length
+    mov r14, rdx            # r14 = offset
+    call _mem_file_name
+    mov rdi, r14
+    call _mem_addr
+    mov r12, rax            # r12 = host pointer
+    movzx r14d, r14w        # offset as an unsigned 16-bit value
+    cmp rbx, 65535
+    ja _rt_illegal_call     # unsigned compare also rejects negatives
+    lea rax, [r14 + rbx]
+    cmp rax, 65536
+    ja _rt_illegal_call
+
+    lea rcx, [rip + _mem_header]
+    mov BYTE PTR [rcx], BSAVE_MARKER
+    mov rax, QWORD PTR [rip + _mem_seg]
+    mov WORD PTR [rcx + 1], ax
+    mov WORD PTR [rcx + 3], r14w
+    mov WORD PTR [rcx + 5], bx
+
+    # fopen(name, "wb")
+    lea rdi, [rip + _file_name_buf]
+    lea rsi, [rip + _mem_mode_write]
+    call {libc}fopen
+    lea rdi, [rip + _mem_access_msg]
+    test rax, rax
+    jz _rt_error
+    mov r13, rax            # r13 = FILE*
+
+    # fwrite(header, 1, 7, f); fwrite(data, 1, length, f)
+    lea rdi, [rip + _mem_header]
+    mov esi, 1
+    mov edx, BSAVE_HEADER_SIZE
+    mov rcx, r13
+    call {libc}fwrite
+    mov rdi, r12
+    mov esi, 1
+    mov rdx, rbx
+    mov rcx, r13
+    call {libc}fwrite
+    mov rdi, r13
+    call {libc}fclose
+
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_bload - Load a file written by BSAVE into memory (BLOAD statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = filename pointer (BASIC string, not null-terminated)
+#   rsi = filename length
+#   rdx = offset within the current segment
+#   rcx = 1 if an offset was given, 0 to load at the segment and offset
+#         recorded in the file
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_bload
+_rt_bload:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+
+    mov rbx, rcx            # rbx = offset given?
+    mov r14, rdx            # r14 = offset
+    call _mem_file_name
+
+    # fopen(name, "rb")
+    lea rdi, [rip + _file_name_buf]
+    lea rsi, [rip + _mem_mode_read]
+    call {libc}fopen
+    lea rdi, [rip + _mem_not_found_msg]
+    test rax, rax
+    jz _rt_error
+    mov r13, rax            # r13 = FILE*
+
+    # fread(header, 1, 7, f) and check the marker
+    lea rdi, [rip + _mem_header]
+    mov esi, 1
+    mov edx, BSAVE_HEADER_SIZE
+    mov rcx, r13
+    call {libc}fread
+    lea rdi, [rip + _mem_bad_mode_msg]
+    cmp rax, BSAVE_HEADER_SIZE
+    jne _rt_error
+    cmp BYTE PTR [rip + _mem_header], BSAVE_MARKER
+    jne _rt_error
+
+    test rbx, rbx
+    jnz .Lbload_at_offset
+    movzx r14d, WORD PTR [rip + _mem_header + 3]
+    movzx esi, WORD PTR [rip + _mem_header + 1]
+    mov rdi, r14
+    call _mem_seg_addr
+    jmp .Lbload_read
+.Lbload_at_offset:
+    mov rdi, r14
+    call _mem_addr
+    movzx r14d, r14w        # offset as an unsigned 16-bit value
+.Lbload_read:
+    mov r12, rax            # r12 = host pointer
+    movzx ebx, WORD PTR [rip + _mem_header + 5]    # rbx = length
+    lea rax, [r14 + rbx]
+    cmp rax, 65536
+    ja _rt_illegal_call
+
+    # fread(data, 1, length, f)
+    mov rdi, r12
+    mov esi, 1
+    mov rdx, rbx
+    mov rcx, r13
+    call {libc}fread
+    mov rdi, r13
+    call {libc}fclose
+
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _mem_file_name - Copy a BSAVE/BLOAD filename into _file_name_buf (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = filename pointer, rsi = filename length
+#
+# Returns: nothing. Clobbers caller-saved registers.
+# ------------------------------------------------------------------------------
+_mem_file_name:
+    push rbx
+    mov rbx, rsi
+    cmp rbx, 1023
+    ja _rt_illegal_call     # longer than _file_name_buf (file.s)
+    mov rdx, rsi
+    mov rsi, rdi
+    lea rdi, [rip + _file_name_buf]
+    call {libc}memcpy
+    lea rax, [rip + _file_name_buf]
+    mov BYTE PTR [rax + rbx], 0
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _mem_addr - Translate an offset in the current segment (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = offset (-32768 to 65535; negative values wrap like 16-bit integers)
+#
+# Returns: as _mem_seg_addr
+# ------------------------------------------------------------------------------
+_mem_addr:
+    mov rsi, QWORD PTR [rip + _mem_seg]
+    # Fall through
+
+# ------------------------------------------------------------------------------
+# _mem_seg_addr - Translate a segment:offset address (internal)
+# ------------------------------------------------------------------------------
+# Allocates the emulated address space on first use.
+#
+# Arguments:
+#   rdi = offset (-32768 to 65535; negative values wrap like 16-bit integers)
+#   rsi = segment (0-65535)
+#
+# Returns:
+#   rax = host pointer to the byte
+#   Clobbers rcx, rdx, rsi, rdi, r8-r11 (calls calloc).
+# ------------------------------------------------------------------------------
+_mem_seg_addr:
+    cmp rdi, -32768
+    jl _rt_illegal_call
+    cmp rdi, 65535
+    jg _rt_illegal_call
+    movzx edi, di
+    mov rax, rsi
+
+    # Segments handed out by VARSEG map straight onto host memory
+    mov rdx, rax
+    sub rdx, MEM_MAP_SEG
+    jb .Lmem_addr_emulated
+    test rdx, 0xFF
+    jnz .Lmem_addr_emulated
+    shr rdx, 8
+    cmp rdx, MEM_MAP_SLOTS
+    jae .Lmem_addr_emulated
+    lea rcx, [rip + _mem_maps]
+    mov rcx, QWORD PTR [rcx + rdx*8]
+    test rcx, rcx
+    jz .Lmem_addr_emulated
+    lea rax, [rcx + rdi]
+    ret
+
+.Lmem_addr_emulated:
+    shl rax, 4
+    add rdi, rax            # rdi = linear address
+    mov rax, QWORD PTR [rip + _mem_base]
+    test rax, rax
+    jnz .Lmem_addr_ready
+    push rdi
+    mov edi, MEM_SIZE
+    mov esi, 1
+    call {libc}calloc
+    pop rdi
+    mov QWORD PTR [rip + _mem_base], rax
+.Lmem_addr_ready:
+    add rax, rdi
+    ret
@@ -8,7 +8,7 @@
 # Format strings are defined in data_defs.s:
 #   _fmt_str     = "%.*s"    - precision-limited string (ptr, len)
 #   _fmt_int     = "%ld"     - long integer
-#   _fmt_float   = "%g"      - floating point (compact representation)
+#   _fmt_digits  = "%.*g"    - floating point to a number of significant digits
 #   _fmt_char    = "%c"      - single character
 #   _fmt_newline = "\n"      - newline
 #
@@ -18,42 +18,62 @@
 #   - Return values: rax (int), xmm0 (float)
 #
 # The {libc} placeholder is replaced with "_" on macOS, "" on Linux.
+#
+# Output channels:
+#   Console output (PRINT) and file output (PRINT #) share one engine that
+#   tracks the cursor column per channel: channel 0 is the console, channels
+#   1-15 are files opened with OPEN. Output wraps to a new line when the
+#   column reaches the channel's width (set by WIDTH), unless the width is
+#   255, which means never wrap. Commas advance to the next 14-column print
+#   zone, and TAB/SPC move the column. A file opened on the console (its
+#   handle is FILE_CONSOLE, see file.s) writes like the console, keeping
+#   its own column.
+#
+# Text screen:
+#   Everything written to the console is also recorded in a 25x80 shadow of
+#   the screen, which SCREEN(row, col) reads. Printable characters fill
+#   cells and wrap at the right edge, newline and carriage return move the
+#   cursor, other control characters take no cell, and a newline on the last
+#   row scrolls. CLS and SCREEN clear it.
+#
+# Global state (from data_defs.s):
+#   _out_col   = current column (0-based) for each channel
+#   _out_width = wrap width for each channel (console 80, files 255)
+#   _out_decimal_point = decimal point of numbers on the console
+#   _scr_text  = text screen cells, row by row
+#   _scr_row   = text screen cursor row (0-based)
+#   _scr_col   = text screen cursor column (0-based, 80 = past the edge)
 # ==============================================================================
 
+.equ OUT_WIDTH_INFINITE, 255
+.equ OUT_ZONE_WIDTH, 14
+.equ OUT_CHANNELS, 16
+.equ FILE_CONSOLE, 1        # file handle of the console devices
+.equ SCR_ROWS, 25
+.equ SCR_COLS, 80
+
 # ------------------------------------------------------------------------------
 # _rt_print_string - Print a string with explicit length
 # ------------------------------------------------------------------------------
-# BASIC strings are (ptr, len) pairs, not null-terminated. We use printf's
-# precision specifier "%.*s" which takes the length as an argument.
+# BASIC strings are (ptr, len) pairs, not null-terminated.
 #
 # Arguments:
 #   rdi = pointer to string data (char*)
 #   rsi = string length (size_t)
 #
 # Returns: nothing
-#
-# printf call: printf("%.*s", length, pointer)
-#   rdi = format string
-#   rsi = precision (string length)
-#   rdx = string pointer
 # ------------------------------------------------------------------------------
 .globl _rt_print_string
 _rt_print_string:
-    push rbp
-    mov rbp, rsp
-    # Rearrange arguments for printf("%.*s", len, ptr)
-    mov rdx, rdi        # ptr → rdx (3rd arg to printf)
-    # rsi already has len (2nd arg to printf, as precision)
-    lea rdi, [rip + _fmt_str]   # format string → rdi (1st arg)
-    xor eax, eax        # no vector registers used (required for varargs)
-    call {libc}printf
-    leave
-    ret
+    mov rdx, rsi            # len
+    mov rsi, rdi            # ptr
+    xor edi, edi            # channel 0 = console
+    jmp _out_write
 
 # ------------------------------------------------------------------------------
 # _rt_print_char - Print a single ASCII character
 # ------------------------------------------------------------------------------
-# Used by PRINT with semicolon/comma separators and CHR$() output.
+# Used for CHR$() output.
 #
 # Arguments:
 #   rdi = character code (int, 0-255)
@@ -62,14 +82,9 @@ _rt_print_string:
 # ------------------------------------------------------------------------------
 .globl _rt_print_char
 _rt_print_char:
-    push rbp
-    mov rbp, rsp
-    mov rsi, rdi        # char → rsi (2nd arg)
-    lea rdi, [rip + _fmt_char]  # format → rdi (1st arg)
-    xor eax, eax        # no vector registers
-    call {libc}printf
-    leave
-    ret
+    mov rsi, rdi
+    xor edi, edi
+    jmp _out_char
 
 # ------------------------------------------------------------------------------
 # _rt_print_newline - Print a newline character
@@ -81,71 +96,898 @@ _rt_print_char:
 # ------------------------------------------------------------------------------
 .globl _rt_print_newline
 _rt_print_newline:
+    xor edi, edi
+    jmp _out_newline
+
+# ------------------------------------------------------------------------------
+# _rt_print_float - Print a numeric value (integer or floating point)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   xmm0 = value to print (double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_print_float
+_rt_print_float:
+    xor edi, edi
+    xor esi, esi            # as many digits as it takes
+    jmp _out_number
+
+# ------------------------------------------------------------------------------
+# _rt_print_single - Print a SINGLE value to single precision
+# ------------------------------------------------------------------------------
+# Arguments:
+#   xmm0 = value to print (a SINGLE widened to double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_print_single
+_rt_print_single:
+    xor edi, edi
+    mov esi, 7              # 7 significant digits
+    jmp _out_number
+
+# ------------------------------------------------------------------------------
+# _rt_print_zone - Advance to the next print zone (comma in PRINT)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_print_zone
+_rt_print_zone:
+    xor edi, edi
+    jmp _out_zone
+
+# ------------------------------------------------------------------------------
+# _rt_print_tab - Move to a column (TAB function in PRINT)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = column (1-based)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_print_tab
+_rt_print_tab:
+    mov rsi, rdi
+    xor edi, edi
+    jmp _out_tab
+
+# ------------------------------------------------------------------------------
+# _rt_print_spc - Print spaces (SPC function in PRINT)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = number of spaces
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_print_spc
+_rt_print_spc:
+    mov rsi, rdi
+    xor edi, edi
+    jmp _out_spc
+
+# ------------------------------------------------------------------------------
+# _rt_width - Set the console width (WIDTH statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = width in columns (1-255; 255 = never wrap)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_width
+_rt_width:
+    mov rsi, rdi
+    xor edi, edi
+    jmp _out_set_width
+
+# ------------------------------------------------------------------------------
+# _out_set_width - Set a channel's wrap width (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = channel
+#   rsi = width in columns (1-255; 255 = never wrap)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_set_width:
+    cmp rsi, 1
+    jl _rt_illegal_call
+    cmp rsi, OUT_WIDTH_INFINITE
+    jg _rt_illegal_call
+    lea rax, [rip + _out_width]
+    mov QWORD PTR [rax + rdi*8], rsi
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_raw - Write bytes to a channel without column bookkeeping (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = channel (0 = console, 1-15 = file number)
+#   rsi = pointer
+#   rdx = length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_raw:
     push rbp
     mov rbp, rsp
-    lea rdi, [rip + _fmt_newline]
+    test rdi, rdi
+    jnz .Lout_raw_file
+.Lout_raw_stdout:
+    push rbx
+    push r12
+    mov rbx, rsi            # rbx = next byte
+    mov r12, rdx            # r12 = bytes left
+    mov rdi, rsi            # keep the text screen up to date
+    mov rsi, rdx
+    call _scr_track
+.Lout_raw_console:
+    # printf stops at a NUL: print up to the next one, then it with putchar
+    mov rdi, rbx
+    xor esi, esi
+    mov rdx, r12
+    call {libc}memchr
+    mov rsi, r12            # rsi = piece length
+    test rax, rax
+    jz .Lout_raw_piece
+    mov rsi, rax
+    sub rsi, rbx
+.Lout_raw_piece:
+    # printf("%.*s", piece, ptr)
+    mov rdx, rbx
+    add rbx, rsi
+    sub r12, rsi
+    lea rdi, [rip + _fmt_str]
     xor eax, eax
     call {libc}printf
+    test r12, r12
+    jz .Lout_raw_console_done
+    xor edi, edi            # the NUL
+    call {libc}putchar
+    inc rbx
+    dec r12
+    jnz .Lout_raw_console
+.Lout_raw_console_done:
+    pop r12
+    pop rbx
+    leave
+    ret
+.Lout_raw_file:
+    # fwrite(ptr, 1, len, file)
+    lea rax, [rip + _file_handles]
+    mov rcx, QWORD PTR [rax + rdi*8]
+    cmp rcx, FILE_CONSOLE
+    je .Lout_raw_stdout
+    mov rdi, rsi
+    mov esi, 1
+    call {libc}fwrite
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_print_float - Print a numeric value (integer or floating point)
+# _out_write - Write a string to a channel, wrapping at its width (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = channel
+#   rsi = pointer
+#   rdx = length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_write:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    mov rbx, rdi            # rbx = channel
+    mov r12, rsi            # r12 = next byte
+    mov r13, rdx            # r13 = bytes left
+
+.Lout_write_loop:
+    test r13, r13
+    jz .Lout_write_done
+    mov r14, r13            # r14 = chunk length
+    lea rax, [rip + _out_width]
+    mov rax, QWORD PTR [rax + rbx*8]
+    cmp rax, OUT_WIDTH_INFINITE
+    je .Lout_write_scan
+    lea rcx, [rip + _out_col]
+    mov rcx, QWORD PTR [rcx + rbx*8]
+    cmp rcx, rax
+    jb .Lout_write_room
+    mov rdi, rbx            # line is full
+    call _out_newline
+    jmp .Lout_write_loop
+.Lout_write_room:
+    sub rax, rcx            # room left on the line
+    cmp r14, rax
+    cmova r14, rax
+
+.Lout_write_scan:
+    # An embedded newline ends the chunk and resets the column
+    mov rdi, r12
+    mov esi, 10
+    mov rdx, r14
+    call {libc}memchr
+    test rax, rax
+    jz .Lout_write_emit
+    sub rax, r12
+    lea r14, [rax + 1]
+
+.Lout_write_emit:
+    mov rdi, rbx
+    mov rsi, r12
+    mov rdx, r14
+    call _out_raw
+    lea rcx, [rip + _out_col]
+    cmp BYTE PTR [r12 + r14 - 1], 10
+    jne .Lout_write_advance
+    mov QWORD PTR [rcx + rbx*8], 0
+    jmp .Lout_write_next
+.Lout_write_advance:
+    add QWORD PTR [rcx + rbx*8], r14
+.Lout_write_next:
+    add r12, r14
+    sub r13, r14
+    jmp .Lout_write_loop
+
+.Lout_write_done:
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_char - Write one character to a channel (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = channel
+#   rsi = character code
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_char:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 16
+    mov BYTE PTR [rbp - 1], sil
+    lea rsi, [rbp - 1]
+    mov edx, 1
+    call _out_write
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_newline - End the current line on a channel (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = channel
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_newline:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 8
+    mov rbx, rdi
+    lea rsi, [rip + _fmt_newline]
+    mov edx, 1
+    call _out_raw
+    lea rax, [rip + _out_col]
+    mov QWORD PTR [rax + rbx*8], 0
+    add rsp, 8
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_number - Write a number to a channel (internal)
+# ------------------------------------------------------------------------------
+# The number is formatted by _format_number. A number is never split across
+# lines: if it does not fit in the rest of the line, it starts a new one.
+#
+# Arguments:
+#   rdi = channel
+#   esi = significant digits for non-whole values (7 for SINGLE, 0 for as
+#         many as a DOUBLE needs)
+#   xmm0 = value (double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_number:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    sub rsp, 64             # formatted number
+    mov rbx, rdi            # rbx = channel
+    # _format_number(buf, digits, value) - digits still in esi, value in xmm0
+    mov rdi, rsp
+    call _format_number
+    test rbx, rbx
+    jnz .Lout_number_formatted      # files always use '.'
+    mov rdi, rsp
+    mov rsi, rax
+    call _out_locale_point
+
+.Lout_number_formatted:
+    mov r12, rax            # r12 = length
+    lea rax, [rip + _out_width]
+    mov rax, QWORD PTR [rax + rbx*8]
+    cmp rax, OUT_WIDTH_INFINITE
+    je .Lout_number_write
+    lea rcx, [rip + _out_col]
+    mov rcx, QWORD PTR [rcx + rbx*8]
+    test rcx, rcx
+    jz .Lout_number_write
+    add rcx, r12
+    cmp rcx, rax
+    jbe .Lout_number_write
+    mov rdi, rbx
+    call _out_newline
+.Lout_number_write:
+    mov rdi, rbx
+    mov rsi, rsp
+    mov rdx, r12
+    call _out_write
+
+    add rsp, 64
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _format_number - Format a number as PRINT and STR$ show it (internal)
 # ------------------------------------------------------------------------------
 # GW-BASIC convention: if a number is a whole number, print without decimal.
 # We achieve this by:
 #   1. Truncate to integer and convert back to double
 #   2. Compare with original: if equal, it's a whole number
-#   3. Print as integer (%ld) or float (%g) accordingly
+#   3. Format as integer (%ld), or else with %.*g: to the given number of
+#      significant digits, or for a DOUBLE with 15, then 16, then 17 until
+#      strtod reads the text back as the same value, so VAL(STR$(X)) = X
+#      with no more digits than that takes
 #
 # Arguments:
-#   xmm0 = value to print (double)
+#   rdi = buffer (at least 32 bytes)
+#   esi = significant digits for non-whole values (0: as many as it takes)
+#   xmm0 = value (double)
 #
-# Returns: nothing
+# Returns:
+#   rax = length of the text
 #
 # Note: %g format automatically chooses between %f and %e notation and
 # strips trailing zeros, giving clean output like "3.14159" not "3.141590".
 # ------------------------------------------------------------------------------
-.globl _rt_print_float
-_rt_print_float:
+_format_number:
     push rbp
     mov rbp, rsp
-    sub rsp, 16         # Stack alignment for potential printf call
+    push rbx
+    push r12
+    push r13
+    sub rsp, 24             # value, length, and alignment for calls
+    mov rbx, rdi            # rbx = buffer
+    mov r12d, esi           # r12 = significant digits
+    movsd QWORD PTR [rsp], xmm0
+
     # Check if value is a whole number
     cvttsd2si rax, xmm0     # truncate to integer
     cvtsi2sd xmm1, rax      # convert back to double
     ucomisd xmm0, xmm1      # compare original with truncated
-    jne .Lprint_as_float    # if different, has fractional part
-    # Print as integer (cleaner output)
-    mov rsi, rax            # integer value → rsi (2nd arg)
-    lea rdi, [rip + _fmt_int]
+    jne .Lformat_number_float
+    # snprintf(buf, 32, "%ld", value)
+    mov rcx, rax
+    mov rdi, rbx
+    mov esi, 32
+    lea rdx, [rip + _fmt_int]
     xor eax, eax
-    call {libc}printf
-    jmp .Lprint_float_done
-.Lprint_as_float:
-    # Print as floating point - value still in xmm0
-    lea rdi, [rip + _fmt_float]
+    call {libc}snprintf
+    jmp .Lformat_number_done
+.Lformat_number_float:
+    xor r13d, r13d          # r13 = 1 while looking for the fewest digits
+    test r12d, r12d
+    jnz .Lformat_number_digits
+    mov r12d, 15
+    mov r13d, 1
+.Lformat_number_digits:
+    # snprintf(buf, 32, "%.*g", digits, value)
+    mov rdi, rbx
+    mov esi, 32
+    lea rdx, [rip + _fmt_digits]
+    mov ecx, r12d
+    movsd xmm0, QWORD PTR [rsp]
     mov eax, 1              # 1 = one vector register argument (xmm0)
-    call {libc}printf
-.Lprint_float_done:
+    call {libc}snprintf
+    test r13d, r13d
+    jz .Lformat_number_done
+    cmp r12d, 17
+    jae .Lformat_number_done
+    mov QWORD PTR [rsp + 8], rax
+    # Done if the text reads back as the value
+    mov rdi, rbx
+    xor esi, esi
+    call {libc}strtod
+    movsd xmm1, QWORD PTR [rsp]
+    ucomisd xmm0, xmm1
+    mov rax, QWORD PTR [rsp + 8]
+    je .Lformat_number_done
+    inc r12d                # one more digit
+    jmp .Lformat_number_digits
+.Lformat_number_done:
+    add rsp, 24
+    pop r13
+    pop r12
+    pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_gosub_overflow - Handle GOSUB stack overflow error
+# _rt_init_locale - Use the locale's decimal point (called at program start)
+# ------------------------------------------------------------------------------
+# With --locale, numbers on the console and in STR$ use the decimal point of
+# the locale the environment names (LC_ALL, LC_NUMERIC or LANG), and VAL
+# reads it. The C library itself stays in the "C" locale, so the runtime's
+# own formatting and parsing keep seeing '.'. A locale that can't be loaded,
+# or whose decimal point is more than one byte, leaves it '.'.
+#
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_init_locale
+_rt_init_locale:
+    push rbp
+    mov rbp, rsp
+    # setlocale(LC_NUMERIC, "")
+    mov edi, LC_NUMERIC
+    lea rsi, [rip + _locale_env]
+    call {libc}setlocale
+    test rax, rax
+    jz .Linit_locale_done
+    # localeconv()->decimal_point, the struct's first field
+    call {libc}localeconv
+    mov rax, QWORD PTR [rax]
+    movzx ecx, BYTE PTR [rax]
+    test ecx, ecx
+    jz .Linit_locale_restore
+    cmp BYTE PTR [rax + 1], 0
+    jne .Linit_locale_restore
+    mov BYTE PTR [rip + _out_decimal_point], cl
+.Linit_locale_restore:
+    # setlocale(LC_NUMERIC, "C")
+    mov edi, LC_NUMERIC
+    lea rsi, [rip + _locale_c]
+    call {libc}setlocale
+.Linit_locale_done:
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_locale_point - Give a formatted number the console's decimal point
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = text from _format_number
+#   rsi = its length
+#
+# Returns: nothing. Uses only rcx and r8.
+# ------------------------------------------------------------------------------
+_out_locale_point:
+    movzx r8d, BYTE PTR [rip + _out_decimal_point]
+    cmp r8d, '.'
+    je .Lout_locale_point_done
+    xor ecx, ecx
+.Lout_locale_point_loop:
+    cmp rcx, rsi
+    jae .Lout_locale_point_done
+    cmp BYTE PTR [rdi + rcx], '.'
+    je .Lout_locale_point_found
+    inc rcx
+    jmp .Lout_locale_point_loop
+.Lout_locale_point_found:
+    mov BYTE PTR [rdi + rcx], r8b
+.Lout_locale_point_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_zone - Advance to the next 14-column print zone (internal)
+# ------------------------------------------------------------------------------
+# Starts a new line instead if the next zone would not fit within the width.
+#
+# Arguments:
+#   rdi = channel
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_zone:
+    lea rax, [rip + _out_col]
+    mov rax, QWORD PTR [rax + rdi*8]
+    mov ecx, OUT_ZONE_WIDTH
+    xor edx, edx
+    div rcx
+    inc rax
+    imul rax, rax, OUT_ZONE_WIDTH   # rax = start of the next zone
+    lea rcx, [rip + _out_width]
+    mov rcx, QWORD PTR [rcx + rdi*8]
+    cmp rcx, OUT_WIDTH_INFINITE
+    je .Lout_zone_pad
+    lea rdx, [rax + OUT_ZONE_WIDTH]
+    cmp rdx, rcx
+    ja _out_newline
+.Lout_zone_pad:
+    mov rsi, rax
+    jmp _out_pad_to
+
+# ------------------------------------------------------------------------------
+# _out_tab - Move to a column (internal)
+# ------------------------------------------------------------------------------
+# Columns past the width wrap around. If the cursor is already past the
+# column, the move continues on the next line.
+#
+# Arguments:
+#   rdi = channel
+#   rsi = column (1-based)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_tab:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    mov rbx, rdi
+    dec rsi
+    jns .Lout_tab_positive
+    xor esi, esi            # columns below 1 mean column 1
+.Lout_tab_positive:
+    lea rcx, [rip + _out_width]
+    mov rcx, QWORD PTR [rcx + rbx*8]
+    cmp rcx, OUT_WIDTH_INFINITE
+    je .Lout_tab_move
+    mov rax, rsi
+    xor edx, edx
+    div rcx
+    mov rsi, rdx            # column modulo width
+.Lout_tab_move:
+    mov r12, rsi            # r12 = target column (0-based)
+    lea rax, [rip + _out_col]
+    cmp QWORD PTR [rax + rbx*8], r12
+    jbe .Lout_tab_pad
+    mov rdi, rbx
+    call _out_newline
+.Lout_tab_pad:
+    mov rdi, rbx
+    mov rsi, r12
+    call _out_pad_to
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_spc - Write spaces (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = channel
+#   rsi = count (negative means 0; counts past the width wrap around)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_spc:
+    test rsi, rsi
+    jle .Lout_spc_done
+    lea rcx, [rip + _out_width]
+    mov rcx, QWORD PTR [rcx + rdi*8]
+    cmp rcx, OUT_WIDTH_INFINITE
+    je .Lout_spc_write
+    mov rax, rsi
+    xor edx, edx
+    div rcx
+    mov rsi, rdx
+.Lout_spc_write:
+    jmp _out_spaces
+.Lout_spc_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_pad_to - Write spaces up to a column on the current line (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = channel
+#   rsi = target column (0-based, not before the current column)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_pad_to:
+    lea rax, [rip + _out_col]
+    sub rsi, QWORD PTR [rax + rdi*8]
+    # Fall through
+
+# ------------------------------------------------------------------------------
+# _out_spaces - Write spaces to a channel (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = channel
+#   rsi = count
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_out_spaces:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    mov rbx, rdi
+    mov r12, rsi
+.Lout_spaces_loop:
+    test r12, r12
+    jle .Lout_spaces_done
+    mov rdx, r12
+    cmp rdx, 16
+    jbe .Lout_spaces_chunk
+    mov edx, 16
+.Lout_spaces_chunk:
+    sub r12, rdx
+    mov rdi, rbx
+    lea rsi, [rip + _out_blanks]
+    call _out_write
+    jmp .Lout_spaces_loop
+.Lout_spaces_done:
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_screen_char - Character code at a text screen position (SCREEN function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = row (1-25)
+#   rsi = column (1-80)
+#
+# Returns:
+#   rax = character code (32 for a blank cell)
+#   A position off the screen -> "Illegal function call", exit 1
+# ------------------------------------------------------------------------------
+.globl _rt_screen_char
+_rt_screen_char:
+    lea rax, [rdi - 1]
+    cmp rax, SCR_ROWS
+    jae _rt_illegal_call
+    lea rcx, [rsi - 1]
+    cmp rcx, SCR_COLS
+    jae _rt_illegal_call
+    imul rax, rax, SCR_COLS
+    add rax, rcx
+    lea rdx, [rip + _scr_text]
+    movzx eax, BYTE PTR [rdx + rax]
+    ret
+
+# ------------------------------------------------------------------------------
+# _scr_clear - Blank the text screen and home its cursor (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_scr_clear:
+    push rbp
+    mov rbp, rsp
+    lea rdi, [rip + _scr_text]
+    mov esi, 32
+    mov edx, SCR_ROWS * SCR_COLS
+    call {libc}memset
+    mov QWORD PTR [rip + _scr_row], 0
+    mov QWORD PTR [rip + _scr_col], 0
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _scr_track - Record console output on the text screen (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = pointer
+#   rsi = length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_scr_track:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    mov rbx, rdi            # rbx = next byte
+    lea r12, [rdi + rsi]    # r12 = end
+.Lscr_track_loop:
+    cmp rbx, r12
+    jae .Lscr_track_done
+    movzx eax, BYTE PTR [rbx]
+    inc rbx
+    cmp eax, 10
+    je .Lscr_track_newline
+    cmp eax, 13
+    je .Lscr_track_return
+    cmp eax, 32
+    jb .Lscr_track_loop     # other control characters take no cell
+    mov rcx, QWORD PTR [rip + _scr_col]
+    cmp rcx, SCR_COLS
+    jb .Lscr_track_store
+    call _scr_newline       # past the right edge: wrap first
+    movzx eax, BYTE PTR [rbx - 1]
+    xor ecx, ecx
+.Lscr_track_store:
+    mov rdx, QWORD PTR [rip + _scr_row]
+    imul rdx, rdx, SCR_COLS
+    add rdx, rcx
+    lea rsi, [rip + _scr_text]
+    mov BYTE PTR [rsi + rdx], al
+    inc rcx
+    mov QWORD PTR [rip + _scr_col], rcx
+    jmp .Lscr_track_loop
+.Lscr_track_newline:
+    call _scr_newline
+    jmp .Lscr_track_loop
+.Lscr_track_return:
+    mov QWORD PTR [rip + _scr_col], 0
+    jmp .Lscr_track_loop
+.Lscr_track_done:
+    pop r12
+    pop rbx
+    leave
+    ret
+
 # ------------------------------------------------------------------------------
-# Called when the GOSUB return stack is exhausted. Prints an error message
-# and terminates the program with exit code 1.
+# _scr_newline - Move the text screen cursor to the next line (internal)
+# ------------------------------------------------------------------------------
+# On the last row, the screen scrolls up one line instead.
 #
 # Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_scr_newline:
+    mov QWORD PTR [rip + _scr_col], 0
+    mov rax, QWORD PTR [rip + _scr_row]
+    inc rax
+    cmp rax, SCR_ROWS
+    jae .Lscr_newline_scroll
+    mov QWORD PTR [rip + _scr_row], rax
+    ret
+.Lscr_newline_scroll:
+    push rbp
+    mov rbp, rsp
+    # memmove(text, text + SCR_COLS, (SCR_ROWS - 1) * SCR_COLS)
+    lea rdi, [rip + _scr_text]
+    lea rsi, [rdi + SCR_COLS]
+    mov edx, (SCR_ROWS - 1) * SCR_COLS
+    call {libc}memmove
+    # memset(last row, ' ', SCR_COLS)
+    lea rdi, [rip + _scr_text + (SCR_ROWS - 1) * SCR_COLS]
+    mov esi, 32
+    mov edx, SCR_COLS
+    call {libc}memset
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_error - Report a runtime error and end the program
+# ------------------------------------------------------------------------------
+# Prints the message to stderr, as "<message> in <line>" once the program has
+# passed a numbered line (codegen keeps _rt_cur_line up to date), and exits with
+# code 1. Output PRINTed so far is flushed first, so it comes before the
+# message. Reached by jump, with any stack alignment.
+#
+# Arguments:
+#   rdi = message (null-terminated)
+#
 # Returns: never (calls exit)
 # ------------------------------------------------------------------------------
-.globl _rt_gosub_overflow
-_rt_gosub_overflow:
+.globl _rt_error
+_rt_error:
     push rbp
     mov rbp, rsp
-    lea rdi, [rip + _gosub_overflow_msg]
+    and rsp, -16            # May be entered with any stack alignment
+    push rdi                # message (pushed twice to keep alignment)
+    push rdi
+    xor edi, edi
+    call {libc}fflush       # fflush(NULL) flushes every output stream
+    # dprintf(2, "%s in %ld\n", message, line), or "%s\n" before any line
+    mov edi, 2
+    lea rsi, [rip + _error_fmt]
+    mov rdx, QWORD PTR [rsp]
+    mov rcx, QWORD PTR [rip + _rt_cur_line]
+    test rcx, rcx
+    jz .Lerror_print
+    lea rsi, [rip + _error_line_fmt]
+.Lerror_print:
     xor eax, eax
-    call {libc}printf
+    call {libc}dprintf
     mov edi, 1              # exit code 1
     call {libc}exit
+
+# ------------------------------------------------------------------------------
+# _rt_gosub_overflow - Handle GOSUB stack overflow error
+# ------------------------------------------------------------------------------
+# Called when the GOSUB return stack is exhausted.
+#
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_gosub_overflow
+_rt_gosub_overflow:
+    lea rdi, [rip + _gosub_overflow_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_gosub_underflow - Handle RETURN without GOSUB
+# ------------------------------------------------------------------------------
+# Called when RETURN finds no return address pushed by the main program or by
+# the current SUB/FUNCTION call.
+#
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_gosub_underflow
+_rt_gosub_underflow:
+    lea rdi, [rip + _gosub_underflow_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_overflow - Handle INTEGER/LONG overflow
+# ------------------------------------------------------------------------------
+# Called (with --overflow-check) when an INTEGER or LONG result falls outside
+# its type's range.
+#
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_overflow
+_rt_overflow:
+    lea rdi, [rip + _overflow_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_subscript_range - Handle an array subscript outside the array's DIM
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_subscript_range
+_rt_subscript_range:
+    lea rdi, [rip + _subscript_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_div_zero - Handle division by zero (/, \ or MOD)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_div_zero
+_rt_div_zero:
+    lea rdi, [rip + _div_zero_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_bad_file - Handle a file number that is out of range or not open
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_bad_file
+_rt_bad_file:
+    lea rdi, [rip + _bad_file_msg]
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_type_mismatch - Handle a value of the wrong type for its PRINT USING field
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_type_mismatch
+_rt_type_mismatch:
+    lea rdi, [rip + _type_mismatch_msg]
+    jmp _rt_error
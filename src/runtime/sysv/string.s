@@ -35,31 +35,68 @@
 #
 # Arguments:
 #   rdi = pointer to string
-#   rsi = length (currently ignored - strtod reads until non-numeric)
+#   rsi = length (ignored unless the console's decimal point isn't '.' -
+#         strtod reads until non-numeric)
 #
 # Returns:
 #   xmm0 = parsed double value
 #
 # Note: We pass NULL as endptr to strtod since we don't need to know where
 # parsing stopped. The string should be null-terminated for strtod.
+#
+# With another decimal point from the locale (see _rt_init_locale), strtod
+# reads a copy of the first VAL_MAX bytes with that decimal point made '.'.
 # ------------------------------------------------------------------------------
+.equ VAL_MAX, 255
+
 .globl _rt_val
 _rt_val:
     push rbp
     mov rbp, rsp
+    movzx eax, BYTE PTR [rip + _out_decimal_point]
+    cmp eax, '.'
+    jne .Lval_locale
     xor rsi, rsi            # endptr = NULL (2nd arg)
     # rdi already has string ptr (1st arg)
     call {libc}strtod       # returns double in xmm0
     leave
     ret
+.Lval_locale:
+    sub rsp, VAL_MAX + 1    # the copy and its NUL
+    cmp rsi, VAL_MAX
+    jbe .Lval_copy
+    mov esi, VAL_MAX
+.Lval_copy:
+    xor ecx, ecx
+.Lval_copy_loop:
+    cmp rcx, rsi
+    jae .Lval_copied
+    movzx edx, BYTE PTR [rdi + rcx]
+    cmp edx, eax
+    jne .Lval_copy_byte
+    mov edx, '.'
+.Lval_copy_byte:
+    mov BYTE PTR [rsp + rcx], dl
+    inc rcx
+    jmp .Lval_copy_loop
+.Lval_copied:
+    mov BYTE PTR [rsp + rcx], 0
+    mov rdi, rsp
+    xor esi, esi
+    call {libc}strtod
+    leave
+    ret
 
 # ------------------------------------------------------------------------------
 # _rt_str - Convert number to string (STR$ function)
 # ------------------------------------------------------------------------------
-# Formats a number as a string using %g format (compact representation).
+# Formats a number as PRINT does (see _format_number), with a leading space
+# for a number that isn't negative, where the sign would go.
 #
 # Arguments:
 #   xmm0 = number to convert (double)
+#   edi  = significant digits for non-whole values (7 for SINGLE, 0 for as
+#          many as a DOUBLE needs)
 #
 # Returns:
 #   rax = pointer to string (_str_buf)
@@ -71,24 +108,21 @@ _rt_val:
 _rt_str:
     push rbp
     mov rbp, rsp
-    sub rsp, 16                     # Stack alignment
-    # sprintf(buffer, "%g", value)
-    lea rdi, [rip + _str_buf]       # destination buffer (1st arg)
-    lea rsi, [rip + _fmt_float]     # format string (2nd arg)
-    mov eax, 1                      # 1 vector register arg (xmm0)
-    call {libc}sprintf
-    # Calculate result length
-    lea rax, [rip + _str_buf]
-    mov rdx, rax                    # save ptr
-    xor rcx, rcx                    # length counter
-.Lstr_len:
-    cmp BYTE PTR [rax + rcx], 0
+    # _format_number(buffer + 1, digits, value), leaving room for the space
+    mov esi, edi
+    lea rdi, [rip + _str_buf + 1]
+    call _format_number
+    mov rdx, rax                    # length
+    lea rdi, [rip + _str_buf + 1]
+    mov rsi, rax
+    call _out_locale_point
+    lea rax, [rip + _str_buf + 1]
+    cmp BYTE PTR [rax], 45          # '-'
     je .Lstr_done
-    inc rcx
-    jmp .Lstr_len
+    dec rax
+    mov BYTE PTR [rax], 32          # ' '
+    inc rdx
 .Lstr_done:
-    mov rax, rdx                    # restore ptr
-    mov rdx, rcx                    # length
     leave
     ret
 
@@ -223,10 +257,12 @@ _rt_mid:
 #   rax = position (1-based) or 0 if not found
 #
 # Algorithm:
-#   1. Adjust haystack pointer and length based on start position
-#   2. At each position, use memcmp to check for match
-#   3. If match found, return 1-based position
-#   4. If no more room for needle, return 0
+#   1. A start before 1 searches from 1; an empty needle is found at the
+#      start, and anything else past the end isn't found
+#   2. Adjust haystack pointer and length based on start position
+#   3. At each position, use memcmp to check for match
+#   4. If match found, return 1-based position
+#   5. If no more room for needle, return 0
 #
 # Register usage (callee-saved registers for surviving memcmp calls):
 #   rbx = current position (0-based)
@@ -252,13 +288,19 @@ _rt_instr:
     mov r14, rdx            # needle ptr
     mov r15, rcx            # needle len
     mov rbx, r8             # start position (1-based)
+    cmp rbx, 1
+    jge .Linstr_start_ok
+    mov rbx, 1              # search from the first character
+.Linstr_start_ok:
+    # Special case: empty needle matches at the start position
+    test r15, r15
+    jz .Linstr_at_start
+    cmp rbx, r13
+    jg .Linstr_not_found    # start is past the end
     # Adjust for start position
     dec rbx                 # convert to 0-based
     add r12, rbx            # advance haystack ptr
     sub r13, rbx            # reduce remaining length
-    # Special case: empty needle matches at current position
-    test r15, r15
-    jz .Linstr_at_start
 .Linstr_loop:
     # Check if enough room for needle
     cmp r13, r15
@@ -280,9 +322,8 @@ _rt_instr:
     add rax, 1              # convert to 1-based
     jmp .Linstr_done
 .Linstr_at_start:
-    # Empty needle: return current position
+    # Empty needle: return the start position
     mov rax, rbx
-    add rax, 1              # convert to 1-based
     jmp .Linstr_done
 .Linstr_not_found:
     xor rax, rax            # return 0
@@ -297,6 +338,90 @@ _rt_instr:
     leave
     ret
 
+# ------------------------------------------------------------------------------
+# _rt_instrrev - Find the last substring position (INSTRREV function)
+# ------------------------------------------------------------------------------
+# Searches backwards for needle in haystack, for a match starting at or
+# before a given position. Returns 1-based position of the last such match,
+# or 0 if there is none.
+#
+# Arguments:
+#   rdi = haystack pointer
+#   rsi = haystack length
+#   rdx = needle pointer
+#   rcx = needle length
+#   r8  = start position (1-based; past the end means the end)
+#
+# Returns:
+#   rax = position (1-based) or 0 if not found
+#
+# Register usage (callee-saved registers for surviving memcmp calls):
+#   rbx = candidate position (1-based)
+#   r12 = haystack pointer
+#   r13 = haystack length
+#   r14 = needle pointer
+#   r15 = needle length
+# ------------------------------------------------------------------------------
+.globl _rt_instrrev
+_rt_instrrev:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    sub rsp, 8              # Align stack for calls
+    mov r12, rdi            # haystack ptr
+    mov r13, rsi            # haystack len
+    mov r14, rdx            # needle ptr
+    mov r15, rcx            # needle len
+    mov rbx, r8             # start position (1-based)
+    cmp rbx, r13
+    jle .Linstrrev_start_ok
+    mov rbx, r13            # search from the last character
+.Linstrrev_start_ok:
+    # Empty needle: found at the start position
+    test r15, r15
+    jz .Linstrrev_loop
+    # The last position a whole needle fits at
+    mov rax, r13
+    sub rax, r15
+    inc rax
+    cmp rbx, rax
+    jle .Linstrrev_loop
+    mov rbx, rax
+.Linstrrev_loop:
+    cmp rbx, 1
+    jl .Linstrrev_not_found # before the first character
+    test r15, r15
+    jz .Linstrrev_found
+    # Compare: memcmp(haystack + position - 1, needle, needle_len)
+    mov rdi, r12
+    add rdi, rbx
+    dec rdi
+    mov rsi, r14
+    mov rdx, r15
+    call {libc}memcmp
+    test eax, eax
+    jz .Linstrrev_found
+    dec rbx                 # one position back
+    jmp .Linstrrev_loop
+.Linstrrev_found:
+    mov rax, rbx
+    jmp .Linstrrev_done
+.Linstrrev_not_found:
+    xor rax, rax            # return 0
+.Linstrrev_done:
+    add rsp, 8
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
 # ------------------------------------------------------------------------------
 # _rt_strcat - Concatenate two strings (+ operator)
 # ------------------------------------------------------------------------------
@@ -372,3 +497,56 @@ _rt_strcat:
     pop r12
     leave
     ret
+
+# ------------------------------------------------------------------------------
+# _rt_strcmp - Compare two strings (=, <>, <, >, <=, >= operators)
+# ------------------------------------------------------------------------------
+# Compares byte by byte (as unsigned characters); when one string is a prefix
+# of the other, the shorter one is less.
+#
+# Arguments:
+#   rdi = left string pointer
+#   rsi = left string length
+#   rdx = right string pointer
+#   rcx = right string length
+#
+# Returns:
+#   eax = -1, 0 or 1 as left is less than, equal to or greater than right
+# ------------------------------------------------------------------------------
+.globl _rt_strcmp
+_rt_strcmp:
+    push rbp
+    mov rbp, rsp
+    push r12
+    push r13
+
+    mov r12, rsi            # left len
+    mov r13, rcx            # right len
+
+    # memcmp(left, right, min(left_len, right_len))
+    mov rsi, rdx
+    mov rdx, r12
+    cmp rdx, r13
+    cmova rdx, r13
+    call {libc}memcmp
+    test eax, eax
+    jnz .Lstrcmp_sign
+
+    # Common prefix is equal: the longer string is greater
+    cmp r12, r13
+    seta al
+    setb cl
+    movzx eax, al
+    movzx ecx, cl
+    sub eax, ecx
+    jmp .Lstrcmp_done
+
+.Lstrcmp_sign:
+    sar eax, 31             # -1 if negative, else 0
+    or eax, 1               # -1 or 1
+
+.Lstrcmp_done:
+    pop r13
+    pop r12
+    leave
+    ret
@@ -12,6 +12,9 @@
 #   0       8     Type tag: TYPE_INTEGER, TYPE_FLOAT, or TYPE_STRING
 #   8       8     Value: integer, double bits, or string pointer
 #
+#   A string's length is the quad just before its bytes, so it may hold
+#   any byte, NULs included; a NUL follows the bytes for strtod.
+#
 # Global State:
 #   _data_table = Array of 16-byte entries (generated by compiler)
 #   _data_count = Number of entries in table
@@ -90,33 +93,17 @@ _rt_read_number:
 # ------------------------------------------------------------------------------
 .globl _rt_read_string
 _rt_read_string:
-    push rbp
-    mov rbp, rsp
-    push rdi                            # rdi is callee-saved
-    sub rsp, 40                         # Shadow space + alignment
-
     # Calculate entry address
     mov rax, QWORD PTR [rip + _data_ptr]
     shl rax, 4                          # offset = index * 16
     lea rcx, [rip + _data_table]
     add rcx, rax                        # rcx = entry address
 
-    # Load string pointer and save for later
-    mov rdi, QWORD PTR [rcx + 8]        # rdi = string pointer
-
-    # Calculate length using lstrlenA (Win32 API)
-    mov rcx, rdi
-    call lstrlenA                       # returns length in eax
-
-    mov rdx, rax                        # length → rdx
-    mov rax, rdi                        # string pointer → rax
+    mov rax, QWORD PTR [rcx + 8]        # rax = string pointer
+    mov rdx, QWORD PTR [rax - 8]        # rdx = length, stored before it
 
     # Advance to next entry
     inc QWORD PTR [rip + _data_ptr]
-
-    add rsp, 40
-    pop rdi
-    leave
     ret
 
 # ------------------------------------------------------------------------------
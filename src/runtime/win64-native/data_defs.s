@@ -13,9 +13,40 @@
 
 # Format strings for sprintf (number formatting)
 _fmt_int: .asciz "%lld"
-_fmt_float: .asciz "%g"
+_fmt_digits: .asciz "%.*g"
 
-# Error messages
-_gosub_overflow_msg: .ascii "Error: GOSUB stack overflow\r\n"
-.equ _gosub_overflow_msg_len, 30
+# Runtime errors (see _rt_error in print.s): the last numbered line reached
+.globl _rt_cur_line
+_rt_cur_line: .quad 0
+_error_line_fmt: .asciz " in %lld"
+_error_buf: .skip 32
+_gosub_overflow_msg: .ascii "GOSUB stack overflow"
+.equ _gosub_overflow_msg_len, 20
+_gosub_underflow_msg: .ascii "RETURN without GOSUB"
+.equ _gosub_underflow_msg_len, 20
+_overflow_msg: .ascii "Overflow"
+.equ _overflow_msg_len, 8
+_subscript_msg: .ascii "Subscript out of range"
+.equ _subscript_msg_len, 22
+_div_zero_msg: .ascii "Division by zero"
+.equ _div_zero_msg_len, 16
+_bad_file_msg: .ascii "Bad file number"
+.equ _bad_file_msg_len, 15
+_type_mismatch_msg: .ascii "Type mismatch"
+.equ _type_mismatch_msg_len, 13
 
+
+# Output channels: 0 = console, 1-15 = files (see print.s)
+_out_col: .skip 128
+_out_width: .quad 80
+    .rept 15
+    .quad 255
+    .endr
+_out_blanks: .ascii "                "
+_out_decimal_point: .ascii "."  # the console's (see _rt_init_locale)
+_locale_env: .asciz ""          # setlocale: the environment's locale
+_locale_c: .asciz "C"
+# Text screen for SCREEN(row, col) (see print.s)
+_scr_text: .skip 2000, 32
+_scr_row: .quad 0
+_scr_col: .quad 0
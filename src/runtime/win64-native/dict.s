@@ -0,0 +1,564 @@
+# ==============================================================================
+# BASIC Runtime: Dictionaries
+# ==============================================================================
+#
+# DICTNEW, DICTSET, DICTDEL, DICTGET$, DICTHAS, DICTCOUNT and DICTKEY$:
+# string-keyed associative arrays, an xbasic64 extension.
+#
+# Handles:
+#   DICTNEW returns a handle, 1 to DICT_MAX - 1: the dictionary's index in
+#   _dict_table. Dictionaries last until the program ends. Any other number
+#   given as a handle is an illegal function call.
+#
+# Layout:
+#   A dictionary (DICT_SIZE bytes) keeps its entries in the order their keys
+#   were first set, each a copy of the key and of the value. Like other
+#   strings they are never freed. A hash index of 2 * DICT_CAP 32-bit slots,
+#   each 0 (empty) or an entry number + 1, finds keys by FNV-1a hash with
+#   linear probing. The entries grow by doubling, and the index is rebuilt
+#   when they do and after a DICTDEL.
+#
+# Memory comes from the process heap (HeapAlloc, HeapReAlloc, HeapFree).
+#
+# Windows x64 calling convention:
+#   - Arguments in rcx, rdx, r8, r9, then the stack
+#   - 32-byte shadow space required before calls
+# ==============================================================================
+
+.equ DICT_MAX, 256          # handles 1 to DICT_MAX - 1
+.equ DICT_ENTRIES, 0        # dictionary: the entry array
+.equ DICT_COUNT, 8          #   entries in use
+.equ DICT_CAP, 16           #   entries there is room for
+.equ DICT_INDEX, 24         #   the hash index
+.equ DICT_SIZE, 32
+.equ DICT_FIRST_CAP, 8
+.equ ENTRY_KEY, 0           # entry: key pointer and length
+.equ ENTRY_KEY_LEN, 8
+.equ ENTRY_VALUE, 16        #   value pointer and length
+.equ ENTRY_VALUE_LEN, 24
+.equ ENTRY_SHIFT, 5         # 32 bytes per entry
+
+.data
+_dict_table: .skip DICT_MAX * 8
+_dict_next: .quad 1         # the handle the next DICTNEW returns
+_dict_empty: .byte 0        # DICTGET$ of a missing key
+_dict_full_msg: .ascii "Out of memory"
+_dict_full_msg_len = 13
+
+.text
+
+# ------------------------------------------------------------------------------
+# _rt_dict_new - Create an empty dictionary (DICTNEW function)
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   rax = handle ("Out of memory" after DICT_MAX - 1 of them)
+# ------------------------------------------------------------------------------
+.globl _rt_dict_new
+_rt_dict_new:
+    mov rax, QWORD PTR [rip + _dict_next]
+    cmp rax, DICT_MAX
+    jae .Ldict_full
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32
+
+    # HeapAlloc(GetProcessHeap(), HEAP_ZERO_MEMORY, DICT_SIZE)
+    call GetProcessHeap
+    mov rcx, rax
+    mov edx, 8              # HEAP_ZERO_MEMORY
+    mov r8d, DICT_SIZE
+    call HeapAlloc
+    mov rcx, QWORD PTR [rip + _dict_next]
+    lea rdx, [rip + _dict_table]
+    mov QWORD PTR [rdx + rcx*8], rax
+    lea rax, [rcx + 1]
+    mov QWORD PTR [rip + _dict_next], rax
+    mov rax, rcx
+    leave
+    ret
+.Ldict_full:
+    lea rcx, [rip + _dict_full_msg]
+    mov edx, _dict_full_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_dict_set - Set a key's value (DICTSET statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = handle
+#   rdx = key pointer
+#   r8  = key length
+#   r9  = value pointer
+#   [rsp+40] = value length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_dict_set
+_rt_dict_set:
+    call _dict_check
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    push rsi
+    sub rsp, 32             # Shadow space (must be 0 mod 16)
+    mov rbx, rax            # rbx = dictionary
+    mov r12, rdx            # r12 = key
+    mov r13, r8             # r13 = key length
+    mov r15, QWORD PTR [rbp + 48]   # r15 = value length
+    mov rcx, r9
+    mov rdx, r15
+    call _dict_copy
+    mov r14, rax            # r14 = copy of the value
+
+    mov rcx, rbx
+    mov rdx, r12
+    mov r8, r13
+    call _dict_slot
+    test rax, rax
+    jz .Ldict_set_new
+    mov ecx, DWORD PTR [rax]
+    test ecx, ecx
+    jz .Ldict_set_new
+    # A key already there gets the new value
+    dec ecx
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rbx + DICT_ENTRIES]
+    mov QWORD PTR [rcx + ENTRY_VALUE], r14
+    mov QWORD PTR [rcx + ENTRY_VALUE_LEN], r15
+    jmp .Ldict_set_done
+
+.Ldict_set_new:
+    # Full: double the entries
+    mov rax, QWORD PTR [rbx + DICT_COUNT]
+    cmp rax, QWORD PTR [rbx + DICT_CAP]
+    jb .Ldict_set_add
+    mov rsi, QWORD PTR [rbx + DICT_CAP]
+    add rsi, rsi
+    mov ecx, DICT_FIRST_CAP
+    cmovz rsi, rcx
+    mov QWORD PTR [rbx + DICT_CAP], rsi
+    shl rsi, ENTRY_SHIFT    # rsi = new size in bytes
+    call GetProcessHeap
+    mov rcx, rax
+    xor edx, edx
+    mov r9, rsi
+    mov r8, QWORD PTR [rbx + DICT_ENTRIES]
+    test r8, r8
+    jz .Ldict_set_first
+    # HeapReAlloc(heap, 0, entries, size)
+    call HeapReAlloc
+    jmp .Ldict_set_grown
+.Ldict_set_first:
+    # HeapAlloc(heap, 0, size): HeapReAlloc needs an existing block
+    mov r8, r9
+    call HeapAlloc
+.Ldict_set_grown:
+    mov QWORD PTR [rbx + DICT_ENTRIES], rax
+    mov rcx, rbx
+    call _dict_reindex
+
+.Ldict_set_add:
+    # New entry at the end: copies of the key and value
+    mov rcx, r12
+    mov rdx, r13
+    call _dict_copy
+    mov rcx, QWORD PTR [rbx + DICT_COUNT]
+    mov rdx, rcx
+    shl rdx, ENTRY_SHIFT
+    add rdx, QWORD PTR [rbx + DICT_ENTRIES]
+    mov QWORD PTR [rdx + ENTRY_KEY], rax
+    mov QWORD PTR [rdx + ENTRY_KEY_LEN], r13
+    mov QWORD PTR [rdx + ENTRY_VALUE], r14
+    mov QWORD PTR [rdx + ENTRY_VALUE_LEN], r15
+    inc rcx
+    mov QWORD PTR [rbx + DICT_COUNT], rcx
+    # and in the index, where the search for the key stopped
+    mov rcx, rbx
+    mov rdx, r12
+    mov r8, r13
+    call _dict_slot
+    mov rcx, QWORD PTR [rbx + DICT_COUNT]
+    mov DWORD PTR [rax], ecx
+
+.Ldict_set_done:
+    add rsp, 32
+    pop rsi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_del - Delete a key (DICTDEL statement)
+# ------------------------------------------------------------------------------
+# Deleting a key that isn't there does nothing.
+#
+# Arguments:
+#   rcx = handle
+#   rdx = key pointer
+#   r8  = key length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_dict_del
+_rt_dict_del:
+    call _dict_check
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40
+    mov rbx, rax            # rbx = dictionary
+    mov rcx, rax
+    call _dict_slot
+    test rax, rax
+    jz .Ldict_del_done
+    mov ecx, DWORD PTR [rax]
+    test ecx, ecx
+    jz .Ldict_del_done
+    # Move the later entries down over it, a quadword at a time
+    dec ecx
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rbx + DICT_ENTRIES]     # rcx = the entry
+    mov rdx, QWORD PTR [rbx + DICT_COUNT]
+    dec rdx
+    mov QWORD PTR [rbx + DICT_COUNT], rdx
+    shl rdx, ENTRY_SHIFT
+    add rdx, QWORD PTR [rbx + DICT_ENTRIES]     # rdx = the new end
+.Ldict_del_move:
+    cmp rcx, rdx
+    jae .Ldict_del_moved
+    mov rax, QWORD PTR [rcx + 32]
+    mov QWORD PTR [rcx], rax
+    add rcx, 8
+    jmp .Ldict_del_move
+.Ldict_del_moved:
+    mov rcx, rbx
+    call _dict_reindex
+.Ldict_del_done:
+    add rsp, 40
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_get - A key's value (DICTGET$ function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = handle
+#   rdx = key pointer
+#   r8  = key length
+#
+# Returns:
+#   rax = value pointer
+#   rdx = value length; empty if the key isn't there
+# ------------------------------------------------------------------------------
+.globl _rt_dict_get
+_rt_dict_get:
+    call _dict_check
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40
+    mov rbx, rax            # rbx = dictionary
+    mov rcx, rax
+    call _dict_slot
+    test rax, rax
+    jz .Ldict_get_missing
+    mov ecx, DWORD PTR [rax]
+    test ecx, ecx
+    jz .Ldict_get_missing
+    dec ecx
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rbx + DICT_ENTRIES]
+    mov rax, QWORD PTR [rcx + ENTRY_VALUE]
+    mov rdx, QWORD PTR [rcx + ENTRY_VALUE_LEN]
+    jmp .Ldict_get_done
+.Ldict_get_missing:
+    lea rax, [rip + _dict_empty]
+    xor edx, edx
+.Ldict_get_done:
+    add rsp, 40
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_has - Whether a dictionary has a key (DICTHAS function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = handle
+#   rdx = key pointer
+#   r8  = key length
+#
+# Returns:
+#   rax = -1 if the key is there, else 0
+# ------------------------------------------------------------------------------
+.globl _rt_dict_has
+_rt_dict_has:
+    call _dict_check
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32
+    mov rcx, rax
+    call _dict_slot
+    test rax, rax
+    jz .Ldict_has_done
+    mov eax, DWORD PTR [rax]
+    neg eax                 # CF = entry number not 0
+    sbb rax, rax
+.Ldict_has_done:
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_count - Number of keys in a dictionary (DICTCOUNT function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = handle
+#
+# Returns:
+#   rax = number of keys
+# ------------------------------------------------------------------------------
+.globl _rt_dict_count
+_rt_dict_count:
+    call _dict_check
+    mov rax, QWORD PTR [rax + DICT_COUNT]
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_dict_key - A dictionary's nth key (DICTKEY$ function)
+# ------------------------------------------------------------------------------
+# Keys are numbered from 1 in the order they were first set.
+#
+# Arguments:
+#   rcx = handle
+#   rdx = key number (1 to DICTCOUNT, else an illegal function call)
+#
+# Returns:
+#   rax = key pointer
+#   rdx = key length
+# ------------------------------------------------------------------------------
+.globl _rt_dict_key
+_rt_dict_key:
+    call _dict_check
+    lea rcx, [rdx - 1]
+    cmp rcx, QWORD PTR [rax + DICT_COUNT]
+    jae _rt_illegal_call    # unsigned compare also rejects 0 and negatives
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rax + DICT_ENTRIES]
+    mov rax, QWORD PTR [rcx + ENTRY_KEY]
+    mov rdx, QWORD PTR [rcx + ENTRY_KEY_LEN]
+    ret
+
+# ------------------------------------------------------------------------------
+# _dict_check - The dictionary a handle refers to (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = handle
+#
+# Returns: rax = the dictionary; an illegal function call for a handle
+# DICTNEW didn't return. Uses only rax and r11, so the caller's arguments
+# are kept.
+# ------------------------------------------------------------------------------
+_dict_check:
+    lea rax, [rcx - 1]
+    cmp rax, DICT_MAX - 2
+    ja _rt_illegal_call     # unsigned compare also rejects 0 and negatives
+    lea r11, [rip + _dict_table]
+    mov rax, QWORD PTR [r11 + rcx*8]
+    test rax, rax
+    jz _rt_illegal_call
+    ret
+
+# ------------------------------------------------------------------------------
+# _dict_slot - Find a key's slot in a dictionary's index (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = dictionary
+#   rdx = key pointer
+#   r8  = key length
+#
+# Returns:
+#   rax = the key's slot (a DWORD holding its entry number + 1), or the
+#         empty slot where the search stopped if it isn't there; 0 if the
+#         dictionary has never had an entry
+# ------------------------------------------------------------------------------
+_dict_slot:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    push rsi
+    sub rsp, 32
+    mov rbx, rcx            # rbx = dictionary
+    mov r12, rdx            # r12 = key
+    mov r13, r8             # r13 = key length
+    xor eax, eax
+    cmp QWORD PTR [rbx + DICT_INDEX], 0
+    je .Ldict_slot_done
+
+    # FNV-1a hash of the key
+    mov rax, 0xCBF29CE484222325
+    mov r9, 0x100000001B3
+    xor ecx, ecx
+.Ldict_hash:
+    cmp rcx, r13
+    jae .Ldict_hashed
+    movzx edx, BYTE PTR [r12 + rcx]
+    xor rax, rdx
+    imul rax, r9
+    inc rcx
+    jmp .Ldict_hash
+.Ldict_hashed:
+    mov r15, QWORD PTR [rbx + DICT_CAP]
+    lea r15, [r15 + r15 - 1]    # r15 = slot mask (2 * DICT_CAP slots)
+    mov r14, rax
+    and r14, r15            # r14 = slot number
+
+.Ldict_probe:
+    mov rsi, QWORD PTR [rbx + DICT_INDEX]
+    lea rsi, [rsi + r14*4]  # rsi = the slot
+    mov ecx, DWORD PTR [rsi]
+    test ecx, ecx
+    jz .Ldict_slot_found    # an empty slot ends the search
+    dec ecx
+    shl rcx, ENTRY_SHIFT
+    add rcx, QWORD PTR [rbx + DICT_ENTRIES]
+    cmp QWORD PTR [rcx + ENTRY_KEY_LEN], r13
+    jne .Ldict_probe_next
+    # memcmp(entry key, key, length)
+    mov rcx, QWORD PTR [rcx + ENTRY_KEY]
+    mov rdx, r12
+    mov r8, r13
+    call memcmp
+    test eax, eax
+    jz .Ldict_slot_found
+.Ldict_probe_next:
+    inc r14
+    and r14, r15
+    jmp .Ldict_probe
+
+.Ldict_slot_found:
+    mov rax, rsi
+.Ldict_slot_done:
+    add rsp, 32
+    pop rsi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _dict_reindex - Rebuild a dictionary's index (internal)
+# ------------------------------------------------------------------------------
+# Makes a new, empty index of 2 * DICT_CAP slots and adds every entry to it.
+#
+# Arguments:
+#   rcx = dictionary
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_dict_reindex:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push rsi
+    sub rsp, 32
+    mov rbx, rcx            # rbx = dictionary
+    call GetProcessHeap
+    mov rsi, rax            # rsi = heap
+    mov r8, QWORD PTR [rbx + DICT_INDEX]
+    test r8, r8
+    jz .Ldict_reindex_new
+    # HeapFree(heap, 0, index)
+    mov rcx, rsi
+    xor edx, edx
+    call HeapFree
+.Ldict_reindex_new:
+    # HeapAlloc(heap, HEAP_ZERO_MEMORY, 2 * DICT_CAP * 4)
+    mov rcx, rsi
+    mov edx, 8              # HEAP_ZERO_MEMORY
+    mov r8, QWORD PTR [rbx + DICT_CAP]
+    shl r8, 3
+    call HeapAlloc
+    mov QWORD PTR [rbx + DICT_INDEX], rax
+
+    xor r12d, r12d          # r12 = entry number
+.Ldict_reindex_loop:
+    cmp r12, QWORD PTR [rbx + DICT_COUNT]
+    jae .Ldict_reindex_done
+    mov r13, r12
+    shl r13, ENTRY_SHIFT
+    add r13, QWORD PTR [rbx + DICT_ENTRIES]
+    # Keys are distinct, so the search stops at an empty slot
+    mov rcx, rbx
+    mov rdx, QWORD PTR [r13 + ENTRY_KEY]
+    mov r8, QWORD PTR [r13 + ENTRY_KEY_LEN]
+    call _dict_slot
+    inc r12
+    mov DWORD PTR [rax], r12d
+    jmp .Ldict_reindex_loop
+.Ldict_reindex_done:
+    add rsp, 32
+    pop rsi
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _dict_copy - Copy a string for a dictionary to keep (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = pointer
+#   rdx = length
+#
+# Returns:
+#   rax = the copy (from HeapAlloc)
+# ------------------------------------------------------------------------------
+_dict_copy:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    sub rsp, 40
+    mov rbx, rcx
+    mov r12, rdx
+    # HeapAlloc(GetProcessHeap(), 0, length + 1)
+    call GetProcessHeap
+    mov rcx, rax
+    xor edx, edx
+    lea r8, [r12 + 1]
+    call HeapAlloc
+    mov r13, rax
+    # memcpy(copy, pointer, length)
+    mov rcx, rax
+    mov rdx, rbx
+    mov r8, r12
+    call memcpy
+    mov rax, r13
+    add rsp, 40
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
@@ -0,0 +1,1136 @@
+# ==============================================================================
+# BASIC Runtime: Event Trapping (Win64 Native - Pure Win32 API)
+# ==============================================================================
+#
+# ON KEY(n) GOSUB, KEY(n) ON/OFF/STOP, ON TIMER(n) GOSUB, TIMER ON/OFF/STOP,
+# ON BREAK GOSUB, BREAK ON/OFF/STOP, ON MOUSE GOSUB, MOUSE ON/OFF/STOP, the
+# MOUSEX, MOUSEY and MOUSEB functions, and the STICK and STRIG joystick
+# functions.
+#
+# Each event source is a numbered slot with a handler address (the GOSUB
+# target), a trap state and a pending flag. Slot 0 is the timer; slots 1-31
+# are keys, numbered as in GW-BASIC and QuickBASIC:
+#   1-10 = F1-F10, 11 = Up, 12 = Left, 13 = Right, 14 = Down,
+#   30 = F11, 31 = F12
+# slot 32 is Ctrl-C and slot 33 is a mouse button press.
+#
+# Trap states:
+#   OFF  - events are ignored
+#   ON   - events run the handler
+#   STOP - events are remembered and run the handler once the trap is ON
+#
+# When a program uses event trapping, the compiled code calls _rt_event_poll
+# at every statement boundary and at the top of every loop. If an event is
+# pending, the poll returns the handler address and the compiled code runs
+# it as a GOSUB. While a handler runs, its event is held as if stopped, and
+# _rt_event_return releases it when the handler RETURNs.
+#
+# The timer event occurs every n seconds of GetTickCount64 time while the
+# timer trap is not OFF, checked when the program polls. Turning the trap ON
+# from OFF starts the count again.
+#
+# Keys are read from stdin without blocking. From a console, key events are
+# read with ReadConsoleInputA and matched by virtual key code. From a pipe,
+# bytes are decoded from their ANSI/xterm escape sequences, as on Unix.
+# Other input read while keys are trapped is discarded.
+#
+# While the mouse trap is not OFF and stdin is a console, mouse input is
+# enabled in the console mode (and Quick Edit turned off) and MOUSE_EVENT
+# records are read with the keys; from a pipe, xterm SGR reports
+# "ESC [ < b ; x ; y M/m" are decoded as on Unix. They update the pointer
+# position (1-based column and row) and the buttons held down (1 = left,
+# 2 = right, 4 = middle), which MOUSEX, MOUSEY and MOUSEB return; a button
+# going down is the mouse event. The console mode is restored by MOUSE OFF
+# or when the program exits.
+#
+# STICK and STRIG read the first joystick with joyGetPosEx (winmm) on every
+# call. Joystick A is its X and Y axes and joystick B its Z and R axes (the
+# right stick of many gamepads); axes are scaled to 1-200 with 100 at the
+# centre. The four buttons A1, B1, A2 and B2 are its buttons 1-4. Without a
+# joystick the sticks stay centred and no button is pressed.
+#
+# Every program installs a console control handler for Ctrl-C and
+# Ctrl-Break from the start (codegen calls _rt_init_break). It runs on its
+# own thread: while BREAK is trapped it only marks the break event pending;
+# otherwise it ends the program through _rt_error with "Break" ("Break in
+# <line>").
+#
+# Win64 ABI:
+#   - Integer args: rcx, rdx, r8, r9 (then stack)
+#   - 32-byte shadow space required before every call
+#   - Callee-saved: rbx, rbp, rdi, rsi, r12-r15
+# ==============================================================================
+
+.equ EVT_SLOTS, 34
+.equ EVT_OFF, 0
+.equ EVT_ON, 1
+.equ EVT_STOP, 2
+.equ EVT_TIMER, 0           # timer slot
+.equ TIMER_MAX, 86400       # longest ON TIMER interval, in seconds
+.equ MS_PER_SEC, 1000
+.equ EVT_KEY_FIRST, 1       # F1
+.equ EVT_KEY_ARROWS, 14     # last of F1-F10 and the arrow keys
+.equ EVT_KEY_F11, 30
+.equ EVT_KEY_LAST, 31       # F12
+.equ EVT_BREAK, 32          # Ctrl-C slot
+.equ EVT_MOUSE, 33          # mouse slot
+.equ SGR_FIELDS, 3          # button, column and row in a mouse report
+.equ SGR_MAX, 9999          # largest field value we accept
+.equ SGR_NOT_BUTTON, 96     # motion (32) and wheel (64) reports
+.equ SGR_BUTTON_BITS, 3     # 0 = left, 1 = middle, 2 = right, 3 = none
+.equ CTRL_BREAK_EVENT, 1    # highest of CTRL_C_EVENT and CTRL_BREAK_EVENT
+.equ EVT_TILDE_MAX, 24      # highest n in "ESC [ n ~" we recognize
+.equ KEY_BUF_SIZE, 256      # room for a burst of mouse reports
+.equ CHAR_ESC, 27
+.equ JOY_AXES, 4            # joystick A x and y, joystick B x and y
+.equ JOY_BUTTON_BITS, 0xF   # A1, B1, A2, B2
+.equ JOY_STRIG_LAST, 7      # highest STRIG number
+.equ JOY_CENTER, 100
+.equ JOY_RANGE, 199         # STICK values run 1 to 1 + JOY_RANGE
+
+# JOYINFOEX
+.equ JOYINFOEX_SIZE, 52
+.equ JOY_RETURNALL, 0xFF
+.equ JOY_FLAGS_OFFSET, 4    # dwFlags
+.equ JOY_XPOS_OFFSET, 8     # dwXpos, dwYpos, dwZpos, dwRpos
+.equ JOY_BUTTONS_OFFSET, 32 # dwButtons
+
+# Console input records
+.equ KEY_EVENT, 1
+.equ MOUSE_EVENT, 2
+.equ INPUT_RECORD_SIZE, 20
+.equ KEY_DOWN_OFFSET, 4     # KEY_EVENT_RECORD.bKeyDown
+.equ VKEY_OFFSET, 10        # KEY_EVENT_RECORD.wVirtualKeyCode
+.equ MOUSE_X_OFFSET, 4      # MOUSE_EVENT_RECORD.dwMousePosition.X
+.equ MOUSE_Y_OFFSET, 6      # MOUSE_EVENT_RECORD.dwMousePosition.Y
+.equ MOUSE_BUTTONS_OFFSET, 8    # MOUSE_EVENT_RECORD.dwButtonState
+.equ MOUSE_BUTTON_BITS, 7   # left, right and middle, as MOUSEB numbers them
+.equ ENABLE_MOUSE_INPUT, 0x10
+.equ ENABLE_QUICK_EDIT_MODE, 0x40
+.equ ENABLE_EXTENDED_FLAGS, 0x80
+.equ VK_LEFT, 0x25          # VK_LEFT, VK_UP, VK_RIGHT, VK_DOWN
+.equ VK_DOWN, 0x28
+.equ VK_F1, 0x70            # VK_F1 - VK_F12
+.equ VK_F12, 0x7B
+
+.data
+_evt_handler: .skip EVT_SLOTS * 8
+_evt_state: .skip EVT_SLOTS
+_evt_pending: .skip EVT_SLOTS
+_evt_busy: .skip EVT_SLOTS
+_evt_nest: .skip EVT_SLOTS
+_evt_depth: .quad 0
+_evt_armed: .quad 0         # slots whose trap is not OFF
+_evt_keys_armed: .quad 0    # key and mouse slots whose trap is not OFF
+_evt_stdin_eof: .quad 0
+_evt_timer_interval: .quad 0    # ms
+_evt_timer_next: .quad 0        # ms, on the monotonic clock
+_evt_count: .quad 0         # console mode / event count / bytes available
+_evt_record: .skip INPUT_RECORD_SIZE
+_evt_mouse_x: .quad 0
+_evt_mouse_y: .quad 0
+_evt_mouse_b: .quad 0
+_evt_mouse_on: .quad 0      # 1 = console mouse input turned on
+_evt_mouse_atexit: .quad 0
+_evt_console_mode: .quad 0  # stdin console mode before mouse input
+_evt_key_buf: .skip KEY_BUF_SIZE
+# Key numbers for "ESC [ n ~", indexed by n (F1-F4 also arrive as ESC O P-S)
+_evt_tilde_keys: .byte 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+    .byte 1, 2, 3, 4, 5, 0, 6, 7, 8, 9, 10, 0, 30, 31
+# Key numbers for "ESC [ A" (Up), B (Down), C (Right), D (Left)
+_evt_arrow_keys: .byte 11, 14, 13, 12
+_joy_missing: .quad 0       # 1 = no joystick
+_joy_axes: .quad JOY_CENTER, JOY_CENTER, JOY_CENTER, JOY_CENTER
+_joy_down: .quad 0
+_joy_pressed: .quad 0
+_joy_info: .skip JOYINFOEX_SIZE
+# MOUSEB bits for SGR buttons 0 (left), 1 (middle) and 2 (right)
+_evt_sgr_buttons: .byte 1, 4, 2
+# Key numbers for VK_LEFT, VK_UP, VK_RIGHT, VK_DOWN
+_evt_vk_arrows: .byte 12, 11, 13, 14
+# Key numbers for VK_F1 - VK_F12
+_evt_vk_fkeys: .byte 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 30, 31
+_evt_break_msg: .ascii "Break"
+_evt_break_msg_len = 5
+
+.text
+
+# ------------------------------------------------------------------------------
+# _rt_on_key - Set the handler for a key (ON KEY(n) GOSUB)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = key number (1-14, 30, 31)
+#   rdx = handler address
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_on_key
+_rt_on_key:
+    call _evt_check_key
+    lea rax, [rip + _evt_handler]
+    mov QWORD PTR [rax + rcx*8], rdx
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_key_trap - Turn trapping of a key on, off or stop it (KEY(n) ON/OFF/STOP)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = key number (1-14, 30, 31; 0 = all keys)
+#   rdx = state (0 = OFF, 1 = ON, 2 = STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_key_trap
+_rt_key_trap:
+    test rcx, rcx
+    jz .Lkey_trap_all
+    call _evt_check_key
+    jmp _evt_set_state
+
+.Lkey_trap_all:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    sub rsp, 32             # Shadow space
+    mov r12, rdx            # r12 = state
+    mov ebx, EVT_KEY_FIRST  # rbx = key
+.Lkey_trap_loop:
+    mov rcx, rbx
+    mov rdx, r12
+    call _evt_set_state
+    inc rbx
+    cmp rbx, EVT_KEY_ARROWS
+    jbe .Lkey_trap_loop
+    cmp rbx, EVT_KEY_F11
+    jae .Lkey_trap_high
+    mov ebx, EVT_KEY_F11    # skip the unused key numbers
+.Lkey_trap_high:
+    cmp rbx, EVT_KEY_LAST
+    jbe .Lkey_trap_loop
+    add rsp, 32
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_on_timer - Set the timer interval and handler (ON TIMER(n) GOSUB)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = interval in seconds (1-86400)
+#   rdx = handler address
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_on_timer
+_rt_on_timer:
+    cmp rcx, 1
+    jl _rt_illegal_call
+    cmp rcx, TIMER_MAX
+    jg _rt_illegal_call
+    imul rcx, rcx, MS_PER_SEC
+    mov QWORD PTR [rip + _evt_timer_interval], rcx
+    mov QWORD PTR [rip + _evt_handler + EVT_TIMER * 8], rdx
+    jmp _evt_timer_restart
+
+# ------------------------------------------------------------------------------
+# _rt_timer_trap - Turn the timer on, off or stop it (TIMER ON/OFF/STOP)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = state (0 = OFF, 1 = ON, 2 = STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_timer_trap
+_rt_timer_trap:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx            # rbx = state
+    cmp ebx, EVT_OFF
+    je .Ltimer_trap_set
+    cmp BYTE PTR [rip + _evt_state + EVT_TIMER], EVT_OFF
+    jne .Ltimer_trap_set    # already counting
+    call _evt_timer_restart
+.Ltimer_trap_set:
+    mov ecx, EVT_TIMER
+    mov rdx, rbx
+    call _evt_set_state
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_on_break - Set the Ctrl-C handler (ON BREAK GOSUB)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = handler address
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_on_break
+_rt_on_break:
+    mov QWORD PTR [rip + _evt_handler + EVT_BREAK * 8], rcx
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_break_trap - Turn Ctrl-C trapping on, off or stop it (BREAK ON/OFF/STOP)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = state (0 = OFF, 1 = ON, 2 = STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_break_trap
+_rt_break_trap:
+    mov rdx, rcx
+    mov ecx, EVT_BREAK
+    jmp _evt_set_state
+
+# ------------------------------------------------------------------------------
+# _rt_on_mouse - Set the mouse button handler (ON MOUSE GOSUB)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = handler address
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_on_mouse
+_rt_on_mouse:
+    mov QWORD PTR [rip + _evt_handler + EVT_MOUSE * 8], rcx
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_mouse_trap - Turn mouse trapping on, off or stop it (MOUSE ON/OFF/STOP)
+# ------------------------------------------------------------------------------
+# Mouse input stays on while the trap is ON or STOP, so a program can read
+# MOUSEX, MOUSEY and MOUSEB after MOUSE STOP without a handler.
+#
+# Arguments:
+#   rcx = state (0 = OFF, 1 = ON, 2 = STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_mouse_trap
+_rt_mouse_trap:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx            # rbx = state
+    mov rdx, rcx
+    mov ecx, EVT_MOUSE
+    call _evt_set_state
+    xor ecx, ecx
+    cmp ebx, EVT_OFF
+    setne cl
+    call _evt_mouse_report
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_mouse_x - Pointer column (MOUSEX)
+# _rt_mouse_y - Pointer row (MOUSEY)
+# _rt_mouse_b - Buttons held down (MOUSEB: 1 = left, 2 = right, 4 = middle)
+# ------------------------------------------------------------------------------
+# Each reads any pending input first. The position is 1-based, and 0 before
+# the first mouse event.
+#
+# Arguments: none
+#
+# Returns:
+#   rax = the value
+# ------------------------------------------------------------------------------
+.globl _rt_mouse_x
+_rt_mouse_x:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    call _evt_mouse_update
+    mov rax, QWORD PTR [rip + _evt_mouse_x]
+    leave
+    ret
+
+.globl _rt_mouse_y
+_rt_mouse_y:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    call _evt_mouse_update
+    mov rax, QWORD PTR [rip + _evt_mouse_y]
+    leave
+    ret
+
+.globl _rt_mouse_b
+_rt_mouse_b:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    call _evt_mouse_update
+    mov rax, QWORD PTR [rip + _evt_mouse_b]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_init_break - Catch Ctrl-C (called at program start)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_init_break
+_rt_init_break:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    # SetConsoleCtrlHandler(_evt_break_handler, TRUE)
+    lea rcx, [rip + _evt_break_handler]
+    mov edx, 1
+    call SetConsoleCtrlHandler
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_break_handler - Console control handler (internal)
+# ------------------------------------------------------------------------------
+# Marks the break event pending while BREAK is trapped with a handler set,
+# and otherwise ends the program with "Break". Other control events (closing
+# the console, logging off) are left to the next handler.
+#
+# Arguments:
+#   ecx = control event
+#
+# Returns:
+#   eax = TRUE if the event was handled
+# ------------------------------------------------------------------------------
+_evt_break_handler:
+    xor eax, eax
+    cmp ecx, CTRL_BREAK_EVENT
+    ja .Lbreak_handler_done
+    cmp BYTE PTR [rip + _evt_state + EVT_BREAK], EVT_OFF
+    je .Lbreak_handler_end
+    cmp QWORD PTR [rip + _evt_handler + EVT_BREAK * 8], 0
+    je .Lbreak_handler_end
+    mov BYTE PTR [rip + _evt_pending + EVT_BREAK], 1
+    mov eax, 1
+.Lbreak_handler_done:
+    ret
+.Lbreak_handler_end:
+    lea rcx, [rip + _evt_break_msg]
+    mov edx, _evt_break_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_event_poll - Check for a trapped event (called at statement boundaries)
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   rax = handler address to run as a GOSUB, or 0 if no event is ready.
+#         The event is marked running until _rt_event_return.
+# ------------------------------------------------------------------------------
+.globl _rt_event_poll
+_rt_event_poll:
+    cmp QWORD PTR [rip + _evt_armed], 0
+    jne .Lpoll_armed
+    xor eax, eax            # fast path: nothing is trapped
+    ret
+
+.Lpoll_armed:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    cmp QWORD PTR [rip + _evt_keys_armed], 0
+    je .Lpoll_timer
+    call _evt_read_keys
+
+.Lpoll_timer:
+    cmp BYTE PTR [rip + _evt_state + EVT_TIMER], EVT_OFF
+    je .Lpoll_scan_start
+    call _evt_now_ms
+    cmp rax, QWORD PTR [rip + _evt_timer_next]
+    jl .Lpoll_scan_start
+    mov BYTE PTR [rip + _evt_pending + EVT_TIMER], 1
+    add rax, QWORD PTR [rip + _evt_timer_interval]
+    mov QWORD PTR [rip + _evt_timer_next], rax
+
+.Lpoll_scan_start:
+    # Run the lowest-numbered pending event that is ON and not already running
+    xor ecx, ecx
+.Lpoll_scan:
+    lea rax, [rip + _evt_pending]
+    cmp BYTE PTR [rax + rcx], 0
+    je .Lpoll_next
+    lea rax, [rip + _evt_state]
+    cmp BYTE PTR [rax + rcx], EVT_ON
+    jne .Lpoll_next
+    lea rax, [rip + _evt_busy]
+    cmp BYTE PTR [rax + rcx], 0
+    jne .Lpoll_next
+    lea rax, [rip + _evt_handler]
+    mov rax, QWORD PTR [rax + rcx*8]
+    test rax, rax
+    jnz .Lpoll_dispatch
+.Lpoll_next:
+    inc ecx
+    cmp ecx, EVT_SLOTS
+    jb .Lpoll_scan
+    xor eax, eax
+    leave
+    ret
+
+.Lpoll_dispatch:
+    lea rdx, [rip + _evt_pending]
+    mov BYTE PTR [rdx + rcx], 0
+    lea rdx, [rip + _evt_busy]
+    mov BYTE PTR [rdx + rcx], 1
+    mov rdx, QWORD PTR [rip + _evt_depth]
+    lea r8, [rip + _evt_nest]
+    mov BYTE PTR [r8 + rdx], cl
+    inc rdx
+    mov QWORD PTR [rip + _evt_depth], rdx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_event_return - Release the event whose handler just RETURNed
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_event_return
+_rt_event_return:
+    mov rax, QWORD PTR [rip + _evt_depth]
+    test rax, rax
+    jz .Levent_return_done
+    dec rax
+    mov QWORD PTR [rip + _evt_depth], rax
+    lea rcx, [rip + _evt_nest]
+    movzx ecx, BYTE PTR [rcx + rax]
+    lea rax, [rip + _evt_busy]
+    mov BYTE PTR [rax + rcx], 0
+.Levent_return_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_check_key - Validate a key number (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = key number
+#
+# Returns: nothing (invalid numbers are an illegal function call)
+# ------------------------------------------------------------------------------
+_evt_check_key:
+    cmp rcx, EVT_KEY_FIRST
+    jl _rt_illegal_call
+    cmp rcx, EVT_KEY_ARROWS
+    jle .Lcheck_key_ok
+    cmp rcx, EVT_KEY_F11
+    jl _rt_illegal_call
+    cmp rcx, EVT_KEY_LAST
+    jg _rt_illegal_call
+.Lcheck_key_ok:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_set_state - Set the trap state of a slot (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = slot
+#   rdx = state (EVT_OFF, EVT_ON or EVT_STOP)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_set_state:
+    lea rax, [rip + _evt_state]
+    movzx r8d, BYTE PTR [rax + rcx]     # r8d = old state
+    mov BYTE PTR [rax + rcx], dl
+    cmp edx, EVT_OFF
+    jne .Lset_state_count
+    lea rax, [rip + _evt_pending]       # OFF forgets events seen while stopped
+    mov BYTE PTR [rax + rcx], 0
+
+.Lset_state_count:
+    # rax = (new state armed) - (old state armed)
+    xor r9d, r9d
+    test r8d, r8d
+    setnz r9b
+    xor eax, eax
+    test edx, edx
+    setnz al
+    sub rax, r9
+    add QWORD PTR [rip + _evt_armed], rax
+    test rcx, rcx           # the timer and Ctrl-C are not input
+    jz .Lset_state_done
+    cmp rcx, EVT_BREAK
+    je .Lset_state_done
+    add QWORD PTR [rip + _evt_keys_armed], rax
+.Lset_state_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_timer_restart - Start a new timer interval from now (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_timer_restart:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    call _evt_now_ms
+    add rax, QWORD PTR [rip + _evt_timer_interval]
+    mov QWORD PTR [rip + _evt_timer_next], rax
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_now_ms - Read the monotonic clock (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   rax = milliseconds since system start
+# ------------------------------------------------------------------------------
+_evt_now_ms:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    call GetTickCount64
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_read_keys - Read pending stdin input and record key events (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_read_keys:
+    cmp QWORD PTR [rip + _evt_stdin_eof], 0
+    jne .Lread_keys_ret
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 56             # Shadow space + stack args + alignment
+    mov rbx, QWORD PTR [rip + _stdin_handle]
+
+    # GetConsoleMode fails unless stdin is a console
+    mov rcx, rbx
+    lea rdx, [rip + _evt_count]
+    call GetConsoleMode
+    test eax, eax
+    jz .Lread_keys_pipe
+
+.Lread_keys_console:
+    # GetNumberOfConsoleInputEvents(handle, &count)
+    mov rcx, rbx
+    lea rdx, [rip + _evt_count]
+    call GetNumberOfConsoleInputEvents
+    test eax, eax
+    jz .Lread_keys_done
+    cmp DWORD PTR [rip + _evt_count], 0
+    je .Lread_keys_done
+    # ReadConsoleInputA(handle, &record, 1, &count)
+    mov rcx, rbx
+    lea rdx, [rip + _evt_record]
+    mov r8d, 1
+    lea r9, [rip + _evt_count]
+    call ReadConsoleInputA
+    test eax, eax
+    jz .Lread_keys_done
+    lea rax, [rip + _evt_record]
+    cmp WORD PTR [rax], MOUSE_EVENT
+    je .Lread_keys_mouse
+    cmp WORD PTR [rax], KEY_EVENT
+    jne .Lread_keys_console
+    cmp DWORD PTR [rax + KEY_DOWN_OFFSET], 0
+    je .Lread_keys_console
+    movzx ecx, WORD PTR [rax + VKEY_OFFSET]
+    call _evt_vkey_hit
+    jmp .Lread_keys_console
+.Lread_keys_mouse:
+    mov ecx, DWORD PTR [rax + MOUSE_BUTTONS_OFFSET]
+    and ecx, MOUSE_BUTTON_BITS
+    movsx rdx, WORD PTR [rax + MOUSE_X_OFFSET]
+    inc rdx                 # console cells count from 0
+    movsx r8, WORD PTR [rax + MOUSE_Y_OFFSET]
+    inc r8
+    call _evt_mouse_hit
+    jmp .Lread_keys_console
+
+.Lread_keys_pipe:
+    # PeekNamedPipe(handle, NULL, 0, NULL, &available, NULL)
+    mov rcx, rbx
+    xor edx, edx
+    xor r8d, r8d
+    xor r9d, r9d
+    lea rax, [rip + _evt_count]
+    mov QWORD PTR [rsp + 32], rax
+    mov QWORD PTR [rsp + 40], 0
+    call PeekNamedPipe
+    test eax, eax
+    jz .Lread_keys_eof      # writer closed the pipe (or not a pipe)
+    mov r8d, DWORD PTR [rip + _evt_count]
+    test r8d, r8d
+    jz .Lread_keys_done
+    cmp r8d, KEY_BUF_SIZE
+    jbe .Lread_keys_read
+    mov r8d, KEY_BUF_SIZE
+.Lread_keys_read:
+    # ReadFile(handle, buf, count, &count, NULL)
+    mov rcx, rbx
+    lea rdx, [rip + _evt_key_buf]
+    lea r9, [rip + _evt_count]
+    mov QWORD PTR [rsp + 32], 0
+    call ReadFile
+    test eax, eax
+    jz .Lread_keys_eof
+    mov edx, DWORD PTR [rip + _evt_count]
+    test edx, edx
+    jz .Lread_keys_eof
+    lea rcx, [rip + _evt_key_buf]
+    call _evt_decode_keys
+    jmp .Lread_keys_done
+
+.Lread_keys_eof:
+    mov QWORD PTR [rip + _evt_stdin_eof], 1
+.Lread_keys_done:
+    add rsp, 56
+    pop rbx
+    leave
+.Lread_keys_ret:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_vkey_hit - Record a console key press (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = virtual key code
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_vkey_hit:
+    cmp ecx, VK_LEFT
+    jb .Lvkey_done
+    cmp ecx, VK_DOWN
+    ja .Lvkey_function
+    lea rax, [rip + _evt_vk_arrows]
+    movzx ecx, BYTE PTR [rax + rcx - VK_LEFT]
+    jmp _evt_key_hit
+.Lvkey_function:
+    cmp ecx, VK_F1
+    jb .Lvkey_done
+    cmp ecx, VK_F12
+    ja .Lvkey_done
+    lea rax, [rip + _evt_vk_fkeys]
+    movzx ecx, BYTE PTR [rax + rcx - VK_F1]
+    jmp _evt_key_hit
+.Lvkey_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_decode_keys - Record the keys in a block of terminal input (internal)
+# ------------------------------------------------------------------------------
+# Recognizes ESC O P-S (F1-F4), ESC [ A-D (arrows) and ESC [ n ~ (F1-F12).
+#
+# Arguments:
+#   rcx = pointer to input
+#   rdx = length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_decode_keys:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    sub rsp, 56             # Shadow space + mouse report fields (dwords)
+    mov rbx, rcx            # rbx = next byte
+    lea r12, [rcx + rdx]    # r12 = end of input
+
+.Ldecode_loop:
+    cmp rbx, r12
+    jae .Ldecode_done
+    movzx eax, BYTE PTR [rbx]
+    inc rbx
+    cmp eax, CHAR_ESC
+    jne .Ldecode_loop
+    lea rax, [rbx + 1]      # need two more bytes
+    cmp rax, r12
+    jae .Ldecode_done
+    movzx eax, BYTE PTR [rbx]
+    movzx edx, BYTE PTR [rbx + 1]
+    add rbx, 2
+    cmp eax, 'O'
+    je .Ldecode_ss3
+    cmp eax, '['
+    jne .Ldecode_loop
+    cmp edx, '<'
+    je .Ldecode_sgr
+
+    # ESC [ A-D: arrow keys
+    sub edx, 'A'
+    cmp edx, 3
+    ja .Ldecode_number
+    lea rax, [rip + _evt_arrow_keys]
+    movzx ecx, BYTE PTR [rax + rdx]
+    call _evt_key_hit
+    jmp .Ldecode_loop
+
+.Ldecode_number:
+    # ESC [ n ~: function keys
+    add edx, 'A' - '0'
+    cmp edx, 9
+    ja .Ldecode_loop
+    mov r8d, edx            # r8d = n
+.Ldecode_digits:
+    cmp rbx, r12
+    jae .Ldecode_done
+    movzx edx, BYTE PTR [rbx]
+    inc rbx
+    cmp edx, '~'
+    je .Ldecode_tilde
+    sub edx, '0'
+    cmp edx, 9
+    ja .Ldecode_loop
+    imul r8d, r8d, 10
+    add r8d, edx
+    cmp r8d, EVT_TILDE_MAX
+    jbe .Ldecode_digits
+    jmp .Ldecode_loop
+.Ldecode_tilde:
+    cmp r8d, EVT_TILDE_MAX
+    ja .Ldecode_loop
+    lea rax, [rip + _evt_tilde_keys]
+    movzx ecx, BYTE PTR [rax + r8]
+    call _evt_key_hit
+    jmp .Ldecode_loop
+
+.Ldecode_ss3:
+    # ESC O P-S: F1-F4
+    sub edx, 'P'
+    cmp edx, 3
+    ja .Ldecode_loop
+    lea ecx, [rdx + 1]
+    call _evt_key_hit
+    jmp .Ldecode_loop
+
+.Ldecode_sgr:
+    # ESC [ < b ; x ; y M/m: mouse report
+    xor r13d, r13d          # r13 = field number
+.Ldecode_sgr_field:
+    xor r8d, r8d            # r8d = field value
+.Ldecode_sgr_digit:
+    cmp rbx, r12
+    jae .Ldecode_done
+    movzx eax, BYTE PTR [rbx]
+    inc rbx
+    lea edx, [rax - '0']
+    cmp edx, 9
+    ja .Ldecode_sgr_end
+    imul r8d, r8d, 10
+    add r8d, edx
+    cmp r8d, SGR_MAX
+    jbe .Ldecode_sgr_digit
+    jmp .Ldecode_loop
+.Ldecode_sgr_end:
+    mov DWORD PTR [rbp + r13*4 - 40], r8d
+    inc r13
+    cmp r13, SGR_FIELDS
+    jae .Ldecode_sgr_final
+    cmp eax, ';'
+    je .Ldecode_sgr_field
+    jmp .Ldecode_loop
+.Ldecode_sgr_final:
+    mov r9d, 1
+    cmp eax, 'M'
+    je .Ldecode_sgr_hit
+    xor r9d, r9d
+    cmp eax, 'm'
+    jne .Ldecode_loop
+.Ldecode_sgr_hit:
+    mov ecx, DWORD PTR [rbp - 40]
+    mov edx, DWORD PTR [rbp - 36]
+    mov r8d, DWORD PTR [rbp - 32]
+    call _evt_mouse_sgr
+    jmp .Ldecode_loop
+
+.Ldecode_done:
+    mov r13, QWORD PTR [rbp - 24]
+    mov r12, QWORD PTR [rbp - 16]
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_key_hit - Record a key press (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = key number (0 = not a trappable key)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_key_hit:
+    test ecx, ecx
+    jz .Lkey_hit_done
+    lea rax, [rip + _evt_state]
+    cmp BYTE PTR [rax + rcx], EVT_OFF
+    je .Lkey_hit_done
+    lea rax, [rip + _evt_pending]
+    mov BYTE PTR [rax + rcx], 1
+.Lkey_hit_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_sgr - Record an SGR mouse report (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = button code (b)
+#   rdx = column
+#   r8  = row
+#   r9  = 1 for a press or motion (M), 0 for a release (m)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_sgr:
+    mov rax, QWORD PTR [rip + _evt_mouse_b]     # rax = new buttons
+    test ecx, SGR_NOT_BUTTON
+    jnz .Lmouse_sgr_hit
+    and ecx, SGR_BUTTON_BITS
+    cmp ecx, SGR_BUTTON_BITS
+    je .Lmouse_sgr_hit
+    lea r10, [rip + _evt_sgr_buttons]
+    movzx r10d, BYTE PTR [r10 + rcx]
+    or rax, r10
+    test r9d, r9d
+    jnz .Lmouse_sgr_hit
+    xor rax, r10            # released
+.Lmouse_sgr_hit:
+    mov rcx, rax
+    jmp _evt_mouse_hit
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_hit - Record the pointer state (internal)
+# ------------------------------------------------------------------------------
+# A button going down is a mouse event.
+#
+# Arguments:
+#   rcx = buttons held down
+#   rdx = column
+#   r8  = row
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_hit:
+    mov QWORD PTR [rip + _evt_mouse_x], rdx
+    mov QWORD PTR [rip + _evt_mouse_y], r8
+    mov rax, QWORD PTR [rip + _evt_mouse_b]
+    mov QWORD PTR [rip + _evt_mouse_b], rcx
+    not rax
+    test rax, rcx
+    jz .Lmouse_hit_done
+    cmp BYTE PTR [rip + _evt_state + EVT_MOUSE], EVT_OFF
+    je .Lmouse_hit_done
+    mov BYTE PTR [rip + _evt_pending + EVT_MOUSE], 1
+.Lmouse_hit_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_update - Read pending input for the mouse functions (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_update:
+    cmp QWORD PTR [rip + _evt_keys_armed], 0
+    jne _evt_read_keys
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_report - Turn console mouse input on or off (internal)
+# ------------------------------------------------------------------------------
+# Does nothing if stdin is not a console. Turning it on the first time
+# registers _evt_mouse_off to run at exit.
+#
+# Arguments:
+#   rcx = 1 to turn mouse input on, 0 to turn it off
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_report:
+    cmp rcx, QWORD PTR [rip + _evt_mouse_on]
+    je .Lmouse_report_ret
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx            # rbx = on
+    mov edx, DWORD PTR [rip + _evt_console_mode]
+    test rbx, rbx
+    jz .Lmouse_report_set
+    # GetConsoleMode fails unless stdin is a console
+    mov rcx, QWORD PTR [rip + _stdin_handle]
+    lea rdx, [rip + _evt_console_mode]
+    call GetConsoleMode
+    test eax, eax
+    jz .Lmouse_report_done
+    mov edx, DWORD PTR [rip + _evt_console_mode]
+    or edx, ENABLE_MOUSE_INPUT | ENABLE_EXTENDED_FLAGS
+    and edx, ~ENABLE_QUICK_EDIT_MODE
+.Lmouse_report_set:
+    # SetConsoleMode(handle, mode)
+    mov rcx, QWORD PTR [rip + _stdin_handle]
+    call SetConsoleMode
+    mov QWORD PTR [rip + _evt_mouse_on], rbx
+    cmp QWORD PTR [rip + _evt_mouse_atexit], 0
+    jne .Lmouse_report_done
+    mov QWORD PTR [rip + _evt_mouse_atexit], 1
+    lea rcx, [rip + _evt_mouse_off]
+    call atexit
+.Lmouse_report_done:
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+.Lmouse_report_ret:
+    ret
+
+# ------------------------------------------------------------------------------
+# _evt_mouse_off - Restore the console mode (internal, run at exit)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_evt_mouse_off:
+    xor ecx, ecx
+    jmp _evt_mouse_report
+
+# ------------------------------------------------------------------------------
+# _rt_stick - Joystick position (STICK)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = 0 (A x), 1 (A y), 2 (B x) or 3 (B y)
+#
+# Returns:
+#   rax = position, 1-200
+# ------------------------------------------------------------------------------
+.globl _rt_stick
+_rt_stick:
+    cmp rcx, JOY_AXES - 1
+    ja _rt_illegal_call     # (unsigned, so negative numbers fail too)
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx            # rbx = axis
+    call _joy_update
+    lea rax, [rip + _joy_axes]
+    mov rax, QWORD PTR [rax + rbx*8]
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_strig - Joystick button (STRIG)
+# ------------------------------------------------------------------------------
+# Even numbers ask whether the button was pressed since the last time they
+# were asked, odd numbers whether it is down now:
+#   0, 1 = A1   2, 3 = B1   4, 5 = A2   6, 7 = B2
+#
+# Arguments:
+#   rcx = 0-7
+#
+# Returns:
+#   rax = -1 if so, 0 if not
+# ------------------------------------------------------------------------------
+.globl _rt_strig
+_rt_strig:
+    cmp rcx, JOY_STRIG_LAST
+    ja _rt_illegal_call
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx            # rbx = STRIG number
+    call _joy_update
+    mov ecx, ebx
+    shr ecx, 1
+    mov edx, 1
+    shl edx, cl             # rdx = button bit
+    xor eax, eax
+    test ebx, 1
+    jnz .Lstrig_down
+    mov rcx, QWORD PTR [rip + _joy_pressed]
+    test rcx, rdx
+    jz .Lstrig_done
+    not rdx                 # reported: forget the press
+    and QWORD PTR [rip + _joy_pressed], rdx
+    dec rax
+    jmp .Lstrig_done
+.Lstrig_down:
+    mov rcx, QWORD PTR [rip + _joy_down]
+    test rcx, rdx
+    jz .Lstrig_done
+    dec rax
+.Lstrig_done:
+    mov rbx, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _joy_update - Read the joystick (internal)
+# ------------------------------------------------------------------------------
+# A joystick that can't be read is treated as missing from then on, as
+# joyGetPosEx is slow to fail.
+#
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_joy_update:
+    cmp QWORD PTR [rip + _joy_missing], 0
+    jne .Ljoy_ret
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    # joyGetPosEx(JOYSTICKID1, &info)
+    lea rdx, [rip + _joy_info]
+    mov DWORD PTR [rdx], JOYINFOEX_SIZE
+    mov DWORD PTR [rdx + JOY_FLAGS_OFFSET], JOY_RETURNALL
+    xor ecx, ecx
+    call joyGetPosEx
+    test eax, eax
+    jnz .Ljoy_lost
+
+    # Axes: 0..65535 -> 1..200
+    xor ecx, ecx
+.Ljoy_axis:
+    lea rax, [rip + _joy_info + JOY_XPOS_OFFSET]
+    mov eax, DWORD PTR [rax + rcx*4]
+    imul eax, eax, JOY_RANGE
+    xor edx, edx
+    mov r8d, 65535
+    div r8d
+    inc eax
+    lea rdx, [rip + _joy_axes]
+    mov QWORD PTR [rdx + rcx*8], rax
+    inc ecx
+    cmp ecx, JOY_AXES
+    jb .Ljoy_axis
+
+    # Buttons newly down count as pressed
+    mov eax, DWORD PTR [rip + _joy_info + JOY_BUTTONS_OFFSET]
+    and eax, JOY_BUTTON_BITS
+    mov rcx, QWORD PTR [rip + _joy_down]
+    not rcx
+    and rcx, rax
+    or QWORD PTR [rip + _joy_pressed], rcx
+    mov QWORD PTR [rip + _joy_down], rax
+    jmp .Ljoy_done
+.Ljoy_lost:
+    mov QWORD PTR [rip + _joy_missing], 1
+.Ljoy_done:
+    leave
+.Ljoy_ret:
+    ret
@@ -5,11 +5,70 @@
 # File input/output functions using Win32 API instead of libc stdio.
 # Uses CreateFileA, CloseHandle, WriteFile, ReadFile.
 #
+# A file number outside 1-15, or one with no open file (including one whose
+# CreateFileA for OUTPUT or APPEND failed), raises "Bad file number"
+# (_rt_bad_file), CLOSE included. OPEN raises "File already open" for a
+# number that has one, and "File not found" for a missing file FOR INPUT.
+# INPUT # and LINE INPUT # raise "Bad file mode" on a file opened FOR OUTPUT
+# or APPEND (_file_written[n]) that isn't a TCP connection.
+#
+# INPUT # reads a line at a time into the file's slot in _file_lines and
+# splits it into comma-separated fields with _in_next_field (input.s), just
+# like INPUT. _file_line_pos[n] points at the next field of file n's line, or
+# is 0 when the next read needs a new line, and _file_line_len[n] counts the
+# bytes the line took in the file (CRs and the newline included).
+#
 # File Handle Table:
 #   _file_handles is an array of 16 HANDLE values (128 bytes).
 #   Index 0 is unused (BASIC file numbers start at 1).
 #   Handles 1-15 are available for user files.
 #
+# Device Names:
+#   "SCRN:" (output), "KYBD:" (input) and "CONS:" (either), in any case,
+#   open a duplicate of the standard output or input handle instead of a
+#   file, and "STDERR:" (output) one of the standard error handle, so they
+#   read and write like any file and CLOSE closes only the duplicate.
+#   Opening a device the wrong way raises "Bad file mode".
+#
+# Pipes:
+#   "PIPE:command" runs the command with the CRT's _popen: FOR INPUT reads
+#   its output, FOR OUTPUT or APPEND feeds its input. The file's HANDLE is
+#   the pipe end under the stream, which I/O uses like any other, and
+#   _file_pipes[n] keeps the stream itself, so CLOSE waits for the command
+#   with _pclose.
+#
+# TCP Connections:
+#   "TCP:host:port" connects to a server, trying each address getaddrinfo
+#   finds, and "TCPL:port" listens on the port (IPv4) and waits in OPEN for
+#   one client to connect. Sockets come from WSASocketA without overlapped
+#   I/O, so ReadFile and WriteFile use them like any file in any mode, and
+#   _file_sockets[n] marks them for closesocket. Only they open both ways
+#   (OPEN with no FOR).
+#
+# Buffering:
+#   Disk files opened for OUTPUT or APPEND collect what is printed to them
+#   in their slot of _file_bufs (_file_buffered[n] marks them, and
+#   _file_buf_len[n] counts the bytes waiting), which is written out when it
+#   fills, by FLUSH # and CLOSE, before a PIPE: command starts or is waited
+#   for, and when the program ends, even with a runtime error. Devices,
+#   pipes and sockets write each piece as it is printed, so whoever is on
+#   the other end sees every line as soon as it is finished.
+#
+# Positions:
+#   SEEK # writes out the file's buffer, moves a disk file to a byte
+#   position (1 is the first byte) with SetFilePointerEx and drops any
+#   partly read INPUT # line. SEEK() is the position the next read or write
+#   uses: the file pointer, plus what is waiting in the buffer, less the
+#   part of the current line INPUT # hasn't used. Devices, pipes and sockets
+#   have no position ("Bad file mode").
+#
+# Locking:
+#   LOCK # and UNLOCK # lock the whole of a disk file with LockFileEx (the
+#   files are sequential, so a record range is only checked), shared for a
+#   file opened FOR INPUT and exclusive otherwise. A lock another process
+#   holds raises "Permission denied"; UNLOCK writes out the file's buffer
+#   first. On devices, pipes and sockets they do nothing.
+#
 # Win64 ABI:
 #   - Args: rcx, rdx, r8, r9 (then stack)
 #   - Callee-saved: rbx, rbp, rdi, rsi, r12-r15
@@ -25,7 +84,26 @@
 .equ OPEN_ALWAYS,           4
 .equ FILE_ATTRIBUTE_NORMAL, 0x80
 .equ INVALID_HANDLE_VALUE,  -1
+.equ FILE_BEGIN,            0       # SetFilePointerEx move methods
+.equ FILE_CURRENT,          1
 .equ FILE_END,              2
+.equ STD_INPUT_HANDLE,      -10
+.equ STD_OUTPUT_HANDLE,     -11
+.equ STD_ERROR_HANDLE,      -12
+.equ CURRENT_PROCESS,       -1      # GetCurrentProcess() pseudo handle
+.equ DUPLICATE_SAME_ACCESS, 2
+
+# File locking
+.equ FILE_TYPE_DISK,        1       # GetFileType of a disk file
+.equ LOCK_SHARED,           1       # LOCKFILE_FAIL_IMMEDIATELY
+.equ LOCK_EXCLUSIVE,        3       # ... | LOCKFILE_EXCLUSIVE_LOCK
+.equ OVERLAPPED_SIZE,       32
+
+# Devices, as _file_device finds them
+.equ DEV_SCRN,              1
+.equ DEV_KYBD,              2
+.equ DEV_CONS,              3
+.equ DEV_STDERR,            4
 
 # ASCII character codes
 .equ CHAR_LF,               10
@@ -35,26 +113,64 @@
 .equ MODE_INPUT,            0
 .equ MODE_OUTPUT,           1
 .equ MODE_APPEND,           2
+.equ MODE_BOTH,             3       # OPEN with no FOR
+
+# Winsock
+.equ WINSOCK_VERSION,       0x202   # 2.2
+.equ AF_INET,               2
+.equ SOCK_STREAM,           1
+.equ AI_PASSIVE,            1
+.equ ADDRINFO_ADDR,         32      # offsets in struct addrinfo
+.equ ADDRINFO_NEXT,         40
 
 # Buffer size constants
 .equ INPUT_BUF_SIZE,        1024
-.equ MAX_NUM_INPUT_LEN,     254     # INPUT_BUF_SIZE - 2 (null + safety)
 .equ MAX_STR_INPUT_LEN,     1022    # INPUT_BUF_SIZE - 2 (null + safety)
 
 # I/O size constants
 .equ SINGLE_BYTE,           1
-.equ CRLF_LEN,              2
+.equ FILE_BUF_SIZE,         8192    # output buffer of each disk file
+.equ FILE_BUF_SHIFT,        13      # log2(FILE_BUF_SIZE)
 
 .data
 _file_handles: .skip 128        # 16 * 8 bytes = 16 HANDLEs
 _file_name_buf: .skip 1024      # Buffer for null-terminated filename
-_file_output_buf: .skip 256     # Buffer for formatted output
-_file_bytes_written: .quad 0    # For WriteFile output
 _file_bytes_read: .quad 0       # For ReadFile output
-_file_input_buf: .skip 1024     # Buffer for file input
-_file_fmt_int:     .asciz "%lld"
-_file_fmt_float:   .asciz "%g"
-_file_newline:     .ascii "\r\n"
+_file_bytes_written: .quad 0    # For WriteFile output
+_file_lines: .skip 16 * 1024    # INPUT # line buffer for each file
+_file_line_pos: .skip 128       # Next field in each file's line
+_file_line_len: .skip 128       # Bytes each file's line took in the file
+# Device names, in DEV_* order, ending with an empty one
+_file_devices: .asciz "SCRN:", "KYBD:", "CONS:", "STDERR:", ""
+_file_bad_mode_msg: .ascii "Bad file mode"
+_file_bad_mode_msg_len = 13
+_file_open_msg: .ascii "File already open"
+_file_open_msg_len = 17
+_file_not_found_msg: .ascii "File not found"
+_file_not_found_msg_len = 14
+_file_locked_msg: .ascii "Permission denied"
+_file_locked_msg_len = 17
+# Pipe files: "PIPE:" prefix, _popen modes, and each pipe file's FILE*
+_file_pipe_prefix: .asciz "PIPE:"
+.equ PIPE_PREFIX_LEN, 5
+_file_pipe_read: .asciz "rb"
+_file_pipe_write: .asciz "wb"
+_file_pipes: .skip 128
+# TCP files: "TCP:" and "TCPL:" prefixes, Winsock's startup data, and a
+# flag byte per file number for sockets
+_file_tcp_prefix: .asciz "TCP:"
+.equ TCP_PREFIX_LEN, 4
+_file_tcpl_prefix: .asciz "TCPL:"
+.equ TCPL_PREFIX_LEN, 5
+_file_wsa_data: .skip 408       # WSADATA
+_file_sockets: .skip 16
+# A flag byte per file number: opened FOR OUTPUT or APPEND, so not readable
+_file_written: .skip 16
+# Output buffers of disk files (see Buffering above)
+_file_buffered: .skip 16
+_file_buf_len: .skip 128
+_file_bufs: .skip 16 * FILE_BUF_SIZE
+_file_exit_registered: .quad 0
 
 .text
 
@@ -71,6 +187,17 @@ _file_newline:     .ascii "\r\n"
 # ------------------------------------------------------------------------------
 .globl _rt_file_open
 _rt_file_open:
+    lea eax, [r9 - 1]       # file numbers are 1-15
+    cmp eax, 14
+    ja _rt_bad_file
+    mov eax, r9d            # and must be CLOSEd before they are reused
+    lea r11, [rip + _file_handles]
+    mov rax, QWORD PTR [r11 + rax*8]
+    test rax, rax
+    jz .Lfile_open_free
+    cmp rax, INVALID_HANDLE_VALUE
+    jne _file_already_open
+.Lfile_open_free:
     push rbp
     mov rbp, rsp
     push rbx
@@ -94,6 +221,44 @@ _rt_file_open:
     call memcpy
     lea rax, [rip + _file_name_buf]
     mov BYTE PTR [rax + rsi], 0
+    lea rax, [rip + _file_pipes]
+    mov QWORD PTR [rax + rbx*8], 0
+    lea rax, [rip + _file_sockets]
+    mov BYTE PTR [rax + rbx], 0
+    lea rax, [rip + _file_buffered]
+    mov BYTE PTR [rax + rbx], 0
+    # Mode 1 or 2: written to, not read
+    lea eax, [r14 - 1]
+    cmp eax, 1
+    setbe cl
+    lea rax, [rip + _file_written]
+    mov BYTE PTR [rax + rbx], cl
+
+    call _file_device
+    test eax, eax
+    jnz .Lfile_open_device
+
+    # _strnicmp(name, prefix, length) for "TCP:", "TCPL:" and "PIPE:"
+    lea rcx, [rip + _file_name_buf]
+    lea rdx, [rip + _file_tcp_prefix]
+    mov r8d, TCP_PREFIX_LEN
+    call _strnicmp
+    test eax, eax
+    jz .Lfile_open_tcp
+    lea rcx, [rip + _file_name_buf]
+    lea rdx, [rip + _file_tcpl_prefix]
+    mov r8d, TCPL_PREFIX_LEN
+    call _strnicmp
+    test eax, eax
+    jz .Lfile_open_tcpl
+    cmp r14d, MODE_BOTH     # only TCP connections go both ways
+    je .Lfile_open_bad_mode
+    lea rcx, [rip + _file_name_buf]
+    lea rdx, [rip + _file_pipe_prefix]
+    mov r8d, PIPE_PREFIX_LEN
+    call _strnicmp
+    test eax, eax
+    jz .Lfile_open_pipe
 
     # Determine access and creation mode based on mode argument
     # r12 = dwDesiredAccess, r13 = dwCreationDisposition
@@ -128,10 +293,41 @@ _rt_file_open:
     mov QWORD PTR [rsp + 48], 0          # hTemplateFile = NULL
     call CreateFileA
 
+    # Files written to are buffered, and flushed at exit (registered once);
+    # a file to read must be there
+    cmp r14d, MODE_INPUT
+    jne .Lfile_open_buffered
+    cmp rax, INVALID_HANDLE_VALUE
+    jne .Lfile_open_store
+    lea rcx, [rip + _file_not_found_msg]
+    mov edx, _file_not_found_msg_len
+    jmp _rt_error
+.Lfile_open_buffered:
+    lea rcx, [rip + _file_buffered]
+    mov BYTE PTR [rcx + rbx], 1
+    lea rcx, [rip + _file_buf_len]
+    mov QWORD PTR [rcx + rbx*8], 0
+    cmp QWORD PTR [rip + _file_exit_registered], 0
+    jne .Lfile_open_store
+    mov QWORD PTR [rip + _file_exit_registered], 1
+    mov r12, rax
+    lea rcx, [rip + _file_flush_all]
+    call atexit
+    mov rax, r12
+
+.Lfile_open_store:
     # Store HANDLE in handle table
     lea rcx, [rip + _file_handles]
     mov [rcx + rbx*8], rax
 
+    # New files start at column 0 with no line width limit, and no line read
+    lea rcx, [rip + _file_line_pos]
+    mov QWORD PTR [rcx + rbx*8], 0
+    lea rcx, [rip + _out_col]
+    mov QWORD PTR [rcx + rbx*8], 0
+    lea rcx, [rip + _out_width]
+    mov QWORD PTR [rcx + rbx*8], OUT_WIDTH_INFINITE
+
     # If APPEND mode, seek to end
     cmp r14d, MODE_APPEND
     jne .Lfile_open_done
@@ -154,16 +350,307 @@ _rt_file_open:
     leave
     ret
 
+.Lfile_open_device:
+    # KYBD: only reads, SCRN: and STDERR: only write, CONS: does either
+    cmp r14d, MODE_BOTH
+    je .Lfile_open_bad_mode
+    cmp eax, DEV_CONS
+    je .Lfile_open_std
+    cmp eax, DEV_KYBD
+    sete cl
+    cmp r14d, MODE_INPUT
+    sete dl
+    cmp cl, dl
+    jne .Lfile_open_bad_mode
+.Lfile_open_std:
+    # GetStdHandle(STD_ERROR_HANDLE, or the input or output one)
+    mov ecx, STD_ERROR_HANDLE
+    cmp eax, DEV_STDERR
+    je .Lfile_open_get_std
+    mov ecx, STD_OUTPUT_HANDLE
+    cmp r14d, MODE_INPUT
+    jne .Lfile_open_get_std
+    mov ecx, STD_INPUT_HANDLE
+.Lfile_open_get_std:
+    call GetStdHandle
+    # DuplicateHandle(process, handle, process, &copy, 0, FALSE,
+    #                 DUPLICATE_SAME_ACCESS); a failure leaves the copy
+    #                 invalid, like a failed CreateFileA
+    mov rdx, rax
+    mov rcx, CURRENT_PROCESS
+    mov r8, CURRENT_PROCESS
+    lea r9, [rsp + 56]
+    mov QWORD PTR [rsp + 32], 0
+    mov QWORD PTR [rsp + 40], 0
+    mov QWORD PTR [rsp + 48], DUPLICATE_SAME_ACCESS
+    mov QWORD PTR [rsp + 56], INVALID_HANDLE_VALUE
+    call DuplicateHandle
+    mov rax, QWORD PTR [rsp + 56]
+    jmp .Lfile_open_store
+.Lfile_open_bad_mode:
+    lea rcx, [rip + _file_bad_mode_msg]
+    mov edx, _file_bad_mode_msg_len
+    jmp _rt_error
+.Lfile_open_pipe:
+    # _popen(command, "rb") for INPUT, "wb" for OUTPUT and APPEND, after
+    # writing out the files, so the command finds them complete
+    call _file_flush_all
+    lea rdx, [rip + _file_pipe_read]
+    cmp r14d, MODE_INPUT
+    je .Lfile_open_popen
+    lea rdx, [rip + _file_pipe_write]
+.Lfile_open_popen:
+    lea rcx, [rip + _file_name_buf]
+    add rcx, PIPE_PREFIX_LEN
+    call _popen
+    mov rdi, rax
+    mov rax, INVALID_HANDLE_VALUE
+    test rdi, rdi
+    jz .Lfile_open_store
+    lea rcx, [rip + _file_pipes]
+    mov [rcx + rbx*8], rdi
+    # _get_osfhandle(_fileno(stream))
+    mov rcx, rdi
+    call _fileno
+    mov ecx, eax
+    call _get_osfhandle
+    jmp .Lfile_open_store
+.Lfile_open_tcp:
+    call _file_tcp_connect
+    jmp .Lfile_open_socket
+.Lfile_open_tcpl:
+    call _file_tcp_listen
+.Lfile_open_socket:
+    # Connections read in any mode
+    lea rcx, [rip + _file_written]
+    mov BYTE PTR [rcx + rbx], 0
+    # A failed connection leaves the handle invalid, like a failed CreateFileA
+    cmp rax, INVALID_HANDLE_VALUE
+    je .Lfile_open_store
+    lea rcx, [rip + _file_sockets]
+    mov BYTE PTR [rcx + rbx], 1
+    jmp .Lfile_open_store
+
+# ------------------------------------------------------------------------------
+# _file_tcp_connect - Connect to "TCP:host:port" (internal)
+# ------------------------------------------------------------------------------
+# Splits the name in _file_name_buf at its last colon and tries each
+# address getaddrinfo finds for the host and port until one connects.
+#
+# Arguments: none (the name is in _file_name_buf)
+#
+# Returns:
+#   rax = the connected socket, or INVALID_HANDLE_VALUE
+# ------------------------------------------------------------------------------
+_file_tcp_connect:
+    push rbx
+    push rsi
+    push rdi
+    sub rsp, 112            # Shadow space, 2 stack args, hints, result list
+    mov rdi, INVALID_HANDLE_VALUE   # rdi = socket
+
+    # WSAStartup(2.2, &data), which counts calls, so every OPEN may start it
+    mov ecx, WINSOCK_VERSION
+    lea rdx, [rip + _file_wsa_data]
+    call WSAStartup
+
+    # strrchr(name, ':') ends the host; the port follows
+    lea rcx, [rip + _file_name_buf]
+    mov edx, ':'
+    call strrchr
+    mov BYTE PTR [rax], 0
+    lea rsi, [rax + 1]
+
+    # getaddrinfo(host, port, &hints, &list), hints = any family, stream
+    mov QWORD PTR [rsp + 48], 0
+    mov QWORD PTR [rsp + 56], 0
+    mov QWORD PTR [rsp + 64], 0
+    mov QWORD PTR [rsp + 72], 0
+    mov QWORD PTR [rsp + 80], 0
+    mov QWORD PTR [rsp + 88], 0
+    mov DWORD PTR [rsp + 56], SOCK_STREAM
+    lea rcx, [rip + _file_name_buf]
+    add rcx, TCP_PREFIX_LEN
+    mov rdx, rsi
+    lea r8, [rsp + 48]
+    lea r9, [rsp + 96]
+    call getaddrinfo
+    test eax, eax
+    jnz .Ltcp_connect_done
+    mov rbx, [rsp + 96]     # rbx = address to try
+.Ltcp_connect_next:
+    test rbx, rbx
+    jz .Ltcp_connect_free
+    # WSASocketA(ai_family, ai_socktype, ai_protocol, NULL, 0, 0)
+    mov ecx, DWORD PTR [rbx + 4]
+    mov edx, DWORD PTR [rbx + 8]
+    mov r8d, DWORD PTR [rbx + 12]
+    xor r9d, r9d
+    mov QWORD PTR [rsp + 32], 0
+    mov QWORD PTR [rsp + 40], 0
+    call WSASocketA
+    mov rdi, rax
+    cmp rax, INVALID_HANDLE_VALUE
+    je .Ltcp_connect_skip
+    # connect(socket, ai_addr, ai_addrlen)
+    mov rcx, rax
+    mov rdx, [rbx + ADDRINFO_ADDR]
+    mov r8d, DWORD PTR [rbx + 16]
+    call connect
+    test eax, eax
+    jz .Ltcp_connect_free
+    mov rcx, rdi
+    call closesocket
+    mov rdi, INVALID_HANDLE_VALUE
+.Ltcp_connect_skip:
+    mov rbx, [rbx + ADDRINFO_NEXT]
+    jmp .Ltcp_connect_next
+.Ltcp_connect_free:
+    mov rcx, [rsp + 96]
+    call freeaddrinfo
+.Ltcp_connect_done:
+    mov rax, rdi
+    add rsp, 112
+    pop rdi
+    pop rsi
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _file_tcp_listen - Wait for a connection on "TCPL:port" (internal)
+# ------------------------------------------------------------------------------
+# Listens on the port on every IPv4 address, accepts one connection and
+# closes the listening socket.
+#
+# Arguments: none (the name is in _file_name_buf)
+#
+# Returns:
+#   rax = the connected socket, or INVALID_HANDLE_VALUE
+# ------------------------------------------------------------------------------
+_file_tcp_listen:
+    push rbx
+    push rsi
+    push rdi
+    sub rsp, 112            # Shadow space, 2 stack args, hints, result list
+    mov rdi, INVALID_HANDLE_VALUE   # rdi = connected socket
+
+    # WSAStartup(2.2, &data)
+    mov ecx, WINSOCK_VERSION
+    lea rdx, [rip + _file_wsa_data]
+    call WSAStartup
+
+    # getaddrinfo(NULL, port, &hints, &list), hints = passive IPv4 stream
+    mov QWORD PTR [rsp + 48], 0
+    mov QWORD PTR [rsp + 56], 0
+    mov QWORD PTR [rsp + 64], 0
+    mov QWORD PTR [rsp + 72], 0
+    mov QWORD PTR [rsp + 80], 0
+    mov QWORD PTR [rsp + 88], 0
+    mov DWORD PTR [rsp + 48], AI_PASSIVE
+    mov DWORD PTR [rsp + 52], AF_INET
+    mov DWORD PTR [rsp + 56], SOCK_STREAM
+    xor ecx, ecx
+    lea rdx, [rip + _file_name_buf]
+    add rdx, TCPL_PREFIX_LEN
+    lea r8, [rsp + 48]
+    lea r9, [rsp + 96]
+    call getaddrinfo
+    test eax, eax
+    jnz .Ltcp_listen_done
+    mov rbx, [rsp + 96]
+
+    # WSASocketA(ai_family, ai_socktype, ai_protocol, NULL, 0, 0)
+    mov ecx, DWORD PTR [rbx + 4]
+    mov edx, DWORD PTR [rbx + 8]
+    mov r8d, DWORD PTR [rbx + 12]
+    xor r9d, r9d
+    mov QWORD PTR [rsp + 32], 0
+    mov QWORD PTR [rsp + 40], 0
+    call WSASocketA
+    mov rsi, rax            # rsi = listening socket
+    cmp rax, INVALID_HANDLE_VALUE
+    je .Ltcp_listen_free
+    # bind(socket, ai_addr, ai_addrlen), listen(socket, 1)
+    mov rcx, rsi
+    mov rdx, [rbx + ADDRINFO_ADDR]
+    mov r8d, DWORD PTR [rbx + 16]
+    call bind
+    test eax, eax
+    jnz .Ltcp_listen_close
+    mov rcx, rsi
+    mov edx, 1
+    call listen
+    test eax, eax
+    jnz .Ltcp_listen_close
+    # accept(socket, NULL, NULL); the new socket shares the listening
+    # one's attributes, so it isn't overlapped either
+    mov rcx, rsi
+    xor edx, edx
+    xor r8d, r8d
+    call accept
+    mov rdi, rax
+.Ltcp_listen_close:
+    mov rcx, rsi
+    call closesocket
+.Ltcp_listen_free:
+    mov rcx, [rsp + 96]
+    call freeaddrinfo
+.Ltcp_listen_done:
+    mov rax, rdi
+    add rsp, 112
+    pop rdi
+    pop rsi
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _file_device - Which device a file name names (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none (the name is in _file_name_buf)
+#
+# Returns:
+#   eax = DEV_SCRN, DEV_KYBD, DEV_CONS or DEV_STDERR, or 0 for a file
+# ------------------------------------------------------------------------------
+_file_device:
+    push rbx
+    push r12
+    sub rsp, 40             # Shadow space + alignment
+    lea rbx, [rip + _file_devices]
+    mov r12d, 1             # r12 = device of the name at rbx
+.Lfile_device_next:
+    cmp BYTE PTR [rbx], 0
+    je .Lfile_device_none
+    # lstrcmpiA(name, device)
+    lea rcx, [rip + _file_name_buf]
+    mov rdx, rbx
+    call lstrcmpiA
+    test eax, eax
+    jz .Lfile_device_found
+    mov rcx, rbx
+    call lstrlenA
+    lea rbx, [rbx + rax + 1]
+    inc r12d
+    jmp .Lfile_device_next
+.Lfile_device_none:
+    xor r12d, r12d
+.Lfile_device_found:
+    mov eax, r12d
+    add rsp, 40
+    pop r12
+    pop rbx
+    ret
+
 # ------------------------------------------------------------------------------
 # _rt_file_close - Close a file (CLOSE statement)
 # ------------------------------------------------------------------------------
 # Arguments:
 #   rcx = file number (1-15)
 #
-# Returns: nothing
+# Returns: nothing ("Bad file number" if no file is open on the number)
 # ------------------------------------------------------------------------------
 .globl _rt_file_close
 _rt_file_close:
+    call _file_check
     push rbp
     mov rbp, rsp
     push rbx
@@ -171,309 +658,764 @@ _rt_file_close:
 
     mov ebx, ecx            # save file number
 
-    # Get HANDLE from table
+    # Write out what is waiting in the file's buffer
+    mov ecx, ebx
+    call _file_flush
     lea rax, [rip + _file_handles]
     mov rcx, [rax + rbx*8]
 
-    # Check for NULL/INVALID
-    test rcx, rcx
-    jz .Lfile_close_done
-    cmp rcx, INVALID_HANDLE_VALUE
-    je .Lfile_close_done
+    # _pclose(stream) for a pipe, which closes its handle too, and
+    # closesocket for a socket
+    lea rax, [rip + _file_pipes]
+    mov rax, [rax + rbx*8]
+    test rax, rax
+    jnz .Lfile_close_pipe
+    lea rax, [rip + _file_sockets]
+    cmp BYTE PTR [rax + rbx], 0
+    jne .Lfile_close_socket
 
     # CloseHandle(hFile)
     call CloseHandle
+    jmp .Lfile_close_clear
+
+.Lfile_close_pipe:
+    mov QWORD PTR [rbp - 16], rax
+    call _file_flush_all    # as at _popen
+    mov rcx, QWORD PTR [rbp - 16]
+    call _pclose
+    lea rax, [rip + _file_pipes]
+    mov QWORD PTR [rax + rbx*8], 0
+    jmp .Lfile_close_clear
+
+.Lfile_close_socket:
+    call closesocket
+    lea rax, [rip + _file_sockets]
+    mov BYTE PTR [rax + rbx], 0
+
+.Lfile_close_clear:
 
-    # Clear handle from table
+    # Clear handle from table, dropping any partly read line
     lea rax, [rip + _file_handles]
     mov QWORD PTR [rax + rbx*8], 0
+    lea rax, [rip + _file_buffered]
+    mov BYTE PTR [rax + rbx], 0
+    lea rax, [rip + _file_line_pos]
+    mov QWORD PTR [rax + rbx*8], 0
 
-.Lfile_close_done:
     add rsp, 40
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_print_string - Write string to file
+# _rt_file_flush - Write out what is waiting in a file's buffer (FLUSH #)
 # ------------------------------------------------------------------------------
 # Arguments:
 #   rcx = file number
-#   rdx = string pointer
-#   r8  = string length
 #
 # Returns: nothing
 # ------------------------------------------------------------------------------
-.globl _rt_file_print_string
-_rt_file_print_string:
-    push rbp
-    mov rbp, rsp
-    push rbx
-    push rdi
-    push rsi
-    sub rsp, 40             # Shadow space + stack arg
-
-    mov ebx, ecx            # save file number
-    mov rdi, rdx            # save string ptr
-    mov rsi, r8             # save string len
+.globl _rt_file_flush
+_rt_file_flush:
+    call _file_check
+    jmp _file_flush
 
-    # Get HANDLE from table
+# ------------------------------------------------------------------------------
+# _file_flush - Write out a file's buffer (internal)
+# ------------------------------------------------------------------------------
+# Does nothing for a file with nothing waiting, which includes every file
+# that isn't buffered.
+#
+# Arguments:
+#   rcx = file number (1-15)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_file_flush:
+    push rbx
+    sub rsp, 48             # Shadow space + stack arg + alignment
+    mov ebx, ecx
+    lea rax, [rip + _file_buf_len]
+    mov r8, QWORD PTR [rax + rbx*8]
+    test r8, r8
+    jz .Lfile_flush_done
+    mov QWORD PTR [rax + rbx*8], 0
+    # WriteFile(handle, buffer, waiting, &bytesWritten, NULL)
     lea rax, [rip + _file_handles]
-    mov rcx, [rax + rbx*8]  # hFile
-
-    # WriteFile(hFile, lpBuffer, nNumberOfBytesToWrite, lpNumberOfBytesWritten, lpOverlapped)
-    mov rdx, rdi            # lpBuffer = string ptr
-    mov r8, rsi             # nNumberOfBytesToWrite = length
+    mov rcx, QWORD PTR [rax + rbx*8]
+    mov rdx, rbx
+    shl rdx, FILE_BUF_SHIFT
+    lea rax, [rip + _file_bufs]
+    add rdx, rax
     lea r9, [rip + _file_bytes_written]
-    mov QWORD PTR [rsp + 32], 0  # lpOverlapped = NULL
+    mov QWORD PTR [rsp + 32], 0
     call WriteFile
+.Lfile_flush_done:
+    add rsp, 48
+    pop rbx
+    ret
 
-    add rsp, 40
-    pop rsi
-    pop rdi
+# ------------------------------------------------------------------------------
+# _file_flush_all - Write out every file's buffer (internal)
+# ------------------------------------------------------------------------------
+# Registered with atexit by the first OPEN of a disk file for output, and
+# called by _rt_error, since ExitProcess skips atexit handlers.
+#
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_file_flush_all:
+    push rbx
+    sub rsp, 32             # Shadow space
+    mov ebx, 1
+.Lfile_flush_all_loop:
+    mov ecx, ebx
+    call _file_flush
+    inc ebx
+    cmp ebx, 15
+    jbe .Lfile_flush_all_loop
+    add rsp, 32
     pop rbx
-    leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_print_float - Write number to file
+# _file_buffer - Add output to a buffered file (internal)
 # ------------------------------------------------------------------------------
+# Called by _out_raw for files with _file_buffered set. Writes out the
+# buffer first if the text doesn't fit, and text as big as the buffer
+# straight to the file.
+#
 # Arguments:
 #   rcx = file number
-#   xmm0 = value to write (double)
+#   rdx = pointer
+#   r8  = length
 #
 # Returns: nothing
 # ------------------------------------------------------------------------------
-.globl _rt_file_print_float
-_rt_file_print_float:
+_file_buffer:
     push rbp
     mov rbp, rsp
     push rbx
     push r12
-    sub rsp, 48             # Shadow space + alignment
-
-    mov ebx, ecx            # save file number
-
-    # Check if value is a whole number
-    cvttsd2si rax, xmm0     # truncate to integer
-    cvtsi2sd xmm1, rax      # convert back
-    ucomisd xmm0, xmm1      # compare
-    jne .Lfile_print_as_float
-
-    # Format as integer using sprintf
-    lea rcx, [rip + _file_output_buf]
-    lea rdx, [rip + _file_fmt_int]
-    mov r8, rax             # integer value
-    call sprintf
-    jmp .Lfile_print_formatted
-
-.Lfile_print_as_float:
-    # Format as float using sprintf
-    lea rcx, [rip + _file_output_buf]
-    lea rdx, [rip + _file_fmt_float]
-    movsd xmm2, xmm0        # value in xmm2
-    movq r8, xmm0           # also in r8 for varargs
-    call sprintf
-
-.Lfile_print_formatted:
-    mov r12, rax            # save length from sprintf
-
-    # Get HANDLE from table
+    push r13
+    sub rsp, 40             # Shadow space + stack arg
+    mov ebx, ecx            # rbx = file number
+    mov r12, rdx            # r12 = text
+    mov r13, r8             # r13 = length
+    lea rax, [rip + _file_buf_len]
+    mov rax, QWORD PTR [rax + rbx*8]
+    add rax, r13
+    cmp rax, FILE_BUF_SIZE
+    jbe .Lfile_buffer_copy
+    mov ecx, ebx
+    call _file_flush
+    cmp r13, FILE_BUF_SIZE
+    jb .Lfile_buffer_copy
+    # WriteFile(handle, text, length, &bytesWritten, NULL)
     lea rax, [rip + _file_handles]
-    mov rcx, [rax + rbx*8]  # hFile
-
-    # WriteFile(hFile, buffer, length, &bytesWritten, NULL)
-    lea rdx, [rip + _file_output_buf]
-    mov r8, r12             # length
+    mov rcx, QWORD PTR [rax + rbx*8]
+    mov rdx, r12
+    mov r8, r13
     lea r9, [rip + _file_bytes_written]
     mov QWORD PTR [rsp + 32], 0
     call WriteFile
-
-    add rsp, 48
+    jmp .Lfile_buffer_done
+.Lfile_buffer_copy:
+    # memcpy(buffer + waiting, text, length)
+    lea rax, [rip + _file_buf_len]
+    mov rcx, rbx
+    shl rcx, FILE_BUF_SHIFT
+    add rcx, QWORD PTR [rax + rbx*8]
+    lea rax, [rip + _file_bufs]
+    add rcx, rax
+    mov rdx, r12
+    mov r8, r13
+    call memcpy
+    lea rax, [rip + _file_buf_len]
+    add QWORD PTR [rax + rbx*8], r13
+.Lfile_buffer_done:
+    add rsp, 40
+    pop r13
     pop r12
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_print_char - Write single character to file
+# _rt_file_seek - Move a file to a byte position (SEEK # statement)
 # ------------------------------------------------------------------------------
 # Arguments:
 #   rcx = file number
-#   rdx = character code
+#   rdx = position (1 = the first byte)
 #
 # Returns: nothing
 # ------------------------------------------------------------------------------
-.globl _rt_file_print_char
-_rt_file_print_char:
+.globl _rt_file_seek
+_rt_file_seek:
+    call _file_check
+    cmp rdx, 1
+    jl _rt_illegal_call
     push rbp
     mov rbp, rsp
     push rbx
-    sub rsp, 40             # Shadow space + stack arg
-
-    mov ebx, ecx            # save file number
-
-    # Store char in buffer
-    lea rax, [rip + _file_output_buf]
-    mov [rax], dl
-
-    # Get HANDLE
+    push r12
+    sub rsp, 32             # Shadow space
+    mov ebx, ecx            # rbx = file number
+    lea r12, [rdx - 1]      # r12 = offset from the start
     lea rax, [rip + _file_handles]
-    mov rcx, [rax + rbx*8]  # hFile
-
-    # WriteFile(hFile, buffer, 1, &bytesWritten, NULL)
-    lea rdx, [rip + _file_output_buf]
-    mov r8, SINGLE_BYTE
-    lea r9, [rip + _file_bytes_written]
-    mov QWORD PTR [rsp + 32], 0
-    call WriteFile
-
-    add rsp, 40
+    mov rcx, QWORD PTR [rax + rbx*8]
+    call GetFileType
+    cmp eax, FILE_TYPE_DISK
+    jne _file_bad_mode
+    mov ecx, ebx
+    call _file_flush
+    # SetFilePointerEx(handle, offset, NULL, FILE_BEGIN)
+    lea rax, [rip + _file_handles]
+    mov rcx, QWORD PTR [rax + rbx*8]
+    mov rdx, r12
+    xor r8d, r8d
+    mov r9d, FILE_BEGIN
+    call SetFilePointerEx
+    # The next INPUT # reads from the new position
+    lea rax, [rip + _file_line_pos]
+    mov QWORD PTR [rax + rbx*8], 0
+    add rsp, 32
+    pop r12
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_print_newline - Write CRLF newline to file
+# _rt_file_seek_pos - Position of a file's next read or write (SEEK function)
 # ------------------------------------------------------------------------------
 # Arguments:
 #   rcx = file number
 #
-# Returns: nothing
+# Returns:
+#   rax = position (1 = the first byte)
 # ------------------------------------------------------------------------------
-.globl _rt_file_print_newline
-_rt_file_print_newline:
+.globl _rt_file_seek_pos
+_rt_file_seek_pos:
+    call _file_check
     push rbp
     mov rbp, rsp
     push rbx
-    sub rsp, 40             # Shadow space + stack arg
-
-    mov ebx, ecx            # save file number
-
-    # Get HANDLE
+    sub rsp, 40             # Shadow space + the new pointer
+    mov ebx, ecx            # rbx = file number
     lea rax, [rip + _file_handles]
-    mov rcx, [rax + rbx*8]  # hFile
-
-    # WriteFile(hFile, "\r\n", CRLF_LEN, &bytesWritten, NULL)
-    lea rdx, [rip + _file_newline]
-    mov r8, CRLF_LEN
-    lea r9, [rip + _file_bytes_written]
-    mov QWORD PTR [rsp + 32], 0
-    call WriteFile
-
+    mov rcx, QWORD PTR [rax + rbx*8]
+    call GetFileType
+    cmp eax, FILE_TYPE_DISK
+    jne _file_bad_mode
+    # SetFilePointerEx(handle, 0, &pointer, FILE_CURRENT)
+    lea rax, [rip + _file_handles]
+    mov rcx, QWORD PTR [rax + rbx*8]
+    xor edx, edx
+    lea r8, [rsp + 32]
+    mov r9d, FILE_CURRENT
+    call SetFilePointerEx
+    mov rax, QWORD PTR [rsp + 32]
+    lea rcx, [rip + _file_buf_len]
+    add rax, QWORD PTR [rcx + rbx*8]
+    # Back up over what INPUT # hasn't used of the current line:
+    # position - line length + (next field - line start)
+    lea rcx, [rip + _file_line_pos]
+    mov rdx, QWORD PTR [rcx + rbx*8]
+    test rdx, rdx
+    jz .Lseek_pos_done
+    lea rcx, [rip + _file_line_len]
+    sub rax, QWORD PTR [rcx + rbx*8]
+    add rax, rdx
+    mov rcx, rbx
+    shl rcx, 10
+    lea rdx, [rip + _file_lines]
+    add rcx, rdx
+    sub rax, rcx
+.Lseek_pos_done:
+    inc rax
     add rsp, 40
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_input_number - Read number from file
+# _rt_file_lock - Lock a file against other processes (LOCK # statement)
 # ------------------------------------------------------------------------------
-# Reads one line (up to newline) and parses as number.
-#
 # Arguments:
 #   rcx = file number
+#   rdx = first record
+#   r8  = last record
+#   r9  = 1 if a record range was given, else 0
 #
-# Returns:
-#   xmm0 = value read (double)
+# Returns: nothing ("Permission denied" if another process holds a lock)
 # ------------------------------------------------------------------------------
-.globl _rt_file_input_number
-_rt_file_input_number:
+.globl _rt_file_lock
+_rt_file_lock:
+    mov r10d, 1
+    jmp _file_lock
+
+# ------------------------------------------------------------------------------
+# _rt_file_unlock - Release a file's lock (UNLOCK # statement)
+# ------------------------------------------------------------------------------
+# Arguments: as _rt_file_lock
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_unlock
+_rt_file_unlock:
+    xor r10d, r10d
+    jmp _file_lock
+
+# ------------------------------------------------------------------------------
+# _file_lock - Lock or unlock a file (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx-r9 = as _rt_file_lock
+#   r10 = 1 to lock, 0 to unlock
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_file_lock:
+    call _file_check
+    # Records are numbered from 1, and a range can't end before it starts
+    test r9, r9
+    jz .Llock_file
+    cmp rdx, 1
+    jl _rt_illegal_call
+    cmp r8, rdx
+    jl _rt_illegal_call
+.Llock_file:
     push rbp
     mov rbp, rsp
     push rbx
     push r12
-    sub rsp, 48             # Shadow space + stack arg (must be 0 mod 16)
+    push r13
+    sub rsp, 88             # Shadow space + 2 stack args + OVERLAPPED
+    mov ebx, ecx            # rbx = file number
+    mov r12d, r10d          # r12 = 1 to lock
+    lea rax, [rip + _file_handles]
+    mov r13, QWORD PTR [rax + rbx*8]    # r13 = HANDLE
+    mov rcx, r13
+    call GetFileType
+    cmp eax, FILE_TYPE_DISK
+    jne .Llock_done
+
+    # OVERLAPPED with offset 0: the range starts at the start of the file
+    lea rax, [rsp + 48]
+    mov QWORD PTR [rax], 0
+    mov QWORD PTR [rax + 8], 0
+    mov QWORD PTR [rax + 16], 0
+    mov QWORD PTR [rax + 24], 0
+    test r12d, r12d
+    jnz .Llock_take
+
+    # Others see everything written before UNLOCK
+    mov ecx, ebx
+    call _file_flush
+    # UnlockFileEx(handle, 0, -1, -1, &overlapped); fails only if not locked
+    mov rcx, r13
+    xor edx, edx
+    mov r8d, -1
+    mov r9d, -1
+    lea rax, [rsp + 48]
+    mov QWORD PTR [rsp + 32], rax
+    call UnlockFileEx
+    jmp .Llock_done
+
+.Llock_take:
+    # LockFileEx(handle, flags, 0, -1, -1, &overlapped): the whole file,
+    # shared unless the file is written (only written disk files are buffered)
+    mov rcx, r13
+    mov edx, LOCK_SHARED
+    lea rax, [rip + _file_buffered]
+    cmp BYTE PTR [rax + rbx], 0
+    je .Llock_flags
+    mov edx, LOCK_EXCLUSIVE
+.Llock_flags:
+    xor r8d, r8d
+    mov r9d, -1
+    mov QWORD PTR [rsp + 32], -1
+    lea rax, [rsp + 48]
+    mov QWORD PTR [rsp + 40], rax
+    call LockFileEx
+    test eax, eax
+    jnz .Llock_done
+    lea rcx, [rip + _file_locked_msg]
+    mov edx, _file_locked_msg_len
+    jmp _rt_error
+.Llock_done:
+    add rsp, 88
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
 
-    mov ebx, ecx            # save file number
-    xor r12d, r12d          # r12 = position in buffer
+# ------------------------------------------------------------------------------
+# _rt_file_print_string - Write string to file
+# ------------------------------------------------------------------------------
+# File output goes through the output channel engine in print.s, which
+# tracks the column for WIDTH #.
+#
+# Arguments:
+#   rcx = file number
+#   rdx = string pointer
+#   r8  = string length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_string
+_rt_file_print_string:
+    call _file_check
+    jmp _out_write          # channel = file number
 
-.Lfile_input_num_loop:
-    # Check buffer overflow
-    cmp r12d, MAX_NUM_INPUT_LEN
-    jge .Lfile_input_num_parse
+# ------------------------------------------------------------------------------
+# _rt_file_print_float - Write number to file
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   xmm0 = value to write (double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_float
+_rt_file_print_float:
+    call _file_check
+    xor edx, edx            # as many digits as it takes
+    jmp _out_number
 
-    # ReadFile(hFile, &buffer[pos], 1, &bytesRead, NULL)
-    lea rax, [rip + _file_handles]
-    mov rcx, [rax + rbx*8]  # hFile
-    lea rdx, [rip + _file_input_buf]
-    add rdx, r12            # &buffer[pos]
-    mov r8, SINGLE_BYTE
-    lea r9, [rip + _file_bytes_read]
-    mov QWORD PTR [rsp + 32], 0
-    call ReadFile
+# ------------------------------------------------------------------------------
+# _rt_file_print_single - Write a SINGLE value to file
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   xmm0 = value to write (a SINGLE widened to double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_single
+_rt_file_print_single:
+    call _file_check
+    mov edx, 7              # 7 significant digits
+    jmp _out_number
 
-    # Check if we read anything
-    lea rax, [rip + _file_bytes_read]
-    mov rax, [rax]
-    test rax, rax
-    jz .Lfile_input_num_parse   # EOF
+# ------------------------------------------------------------------------------
+# _rt_file_print_char - Write single character to file
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   rdx = character code
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_char
+_rt_file_print_char:
+    call _file_check
+    jmp _out_char
 
-    # Check if it's a newline
-    lea rax, [rip + _file_input_buf]
-    mov cl, BYTE PTR [rax + r12]
-    cmp cl, CHAR_LF
-    je .Lfile_input_num_parse
-    cmp cl, CHAR_CR         # CR - skip it
-    je .Lfile_input_num_loop
+# ------------------------------------------------------------------------------
+# _rt_file_print_newline - Write CRLF newline to file
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_newline
+_rt_file_print_newline:
+    call _file_check
+    jmp _out_newline
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_zone - Advance to the next print zone (comma in PRINT #)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_zone
+_rt_file_print_zone:
+    call _file_check
+    jmp _out_zone
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_tab - Move to a column (TAB function in PRINT #)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   rdx = column (1-based)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_tab
+_rt_file_print_tab:
+    call _file_check
+    jmp _out_tab
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_spc - Write spaces (SPC function in PRINT #)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   rdx = number of spaces
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_spc
+_rt_file_print_spc:
+    call _file_check
+    jmp _out_spc
 
-    inc r12d                # next position
-    jmp .Lfile_input_num_loop
+# ------------------------------------------------------------------------------
+# _rt_file_print_using - Start a PRINT # USING statement
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   rdx = format pointer
+#   r8  = format length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_using
+_rt_file_print_using:
+    call _file_check
+    mov rcx, rdx
+    mov rdx, r8
+    jmp _rt_print_using
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_using_number - Write a number through the next USING field
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   xmm0 = value (double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_using_number
+_rt_file_print_using_number:
+    call _file_check
+    jmp _using_number
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_using_string - Write a string through the next USING field
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   rdx = pointer to string data
+#   r8  = string length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_using_string
+_rt_file_print_using_string:
+    call _file_check
+    jmp _using_string
+
+# ------------------------------------------------------------------------------
+# _rt_file_print_using_end - Write the USING format's text after the last
+# field used
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_print_using_end
+_rt_file_print_using_end:
+    call _file_check
+    jmp _using_text
 
-.Lfile_input_num_parse:
-    # Null-terminate
-    lea rax, [rip + _file_input_buf]
-    mov BYTE PTR [rax + r12], 0
+# ------------------------------------------------------------------------------
+# _rt_file_width - Set the line width of a file (WIDTH # statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#   rdx = width in columns (1-255; 255 = never wrap, the default)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_file_width
+_rt_file_width:
+    call _file_check
+    jmp _out_set_width
 
-    # Parse number using strtod(buffer, NULL)
-    lea rcx, [rip + _file_input_buf]
-    xor rdx, rdx            # endptr = NULL
+# ------------------------------------------------------------------------------
+# _rt_file_input_number - Read number from file (INPUT# with number)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#
+# Returns:
+#   xmm0 = value read (double); 0 for an empty field or at end of file
+# ------------------------------------------------------------------------------
+.globl _rt_file_input_number
+_rt_file_input_number:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    call _file_next_field
+    # strtod(field, NULL) stops at the comma or quote that ends the field
+    mov rcx, rax
+    xor edx, edx
     call strtod
+    leave
+    ret
 
-    # Result in xmm0
-    add rsp, 48
+# ------------------------------------------------------------------------------
+# _rt_file_input_string - Read string from file (INPUT# with string)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#
+# Returns:
+#   rax = pointer to a copy of the field (allocated by _rt_strcat)
+#   rdx = string length; empty at end of file
+# ------------------------------------------------------------------------------
+.globl _rt_file_input_string
+_rt_file_input_string:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    call _file_next_field
+    # Copy the field out of the line buffer: field + ""
+    mov rcx, rax
+    xor r9d, r9d
+    call _rt_strcat
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_file_line_input - Read a line from a file (LINE INPUT #)
+# ------------------------------------------------------------------------------
+# Takes the rest of the file's current line when INPUT # has read part of it,
+# else its next line.
+#
+# Arguments:
+#   rcx = file number
+#
+# Returns:
+#   rax = pointer to a copy of the line (allocated by _rt_strcat)
+#   rdx = string length; empty at end of file
+# ------------------------------------------------------------------------------
+.globl _rt_file_line_input
+_rt_file_line_input:
+    call _file_check_read
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    sub rsp, 32             # Shadow space
+
+    mov ebx, ecx            # save file number
+    lea rax, [rip + _file_line_pos]
+    mov r12, [rax + rbx*8]
+    test r12, r12
+    jnz .Lfile_line_rest
+    call _file_read_line
+    mov r12, rax
+
+.Lfile_line_rest:
+    # The whole line is used up
+    lea rax, [rip + _file_line_pos]
+    mov QWORD PTR [rax + rbx*8], 0
+    # Copy it out of the line buffer: line + ""
+    mov rcx, r12
+    call strlen
+    mov rcx, r12
+    mov rdx, rax
+    xor r9d, r9d
+    call _rt_strcat
+
+    add rsp, 32
     pop r12
     pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_file_input_string - Read string from file (line)
+# _file_next_field - Next INPUT # field of a file
 # ------------------------------------------------------------------------------
+# Takes the next comma-separated field of the file's current line, reading a
+# new line first when the last one is used up.
+#
 # Arguments:
 #   rcx = file number
 #
 # Returns:
-#   rax = pointer to string data (_file_input_buf)
-#   rdx = string length
+#   rax = field start (in _file_lines)
+#   rdx = field length
 # ------------------------------------------------------------------------------
-.globl _rt_file_input_string
-_rt_file_input_string:
+_file_next_field:
+    call _file_check_read
     push rbp
     mov rbp, rsp
     push rbx
     push r12
-    sub rsp, 48             # Shadow space + stack arg (must be 0 mod 16)
+    sub rsp, 32             # Shadow space
 
     mov ebx, ecx            # save file number
+    lea rax, [rip + _file_line_pos]
+    mov r8, [rax + rbx*8]
+    test r8, r8
+    jnz .Lfile_field_split
+    call _file_read_line
+    mov r8, rax
+
+.Lfile_field_split:
+    call _in_next_field
+    # Keep reading this line after a comma; a new line is needed at its end
+    test r9d, r9d
+    jnz .Lfile_field_save
+    xor r8d, r8d
+.Lfile_field_save:
+    lea rcx, [rip + _file_line_pos]
+    mov [rcx + rbx*8], r8
+
+    add rsp, 32
+    pop r12
+    pop rbx
+    leave
+    ret
 
-    # Clear buffer
-    lea rax, [rip + _file_input_buf]
-    mov BYTE PTR [rax], 0
+# ------------------------------------------------------------------------------
+# _file_read_line - Read a file's next line into its line buffer
+# ------------------------------------------------------------------------------
+# Reads one byte at a time, dropping CRs.
+#
+# Arguments:
+#   rcx = file number (already checked)
+#
+# Returns:
+#   rax = the line (in _file_lines), its newline stripped; empty at end of
+#         file. Its length as read goes in _file_line_len.
+# ------------------------------------------------------------------------------
+_file_read_line:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    sub rsp, 40             # Shadow space + stack arg (must be 0 mod 16)
 
-    # Read one character at a time until newline or EOF
-    xor r12d, r12d          # r12 = position in buffer
+    mov ebx, ecx            # save file number
+    # r12 = this file's line buffer, r13 = position in it
+    mov r12, rbx
+    shl r12, 10
+    lea rax, [rip + _file_lines]
+    add r12, rax
+    xor r13d, r13d
+    lea rax, [rip + _file_line_len]
+    mov QWORD PTR [rax + rbx*8], 0
 
-.Lfile_input_str_loop:
-    # Check buffer overflow
-    cmp r12d, MAX_STR_INPUT_LEN
-    jge .Lfile_input_str_done
+.Lfile_read_next:
+    cmp r13d, MAX_STR_INPUT_LEN
+    jge .Lfile_read_done
 
     # ReadFile(hFile, &buffer[pos], 1, &bytesRead, NULL)
     lea rax, [rip + _file_handles]
     mov rcx, [rax + rbx*8]  # hFile
-    lea rdx, [rip + _file_input_buf]
-    add rdx, r12            # &buffer[pos]
+    lea rdx, [r12 + r13]    # &buffer[pos]
     mov r8, SINGLE_BYTE
     lea r9, [rip + _file_bytes_read]
     mov QWORD PTR [rsp + 32], 0
@@ -483,28 +1425,81 @@ _rt_file_input_string:
     lea rax, [rip + _file_bytes_read]
     mov rax, [rax]
     test rax, rax
-    jz .Lfile_input_str_done    # EOF
+    jz .Lfile_read_done     # EOF
+    lea rax, [rip + _file_line_len]
+    inc QWORD PTR [rax + rbx*8]
 
     # Check if it's a newline
-    lea rax, [rip + _file_input_buf]
-    mov cl, BYTE PTR [rax + r12]
+    mov cl, BYTE PTR [r12 + r13]
     cmp cl, CHAR_LF
-    je .Lfile_input_str_done
+    je .Lfile_read_done
     cmp cl, CHAR_CR         # CR - skip it
-    je .Lfile_input_str_loop
+    je .Lfile_read_next
 
-    inc r12d                # next position
-    jmp .Lfile_input_str_loop
+    inc r13d                # next position
+    jmp .Lfile_read_next
 
-.Lfile_input_str_done:
-    # Null-terminate
-    lea rax, [rip + _file_input_buf]
-    mov BYTE PTR [rax + r12], 0
-    mov rdx, r12            # length
+.Lfile_read_done:
+    mov BYTE PTR [r12 + r13], 0
+    mov rax, r12
 
-    add rsp, 48
+    add rsp, 40
+    pop r13
     pop r12
     pop rbx
     leave
     ret
 
+# ------------------------------------------------------------------------------
+# _file_check - Raise "Bad file number" unless the file number is open
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#
+# Returns: only if file number rcx (1-15) has an open file. Uses only rax and
+# r11, so the caller's arguments are kept.
+# ------------------------------------------------------------------------------
+_file_check:
+    lea eax, [rcx - 1]      # file numbers are 1-15
+    cmp eax, 14
+    ja _rt_bad_file
+    mov r11d, ecx
+    lea rax, [rip + _file_handles]
+    mov rax, QWORD PTR [rax + r11*8]
+    test rax, rax
+    jz _rt_bad_file
+    cmp rax, INVALID_HANDLE_VALUE
+    je _rt_bad_file
+    ret
+
+# ------------------------------------------------------------------------------
+# _file_check_read - _file_check for INPUT #, also raising "Bad file mode"
+# for a file opened FOR OUTPUT or APPEND
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = file number
+#
+# Returns: only if the file is open to read. Uses only rax and r11.
+# ------------------------------------------------------------------------------
+_file_check_read:
+    call _file_check
+    lea rax, [rip + _file_written]
+    cmp BYTE PTR [rax + r11], 0
+    jne _file_bad_mode
+    ret
+
+# ------------------------------------------------------------------------------
+# _file_bad_mode - Raise "Bad file mode" (jumped to; never returns)
+# ------------------------------------------------------------------------------
+_file_bad_mode:
+    lea rcx, [rip + _file_bad_mode_msg]
+    mov edx, _file_bad_mode_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _file_already_open - Raise "File already open" (jumped to; never returns)
+# ------------------------------------------------------------------------------
+_file_already_open:
+    lea rcx, [rip + _file_open_msg]
+    mov edx, _file_open_msg_len
+    jmp _rt_error
@@ -0,0 +1,1713 @@
+# ==============================================================================
+# BASIC Runtime: Graphics Functions (Win64 Native - Pure Win32 API)
+# ==============================================================================
+#
+# Pixel graphics for SCREEN, PSET, PRESET, LINE, CIRCLE, PAINT, DRAW, GET,
+# PUT, PALETTE, POINT and RGB.
+#
+# The runtime keeps an in-memory framebuffer with one byte per pixel holding
+# a palette index. The framebuffer backend writes that buffer out as a binary
+# PPM (P6) image whenever the program executes DISPLAY, switches back to
+# SCREEN 0, or exits (an atexit handler is registered by the first SCREEN).
+#
+# The image is written to the file named by the XBASIC64_FRAMEBUFFER
+# environment variable, or "screen.ppm" in the current directory if unset.
+#
+# Supported modes:
+#   SCREEN 1  = 320x200,   4 colors (CGA palette 1)
+#   SCREEN 2  = 640x200,   2 colors
+#   SCREEN 7  = 320x200,  16 colors
+#   SCREEN 8  = 640x200,  16 colors
+#   SCREEN 9  = 640x350,  16 colors
+#   SCREEN 11 = 640x480,   2 colors
+#   SCREEN 12 = 640x480,  16 colors
+#   SCREEN 13 = 320x200, 256 colors (VGA default palette)
+#   SCREEN 0  = text mode (flushes and releases the framebuffer)
+#
+# Win64 ABI:
+#   - Args: rcx, rdx, r8, r9, then stack (after 32-byte shadow space)
+#   - Public entry points move their arguments into rdi/rsi/rdx/rcx/r8 so
+#     the internal drawing helpers are shared with the System V runtime.
+#
+# Coordinates outside the screen are clipped. Colors are masked to the
+# number of colors in the current mode. A color argument of -1 selects the
+# mode's default foreground color. PALETTE takes VGA (&HBBGGRR, 0-63 per
+# component) or RGB() (&HFFRRGGBB) colors, as in the System V runtime.
+#
+# Global state:
+#   _gfx_buf      = pointer to framebuffer (NULL in text mode)
+#   _gfx_width    = width in pixels
+#   _gfx_height   = height in pixels
+#   _gfx_colors   = number of colors (always a power of two)
+#   _gfx_fg       = default foreground color
+#   _gfx_last_x/y = last point referenced (graphics cursor)
+#   _gfx_palette  = active palette, 256 RGB triples
+#   _gfx_draw_*   = DRAW state (color, scale, rotation)
+# ==============================================================================
+
+# Win32 API Constants
+.equ GENERIC_WRITE,         0x40000000
+.equ CREATE_ALWAYS,         2
+.equ FILE_ATTRIBUTE_NORMAL, 0x80
+.equ INVALID_HANDLE_VALUE,  -1
+.equ HEAP_ZERO_MEMORY,      8
+.equ STD_OUTPUT_HANDLE,     -11
+.equ GFX_PATH_MAX,          260
+.equ GFX_FILL_STACK_ENTRIES, 614400     # 2 seeds per pixel at 640x480
+
+.data
+_gfx_buf: .quad 0
+_gfx_width: .quad 0
+_gfx_height: .quad 0
+_gfx_colors: .quad 0
+_gfx_fg: .quad 0
+_gfx_last_x: .quad 0
+_gfx_last_y: .quad 0
+_gfx_exit_registered: .quad 0
+_gfx_palette: .skip 768
+_gfx_draw_color: .quad -1
+_gfx_draw_scale: .quad 4
+_gfx_draw_cos: .double 1.0
+_gfx_draw_sin: .double 0.0
+_gfx_deg_to_rad: .double 0.017453292519943295
+_gfx_env_name: .asciz "XBASIC64_FRAMEBUFFER"
+_gfx_default_file: .asciz "screen.ppm"
+_gfx_ppm_header: .asciz "P6\n%lld %lld\n255\n"
+_gfx_error_msg: .ascii "Illegal function call"
+_gfx_error_msg_len = 21
+_gfx_path_buf: .skip 260
+_gfx_header_buf: .skip 64
+_gfx_bytes_written: .quad 0
+
+# Mode table: mode number, width, height, colors, default foreground
+_gfx_modes:
+    .quad 1, 320, 200, 4, 3
+    .quad 2, 640, 200, 2, 1
+    .quad 7, 320, 200, 16, 15
+    .quad 8, 640, 200, 16, 15
+    .quad 9, 640, 350, 16, 15
+    .quad 11, 640, 480, 2, 1
+    .quad 12, 640, 480, 16, 15
+    .quad 13, 320, 200, 256, 15
+    .quad -1
+
+# CGA palette 1 (black, cyan, magenta, white) for SCREEN 1
+_gfx_cga_palette:
+    .byte 0, 0, 0, 85, 255, 255, 255, 85, 255, 255, 255, 255
+
+# Two-color palette (black, white) for SCREEN 2 and SCREEN 11
+_gfx_mono_palette:
+    .byte 0, 0, 0, 255, 255, 255
+
+# VGA default palette (the first 16 entries are the EGA colors)
+_gfx_vga_palette:
+    .byte 0, 0, 0, 0, 0, 170, 0, 170, 0, 0, 170, 170   # 0-3
+    .byte 170, 0, 0, 170, 0, 170, 170, 85, 0, 170, 170, 170   # 4-7
+    .byte 85, 85, 85, 85, 85, 255, 85, 255, 85, 85, 255, 255   # 8-11
+    .byte 255, 85, 85, 255, 85, 255, 255, 255, 85, 255, 255, 255   # 12-15
+    .byte 0, 0, 0, 20, 20, 20, 32, 32, 32, 45, 45, 45   # 16-19
+    .byte 57, 57, 57, 69, 69, 69, 81, 81, 81, 97, 97, 97   # 20-23
+    .byte 113, 113, 113, 130, 130, 130, 146, 146, 146, 162, 162, 162   # 24-27
+    .byte 182, 182, 182, 202, 202, 202, 227, 227, 227, 255, 255, 255   # 28-31
+    .byte 0, 0, 255, 65, 0, 255, 125, 0, 255, 190, 0, 255   # 32-35
+    .byte 255, 0, 255, 255, 0, 190, 255, 0, 125, 255, 0, 65   # 36-39
+    .byte 255, 0, 0, 255, 65, 0, 255, 125, 0, 255, 190, 0   # 40-43
+    .byte 255, 255, 0, 190, 255, 0, 125, 255, 0, 65, 255, 0   # 44-47
+    .byte 0, 255, 0, 0, 255, 65, 0, 255, 125, 0, 255, 190   # 48-51
+    .byte 0, 255, 255, 0, 190, 255, 0, 125, 255, 0, 65, 255   # 52-55
+    .byte 125, 125, 255, 158, 125, 255, 190, 125, 255, 223, 125, 255   # 56-59
+    .byte 255, 125, 255, 255, 125, 223, 255, 125, 190, 255, 125, 158   # 60-63
+    .byte 255, 125, 125, 255, 158, 125, 255, 190, 125, 255, 223, 125   # 64-67
+    .byte 255, 255, 125, 223, 255, 125, 190, 255, 125, 158, 255, 125   # 68-71
+    .byte 125, 255, 125, 125, 255, 158, 125, 255, 190, 125, 255, 223   # 72-75
+    .byte 125, 255, 255, 125, 223, 255, 125, 190, 255, 125, 158, 255   # 76-79
+    .byte 182, 182, 255, 198, 182, 255, 219, 182, 255, 235, 182, 255   # 80-83
+    .byte 255, 182, 255, 255, 182, 235, 255, 182, 219, 255, 182, 198   # 84-87
+    .byte 255, 182, 182, 255, 198, 182, 255, 219, 182, 255, 235, 182   # 88-91
+    .byte 255, 255, 182, 235, 255, 182, 219, 255, 182, 198, 255, 182   # 92-95
+    .byte 182, 255, 182, 182, 255, 198, 182, 255, 219, 182, 255, 235   # 96-99
+    .byte 182, 255, 255, 182, 235, 255, 182, 219, 255, 182, 198, 255   # 100-103
+    .byte 0, 0, 113, 28, 0, 113, 57, 0, 113, 85, 0, 113   # 104-107
+    .byte 113, 0, 113, 113, 0, 85, 113, 0, 57, 113, 0, 28   # 108-111
+    .byte 113, 0, 0, 113, 28, 0, 113, 57, 0, 113, 85, 0   # 112-115
+    .byte 113, 113, 0, 85, 113, 0, 57, 113, 0, 28, 113, 0   # 116-119
+    .byte 0, 113, 0, 0, 113, 28, 0, 113, 57, 0, 113, 85   # 120-123
+    .byte 0, 113, 113, 0, 85, 113, 0, 57, 113, 0, 28, 113   # 124-127
+    .byte 57, 57, 113, 69, 57, 113, 85, 57, 113, 97, 57, 113   # 128-131
+    .byte 113, 57, 113, 113, 57, 97, 113, 57, 85, 113, 57, 69   # 132-135
+    .byte 113, 57, 57, 113, 69, 57, 113, 85, 57, 113, 97, 57   # 136-139
+    .byte 113, 113, 57, 97, 113, 57, 85, 113, 57, 69, 113, 57   # 140-143
+    .byte 57, 113, 57, 57, 113, 69, 57, 113, 85, 57, 113, 97   # 144-147
+    .byte 57, 113, 113, 57, 97, 113, 57, 85, 113, 57, 69, 113   # 148-151
+    .byte 81, 81, 113, 89, 81, 113, 97, 81, 113, 105, 81, 113   # 152-155
+    .byte 113, 81, 113, 113, 81, 105, 113, 81, 97, 113, 81, 89   # 156-159
+    .byte 113, 81, 81, 113, 89, 81, 113, 97, 81, 113, 105, 81   # 160-163
+    .byte 113, 113, 81, 105, 113, 81, 97, 113, 81, 89, 113, 81   # 164-167
+    .byte 81, 113, 81, 81, 113, 89, 81, 113, 97, 81, 113, 105   # 168-171
+    .byte 81, 113, 113, 81, 105, 113, 81, 97, 113, 81, 89, 113   # 172-175
+    .byte 0, 0, 65, 16, 0, 65, 32, 0, 65, 49, 0, 65   # 176-179
+    .byte 65, 0, 65, 65, 0, 49, 65, 0, 32, 65, 0, 16   # 180-183
+    .byte 65, 0, 0, 65, 16, 0, 65, 32, 0, 65, 49, 0   # 184-187
+    .byte 65, 65, 0, 49, 65, 0, 32, 65, 0, 16, 65, 0   # 188-191
+    .byte 0, 65, 0, 0, 65, 16, 0, 65, 32, 0, 65, 49   # 192-195
+    .byte 0, 65, 65, 0, 49, 65, 0, 32, 65, 0, 16, 65   # 196-199
+    .byte 32, 32, 65, 36, 32, 65, 45, 32, 65, 49, 32, 65   # 200-203
+    .byte 65, 32, 65, 65, 32, 49, 65, 32, 45, 65, 32, 36   # 204-207
+    .byte 65, 32, 32, 65, 36, 32, 65, 45, 32, 65, 49, 32   # 208-211
+    .byte 65, 65, 32, 49, 65, 32, 45, 65, 32, 36, 65, 32   # 212-215
+    .byte 32, 65, 32, 32, 65, 36, 32, 65, 45, 32, 65, 49   # 216-219
+    .byte 32, 65, 65, 32, 49, 65, 32, 45, 65, 32, 36, 65   # 220-223
+    .byte 45, 45, 65, 49, 45, 65, 53, 45, 65, 61, 45, 65   # 224-227
+    .byte 65, 45, 65, 65, 45, 61, 65, 45, 53, 65, 45, 49   # 228-231
+    .byte 65, 45, 45, 65, 49, 45, 65, 53, 45, 65, 61, 45   # 232-235
+    .byte 65, 65, 45, 61, 65, 45, 53, 65, 45, 49, 65, 45   # 236-239
+    .byte 45, 65, 45, 45, 65, 49, 45, 65, 53, 45, 65, 61   # 240-243
+    .byte 45, 65, 65, 45, 61, 65, 45, 53, 65, 45, 49, 65   # 244-247
+    .byte 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0   # 248-251
+    .byte 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0   # 252-255
+
+.bss
+_gfx_fill_stack: .skip 8 * GFX_FILL_STACK_ENTRIES   # PAINT seeds (x, y as 32-bit pairs)
+
+.text
+
+# ------------------------------------------------------------------------------
+# _rt_screen - Select screen mode (SCREEN statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = mode number
+#
+# Returns: nothing (unsupported mode -> "Illegal function call", exit 1)
+# ------------------------------------------------------------------------------
+.globl _rt_screen
+_rt_screen:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push rdi
+    push rsi
+    sub rsp, 32             # Shadow space
+    mov rbx, rcx            # rbx = requested mode
+    call _scr_clear         # the text screen too (see print.s)
+    test rbx, rbx
+    jnz .Lscreen_graphics
+
+    # SCREEN 0: write out the last image, then release the framebuffer
+    call _rt_gfx_flush
+    call _gfx_free
+    jmp .Lscreen_done
+
+.Lscreen_graphics:
+    # Look up mode in table (40 bytes per entry, -1 terminated)
+    lea r12, [rip + _gfx_modes]
+.Lscreen_find:
+    mov rax, QWORD PTR [r12]
+    cmp rax, -1
+    je _rt_illegal_call
+    cmp rax, rbx
+    je .Lscreen_found
+    add r12, 40
+    jmp .Lscreen_find
+
+.Lscreen_found:
+    # Changing modes clears the screen: drop any previous framebuffer
+    call _gfx_free
+
+    mov rax, QWORD PTR [r12 + 8]
+    mov QWORD PTR [rip + _gfx_width], rax
+    shr rax, 1
+    mov QWORD PTR [rip + _gfx_last_x], rax   # graphics cursor starts centered
+    mov rax, QWORD PTR [r12 + 16]
+    mov QWORD PTR [rip + _gfx_height], rax
+    shr rax, 1
+    mov QWORD PTR [rip + _gfx_last_y], rax
+    mov rax, QWORD PTR [r12 + 24]
+    mov QWORD PTR [rip + _gfx_colors], rax
+    mov rax, QWORD PTR [r12 + 32]
+    mov QWORD PTR [rip + _gfx_fg], rax
+
+    # HeapAlloc(GetProcessHeap(), HEAP_ZERO_MEMORY, width * height)
+    call GetProcessHeap
+    mov rcx, rax
+    mov edx, HEAP_ZERO_MEMORY
+    mov r8, QWORD PTR [rip + _gfx_width]
+    imul r8, QWORD PTR [rip + _gfx_height]
+    call HeapAlloc
+    mov QWORD PTR [rip + _gfx_buf], rax
+
+    call _gfx_load_palette
+
+    # Flush the framebuffer at program exit (registered once)
+    cmp QWORD PTR [rip + _gfx_exit_registered], 0
+    jne .Lscreen_done
+    mov QWORD PTR [rip + _gfx_exit_registered], 1
+    lea rcx, [rip + _rt_gfx_flush]
+    call atexit
+
+.Lscreen_done:
+    add rsp, 32
+    pop rsi
+    pop rdi
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_pset - Set a pixel (PSET / PRESET statements)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = x coordinate
+#   rdx = y coordinate
+#   r8  = color (-1 = default foreground)
+#
+# Returns: nothing. Updates the graphics cursor to (x, y).
+# ------------------------------------------------------------------------------
+.globl _rt_pset
+_rt_pset:
+    push rbp
+    mov rbp, rsp
+    push rdi
+    push rsi
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+    mov rdi, rcx
+    mov rsi, rdx
+    mov rdx, r8
+    call _gfx_color
+    mov QWORD PTR [rip + _gfx_last_x], rdi
+    mov QWORD PTR [rip + _gfx_last_y], rsi
+    call _gfx_plot
+    pop rsi
+    pop rdi
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_point - Read a pixel (POINT function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = x coordinate
+#   rdx = y coordinate
+#
+# Returns:
+#   rax = palette index at (x, y), or -1 if the point is off screen
+# ------------------------------------------------------------------------------
+.globl _rt_point
+_rt_point:
+    push rbp
+    mov rbp, rsp
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+    mov rax, -1
+    cmp rcx, QWORD PTR [rip + _gfx_width]
+    jae .Lpoint_done        # unsigned compare also rejects negatives
+    cmp rdx, QWORD PTR [rip + _gfx_height]
+    jae .Lpoint_done
+    mov rax, rdx
+    imul rax, QWORD PTR [rip + _gfx_width]
+    add rax, rcx
+    add rax, QWORD PTR [rip + _gfx_buf]
+    movzx eax, BYTE PTR [rax]
+.Lpoint_done:
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_palette - Set the color of a palette entry (PALETTE attribute, color)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = attribute (0 to the mode's colors - 1)
+#   rdx = color: &HBBGGRR with 0-63 per component, or &HFFRRGGBB from RGB
+#
+# Returns: nothing (text mode or a bad argument -> "Illegal function call")
+# ------------------------------------------------------------------------------
+.globl _rt_palette
+_rt_palette:
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+    cmp rcx, QWORD PTR [rip + _gfx_colors]
+    jae _rt_illegal_call    # unsigned compare also rejects negatives
+    lea rcx, [rcx + rcx*2]
+    lea r8, [rip + _gfx_palette]
+    add r8, rcx             # r8 = palette entry
+    mov rax, rdx
+    sar rax, 24
+    cmp rax, -1             # &HFFRRGGBB as a LONG
+    je .Lpalette_rgb
+    cmp rax, 255            # or as a positive number
+    je .Lpalette_rgb
+    test rax, rax
+    jnz _rt_illegal_call
+    test edx, 0xC0C0C0      # VGA components stop at 63
+    jnz _rt_illegal_call
+    # Scale each 6-bit component to 8 bits: (v << 2) | (v >> 4)
+    xor ecx, ecx
+.Lpalette_vga:
+    mov eax, edx
+    and eax, 63
+    lea r9d, [rax*4]
+    shr eax, 4
+    or eax, r9d
+    mov BYTE PTR [r8 + rcx], al
+    shr edx, 8
+    inc ecx
+    cmp ecx, 3
+    jb .Lpalette_vga
+    ret
+.Lpalette_rgb:
+    mov eax, edx
+    shr eax, 16
+    mov BYTE PTR [r8], al
+    mov eax, edx
+    shr eax, 8
+    mov BYTE PTR [r8 + 1], al
+    mov BYTE PTR [r8 + 2], dl
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_palette_reset - Restore the mode's default palette (PALETTE statement)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing (text mode -> "Illegal function call")
+# ------------------------------------------------------------------------------
+.globl _rt_palette_reset
+_rt_palette_reset:
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+    push rdi
+    push rsi
+    call _gfx_load_palette
+    pop rsi
+    pop rdi
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_rgb - Pack a 24-bit color for PALETTE (RGB function)
+# ------------------------------------------------------------------------------
+# Components are clamped to 0-255.
+#
+# Arguments:
+#   rcx = red
+#   rdx = green
+#   r8  = blue
+#
+# Returns:
+#   eax = &HFFRRGGBB (a negative LONG)
+# ------------------------------------------------------------------------------
+.globl _rt_rgb
+_rt_rgb:
+    mov r9, rdx             # r9 = green (the helper clobbers rcx and rdx)
+    mov eax, 0xFF
+    call _gfx_rgb_shift     # red is already in rcx
+    mov rcx, r9
+    call _gfx_rgb_shift
+    mov rcx, r8
+    call _gfx_rgb_shift
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_line - Draw a line or box (LINE statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx        = x1
+#   rdx        = y1
+#   r8         = x2
+#   r9         = y2
+#   [rbp + 48] = color (-1 = default foreground)
+#   [rbp + 56] = style: 0 = line, 1 = box outline (B), 2 = filled box (BF)
+#
+# Returns: nothing. Updates the graphics cursor to (x2, y2).
+# ------------------------------------------------------------------------------
+.globl _rt_line
+_rt_line:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    push rdi
+    push rsi
+    sub rsp, 8              # Keep 16-byte alignment
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+
+    mov r12, rcx            # r12 = x1
+    mov r13, rdx            # r13 = y1
+    mov r14, r8             # r14 = x2
+    mov r15, r9             # r15 = y2
+    mov rbx, QWORD PTR [rbp + 56]   # rbx = style
+    mov rdx, QWORD PTR [rbp + 48]
+    call _gfx_color         # rdx = resolved color
+    mov QWORD PTR [rip + _gfx_last_x], r14
+    mov QWORD PTR [rip + _gfx_last_y], r15
+
+    cmp rbx, 2
+    je .Lline_fill
+    cmp rbx, 1
+    je .Lline_box
+
+    mov rdi, r12
+    mov rsi, r13
+    mov rcx, r14
+    mov r8, r15
+    call _gfx_line
+    jmp .Lline_done
+
+.Lline_box:
+    # Four edges: top, right, bottom, left
+    mov rdi, r12
+    mov rsi, r13
+    mov rcx, r14
+    mov r8, r13
+    call _gfx_line
+    mov rdi, r14
+    mov rsi, r13
+    mov rcx, r14
+    mov r8, r15
+    call _gfx_line
+    mov rdi, r14
+    mov rsi, r15
+    mov rcx, r12
+    mov r8, r15
+    call _gfx_line
+    mov rdi, r12
+    mov rsi, r15
+    mov rcx, r12
+    mov r8, r13
+    call _gfx_line
+    jmp .Lline_done
+
+.Lline_fill:
+    # Normalize corners so x1 <= x2 and y1 <= y2, then draw horizontal spans
+    cmp r12, r14
+    jle .Lline_fill_x_ok
+    xchg r12, r14
+.Lline_fill_x_ok:
+    cmp r13, r15
+    jle .Lline_fill_y_ok
+    xchg r13, r15
+.Lline_fill_y_ok:
+    mov rbx, r13            # rbx = current row
+.Lline_fill_loop:
+    cmp rbx, r15
+    jg .Lline_done
+    mov rdi, r12
+    mov rsi, rbx
+    mov rcx, r14
+    mov r8, rbx
+    call _gfx_line
+    inc rbx
+    jmp .Lline_fill_loop
+
+.Lline_done:
+    add rsp, 8
+    pop rsi
+    pop rdi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_line_to - Draw from the graphics cursor (LINE -(x2, y2) statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = x2
+#   rdx = y2
+#   r8  = color (-1 = default foreground)
+#   r9  = style (as for _rt_line)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_line_to
+_rt_line_to:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 48             # Shadow space + 2 stack args
+    mov QWORD PTR [rsp + 32], r8
+    mov QWORD PTR [rsp + 40], r9
+    mov r8, rcx
+    mov r9, rdx
+    mov rcx, QWORD PTR [rip + _gfx_last_x]
+    mov rdx, QWORD PTR [rip + _gfx_last_y]
+    call _rt_line
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_circle - Draw a circle (CIRCLE statement)
+# ------------------------------------------------------------------------------
+# Uses the midpoint circle algorithm. Circles are round in pixel space
+# (no aspect ratio correction).
+#
+# Arguments:
+#   rcx = center x
+#   rdx = center y
+#   r8  = radius
+#   r9  = color (-1 = default foreground)
+#
+# Returns: nothing. Updates the graphics cursor to the center.
+# ------------------------------------------------------------------------------
+.globl _rt_circle
+_rt_circle:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    push rdi
+    push rsi
+    sub rsp, 8              # Keep 16-byte alignment
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+
+    mov r12, rcx            # r12 = center x
+    mov r13, rdx            # r13 = center y
+    mov r14, r8             # r14 = x offset (starts at radius)
+    mov rdx, r9
+    call _gfx_color         # rdx = resolved color
+    mov QWORD PTR [rip + _gfx_last_x], r12
+    mov QWORD PTR [rip + _gfx_last_y], r13
+
+    test r14, r14
+    jge .Lcircle_radius_ok
+    neg r14
+.Lcircle_radius_ok:
+    xor r15d, r15d          # r15 = y offset
+    mov rbx, 1
+    sub rbx, r14            # rbx = decision variable (1 - radius)
+
+.Lcircle_loop:
+    cmp r15, r14
+    jg .Lcircle_done
+    # Plot one point in each octant
+    lea rdi, [r12 + r14]
+    lea rsi, [r13 + r15]
+    call _gfx_plot
+    mov rdi, r12
+    sub rdi, r14
+    call _gfx_plot
+    mov rsi, r13
+    sub rsi, r15
+    call _gfx_plot
+    lea rdi, [r12 + r14]
+    call _gfx_plot
+    lea rdi, [r12 + r15]
+    lea rsi, [r13 + r14]
+    call _gfx_plot
+    mov rdi, r12
+    sub rdi, r15
+    call _gfx_plot
+    mov rsi, r13
+    sub rsi, r14
+    call _gfx_plot
+    lea rdi, [r12 + r15]
+    call _gfx_plot
+
+    inc r15
+    test rbx, rbx
+    jge .Lcircle_step_x
+    lea rbx, [rbx + r15*2 + 1]      # d += 2y + 1
+    jmp .Lcircle_loop
+.Lcircle_step_x:
+    dec r14
+    mov rax, r15
+    sub rax, r14
+    lea rbx, [rbx + rax*2 + 1]      # d += 2(y - x) + 1
+    jmp .Lcircle_loop
+
+.Lcircle_done:
+    add rsp, 8
+    pop rsi
+    pop rdi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_paint - Flood fill a region (PAINT statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = x coordinate
+#   rdx = y coordinate
+#   r8  = paint color (-1 = default foreground)
+#   r9  = border color (-1 = same as paint color)
+#
+# Returns: nothing. Updates the graphics cursor to (x, y).
+# ------------------------------------------------------------------------------
+.globl _rt_paint
+_rt_paint:
+    push rbp
+    mov rbp, rsp
+    push rdi
+    push rsi
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+    mov rdi, rcx
+    mov rsi, rdx
+    mov rdx, r8
+    mov rcx, r9
+    call _gfx_color         # rdx = paint color
+    cmp rcx, -1
+    jne .Lrt_paint_border
+    mov rcx, rdx
+    jmp .Lrt_paint_fill
+.Lrt_paint_border:
+    mov rax, QWORD PTR [rip + _gfx_colors]
+    dec rax
+    and rcx, rax
+.Lrt_paint_fill:
+    mov QWORD PTR [rip + _gfx_last_x], rdi
+    mov QWORD PTR [rip + _gfx_last_y], rsi
+    cmp rdi, QWORD PTR [rip + _gfx_width]
+    jae .Lrt_paint_done     # Off screen: nothing to fill
+    cmp rsi, QWORD PTR [rip + _gfx_height]
+    jae .Lrt_paint_done
+    call _gfx_paint
+.Lrt_paint_done:
+    pop rsi
+    pop rdi
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_draw - Run a DRAW command string (DRAW statement)
+# ------------------------------------------------------------------------------
+# See _gfx_draw for the command language.
+#
+# Arguments:
+#   rcx = string pointer
+#   rdx = string length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_draw
+_rt_draw:
+    push rbp
+    mov rbp, rsp
+    push rdi
+    push rsi
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+    mov rdi, rcx
+    mov rsi, rdx
+    call _gfx_draw
+    pop rsi
+    pop rdi
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_get_image - Capture a screen rectangle into an array (GET statement)
+# ------------------------------------------------------------------------------
+# See _gfx_get for the buffer layout.
+#
+# Arguments:
+#   rcx = x1, rdx = y1, r8 = x2, r9 = y2
+#   [rbp + 48] = array buffer pointer
+#   [rbp + 56] = array buffer size in bytes
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_get_image
+_rt_get_image:
+    push rbp
+    mov rbp, rsp
+    push rdi
+    push rsi
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+    mov rdi, rcx
+    mov rsi, rdx
+    mov rdx, r8
+    mov rcx, r9
+    mov r8, QWORD PTR [rbp + 48]
+    mov r9, QWORD PTR [rbp + 56]
+    call _gfx_get
+    pop rsi
+    pop rdi
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_put_image - Draw an image captured by GET (PUT statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = x, rdx = y
+#   r8  = array buffer pointer
+#   r9  = array buffer size in bytes
+#   [rbp + 48] = mode: 0 = PSET, 1 = PRESET, 2 = AND, 3 = OR, 4 = XOR
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_put_image
+_rt_put_image:
+    push rbp
+    mov rbp, rsp
+    push rdi
+    push rsi
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je _rt_illegal_call
+    mov rdi, rcx
+    mov rsi, rdx
+    mov rdx, r8
+    mov rcx, r9
+    mov r8, QWORD PTR [rbp + 48]
+    call _gfx_put
+    pop rsi
+    pop rdi
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_gfx_flush - Write the framebuffer out (DISPLAY statement, program exit)
+# ------------------------------------------------------------------------------
+# Writes the current image as a binary PPM file. Does nothing in text mode.
+# I/O errors are ignored: graphics output is best effort.
+#
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_gfx_flush
+_rt_gfx_flush:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push rdi
+    push rsi
+    sub rsp, 64             # Shadow space + 3 stack args (must be 0 mod 16)
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je .Lflush_done
+
+    # Output file: %XBASIC64_FRAMEBUFFER% or the default name
+    lea rcx, [rip + _gfx_env_name]
+    lea rdx, [rip + _gfx_path_buf]
+    mov r8d, GFX_PATH_MAX
+    call GetEnvironmentVariableA
+    lea rcx, [rip + _gfx_path_buf]
+    test eax, eax
+    jz .Lflush_default_name
+    cmp eax, GFX_PATH_MAX
+    jb .Lflush_open
+.Lflush_default_name:
+    lea rcx, [rip + _gfx_default_file]
+.Lflush_open:
+    # CreateFileA(name, GENERIC_WRITE, 0, NULL, CREATE_ALWAYS, NORMAL, NULL)
+    mov edx, GENERIC_WRITE
+    xor r8d, r8d
+    xor r9d, r9d
+    mov DWORD PTR [rsp + 32], CREATE_ALWAYS
+    mov DWORD PTR [rsp + 40], FILE_ATTRIBUTE_NORMAL
+    mov QWORD PTR [rsp + 48], 0
+    call CreateFileA
+    cmp rax, INVALID_HANDLE_VALUE
+    je .Lflush_done
+    mov rbx, rax            # rbx = file HANDLE
+
+    # sprintf(header_buf, "P6\n%lld %lld\n255\n", width, height)
+    lea rcx, [rip + _gfx_header_buf]
+    lea rdx, [rip + _gfx_ppm_header]
+    mov r8, QWORD PTR [rip + _gfx_width]
+    mov r9, QWORD PTR [rip + _gfx_height]
+    call sprintf
+
+    # WriteFile(handle, header_buf, len, &written, NULL)
+    mov rcx, rbx
+    lea rdx, [rip + _gfx_header_buf]
+    mov r8d, eax
+    lea r9, [rip + _gfx_bytes_written]
+    mov QWORD PTR [rsp + 32], 0
+    call WriteFile
+
+    # Expand palette indices into an RGB image
+    mov r12, QWORD PTR [rip + _gfx_width]
+    imul r12, QWORD PTR [rip + _gfx_height]  # r12 = pixel count
+    call GetProcessHeap
+    mov r14, rax            # r14 = process heap
+    mov rcx, rax
+    xor edx, edx
+    lea r8, [r12 + r12*2]
+    call HeapAlloc
+    test rax, rax
+    jz .Lflush_close
+    mov r13, rax            # r13 = RGB buffer
+
+    mov rsi, QWORD PTR [rip + _gfx_buf]
+    mov rdi, r13
+    mov rcx, r12
+    lea r8, [rip + _gfx_palette]
+.Lflush_pixel:
+    test rcx, rcx
+    jz .Lflush_write
+    movzx eax, BYTE PTR [rsi]
+    lea rax, [rax + rax*2]  # palette offset = index * 3
+    movzx r9d, WORD PTR [r8 + rax]
+    mov WORD PTR [rdi], r9w
+    movzx r9d, BYTE PTR [r8 + rax + 2]
+    mov BYTE PTR [rdi + 2], r9b
+    inc rsi
+    add rdi, 3
+    dec rcx
+    jmp .Lflush_pixel
+
+.Lflush_write:
+    # WriteFile(handle, rgb, count * 3, &written, NULL)
+    mov rcx, rbx
+    mov rdx, r13
+    lea r8, [r12 + r12*2]
+    lea r9, [rip + _gfx_bytes_written]
+    mov QWORD PTR [rsp + 32], 0
+    call WriteFile
+    # HeapFree(heap, 0, rgb)
+    mov rcx, r14
+    xor edx, edx
+    mov r8, r13
+    call HeapFree
+
+.Lflush_close:
+    mov rcx, rbx
+    call CloseHandle
+
+.Lflush_done:
+    add rsp, 64
+    pop rsi
+    pop rdi
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_illegal_call - Report an "Illegal function call" error
+# ------------------------------------------------------------------------------
+# Reached (by jump) when a statement gets an argument it cannot handle, such
+# as an unsupported screen mode, drawing while in text mode, or a PEEK/POKE
+# address out of range.
+#
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_illegal_call
+_rt_illegal_call:
+    lea rcx, [rip + _gfx_error_msg]
+    mov edx, _gfx_error_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _gfx_free - Release the framebuffer (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing. _gfx_buf is NULL afterwards.
+# ------------------------------------------------------------------------------
+_gfx_free:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    cmp QWORD PTR [rip + _gfx_buf], 0
+    je .Lfree_done
+    call GetProcessHeap
+    mov rcx, rax
+    xor edx, edx
+    mov r8, QWORD PTR [rip + _gfx_buf]
+    call HeapFree
+    mov QWORD PTR [rip + _gfx_buf], 0
+.Lfree_done:
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_load_palette - Load the current mode's default palette (internal)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: nothing. Clobbers rax, rcx, rsi and rdi.
+# ------------------------------------------------------------------------------
+_gfx_load_palette:
+    mov rax, QWORD PTR [rip + _gfx_colors]
+    lea rsi, [rip + _gfx_vga_palette]
+    mov ecx, 768
+    cmp rax, 4
+    jne .Lload_palette_not_cga
+    lea rsi, [rip + _gfx_cga_palette]
+    mov ecx, 12
+    jmp .Lload_palette_copy
+.Lload_palette_not_cga:
+    cmp rax, 2
+    jne .Lload_palette_copy
+    lea rsi, [rip + _gfx_mono_palette]
+    mov ecx, 6
+.Lload_palette_copy:
+    lea rdi, [rip + _gfx_palette]
+    rep movsb
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_rgb_shift - Append a color component to a packed color (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   eax = color so far
+#   rcx = component (clamped to 0-255)
+#
+# Returns:
+#   eax = (eax << 8) | component. Clobbers rcx and rdx.
+# ------------------------------------------------------------------------------
+_gfx_rgb_shift:
+    xor edx, edx
+    test rcx, rcx
+    cmovs rcx, rdx
+    mov edx, 255
+    cmp rcx, rdx
+    cmovg rcx, rdx
+    shl eax, 8
+    or eax, ecx
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_color - Resolve a color argument (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdx = color (-1 = default foreground)
+#
+# Returns:
+#   rdx = color masked to the current mode's color count
+#   Clobbers rax.
+# ------------------------------------------------------------------------------
+_gfx_color:
+    cmp rdx, -1
+    jne .Lcolor_mask
+    mov rdx, QWORD PTR [rip + _gfx_fg]
+.Lcolor_mask:
+    mov rax, QWORD PTR [rip + _gfx_colors]
+    dec rax
+    and rdx, rax
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_plot - Store one pixel with clipping (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = x
+#   rsi = y
+#   dl  = color (already resolved)
+#
+# Returns: nothing. Clobbers rax only.
+# ------------------------------------------------------------------------------
+_gfx_plot:
+    cmp rdi, QWORD PTR [rip + _gfx_width]
+    jae .Lplot_done         # unsigned compare also rejects negatives
+    cmp rsi, QWORD PTR [rip + _gfx_height]
+    jae .Lplot_done
+    mov rax, rsi
+    imul rax, QWORD PTR [rip + _gfx_width]
+    add rax, rdi
+    add rax, QWORD PTR [rip + _gfx_buf]
+    mov BYTE PTR [rax], dl
+.Lplot_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_line - Draw a line with Bresenham's algorithm (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rdi = x1, rsi = y1
+#   rcx = x2, r8 = y2
+#   dl  = color (already resolved)
+#
+# Returns: nothing. Clobbers rax, rdi, rsi, r9, r10, r11.
+# ------------------------------------------------------------------------------
+_gfx_line:
+    push rbx
+    push r12
+    # r9 = dx = |x2 - x1|, rbx = x step
+    mov r9, rcx
+    sub r9, rdi
+    mov rbx, 1
+    jge .Lgline_dx_pos
+    neg r9
+    neg rbx
+.Lgline_dx_pos:
+    # r10 = dy = -|y2 - y1|, r12 = y step
+    mov r10, r8
+    sub r10, rsi
+    mov r12, 1
+    jge .Lgline_dy_pos
+    neg r10
+    neg r12
+.Lgline_dy_pos:
+    neg r10
+    lea r11, [r9 + r10]     # r11 = error term (dx + dy)
+.Lgline_loop:
+    call _gfx_plot
+    cmp rdi, rcx
+    jne .Lgline_step
+    cmp rsi, r8
+    je .Lgline_done
+.Lgline_step:
+    lea rax, [r11 + r11]    # e2 = 2 * err
+    cmp rax, r10
+    jl .Lgline_skip_x
+    add r11, r10
+    add rdi, rbx
+.Lgline_skip_x:
+    cmp rax, r9
+    jg .Lgline_loop
+    add r11, r9
+    add rsi, r12
+    jmp .Lgline_loop
+.Lgline_done:
+    pop r12
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_paint - Scanline flood fill (internal)
+# ------------------------------------------------------------------------------
+# Fills the region containing (x, y) up to pixels of the border color.
+# Pixels already in the paint color also stop the fill, which guarantees
+# termination. Seeds are kept on the static _gfx_fill_stack.
+#
+# Arguments:
+#   rdi = x (must be on screen)
+#   rsi = y (must be on screen)
+#   rdx = paint color (already resolved)
+#   rcx = border color (already resolved)
+#
+# Returns: nothing. Clobbers rax, rcx, rdx, rdi, rsi, r8-r11.
+# ------------------------------------------------------------------------------
+_gfx_paint:
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov r14, rdx            # r14 = paint color
+    mov r15, rcx            # r15 = border color
+    mov r10, QWORD PTR [rip + _gfx_width]
+    mov r11, QWORD PTR [rip + _gfx_height]
+    lea r12, [rip + _gfx_fill_stack]
+    mov DWORD PTR [r12], edi
+    mov DWORD PTR [r12 + 4], esi
+    mov ebx, 1              # rbx = number of seeds on the stack
+
+.Lpaint_pop:
+    test rbx, rbx
+    jz .Lpaint_done
+    dec rbx
+    mov edi, DWORD PTR [r12 + rbx*8]        # rdi = x
+    mov esi, DWORD PTR [r12 + rbx*8 + 4]    # rsi = y
+    mov r13, rsi
+    imul r13, r10
+    add r13, QWORD PTR [rip + _gfx_buf]     # r13 = row pointer
+    movzx eax, BYTE PTR [r13 + rdi]
+    cmp al, r14b
+    je .Lpaint_pop
+    cmp al, r15b
+    je .Lpaint_pop
+
+    # Extend the span left (r8) and right (r9) from the seed
+    mov r8, rdi
+.Lpaint_left:
+    test r8, r8
+    jz .Lpaint_right_start
+    movzx eax, BYTE PTR [r13 + r8 - 1]
+    cmp al, r14b
+    je .Lpaint_right_start
+    cmp al, r15b
+    je .Lpaint_right_start
+    dec r8
+    jmp .Lpaint_left
+.Lpaint_right_start:
+    mov r9, rdi
+.Lpaint_right:
+    lea rax, [r9 + 1]
+    cmp rax, r10
+    jae .Lpaint_fill
+    movzx eax, BYTE PTR [r13 + r9 + 1]
+    cmp al, r14b
+    je .Lpaint_fill
+    cmp al, r15b
+    je .Lpaint_fill
+    inc r9
+    jmp .Lpaint_right
+
+.Lpaint_fill:
+    mov rcx, r8
+.Lpaint_fill_loop:
+    mov BYTE PTR [r13 + rcx], r14b
+    inc rcx
+    cmp rcx, r9
+    jbe .Lpaint_fill_loop
+
+    # Seed the rows above and below the span
+    lea rdx, [rsi - 1]
+    call _gfx_paint_seed_row
+    lea rdx, [rsi + 1]
+    call _gfx_paint_seed_row
+    jmp .Lpaint_pop
+
+.Lpaint_done:
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_paint_seed_row - Push one seed per fillable run in a row (internal)
+# ------------------------------------------------------------------------------
+# Helper for _gfx_paint; uses its register state.
+#
+# Arguments:
+#   rdx = row y (ignored if off screen)
+#   r8  = first x of span, r9 = last x of span
+#
+# Returns: nothing. Clobbers rax, rcx, rdi, r13.
+# ------------------------------------------------------------------------------
+_gfx_paint_seed_row:
+    cmp rdx, r11
+    jae .Lseed_done         # unsigned compare also rejects -1
+    mov rdi, rdx
+    imul rdi, r10
+    add rdi, QWORD PTR [rip + _gfx_buf]     # rdi = row pointer
+    mov rcx, r8
+    xor r13d, r13d          # r13 = inside a fillable run
+.Lseed_loop:
+    cmp rcx, r9
+    ja .Lseed_done
+    movzx eax, BYTE PTR [rdi + rcx]
+    cmp al, r14b
+    je .Lseed_blocked
+    cmp al, r15b
+    je .Lseed_blocked
+    test r13, r13
+    jnz .Lseed_next
+    mov r13d, 1
+    cmp rbx, GFX_FILL_STACK_ENTRIES
+    jae .Lseed_next
+    mov DWORD PTR [r12 + rbx*8], ecx
+    mov DWORD PTR [r12 + rbx*8 + 4], edx
+    inc rbx
+    jmp .Lseed_next
+.Lseed_blocked:
+    xor r13d, r13d
+.Lseed_next:
+    inc rcx
+    jmp .Lseed_loop
+.Lseed_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_draw - DRAW command language interpreter (internal)
+# ------------------------------------------------------------------------------
+# Commands (n defaults to 1; spaces and semicolons are ignored):
+#   U n, D n, L n, R n     = move up, down, left, right
+#   E n, F n, G n, H n     = move diagonally (up-right, down-right,
+#                            down-left, up-left)
+#   M x,y                  = move to absolute point, or relative if x is
+#                            signed (M+10,-5)
+#   B prefix               = move without drawing
+#   N prefix               = draw without moving the graphics cursor
+#   C n                    = set drawing color
+#   S n                    = set scale (distance = n * S / 4, default S4)
+#   A n                    = rotate by n * 90 degrees
+#   TA n                   = rotate by n degrees (counterclockwise)
+#   P paint,border         = flood fill at the graphics cursor
+#
+# Any other command is an "Illegal function call".
+#
+# Arguments:
+#   rdi = command string pointer
+#   rsi = command string length
+#
+# Returns: nothing. Clobbers rax, rcx, rdx, rdi, rsi, r8-r11, xmm0-xmm5.
+# ------------------------------------------------------------------------------
+_gfx_draw:
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov r12, rdi            # r12 = current position in string
+    lea r13, [rdi + rsi]    # r13 = end of string
+    xor r14d, r14d          # r14 = prefix flags: 1 = B, 2 = N
+
+.Ldraw_next:
+    cmp r12, r13
+    jae .Ldraw_done
+    movzx eax, BYTE PTR [r12]
+    inc r12
+    cmp al, 'a'
+    jb .Ldraw_dispatch
+    cmp al, 'z'
+    ja .Ldraw_dispatch
+    sub al, 32              # Commands are case-insensitive
+.Ldraw_dispatch:
+    cmp al, ' '
+    je .Ldraw_next
+    cmp al, ';'
+    je .Ldraw_next
+    cmp al, 'B'
+    je .Ldraw_prefix_b
+    cmp al, 'N'
+    je .Ldraw_prefix_n
+    cmp al, 'U'
+    je .Ldraw_u
+    cmp al, 'D'
+    je .Ldraw_d
+    cmp al, 'L'
+    je .Ldraw_l
+    cmp al, 'R'
+    je .Ldraw_r
+    cmp al, 'E'
+    je .Ldraw_e
+    cmp al, 'F'
+    je .Ldraw_f
+    cmp al, 'G'
+    je .Ldraw_g
+    cmp al, 'H'
+    je .Ldraw_h
+    cmp al, 'M'
+    je .Ldraw_m
+    cmp al, 'C'
+    je .Ldraw_c
+    cmp al, 'S'
+    je .Ldraw_s
+    cmp al, 'A'
+    je .Ldraw_a
+    cmp al, 'T'
+    je .Ldraw_t
+    cmp al, 'P'
+    je .Ldraw_p
+    jmp _rt_illegal_call
+
+.Ldraw_prefix_b:
+    or r14, 1
+    jmp .Ldraw_next
+.Ldraw_prefix_n:
+    or r14, 2
+    jmp .Ldraw_next
+
+    # Direction commands: r8/r9 = unit vector
+.Ldraw_u:
+    mov r8, 0
+    mov r9, -1
+    jmp .Ldraw_dir
+.Ldraw_d:
+    mov r8, 0
+    mov r9, 1
+    jmp .Ldraw_dir
+.Ldraw_l:
+    mov r8, -1
+    mov r9, 0
+    jmp .Ldraw_dir
+.Ldraw_r:
+    mov r8, 1
+    mov r9, 0
+    jmp .Ldraw_dir
+.Ldraw_e:
+    mov r8, 1
+    mov r9, -1
+    jmp .Ldraw_dir
+.Ldraw_f:
+    mov r8, 1
+    mov r9, 1
+    jmp .Ldraw_dir
+.Ldraw_g:
+    mov r8, -1
+    mov r9, 1
+    jmp .Ldraw_dir
+.Ldraw_h:
+    mov r8, -1
+    mov r9, -1
+.Ldraw_dir:
+    call _gfx_draw_number   # rax = count
+    imul rax, QWORD PTR [rip + _gfx_draw_scale]
+    cqo
+    mov rcx, 4
+    idiv rcx                # rax = distance in pixels
+    imul r8, rax
+    imul r9, rax
+    jmp .Ldraw_relative
+
+.Ldraw_m:
+    call _gfx_draw_number
+    mov rbx, rax            # rbx = x
+    mov r15, rcx            # r15 = x number flags
+    call _gfx_draw_comma
+    call _gfx_draw_number   # rax = y
+    test r15, 2
+    jnz .Ldraw_m_relative
+    mov r15, rax            # Absolute target: (rbx, r15)
+    jmp .Ldraw_to
+.Ldraw_m_relative:
+    mov r8, rbx
+    mov r9, rax
+    mov rax, r8
+    imul rax, QWORD PTR [rip + _gfx_draw_scale]
+    cqo
+    mov rcx, 4
+    idiv rcx
+    mov r8, rax
+    mov rax, r9
+    imul rax, QWORD PTR [rip + _gfx_draw_scale]
+    cqo
+    mov rcx, 4
+    idiv rcx
+    mov r9, rax
+
+.Ldraw_relative:
+    # Target = cursor + rotated (r8, r9)
+    call _gfx_draw_rotate
+    mov rbx, QWORD PTR [rip + _gfx_last_x]
+    add rbx, r8
+    mov r15, QWORD PTR [rip + _gfx_last_y]
+    add r15, r9
+
+.Ldraw_to:
+    # Draw (unless B) and move (unless N) to (rbx, r15)
+    test r14, 1
+    jnz .Ldraw_no_line
+    mov rdx, QWORD PTR [rip + _gfx_draw_color]
+    call _gfx_color
+    mov rdi, QWORD PTR [rip + _gfx_last_x]
+    mov rsi, QWORD PTR [rip + _gfx_last_y]
+    mov rcx, rbx
+    mov r8, r15
+    call _gfx_line
+.Ldraw_no_line:
+    test r14, 2
+    jnz .Ldraw_keep_cursor
+    mov QWORD PTR [rip + _gfx_last_x], rbx
+    mov QWORD PTR [rip + _gfx_last_y], r15
+.Ldraw_keep_cursor:
+    xor r14d, r14d
+    jmp .Ldraw_next
+
+.Ldraw_c:
+    call _gfx_draw_number
+    mov QWORD PTR [rip + _gfx_draw_color], rax
+    jmp .Ldraw_next
+
+.Ldraw_s:
+    call _gfx_draw_number
+    mov QWORD PTR [rip + _gfx_draw_scale], rax
+    jmp .Ldraw_next
+
+.Ldraw_a:
+    call _gfx_draw_number
+    imul rax, rax, 90       # A n = TA n*90
+    jmp .Ldraw_set_angle
+
+.Ldraw_t:
+    cmp r12, r13
+    jae _rt_illegal_call
+    movzx eax, BYTE PTR [r12]
+    or al, 32               # lowercase
+    cmp al, 'a'
+    jne _rt_illegal_call
+    inc r12
+    call _gfx_draw_number   # rax = degrees
+.Ldraw_set_angle:
+    cvtsi2sd xmm0, rax
+    mulsd xmm0, QWORD PTR [rip + _gfx_deg_to_rad]
+    movsd QWORD PTR [rip + _gfx_draw_cos], xmm0
+    fld QWORD PTR [rip + _gfx_draw_cos]
+    fsincos                 # st0 = cos, st1 = sin
+    fstp QWORD PTR [rip + _gfx_draw_cos]
+    fstp QWORD PTR [rip + _gfx_draw_sin]
+    jmp .Ldraw_next
+
+.Ldraw_p:
+    call _gfx_draw_number
+    mov rbx, rax            # rbx = paint color
+    call _gfx_draw_comma
+    call _gfx_draw_number   # rax = border color
+    mov rcx, rax
+    mov rax, QWORD PTR [rip + _gfx_colors]
+    dec rax
+    and rcx, rax
+    mov rdx, rbx
+    call _gfx_color
+    mov rdi, QWORD PTR [rip + _gfx_last_x]
+    mov rsi, QWORD PTR [rip + _gfx_last_y]
+    cmp rdi, QWORD PTR [rip + _gfx_width]
+    jae .Ldraw_next
+    cmp rsi, QWORD PTR [rip + _gfx_height]
+    jae .Ldraw_next
+    call _gfx_paint
+    jmp .Ldraw_next
+
+.Ldraw_done:
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_draw_number - Parse a DRAW numeric argument (internal)
+# ------------------------------------------------------------------------------
+# Skips spaces, then reads an optionally signed decimal number at r12
+# (bounded by r13), advancing r12 past it.
+#
+# Returns:
+#   rax = value (1 if no digits are present)
+#   rcx = flags: bit 0 = digits present, bit 1 = explicit sign present
+#   Clobbers rdx, r10.
+# ------------------------------------------------------------------------------
+_gfx_draw_number:
+    xor eax, eax
+    xor ecx, ecx
+    xor edx, edx            # rdx = negative
+.Lnum_space:
+    cmp r12, r13
+    jae .Lnum_end
+    cmp BYTE PTR [r12], ' '
+    jne .Lnum_sign
+    inc r12
+    jmp .Lnum_space
+.Lnum_sign:
+    cmp BYTE PTR [r12], '+'
+    je .Lnum_signed
+    cmp BYTE PTR [r12], '-'
+    jne .Lnum_digits
+    mov edx, 1
+.Lnum_signed:
+    or ecx, 2
+    inc r12
+.Lnum_digits:
+    cmp r12, r13
+    jae .Lnum_end
+    movzx r10d, BYTE PTR [r12]
+    sub r10d, '0'
+    cmp r10d, 9
+    ja .Lnum_end
+    imul rax, rax, 10
+    add rax, r10
+    or ecx, 1
+    inc r12
+    jmp .Lnum_digits
+.Lnum_end:
+    test ecx, 1
+    jnz .Lnum_apply_sign
+    mov eax, 1
+.Lnum_apply_sign:
+    test edx, edx
+    jz .Lnum_done
+    neg rax
+.Lnum_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_draw_comma - Require a comma in a DRAW command (internal)
+# ------------------------------------------------------------------------------
+# Skips spaces at r12 and consumes a comma; anything else is an
+# "Illegal function call".
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_gfx_draw_comma:
+    cmp r12, r13
+    jae _rt_illegal_call
+    cmp BYTE PTR [r12], ' '
+    jne .Lcomma_check
+    inc r12
+    jmp _gfx_draw_comma
+.Lcomma_check:
+    cmp BYTE PTR [r12], ','
+    jne _rt_illegal_call
+    inc r12
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_draw_rotate - Rotate a DRAW vector by the current angle (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   r8 = dx, r9 = dy
+#
+# Returns:
+#   r8, r9 = rotated vector, rounded to integers
+#   Clobbers xmm0-xmm5.
+# ------------------------------------------------------------------------------
+_gfx_draw_rotate:
+    cvtsi2sd xmm0, r8
+    cvtsi2sd xmm1, r9
+    movsd xmm2, QWORD PTR [rip + _gfx_draw_cos]
+    movsd xmm3, QWORD PTR [rip + _gfx_draw_sin]
+    movapd xmm4, xmm0
+    mulsd xmm4, xmm2
+    movapd xmm5, xmm1
+    mulsd xmm5, xmm3
+    addsd xmm4, xmm5        # dx' = dx*cos + dy*sin
+    mulsd xmm1, xmm2
+    mulsd xmm0, xmm3
+    subsd xmm1, xmm0        # dy' = dy*cos - dx*sin
+    cvtsd2si r8, xmm4
+    cvtsd2si r9, xmm1
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_get - Copy a screen rectangle into a buffer (internal)
+# ------------------------------------------------------------------------------
+# Buffer layout: width (16-bit), height (16-bit), then width * height
+# palette indices, row by row. The rectangle must be on screen and the
+# buffer large enough, otherwise "Illegal function call".
+#
+# Arguments:
+#   rdi = x1, rsi = y1, rdx = x2, rcx = y2 (any corner order)
+#   r8  = buffer pointer
+#   r9  = buffer size in bytes
+#
+# Returns: nothing. Clobbers rax, rcx, rdx, rdi, rsi, r8-r11.
+# ------------------------------------------------------------------------------
+_gfx_get:
+    cmp rdi, rdx
+    jle .Lget_x_ok
+    xchg rdi, rdx
+.Lget_x_ok:
+    cmp rsi, rcx
+    jle .Lget_y_ok
+    xchg rsi, rcx
+.Lget_y_ok:
+    test rdi, rdi
+    js _rt_illegal_call
+    test rsi, rsi
+    js _rt_illegal_call
+    cmp rdx, QWORD PTR [rip + _gfx_width]
+    jge _rt_illegal_call
+    cmp rcx, QWORD PTR [rip + _gfx_height]
+    jge _rt_illegal_call
+
+    mov r10, rdx
+    sub r10, rdi
+    inc r10                 # r10 = width
+    mov r11, rcx
+    sub r11, rsi
+    inc r11                 # r11 = height
+    mov rax, r10
+    imul rax, r11
+    add rax, 4              # header + pixels
+    cmp rax, r9
+    ja _rt_illegal_call
+
+    mov WORD PTR [r8], r10w
+    mov WORD PTR [r8 + 2], r11w
+    add r8, 4               # r8 = destination
+    mov rax, rsi
+    imul rax, QWORD PTR [rip + _gfx_width]
+    add rax, rdi
+    add rax, QWORD PTR [rip + _gfx_buf]     # rax = source row
+    mov rdx, r11            # rdx = rows left
+.Lget_row:
+    mov rsi, rax
+    mov rdi, r8
+    mov rcx, r10
+    rep movsb
+    mov r8, rdi
+    add rax, QWORD PTR [rip + _gfx_width]
+    dec rdx
+    jnz .Lget_row
+    ret
+
+# ------------------------------------------------------------------------------
+# _gfx_put - Draw a buffer captured by _gfx_get (internal)
+# ------------------------------------------------------------------------------
+# The image must fit on screen at (x, y), otherwise "Illegal function call".
+#
+# Arguments:
+#   rdi = x, rsi = y (top-left corner)
+#   rdx = buffer pointer
+#   rcx = buffer size in bytes
+#   r8  = mode: 0 = PSET, 1 = PRESET, 2 = AND, 3 = OR, 4 = XOR
+#
+# Returns: nothing. Clobbers rax, rcx, rdx, rdi, rsi, r9-r11.
+# ------------------------------------------------------------------------------
+_gfx_put:
+    cmp rcx, 4
+    jb _rt_illegal_call
+    movzx r10d, WORD PTR [rdx]      # r10 = width
+    movzx r11d, WORD PTR [rdx + 2]  # r11 = height
+    mov rax, r10
+    imul rax, r11
+    add rax, 4
+    cmp rax, rcx
+    ja _rt_illegal_call
+    test r10, r10
+    jz .Lput_done
+    test r11, r11
+    jz .Lput_done
+    test rdi, rdi
+    js _rt_illegal_call
+    test rsi, rsi
+    js _rt_illegal_call
+    lea rax, [rdi + r10]
+    cmp rax, QWORD PTR [rip + _gfx_width]
+    ja _rt_illegal_call
+    lea rax, [rsi + r11]
+    cmp rax, QWORD PTR [rip + _gfx_height]
+    ja _rt_illegal_call
+
+    mov rax, rsi
+    imul rax, QWORD PTR [rip + _gfx_width]
+    add rax, rdi
+    add rax, QWORD PTR [rip + _gfx_buf]
+    mov rdi, rax            # rdi = destination row
+    lea rsi, [rdx + 4]      # rsi = source pixels
+    mov r9, QWORD PTR [rip + _gfx_colors]
+    dec r9                  # r9 = color mask
+.Lput_row:
+    xor ecx, ecx            # rcx = column
+.Lput_col:
+    movzx eax, BYTE PTR [rsi]
+    movzx edx, BYTE PTR [rdi + rcx]
+    cmp r8, 1
+    jb .Lput_pset
+    je .Lput_preset
+    cmp r8, 3
+    jb .Lput_and
+    je .Lput_or
+    xor edx, eax            # XOR
+    jmp .Lput_store
+.Lput_pset:
+    mov edx, eax
+    jmp .Lput_store
+.Lput_preset:
+    mov edx, eax
+    not edx
+    jmp .Lput_store
+.Lput_and:
+    and edx, eax
+    jmp .Lput_store
+.Lput_or:
+    or edx, eax
+.Lput_store:
+    and edx, r9d
+    mov BYTE PTR [rdi + rcx], dl
+    inc rsi
+    inc rcx
+    cmp rcx, r10
+    jb .Lput_col
+    add rdi, QWORD PTR [rip + _gfx_width]
+    dec r11
+    jnz .Lput_row
+.Lput_done:
+    ret
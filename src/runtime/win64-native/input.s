@@ -3,7 +3,9 @@
 # ==============================================================================
 #
 # Keyboard input functions using Win32 API (ReadFile) instead of libc scanf.
-# Uses UCRT strtod for number parsing.
+# Uses UCRT strtod for number parsing. INPUT reads a whole line and splits it
+# into comma-separated fields with _in_next_field, which INPUT # (file.s)
+# shares; _input_pos points at the next field of the line in _input_buf.
 #
 # Win64 ABI:
 #   - Integer args: rcx, rdx, r8, r9 (then stack)
@@ -27,6 +29,9 @@
 _stdin_handle: .quad 0
 _input_buf: .skip 1024           # Buffer for string input
 _bytes_read: .quad 0             # For ReadFile output parameter
+_input_pos: .quad 0              # Next field of the INPUT line
+_redo_msg: .ascii "?Redo from start"
+.equ _redo_msg_len, 16
 
 .text
 
@@ -81,6 +86,7 @@ _rt_input_string:
     lea r9, [rip + _bytes_read]     # &bytesRead → r9 (4th arg)
     mov QWORD PTR [rsp + 32], 0     # NULL → 5th arg (stack)
     call ReadFile
+    mov QWORD PTR [rip + _out_col], 0   # Enter moved the cursor to column 0
 
     # Get number of bytes read
     lea rax, [rip + _bytes_read]
@@ -118,48 +124,242 @@ _rt_input_string:
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_input_number - Read a numeric value from stdin
+# _rt_input_line - Prompt for and read the line an INPUT statement takes apart
 # ------------------------------------------------------------------------------
-# Reads a double-precision floating point number. Uses strtod from UCRT.
+# Prints the prompt and reads a line, which must hold one comma-separated field
+# per INPUT target. A numeric target needs a number (or an empty field, which
+# is 0). Otherwise "?Redo from start" is printed and the line asked for again.
+# The fields are then handed out by _rt_input_next_number and
+# _rt_input_next_string, one per target. At end of input the line is taken as
+# it is, so a program fed too little input doesn't loop forever.
 #
-# Arguments: none
+# Arguments:
+#   rcx = prompt pointer (already ending in "? " unless the program used ",")
+#   rdx = prompt length
+#   r8  = kinds pointer: 'N' (number) or 'S' (string) for each target
+#   r9  = number of targets
 #
-# Returns:
-#   xmm0 = the number read (double)
+# Returns: nothing (the line is kept in _input_buf)
+#
+# Register usage:
+#   rbx = field index, r12/r13 = prompt, r14/r15 = kinds
 # ------------------------------------------------------------------------------
-.globl _rt_input_number
-_rt_input_number:
+.globl _rt_input_line
+_rt_input_line:
     push rbp
     mov rbp, rsp
-    sub rsp, 48             # Shadow space + stack args
-
-    # Clear buffer
-    lea rax, [rip + _input_buf]
-    mov BYTE PTR [rax], 0
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    sub rsp, 72             # Shadow space + saved r8/r9, field end, strtod end
 
-    # Get stdin handle
-    lea rax, [rip + _stdin_handle]
-    mov rcx, [rax]          # handle
+    mov r12, rcx
+    mov r13, rdx
+    mov r14, r8
+    mov r15, r9
 
-    # ReadFile(handle, buffer, maxlen, &bytesRead, NULL)
-    lea rdx, [rip + _input_buf]
-    mov r8, MAX_INPUT_LEN
-    lea r9, [rip + _bytes_read]
-    mov QWORD PTR [rsp + 32], 0
-    call ReadFile
+.Linput_line_prompt:
+    mov rcx, r12
+    mov rdx, r13
+    call _rt_print_string
 
-    # Null-terminate the input
+    call _rt_input_string
     lea rax, [rip + _bytes_read]
-    mov rcx, [rax]          # bytes read
+    cmp QWORD PTR [rax], 0  # nothing read: input has run out
+    je .Linput_line_accept
+    test r15, r15
+    jz .Linput_line_accept
+
+    # Check each field against its target
+    lea r8, [rip + _input_buf]
+    xor ebx, ebx
+.Linput_line_field:
+    call _in_next_field
+    cmp BYTE PTR [r14 + rbx], 'N'
+    jne .Linput_line_next
+    test rdx, rdx           # an empty field reads as 0
+    jz .Linput_line_next
+    # The whole field must be a number: strtod(field, &end) stops at its end
+    mov [rbp - 48], r8
+    mov [rbp - 56], r9
+    add rdx, rax
+    mov [rbp - 64], rdx     # where the field ends
+    mov rcx, rax
+    lea rdx, [rbp - 72]
+    call strtod
+    mov r8, [rbp - 48]
+    mov r9, [rbp - 56]
+    mov rax, [rbp - 72]
+    cmp rax, [rbp - 64]
+    jne .Linput_line_redo
+.Linput_line_next:
+    inc rbx
+    cmp rbx, r15
+    je .Linput_line_last
+    cmp r9d, ','            # too few fields, or junk after a quote
+    jne .Linput_line_redo
+    jmp .Linput_line_field
+.Linput_line_last:
+    test r9d, r9d           # too many fields
+    jnz .Linput_line_redo
+
+.Linput_line_accept:
     lea rax, [rip + _input_buf]
-    mov BYTE PTR [rax + rcx], 0
+    lea rcx, [rip + _input_pos]
+    mov [rcx], rax
+    add rsp, 72
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
 
-    # Parse number using strtod(buffer, NULL)
-    lea rcx, [rip + _input_buf]
-    xor rdx, rdx            # NULL endptr
+.Linput_line_redo:
+    lea rcx, [rip + _redo_msg]
+    mov rdx, _redo_msg_len
+    call _rt_print_string
+    call _rt_print_newline
+    jmp .Linput_line_prompt
+
+# ------------------------------------------------------------------------------
+# _rt_input_next_number - Take the next field of the INPUT line as a number
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   xmm0 = the number (double); 0 for an empty field
+# ------------------------------------------------------------------------------
+.globl _rt_input_next_number
+_rt_input_next_number:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    lea rcx, [rip + _input_pos]
+    mov r8, [rcx]
+    call _in_next_field
+    lea rcx, [rip + _input_pos]
+    mov [rcx], r8
+    # strtod(field, NULL) stops at the comma or quote that ends the field
+    mov rcx, rax
+    xor edx, edx
     call strtod
+    leave
+    ret
 
-    # Result is in xmm0
+# ------------------------------------------------------------------------------
+# _rt_input_next_string - Take the next field of the INPUT line as a string
+# ------------------------------------------------------------------------------
+# Arguments: none
+#
+# Returns:
+#   rax = pointer to a copy of the field (allocated by _rt_strcat)
+#   rdx = length
+# ------------------------------------------------------------------------------
+.globl _rt_input_next_string
+_rt_input_next_string:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    lea rcx, [rip + _input_pos]
+    mov r8, [rcx]
+    call _in_next_field
+    lea rcx, [rip + _input_pos]
+    mov [rcx], r8
+    # Copy the field out of the line buffer: field + ""
+    mov rcx, rax
+    xor r9d, r9d
+    call _rt_strcat
     leave
     ret
 
+# ------------------------------------------------------------------------------
+# _in_next_field - Split the next field off an input line (INPUT and INPUT #)
+# ------------------------------------------------------------------------------
+# Fields are separated by commas. Blanks around a field are dropped, and a
+# field in double quotes is taken as it is, commas and all.
+#
+# Arguments:
+#   r8 = next character of a NUL-terminated line
+#
+# Returns:
+#   rax = field start
+#   rdx = field length
+#   r8  = character after the field's comma (or the NUL ending the line)
+#   r9  = what ended the field: ',' or 0 at the end of the line; anything else
+#         is junk after a quoted field
+#
+# Uses only rax, rdx, r8 and r9, so callers keep their other registers.
+# ------------------------------------------------------------------------------
+_in_next_field:
+    movzx r9d, BYTE PTR [r8]        # skip leading blanks
+    cmp r9d, ' '
+    je .Lfield_blank
+    cmp r9d, 9
+    jne .Lfield_start
+.Lfield_blank:
+    inc r8
+    jmp _in_next_field
+.Lfield_start:
+    cmp r9d, '"'
+    je .Lfield_quoted
+    mov rax, r8                     # unquoted: up to a comma or the line end
+.Lfield_scan:
+    movzx r9d, BYTE PTR [r8]
+    test r9d, r9d
+    jz .Lfield_trim
+    cmp r9d, ','
+    je .Lfield_trim
+    inc r8
+    jmp .Lfield_scan
+.Lfield_trim:
+    mov rdx, r8                     # drop trailing blanks
+.Lfield_trim_loop:
+    cmp rdx, rax
+    je .Lfield_length
+    cmp BYTE PTR [rdx - 1], ' '
+    je .Lfield_trim_next
+    cmp BYTE PTR [rdx - 1], 9
+    jne .Lfield_length
+.Lfield_trim_next:
+    dec rdx
+    jmp .Lfield_trim_loop
+.Lfield_length:
+    sub rdx, rax
+    jmp .Lfield_end
+.Lfield_quoted:
+    inc r8
+    mov rax, r8                     # quoted: up to the closing quote
+.Lfield_quote_scan:
+    movzx r9d, BYTE PTR [r8]
+    test r9d, r9d
+    jz .Lfield_quote_end            # unclosed: runs to the end of the line
+    cmp r9d, '"'
+    je .Lfield_quote_end
+    inc r8
+    jmp .Lfield_quote_scan
+.Lfield_quote_end:
+    mov rdx, r8
+    sub rdx, rax
+    test r9d, r9d
+    jz .Lfield_end
+    inc r8                          # skip the quote and any blanks after it
+.Lfield_quote_blank:
+    movzx r9d, BYTE PTR [r8]
+    cmp r9d, ' '
+    je .Lfield_quote_skip
+    cmp r9d, 9
+    jne .Lfield_end
+.Lfield_quote_skip:
+    inc r8
+    jmp .Lfield_quote_blank
+.Lfield_end:
+    movzx r9d, BYTE PTR [r8]
+    cmp r9d, ','
+    jne .Lfield_done
+    inc r8
+.Lfield_done:
+    ret
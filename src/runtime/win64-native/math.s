@@ -104,6 +104,95 @@ _rt_timer:
     leave
     ret
 
+# ------------------------------------------------------------------------------
+# _rt_round - Round to a number of decimal places (ROUND function)
+# ------------------------------------------------------------------------------
+# Rounds half away from zero: ROUND(2.5) is 3 and ROUND(-2.5) is -3.
+# Negative digits round to tens, hundreds and so on.
+#
+# Arguments:
+#   xmm0 = value
+#   rcx = digits after the decimal point
+#
+# Returns:
+#   xmm0 = rounded value
+#
+# With digits >= 0 the value is scaled up by 10^digits, rounded and scaled
+# back; a scaled value of 2^52 or more is whole already (as are infinities
+# and NaNs), and is returned unchanged. With digits < 0 it's scaled down.
+# ------------------------------------------------------------------------------
+.globl _rt_round
+_rt_round:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 64             # Shadow space + locals
+    movsd QWORD PTR [rbp - 8], xmm0
+    mov QWORD PTR [rbp - 16], rcx
+    mov rax, rcx
+    neg rax
+    cmovs rax, rcx          # |digits|
+    cvtsi2sd xmm1, rax
+    mov rax, 0x4024000000000000  # 10.0
+    movq xmm0, rax
+    call pow                # scale = 10^|digits|
+    movsd QWORD PTR [rbp - 24], xmm0
+    movsd xmm1, xmm0
+    movsd xmm0, QWORD PTR [rbp - 8]
+    cmp QWORD PTR [rbp - 16], 0
+    jl .Lround_down
+    mulsd xmm0, xmm1        # value * scale
+    mov rax, 0x7FFFFFFFFFFFFFFF
+    movq xmm2, rax
+    andpd xmm2, xmm0        # |value * scale|
+    mov rax, 0x4330000000000000  # 2^52
+    movq xmm3, rax
+    ucomisd xmm2, xmm3
+    jae .Lround_same
+    jp .Lround_same
+    call _rt_round_half
+    divsd xmm0, QWORD PTR [rbp - 24]
+    leave
+    ret
+.Lround_down:
+    divsd xmm0, xmm1        # value / scale
+    call _rt_round_half
+    xorpd xmm1, xmm1
+    ucomisd xmm0, xmm1
+    je .Lround_done         # 0 stays 0, even when the scale is infinite
+    mulsd xmm0, QWORD PTR [rbp - 24]
+.Lround_done:
+    leave
+    ret
+.Lround_same:
+    movsd xmm0, QWORD PTR [rbp - 8]
+    leave
+    ret
+
+# _rt_round_half - xmm0 rounded to a whole number, half away from zero
+# (clobbers rax, xmm1-xmm3)
+.globl _rt_round_half
+_rt_round_half:
+    roundsd xmm1, xmm0, 3   # truncated
+    movsd xmm2, xmm0
+    subsd xmm2, xmm1        # fraction, exact
+    mov rax, 0x7FFFFFFFFFFFFFFF
+    movq xmm3, rax
+    andpd xmm2, xmm3        # |fraction|
+    mov rax, 0x3FE0000000000000  # 0.5
+    movq xmm3, rax
+    ucomisd xmm2, xmm3
+    jb .Lround_half_done
+    mov rax, 0x8000000000000000
+    movq xmm2, rax
+    andpd xmm2, xmm0        # the value's sign
+    mov rax, 0x3FF0000000000000  # 1.0
+    movq xmm3, rax
+    orpd xmm3, xmm2         # +1 or -1
+    addsd xmm1, xmm3
+.Lround_half_done:
+    movsd xmm0, xmm1
+    ret
+
 # ------------------------------------------------------------------------------
 # _rt_cls - Clear screen (CLS statement)
 # ------------------------------------------------------------------------------
@@ -117,6 +206,7 @@ _rt_cls:
     push rbp
     mov rbp, rsp
     sub rsp, 48             # Shadow space + stack arg
+    call _scr_clear         # blank the text screen (see print.s)
 
     # Get stdout handle
     mov ecx, STD_OUTPUT_HANDLE
@@ -129,7 +219,17 @@ _rt_cls:
     lea r9, [rip + _cls_bytes_written]
     mov QWORD PTR [rsp + 32], 0
     call WriteFile
-
+    mov QWORD PTR [rip + _out_col], 0
+
+    # In a graphics mode, also clear the framebuffer (see graphics.s)
+    mov rcx, QWORD PTR [rip + _gfx_buf]
+    test rcx, rcx
+    jz .Lcls_done
+    xor edx, edx            # memset(buf, 0, width * height)
+    mov r8, QWORD PTR [rip + _gfx_width]
+    imul r8, QWORD PTR [rip + _gfx_height]
+    call memset
+.Lcls_done:
     leave
     ret
 
@@ -0,0 +1,422 @@
+# ==============================================================================
+# BASIC Runtime: Emulated Memory
+# ==============================================================================
+#
+# PEEK, POKE, DEF SEG, VARSEG, BSAVE and BLOAD against an emulated real-mode
+# address space.
+#
+# Old BASIC programs read and write memory through a 16-bit segment and a
+# 16-bit offset. The runtime emulates that with a zero-filled block covering
+# the full segment:offset range (segment * 16 + offset, up to 0x10FFEF), so
+# tables stored with POKE can be read back with PEEK. Nothing is mapped into
+# the block: POKEs to video memory or BIOS areas are stored but have no
+# other effect.
+#
+# The block is allocated on first use.
+#
+# VARSEG maps the 64KB-aligned host region holding a variable onto one of a
+# small set of reserved segments (D000, D100, ... EF00), and VARPTR gives the
+# variable's offset within that region. PEEK and POKE through those segments
+# read and write the program's real variables.
+#
+# Windows x64 calling convention:
+#   - Arguments in rcx, rdx
+#   - 32-byte shadow space required before calls
+#
+# BSAVE files use the GW-BASIC layout: a 0xFD marker byte, then the segment,
+# offset and length as 16-bit little-endian values, then the data.
+#
+# Global state:
+#   _mem_base = pointer to the emulated address space (NULL until first use)
+#   _mem_seg  = current segment set by DEF SEG (default 0)
+#   _mem_maps = host region base for each reserved segment (0 = unused)
+# ==============================================================================
+
+.equ MEM_SIZE, 0x110000     # 1MB plus the 64KB reachable above segment FFFF
+.equ MEM_MAP_SEG, 0xD000    # first segment handed out by VARSEG
+.equ MEM_MAP_SLOTS, 32      # reserved segments, 0x100 apart
+.equ BSAVE_MARKER, 0xFD
+.equ BSAVE_HEADER_SIZE, 7
+
+.data
+_mem_base: .quad 0
+_mem_seg: .quad 0
+_mem_maps: .skip MEM_MAP_SLOTS * 8
+_mem_header: .skip BSAVE_HEADER_SIZE
+_mem_bytes_done: .quad 0
+_mem_not_found_msg: .ascii "File not found"
+_mem_not_found_msg_len = 14
+_mem_bad_mode_msg: .ascii "Bad file mode"
+_mem_bad_mode_msg_len = 13
+_mem_access_msg: .ascii "Path/File access error"
+_mem_access_msg_len = 22
+
+.text
+
+# ------------------------------------------------------------------------------
+# _rt_def_seg - Select the segment used by PEEK and POKE (DEF SEG)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = segment (-32768 to 65535; negative values wrap like 16-bit integers)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_def_seg
+_rt_def_seg:
+    cmp rcx, -32768
+    jl _rt_illegal_call
+    cmp rcx, 65535
+    jg _rt_illegal_call
+    movzx ecx, cx
+    mov QWORD PTR [rip + _mem_seg], rcx
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_peek - Read a byte of emulated memory (PEEK function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = offset within the current segment
+#
+# Returns:
+#   rax = byte value (0-255)
+# ------------------------------------------------------------------------------
+.globl _rt_peek
+_rt_peek:
+    push rbp
+    mov rbp, rsp
+    call _mem_addr
+    movzx eax, BYTE PTR [rax]
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_poke - Write a byte of emulated memory (POKE statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = offset within the current segment
+#   rdx = value (0-255)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_poke
+_rt_poke:
+    push rbp
+    mov rbp, rsp
+    cmp rdx, 255
+    ja _rt_illegal_call     # unsigned compare also rejects negatives
+    push rdx
+    sub rsp, 8
+    call _mem_addr
+    add rsp, 8
+    pop rdx
+    mov BYTE PTR [rax], dl
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_varseg - Map the memory holding a variable (VARSEG function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = host address of the variable
+#
+# Returns:
+#   rax = reserved segment mapped onto the variable's 64KB host region.
+#         VARPTR gives the offset (the low 16 bits of the address).
+# ------------------------------------------------------------------------------
+.globl _rt_varseg
+_rt_varseg:
+    and rcx, -65536         # region base
+    lea rdx, [rip + _mem_maps]
+    xor eax, eax
+.Lvarseg_scan:
+    mov r8, QWORD PTR [rdx + rax*8]
+    cmp r8, rcx
+    je .Lvarseg_found
+    test r8, r8
+    jz .Lvarseg_claim
+    inc rax
+    cmp rax, MEM_MAP_SLOTS
+    jb .Lvarseg_scan
+    jmp _rt_illegal_call    # all reserved segments in use
+.Lvarseg_claim:
+    mov QWORD PTR [rdx + rax*8], rcx
+.Lvarseg_found:
+    shl rax, 8
+    add rax, MEM_MAP_SEG
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_bsave - Save a block of memory to a file (BSAVE statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = filename pointer (BASIC string, not null-terminated)
+#   rdx = filename length
+#   r8  = offset within the current segment
+#   r9  = length in bytes (the block must not run past the segment's end)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_bsave
+_rt_bsave:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push rdi
+    push rsi
+    sub rsp, 64             # Shadow space + stack args (must be 0 mod 16)
+
+    mov rbx, r9             # rbx = length
+    mov r14, r8             # r14 = offset
+    call _mem_file_name
+    mov rcx, r14
+    call _mem_addr
+    mov r12, rax            # r12 = host pointer
+    movzx r14d, r14w        # offset as an unsigned 16-bit value
+    cmp rbx, 65535
+    ja _rt_illegal_call     # unsigned compare also rejects negatives
+    lea rax, [r14 + rbx]
+    cmp rax, 65536
+    ja _rt_illegal_call
+
+    lea rcx, [rip + _mem_header]
+    mov BYTE PTR [rcx], BSAVE_MARKER
+    mov rax, QWORD PTR [rip + _mem_seg]
+    mov WORD PTR [rcx + 1], ax
+    mov WORD PTR [rcx + 3], r14w
+    mov WORD PTR [rcx + 5], bx
+
+    # CreateFileA(name, GENERIC_WRITE, FILE_SHARE_READ, NULL,
+    #             CREATE_ALWAYS, FILE_ATTRIBUTE_NORMAL, NULL)
+    lea rcx, [rip + _file_name_buf]
+    mov edx, GENERIC_WRITE
+    mov r8d, FILE_SHARE_READ
+    xor r9d, r9d
+    mov DWORD PTR [rsp + 32], CREATE_ALWAYS
+    mov DWORD PTR [rsp + 40], FILE_ATTRIBUTE_NORMAL
+    mov QWORD PTR [rsp + 48], 0
+    call CreateFileA
+    lea rcx, [rip + _mem_access_msg]
+    mov edx, _mem_access_msg_len
+    cmp rax, INVALID_HANDLE_VALUE
+    je _rt_error
+    mov r13, rax            # r13 = file handle
+
+    # WriteFile(h, header, 7, &done, NULL); WriteFile(h, data, length, &done, NULL)
+    mov rcx, r13
+    lea rdx, [rip + _mem_header]
+    mov r8d, BSAVE_HEADER_SIZE
+    lea r9, [rip + _mem_bytes_done]
+    mov QWORD PTR [rsp + 32], 0
+    call WriteFile
+    mov rcx, r13
+    mov rdx, r12
+    mov r8, rbx
+    lea r9, [rip + _mem_bytes_done]
+    mov QWORD PTR [rsp + 32], 0
+    call WriteFile
+    mov rcx, r13
+    call CloseHandle
+
+    add rsp, 64
+    pop rsi
+    pop rdi
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_bload - Load a file written by BSAVE into memory (BLOAD statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = filename pointer (BASIC string, not null-terminated)
+#   rdx = filename length
+#   r8  = offset within the current segment
+#   r9  = 1 if an offset was given, 0 to load at the segment and offset
+#         recorded in the file
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_bload
+_rt_bload:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push rdi
+    push rsi
+    sub rsp, 64             # Shadow space + stack args (must be 0 mod 16)
+
+    mov rbx, r9             # rbx = offset given?
+    mov r14, r8             # r14 = offset
+    call _mem_file_name
+
+    # CreateFileA(name, GENERIC_READ, FILE_SHARE_READ, NULL,
+    #             OPEN_EXISTING, FILE_ATTRIBUTE_NORMAL, NULL)
+    lea rcx, [rip + _file_name_buf]
+    mov edx, GENERIC_READ
+    mov r8d, FILE_SHARE_READ
+    xor r9d, r9d
+    mov DWORD PTR [rsp + 32], OPEN_EXISTING
+    mov DWORD PTR [rsp + 40], FILE_ATTRIBUTE_NORMAL
+    mov QWORD PTR [rsp + 48], 0
+    call CreateFileA
+    lea rcx, [rip + _mem_not_found_msg]
+    mov edx, _mem_not_found_msg_len
+    cmp rax, INVALID_HANDLE_VALUE
+    je _rt_error
+    mov r13, rax            # r13 = file handle
+
+    # ReadFile(h, header, 7, &done, NULL) and check the marker
+    mov rcx, r13
+    lea rdx, [rip + _mem_header]
+    mov r8d, BSAVE_HEADER_SIZE
+    lea r9, [rip + _mem_bytes_done]
+    mov QWORD PTR [rsp + 32], 0
+    call ReadFile
+    lea rcx, [rip + _mem_bad_mode_msg]
+    mov edx, _mem_bad_mode_msg_len
+    cmp DWORD PTR [rip + _mem_bytes_done], BSAVE_HEADER_SIZE
+    jne _rt_error
+    cmp BYTE PTR [rip + _mem_header], BSAVE_MARKER
+    jne _rt_error
+
+    test rbx, rbx
+    jnz .Lbload_at_offset
+    movzx r14d, WORD PTR [rip + _mem_header + 3]
+    movzx edx, WORD PTR [rip + _mem_header + 1]
+    mov rcx, r14
+    call _mem_seg_addr
+    jmp .Lbload_read
+.Lbload_at_offset:
+    mov rcx, r14
+    call _mem_addr
+    movzx r14d, r14w        # offset as an unsigned 16-bit value
+.Lbload_read:
+    mov r12, rax            # r12 = host pointer
+    movzx ebx, WORD PTR [rip + _mem_header + 5]    # rbx = length
+    lea rax, [r14 + rbx]
+    cmp rax, 65536
+    ja _rt_illegal_call
+
+    # ReadFile(h, data, length, &done, NULL)
+    mov rcx, r13
+    mov rdx, r12
+    mov r8, rbx
+    lea r9, [rip + _mem_bytes_done]
+    mov QWORD PTR [rsp + 32], 0
+    call ReadFile
+    mov rcx, r13
+    call CloseHandle
+
+    add rsp, 64
+    pop rsi
+    pop rdi
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _mem_file_name - Copy a BSAVE/BLOAD filename into _file_name_buf (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = filename pointer, rdx = filename length
+#
+# Returns: nothing. Clobbers caller-saved registers.
+# ------------------------------------------------------------------------------
+_mem_file_name:
+    push rbx
+    sub rsp, 32
+    mov rbx, rdx
+    cmp rbx, 1023
+    ja _rt_illegal_call     # longer than _file_name_buf (file.s)
+    mov r8, rdx
+    mov rdx, rcx
+    lea rcx, [rip + _file_name_buf]
+    call memcpy
+    lea rax, [rip + _file_name_buf]
+    mov BYTE PTR [rax + rbx], 0
+    add rsp, 32
+    pop rbx
+    ret
+
+# ------------------------------------------------------------------------------
+# _mem_addr - Translate an offset in the current segment (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = offset (-32768 to 65535; negative values wrap like 16-bit integers)
+#
+# Returns: as _mem_seg_addr
+# ------------------------------------------------------------------------------
+_mem_addr:
+    mov rdx, QWORD PTR [rip + _mem_seg]
+    # Fall through
+
+# ------------------------------------------------------------------------------
+# _mem_seg_addr - Translate a segment:offset address (internal)
+# ------------------------------------------------------------------------------
+# Allocates the emulated address space on first use.
+#
+# Arguments:
+#   rcx = offset (-32768 to 65535; negative values wrap like 16-bit integers)
+#   rdx = segment (0-65535)
+#
+# Returns:
+#   rax = host pointer to the byte
+#   Clobbers rcx, rdx, r8-r11 (calls HeapAlloc).
+# ------------------------------------------------------------------------------
+_mem_seg_addr:
+    cmp rcx, -32768
+    jl _rt_illegal_call
+    cmp rcx, 65535
+    jg _rt_illegal_call
+    movzx ecx, cx
+    mov rax, rdx
+
+    # Segments handed out by VARSEG map straight onto host memory
+    mov rdx, rax
+    sub rdx, MEM_MAP_SEG
+    jb .Lmem_addr_emulated
+    test rdx, 0xFF
+    jnz .Lmem_addr_emulated
+    shr rdx, 8
+    cmp rdx, MEM_MAP_SLOTS
+    jae .Lmem_addr_emulated
+    lea r8, [rip + _mem_maps]
+    mov r8, QWORD PTR [r8 + rdx*8]
+    test r8, r8
+    jz .Lmem_addr_emulated
+    lea rax, [r8 + rcx]
+    ret
+
+.Lmem_addr_emulated:
+    shl rax, 4
+    add rcx, rax            # rcx = linear address
+    mov rax, QWORD PTR [rip + _mem_base]
+    test rax, rax
+    jnz .Lmem_addr_ready
+    push rcx
+    sub rsp, 32
+
+    # HeapAlloc(GetProcessHeap(), HEAP_ZERO_MEMORY, MEM_SIZE)
+    call GetProcessHeap
+    mov rcx, rax
+    mov edx, 8              # HEAP_ZERO_MEMORY
+    mov r8d, MEM_SIZE
+    call HeapAlloc
+    add rsp, 32
+    pop rcx
+    mov QWORD PTR [rip + _mem_base], rax
+.Lmem_addr_ready:
+    add rax, rcx
+    ret
@@ -5,6 +5,19 @@
 # Output functions using Win32 API (WriteFile) instead of libc printf.
 # Uses UCRT sprintf for number formatting.
 #
+# Output channels:
+#   Console output (PRINT) and file output (PRINT #) share one engine that
+#   tracks the cursor column per channel: channel 0 is the console, channels
+#   1-15 are files opened with OPEN. Output wraps to a new line when the
+#   column reaches the channel's width (set by WIDTH), unless the width is
+#   255, which means never wrap. Commas advance to the next 14-column print
+#   zone, and TAB/SPC move the column.
+#
+# Text screen:
+#   Everything written to the console is also recorded in a 25x80 shadow of
+#   the screen, which SCREEN(row, col) reads (_scr_track has the rules, as
+#   in the SysV runtime). CLS and SCREEN clear it.
+#
 # Win64 ABI:
 #   - Integer args: rcx, rdx, r8, r9 (then stack)
 #   - 32-byte shadow space required before every call
@@ -14,14 +27,21 @@
 
 # Win32 API Constants
 .equ STD_OUTPUT_HANDLE, -11
+.equ STD_ERROR_HANDLE, -12
+.equ LC_NUMERIC, 4
 
 # I/O size constants
 .equ SINGLE_BYTE, 1
 .equ CRLF_LEN, 2
 
+# Output channel constants
+.equ OUT_WIDTH_INFINITE, 255
+.equ OUT_ZONE_WIDTH, 14
+.equ SCR_ROWS, 25
+.equ SCR_COLS, 80
+
 .data
 _stdout_handle: .quad 0
-_print_buffer: .skip 64          # Buffer for number formatting
 _bytes_written: .quad 0          # For WriteFile output parameter
 _newline_str: .ascii "\r\n"      # Windows uses CRLF
 
@@ -54,25 +74,10 @@ _rt_init_console:
 # ------------------------------------------------------------------------------
 .globl _rt_print_string
 _rt_print_string:
-    push rbp
-    mov rbp, rsp
-    sub rsp, 48             # Shadow space + stack args
-
-    # Save args
-    mov r8, rdx             # length → r8 (3rd arg for WriteFile)
-    mov rdx, rcx            # buffer → rdx (2nd arg for WriteFile)
-
-    # Get stdout handle
-    lea rax, [rip + _stdout_handle]
-    mov rcx, [rax]          # handle → rcx (1st arg)
-
-    # WriteFile(handle, buffer, length, &bytesWritten, NULL)
-    lea r9, [rip + _bytes_written]  # &bytesWritten → r9 (4th arg)
-    mov QWORD PTR [rsp + 32], 0     # NULL → 5th arg (stack)
-    call WriteFile
-
-    leave
-    ret
+    mov r8, rdx             # len
+    mov rdx, rcx            # ptr
+    xor ecx, ecx            # channel 0 = console
+    jmp _out_write
 
 # ------------------------------------------------------------------------------
 # _rt_print_char - Print a single ASCII character
@@ -82,129 +87,877 @@ _rt_print_string:
 # ------------------------------------------------------------------------------
 .globl _rt_print_char
 _rt_print_char:
-    push rbp
-    mov rbp, rsp
-    sub rsp, 48
+    mov rdx, rcx
+    xor ecx, ecx
+    jmp _out_char
 
-    # Store char in buffer
-    lea rax, [rip + _print_buffer]
-    mov [rax], cl
+# ------------------------------------------------------------------------------
+# _rt_print_newline - Print CRLF newline
+# ------------------------------------------------------------------------------
+.globl _rt_print_newline
+_rt_print_newline:
+    xor ecx, ecx
+    jmp _out_newline
 
-    # Get stdout handle
-    lea rax, [rip + _stdout_handle]
-    mov rcx, [rax]          # handle
+# ------------------------------------------------------------------------------
+# _rt_print_float - Print a numeric value
+# ------------------------------------------------------------------------------
+# Arguments:
+#   xmm0 = value to print (double)
+# ------------------------------------------------------------------------------
+.globl _rt_print_float
+_rt_print_float:
+    xor ecx, ecx
+    xor edx, edx            # as many digits as it takes
+    jmp _out_number
 
-    # WriteFile(handle, buffer, 1, &bytesWritten, NULL)
-    lea rdx, [rip + _print_buffer]
-    mov r8, SINGLE_BYTE
+# ------------------------------------------------------------------------------
+# _rt_print_single - Print a SINGLE value to single precision
+# ------------------------------------------------------------------------------
+# Arguments:
+#   xmm0 = value to print (a SINGLE widened to double)
+# ------------------------------------------------------------------------------
+.globl _rt_print_single
+_rt_print_single:
+    xor ecx, ecx
+    mov edx, 7              # 7 significant digits
+    jmp _out_number
+
+# ------------------------------------------------------------------------------
+# _rt_print_zone - Advance to the next print zone (comma in PRINT)
+# ------------------------------------------------------------------------------
+.globl _rt_print_zone
+_rt_print_zone:
+    xor ecx, ecx
+    jmp _out_zone
+
+# ------------------------------------------------------------------------------
+# _rt_print_tab - Move to a column (TAB function in PRINT)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = column (1-based)
+# ------------------------------------------------------------------------------
+.globl _rt_print_tab
+_rt_print_tab:
+    mov rdx, rcx
+    xor ecx, ecx
+    jmp _out_tab
+
+# ------------------------------------------------------------------------------
+# _rt_print_spc - Print spaces (SPC function in PRINT)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = number of spaces
+# ------------------------------------------------------------------------------
+.globl _rt_print_spc
+_rt_print_spc:
+    mov rdx, rcx
+    xor ecx, ecx
+    jmp _out_spc
+
+# ------------------------------------------------------------------------------
+# _rt_width - Set the console width (WIDTH statement)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = width in columns (1-255; 255 = never wrap)
+# ------------------------------------------------------------------------------
+.globl _rt_width
+_rt_width:
+    mov rdx, rcx
+    xor ecx, ecx
+    jmp _out_set_width
+
+# ------------------------------------------------------------------------------
+# _out_set_width - Set a channel's wrap width (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+#   rdx = width in columns (1-255; 255 = never wrap)
+# ------------------------------------------------------------------------------
+_out_set_width:
+    cmp rdx, 1
+    jl _rt_illegal_call
+    cmp rdx, OUT_WIDTH_INFINITE
+    jg _rt_illegal_call
+    lea rax, [rip + _out_width]
+    mov QWORD PTR [rax + rcx*8], rdx
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_raw - Write bytes to a channel without column bookkeeping (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel (0 = console, 1-15 = file number)
+#   rdx = pointer
+#   r8  = length
+# ------------------------------------------------------------------------------
+_out_raw:
+    lea rax, [rip + _file_buffered]
+    cmp BYTE PTR [rax + rcx], 0
+    jne _file_buffer        # disk files collect their output (see file.s)
+    push rbp
+    mov rbp, rsp
+    sub rsp, 64             # Shadow space + stack arg + saved arguments
+    test rcx, rcx
+    jnz .Lout_raw_file
+    mov QWORD PTR [rbp - 8], rdx
+    mov QWORD PTR [rbp - 16], r8
+    mov rcx, rdx            # keep the text screen up to date
+    mov rdx, r8
+    call _scr_track
+    mov rdx, QWORD PTR [rbp - 8]
+    mov r8, QWORD PTR [rbp - 16]
+    mov rcx, QWORD PTR [rip + _stdout_handle]
+    jmp .Lout_raw_write
+.Lout_raw_file:
+    lea rax, [rip + _file_handles]
+    mov rcx, QWORD PTR [rax + rcx*8]
+.Lout_raw_write:
+    # WriteFile(handle, buffer, length, &bytesWritten, NULL)
     lea r9, [rip + _bytes_written]
     mov QWORD PTR [rsp + 32], 0
     call WriteFile
+    leave
+    ret
 
+# ------------------------------------------------------------------------------
+# _out_write - Write a string to a channel, wrapping at its width (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+#   rdx = pointer
+#   r8  = length
+# ------------------------------------------------------------------------------
+_out_write:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    sub rsp, 32             # Shadow space
+    mov rbx, rcx            # rbx = channel
+    mov r12, rdx            # r12 = next byte
+    mov r13, r8             # r13 = bytes left
+
+.Lout_write_loop:
+    test r13, r13
+    jz .Lout_write_done
+    mov r14, r13            # r14 = chunk length
+    lea rax, [rip + _out_width]
+    mov rax, QWORD PTR [rax + rbx*8]
+    cmp rax, OUT_WIDTH_INFINITE
+    je .Lout_write_scan
+    lea rcx, [rip + _out_col]
+    mov rcx, QWORD PTR [rcx + rbx*8]
+    cmp rcx, rax
+    jb .Lout_write_room
+    mov rcx, rbx            # line is full
+    call _out_newline
+    jmp .Lout_write_loop
+.Lout_write_room:
+    sub rax, rcx            # room left on the line
+    cmp r14, rax
+    cmova r14, rax
+
+.Lout_write_scan:
+    # An embedded newline ends the chunk and resets the column
+    mov rcx, r12
+    mov edx, 10
+    mov r8, r14
+    call memchr
+    test rax, rax
+    jz .Lout_write_emit
+    sub rax, r12
+    lea r14, [rax + 1]
+
+.Lout_write_emit:
+    mov rcx, rbx
+    mov rdx, r12
+    mov r8, r14
+    call _out_raw
+    lea rcx, [rip + _out_col]
+    cmp BYTE PTR [r12 + r14 - 1], 10
+    jne .Lout_write_advance
+    mov QWORD PTR [rcx + rbx*8], 0
+    jmp .Lout_write_next
+.Lout_write_advance:
+    add QWORD PTR [rcx + rbx*8], r14
+.Lout_write_next:
+    add r12, r14
+    sub r13, r14
+    jmp .Lout_write_loop
+
+.Lout_write_done:
+    add rsp, 32
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_print_newline - Print CRLF newline
+# _out_char - Write one character to a channel (internal)
 # ------------------------------------------------------------------------------
-.globl _rt_print_newline
-_rt_print_newline:
+# Arguments:
+#   rcx = channel
+#   rdx = character code
+# ------------------------------------------------------------------------------
+_out_char:
     push rbp
     mov rbp, rsp
     sub rsp, 48
+    mov BYTE PTR [rbp - 1], dl
+    lea rdx, [rbp - 1]
+    mov r8d, SINGLE_BYTE
+    call _out_write
+    leave
+    ret
 
-    # Get stdout handle
-    lea rax, [rip + _stdout_handle]
-    mov rcx, [rax]
-
-    # WriteFile(handle, "\r\n", 2, &bytesWritten, NULL)
+# ------------------------------------------------------------------------------
+# _out_newline - End the current line on a channel with CRLF (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+# ------------------------------------------------------------------------------
+_out_newline:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx
     lea rdx, [rip + _newline_str]
-    mov r8, CRLF_LEN
-    lea r9, [rip + _bytes_written]
-    mov QWORD PTR [rsp + 32], 0
-    call WriteFile
+    mov r8d, CRLF_LEN
+    call _out_raw
+    lea rax, [rip + _out_col]
+    mov QWORD PTR [rax + rbx*8], 0
+    add rsp, 40
+    pop rbx
+    leave
+    ret
 
+# ------------------------------------------------------------------------------
+# _out_number - Write a number to a channel (internal)
+# ------------------------------------------------------------------------------
+# The number is formatted by _format_number. A number is never split across
+# lines: if it does not fit in the rest of the line, it starts a new one.
+#
+# Arguments:
+#   rcx = channel
+#   edx = significant digits for non-whole values (7 for SINGLE, 0 for as
+#         many as a DOUBLE needs)
+#   xmm0 = value (double)
+# ------------------------------------------------------------------------------
+_out_number:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    sub rsp, 96             # Shadow space + formatted number
+    mov rbx, rcx            # rbx = channel
+
+    # _format_number(buffer, digits, value) - digits still in edx
+    lea rcx, [rsp + 32]
+    call _format_number
+    test rbx, rbx
+    jnz .Lout_number_formatted      # files always use '.'
+    lea rcx, [rsp + 32]
+    mov rdx, rax
+    call _out_locale_point
+
+.Lout_number_formatted:
+    mov r12, rax            # r12 = length from sprintf
+    lea rax, [rip + _out_width]
+    mov rax, QWORD PTR [rax + rbx*8]
+    cmp rax, OUT_WIDTH_INFINITE
+    je .Lout_number_write
+    lea rcx, [rip + _out_col]
+    mov rcx, QWORD PTR [rcx + rbx*8]
+    test rcx, rcx
+    jz .Lout_number_write
+    add rcx, r12
+    cmp rcx, rax
+    jbe .Lout_number_write
+    mov rcx, rbx
+    call _out_newline
+.Lout_number_write:
+    mov rcx, rbx
+    lea rdx, [rsp + 32]
+    mov r8, r12
+    call _out_write
+
+    add rsp, 96
+    pop r12
+    pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_print_float - Print a numeric value
+# _format_number - Format a number as PRINT and STR$ show it (internal)
 # ------------------------------------------------------------------------------
+# Whole numbers are formatted without a decimal point; others with %.*g, to
+# the given number of significant digits, or for a DOUBLE with 15, then 16,
+# then 17 until strtod reads the text back as the same value.
+#
 # Arguments:
-#   xmm0 = value to print (double)
+#   rcx = buffer (at least 32 bytes)
+#   edx = significant digits for non-whole values (0: as many as it takes)
+#   xmm0 = value (double)
+#
+# Returns:
+#   rax = length of the text
 # ------------------------------------------------------------------------------
-.globl _rt_print_float
-_rt_print_float:
+_format_number:
     push rbp
     mov rbp, rsp
-    sub rsp, 64             # Shadow space + locals
+    push rbx
+    push r12
+    push r13
+    sub rsp, 56             # Shadow space, value, length + alignment
+    mov rbx, rcx            # rbx = buffer
+    mov r12d, edx           # r12 = significant digits
+    movsd QWORD PTR [rsp + 32], xmm0
 
     # Check if value is a whole number
     cvttsd2si rax, xmm0     # truncate to integer
     cvtsi2sd xmm1, rax      # convert back to double
     ucomisd xmm0, xmm1      # compare
-    jne .Lprint_as_float
+    jne .Lformat_number_float
 
-    # Format as integer using sprintf
     # sprintf(buffer, "%lld", value)
-    lea rcx, [rip + _print_buffer]
+    mov rcx, rbx
     lea rdx, [rip + _fmt_int]
     mov r8, rax             # integer value
     call sprintf
-    jmp .Lprint_formatted
-
-.Lprint_as_float:
-    # Format as float using sprintf
-    # sprintf(buffer, "%g", value)
-    lea rcx, [rip + _print_buffer]
-    lea rdx, [rip + _fmt_float]
-    movsd xmm2, xmm0        # value in xmm2
-    movq r8, xmm0           # also in r8 for varargs
+    jmp .Lformat_number_done
+
+.Lformat_number_float:
+    xor r13d, r13d          # r13 = 1 while looking for the fewest digits
+    test r12d, r12d
+    jnz .Lformat_number_digits
+    mov r12d, 15
+    mov r13d, 1
+.Lformat_number_digits:
+    # sprintf(buffer, "%.*g", digits, value)
+    mov rcx, rbx
+    lea rdx, [rip + _fmt_digits]
+    mov r8d, r12d
+    movsd xmm3, QWORD PTR [rsp + 32]    # value in xmm3
+    movq r9, xmm3                       # also in r9 for varargs
     call sprintf
+    test r13d, r13d
+    jz .Lformat_number_done
+    cmp r12d, 17
+    jae .Lformat_number_done
+    mov QWORD PTR [rsp + 40], rax
+
+    # Done if the text reads back as the value
+    mov rcx, rbx
+    xor edx, edx
+    call strtod
+    movsd xmm1, QWORD PTR [rsp + 32]
+    ucomisd xmm0, xmm1
+    mov rax, QWORD PTR [rsp + 40]
+    je .Lformat_number_done
+    inc r12d                # one more digit
+    jmp .Lformat_number_digits
+
+.Lformat_number_done:
+    add rsp, 56
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
 
-.Lprint_formatted:
-    # rax = number of chars written by sprintf
+# ------------------------------------------------------------------------------
+# _rt_init_locale - Use the locale's decimal point (called at program start)
+# ------------------------------------------------------------------------------
+# With --locale, numbers on the console and in STR$ use the decimal point of
+# the user's locale, and VAL reads it. The CRT stays in the "C" locale, so
+# sprintf and strtod keep using '.'. A decimal point of more than one byte
+# leaves it '.'.
+#
+# Arguments: none
+# Returns: nothing
+# ------------------------------------------------------------------------------
+.globl _rt_init_locale
+_rt_init_locale:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    # setlocale(LC_NUMERIC, "")
+    mov ecx, LC_NUMERIC
+    lea rdx, [rip + _locale_env]
+    call setlocale
+    test rax, rax
+    jz .Linit_locale_done
+    # localeconv()->decimal_point, the struct's first field
+    call localeconv
+    mov rax, QWORD PTR [rax]
+    movzx ecx, BYTE PTR [rax]
+    test ecx, ecx
+    jz .Linit_locale_restore
+    cmp BYTE PTR [rax + 1], 0
+    jne .Linit_locale_restore
+    mov BYTE PTR [rip + _out_decimal_point], cl
+.Linit_locale_restore:
+    # setlocale(LC_NUMERIC, "C")
+    mov ecx, LC_NUMERIC
+    lea rdx, [rip + _locale_c]
+    call setlocale
+.Linit_locale_done:
+    leave
+    ret
 
-    # Get stdout handle
-    lea rcx, [rip + _stdout_handle]
-    mov rcx, [rcx]
+# ------------------------------------------------------------------------------
+# _out_locale_point - Give a formatted number the console's decimal point
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = text from _format_number
+#   rdx = its length
+#
+# Returns: nothing. Uses only r8 and r9.
+# ------------------------------------------------------------------------------
+_out_locale_point:
+    movzx r8d, BYTE PTR [rip + _out_decimal_point]
+    cmp r8d, '.'
+    je .Lout_locale_point_done
+    xor r9d, r9d
+.Lout_locale_point_loop:
+    cmp r9, rdx
+    jae .Lout_locale_point_done
+    cmp BYTE PTR [rcx + r9], '.'
+    je .Lout_locale_point_found
+    inc r9
+    jmp .Lout_locale_point_loop
+.Lout_locale_point_found:
+    mov BYTE PTR [rcx + r9], r8b
+.Lout_locale_point_done:
+    ret
 
-    # WriteFile(handle, buffer, strlen, &bytesWritten, NULL)
-    lea rdx, [rip + _print_buffer]
-    mov r8, rax             # length from sprintf return
-    lea r9, [rip + _bytes_written]
-    mov QWORD PTR [rsp + 32], 0
-    call WriteFile
+# ------------------------------------------------------------------------------
+# _out_zone - Advance to the next 14-column print zone (internal)
+# ------------------------------------------------------------------------------
+# Starts a new line instead if the next zone would not fit within the width.
+#
+# Arguments:
+#   rcx = channel
+# ------------------------------------------------------------------------------
+_out_zone:
+    lea rax, [rip + _out_col]
+    mov rax, QWORD PTR [rax + rcx*8]
+    mov r8d, OUT_ZONE_WIDTH
+    xor edx, edx
+    div r8
+    inc rax
+    imul rax, rax, OUT_ZONE_WIDTH   # rax = start of the next zone
+    lea r8, [rip + _out_width]
+    mov r8, QWORD PTR [r8 + rcx*8]
+    cmp r8, OUT_WIDTH_INFINITE
+    je .Lout_zone_pad
+    lea rdx, [rax + OUT_ZONE_WIDTH]
+    cmp rdx, r8
+    ja _out_newline
+.Lout_zone_pad:
+    mov rdx, rax
+    jmp _out_pad_to
 
+# ------------------------------------------------------------------------------
+# _out_tab - Move to a column (internal)
+# ------------------------------------------------------------------------------
+# Columns past the width wrap around. If the cursor is already past the
+# column, the move continues on the next line.
+#
+# Arguments:
+#   rcx = channel
+#   rdx = column (1-based)
+# ------------------------------------------------------------------------------
+_out_tab:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    sub rsp, 32             # Shadow space
+    mov rbx, rcx
+    mov rax, rdx
+    dec rax
+    jns .Lout_tab_positive
+    xor eax, eax            # columns below 1 mean column 1
+.Lout_tab_positive:
+    lea r8, [rip + _out_width]
+    mov r8, QWORD PTR [r8 + rbx*8]
+    cmp r8, OUT_WIDTH_INFINITE
+    je .Lout_tab_move
+    xor edx, edx
+    div r8
+    mov rax, rdx            # column modulo width
+.Lout_tab_move:
+    mov r12, rax            # r12 = target column (0-based)
+    lea rax, [rip + _out_col]
+    cmp QWORD PTR [rax + rbx*8], r12
+    jbe .Lout_tab_pad
+    mov rcx, rbx
+    call _out_newline
+.Lout_tab_pad:
+    mov rcx, rbx
+    mov rdx, r12
+    call _out_pad_to
+    add rsp, 32
+    pop r12
+    pop rbx
     leave
     ret
 
 # ------------------------------------------------------------------------------
-# _rt_gosub_overflow - Handle GOSUB stack overflow error
+# _out_spc - Write spaces (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+#   rdx = count (negative means 0; counts past the width wrap around)
+# ------------------------------------------------------------------------------
+_out_spc:
+    test rdx, rdx
+    jle .Lout_spc_done
+    lea r8, [rip + _out_width]
+    mov r8, QWORD PTR [r8 + rcx*8]
+    cmp r8, OUT_WIDTH_INFINITE
+    je .Lout_spc_write
+    mov rax, rdx
+    xor edx, edx
+    div r8                  # rdx = count modulo width
+.Lout_spc_write:
+    jmp _out_spaces
+.Lout_spc_done:
+    ret
+
+# ------------------------------------------------------------------------------
+# _out_pad_to - Write spaces up to a column on the current line (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+#   rdx = target column (0-based, not before the current column)
+# ------------------------------------------------------------------------------
+_out_pad_to:
+    lea rax, [rip + _out_col]
+    sub rdx, QWORD PTR [rax + rcx*8]
+    # Fall through
+
+# ------------------------------------------------------------------------------
+# _out_spaces - Write spaces to a channel (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+#   rdx = count
 # ------------------------------------------------------------------------------
-# Called when the GOSUB return stack is exhausted. Prints an error message
-# and terminates the program with exit code 1.
+_out_spaces:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    sub rsp, 32             # Shadow space
+    mov rbx, rcx
+    mov r12, rdx
+.Lout_spaces_loop:
+    test r12, r12
+    jle .Lout_spaces_done
+    mov r8, r12
+    cmp r8, 16
+    jbe .Lout_spaces_chunk
+    mov r8d, 16
+.Lout_spaces_chunk:
+    sub r12, r8
+    mov rcx, rbx
+    lea rdx, [rip + _out_blanks]
+    call _out_write
+    jmp .Lout_spaces_loop
+.Lout_spaces_done:
+    add rsp, 32
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_screen_char - Character code at a text screen position (SCREEN function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = row (1-25)
+#   rdx = column (1-80)
+#
+# Returns:
+#   rax = character code (32 for a blank cell)
+#   A position off the screen -> "Illegal function call", exit 1
+# ------------------------------------------------------------------------------
+.globl _rt_screen_char
+_rt_screen_char:
+    lea rax, [rcx - 1]
+    cmp rax, SCR_ROWS
+    jae _rt_illegal_call
+    lea rcx, [rdx - 1]
+    cmp rcx, SCR_COLS
+    jae _rt_illegal_call
+    imul rax, rax, SCR_COLS
+    add rax, rcx
+    lea rdx, [rip + _scr_text]
+    movzx eax, BYTE PTR [rdx + rax]
+    ret
+
+# ------------------------------------------------------------------------------
+# _scr_clear - Blank the text screen and home its cursor (internal)
+# ------------------------------------------------------------------------------
+_scr_clear:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    lea rcx, [rip + _scr_text]
+    mov edx, 32
+    mov r8d, SCR_ROWS * SCR_COLS
+    call memset
+    mov QWORD PTR [rip + _scr_row], 0
+    mov QWORD PTR [rip + _scr_col], 0
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _scr_track - Record console output on the text screen (internal)
+# ------------------------------------------------------------------------------
+# Printable characters fill cells and wrap at the right edge, newline and
+# carriage return move the cursor, and other control characters take no
+# cell.
+#
+# Arguments:
+#   rcx = pointer
+#   rdx = length
+# ------------------------------------------------------------------------------
+_scr_track:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    sub rsp, 32             # Shadow space
+    mov rbx, rcx            # rbx = next byte
+    lea r12, [rcx + rdx]    # r12 = end
+.Lscr_track_loop:
+    cmp rbx, r12
+    jae .Lscr_track_done
+    movzx eax, BYTE PTR [rbx]
+    inc rbx
+    cmp eax, 10
+    je .Lscr_track_newline
+    cmp eax, 13
+    je .Lscr_track_return
+    cmp eax, 32
+    jb .Lscr_track_loop     # other control characters take no cell
+    mov rcx, QWORD PTR [rip + _scr_col]
+    cmp rcx, SCR_COLS
+    jb .Lscr_track_store
+    call _scr_newline       # past the right edge: wrap first
+    movzx eax, BYTE PTR [rbx - 1]
+    xor ecx, ecx
+.Lscr_track_store:
+    mov rdx, QWORD PTR [rip + _scr_row]
+    imul rdx, rdx, SCR_COLS
+    add rdx, rcx
+    lea r8, [rip + _scr_text]
+    mov BYTE PTR [r8 + rdx], al
+    inc rcx
+    mov QWORD PTR [rip + _scr_col], rcx
+    jmp .Lscr_track_loop
+.Lscr_track_newline:
+    call _scr_newline
+    jmp .Lscr_track_loop
+.Lscr_track_return:
+    mov QWORD PTR [rip + _scr_col], 0
+    jmp .Lscr_track_loop
+.Lscr_track_done:
+    add rsp, 32
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _scr_newline - Move the text screen cursor to the next line (internal)
+# ------------------------------------------------------------------------------
+# On the last row, the screen scrolls up one line instead.
+# ------------------------------------------------------------------------------
+_scr_newline:
+    mov QWORD PTR [rip + _scr_col], 0
+    mov rax, QWORD PTR [rip + _scr_row]
+    inc rax
+    cmp rax, SCR_ROWS
+    jae .Lscr_newline_scroll
+    mov QWORD PTR [rip + _scr_row], rax
+    ret
+.Lscr_newline_scroll:
+    push rbp
+    mov rbp, rsp
+    sub rsp, 32             # Shadow space
+    # memmove(text, text + SCR_COLS, (SCR_ROWS - 1) * SCR_COLS)
+    lea rcx, [rip + _scr_text]
+    lea rdx, [rcx + SCR_COLS]
+    mov r8d, (SCR_ROWS - 1) * SCR_COLS
+    call memmove
+    # memset(last row, ' ', SCR_COLS)
+    lea rcx, [rip + _scr_text + (SCR_ROWS - 1) * SCR_COLS]
+    mov edx, 32
+    mov r8d, SCR_COLS
+    call memset
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_error - Report a runtime error and end the program
+# ------------------------------------------------------------------------------
+# Prints the message to stderr, as "<message> in <line>" once the program has
+# passed a numbered line (codegen keeps _rt_cur_line up to date), and exits
+# with code 1. Reached by jump, with any stack alignment.
+#
+# Arguments:
+#   rcx = message, rdx = message length
 #
-# Arguments: none
 # Returns: never (calls ExitProcess)
 # ------------------------------------------------------------------------------
-.globl _rt_gosub_overflow
-_rt_gosub_overflow:
+.globl _rt_error
+_rt_error:
     push rbp
     mov rbp, rsp
+    and rsp, -16            # May be entered with any stack alignment
     sub rsp, 48
+    mov rdi, rcx            # Never returns, so rbx/rdi/rsi need no saving
+    mov rsi, rdx
+
+    mov ecx, STD_ERROR_HANDLE
+    call GetStdHandle
+    mov rbx, rax
 
-    # Get stdout handle
-    lea rax, [rip + _stdout_handle]
-    mov rcx, [rax]
+    # WriteFile(stderr, message, length, &bytesWritten, NULL)
+    mov rcx, rbx
+    mov rdx, rdi
+    mov r8, rsi
+    lea r9, [rip + _bytes_written]
+    mov QWORD PTR [rsp + 32], 0
+    call WriteFile
 
-    # WriteFile(handle, message, length, &bytesWritten, NULL)
-    lea rdx, [rip + _gosub_overflow_msg]
-    mov r8, _gosub_overflow_msg_len
+    # " in <line>" once a numbered line has been reached
+    mov r8, QWORD PTR [rip + _rt_cur_line]
+    test r8, r8
+    jz .Lerror_newline
+    lea rcx, [rip + _error_buf]
+    lea rdx, [rip + _error_line_fmt]
+    call sprintf            # returns length in eax
+    mov rcx, rbx
+    lea rdx, [rip + _error_buf]
+    mov r8d, eax
     lea r9, [rip + _bytes_written]
     mov QWORD PTR [rsp + 32], 0
     call WriteFile
 
-    # ExitProcess(1)
+.Lerror_newline:
+    mov rcx, rbx
+    lea rdx, [rip + _newline_str]
+    mov r8, CRLF_LEN
+    lea r9, [rip + _bytes_written]
+    mov QWORD PTR [rsp + 32], 0
+    call WriteFile
+
+    # ExitProcess skips atexit handlers, so write out the files and the
+    # image here
+    call _file_flush_all
+    call _rt_gfx_flush
     mov ecx, 1
     call ExitProcess
+
+# ------------------------------------------------------------------------------
+# _rt_gosub_overflow - Handle GOSUB stack overflow error
+# ------------------------------------------------------------------------------
+# Called when the GOSUB return stack is exhausted.
+#
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_gosub_overflow
+_rt_gosub_overflow:
+    lea rcx, [rip + _gosub_overflow_msg]
+    mov edx, _gosub_overflow_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_gosub_underflow - Handle RETURN without GOSUB
+# ------------------------------------------------------------------------------
+# Called when RETURN finds no return address pushed by the main program or by
+# the current SUB/FUNCTION call.
+#
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_gosub_underflow
+_rt_gosub_underflow:
+    lea rcx, [rip + _gosub_underflow_msg]
+    mov edx, _gosub_underflow_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_overflow - Handle INTEGER/LONG overflow
+# ------------------------------------------------------------------------------
+# Called (with --overflow-check) when an INTEGER or LONG result falls outside
+# its type's range.
+#
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_overflow
+_rt_overflow:
+    lea rcx, [rip + _overflow_msg]
+    mov edx, _overflow_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_subscript_range - Handle an array subscript outside the array's DIM
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_subscript_range
+_rt_subscript_range:
+    lea rcx, [rip + _subscript_msg]
+    mov edx, _subscript_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_div_zero - Handle division by zero (/, \\ or MOD)
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_div_zero
+_rt_div_zero:
+    lea rcx, [rip + _div_zero_msg]
+    mov edx, _div_zero_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_bad_file - Handle a file number that is out of range or not open
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_bad_file
+_rt_bad_file:
+    lea rcx, [rip + _bad_file_msg]
+    mov edx, _bad_file_msg_len
+    jmp _rt_error
+
+# ------------------------------------------------------------------------------
+# _rt_type_mismatch - Handle a value of the wrong type for its PRINT USING field
+# ------------------------------------------------------------------------------
+# Arguments: none
+# Returns: never (reports the error with _rt_error)
+# ------------------------------------------------------------------------------
+.globl _rt_type_mismatch
+_rt_type_mismatch:
+    lea rcx, [rip + _type_mismatch_msg]
+    mov edx, _type_mismatch_msg_len
+    jmp _rt_error
@@ -35,28 +35,67 @@ _chr_buf: .skip 2           # Buffer for CHR$()
 # ------------------------------------------------------------------------------
 # _rt_val - Convert string to number (VAL function)
 # ------------------------------------------------------------------------------
+# With another decimal point from the locale (see _rt_init_locale), strtod
+# reads a copy of the first VAL_MAX bytes with that decimal point made '.'.
+#
 # Arguments:
 #   rcx = pointer to string
-#   rdx = length (ignored - strtod reads until non-numeric)
+#   rdx = length (ignored unless the console's decimal point isn't '.' -
+#         strtod reads until non-numeric)
 #
 # Returns:
 #   xmm0 = parsed double value
 # ------------------------------------------------------------------------------
+.equ VAL_MAX, 255
+
 .globl _rt_val
 _rt_val:
     push rbp
     mov rbp, rsp
     sub rsp, 32             # Shadow space
+    movzx eax, BYTE PTR [rip + _out_decimal_point]
+    cmp eax, '.'
+    jne .Lval_locale
     xor rdx, rdx            # endptr = NULL
     call strtod             # returns double in xmm0
     leave
     ret
+.Lval_locale:
+    sub rsp, VAL_MAX + 1    # the copy and its NUL, above the shadow space
+    cmp rdx, VAL_MAX
+    jbe .Lval_copy
+    mov edx, VAL_MAX
+.Lval_copy:
+    xor r9d, r9d
+.Lval_copy_loop:
+    cmp r9, rdx
+    jae .Lval_copied
+    movzx r8d, BYTE PTR [rcx + r9]
+    cmp r8d, eax
+    jne .Lval_copy_byte
+    mov r8d, '.'
+.Lval_copy_byte:
+    mov BYTE PTR [rsp + 32 + r9], r8b
+    inc r9
+    jmp .Lval_copy_loop
+.Lval_copied:
+    mov BYTE PTR [rsp + 32 + r9], 0
+    lea rcx, [rsp + 32]
+    xor edx, edx
+    call strtod
+    leave
+    ret
 
 # ------------------------------------------------------------------------------
 # _rt_str - Convert number to string (STR$ function)
 # ------------------------------------------------------------------------------
+# Formats a number as PRINT does (see _format_number), with a leading space
+# for a number that isn't negative, where the sign would go.
+#
 # Arguments:
 #   xmm0 = number to convert (double)
+#   ecx  = significant digits for non-whole values (7 for SINGLE, 0 for as
+#          many as a DOUBLE needs)
 #
 # Returns:
 #   rax = pointer to string (_str_buf)
@@ -66,26 +105,22 @@ _rt_val:
 _rt_str:
     push rbp
     mov rbp, rsp
-    sub rsp, 48             # Shadow space + alignment
-
-    # sprintf(buffer, "%g", value)
-    lea rcx, [rip + _str_buf]
-    lea rdx, [rip + _fmt_float]
-    movsd xmm2, xmm0        # value in xmm2
-    movq r8, xmm0           # also in r8 for varargs
-    call sprintf
-
-    # Calculate result length
-    lea rax, [rip + _str_buf]
-    mov rcx, rax            # save ptr
-    xor rdx, rdx            # length counter
-.Lstr_len:
-    cmp BYTE PTR [rax + rdx], 0
+    sub rsp, 32             # Shadow space
+
+    # _format_number(buffer + 1, digits, value), leaving room for the space
+    mov edx, ecx
+    lea rcx, [rip + _str_buf + 1]
+    call _format_number
+    mov rdx, rax            # length
+    lea rcx, [rip + _str_buf + 1]
+    call _out_locale_point
+    lea rax, [rip + _str_buf + 1]
+    cmp BYTE PTR [rax], 45  # '-'
     je .Lstr_done
+    dec rax
+    mov BYTE PTR [rax], 32  # ' '
     inc rdx
-    jmp .Lstr_len
 .Lstr_done:
-    mov rax, rcx            # restore ptr
     leave
     ret
 
@@ -221,16 +256,22 @@ _rt_instr:
     mov r14, r8             # needle ptr
     mov r15, r9             # needle len
     mov rbx, rdi            # start position (1-based)
+    cmp rbx, 1
+    jge .Linstr_start_ok
+    mov rbx, 1              # search from the first character
+.Linstr_start_ok:
+
+    # Special case: empty needle matches at the start position
+    test r15, r15
+    jz .Linstr_at_start
+    cmp rbx, r13
+    jg .Linstr_not_found    # start is past the end
 
     # Adjust for start position
     dec rbx                 # convert to 0-based
     add r12, rbx            # advance haystack ptr
     sub r13, rbx            # reduce remaining length
 
-    # Special case: empty needle
-    test r15, r15
-    jz .Linstr_at_start
-
 .Linstr_loop:
     cmp r13, r15
     jb .Linstr_not_found
@@ -255,7 +296,6 @@ _rt_instr:
 
 .Linstr_at_start:
     mov rax, rbx
-    add rax, 1
     jmp .Linstr_done
 
 .Linstr_not_found:
@@ -273,6 +313,93 @@ _rt_instr:
     leave
     ret
 
+# ------------------------------------------------------------------------------
+# _rt_instrrev - Find the last substring position (INSTRREV function)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = haystack pointer
+#   rdx = haystack length
+#   r8  = needle pointer
+#   r9  = needle length
+#   [rsp+40] = start position (1-based; past the end means the end)
+#
+# Returns:
+#   rax = position (1-based) of the last match starting at or before the
+#         start position, or 0 if not found
+# ------------------------------------------------------------------------------
+.globl _rt_instrrev
+_rt_instrrev:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    push rdi
+    push rsi
+    sub rsp, 40             # Shadow space + alignment
+
+    mov r12, rcx            # haystack ptr
+    mov r13, rdx            # haystack len
+    mov r14, r8             # needle ptr
+    mov r15, r9             # needle len
+    mov rbx, QWORD PTR [rbp + 48]   # start position (1-based)
+    cmp rbx, r13
+    jle .Linstrrev_start_ok
+    mov rbx, r13            # search from the last character
+.Linstrrev_start_ok:
+
+    # Empty needle: found at the start position
+    test r15, r15
+    jz .Linstrrev_loop
+
+    # The last position a whole needle fits at
+    mov rax, r13
+    sub rax, r15
+    inc rax
+    cmp rbx, rax
+    jle .Linstrrev_loop
+    mov rbx, rax
+
+.Linstrrev_loop:
+    cmp rbx, 1
+    jl .Linstrrev_not_found
+    test r15, r15
+    jz .Linstrrev_found
+
+    # memcmp(haystack + position - 1, needle, needle_len)
+    mov rcx, r12
+    add rcx, rbx
+    dec rcx
+    mov rdx, r14
+    mov r8, r15
+    call memcmp
+    test eax, eax
+    jz .Linstrrev_found
+
+    dec rbx
+    jmp .Linstrrev_loop
+
+.Linstrrev_found:
+    mov rax, rbx
+    jmp .Linstrrev_done
+
+.Linstrrev_not_found:
+    xor rax, rax
+
+.Linstrrev_done:
+    add rsp, 40
+    pop rsi
+    pop rdi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
 # ------------------------------------------------------------------------------
 # _rt_strcat - Concatenate two strings (+ operator)
 # ------------------------------------------------------------------------------
@@ -346,3 +473,58 @@ _rt_strcat:
     leave
     ret
 
+
+# ------------------------------------------------------------------------------
+# _rt_strcmp - Compare two strings (=, <>, <, >, <=, >= operators)
+# ------------------------------------------------------------------------------
+# Compares byte by byte (as unsigned characters); when one string is a prefix
+# of the other, the shorter one is less.
+#
+# Arguments:
+#   rcx = left string pointer
+#   rdx = left string length
+#   r8  = right string pointer
+#   r9  = right string length
+#
+# Returns:
+#   eax = -1, 0 or 1 as left is less than, equal to or greater than right
+# ------------------------------------------------------------------------------
+.globl _rt_strcmp
+_rt_strcmp:
+    push rbp
+    mov rbp, rsp
+    push r12
+    push r13
+    sub rsp, 32             # Shadow space
+
+    mov r12, rdx            # left len
+    mov r13, r9             # right len
+
+    # memcmp(left, right, min(left_len, right_len))
+    mov rdx, r8
+    mov r8, r12
+    cmp r8, r13
+    cmova r8, r13
+    call memcmp
+    test eax, eax
+    jnz .Lstrcmp_sign
+
+    # Common prefix is equal: the longer string is greater
+    cmp r12, r13
+    seta al
+    setb cl
+    movzx eax, al
+    movzx ecx, cl
+    sub eax, ecx
+    jmp .Lstrcmp_done
+
+.Lstrcmp_sign:
+    sar eax, 31             # -1 if negative, else 0
+    or eax, 1               # -1 or 1
+
+.Lstrcmp_done:
+    add rsp, 32
+    pop r13
+    pop r12
+    leave
+    ret
@@ -0,0 +1,837 @@
+# ==============================================================================
+# BASIC Runtime: PRINT USING (Win64 Native)
+# ==============================================================================
+#
+# PRINT USING and PRINT # USING write their items through a format string.
+# The compiled code calls _rt_print_using with the format, then
+# _rt_print_using_number or _rt_print_using_string for each item, then
+# _rt_print_using_end (the PRINT # versions in file.s check the file and do
+# the same on its channel). The fields and the rules for them are those of
+# the SysV runtime (see sysv/using.s); numbers are formatted with UCRT
+# sprintf.
+#
+# Win64 ABI:
+#   - Integer args: rcx, rdx, r8, r9 (then stack)
+#   - 32-byte shadow space required before every call
+#   - Callee-saved: rbx, rbp, rdi, rsi, r12-r15
+#
+# Global state:
+#   _using_buf  = copy of the format (heap, grown as needed)
+#   _using_size = bytes allocated for _using_buf
+#   _using_len  = format length
+#   _using_pos  = where the next item's text starts
+#   _using_spec = the field _using_field found last
+# ==============================================================================
+
+.equ USING_NONE, 0          # field kinds
+.equ USING_NUMBER, 1
+.equ USING_STRING, 2
+.equ USING_PLUS, 1          # number field flags: leading +
+.equ USING_TRAIL_PLUS, 2
+.equ USING_TRAIL_MINUS, 4
+.equ USING_TRAILING, 6      # either trailing sign
+.equ USING_SIGNED, 7        # any sign
+.equ USING_COMMA, 8
+.equ USING_STARS, 16
+.equ USING_DOLLAR, 32
+.equ USING_WIDTH, 0         # _using_spec: characters the field takes
+.equ USING_DIGITS, 8        # digit positions before the point; for a string
+                            # field, characters written (0 = all)
+.equ USING_FRAC, 16         # digits after the point (-1 = no point)
+.equ USING_FLAGS, 24
+.equ USING_EXP, 32          # exponent digits (0 = no exponent)
+.equ USING_SPEC_SIZE, 40
+.equ USING_MAX_DIGITS, 100  # most decimals (and exponent digits) written
+.equ USING_TEXT, 32         # _using_number frame, above the shadow space:
+                            # the number's digits,
+.equ USING_RAW, 544         # sprintf's %e text,
+.equ USING_EXPONENT, 1056   # the exponent,
+.equ USING_VALUE, 1088      # the value without its sign,
+.equ USING_NEG, 1096        # 1 if it was negative,
+.equ USING_EXP_LEN, 1104    # the exponent's length
+.equ USING_TAIL_LEN, 1112   # and the length of the text after the digits
+.equ USING_FRAME, 1128
+
+.data
+_using_buf: .quad 0
+_using_size: .quad 0
+_using_len: .quad 0
+_using_pos: .quad 0
+_using_spec: .skip USING_SPEC_SIZE
+_using_fixed_fmt: .asciz "%.*f"
+_using_exp_fmt: .asciz "%.*e"
+_using_exp_digits_fmt: .asciz "E%+0*lld"
+
+.text
+
+# ------------------------------------------------------------------------------
+# _rt_print_using - Start a PRINT USING statement
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = format pointer
+#   rdx = format length
+#
+# Returns: nothing (a format without fields -> "Illegal function call")
+# ------------------------------------------------------------------------------
+.globl _rt_print_using
+_rt_print_using:
+    test rdx, rdx
+    jz _rt_illegal_call
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    sub rsp, 32             # Shadow space
+    mov rbx, rcx            # rbx = format
+    mov r12, rdx            # r12 = length
+    cmp r12, QWORD PTR [rip + _using_size]
+    jbe .Lusing_copy
+    # HeapReAlloc(GetProcessHeap(), 0, buf, length), or HeapAlloc the first time
+    call GetProcessHeap
+    mov rcx, rax
+    xor edx, edx
+    mov r8, QWORD PTR [rip + _using_buf]
+    test r8, r8
+    jz .Lusing_alloc
+    mov r9, r12
+    call HeapReAlloc
+    jmp .Lusing_grown
+.Lusing_alloc:
+    mov r8, r12
+    call HeapAlloc
+.Lusing_grown:
+    mov QWORD PTR [rip + _using_buf], rax
+    mov QWORD PTR [rip + _using_size], r12
+.Lusing_copy:
+    mov rcx, QWORD PTR [rip + _using_buf]
+    mov rdx, rbx
+    mov r8, r12
+    call memcpy
+    mov QWORD PTR [rip + _using_len], r12
+    mov QWORD PTR [rip + _using_pos], 0
+    xor ecx, ecx
+    call _using_scan
+    cmp rax, r12
+    jb .Lusing_start_done
+    call _rt_illegal_call   # no fields
+.Lusing_start_done:
+    add rsp, 32
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _rt_print_using_number - Print a number through the next field
+# ------------------------------------------------------------------------------
+# Arguments:
+#   xmm0 = value (double)
+# ------------------------------------------------------------------------------
+.globl _rt_print_using_number
+_rt_print_using_number:
+    xor ecx, ecx
+    jmp _using_number
+
+# ------------------------------------------------------------------------------
+# _rt_print_using_string - Print a string through the next field
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = pointer to string data
+#   rdx = string length
+# ------------------------------------------------------------------------------
+.globl _rt_print_using_string
+_rt_print_using_string:
+    mov r8, rdx
+    mov rdx, rcx
+    xor ecx, ecx
+    jmp _using_string
+
+# ------------------------------------------------------------------------------
+# _rt_print_using_end - Print the format's text after the last field used
+# ------------------------------------------------------------------------------
+.globl _rt_print_using_end
+_rt_print_using_end:
+    xor ecx, ecx
+    jmp _using_text
+
+# ------------------------------------------------------------------------------
+# _using_number - Write a number through the next field (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+#   xmm0 = value (double)
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_using_number:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    sub rsp, USING_FRAME
+    mov rbx, rcx            # rbx = channel
+    movsd QWORD PTR [rsp + USING_VALUE], xmm0
+    call _using_next
+    cmp eax, USING_NUMBER
+    je .Lusing_number_field
+    call _rt_type_mismatch
+.Lusing_number_field:
+    lea r12, [rip + _using_spec]    # r12 = field
+
+    # Split the value into its sign and size
+    movsd xmm0, QWORD PTR [rsp + USING_VALUE]
+    xorpd xmm1, xmm1
+    xor eax, eax
+    ucomisd xmm0, xmm1
+    jae .Lusing_number_positive
+    mov eax, 1
+    subsd xmm1, xmm0
+    movsd QWORD PTR [rsp + USING_VALUE], xmm1
+    jmp .Lusing_number_size
+.Lusing_number_positive:
+    addsd xmm0, xmm1        # -0 becomes 0
+    movsd QWORD PTR [rsp + USING_VALUE], xmm0
+.Lusing_number_size:
+    mov QWORD PTR [rsp + USING_NEG], rax
+    mov QWORD PTR [rsp + USING_EXP_LEN], 0
+    mov r13, QWORD PTR [r12 + USING_DIGITS]     # r13 = digit positions
+    mov r14, QWORD PTR [r12 + USING_FRAC]
+    test r14, r14
+    jns .Lusing_number_decimals
+    xor r14d, r14d          # r14 = decimals
+.Lusing_number_decimals:
+    mov eax, USING_MAX_DIGITS
+    cmp r14, rax
+    cmova r14, rax
+    cmp QWORD PTR [r12 + USING_EXP], 0
+    jne .Lusing_number_exponent
+
+    # sprintf(text, "%.*f", decimals, value)
+    lea rcx, [rsp + USING_TEXT]
+    lea rdx, [rip + _using_fixed_fmt]
+    mov r8, r14
+    movsd xmm3, QWORD PTR [rsp + USING_VALUE]   # value in xmm3
+    movq r9, xmm3                               # also in r9 for varargs
+    call sprintf
+    jmp .Lusing_number_text
+
+.Lusing_number_exponent:
+    # The digits before the point fill the digit positions, less one kept
+    # for a minus sign unless the field has a sign of its own
+    mov rax, QWORD PTR [r12 + USING_FLAGS]
+    test eax, USING_SIGNED
+    jnz .Lusing_exp_positions
+    dec r13
+    jns .Lusing_exp_positions
+    xor r13d, r13d
+.Lusing_exp_positions:
+    mov rax, r13
+    or rax, r14
+    jnz .Lusing_exp_digits
+    mov r13d, 1             # at least one digit
+.Lusing_exp_digits:
+    # sprintf(raw, "%.*e", digits - 1, value)
+    lea rcx, [rsp + USING_RAW]
+    lea rdx, [rip + _using_exp_fmt]
+    lea r8, [r13 + r14 - 1]
+    mov eax, USING_MAX_DIGITS
+    cmp r8, rax
+    cmova r8, rax
+    movsd xmm3, QWORD PTR [rsp + USING_VALUE]
+    movq r9, xmm3
+    call sprintf
+
+    # Copy the digits to text with the point after the first r13 of them
+    lea r8, [rsp + USING_RAW]
+    lea r9, [rsp + USING_TEXT]
+    xor ecx, ecx            # rcx = digits copied
+    test r13, r13
+    jnz .Lusing_exp_copy
+    mov BYTE PTR [r9], '0'
+    inc r9
+.Lusing_exp_copy:
+    movzx eax, BYTE PTR [r8]
+    inc r8
+    cmp al, 'e'
+    je .Lusing_exp_copied
+    test al, al
+    jz .Lusing_exp_end      # inf or nan
+    cmp al, '.'
+    je .Lusing_exp_copy
+    cmp rcx, r13
+    jne .Lusing_exp_digit
+    mov BYTE PTR [r9], '.'
+    inc r9
+.Lusing_exp_digit:
+    mov BYTE PTR [r9], al
+    inc r9
+    inc rcx
+    jmp .Lusing_exp_copy
+.Lusing_exp_end:
+    dec r8
+.Lusing_exp_copied:
+    mov BYTE PTR [r9], 0
+
+    # The exponent, less the extra digits before the point (zero stays 0)
+    mov rcx, r8
+    xor edx, edx
+    mov r8d, 10
+    call strtol
+    movsxd rax, eax         # long is 32 bits on Windows
+    lea rcx, [r13 - 1]
+    sub rax, rcx
+    movsd xmm0, QWORD PTR [rsp + USING_VALUE]
+    xorpd xmm1, xmm1
+    ucomisd xmm0, xmm1
+    jne .Lusing_exp_text
+    xor eax, eax
+.Lusing_exp_text:
+    # sprintf(exponent, "E%+0*lld", digits + 1, exponent)
+    mov r9, rax
+    lea rcx, [rsp + USING_EXPONENT]
+    lea rdx, [rip + _using_exp_digits_fmt]
+    mov r8, QWORD PTR [r12 + USING_EXP]
+    inc r8
+    call sprintf
+    mov QWORD PTR [rsp + USING_EXP_LEN], rax
+
+.Lusing_number_text:
+    lea rcx, [rsp + USING_TEXT]
+    call strlen
+    mov r15, rax            # r15 = text length
+    lea rcx, [rsp + USING_TEXT]
+    mov edx, '.'
+    call strchr
+    mov r14, r15
+    test rax, rax
+    jz .Lusing_number_whole
+    lea rcx, [rsp + USING_TEXT]
+    sub rax, rcx
+    mov r14, rax            # r14 = digits before the point
+.Lusing_number_whole:
+    # A field with no digit positions before the point drops a lone 0
+    test r13, r13
+    lea r13, [rsp + USING_TEXT]     # r13 = text to write
+    jnz .Lusing_number_measure
+    cmp r14, 1
+    jne .Lusing_number_measure
+    cmp BYTE PTR [r13], '0'
+    jne .Lusing_number_measure
+    inc r13
+    dec r14
+    dec r15
+
+.Lusing_number_measure:
+    sub r15, r14
+    mov QWORD PTR [rsp + USING_TAIL_LEN], r15
+    add r15, r14
+    mov rcx, QWORD PTR [r12 + USING_FLAGS]
+    test ecx, USING_COMMA
+    jz .Lusing_measure_point
+    test r14, r14
+    jz .Lusing_measure_point
+    lea rax, [r14 - 1]
+    mov r8d, 3
+    xor edx, edx
+    div r8
+    add r15, rax            # commas
+.Lusing_measure_point:
+    cmp QWORD PTR [r12 + USING_FRAC], 0
+    jne .Lusing_measure_sign
+    inc r15                 # a point with no decimals after it
+.Lusing_measure_sign:
+    add r15, QWORD PTR [rsp + USING_EXP_LEN]
+    test ecx, USING_DOLLAR
+    jz .Lusing_measure_dollar
+    inc r15
+.Lusing_measure_dollar:
+    test ecx, USING_SIGNED
+    jnz .Lusing_measure_signed
+    cmp QWORD PTR [rsp + USING_NEG], 0
+    je .Lusing_number_pad
+.Lusing_measure_signed:
+    inc r15                 # r15 = characters to write
+
+.Lusing_number_pad:
+    # Right-align in the field, or mark a number too wide for it
+    mov rax, QWORD PTR [r12 + USING_WIDTH]
+    sub rax, r15
+    jae .Lusing_number_fill
+    mov rcx, rbx
+    mov edx, '%'
+    call _out_char
+    jmp .Lusing_number_sign
+.Lusing_number_fill:
+    mov r15, rax            # r15 = fill characters
+    mov rax, QWORD PTR [r12 + USING_FLAGS]
+    test eax, USING_STARS
+    jnz .Lusing_number_stars
+    mov rcx, rbx
+    mov rdx, r15
+    call _out_spaces
+    jmp .Lusing_number_sign
+.Lusing_number_stars:
+    test r15, r15
+    jz .Lusing_number_sign
+    mov rcx, rbx
+    mov edx, '*'
+    call _out_char
+    dec r15
+    jmp .Lusing_number_stars
+
+.Lusing_number_sign:
+    mov rcx, QWORD PTR [r12 + USING_FLAGS]
+    test ecx, USING_PLUS
+    jnz .Lusing_sign_char
+    test ecx, USING_TRAILING
+    jnz .Lusing_number_dollar
+    cmp QWORD PTR [rsp + USING_NEG], 0
+    je .Lusing_number_dollar
+.Lusing_sign_char:
+    mov edx, '+'
+    cmp QWORD PTR [rsp + USING_NEG], 0
+    je .Lusing_sign_write
+    mov edx, '-'
+.Lusing_sign_write:
+    mov rcx, rbx
+    call _out_char
+.Lusing_number_dollar:
+    mov rax, QWORD PTR [r12 + USING_FLAGS]
+    test eax, USING_DOLLAR
+    jz .Lusing_number_digits
+    mov rcx, rbx
+    mov edx, '$'
+    call _out_char
+
+.Lusing_number_digits:
+    xor r15d, r15d          # r15 = digits written
+.Lusing_digit_loop:
+    cmp r15, r14
+    jae .Lusing_number_tail
+    mov rcx, rbx
+    movzx edx, BYTE PTR [r13 + r15]
+    call _out_char
+    inc r15
+    mov rax, QWORD PTR [r12 + USING_FLAGS]
+    test eax, USING_COMMA
+    jz .Lusing_digit_loop
+    mov rax, r14
+    sub rax, r15            # digits still to come
+    jz .Lusing_number_tail
+    mov ecx, 3
+    xor edx, edx
+    div rcx
+    test rdx, rdx
+    jnz .Lusing_digit_loop
+    mov rcx, rbx
+    mov edx, ','
+    call _out_char
+    jmp .Lusing_digit_loop
+
+.Lusing_number_tail:
+    mov rcx, rbx
+    lea rdx, [r13 + r14]
+    mov r8, QWORD PTR [rsp + USING_TAIL_LEN]
+    call _out_write
+    cmp QWORD PTR [r12 + USING_FRAC], 0
+    jne .Lusing_number_exp
+    mov rcx, rbx
+    mov edx, '.'
+    call _out_char
+.Lusing_number_exp:
+    mov rcx, rbx
+    lea rdx, [rsp + USING_EXPONENT]
+    mov r8, QWORD PTR [rsp + USING_EXP_LEN]
+    call _out_write
+    mov rcx, QWORD PTR [r12 + USING_FLAGS]
+    test ecx, USING_TRAILING
+    jz .Lusing_number_done
+    mov edx, '-'
+    cmp QWORD PTR [rsp + USING_NEG], 0
+    jne .Lusing_trail_char
+    mov edx, '+'
+    test ecx, USING_TRAIL_PLUS
+    jnz .Lusing_trail_char
+    mov edx, ' '
+.Lusing_trail_char:
+    mov rcx, rbx
+    call _out_char
+
+.Lusing_number_done:
+    add rsp, USING_FRAME
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _using_string - Write a string through the next field (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+#   rdx = pointer to string data
+#   r8  = string length
+#
+# Returns: nothing
+# ------------------------------------------------------------------------------
+_using_string:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    push r14
+    sub rsp, 32             # Shadow space
+    mov rbx, rcx            # rbx = channel
+    mov r12, rdx            # r12 = string
+    mov r13, r8             # r13 = length
+    call _using_next
+    cmp eax, USING_STRING
+    je .Lusing_string_field
+    call _rt_type_mismatch
+.Lusing_string_field:
+    lea rax, [rip + _using_spec]
+    mov r14, QWORD PTR [rax + USING_DIGITS]     # r14 = characters
+    test r14, r14
+    jnz .Lusing_string_write
+    mov r14, r13            # & takes the whole string
+.Lusing_string_write:
+    mov r8, r13
+    cmp r8, r14
+    cmova r8, r14
+    sub r14, r8             # r14 = padding
+    mov rcx, rbx
+    mov rdx, r12
+    call _out_write
+    mov rcx, rbx
+    mov rdx, r14
+    call _out_spaces
+    add rsp, 32
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _using_next - Write the text before the next field and move past it
+# (internal)
+# ------------------------------------------------------------------------------
+# Starts the format over if there are no fields left.
+#
+# Arguments:
+#   rcx = channel
+#
+# Returns:
+#   rax = USING_NUMBER or USING_STRING, with _using_spec describing the field
+# ------------------------------------------------------------------------------
+_using_next:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx
+    call _using_text
+    mov rax, QWORD PTR [rip + _using_pos]
+    cmp rax, QWORD PTR [rip + _using_len]
+    jb .Lusing_next_field
+    mov QWORD PTR [rip + _using_pos], 0
+    mov rcx, rbx
+    call _using_text
+.Lusing_next_field:
+    mov rcx, QWORD PTR [rip + _using_pos]
+    call _using_field
+    lea rcx, [rip + _using_spec]
+    mov rcx, QWORD PTR [rcx + USING_WIDTH]
+    add rcx, QWORD PTR [rip + _using_pos]
+    mov QWORD PTR [rip + _using_pos], rcx
+    add rsp, 40
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _using_text - Write the format's text up to the next field (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = channel
+#
+# Returns: nothing. _using_pos is at the field, or the end of the format.
+# ------------------------------------------------------------------------------
+_using_text:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    push r12
+    push r13
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx            # rbx = channel
+    mov rcx, QWORD PTR [rip + _using_pos]
+    call _using_scan
+    mov r12, rax            # r12 = end of the text
+    mov r13, QWORD PTR [rip + _using_pos]   # r13 = position
+.Lusing_text_loop:
+    cmp r13, r12
+    jae .Lusing_text_done
+    mov rax, QWORD PTR [rip + _using_buf]
+    movzx edx, BYTE PTR [rax + r13]
+    cmp edx, '_'
+    jne .Lusing_text_char
+    lea rcx, [r13 + 1]
+    cmp rcx, r12
+    jae .Lusing_text_char
+    mov r13, rcx            # _ writes the next character as text
+    movzx edx, BYTE PTR [rax + r13]
+.Lusing_text_char:
+    mov rcx, rbx
+    call _out_char
+    inc r13
+    jmp .Lusing_text_loop
+.Lusing_text_done:
+    mov QWORD PTR [rip + _using_pos], r12
+    add rsp, 40
+    pop r13
+    pop r12
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _using_scan - Find the next field in the format (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = position to start at
+#
+# Returns:
+#   rax = position of the next field, or the format length if there is none
+# ------------------------------------------------------------------------------
+_using_scan:
+    push rbp
+    mov rbp, rsp
+    push rbx
+    sub rsp, 40             # Shadow space + alignment
+    mov rbx, rcx            # rbx = position
+.Lusing_scan_loop:
+    cmp rbx, QWORD PTR [rip + _using_len]
+    jae .Lusing_scan_done
+    mov rcx, rbx
+    call _using_field
+    test eax, eax
+    jnz .Lusing_scan_done
+    mov rax, QWORD PTR [rip + _using_buf]
+    cmp BYTE PTR [rax + rbx], '_'
+    jne .Lusing_scan_next
+    lea rcx, [rbx + 1]
+    cmp rcx, QWORD PTR [rip + _using_len]
+    jae .Lusing_scan_next
+    mov rbx, rcx            # skip the escaped character
+.Lusing_scan_next:
+    inc rbx
+    jmp .Lusing_scan_loop
+.Lusing_scan_done:
+    mov rax, rbx
+    add rsp, 40
+    pop rbx
+    leave
+    ret
+
+# ------------------------------------------------------------------------------
+# _using_field - Recognize a field in the format (internal)
+# ------------------------------------------------------------------------------
+# Arguments:
+#   rcx = position (within the format)
+#
+# Returns:
+#   rax = USING_NONE, USING_NUMBER or USING_STRING; for a field, _using_spec
+#         describes it
+# ------------------------------------------------------------------------------
+_using_field:
+    push rsi
+    push rdi
+    mov rdi, rcx            # rdi = field start
+    mov rsi, QWORD PTR [rip + _using_buf]
+    mov r8, QWORD PTR [rip + _using_len]
+    lea r11, [rip + _using_spec]
+    xor r9d, r9d            # r9 = flags
+    xor r10d, r10d          # r10 = digit positions before the point
+    movzx eax, BYTE PTR [rsi + rcx]
+    cmp al, '!'
+    je .Lfield_first_char
+    cmp al, '&'
+    je .Lfield_whole_string
+    cmp al, '\\'
+    je .Lfield_backslash
+    cmp al, '+'
+    jne .Lfield_lead
+    or r9d, USING_PLUS
+    inc rcx
+
+.Lfield_lead:
+    # "**", "**$" or "$$" before the digits
+    lea rax, [rcx + 1]
+    cmp rax, r8
+    jae .Lfield_digits
+    movzx eax, BYTE PTR [rsi + rcx]
+    cmp al, BYTE PTR [rsi + rcx + 1]
+    jne .Lfield_digits
+    cmp al, '$'
+    je .Lfield_dollars
+    cmp al, '*'
+    jne .Lfield_digits
+    or r9d, USING_STARS
+    add rcx, 2
+    mov r10d, 2
+    cmp rcx, r8
+    jae .Lfield_digits
+    cmp BYTE PTR [rsi + rcx], '$'
+    jne .Lfield_digits
+.Lfield_dollar:
+    or r9d, USING_DOLLAR
+    inc rcx
+    jmp .Lfield_digits
+.Lfield_dollars:
+    inc rcx                 # one $ is a digit position, the other the sign
+    inc r10
+    jmp .Lfield_dollar
+
+.Lfield_digits:
+    cmp rcx, r8
+    jae .Lfield_point
+    movzx eax, BYTE PTR [rsi + rcx]
+    cmp al, '#'
+    je .Lfield_digit
+    cmp al, ','
+    jne .Lfield_point
+    # A comma belongs to the field between digit positions and the point
+    test r10, r10
+    jz .Lfield_point
+    lea rdx, [rcx + 1]
+    cmp rdx, r8
+    jae .Lfield_point
+    movzx edx, BYTE PTR [rsi + rdx]
+    cmp dl, '#'
+    je .Lfield_comma
+    cmp dl, ','
+    je .Lfield_comma
+    cmp dl, '.'
+    jne .Lfield_point
+.Lfield_comma:
+    or r9d, USING_COMMA
+.Lfield_digit:
+    inc r10
+    inc rcx
+    jmp .Lfield_digits
+
+.Lfield_point:
+    mov rdx, -1             # rdx = digits after the point (-1 = no point)
+    cmp rcx, r8
+    jae .Lfield_number_end
+    cmp BYTE PTR [rsi + rcx], '.'
+    jne .Lfield_number_end
+    # Without digit positions before it, a point needs one after it
+    test r10, r10
+    jnz .Lfield_take_point
+    lea rax, [rcx + 1]
+    cmp rax, r8
+    jae .Lfield_number_end
+    cmp BYTE PTR [rsi + rax], '#'
+    jne .Lfield_number_end
+.Lfield_take_point:
+    inc rcx
+    xor edx, edx
+.Lfield_decimals:
+    cmp rcx, r8
+    jae .Lfield_number_end
+    cmp BYTE PTR [rsi + rcx], '#'
+    jne .Lfield_number_end
+    inc rdx
+    inc rcx
+    jmp .Lfield_decimals
+
+.Lfield_number_end:
+    # "+" or "." on its own is text
+    test r10, r10
+    jnz .Lfield_exponent
+    test rdx, rdx
+    jle .Lfield_none
+.Lfield_exponent:
+    mov QWORD PTR [r11 + USING_DIGITS], r10
+    mov QWORD PTR [r11 + USING_FRAC], rdx
+    xor edx, edx            # rdx = exponent digits
+    lea rax, [rcx + 4]
+    cmp rax, r8
+    ja .Lfield_sign
+    cmp DWORD PTR [rsi + rcx], 0x5E5E5E5E   # "^^^^"
+    jne .Lfield_sign
+    mov edx, 2
+    add rcx, 4
+    cmp rcx, r8
+    jae .Lfield_sign
+    cmp BYTE PTR [rsi + rcx], '^'
+    jne .Lfield_sign
+    mov edx, 3
+    inc rcx
+.Lfield_sign:
+    mov QWORD PTR [r11 + USING_EXP], rdx
+    test r9d, USING_PLUS
+    jnz .Lfield_number
+    cmp rcx, r8
+    jae .Lfield_number
+    movzx eax, BYTE PTR [rsi + rcx]
+    cmp al, '+'
+    jne .Lfield_minus
+    or r9d, USING_TRAIL_PLUS
+    inc rcx
+    jmp .Lfield_number
+.Lfield_minus:
+    cmp al, '-'
+    jne .Lfield_number
+    or r9d, USING_TRAIL_MINUS
+    inc rcx
+.Lfield_number:
+    mov QWORD PTR [r11 + USING_FLAGS], r9
+    sub rcx, rdi
+    mov QWORD PTR [r11 + USING_WIDTH], rcx
+    mov eax, USING_NUMBER
+    jmp .Lusing_field_done
+
+.Lfield_first_char:
+    mov eax, 1
+    jmp .Lfield_string
+.Lfield_whole_string:
+    xor eax, eax
+    jmp .Lfield_string
+.Lfield_backslash:
+    # "\", then spaces, then "\"
+    inc rcx
+    cmp rcx, r8
+    jae .Lfield_none
+    cmp BYTE PTR [rsi + rcx], ' '
+    je .Lfield_backslash
+    cmp BYTE PTR [rsi + rcx], '\\'
+    jne .Lfield_none
+    lea rax, [rcx + 1]
+    sub rax, rdi
+.Lfield_string:
+    mov QWORD PTR [r11 + USING_DIGITS], rax
+    test rax, rax
+    jnz .Lfield_string_width
+    inc rax
+.Lfield_string_width:
+    mov QWORD PTR [r11 + USING_WIDTH], rax
+    mov eax, USING_STRING
+    jmp .Lusing_field_done
+
+.Lfield_none:
+    xor eax, eax
+.Lusing_field_done:
+    pop rdi
+    pop rsi
+    ret
@@ -0,0 +1,1139 @@
+//! Semantic analysis - checks the AST before code generation
+//!
+//! The main program and each SUB/FUNCTION have their own scope. A procedure
+//! that uses a main-program variable or array must name it in a SHARED
+//! statement (or the main program must DIM SHARED it); otherwise the use is
+//! an error rather than a silently separate local.
+//!
+//! With OPTION EXPLICIT (or the --explicit flag), every variable must also be
+//! assigned or DIM'd before it is used. "Before" means earlier in the source
+//! text, as the program is read from top to bottom. Arrays must always be
+//! DIM'd before they are used, and a call must be to a built-in function or
+//! a FUNCTION the module defines or declares.
+//!
+//! Every expression is also typed (see types.rs): strings and numbers can't
+//! be mixed in operators, assignments, conditions or arguments. Errors point
+//! at the part of the statement that is wrong (the operator, the argument,
+//! the unknown name) when there is one, else at the statement, and name its
+//! procedure.
+//!
+//! Outside the modern dialect, statements that only xbasic64 has (ASM,
+//! DECLARE ... LIB, OPEN without a FOR mode, ON MOUSE) are errors.
+//!
+//! Line numbers must be unique within the main program and within each
+//! procedure, and every GOTO, GOSUB, ON ... GOTO and event handler must jump
+//! to a line in its own scope.
+//!
+//! An error ends the check of its statement, not of the program: every
+//! statement is checked and all the errors are returned, in source order.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::diagnostic::Diagnostic;
+use crate::fold;
+use crate::lexer::{Dialect, Span};
+use crate::parser::{
+    DataType, Expr, ExprKind, FileMode, GotoTarget, Param, PrintItem, Program, Stmt, StmtKind,
+};
+use crate::types::{self, TypeEnv, TypeError};
+use std::collections::{HashMap, HashSet};
+
+/// Checks a parsed program for errors the parser can't see
+#[derive(Default)]
+pub struct Checker {
+    explicit: bool,                         // OPTION EXPLICIT in effect
+    dialect: Dialect,                       // which extensions are allowed
+    globals: HashSet<String>,               // variables defined in the main program
+    global_arrays: HashSet<String>,         // arrays DIM'd in the main program
+    shared: Vec<Param>,                     // DIM SHARED names, visible in every procedure
+    defined: HashSet<String>,               // variables defined so far in the current scope
+    arrays: HashSet<String>,                // arrays DIM'd so far in the current scope
+    proc: Option<String>,                   // the SUB or FUNCTION being checked
+    procs: HashMap<String, Vec<Param>>,     // parameters of each SUB/FUNCTION
+    param_types: HashMap<String, DataType>, // declared types of the current parameters
+    span: Span,                             // the statement being checked, for error messages
+    lines: HashSet<u32>,                    // every line number in the program
+    scope_lines: HashSet<u32>,              // line numbers a jump can reach from here
+    errors: Vec<Diagnostic>,                // found so far
+}
+
+/// An error found checking a statement, and where in it, if that is more
+/// precise than the statement itself
+struct CheckError {
+    message: String,
+    span: Option<Span>,
+}
+
+impl From<String> for CheckError {
+    fn from(message: String) -> Self {
+        CheckError {
+            message,
+            span: None,
+        }
+    }
+}
+
+impl From<TypeError> for CheckError {
+    fn from(error: TypeError) -> Self {
+        CheckError {
+            message: error.message,
+            span: Some(error.span),
+        }
+    }
+}
+
+/// The xbasic64 extension a statement is, if it is one
+fn extension(kind: &StmtKind) -> Option<&'static str> {
+    match kind {
+        StmtKind::Asm { .. } => Some("ASM"),
+        StmtKind::Declare {
+            foreign: Some(_), ..
+        } => Some("DECLARE ... LIB"),
+        StmtKind::Open {
+            mode: FileMode::Both,
+            ..
+        } => Some("OPEN without FOR"),
+        StmtKind::OnMouse { .. } => Some("ON MOUSE"),
+        StmtKind::MouseTrap(_) => Some("MOUSE"),
+        StmtKind::Flush { .. } => Some("FLUSH"),
+        StmtKind::DictSet { value: Some(_), .. } => Some("DICTSET"),
+        StmtKind::DictSet { value: None, .. } => Some("DICTDEL"),
+        _ => None,
+    }
+}
+
+impl TypeEnv for Checker {
+    fn var_type(&self, name: &str) -> DataType {
+        self.param_types
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| DataType::of_name(name, self.dialect))
+    }
+
+    fn proc_params(&self, name: &str) -> Option<&[Param]> {
+        self.procs.get(name).map(Vec::as_slice)
+    }
+
+    fn is_array(&self, name: &str) -> bool {
+        self.arrays.contains(name)
+    }
+
+    fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+}
+
+impl Checker {
+    pub fn new(explicit: bool, dialect: Dialect) -> Self {
+        Checker {
+            explicit,
+            dialect,
+            ..Default::default()
+        }
+    }
+
+    pub fn check(&mut self, program: &Program) -> Result<(), Vec<Diagnostic>> {
+        // With the CONST names replaced by their values, as it is compiled
+        let mut program = program.clone();
+        fold::substitute_consts(&mut program).map_err(|e| vec![e])?;
+        self.check_program(&program);
+        let mut errors = std::mem::take(&mut self.errors);
+        if errors.is_empty() {
+            return Ok(());
+        }
+        errors.sort_by_key(|e| e.span.map(|span| (span.line, span.col)));
+        Err(errors)
+    }
+
+    /// Keep an error, at its expression or else the statement being checked
+    fn report(&mut self, result: Result<(), CheckError>) {
+        if let Err(error) = result {
+            let span = error.span.unwrap_or(self.span);
+            let error = Diagnostic::at(span, error.message).with_code("semantic-error");
+            self.errors.push(error);
+        }
+    }
+
+    fn check_program(&mut self, program: &Program) {
+        if program
+            .statements
+            .iter()
+            .any(|stmt| matches!(stmt.kind, StmtKind::OptionExplicit))
+        {
+            self.explicit = true;
+        }
+        for stmt in &program.statements {
+            match &stmt.kind {
+                StmtKind::Sub { name, params, .. } | StmtKind::Function { name, params, .. } => {
+                    self.procs.insert(name.clone(), params.clone());
+                }
+                // A procedure in another module (see modules.rs)
+                StmtKind::Declare { name, params, .. } => {
+                    self.procs
+                        .entry(name.clone())
+                        .or_insert_with(|| params.clone());
+                }
+                _ => {}
+            }
+        }
+        collect_lines(&program.statements, &mut self.lines);
+        self.collect_scope_lines(&program.statements);
+
+        // Main program first: procedures must know its names to reject them
+        let main = program
+            .statements
+            .iter()
+            .filter(|stmt| !matches!(stmt.kind, StmtKind::Sub { .. } | StmtKind::Function { .. }));
+        for stmt in main {
+            let result = self.check_stmt(stmt);
+            self.report(result);
+        }
+        self.globals = std::mem::take(&mut self.defined);
+        self.global_arrays = std::mem::take(&mut self.arrays);
+
+        for stmt in &program.statements {
+            self.span = stmt.span;
+            match &stmt.kind {
+                StmtKind::Sub { name, params, body } => self.check_proc(name, params, body, false),
+                StmtKind::Function { name, params, body } => {
+                    self.check_proc(name, params, body, true)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Check a SUB or FUNCTION body; a FUNCTION's name holds its result
+    fn check_proc(&mut self, name: &str, params: &[Param], body: &[Stmt], is_function: bool) {
+        self.proc = Some(name.to_string());
+        self.scope_lines.clear();
+        self.collect_scope_lines(body);
+        self.defined.clear();
+        self.arrays.clear();
+        self.param_types = params
+            .iter()
+            .filter(|param| !param.is_array)
+            .map(|param| (param.name.clone(), param.data_type))
+            .collect();
+        if is_function {
+            self.define(name);
+        }
+        let shared = std::mem::take(&mut self.shared);
+        self.declare(&shared);
+        self.shared = shared;
+        self.declare(params);
+        self.check_block(body)
+    }
+
+    /// Enter parameters or SHARED names into the current scope
+    fn declare(&mut self, names: &[Param]) {
+        for param in names {
+            if param.is_array {
+                self.arrays.insert(param.name.clone());
+            } else {
+                self.define(&param.name);
+            }
+        }
+    }
+
+    fn check_block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            let result = self.check_stmt(stmt);
+            self.report(result);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) -> Result<(), CheckError> {
+        self.span = stmt.span;
+        if let Some(extension) = extension(&stmt.kind).filter(|_| self.dialect != Dialect::Modern) {
+            return Err(format!(
+                "{} is not part of {} (it needs --dialect modern)",
+                extension,
+                self.dialect.name()
+            )
+            .into());
+        }
+        match &stmt.kind {
+            StmtKind::Let {
+                name,
+                indices,
+                value,
+            } => {
+                let found = self.check_expr(value)?;
+                let wanted = match indices {
+                    Some(indices) => {
+                        self.check_array(name)?;
+                        self.check_numbers(indices, "an array subscript")?;
+                        DataType::of_name(name, self.dialect)
+                    }
+                    None => {
+                        self.define(name);
+                        self.var_type(name)
+                    }
+                };
+                let what = format!("assignment to {}", name);
+                self.check_assignable(wanted, found, value, &what)?;
+            }
+            // The assembly may read or write the variables it names
+            StmtKind::Asm { vars, .. } => {
+                for var in vars {
+                    if let ExprKind::Variable(name) = &var.kind {
+                        self.define(name);
+                    }
+                }
+            }
+            StmtKind::Print { items, .. } | StmtKind::PrintFile { items, .. } => {
+                for item in items {
+                    match item {
+                        // TAB(n) and SPC(n) move the cursor
+                        PrintItem::Expr(Expr {
+                            kind: ExprKind::FnCall { name, args },
+                            ..
+                        }) if (name == "TAB" || name == "SPC") && args.len() == 1 => {
+                            self.check_number(&args[0], name)?;
+                        }
+                        PrintItem::Expr(expr) => {
+                            self.check_expr(expr)?;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            StmtKind::PrintUsing { format, items, .. } => {
+                self.check_string(format, "PRINT USING")?;
+                for item in items {
+                    self.check_expr(item)?;
+                }
+            }
+            StmtKind::Write { items, .. } => {
+                for item in items {
+                    self.check_expr(item)?;
+                }
+            }
+            StmtKind::Input { vars, .. }
+            | StmtKind::Read(vars)
+            | StmtKind::InputFile { vars, .. } => {
+                for var in vars {
+                    self.check_target(var)?;
+                }
+            }
+            StmtKind::LineInput { var, .. } => {
+                let found = self.check_target(var)?;
+                self.check_assignable(DataType::String, found, var, "LINE INPUT")?;
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let result = self.check_number(condition, "an IF condition");
+                self.report(result);
+                self.check_block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.check_block(else_branch);
+                }
+            }
+            StmtKind::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let result = self.check_numbers([start, end].into_iter().chain(step), "FOR");
+                self.report(result);
+                self.define(var);
+                let found = self.var_type(var);
+                let what = format!("FOR {}", var);
+                let result = self.typed(types::check_assignable(DataType::Double, found, &what));
+                self.report(result);
+                self.check_block(body);
+            }
+            StmtKind::While { condition, body } => {
+                let result = self.check_number(condition, "a WHILE condition");
+                self.report(result);
+                self.check_block(body);
+            }
+            StmtKind::DoLoop {
+                condition,
+                cond_at_start,
+                body,
+                ..
+            } => {
+                if *cond_at_start {
+                    let result = self.check_numbers(condition, "a DO condition");
+                    self.report(result);
+                    self.check_block(body);
+                } else {
+                    self.check_block(body);
+                    self.span = stmt.span;
+                    let result = self.check_numbers(condition, "a LOOP condition");
+                    self.report(result);
+                }
+            }
+            StmtKind::Goto(target) => self.check_jump("GOTO", target)?,
+            StmtKind::Gosub(target) => self.check_jump("GOSUB", target)?,
+            StmtKind::OnGoto { expr, targets } => {
+                self.check_number(expr, "ON ... GOTO")?;
+                for target in targets {
+                    let result = self.check_jump("ON ... GOTO", target);
+                    self.report(result);
+                }
+            }
+            StmtKind::Dim { arrays, shared } => {
+                if *shared && self.proc.is_some() {
+                    return Err("DIM SHARED is only allowed in the main program"
+                        .to_string()
+                        .into());
+                }
+                for array in arrays {
+                    let result = self.check_numbers(&array.dimensions, "DIM");
+                    self.report(result);
+                    let is_array = !array.dimensions.is_empty();
+                    if is_array {
+                        self.arrays.insert(array.name.clone());
+                    } else {
+                        self.define(&array.name);
+                    }
+                    if *shared {
+                        self.shared.push(Param {
+                            name: array.name.clone(),
+                            data_type: DataType::of_name(&array.name, self.dialect),
+                            is_array,
+                        });
+                    }
+                }
+            }
+            StmtKind::Declare { .. } if self.proc.is_some() => {
+                return Err("DECLARE is only allowed outside a SUB or FUNCTION"
+                    .to_string()
+                    .into());
+            }
+            StmtKind::Declare {
+                name,
+                params,
+                foreign: Some(_),
+                ..
+            } if params.iter().any(|param| param.is_array) => {
+                return Err(format!("{}: arrays can't be passed to a LIB procedure", name).into());
+            }
+            // A type suffix must suit the value
+            StmtKind::Const(consts) => {
+                for (name, value) in consts {
+                    let found = self.check_expr(value)?;
+                    if name.ends_with(['%', '&', '!', '#', '$']) {
+                        let what = format!("CONST {}", name);
+                        let wanted = DataType::from_suffix(name);
+                        self.check_assignable(wanted, found, value, &what)?;
+                    }
+                }
+            }
+            StmtKind::Shared(names) => {
+                if self.proc.is_none() {
+                    return Err("SHARED is only allowed in a SUB or FUNCTION"
+                        .to_string()
+                        .into());
+                }
+                self.declare(names);
+            }
+            StmtKind::Call { name, args } => {
+                for arg in args {
+                    self.check_names(arg)?;
+                }
+                if let Some(params) = self.procs.get(name) {
+                    self.typed(types::check_call_args(self, stmt.span, name, params, args))?;
+                }
+            }
+            StmtKind::Width { width, .. } => self.check_number(width, "WIDTH")?,
+            StmtKind::SelectCase { expr, cases } => {
+                let wanted = self.check_expr(expr);
+                for (value, body) in cases {
+                    self.span = stmt.span;
+                    if let (Some(value), Ok(wanted)) = (value, &wanted) {
+                        let result = self
+                            .check_expr(value)
+                            .and_then(|found| self.check_assignable(*wanted, found, value, "CASE"));
+                        self.report(result);
+                    }
+                    self.check_block(body);
+                }
+                self.span = stmt.span;
+                wanted?;
+            }
+            StmtKind::Open { filename, .. } => self.check_string(filename, "OPEN")?,
+            StmtKind::Screen { mode } => self.check_number(mode, "SCREEN")?,
+            StmtKind::Palette {
+                entry: Some((attribute, color)),
+            } => self.check_numbers([attribute, color], "PALETTE")?,
+            StmtKind::Pset { x, y, color, .. } => {
+                self.check_numbers([x, y].into_iter().chain(color), "PSET")?;
+            }
+            StmtKind::GraphicsLine {
+                from, to, color, ..
+            } => {
+                if let Some((x, y)) = from {
+                    self.check_numbers([x, y], "LINE")?;
+                }
+                self.check_numbers([&to.0, &to.1].into_iter().chain(color), "LINE")?;
+            }
+            StmtKind::Circle {
+                x,
+                y,
+                radius,
+                color,
+            } => {
+                self.check_numbers([x, y, radius].into_iter().chain(color), "CIRCLE")?;
+            }
+            StmtKind::Paint {
+                x,
+                y,
+                color,
+                border,
+            } => {
+                self.check_numbers([x, y].into_iter().chain(color).chain(border), "PAINT")?;
+            }
+            StmtKind::Draw { commands } => self.check_string(commands, "DRAW")?,
+            StmtKind::GetImage {
+                from, to, indices, ..
+            } => {
+                self.check_numbers([&from.0, &from.1, &to.0, &to.1], "GET")?;
+                self.check_numbers(indices, "an array subscript")?;
+            }
+            StmtKind::PutImage { at, indices, .. } => {
+                self.check_numbers([&at.0, &at.1], "PUT")?;
+                self.check_numbers(indices, "an array subscript")?;
+            }
+            StmtKind::DefSeg { segment } => self.check_numbers(segment, "DEF SEG")?,
+            StmtKind::Poke { address, value } => self.check_numbers([address, value], "POKE")?,
+            StmtKind::Bsave {
+                filename,
+                offset,
+                length,
+            } => {
+                self.check_string(filename, "BSAVE")?;
+                self.check_numbers([offset, length], "BSAVE")?;
+            }
+            StmtKind::Bload { filename, offset } => {
+                self.check_string(filename, "BLOAD")?;
+                self.check_numbers(offset, "BLOAD")?;
+            }
+            StmtKind::Seek { position, .. } => self.check_number(position, "SEEK")?,
+            StmtKind::Lock {
+                first,
+                last,
+                unlock,
+                ..
+            } => {
+                let name = if *unlock { "UNLOCK" } else { "LOCK" };
+                self.check_numbers(first.iter().chain(last), name)?;
+            }
+            StmtKind::DictSet { dict, key, value } => {
+                let name = if value.is_some() {
+                    "DICTSET"
+                } else {
+                    "DICTDEL"
+                };
+                self.check_number(dict, name)?;
+                self.check_string(key, name)?;
+                if let Some(value) = value {
+                    self.check_string(value, name)?;
+                }
+            }
+            StmtKind::OnKey { key, target } => {
+                self.check_number(key, "KEY")?;
+                self.check_jump("ON KEY ... GOSUB", target)?;
+            }
+            StmtKind::KeyTrap { key, .. } => self.check_number(key, "KEY")?,
+            StmtKind::OnTimer { interval, target } => {
+                self.check_number(interval, "ON TIMER")?;
+                self.check_jump("ON TIMER ... GOSUB", target)?;
+            }
+            StmtKind::OnBreak { target } => self.check_jump("ON BREAK GOSUB", target)?,
+            StmtKind::OnMouse { target } => self.check_jump("ON MOUSE GOSUB", target)?,
+            StmtKind::Restore(Some(target)) => match target {
+                GotoTarget::Line(n) if !self.lines.contains(n) => {
+                    self.typed(Err(format!("RESTORE {}: undefined line number", n)))?
+                }
+                GotoTarget::Line(_) => {}
+                GotoTarget::Label(name) => {
+                    self.typed(Err(format!("RESTORE {}: needs a line number", name)))?
+                }
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check that a jump goes to a line in the current scope
+    fn check_jump(&self, verb: &str, target: &GotoTarget) -> Result<(), CheckError> {
+        let err = match target {
+            GotoTarget::Line(n) if self.scope_lines.contains(n) => return Ok(()),
+            GotoTarget::Line(n) if self.lines.contains(n) => match &self.proc {
+                Some(proc) => format!("{} {}: line {} is outside {}", verb, n, n, proc),
+                None => format!("{} {}: line {} is inside a SUB or FUNCTION", verb, n, n),
+            },
+            GotoTarget::Line(n) => format!("{} {}: undefined line number", verb, n),
+            GotoTarget::Label(name) => format!("{} {}: undefined label", verb, name),
+        };
+        self.typed(Err(err))
+    }
+
+    /// Check an expression's names and find its type
+    fn check_expr(&self, expr: &Expr) -> Result<DataType, CheckError> {
+        self.check_names(expr)?;
+        self.typed(types::infer(self, expr))
+    }
+
+    /// Check an expression that `what` needs to be a number
+    fn check_number(&self, expr: &Expr, what: &str) -> Result<(), CheckError> {
+        let found = self.check_expr(expr)?;
+        self.check_assignable(DataType::Double, found, expr, what)
+    }
+
+    fn check_numbers<'a>(
+        &self,
+        exprs: impl IntoIterator<Item = &'a Expr>,
+        what: &str,
+    ) -> Result<(), CheckError> {
+        for expr in exprs {
+            self.check_number(expr, what)?;
+        }
+        Ok(())
+    }
+
+    /// Check an expression that `what` needs to be a string
+    fn check_string(&self, expr: &Expr, what: &str) -> Result<(), CheckError> {
+        let found = self.check_expr(expr)?;
+        self.check_assignable(DataType::String, found, expr, what)
+    }
+
+    /// Check that `expr`, of type `found`, can be used where `wanted` is
+    fn check_assignable(
+        &self,
+        wanted: DataType,
+        found: DataType,
+        expr: &Expr,
+        what: &str,
+    ) -> Result<(), CheckError> {
+        let result = types::check_assignable(wanted, found, what);
+        self.typed(result.map_err(types::error_at(expr.span)))
+    }
+
+    /// Name the procedure a type error is in, if any
+    fn typed<T, E: Into<CheckError>>(&self, result: Result<T, E>) -> Result<T, CheckError> {
+        result.map_err(|err| {
+            let mut err = err.into();
+            if let Some(proc) = &self.proc {
+                err.message = format!("In {}: {}", proc, err.message);
+            }
+            err
+        })
+    }
+
+    /// Check that every variable and array an expression names is in scope
+    fn check_names(&self, expr: &Expr) -> Result<(), CheckError> {
+        let here = types::error_at(expr.span);
+        match &expr.kind {
+            ExprKind::Literal(_) => Ok(()),
+            ExprKind::Variable(name) => {
+                // A bare array name is an argument, as in UBOUND(A)
+                if self.defined.contains(name) || self.arrays.contains(name) {
+                    Ok(())
+                } else if self.is_global(name) {
+                    Err(here(self.not_shared("Variable", name)).into())
+                } else if self.explicit {
+                    Err(here(format!(
+                        "Variable {} used before it is assigned (OPTION EXPLICIT)",
+                        name
+                    ))
+                    .into())
+                } else {
+                    Ok(())
+                }
+            }
+            ExprKind::ArrayAccess { name, indices } => {
+                self.check_array(name).map_err(here)?;
+                indices.iter().try_for_each(|index| self.check_names(index))
+            }
+            ExprKind::FnCall { name, args } => {
+                // A procedure's A(I) parses as a call when the DIM comes later
+                if self.global_arrays.contains(name) {
+                    self.check_array(name).map_err(here)?;
+                } else if types::builtin_signature(name).is_none()
+                    && !self.procs.contains_key(name)
+                    && !self.arrays.contains(name)
+                {
+                    return Err(here(format!(
+                        "{} is not a FUNCTION, nor an array DIM'd before this",
+                        name
+                    ))
+                    .into());
+                }
+                args.iter().try_for_each(|arg| self.check_names(arg))
+            }
+            ExprKind::Unary { operand, .. } => self.check_names(operand),
+            ExprKind::Binary { left, right, .. } => {
+                self.check_names(left)?;
+                self.check_names(right)
+            }
+        }
+    }
+
+    /// Check a variable or array element that INPUT or READ assigns,
+    /// returning its type
+    fn check_target(&mut self, target: &Expr) -> Result<DataType, CheckError> {
+        let here = types::error_at(target.span);
+        match &target.kind {
+            ExprKind::ArrayAccess { name, indices } => {
+                self.check_array(name).map_err(here)?;
+                self.check_numbers(indices, "an array subscript")?;
+                Ok(DataType::of_name(name, self.dialect))
+            }
+            ExprKind::Variable(name) => {
+                self.define(name);
+                Ok(self.var_type(name))
+            }
+            _ => Err(here("Expected a variable or array element".to_string()).into()),
+        }
+    }
+
+    fn check_array(&self, name: &str) -> Result<(), String> {
+        if self.arrays.contains(name) {
+            Ok(())
+        } else if self.is_global(name) {
+            Err(self.not_shared("Array", name))
+        } else {
+            Err(format!("Array {} used without DIM", name))
+        }
+    }
+
+    /// A main-program name seen from inside a procedure
+    fn is_global(&self, name: &str) -> bool {
+        self.proc.is_some() && (self.globals.contains(name) || self.global_arrays.contains(name))
+    }
+
+    fn not_shared(&self, kind: &str, name: &str) -> String {
+        let parens = if kind == "Array" { "()" } else { "" };
+        format!(
+            "{} {} belongs to the main program; add SHARED {}{} to {} to use it there",
+            kind,
+            name,
+            name,
+            parens,
+            self.proc.as_deref().unwrap_or_default()
+        )
+    }
+
+    fn define(&mut self, name: &str) {
+        self.defined.insert(name.to_string());
+    }
+
+    /// Gather the line numbers a jump can reach from a block: its own and
+    /// those nested in it, but not those of SUB and FUNCTION bodies
+    fn collect_scope_lines(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match &stmt.kind {
+                StmtKind::Label(n) if !self.scope_lines.insert(*n) => {
+                    self.span = stmt.span;
+                    let result = self.typed(Err(format!("Duplicate line number {}", n)));
+                    self.report(result);
+                }
+                StmtKind::Sub { .. } | StmtKind::Function { .. } => continue,
+                _ => {}
+            }
+            for body in stmt.kind.bodies() {
+                self.collect_scope_lines(body);
+            }
+        }
+    }
+}
+
+/// Gather the line numbers of a block and everything nested in it
+fn collect_lines(stmts: &[Stmt], lines: &mut HashSet<u32>) {
+    for stmt in stmts {
+        if let StmtKind::Label(n) = stmt.kind {
+            lines.insert(n);
+        }
+        for body in stmt.kind.bodies() {
+            collect_lines(body, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// The first error a check finds
+    fn check(source: &str, explicit: bool) -> Result<(), String> {
+        check_with_spans(source, explicit).map_err(|errors| errors[0].message.clone())
+    }
+
+    /// The line and column the first (non-explicit) check error points at
+    fn error_at(source: &str) -> (u32, u32) {
+        let span = check_with_spans(source, false).unwrap_err()[0]
+            .span
+            .unwrap();
+        (span.line, span.col)
+    }
+
+    fn check_with_spans(source: &str, explicit: bool) -> Result<(), Vec<Diagnostic>> {
+        let program = Parser::new(Lexer::new(source)).parse().unwrap();
+        Checker::new(explicit, Dialect::Modern).check(&program)
+    }
+
+    // ===================
+    // OPTION EXPLICIT Tests
+    // ===================
+
+    #[test]
+    fn test_implicit_by_default() {
+        assert!(check("PRINT X", false).is_ok());
+    }
+
+    #[test]
+    fn test_explicit_requires_assignment() {
+        assert!(check("OPTION EXPLICIT\nPRINT X", false).is_err());
+        assert!(check("PRINT X", true).is_err());
+        assert!(check("OPTION EXPLICIT\nX = 1\nPRINT X", false).is_ok());
+        // The assigned value is checked before the variable is defined
+        assert!(check("OPTION EXPLICIT\nX = X + 1", false).is_err());
+    }
+
+    #[test]
+    fn test_explicit_definitions() {
+        let source = "OPTION EXPLICIT
+INPUT A
+READ B$
+FOR I = 1 TO A: PRINT I: NEXT I
+DIM C(10)
+C(1) = A + I
+PRINT B$; C(1)
+DATA x";
+        assert!(check(source, false).is_ok());
+        assert!(check("OPTION EXPLICIT\nC(1) = 5", false).is_err());
+    }
+
+    #[test]
+    fn test_undefined_functions_and_arrays() {
+        let err = check("PRINT 1\nPRINT FOO(1)", false).unwrap_err();
+        assert!(err.contains("FOO is not a FUNCTION"), "{}", err);
+        assert_eq!(error_at("PRINT 1\nPRINT FOO$(2)"), (2, 7));
+        let err = check("A(1) = 5", false).unwrap_err();
+        assert!(err.contains("Array A used without DIM"), "{}", err);
+        assert!(check("PRINT A(1)\nDIM A(3)", false).is_err());
+        assert!(
+            check(
+                "DECLARE FUNCTION FOO(X)\nDIM A$(3)\nPRINT FOO(1); A$(2)",
+                false
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_explicit_procedures() {
+        let source = "OPTION EXPLICIT
+G = 1
+SUB S(P)
+    SHARED G
+    L = P + G
+    PRINT L
+END SUB
+FUNCTION F(P)
+    F = P * 2
+END FUNCTION";
+        assert!(check(source, false).is_ok());
+        let source = "OPTION EXPLICIT
+DIM A(5)
+CALL Total(A(), UBOUND(A))
+SUB Total(V(), N)
+    V(0) = N
+END SUB";
+        assert!(check(source, false).is_ok());
+        let source = "OPTION EXPLICIT
+SUB S(P)
+    PRINT Q
+END SUB";
+        assert!(check(source, false).is_err());
+        // A SUB's variables are local to it
+        let source = "OPTION EXPLICIT
+SUB S
+    L = 1
+END SUB
+PRINT L";
+        assert!(check(source, false).is_err());
+    }
+
+    // ===================
+    // Scope Tests
+    // ===================
+
+    #[test]
+    fn test_main_names_need_shared() {
+        let source = "X = 1
+DIM A(5)
+SUB S
+    PRINT X
+END SUB";
+        let err = check(source, false).unwrap_err();
+        assert!(err.contains("SHARED"), "{}", err);
+        let source = "DIM A(5)
+SUB S
+    A(1) = 2
+END SUB";
+        assert!(check(source, false).is_err());
+        let source = "X = 1
+DIM A(5)
+SUB S
+    SHARED X, A()
+    A(1) = X
+END SUB";
+        assert!(check(source, false).is_ok());
+        let source = "DIM SHARED X, A(5)
+SUB S
+    A(1) = X
+END SUB";
+        assert!(check(source, false).is_ok());
+    }
+
+    #[test]
+    fn test_locals_shadow_main() {
+        // Assigning a name first makes it the procedure's own
+        let source = "I = 5
+SUB S
+    FOR I = 1 TO 3
+        PRINT I
+    NEXT I
+END SUB
+FUNCTION F(I)
+    F = I
+END FUNCTION";
+        assert!(check(source, false).is_ok());
+    }
+
+    #[test]
+    fn test_const() {
+        // A main-program CONST is seen in procedures without SHARED
+        let source = "OPTION EXPLICIT
+CONST N = 3, T$ = \"a\"
+DIM A(N * 2)
+SUB S
+    CONST L% = N + 0.5
+    PRINT L%; T$
+END SUB";
+        assert!(check(source, false).is_ok());
+        for (source, message) in [
+            ("CONST N = 1\nN = 2", "Duplicate definition"),
+            ("CONST N = 1\nSUB S(N)\nEND SUB", "Duplicate definition"),
+            ("CONST N = 1, N = 2", "already defined"),
+            ("CONST N = X", "constant value"),
+            ("CONST N% = 40000", "Overflow"),
+            ("CONST T$ = 5", "Type mismatch"),
+        ] {
+            let err = check(source, false).unwrap_err();
+            assert!(err.contains(message), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn test_shared_placement() {
+        assert!(check("SHARED X", false).is_err());
+        assert!(
+            check(
+                "SUB S
+    DIM SHARED X
+END SUB",
+                false
+            )
+            .is_err()
+        );
+    }
+
+    // ===================
+    // Type Tests
+    // ===================
+
+    #[test]
+    fn test_string_number_mixing() {
+        assert!(check("A$ = \"a\" + \"b\"\nIF A$ < \"c\" THEN PRINT A$", false).is_ok());
+        assert!(check("X = 1 + 2.5 * 3 \\ 2", false).is_ok());
+        let err = check("PRINT \"abc\" * 2", false).unwrap_err();
+        assert!(err.contains("Type mismatch"), "{}", err);
+        assert!(check("PRINT \"a\" + 1", false).is_err());
+        assert!(check("PRINT \"a\" - \"b\"", false).is_err());
+        assert!(check("X = -\"a\"", false).is_err());
+        assert!(check("A$ = 5", false).is_err());
+        assert!(check("X = \"5\"", false).is_err());
+        assert!(check("IF \"a\" THEN PRINT 1", false).is_err());
+        assert!(check("FOR A$ = 1 TO 2: NEXT A$", false).is_err());
+    }
+
+    #[test]
+    fn test_argument_types() {
+        assert!(
+            check(
+                "PRINT LEN(\"ab\"); MID$(\"abc\", 2); INSTR(\"ab\", \"b\")",
+                false
+            )
+            .is_ok()
+        );
+        assert!(check("PRINT LEN(5)", false).is_err());
+        assert!(check("PRINT CHR$(\"A\")", false).is_err());
+        assert!(check("PRINT LEFT$(\"abc\")", false).is_err());
+        let source = "SUB S(N AS STRING, X)
+    PRINT N; X
+END SUB
+FUNCTION F$(N)
+    F$ = STR$(N)
+END FUNCTION";
+        assert!(check(&format!("{}\nCALL S(\"a\", 1)\nPRINT F$(2)", source), false).is_ok());
+        assert!(check(&format!("{}\nCALL S(5, 1)", source), false).is_err());
+        assert!(check(&format!("{}\nCALL S(\"a\")", source), false).is_err());
+        assert!(check(&format!("{}\nPRINT F$(\"x\")", source), false).is_err());
+        assert!(check(&format!("{}\nX = F$(1)", source), false).is_err());
+    }
+
+    #[test]
+    fn test_restore_targets() {
+        assert!(check("10 DATA 1\n20 DATA 2\nRESTORE 20", false).is_ok());
+        let err = check("10 DATA 1\nRESTORE 30", false).unwrap_err();
+        assert!(err.contains("RESTORE 30"), "{}", err);
+        assert!(check("RESTORE Here", false).is_err());
+    }
+
+    // ===================
+    // Jump Target Tests
+    // ===================
+
+    #[test]
+    fn test_jump_targets() {
+        assert!(check("10 GOSUB 30\n20 END\n30 RETURN", false).is_ok());
+        assert!(check("10 ON X GOTO 10, 20\n20 END", false).is_ok());
+        let err = check("10 PRINT 1\n20 GOTO 50", false).unwrap_err();
+        assert_eq!(err, "GOTO 50: undefined line number");
+        let err = check("10 ON X GOTO 10, 30\n20 END", false).unwrap_err();
+        assert!(err.contains("ON ... GOTO 30"), "{}", err);
+        let err = check("10 IF X THEN 40", false).unwrap_err();
+        assert!(err.contains("GOTO 40"), "{}", err);
+        let err = check("ON TIMER(1) GOSUB 100", false).unwrap_err();
+        assert!(err.contains("GOSUB 100"), "{}", err);
+        let err = check("GOTO Done", false).unwrap_err();
+        assert!(err.contains("undefined label"), "{}", err);
+    }
+
+    #[test]
+    fn test_all_errors_reported() {
+        // Each bad statement is reported, in source order, procedures included
+        let source = "10 GOTO 99\n20 GOSUB 30\nSUB S\n10 PRINT\n10 GOTO 40\nEND SUB\n\
+                      IF X THEN\nA$ = 1\nEND IF\nON X GOTO 10, 50, 60";
+        let errors = check_with_spans(source, false).unwrap_err();
+        let found: Vec<(u32, &str)> = errors
+            .iter()
+            .map(|e| (e.span.unwrap().line, e.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, "GOTO 99: undefined line number"),
+                (2, "GOSUB 30: undefined line number"),
+                (5, "In S: Duplicate line number 10"),
+                (5, "In S: GOTO 40: undefined line number"),
+                (8, "Type mismatch: assignment to A$ needs a string"),
+                (10, "ON ... GOTO 50: undefined line number"),
+                (10, "ON ... GOTO 60: undefined line number"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jumps_stay_in_scope() {
+        // Each procedure has its own line numbers
+        let source = "10 GOTO 20\n20 END\nSUB S\n10 GOTO 20\n20 PRINT\nEND SUB";
+        assert!(check(source, false).is_ok());
+        let err = check("10 END\nSUB S\nGOTO 10\nEND SUB", false).unwrap_err();
+        assert_eq!(err, "In S: GOTO 10: line 10 is outside S");
+        let err = check("GOSUB 10\nSUB S\n10 PRINT\nEND SUB", false).unwrap_err();
+        assert!(err.contains("inside a SUB or FUNCTION"), "{}", err);
+    }
+
+    #[test]
+    fn test_duplicate_line_numbers() {
+        let err = check("10 PRINT 1\n20 PRINT 2\n10 PRINT 3", false).unwrap_err();
+        assert_eq!(err, "Duplicate line number 10");
+        assert_eq!(error_at("10 PRINT 1\n20 PRINT 2\n10 PRINT 3"), (3, 1));
+        let err = check(
+            "SUB S\n10 PRINT\nIF X THEN\n10 PRINT\nEND IF\nEND SUB",
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(err, "In S: Duplicate line number 10");
+    }
+
+    #[test]
+    fn test_type_error_location() {
+        // A value of the wrong type is reported at the value
+        assert_eq!(error_at("10 X = 1\n20 A$ = X"), (2, 9));
+        let err = check("SUB S\n    PRINT 1 + \"a\"\nEND SUB", false).unwrap_err();
+        assert!(err.starts_with("In S:"), "{}", err);
+        // A mismatched operator at the operator, however deep it is
+        assert_eq!(error_at("SUB S\n    PRINT 1 + \"a\"\nEND SUB"), (2, 13));
+        assert_eq!(error_at("PRINT 1 + (2 * \"a\")"), (1, 14));
+        assert_eq!(error_at("X = -\"a\""), (1, 5));
+        // A bad argument at the argument, a wrong count at the call
+        assert_eq!(error_at("PRINT 1; LEFT$(1, 2)"), (1, 16));
+        assert_eq!(error_at("X = MID$(\"a\")"), (1, 5));
+        assert_eq!(error_at("SUB S (A$)\nEND SUB\nCALL S(1)"), (3, 8));
+        assert_eq!(error_at("SUB S (A$)\nEND SUB\nCALL S"), (3, 1));
+        assert_eq!(error_at("DIM A(5)\nPRINT A(\"x\")"), (2, 9));
+        // A post-test condition is reported at the condition, not the DO
+        assert_eq!(error_at("DO\n  PRINT 1\nLOOP UNTIL \"a\""), (3, 12));
+        assert_eq!(error_at("IF \"a\" THEN PRINT 1"), (1, 4));
+        assert_eq!(error_at("10 PRINT 1\n20 GOTO 50"), (2, 4));
+    }
+
+    #[test]
+    fn test_name_error_location() {
+        assert_eq!(error_at("PRINT 1 + FOO(2)"), (1, 11));
+        assert_eq!(error_at("PRINT 1, A(2)\nDIM A(3)"), (1, 10));
+        assert_eq!(error_at("OPTION EXPLICIT\nX = 1\nPRINT X + Y"), (3, 11));
+        assert_eq!(error_at("X = 1\nSUB S\nPRINT 2 * X\nEND SUB"), (3, 11));
+    }
+
+    // ===================
+    // Dialect Tests
+    // ===================
+
+    #[test]
+    fn test_extensions_need_modern() {
+        let in_dialect = |source: &str, dialect| {
+            let program = Parser::new(Lexer::new(source)).parse().unwrap();
+            Checker::new(false, dialect)
+                .check(&program)
+                .map_err(|errors| errors[0].message.clone())
+        };
+        let declare = "DECLARE FUNCTION Abs% LIB \"c\" (BYVAL N%)";
+        assert!(in_dialect(declare, Dialect::Modern).is_ok());
+        let err = in_dialect(declare, Dialect::Qb45).unwrap_err();
+        assert_eq!(
+            err,
+            "DECLARE ... LIB is not part of QuickBASIC 4.5 (it needs --dialect modern)"
+        );
+        let err = in_dialect("ASM\nnop\nEND ASM", Dialect::Gw).unwrap_err();
+        assert!(err.starts_with("ASM is not part of GW-BASIC"), "{}", err);
+        let err = in_dialect("OPEN \"TCP:h:1\" AS #1", Dialect::Qb45).unwrap_err();
+        assert!(err.starts_with("OPEN without FOR"), "{}", err);
+        assert!(in_dialect("OPEN \"f\" FOR INPUT AS #1", Dialect::Qb45).is_ok());
+        let err = in_dialect("ON MOUSE GOSUB 10\n10 RETURN", Dialect::Qb45).unwrap_err();
+        assert!(err.starts_with("ON MOUSE is not part of"), "{}", err);
+        let err = in_dialect("FLUSH #1", Dialect::Gw).unwrap_err();
+        assert!(err.starts_with("FLUSH is not part of GW-BASIC"), "{}", err);
+        let err = in_dialect("DICTSET D, \"k\", \"v\"", Dialect::Qb45).unwrap_err();
+        assert!(err.starts_with("DICTSET is not part of"), "{}", err);
+        let err = in_dialect("DICTDEL D, \"k\"", Dialect::Gw).unwrap_err();
+        assert!(err.starts_with("DICTDEL is not part of"), "{}", err);
+    }
+}
@@ -0,0 +1,236 @@
+//! Spec tests - `xbasic64 test`
+//!
+//! A program states the output it should print in comments, one line per
+//! `'EXPECT:` comment, and optionally what to type at it, one line per
+//! `'STDIN:` comment:
+//!
+//! ```text
+//! INPUT "Name"; N$
+//! PRINT "Hello, "; N$
+//! 'STDIN: Ada
+//! 'EXPECT: Name? Hello, Ada
+//! ```
+//!
+//! The text starts after the colon and one space. A comment at the end of
+//! a statement counts too. The program is compiled, run with the input, and
+//! passes if it exits cleanly having printed the expected lines; trailing
+//! blanks and blank lines at the end are ignored.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::compiler::{Compiler, Output};
+use crate::diagnostic::Diagnostics;
+use crate::lexer::Lexer;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// What a program should print, and the input to give it
+#[derive(Debug, Default, PartialEq)]
+pub struct Spec {
+    pub expect: Vec<String>,
+    pub stdin: String,
+}
+
+/// How a spec test went
+#[derive(Debug)]
+pub enum Outcome {
+    Pass,
+    /// The program ran but didn't do what the spec says: why
+    Fail(String),
+    /// The program didn't compile
+    Error(Diagnostics),
+}
+
+/// The spec in a program's comments, or None if it has no `'EXPECT:`
+pub fn parse(text: &str) -> Option<Spec> {
+    let mut lexer = Lexer::new(text);
+    // Comments up to a lexer error still count; compiling reports it
+    for _ in lexer.by_ref() {}
+    let mut spec = Spec::default();
+    let mut expects = false;
+    for comment in lexer.comments() {
+        let Some(body) = comment.text.strip_prefix('\'') else {
+            continue;
+        };
+        let body = body.trim_start();
+        let value = |rest: &str| rest.strip_prefix(' ').unwrap_or(rest).to_string();
+        if let Some(rest) = body.strip_prefix("EXPECT:") {
+            spec.expect.push(value(rest));
+            expects = true;
+        } else if let Some(rest) = body.strip_prefix("STDIN:") {
+            spec.stdin.push_str(&value(rest));
+            spec.stdin.push('\n');
+        }
+    }
+    expects.then_some(spec)
+}
+
+/// Compile the program at `path`, run it with the spec's input and compare
+/// what it prints. It runs in its own directory, and is stopped if it takes
+/// longer than `timeout`.
+pub fn run(compiler: &mut Compiler, path: &str, spec: &Spec, timeout: Duration) -> Outcome {
+    let built = (|| {
+        let sources = [compiler.read(path)?];
+        let programs = vec![compiler.parse(&sources[0])?];
+        compiler.check(&sources, &programs, true)?;
+        let modules = compiler.generate(programs);
+        let asms = compiler.emit(&sources, &modules);
+        let stem = Path::new(path).file_stem().unwrap_or_default();
+        let name = format!(
+            "xbasic64-test-{}-{}",
+            std::process::id(),
+            stem.to_string_lossy()
+        );
+        let exe = std::env::temp_dir().join(name);
+        let exe = compiler.output_file(&exe.to_string_lossy(), Output::Executable);
+        compiler.build(&sources, asms, Output::Executable, &exe)?;
+        Ok(exe)
+    })();
+    let exe = match built {
+        Ok(exe) => exe,
+        Err(e) => return Outcome::Error(e),
+    };
+    let outcome = run_exe(&exe, path, spec, timeout);
+    let _ = std::fs::remove_file(&exe);
+    outcome
+}
+
+fn run_exe(exe: &str, path: &str, spec: &Spec, timeout: Duration) -> Outcome {
+    let dir = Path::new(path)
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty());
+    let mut command = Command::new(exe);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return Outcome::Fail(format!("Can't run the program: {}", e)),
+    };
+
+    // Feed and drain the pipes on their own threads so a program that
+    // prints a lot (or ignores its input) can't block the wait
+    let mut stdin = child.stdin.take().expect("piped");
+    let input = spec.stdin.clone();
+    std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let drain = |mut pipe: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut bytes = Vec::new();
+            let _ = pipe.read_to_end(&mut bytes);
+            String::from_utf8_lossy(&bytes).replace("\r\n", "\n")
+        })
+    };
+    let stdout = drain(Box::new(child.stdout.take().expect("piped")));
+    let stderr = drain(Box::new(child.stderr.take().expect("piped")));
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() < timeout => {
+                std::thread::sleep(Duration::from_millis(10))
+            }
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Outcome::Fail(format!("Still running after {:?}", timeout));
+            }
+            Err(e) => return Outcome::Fail(format!("Can't wait for the program: {}", e)),
+        }
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    if !status.success() {
+        let why = stderr.trim_end();
+        return Outcome::Fail(format!("Exited with {}: {}", status, why));
+    }
+    match compare(&spec.expect, &stdout) {
+        Some(why) => Outcome::Fail(why),
+        None => Outcome::Pass,
+    }
+}
+
+/// Why the output isn't the expected lines, if it isn't
+fn compare(expect: &[String], output: &str) -> Option<String> {
+    let expected = trimmed(expect.iter().map(String::as_str));
+    let got = trimmed(output.lines());
+    let n = expected
+        .iter()
+        .zip(&got)
+        .take_while(|(a, b)| a == b)
+        .count();
+    match (expected.get(n), got.get(n)) {
+        (None, None) => None,
+        (Some(want), Some(have)) => Some(format!(
+            "Line {}: expected {:?}, got {:?}",
+            n + 1,
+            want,
+            have
+        )),
+        (Some(want), None) => Some(format!(
+            "Line {}: expected {:?}, but the output ended",
+            n + 1,
+            want
+        )),
+        (None, Some(have)) => Some(format!("Line {}: unexpected {:?}", n + 1, have)),
+    }
+}
+
+/// Lines without trailing blanks, or blank lines at the end
+fn trimmed<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut lines: Vec<&str> = lines.map(str::trim_end).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ===================
+    // Spec Tests
+    // ===================
+
+    #[test]
+    fn test_parse_spec() {
+        let text = "INPUT N\nPRINT N * 2 'EXPECT: ? 42\n'STDIN: 21\n' EXPECT:\nREM EXPECT: no\n";
+        assert_eq!(
+            parse(text),
+            Some(Spec {
+                expect: vec!["? 42".into(), "".into()],
+                stdin: "21\n".into(),
+            })
+        );
+        // Only in comments, and only with an EXPECT
+        assert_eq!(parse("PRINT \"'EXPECT: 1\"\n"), None);
+        assert_eq!(parse("'STDIN: 1\n"), None);
+    }
+
+    #[test]
+    fn test_compare() {
+        let expect = |lines: &[&str]| lines.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(compare(&expect(&[" 1", "a"]), " 1 \na\n\n"), None);
+        assert_eq!(
+            compare(&expect(&[" 1", "b"]), " 1 \na\n"),
+            Some("Line 2: expected \"b\", got \"a\"".into())
+        );
+        assert_eq!(
+            compare(&expect(&["a", "b"]), "a\n"),
+            Some("Line 2: expected \"b\", but the output ended".into())
+        );
+        assert_eq!(
+            compare(&expect(&["a"]), "a\nb\n"),
+            Some("Line 2: unexpected \"b\"".into())
+        );
+    }
+}
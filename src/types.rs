@@ -0,0 +1,394 @@
+//! Expression typing - the type rules shared by the checker and code generator
+//!
+//! Every expression has a static type, found bottom-up from literals,
+//! variable suffixes (or a parameter's AS type), built-in function
+//! signatures and operator rules. The checker runs `infer` over the whole
+//! program to reject mismatches such as `"abc" * 2`; code generation uses the
+//! same rules to pick numeric or string code for each operation.
+//!
+//! A type error points at the expression it is about: the operator for a
+//! mismatched operation, the argument for a bad argument, the call for a
+//! wrong number of them.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::lexer::{Dialect, Span};
+use crate::parser::{BinaryOp, DataType, Expr, ExprKind, Literal, Param, UnaryOp};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// What a built-in function accepts in one argument position
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    Num,
+    Str,
+    Array, // an array name, as in UBOUND(A)
+    Var,   // a variable or array element (VARPTR, VARSEG)
+}
+
+/// Signature of a built-in function
+pub struct Builtin {
+    pub args: &'static [ArgKind],
+    pub required: usize, // arguments past this one are optional
+    pub returns: DataType,
+}
+
+const fn builtin(args: &'static [ArgKind], required: usize, returns: DataType) -> Builtin {
+    Builtin {
+        args,
+        required,
+        returns,
+    }
+}
+
+use ArgKind::{Array, Num, Str, Var};
+
+/// Built-in functions by name. INSTR and INSTRREV also accept
+/// `INSTR(hay$, needle$)`.
+static BUILTINS: LazyLock<HashMap<&'static str, Builtin>> = LazyLock::new(|| {
+    let math = || builtin(&[Num], 1, DataType::Double);
+    HashMap::from([
+        ("SIN", math()),
+        ("COS", math()),
+        ("TAN", math()),
+        ("ATN", math()),
+        ("EXP", math()),
+        ("LOG", math()),
+        ("LOG10", math()),
+        ("SQR", math()),
+        ("INT", math()),
+        ("FIX", math()),
+        ("ABS", math()),
+        ("SGN", math()),
+        ("CSNG", math()),
+        ("CDBL", math()),
+        ("ROUND", builtin(&[Num, Num], 1, DataType::Double)),
+        ("MIN", builtin(&[Num, Num], 2, DataType::Double)),
+        ("MAX", builtin(&[Num, Num], 2, DataType::Double)),
+        ("RND", builtin(&[Num], 0, DataType::Double)),
+        ("TIMER", builtin(&[], 0, DataType::Double)),
+        ("VAL", builtin(&[Str], 1, DataType::Double)),
+        ("LEN", builtin(&[Str], 1, DataType::Long)),
+        ("ASC", builtin(&[Str, Num], 1, DataType::Long)),
+        ("CINT", builtin(&[Num], 1, DataType::Long)),
+        ("CLNG", builtin(&[Num], 1, DataType::Long)),
+        ("PEEK", builtin(&[Num], 1, DataType::Long)),
+        ("SHL", builtin(&[Num, Num], 2, DataType::Double)),
+        ("SHR", builtin(&[Num, Num], 2, DataType::Double)),
+        ("POINT", builtin(&[Num, Num], 2, DataType::Long)),
+        ("SCREEN", builtin(&[Num, Num], 2, DataType::Long)),
+        ("RGB", builtin(&[Num, Num, Num], 3, DataType::Long)),
+        ("MOUSEX", builtin(&[], 0, DataType::Long)),
+        ("MOUSEY", builtin(&[], 0, DataType::Long)),
+        ("MOUSEB", builtin(&[], 0, DataType::Long)),
+        ("STICK", builtin(&[Num], 1, DataType::Long)),
+        ("STRIG", builtin(&[Num], 1, DataType::Long)),
+        ("SEEK", builtin(&[Num], 1, DataType::Long)),
+        ("DICTNEW", builtin(&[], 0, DataType::Long)),
+        ("DICTHAS", builtin(&[Num, Str], 2, DataType::Long)),
+        ("DICTCOUNT", builtin(&[Num], 1, DataType::Long)),
+        ("INSTR", builtin(&[Num, Str, Str], 2, DataType::Long)),
+        ("INSTRREV", builtin(&[Num, Str, Str], 2, DataType::Long)),
+        ("LBOUND", builtin(&[Array, Num], 1, DataType::Long)),
+        ("UBOUND", builtin(&[Array, Num], 1, DataType::Long)),
+        ("VARPTR", builtin(&[Var], 1, DataType::Long)),
+        ("VARSEG", builtin(&[Var], 1, DataType::Long)),
+        ("CHR$", builtin(&[Num], 1, DataType::String)),
+        ("STR$", builtin(&[Num], 1, DataType::String)),
+        ("LEFT$", builtin(&[Str, Num], 2, DataType::String)),
+        ("RIGHT$", builtin(&[Str, Num], 2, DataType::String)),
+        ("MID$", builtin(&[Str, Num, Num], 2, DataType::String)),
+        ("DICTGET$", builtin(&[Num, Str], 2, DataType::String)),
+        ("DICTKEY$", builtin(&[Num, Num], 2, DataType::String)),
+    ])
+});
+
+/// The signature of a built-in function, if `name` is one
+pub fn builtin_signature(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.get(name)
+}
+
+/// Whether a built-in keeps a SINGLE argument's type, as QBasic's do
+pub fn keeps_single(name: &str) -> bool {
+    matches!(name, "ABS" | "SGN" | "INT" | "FIX" | "SQR")
+}
+
+/// A type error and the expression it was found at
+#[derive(Debug)]
+pub struct TypeError {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Turn a message into a type error at `span`
+pub fn error_at(span: Span) -> impl FnOnce(String) -> TypeError {
+    move |message| TypeError { span, message }
+}
+
+/// What the type rules need to know about the scope an expression is in
+pub trait TypeEnv {
+    /// Type of a scalar variable: a parameter's declared type, else its name's
+    fn var_type(&self, name: &str) -> DataType;
+    /// Parameters of a SUB or FUNCTION, if `name` is one
+    fn proc_params(&self, name: &str) -> Option<&[Param]>;
+    /// Whether `name` is an array DIM'd (or passed in) in this scope
+    fn is_array(&self, name: &str) -> bool;
+    /// The dialect, which gives unsuffixed names their type
+    fn dialect(&self) -> Dialect;
+}
+
+/// Type of a literal: integer literals are Long, larger ones Double constants,
+/// and a type suffix gives its own type
+pub fn literal_type(lit: &Literal) -> DataType {
+    match lit {
+        Literal::Integer(n) if i32::try_from(*n).is_err() => DataType::Double,
+        Literal::Integer(_) => DataType::Long,
+        Literal::Float(_) => DataType::Double,
+        Literal::Typed(_, data_type) => *data_type,
+        Literal::String(_) => DataType::String,
+    }
+}
+
+/// Find the type of an expression, or the first type error in it
+pub fn infer(env: &impl TypeEnv, expr: &Expr) -> Result<DataType, TypeError> {
+    match &expr.kind {
+        ExprKind::Literal(lit) => Ok(literal_type(lit)),
+        ExprKind::Variable(name) => Ok(env.var_type(name)),
+        ExprKind::ArrayAccess { name, indices } => {
+            check_indices(env, name, indices)?;
+            Ok(DataType::of_name(name, env.dialect()))
+        }
+        ExprKind::FnCall { name, args } => {
+            if let Some(builtin) = BUILTINS.get(name.as_str()) {
+                check_builtin_args(env, expr.span, name, builtin, args)?;
+                if keeps_single(name) && infer(env, &args[0])? == DataType::Single {
+                    return Ok(DataType::Single);
+                }
+                Ok(builtin.returns)
+            } else if let Some(params) = env.proc_params(name) {
+                check_call_args(env, expr.span, name, params, args)?;
+                Ok(DataType::of_name(name, env.dialect()))
+            } else {
+                // An array, possibly used before (or without) its DIM
+                check_indices(env, name, args)?;
+                Ok(DataType::of_name(name, env.dialect()))
+            }
+        }
+        ExprKind::Unary { op, operand } => {
+            let operand_type = infer(env, operand)?;
+            match op {
+                _ if operand_type == DataType::String => Err(error_at(expr.span)(format!(
+                    "Type mismatch: {} needs a number, not a string",
+                    unary_symbol(*op)
+                ))),
+                UnaryOp::Neg => Ok(operand_type),
+                UnaryOp::Not => Ok(DataType::Long),
+            }
+        }
+        ExprKind::Binary { op, left, right } => {
+            let left = infer(env, left)?;
+            let right = infer(env, right)?;
+            binary_type(*op, left, right).map_err(error_at(expr.span))
+        }
+    }
+}
+
+/// Result type of a binary operator, rejecting strings where they don't belong
+pub fn binary_type(op: BinaryOp, left: DataType, right: DataType) -> Result<DataType, String> {
+    let strings = (left == DataType::String) as u8 + (right == DataType::String) as u8;
+    match strings {
+        0 => Ok(promote(op, left, right)),
+        2 if op == BinaryOp::Add || is_comparison(op) => Ok(promote(op, left, right)),
+        2 => Err(format!(
+            "Type mismatch: {} needs numbers, not strings",
+            binary_symbol(op)
+        )),
+        _ => Err(format!(
+            "Type mismatch: {} between a string and a number",
+            binary_symbol(op)
+        )),
+    }
+}
+
+/// Result type of a binary operator on operands of compatible types
+pub fn promote(op: BinaryOp, left: DataType, right: DataType) -> DataType {
+    match op {
+        // Comparisons give 0 or -1, as a Long
+        _ if is_comparison(op) => DataType::Long,
+        // Division (/) and power (^) are done in Double, per GW-BASIC
+        BinaryOp::Div | BinaryOp::Pow => DataType::Double,
+        // Integer division, MOD and logical operators work on integers
+        BinaryOp::IntDiv | BinaryOp::Mod => DataType::Long,
+        // Short-circuit operators give -1 or 0, like comparisons
+        BinaryOp::AndAlso | BinaryOp::OrElse => DataType::Long,
+        BinaryOp::And | BinaryOp::Or | BinaryOp::Xor
+            if !(left.is_integer() && right.is_integer()) =>
+        {
+            DataType::Long
+        }
+        _ => widest(left, right),
+    }
+}
+
+/// The wider of two types: Integer < Long < Single < Double (String + String)
+pub fn widest(left: DataType, right: DataType) -> DataType {
+    match (left, right) {
+        (DataType::String, _) | (_, DataType::String) => DataType::String,
+        (DataType::Double, _) | (_, DataType::Double) => DataType::Double,
+        (DataType::Single, _) | (_, DataType::Single) => DataType::Single,
+        (DataType::Long, _) | (_, DataType::Long) => DataType::Long,
+        _ => DataType::Integer,
+    }
+}
+
+pub fn is_comparison(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge
+    )
+}
+
+/// Check that a value of type `found` can be used where `wanted` is expected
+/// (any number converts to any other number; strings only match strings)
+pub fn check_assignable(wanted: DataType, found: DataType, what: &str) -> Result<(), String> {
+    match (wanted == DataType::String, found == DataType::String) {
+        (true, false) => Err(format!("Type mismatch: {} needs a string", what)),
+        (false, true) => Err(format!("Type mismatch: {} needs a number", what)),
+        _ => Ok(()),
+    }
+}
+
+/// Check that an expression is numeric
+pub fn check_number(env: &impl TypeEnv, expr: &Expr, what: &str) -> Result<(), TypeError> {
+    check_assignable(DataType::Double, infer(env, expr)?, what).map_err(error_at(expr.span))
+}
+
+fn check_indices(env: &impl TypeEnv, name: &str, indices: &[Expr]) -> Result<(), TypeError> {
+    for index in indices {
+        check_number(env, index, &format!("subscript of {}", name))?;
+    }
+    Ok(())
+}
+
+fn check_builtin_args(
+    env: &impl TypeEnv,
+    span: Span,
+    name: &str,
+    builtin: &Builtin,
+    args: &[Expr],
+) -> Result<(), TypeError> {
+    if args.len() < builtin.required || args.len() > builtin.args.len() {
+        return Err(error_at(span)(format!(
+            "Wrong number of arguments to {}",
+            name
+        )));
+    }
+    // INSTR's start position is optional but comes first
+    let skip = builtin.args.len() - args.len();
+    let kinds = match name {
+        "INSTR" | "INSTRREV" => &builtin.args[skip..],
+        _ => &builtin.args[..args.len()],
+    };
+    for (i, (arg, kind)) in args.iter().zip(kinds).enumerate() {
+        let what = format!("argument {} of {}", i + 1, name);
+        match kind {
+            Num => check_number(env, arg, &what)?,
+            Str => check_assignable(DataType::String, infer(env, arg)?, &what)
+                .map_err(error_at(arg.span))?,
+            Array => array_name(arg)
+                .ok_or_else(|| format!("{} needs an array name", what))
+                .and_then(|array| check_is_array(env, array))
+                .map_err(error_at(arg.span))?,
+            Var => {
+                array_name(arg).ok_or_else(|| {
+                    error_at(arg.span)(format!("{} needs a variable or array element", what))
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check the arguments of a SUB or FUNCTION call (at `span`) against its
+/// parameters
+pub fn check_call_args(
+    env: &impl TypeEnv,
+    span: Span,
+    name: &str,
+    params: &[Param],
+    args: &[Expr],
+) -> Result<(), TypeError> {
+    if args.len() != params.len() {
+        return Err(error_at(span)(format!(
+            "{} takes {} argument(s), got {}",
+            name,
+            params.len(),
+            args.len()
+        )));
+    }
+    for (param, arg) in params.iter().zip(args) {
+        let what = format!("argument {} of {}", param.name, name);
+        let found = if param.is_array {
+            // The caller's array is passed by name
+            array_name(arg)
+                .ok_or_else(|| format!("{} needs an array name", what))
+                .and_then(|array| check_is_array(env, array).map(|()| array))
+                .map(|array| DataType::of_name(array, env.dialect()))
+        } else {
+            Ok(infer(env, arg)?)
+        };
+        found
+            .and_then(|found| check_assignable(param.data_type, found, &what))
+            .map_err(error_at(arg.span))?;
+    }
+    Ok(())
+}
+
+fn check_is_array(env: &impl TypeEnv, name: &str) -> Result<(), String> {
+    if env.is_array(name) {
+        Ok(())
+    } else {
+        Err(format!("{} is not an array", name))
+    }
+}
+
+/// The name in `A`, `A()` or `A(I)`, the forms an array (or variable) argument takes
+pub fn array_name(expr: &Expr) -> Option<&str> {
+    match &expr.kind {
+        ExprKind::Variable(name)
+        | ExprKind::ArrayAccess { name, .. }
+        | ExprKind::FnCall { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+fn unary_symbol(op: UnaryOp) -> &'static str {
+    match op {
+        UnaryOp::Neg => "-",
+        UnaryOp::Not => "NOT",
+    }
+}
+
+fn binary_symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::IntDiv => "\\",
+        BinaryOp::Mod => "MOD",
+        BinaryOp::Pow => "^",
+        BinaryOp::Eq => "=",
+        BinaryOp::Ne => "<>",
+        BinaryOp::Lt => "<",
+        BinaryOp::Gt => ">",
+        BinaryOp::Le => "<=",
+        BinaryOp::Ge => ">=",
+        BinaryOp::And => "AND",
+        BinaryOp::Or => "OR",
+        BinaryOp::Xor => "XOR",
+        BinaryOp::AndAlso => "ANDALSO",
+        BinaryOp::OrElse => "ORELSE",
+    }
+}
@@ -0,0 +1,486 @@
+//! Compiler warnings - legal code that is probably a mistake
+//!
+//! Run after the checker, on a program known to be valid. Four things are
+//! flagged:
+//!
+//! - A variable that is assigned with LET (or `X = ...`) but never read
+//!   anywhere in the program. A FUNCTION's result variable is exempt.
+//! - Statements that can't be reached: those after END, STOP, GOTO or
+//!   RETURN in the same block, up to the next line number that something
+//!   jumps to. DATA and SUB/FUNCTION definitions there are fine.
+//! - A GOTO, GOSUB or ON ... GOTO from outside a FOR loop to a line inside
+//!   it, which runs NEXT with whatever end and step the loop last had.
+//!   Jumping out of a loop is fine.
+//! - An expression with binary operators nested more than 256 deep. Each
+//!   level holds its left operand on the stack while the right is computed.
+//!
+//! Mismatched NEXT variables are errors, reported by the parser.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{BinaryOp, Expr, ExprKind, GotoTarget, PrintItem, Program, Stmt, StmtKind};
+use std::collections::{HashMap, HashSet};
+
+/// Binary operator nesting past which an expression is flagged
+const MAX_EXPR_DEPTH: u32 = 256;
+
+/// Find the warnings for a program, in source order
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut reads = HashSet::new();
+    collect_reads(&program.statements, &mut reads);
+
+    let mut warnings = Vec::new();
+    unused_assignments(&program.statements, None, &reads, &mut warnings);
+    let mut targets = HashSet::new();
+    collect_targets(&program.statements, &mut targets);
+    unreachable_code(&program.statements, &targets, &mut warnings);
+    jumps_into_loops(&program.statements, &mut warnings);
+    deep_expressions(&program.statements, &mut warnings);
+
+    for stmt in &program.statements {
+        if let StmtKind::Sub { name, body, .. } | StmtKind::Function { name, body, .. } = &stmt.kind
+        {
+            let result = matches!(stmt.kind, StmtKind::Function { .. }).then_some(name.as_str());
+            unused_assignments(body, result, &reads, &mut warnings);
+            let mut targets = HashSet::new();
+            collect_targets(body, &mut targets);
+            unreachable_code(body, &targets, &mut warnings);
+            jumps_into_loops(body, &mut warnings);
+            deep_expressions(body, &mut warnings);
+        }
+    }
+
+    warnings.sort_by_key(|w| w.span.map(|span| (span.line, span.col)));
+    warnings
+}
+
+/// Warn once for each variable a scope assigns that nothing ever reads
+fn unused_assignments(
+    stmts: &[Stmt],
+    result: Option<&str>,
+    reads: &HashSet<String>,
+    warnings: &mut Vec<Diagnostic>,
+) {
+    let mut first = HashMap::new();
+    collect_assignments(stmts, &mut first);
+    for (name, stmt) in first {
+        if !reads.contains(name) && Some(name) != result {
+            let message = format!("Variable {} is assigned but never used", name);
+            warnings.push(
+                Diagnostic::at(stmt.span, message)
+                    .with_code("unused-variable")
+                    .as_warning(),
+            );
+        }
+    }
+}
+
+/// The first LET of each scalar variable in a scope
+fn collect_assignments<'a>(stmts: &'a [Stmt], first: &mut HashMap<&'a str, &'a Stmt>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Let {
+                name,
+                indices: None,
+                ..
+            } => {
+                first.entry(name.as_str()).or_insert(stmt);
+            }
+            StmtKind::Sub { .. } | StmtKind::Function { .. } => continue,
+            _ => {}
+        }
+        for body in stmt.kind.bodies() {
+            collect_assignments(body, first);
+        }
+    }
+}
+
+/// Warn at the first statement of each unreachable stretch of a block
+fn unreachable_code(stmts: &[Stmt], targets: &HashSet<u32>, warnings: &mut Vec<Diagnostic>) {
+    let mut reachable = true;
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Label(n) if targets.contains(n) => reachable = true,
+            StmtKind::Label(_)
+            | StmtKind::Data(_)
+            | StmtKind::Sub { .. }
+            | StmtKind::Function { .. } => continue,
+            _ if !reachable => {
+                let message = "Unreachable code".to_string();
+                warnings.push(
+                    Diagnostic::at(stmt.span, message)
+                        .with_code("unreachable-code")
+                        .as_warning(),
+                );
+                // One warning per stretch is enough
+                reachable = true;
+            }
+            StmtKind::End | StmtKind::Stop | StmtKind::Goto(_) | StmtKind::Return => {
+                reachable = false
+            }
+            _ => {}
+        }
+        for body in stmt.kind.bodies() {
+            unreachable_code(body, targets, warnings);
+        }
+    }
+}
+
+/// Warn at each jump in a scope to a line inside a FOR loop it isn't in
+fn jumps_into_loops(stmts: &[Stmt], warnings: &mut Vec<Diagnostic>) {
+    let mut jumps = Jumps::default();
+    jumps.collect(stmts, &mut Vec::new());
+    for (stmt, keyword, target, from) in &jumps.jumps {
+        let into = jumps.lines.get(target).map_or(&[][..], Vec::as_slice);
+        if into.iter().any(|l| !from.contains(l)) {
+            let message = format!("{} {} jumps into a FOR loop", keyword, target);
+            warnings.push(
+                Diagnostic::at(stmt.span, message)
+                    .with_code("jump-into-loop")
+                    .as_warning(),
+            );
+        }
+    }
+}
+
+/// The FOR loops (numbered in source order) around each line number and
+/// each jump in a scope
+#[derive(Default)]
+struct Jumps<'a> {
+    loops: usize,
+    lines: HashMap<u32, Vec<usize>>,
+    jumps: Vec<(&'a Stmt, &'static str, u32, Vec<usize>)>,
+}
+
+impl<'a> Jumps<'a> {
+    fn collect(&mut self, stmts: &'a [Stmt], around: &mut Vec<usize>) {
+        for stmt in stmts {
+            let (keyword, targets) = match &stmt.kind {
+                StmtKind::Label(n) => {
+                    self.lines.insert(*n, around.clone());
+                    continue;
+                }
+                StmtKind::Goto(target) => ("GOTO", std::slice::from_ref(target)),
+                StmtKind::Gosub(target)
+                | StmtKind::OnKey { target, .. }
+                | StmtKind::OnTimer { target, .. }
+                | StmtKind::OnBreak { target }
+                | StmtKind::OnMouse { target } => ("GOSUB", std::slice::from_ref(target)),
+                StmtKind::OnGoto { targets, .. } => ("GOTO", targets.as_slice()),
+                StmtKind::Sub { .. } | StmtKind::Function { .. } => continue,
+                _ => ("", &[][..]),
+            };
+            for target in targets {
+                if let GotoTarget::Line(n) = target {
+                    self.jumps.push((stmt, keyword, *n, around.clone()));
+                }
+            }
+            let is_for = matches!(stmt.kind, StmtKind::For { .. });
+            if is_for {
+                around.push(self.loops);
+                self.loops += 1;
+            }
+            for body in stmt.kind.bodies() {
+                self.collect(body, around);
+            }
+            if is_for {
+                around.pop();
+            }
+        }
+    }
+}
+
+/// Warn at each statement in a scope with an expression nested too deeply
+fn deep_expressions(stmts: &[Stmt], warnings: &mut Vec<Diagnostic>) {
+    for stmt in stmts {
+        if let StmtKind::Sub { .. } | StmtKind::Function { .. } = stmt.kind {
+            continue;
+        }
+        let depth = stmt_reads(&stmt.kind).into_iter().map(expr_depth).max();
+        if depth.unwrap_or(0) > MAX_EXPR_DEPTH {
+            let message = format!(
+                "Expression nesting exceeds {} levels, stack overflow risk",
+                MAX_EXPR_DEPTH
+            );
+            warnings.push(
+                Diagnostic::at(stmt.span, message)
+                    .with_code("deep-expression")
+                    .as_warning(),
+            );
+        }
+        for body in stmt.kind.bodies() {
+            deep_expressions(body, warnings);
+        }
+    }
+}
+
+/// How deeply binary operators nest in an expression. ANDALSO and ORELSE
+/// branch instead of stacking their left operand, so they don't count.
+fn expr_depth(expr: &Expr) -> u32 {
+    match &expr.kind {
+        ExprKind::Literal(_) | ExprKind::Variable(_) => 0,
+        ExprKind::ArrayAccess { indices: args, .. } | ExprKind::FnCall { args, .. } => {
+            args.iter().map(expr_depth).max().unwrap_or(0)
+        }
+        ExprKind::Unary { operand, .. } => expr_depth(operand),
+        ExprKind::Binary { op, left, right } => {
+            let depth = expr_depth(left).max(expr_depth(right));
+            match op {
+                BinaryOp::AndAlso | BinaryOp::OrElse => depth,
+                _ => depth + 1,
+            }
+        }
+    }
+}
+
+/// Line numbers something in a scope jumps to
+fn collect_targets(stmts: &[Stmt], targets: &mut HashSet<u32>) {
+    for stmt in stmts {
+        let jumps = match &stmt.kind {
+            StmtKind::Goto(target)
+            | StmtKind::Gosub(target)
+            | StmtKind::OnKey { target, .. }
+            | StmtKind::OnTimer { target, .. }
+            | StmtKind::OnBreak { target }
+            | StmtKind::OnMouse { target } => std::slice::from_ref(target),
+            StmtKind::OnGoto { targets, .. } => targets.as_slice(),
+            StmtKind::Sub { .. } | StmtKind::Function { .. } => continue,
+            _ => &[],
+        };
+        for jump in jumps {
+            if let GotoTarget::Line(n) = jump {
+                targets.insert(*n);
+            }
+        }
+        for body in stmt.kind.bodies() {
+            collect_targets(body, targets);
+        }
+    }
+}
+
+/// Gather the name of every variable the program reads, in any scope
+fn collect_reads(stmts: &[Stmt], reads: &mut HashSet<String>) {
+    for stmt in stmts {
+        for expr in stmt_reads(&stmt.kind) {
+            expr_reads(expr, reads);
+        }
+        for body in stmt.kind.bodies() {
+            collect_reads(body, reads);
+        }
+    }
+}
+
+/// The subscripts of INPUT or READ targets
+fn targets(vars: &[Expr]) -> Vec<&Expr> {
+    vars.iter()
+        .flat_map(|var| match &var.kind {
+            ExprKind::ArrayAccess { indices, .. } => indices.iter().collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+fn items(items: &[PrintItem]) -> Vec<&Expr> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            PrintItem::Expr(expr) => Some(expr),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The expressions a statement reads, not counting nested blocks. INPUT
+/// and READ targets are written, so only their subscripts count.
+pub fn stmt_reads(kind: &StmtKind) -> Vec<&Expr> {
+    match kind {
+        StmtKind::Let { indices, value, .. } => std::iter::once(value)
+            .chain(indices.iter().flatten())
+            .collect(),
+        StmtKind::Print { items: list, .. } | StmtKind::PrintFile { items: list, .. } => {
+            items(list)
+        }
+        StmtKind::PrintUsing { format, items, .. } => {
+            std::iter::once(format).chain(items).collect()
+        }
+        StmtKind::Write { items, .. } => items.iter().collect(),
+        StmtKind::Input { vars, .. } | StmtKind::Read(vars) | StmtKind::InputFile { vars, .. } => {
+            targets(vars)
+        }
+        StmtKind::LineInput { var, .. } => targets(std::slice::from_ref(var)),
+        StmtKind::If { condition, .. } | StmtKind::While { condition, .. } => vec![condition],
+        StmtKind::For {
+            start, end, step, ..
+        } => [start, end].into_iter().chain(step).collect(),
+        StmtKind::DoLoop { condition, .. } => condition.iter().collect(),
+        StmtKind::OnGoto { expr, .. } => vec![expr],
+        StmtKind::Dim { arrays, .. } => arrays.iter().flat_map(|a| &a.dimensions).collect(),
+        StmtKind::Call { args, .. } => args.iter().collect(),
+        StmtKind::Width { width, .. } => vec![width],
+        StmtKind::SelectCase { expr, cases } => std::iter::once(expr)
+            .chain(cases.iter().filter_map(|(value, _)| value.as_ref()))
+            .collect(),
+        StmtKind::Open { filename, .. } => vec![filename],
+        StmtKind::Screen { mode } => vec![mode],
+        StmtKind::Palette { entry } => entry.iter().flat_map(|(a, c)| [a, c]).collect(),
+        StmtKind::Pset { x, y, color, .. } => [x, y].into_iter().chain(color).collect(),
+        StmtKind::GraphicsLine {
+            from, to, color, ..
+        } => from
+            .iter()
+            .flat_map(|(x, y)| [x, y])
+            .chain([&to.0, &to.1])
+            .chain(color)
+            .collect(),
+        StmtKind::Circle {
+            x,
+            y,
+            radius,
+            color,
+        } => [x, y, radius].into_iter().chain(color).collect(),
+        StmtKind::Paint {
+            x,
+            y,
+            color,
+            border,
+        } => [x, y].into_iter().chain(color).chain(border).collect(),
+        StmtKind::Draw { commands } => vec![commands],
+        StmtKind::GetImage {
+            from, to, indices, ..
+        } => [&from.0, &from.1, &to.0, &to.1]
+            .into_iter()
+            .chain(indices)
+            .collect(),
+        StmtKind::PutImage { at, indices, .. } => {
+            [&at.0, &at.1].into_iter().chain(indices).collect()
+        }
+        StmtKind::DefSeg { segment } => segment.iter().collect(),
+        StmtKind::Poke { address, value } => vec![address, value],
+        StmtKind::Bsave {
+            filename,
+            offset,
+            length,
+        } => vec![filename, offset, length],
+        StmtKind::Bload { filename, offset } => std::iter::once(filename).chain(offset).collect(),
+        StmtKind::Seek { position, .. } => vec![position],
+        StmtKind::Lock { first, last, .. } => first.iter().chain(last).collect(),
+        StmtKind::DictSet { dict, key, value } => [dict, key].into_iter().chain(value).collect(),
+        StmtKind::OnKey { key, .. } | StmtKind::KeyTrap { key, .. } => vec![key],
+        StmtKind::OnTimer { interval, .. } => vec![interval],
+        StmtKind::Asm { vars, .. } => vars.iter().collect(),
+        StmtKind::Const(consts) => consts.iter().map(|(_, value)| value).collect(),
+        _ => vec![],
+    }
+}
+
+fn expr_reads(expr: &Expr, reads: &mut HashSet<String>) {
+    match &expr.kind {
+        ExprKind::Literal(_) => {}
+        ExprKind::Variable(name) => {
+            reads.insert(name.clone());
+        }
+        ExprKind::ArrayAccess { indices: args, .. } | ExprKind::FnCall { args, .. } => {
+            args.iter().for_each(|arg| expr_reads(arg, reads));
+        }
+        ExprKind::Unary { operand, .. } => expr_reads(operand, reads),
+        ExprKind::Binary { left, right, .. } => {
+            expr_reads(left, reads);
+            expr_reads(right, reads);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Each warning as (line, message)
+    fn warnings(source: &str) -> Vec<(u32, String)> {
+        let program = Parser::new(Lexer::new(source)).parse().unwrap();
+        check(&program)
+            .into_iter()
+            .map(|w| (w.span.unwrap().line, w.message))
+            .collect()
+    }
+
+    // ===================
+    // Unused Variable Tests
+    // ===================
+
+    #[test]
+    fn test_unused_variables() {
+        let found = warnings("X = 1\nY = 2\nPRINT Y\nX = 3");
+        assert_eq!(
+            found,
+            vec![(1, "Variable X is assigned but never used".into())]
+        );
+        // Reads in subscripts, conditions, arguments and other scopes count
+        assert!(warnings("I = 1\nDIM A(5)\nINPUT A(I)").is_empty());
+        assert!(warnings("N = 3\nFOR K = 1 TO N\nNEXT").is_empty());
+        assert!(warnings("X = 1\nCALL S\nSUB S\nSHARED X\nPRINT X\nEND SUB").is_empty());
+        // INPUT and READ targets, and a FUNCTION's result, aren't flagged
+        assert!(warnings("INPUT X\nREAD Y\nDATA 1").is_empty());
+        assert!(warnings("FUNCTION F(N)\nF = N * 2\nEND FUNCTION").is_empty());
+        let found = warnings("SUB S\nT = 1\nEND SUB");
+        assert_eq!(
+            found,
+            vec![(2, "Variable T is assigned but never used".into())]
+        );
+    }
+
+    // ===================
+    // Unreachable Code Tests
+    // ===================
+
+    #[test]
+    fn test_unreachable_code() {
+        let found = warnings("10 PRINT 1\n20 GOTO 10\n30 PRINT 2\n40 PRINT 3");
+        assert_eq!(found, vec![(3, "Unreachable code".into())]);
+        // A line something jumps to is reachable again
+        assert!(warnings("GOSUB 100\nEND\n100 PRINT 1\nRETURN").is_empty());
+        // DATA and procedures after END are fine
+        assert!(warnings("PRINT 1\nEND\nDATA 1, 2\nSUB S\nEND SUB").is_empty());
+        // Nested blocks are checked too
+        let found = warnings("IF X THEN\nRETURN\nPRINT 1\nEND IF\nPRINT 2");
+        assert_eq!(found, vec![(3, "Unreachable code".into())]);
+    }
+
+    // ===================
+    // Jump Into Loop Tests
+    // ===================
+
+    #[test]
+    fn test_jump_into_loop() {
+        let found = warnings("IF X THEN 20\nFOR I = 1 TO 3\n20 PRINT I\nNEXT");
+        assert_eq!(found, vec![(1, "GOTO 20 jumps into a FOR loop".into())]);
+        let found = warnings("FOR I = 1 TO 3\nFOR J = 1 TO 3\n20 PRINT J\nNEXT\nGOSUB 20\nNEXT");
+        assert_eq!(found, vec![(5, "GOSUB 20 jumps into a FOR loop".into())]);
+        // Jumps out of a loop, and within one, are fine
+        assert!(warnings("FOR I = 1 TO 3\nIF I = 2 THEN 30\n20 NEXT\n30 PRINT I").is_empty());
+        assert!(warnings("FOR I = 1 TO 3\n10 PRINT I\nIF I = 0 THEN 10\nNEXT").is_empty());
+        assert!(warnings("IF Y THEN 10\nWHILE X\n10 X = 0\nWEND").is_empty());
+    }
+
+    // ===================
+    // Expression Nesting Tests
+    // ===================
+
+    #[test]
+    fn test_deep_expression() {
+        let sum = |terms: usize| vec!["Y"; terms].join(" + ");
+        let source = format!(
+            "PRINT 1\nPRINT {}\nSUB S\nIF {} THEN PRINT 1\nEND SUB",
+            sum(258),
+            sum(258)
+        );
+        let message = "Expression nesting exceeds 256 levels, stack overflow risk";
+        let found = warnings(&source);
+        assert_eq!(found, vec![(2, message.into()), (4, message.into())]);
+        // 256 operators are fine
+        assert!(warnings(&format!("PRINT {}", sum(257))).is_empty());
+    }
+}
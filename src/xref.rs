@@ -0,0 +1,485 @@
+//! Cross-reference listing (--xref)
+//!
+//! Every variable, array, procedure and line number in a program, with the
+//! lines where it is defined and where it is used:
+//!
+//! - A variable is defined where it is assigned: LET, FOR, INPUT, READ,
+//!   LINE INPUT, DIM, or as a parameter.
+//! - A constant is defined by its CONST.
+//! - An array is defined by its DIM, or as a parameter.
+//! - A procedure is defined by its SUB or FUNCTION, and by the assignments
+//!   that set a FUNCTION's result.
+//! - A line number is defined where it starts a line.
+//!
+//! Names in a SUB or FUNCTION are its own unless SHARED there or DIM SHARED
+//! in the main program, and so are its line numbers, as for the checker. A
+//! CONST in the main program is seen in every procedure.
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::include::Source;
+use crate::lexer::Span;
+use crate::parser::{Expr, ExprKind, GotoTarget, Param, Program, Stmt, StmtKind};
+use crate::types::{ArgKind, builtin_signature};
+use crate::warnings::stmt_reads;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+/// What a name in the listing is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Variable,
+    Constant,
+    Array,
+    Procedure,
+    Line,
+}
+
+impl Kind {
+    fn heading(self) -> &'static str {
+        match self {
+            Kind::Variable => "Variables",
+            Kind::Constant => "Constants",
+            Kind::Array => "Arrays",
+            Kind::Procedure => "Procedures",
+            Kind::Line => "Line numbers",
+        }
+    }
+}
+
+/// One name: its kind, line number (for sorting lines by value), name and
+/// the procedure it is local to
+type Key = (Kind, u32, String, Option<String>);
+
+/// Where a name is defined and used, as lines of the program's text
+#[derive(Debug, Default)]
+struct Entry {
+    defined: BTreeSet<u32>,
+    used: BTreeSet<u32>,
+}
+
+/// The cross-reference of a program
+#[derive(Debug, Default)]
+pub struct CrossReference {
+    entries: BTreeMap<Key, Entry>,
+}
+
+/// Build the cross-reference of a parsed program
+pub fn build(program: &Program) -> CrossReference {
+    let mut walker = Walker {
+        xref: CrossReference::default(),
+        procs: HashSet::new(),
+        dim_shared: HashSet::new(),
+        shared: HashSet::new(),
+        consts: HashSet::new(),
+        local_consts: HashSet::new(),
+        scope: None,
+    };
+    for stmt in &program.statements {
+        match &stmt.kind {
+            StmtKind::Sub { name, .. }
+            | StmtKind::Function { name, .. }
+            | StmtKind::Declare { name, .. } => {
+                walker.procs.insert(name.clone());
+            }
+            StmtKind::Dim {
+                arrays,
+                shared: true,
+            } => walker
+                .dim_shared
+                .extend(arrays.iter().map(|a| a.name.clone())),
+            _ => {}
+        }
+    }
+    collect_consts(&program.statements, &mut walker.consts);
+    walker.block(&program.statements);
+    walker.xref
+}
+
+impl CrossReference {
+    /// How many names of a kind there are (each procedure's locals apart)
+    pub fn count(&self, kind: Kind) -> usize {
+        self.entries.keys().filter(|key| key.0 == kind).count()
+    }
+
+    /// The listing, one section for each kind of name. Lines are those of
+    /// the main file, or `file:line` for an $INCLUDEd one.
+    pub fn listing(&self, source: &Source) -> String {
+        let line = |line: u32| {
+            let (file, _, span) = source.locate(Span { line, col: 1 });
+            if source.in_main_file(line) {
+                span.line.to_string()
+            } else {
+                format!("{}:{}", file, span.line)
+            }
+        };
+        let lines = |label: &str, lines: &BTreeSet<u32>| {
+            if lines.is_empty() {
+                return String::new();
+            }
+            let list: Vec<String> = lines.iter().map(|&n| line(n)).collect();
+            format!("{} {}", label, list.join(", "))
+        };
+        let rows: Vec<(Kind, String, String, String)> = self
+            .entries
+            .iter()
+            .map(|((kind, _, name, scope), entry)| {
+                let mut name = name.clone();
+                if *kind == Kind::Array {
+                    name.push_str("()");
+                }
+                if let Some(scope) = scope {
+                    write!(name, " (in {})", scope).expect("writing to a String");
+                }
+                let defined = lines("defined", &entry.defined);
+                (*kind, name, defined, lines("used", &entry.used))
+            })
+            .collect();
+        let name_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        let defined_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0);
+
+        let mut out = String::new();
+        let mut section = None;
+        for (kind, name, defined, used) in rows {
+            if section != Some(kind) {
+                if section.is_some() {
+                    out.push('\n');
+                }
+                writeln!(out, "{}", kind.heading()).expect("writing to a String");
+                section = Some(kind);
+            }
+            let row = format!(
+                "  {:<name_width$}  {:<defined_width$}  {}",
+                name, defined, used
+            );
+            writeln!(out, "{}", row.trim_end()).expect("writing to a String");
+        }
+        out
+    }
+}
+
+/// Walks the program recording each name where it appears
+struct Walker {
+    xref: CrossReference,
+    procs: HashSet<String>,        // SUBs and FUNCTIONs, defined or DECLAREd
+    dim_shared: HashSet<String>,   // visible in every procedure
+    shared: HashSet<String>,       // SHARED in the procedure being walked
+    consts: HashSet<String>,       // CONSTs of the main program
+    local_consts: HashSet<String>, // CONSTs of the procedure being walked
+    scope: Option<String>,         // the procedure being walked
+}
+
+impl Walker {
+    fn block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let at = stmt.span.line;
+        match &stmt.kind {
+            StmtKind::Label(n) => self.line(*n, at, true),
+            StmtKind::Let {
+                name,
+                indices: None,
+                ..
+            } if self.scope.as_ref() == Some(name) && self.procs.contains(name) => {
+                self.record(Kind::Procedure, name, None, at, true)
+            }
+            StmtKind::Let {
+                name,
+                indices: None,
+                ..
+            }
+            | StmtKind::For { var: name, .. } => self.name(Kind::Variable, name, at, true),
+            StmtKind::Let {
+                name,
+                indices: Some(_),
+                ..
+            } => self.name(Kind::Array, name, at, false),
+            StmtKind::Input { vars, .. }
+            | StmtKind::Read(vars)
+            | StmtKind::InputFile { vars, .. } => {
+                for var in vars {
+                    self.target(var, at);
+                }
+            }
+            StmtKind::LineInput { var, .. } => self.target(var, at),
+            StmtKind::Dim { arrays, .. } => {
+                for array in arrays {
+                    let kind = if array.dimensions.is_empty() {
+                        Kind::Variable
+                    } else {
+                        Kind::Array
+                    };
+                    self.name(kind, &array.name, at, true);
+                }
+            }
+            StmtKind::Shared(names) => {
+                for param in names {
+                    self.record(param_kind(param), &param.name, None, at, false);
+                }
+            }
+            StmtKind::Const(consts) => {
+                for (name, _) in consts {
+                    if self.scope.is_some() {
+                        self.local_consts.insert(name.clone());
+                    }
+                    self.constant(name, at, true);
+                }
+            }
+            StmtKind::Sub { name, params, body } | StmtKind::Function { name, params, body } => {
+                self.record(Kind::Procedure, name, None, at, true);
+                self.scope = Some(name.clone());
+                self.shared = body
+                    .iter()
+                    .filter_map(|stmt| match &stmt.kind {
+                        StmtKind::Shared(names) => Some(names),
+                        _ => None,
+                    })
+                    .flatten()
+                    .map(|param| param.name.clone())
+                    .collect();
+                for param in params {
+                    let scope = self.scope.clone();
+                    self.record(param_kind(param), &param.name, scope, at, true);
+                }
+                self.block(body);
+                self.scope = None;
+                self.shared.clear();
+                self.local_consts.clear();
+                return;
+            }
+            StmtKind::Call { name, .. } => self.record(Kind::Procedure, name, None, at, false),
+            StmtKind::GetImage { array, .. } | StmtKind::PutImage { array, .. } => {
+                self.name(Kind::Array, array, at, false)
+            }
+            StmtKind::Goto(target) | StmtKind::Gosub(target) => self.jump(target, at),
+            StmtKind::OnKey { target, .. }
+            | StmtKind::OnTimer { target, .. }
+            | StmtKind::OnBreak { target }
+            | StmtKind::OnMouse { target } => self.jump(target, at),
+            StmtKind::OnGoto { targets, .. } => {
+                for target in targets {
+                    self.jump(target, at);
+                }
+            }
+            StmtKind::Restore(Some(target)) => self.jump(target, at),
+            _ => {}
+        }
+        for expr in stmt_reads(&stmt.kind) {
+            self.expr(expr, at);
+        }
+        for body in stmt.kind.bodies() {
+            self.block(body);
+        }
+    }
+
+    /// An INPUT or READ target, assigned (a variable) or stored into (an
+    /// array element, whose subscripts `stmt_reads` gives)
+    fn target(&mut self, var: &Expr, at: u32) {
+        match &var.kind {
+            ExprKind::Variable(name) => self.name(Kind::Variable, name, at, true),
+            ExprKind::ArrayAccess { name, .. } | ExprKind::FnCall { name, .. } => {
+                self.name(Kind::Array, name, at, false)
+            }
+            _ => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, at: u32) {
+        match &expr.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::Variable(name) if self.is_const(name) => self.constant(name, at, false),
+            ExprKind::Variable(name) => self.name(Kind::Variable, name, at, false),
+            ExprKind::ArrayAccess { name, indices } => {
+                self.name(Kind::Array, name, at, false);
+                indices.iter().for_each(|index| self.expr(index, at));
+            }
+            ExprKind::FnCall { name, args } => {
+                if let Some(builtin) = builtin_signature(name) {
+                    for (arg, kind) in args.iter().zip(builtin.args) {
+                        match (&arg.kind, kind) {
+                            (ExprKind::Variable(array), ArgKind::Array) => {
+                                self.name(Kind::Array, array, at, false)
+                            }
+                            _ => self.expr(arg, at),
+                        }
+                    }
+                } else {
+                    if self.procs.contains(name) {
+                        self.record(Kind::Procedure, name, None, at, false);
+                    } else {
+                        self.name(Kind::Array, name, at, false);
+                    }
+                    args.iter().for_each(|arg| self.expr(arg, at));
+                }
+            }
+            ExprKind::Unary { operand, .. } => self.expr(operand, at),
+            ExprKind::Binary { left, right, .. } => {
+                self.expr(left, at);
+                self.expr(right, at);
+            }
+        }
+    }
+
+    /// A variable or array, local to the procedure it is in unless shared
+    fn name(&mut self, kind: Kind, name: &str, at: u32, defined: bool) {
+        let scope = self
+            .scope
+            .clone()
+            .filter(|_| !(self.shared.contains(name) || self.dim_shared.contains(name)));
+        self.record(kind, name, scope, at, defined);
+    }
+
+    fn is_const(&self, name: &str) -> bool {
+        self.local_consts.contains(name) || self.consts.contains(name)
+    }
+
+    /// A CONST, local to the procedure it is in if defined there
+    fn constant(&mut self, name: &str, at: u32, defined: bool) {
+        let scope = self
+            .scope
+            .clone()
+            .filter(|_| self.local_consts.contains(name));
+        self.record(Kind::Constant, name, scope, at, defined);
+    }
+
+    fn jump(&mut self, target: &GotoTarget, at: u32) {
+        if let GotoTarget::Line(n) = target {
+            self.line(*n, at, false);
+        }
+    }
+
+    fn line(&mut self, n: u32, at: u32, defined: bool) {
+        let key = (Kind::Line, n, n.to_string(), self.scope.clone());
+        self.add(key, at, defined);
+    }
+
+    fn record(&mut self, kind: Kind, name: &str, scope: Option<String>, at: u32, defined: bool) {
+        self.add((kind, 0, name.to_string(), scope), at, defined);
+    }
+
+    fn add(&mut self, key: Key, at: u32, defined: bool) {
+        let entry = self.xref.entries.entry(key).or_default();
+        if defined {
+            entry.defined.insert(at);
+        } else {
+            entry.used.insert(at);
+        }
+    }
+}
+
+/// The CONST names of the main program, in its blocks but not procedures
+fn collect_consts(stmts: &[Stmt], consts: &mut HashSet<String>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Sub { .. } | StmtKind::Function { .. } => continue,
+            StmtKind::Const(defs) => consts.extend(defs.iter().map(|(name, _)| name.clone())),
+            _ => {}
+        }
+        for body in stmt.kind.bodies() {
+            collect_consts(body, consts);
+        }
+    }
+}
+
+fn param_kind(param: &Param) -> Kind {
+    if param.is_array {
+        Kind::Array
+    } else {
+        Kind::Variable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// A name in the cross-reference: name, scope, defined and used lines
+    type Row = (String, Option<String>, Vec<u32>, Vec<u32>);
+
+    fn xref(source: &str, kind: Kind) -> Vec<Row> {
+        let program = Parser::new(Lexer::new(source)).parse().unwrap();
+        build(&program)
+            .entries
+            .into_iter()
+            .filter(|((k, ..), _)| *k == kind)
+            .map(|((_, _, name, scope), entry)| {
+                let defined = entry.defined.into_iter().collect();
+                (name, scope, defined, entry.used.into_iter().collect())
+            })
+            .collect()
+    }
+
+    fn main(name: &str, defined: &[u32], used: &[u32]) -> Row {
+        (name.to_string(), None, defined.to_vec(), used.to_vec())
+    }
+
+    // ===================
+    // Cross-Reference Tests
+    // ===================
+
+    #[test]
+    fn test_variables_and_arrays() {
+        let source =
+            "DIM A(5)\nFOR I = 1 TO 5\nREAD A(I)\nNEXT\nINPUT N\nPRINT UBOUND(A) + N\nDATA 1";
+        assert_eq!(
+            xref(source, Kind::Variable),
+            vec![main("I", &[2], &[3]), main("N", &[5], &[6])]
+        );
+        assert_eq!(xref(source, Kind::Array), vec![main("A", &[1], &[3, 6])]);
+    }
+
+    #[test]
+    fn test_procedure_scopes() {
+        let source = "DIM SHARED G\nX = 1\nCALL S(X)\nSUB S(X)\nSHARED Y\nG = X + Y\nEND SUB";
+        assert_eq!(
+            xref(source, Kind::Variable),
+            vec![
+                main("G", &[1, 6], &[]),
+                main("X", &[2], &[3]),
+                ("X".into(), Some("S".into()), vec![4], vec![6]),
+                main("Y", &[], &[5, 6]),
+            ]
+        );
+        assert_eq!(xref(source, Kind::Procedure), vec![main("S", &[4], &[3])]);
+    }
+
+    #[test]
+    fn test_constants() {
+        let source = "CONST N = 3, M = N + 1\nDIM A(N)\nSUB S\nCONST L = N\nPRINT L; M\nEND SUB";
+        assert_eq!(
+            xref(source, Kind::Constant),
+            vec![
+                ("L".into(), Some("S".into()), vec![4], vec![5]),
+                main("M", &[1], &[5]),
+                main("N", &[1], &[1, 2, 4]),
+            ]
+        );
+        assert!(xref(source, Kind::Variable).is_empty());
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let source =
+            "10 ON K GOTO 20, 30\n20 RESTORE 30\n30 IF K THEN 10\nSUB S\n10 GOTO 10\nEND SUB";
+        let lines = xref(source, Kind::Line);
+        let names: Vec<_> = lines
+            .iter()
+            .map(|l| (l.0.as_str(), l.1.as_deref()))
+            .collect();
+        // In number order, each procedure's apart
+        assert_eq!(
+            names,
+            [("10", None), ("10", Some("S")), ("20", None), ("30", None)]
+        );
+        assert_eq!(lines[0].3, [3]);
+        assert_eq!(lines[1].3, [5]);
+        assert_eq!(lines[3].3, [1, 2]);
+    }
+}
@@ -3,7 +3,7 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-use crate::common::compile_and_run;
+use crate::common::{compile_and_run, compile_and_run_with_args, run_compiler};
 
 #[test]
 fn test_basic_arithmetic() {
@@ -72,6 +72,25 @@ IF 0 XOR 0 THEN PRINT "xor-d"
     );
 }
 
+#[test]
+fn test_bit_shifts() {
+    // SHL/SHR work on 64-bit integers; SHR is a logical shift
+    let output = compile_and_run(
+        r#"
+PRINT SHL(3, 1)
+PRINT SHR(256, 4)
+PRINT SHL(1, 40) = 2 ^ 40
+PRINT SHR(-1, 60)
+PRINT SHL(1, 64); SHR(5, -1)
+X% = &HF0
+PRINT SHR(X%, 4) AND 7
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["6", "16", "-1", "15", "00", "7"]);
+}
+
 #[test]
 fn test_comparison_operators() {
     let output = compile_and_run(
@@ -188,3 +207,63 @@ A# = 2.71828: PRINT -A#
     assert_eq!(lines[4], "1024", "double power");
     assert_eq!(lines[5], "-2.71828", "double neg");
 }
+
+#[test]
+fn test_negative_int_div_mod() {
+    // \ truncates toward zero and MOD takes the dividend's sign, as in
+    // QBasic; fractional operands are rounded (half to even) first
+    let source = r#"
+PRINT -7 \ 3; 7 \ -3; -7 \ -3; -7 MOD 3; 7 MOD -3; -7 MOD -3
+A = -7: B = 3: A% = -7: B% = 3: A& = -100000: B& = 7
+PRINT A \ B; A MOD B; A% \ B%; A% MOD B%; A& \ B&; A& MOD B&
+PRINT -7.9 \ 2: PRINT -7.9 MOD 2: PRINT 7.5 MOD -2.9: PRINT 7.5 MOD 2: PRINT 6.5 \ 2
+X! = 2.5: Y# = -3.5: PRINT X! \ 1: PRINT Y# MOD 3
+"#;
+    let plain = compile_and_run(source).unwrap();
+    assert_eq!(
+        plain,
+        "-2-22-11-1\n-2-1-2-1-14285-5\n-4\n0\n2\n0\n3\n2\n-1\n"
+    );
+    let optimized = compile_and_run_with_args(source, &["-O"]).unwrap();
+    assert_eq!(plain, optimized);
+    assert_eq!(run_compiler(source, &["--run"]).unwrap(), plain);
+}
+
+#[test]
+fn test_constant_folding_matches_runtime() {
+    // Folded constants print exactly what run-time arithmetic does
+    let source = r#"
+N% = 7
+PI = 3.14159
+PRINT 2 * PI * N%
+PRINT 1 / 3, 2! / 3!, 1.1! * 3
+PRINT 7 \ 2, -7 MOD 3, 6 AND 3, NOT 0
+PRINT 1 < 2, 2.5 = 2.5, "AB" + "C"
+PRINT N% * 1000
+PRINT 32767% + 1%
+"#;
+    let plain = compile_and_run(source).unwrap();
+    let optimized = compile_and_run_with_args(source, &["-O"]).unwrap();
+    assert_eq!(plain, optimized);
+    assert!(optimized.starts_with("43.98226\n"), "{}", optimized);
+
+    // What can't be folded still fails at run time
+    let err = compile_and_run_with_args("N% = 7\nPRINT 1 \\ (N% - 7)\n", &["-O"]).unwrap_err();
+    assert!(err.contains("Division by zero"), "{}", err);
+}
+
+#[test]
+fn test_register_allocation_matches_runtime() {
+    // Deeply nested operands use up the registers and fall back to the stack
+    let source = r#"
+A = 1.5: B = 2: C = 3: D = 4: E = 5
+I% = 3: J& = 100000
+PRINT A * B - C * D
+PRINT (A - (B - (C - (D - (E - A))))) / 2
+PRINT (I% - (I% * 2 - (I% * 3 - (I% * 4 - (I% * 5 - (I% - 1)))))) * J&
+PRINT 1 + A * B, 10 - SQR(D) * A
+"#;
+    let plain = compile_and_run(source).unwrap();
+    let optimized = compile_and_run_with_args(source, &["-O"]).unwrap();
+    assert_eq!(plain, optimized);
+}
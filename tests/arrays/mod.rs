@@ -35,7 +35,11 @@ PRINT Grid(0, 0), Grid(0, 1), Grid(0, 2), Grid(1, 0), Grid(1, 1), Grid(1, 2)
     assert_eq!(lines[0], "10", "1d a(1)");
     assert_eq!(lines[1], "30", "1d a(3)");
     assert_eq!(lines[2], "15", "2d diagonal sum");
-    let values: Vec<&str> = lines[3].split_whitespace().collect();
+    // Six print zones don't fit in 80 columns, so the last value wraps
+    let values: Vec<&str> = lines[3..]
+        .iter()
+        .flat_map(|l| l.split_whitespace())
+        .collect();
     assert_eq!(values, vec!["0", "1", "2", "10", "11", "12"], "2d loop");
 }
 
@@ -59,3 +63,53 @@ PRINT Cube(0, 0, 0) + Cube(1, 1, 1)
     // 1 + 8 = 9
     assert_eq!(output.trim(), "9");
 }
+
+#[test]
+fn test_static_and_runtime_arrays() {
+    // Constant bounds are laid out in .bss (and start out zero); others are
+    // allocated when the DIM runs
+    let output = compile_and_run(
+        r#"
+DIM Big(100000), Names$(3), Grid%(2, -1 + 3)
+N = 4
+DIM Sized(N)
+Big(100000) = 7
+Names$(3) = "last"
+Grid%(2, 2) = 9
+Sized(N) = 5
+PRINT Big(0)
+PRINT Big(100000)
+PRINT LEN(Names$(0))
+PRINT Names$(3)
+PRINT Grid%(2, 2)
+PRINT Sized(4)
+PRINT UBOUND(Big)
+PRINT UBOUND(Grid%, 2)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["0", "7", "0", "last", "9", "5", "100000", "2"]);
+}
+
+#[test]
+fn test_static_array_redim() {
+    // A static array is cleared each time its DIM runs, as a malloc'd one
+    // would be
+    let output = compile_and_run(
+        r#"
+FOR I = 1 TO 3
+    DIM B(3)
+    B(1) = B(1) + 1
+    PRINT B(1)
+NEXT
+DIM A(5)
+A(5) = 9
+DIM A(10)
+PRINT A(5)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["1", "1", "1", "0"]);
+}
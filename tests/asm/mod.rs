@@ -0,0 +1,65 @@
+//! Inline assembly tests (ASM ... END ASM)
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run, compile_and_run_with_args, run_compiler};
+
+const PROGRAM: &str = r#"
+N& = 41
+ASM
+    mov eax, DWORD PTR {n&}
+    inc eax
+    mov DWORD PTR {N&}, eax
+END ASM
+PRINT N&
+FOR I& = 1 TO 3
+    ASM ' square the counter
+        mov eax, DWORD PTR {I&}
+        imul eax, eax
+        mov DWORD PTR {S&}, eax
+    END ASM
+    PRINT S&; " ";
+NEXT
+PRINT
+Twice 21
+D# = 2
+ASM
+    movsd xmm0, QWORD PTR {D#}
+    sqrtsd xmm0, xmm0
+    movsd QWORD PTR {R#}, xmm0
+END ASM
+PRINT R#
+
+SUB Twice(X&)
+    Y& = X&
+    ASM
+        jmp .Ltwice
+        mov DWORD PTR {Y&}, 0
+    .Ltwice:
+        shl DWORD PTR {Y&}, 1
+    END ASM
+    PRINT Y&
+END SUB
+"#;
+
+#[test]
+fn test_asm_block() {
+    let expected = "42\n1 4 9 \n42\n1.4142135623730951\n";
+    assert_eq!(compile_and_run(PROGRAM).unwrap(), expected);
+    // The optimizer leaves the blocks, and the variables they name, alone
+    assert_eq!(
+        compile_and_run_with_args(PROGRAM, &["-O"]).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn test_asm_errors() {
+    let err = compile_and_run("ASM\n    nop\n").unwrap_err();
+    assert!(err.contains("ASM without END ASM"), "{}", err);
+    let err = compile_and_run("ASM\n    mov eax, {1X}\nEND ASM\n").unwrap_err();
+    assert!(err.contains("{1X} in ASM isn't a variable name"), "{}", err);
+    let err = run_compiler("ASM\nnop\nEND ASM\n", &["--run"]).unwrap_err();
+    assert!(err.contains("ASM is not supported by --run"), "{}", err);
+}
@@ -0,0 +1,877 @@
+//! Compiler command-line mode tests
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run_with_args, run_compiler};
+
+#[test]
+fn test_emit_tokens() {
+    let source = "10 X = 1 + 2\nPRINT X\n";
+    let out = run_compiler(source, &["--emit-tokens"]).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "1:1\tLineNumber(10)");
+    assert_eq!(lines[1], "1:4\tIdent(\"X\")");
+    assert_eq!(lines.last(), Some(&"3:1\tEof"));
+
+    let out = run_compiler(source, &["--emit-tokens=json"]).unwrap();
+    assert!(out.trim_start().starts_with('['), "{}", out);
+    assert!(out.contains(r#""token": {"#) && out.contains(r#""LineNumber": 10"#));
+    assert!(out.contains(r#""token": "Eof""#), "{}", out);
+}
+
+#[test]
+fn test_emit_ast() {
+    // Semantic errors don't matter: the AST is printed straight after parsing
+    let source = "X = \"a\" + 1\nPRINT X\n";
+    let out = run_compiler(source, &["--emit-ast"]).unwrap();
+    assert!(out.starts_with("Program {"), "{}", out);
+    assert!(out.contains("kind: Let {"), "{}", out);
+
+    let out = run_compiler(source, &["--emit-ast=json"]).unwrap();
+    assert!(out.contains(r#""Let": {"#), "{}", out);
+    assert!(out.contains(r#""name": "X""#), "{}", out);
+    assert!(out.contains(r#""span": {"#), "{}", out);
+
+    // Parse errors are still reported
+    let err = run_compiler("PRINT (", &["--emit-ast"]).unwrap_err();
+    assert!(err.contains("Parse error"), "{}", err);
+}
+
+#[test]
+fn test_emit_ir() {
+    let source = "X% = 2\nIF X% < 3 THEN PRINT \"small\"\n";
+    let out = run_compiler(source, &["--emit-ir"]).unwrap();
+    let lines: Vec<&str> = out.lines().map(str::trim).collect();
+    assert!(lines.contains(&"enter frame0"), "{}", out);
+    // Main-program variables live in .bss
+    assert!(
+        lines.contains(&"store.int [rip + _var_X_int + 0]"),
+        "{}",
+        out
+    );
+    assert!(lines.contains(&"lt.lng"), "{}", out);
+    assert!(
+        lines
+            .iter()
+            .any(|l| l.starts_with("branch_zero.lng .Lelse")),
+        "{}",
+        out
+    );
+    assert!(lines.contains(&"call _rt_print_string"), "{}", out);
+    assert!(lines.contains(&"return frame0"), "{}", out);
+}
+
+#[test]
+fn test_check() {
+    // Success prints nothing, and procedures may be defined elsewhere
+    let source = "DECLARE SUB ELSEWHERE\nCALL ELSEWHERE\nPRINT 1\n";
+    let out = run_compiler(source, &["--check"]).unwrap();
+    assert!(out.is_empty(), "{}", out);
+
+    let err = run_compiler("X = \"a\" + 1\n", &["--check"]).unwrap_err();
+    assert!(err.contains("Type mismatch"), "{}", err);
+    let err = run_compiler("PRINT 1\nPRINT FOO(1)\n", &["--check"]).unwrap_err();
+    assert!(
+        err.contains(":2:7: Error: FOO is not a FUNCTION"),
+        "{}",
+        err
+    );
+    let err = run_compiler("PRINT FOO$(2)\n", &["--check"]).unwrap_err();
+    assert!(err.contains("FOO$ is not a FUNCTION"), "{}", err);
+    let err = run_compiler("X = 1\n", &["--check", "-W"]).unwrap_err();
+    assert!(err.contains("never used"), "{}", err);
+    let err = run_compiler("PRINT 1\n", &["--check", "-S"]).unwrap_err();
+    assert!(err.contains("cannot be used with"), "{}", err);
+}
+
+#[test]
+fn test_dialect() {
+    // QuickBASIC's block keywords are names in GW-BASIC, which numbers every line
+    let gw = "10 DO = 3: LOOP = 4\n20 PRINT DO + LOOP\n";
+    let out = run_compiler(gw, &["--dialect", "gw", "-o", "gw"]).unwrap();
+    assert!(out.contains("Compiled"), "{}", out);
+    let out = run_compiler(gw, &["--dialect", "gw", "--run"]).unwrap();
+    assert_eq!(out, "7\n");
+    let err = run_compiler(gw, &["--check"]).unwrap_err();
+    assert!(err.contains("Parse error"), "{}", err);
+    let err = run_compiler("10 PRINT 1\nPRINT 2\n", &["--dialect", "gw", "--check"]).unwrap_err();
+    assert!(err.contains("Direct statement in file"), "{}", err);
+
+    // QuickBASIC has the blocks but not the extensions
+    let qb = "DO\n  I = I + 1\nLOOP UNTIL I = 3\nPRINT I\n";
+    assert_eq!(
+        run_compiler(qb, &["--dialect", "qb45", "--run"]).unwrap(),
+        "3\n"
+    );
+    let declare = "DECLARE FUNCTION Abs% LIB \"c\" (BYVAL N%)\nPRINT Abs%(-2)\n";
+    assert!(run_compiler(declare, &["--check"]).is_ok());
+    let err = run_compiler(declare, &["--dialect", "qb45", "--check"]).unwrap_err();
+    assert!(err.contains("not part of QuickBASIC 4.5"), "{}", err);
+    let err = run_compiler(qb, &["--dialect", "basica", "--check"]).unwrap_err();
+    assert!(err.contains("invalid value"), "{}", err);
+}
+
+#[test]
+fn test_dialect_default_type() {
+    // An unsuffixed name is SINGLE in GW-BASIC and QuickBASIC, DOUBLE in
+    // xbasic64, compiled (with and without folding) or interpreted alike
+    let gw = "10 X = 1 / 3: Y = 0.1\n20 PRINT X; Y * 3\n";
+    let qb45 = "DIM A(2)\nA(1) = 2 / 3\nPRINT A(1); F(1)\nCALL S(0.1)\n\
+                FUNCTION F(N)\nF = N / 7\nEND FUNCTION\n\
+                SUB S(V)\nPRINT V * 3\nEND SUB\n";
+    let cases = [
+        ("gw", gw, "0.33333330.3\n"),
+        ("modern", gw, "0.33333333333333330.30000000000000004\n"),
+        ("qb45", qb45, "0.66666670.1428571\n0.3\n"),
+        (
+            "modern",
+            qb45,
+            "0.66666666666666660.14285714285714285\n0.30000000000000004\n",
+        ),
+    ];
+    for (dialect, source, expected) in cases {
+        for args in [&[][..], &["-O"], &["--run"]] {
+            let args = [&["--dialect", dialect][..], args].concat();
+            let out = if args.contains(&"--run") {
+                run_compiler(source, &args)
+            } else {
+                compile_and_run_with_args(source, &args)
+            };
+            assert_eq!(out.unwrap(), expected, "{} {:?}", dialect, args);
+        }
+    }
+}
+
+#[test]
+fn test_timings() {
+    use std::process::Command;
+
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(&bas, "FOR I = 1 TO 2\nPRINT I\nNEXT I\n").unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg(&bas)
+        .arg("--timings")
+        .output()
+        .unwrap();
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{}", err);
+    let phases: Vec<&str> = err.lines().filter_map(|l| l.split(' ').next()).collect();
+    assert_eq!(
+        phases,
+        [
+            "read", "lex", "parse", "check", "generate", "emit", "assemble", "link", "total"
+        ]
+    );
+    assert!(err.contains(" ms  3 lines\n"), "{}", err);
+    assert!(err.contains(" ms  2 statements\n"), "{}", err);
+    assert!(err.contains(" ms  1 variables, 0 arrays\n"), "{}", err);
+
+    // The timings go to stderr, leaving assembly on stdout alone
+    let out = run_compiler("PRINT 1\n", &["-S", "-o", "-", "--timings"]).unwrap();
+    assert!(!out.contains(" ms"), "{}", out);
+}
+
+#[test]
+fn test_xref() {
+    let source = "10 INPUT N\nGOSUB 100\nPRINT SQ(N)\nEND\n100 N = N + 1: RETURN\n\
+                  FUNCTION SQ(X)\nSQ = X * X\nEND FUNCTION\n";
+    let out = run_compiler(source, &["--xref"]).unwrap();
+    assert_eq!(
+        out,
+        "Variables\n\
+         \x20 N          defined 1, 5  used 3, 5\n\
+         \x20 X (in SQ)  defined 6     used 7\n\
+         \n\
+         Procedures\n\
+         \x20 SQ         defined 6, 7  used 3\n\
+         \n\
+         Line numbers\n\
+         \x20 10         defined 1\n\
+         \x20 100        defined 5     used 2\n"
+    );
+}
+
+#[test]
+fn test_optimize_folds_constants() {
+    let source = "R = 2\nX = 2 * 3.14159 * R\nPRINT X\n";
+    let out = run_compiler(source, &["--emit-ir"]).unwrap();
+    assert!(out.contains("const.dbl 3.14159"), "{}", out);
+
+    let out = run_compiler(source, &["-O", "--emit-ir"]).unwrap();
+    assert!(out.contains("const.dbl 12.56636"), "{}", out);
+    assert!(!out.contains("mul.dbl"), "{}", out);
+}
+
+#[test]
+fn test_optimize_removes_dead_code() {
+    let source = "Y = 1\nY = 2\nPRINT Y\nUNUSED = 3 * Y\nGOTO 10\nPRINT \"skipped\"\n10 END\n";
+    let out = run_compiler(source, &["-O", "--emit-ir"]).unwrap();
+    assert!(!out.contains("const.dbl 1.0"), "{}", out);
+    assert!(!out.contains("mul.dbl"), "{}", out);
+    assert!(!out.contains("_rt_print_string"), "{}", out);
+    assert_eq!(out.matches("return frame0").count(), 1, "{}", out);
+}
+
+#[test]
+fn test_optimize_peephole() {
+    // A constant or variable right operand goes straight to the secondary
+    // register, without a round trip through the stack
+    let source = "INPUT A, B\nC = A * B + 1\nPRINT C\n";
+    let out = run_compiler(source, &["--emit-ir"]).unwrap();
+    assert!(out.contains("push.dbl"), "{}", out);
+
+    let out = run_compiler(source, &["-O", "--emit-ir"]).unwrap();
+    assert!(!out.contains("push.dbl"), "{}", out);
+    assert!(out.contains("load_right.dbl [rip + _var_B + 0]"), "{}", out);
+    assert!(out.contains("const_right.dbl 1.0"), "{}", out);
+    // C is still in xmm0 after the store, so it isn't loaded back
+    assert!(!out.contains("load.dbl [rip + _var_C + 0]"), "{}", out);
+}
+
+#[test]
+fn test_optimize_register_allocation() {
+    // The left product waits in a register while the right one is computed
+    let source = "INPUT A, B, C, D\nPRINT A * B - C * D\n";
+    let out = run_compiler(source, &["-O", "--emit-ir"]).unwrap();
+    assert!(!out.contains("push.dbl"), "{}", out);
+    assert!(out.contains("save.dbl t0"), "{}", out);
+    assert!(out.contains("restore_right.dbl t0"), "{}", out);
+
+    // A function call in between clobbers the registers, so the stack is kept
+    let out = run_compiler("INPUT A\nPRINT A - SIN(A)\n", &["-O", "--emit-ir"]).unwrap();
+    assert!(out.contains("push.dbl"), "{}", out);
+}
+
+#[test]
+fn test_jump_table() {
+    // A dense SELECT CASE jumps through a table instead of comparing each case
+    let source = "INPUT N%\nSELECT CASE N%\nCASE 1\nPRINT 1\nCASE 2\nPRINT 2\n\
+                  CASE 3\nPRINT 3\nCASE 4\nPRINT 4\nEND SELECT\n";
+    let out = run_compiler(source, &["--emit-ir"]).unwrap();
+    assert!(out.contains("jmp rax"), "{}", out);
+    assert!(out.contains(".long .Lcase_"), "{}", out);
+}
+
+#[test]
+fn test_static_arrays() {
+    // Constant bounds need no malloc; a computed bound does
+    let out = run_compiler("DIM A(1000)\nA(1) = 2\n", &["--emit-ir"]).unwrap();
+    assert!(!out.contains("call_libc malloc"), "{}", out);
+    assert!(out.contains("lea rax, [rip + _arrdata_A]"), "{}", out);
+
+    let out = run_compiler("N = 10\nDIM A(N)\nA(1) = 2\n", &["--emit-ir"]).unwrap();
+    assert!(out.contains("call_libc malloc"), "{}", out);
+
+    // Bounds that are constant expressions are folded even at -O0
+    let out = run_compiler("DIM A(10 * 4, 2 + 1)\nA(1, 1) = 2\n", &["--emit-ir"]).unwrap();
+    assert!(!out.contains("call_libc malloc"), "{}", out);
+    assert!(out.contains("lea rax, [rip + _arrdata_A]"), "{}", out);
+
+    // And so are bounds made of CONSTs
+    let source = "CONST MAXROWS = 25\nDIM Buf(MAXROWS * 4)\nBuf(1) = 2\n";
+    let out = run_compiler(source, &["--emit-ir"]).unwrap();
+    assert!(!out.contains("call_libc malloc"), "{}", out);
+}
+
+#[test]
+fn test_string_pool() {
+    // Identical string constants share one copy
+    let source = "PRINT \"--\"\nPRINT \"--\"; \"ab\"\nPRINT \"--\"\n";
+    let out = run_compiler(source, &["--emit-ir"]).unwrap();
+    assert_eq!(out.matches("const.str _str_0 ").count(), 3, "{}", out);
+    assert!(out.contains("const.str _str_1 "), "{}", out);
+    assert!(!out.contains("_str_2"), "{}", out);
+
+    // ... in read-only data, with their lengths as symbols
+    let out = run_compiler(source, &["-S", "-o", "-", "--runtime", "none"]).unwrap();
+    assert!(out.contains(".equ _str_0_len, 2\n"), "{}", out);
+    assert!(out.contains("mov rdx, _str_0_len\n"), "{}", out);
+    assert!(out.contains(".section .rodata\n_str_0:"), "{}", out);
+    assert_eq!(out.matches("_str_0:").count(), 1, "{}", out);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_object_file() {
+    use std::process::Command;
+
+    // -c writes an ELF object with no external assembler; cc links it
+    let tmp = tempfile::TempDir::new().unwrap();
+    let source = "FOR I% = 1 TO 3\nPRINT I% * 2\nNEXT\nPRINT \"done\"\n";
+    let out = run_compiler(
+        source,
+        &["-c", "-o", tmp.path().join("prog.o").to_str().unwrap()],
+    )
+    .unwrap();
+    assert!(out.contains("Object written to"), "{}", out);
+    let object = std::fs::read(tmp.path().join("prog.o")).unwrap();
+    assert_eq!(&object[..4], b"\x7fELF");
+
+    let exe = tmp.path().join("prog");
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&exe)
+        .arg(tmp.path().join("prog.o"))
+        .args(["-lm", "-no-pie"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let run = Command::new(&exe).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n4\n6\ndone\n");
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_assembler_and_linker_commands() {
+    use std::process::Command;
+
+    // Named tools replace the built-in ones, and library flags reach cc
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(&bas, "PRINT INT(SQR(81))\n").unwrap();
+    let compile = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+            .arg(&bas)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let out = compile(&["--emit-obj", "--as", "as"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(tmp.path().join("prog.o").exists());
+    assert!(!tmp.path().join("prog.s").exists());
+
+    let lib_dir = tmp.path().to_str().unwrap();
+    let out = compile(&["--as", "as", "--cc", "cc", "-L", lib_dir, "-lm"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let run = Command::new(tmp.path().join("prog")).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "9\n");
+
+    let out = compile(&["--cc", "no-such-cc"]);
+    assert!(!out.status.success());
+    assert!(
+        String::from_utf8_lossy(&out.stderr).contains("Failed to run linker no-such-cc"),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_custom_runtime() {
+    use std::process::Command;
+
+    // A runtime of the user's own replaces the built-in one, as an object
+    // file or a static library
+    let tmp = tempfile::TempDir::new().unwrap();
+    let path = |name: &str| tmp.path().join(name).to_str().unwrap().to_string();
+    std::fs::write(path("prog.bas"), "PRINT \"Hi\"\n").unwrap();
+    std::fs::write(
+        path("rt.c"),
+        "#include <stdio.h>\n\
+         void _rt_print_string(const char *s, long n) { printf(\"<%.*s>\", (int)n, s); }\n\
+         void _rt_print_newline(void) { printf(\"|\\n\"); }\n\
+         void _rt_init_break(void) {}\n",
+    )
+    .unwrap();
+    let status = Command::new("cc")
+        .args(["-c", &path("rt.c"), "-o", &path("rt.o")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new("ar")
+        .args(["rcs", &path("librt.a"), &path("rt.o")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let compile = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+            .arg(path("prog.bas"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    for runtime in ["rt.o", "librt.a"] {
+        let out = compile(&["--runtime", &path(runtime)]);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let run = Command::new(path("prog")).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&run.stdout), "<Hi>|\n");
+    }
+
+    // With none, the program only refers to the runtime
+    let out = compile(&["-S", "-o", "-", "--runtime", "none"]);
+    let asm = String::from_utf8_lossy(&out.stdout);
+    assert!(asm.contains("call _rt_print_string"), "{}", asm);
+    assert!(!asm.contains("_rt_print_string:"), "{}", asm);
+
+    let out = compile(&["--runtime", "rt.c"]);
+    assert!(!out.status.success());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_declare_lib_user_library() {
+    use std::process::Command;
+
+    // A library of the user's own is linked by the name DECLARE gives it;
+    // arguments past the registers go on the stack
+    let tmp = tempfile::TempDir::new().unwrap();
+    let path = |name: &str| tmp.path().join(name).to_str().unwrap().to_string();
+    std::fs::write(
+        path("mix.c"),
+        "#include <string.h>\n\
+         double mix(long a, double b, const char *s, long c, long d, long e, long f,\n\
+                    long g, float h) {\n\
+             return a + b + strlen(s) + c + d + e + f + g * 100 + h;\n\
+         }\n\
+         const char *name(void) { return \"mixer\"; }\n",
+    )
+    .unwrap();
+    let status = Command::new("cc")
+        .args(["-c", &path("mix.c"), "-o", &path("mix.o")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = Command::new("ar")
+        .args(["rcs", &path("libmix.a"), &path("mix.o")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    std::fs::write(
+        path("prog.bas"),
+        "DECLARE FUNCTION Mix# LIB \"mix\" (BYVAL A&, BYVAL B#, BYVAL S$, BYVAL C&, \
+         BYVAL D&, BYVAL E&, BYVAL F&, BYVAL G&, BYVAL H!)\n\
+         DECLARE FUNCTION LibName$ LIB \"mix\" ALIAS \"name\" ()\n\
+         PRINT Mix#(1, 0.5, \"abc\", 2, 3, 4, 5, 6, 0.25)\n\
+         PRINT LibName$()\n",
+    )
+    .unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .args([&path("prog.bas"), "-L", &path("")])
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let run = Command::new(path("prog")).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "618.75\nmixer\n");
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_keep_temps() {
+    use std::process::Command;
+
+    // Intermediate files go to the temp directory and are removed, even
+    // when the build fails, unless --keep-temps puts them by the output
+    let tmp = tempfile::TempDir::new().unwrap();
+    let temps = tmp.path().join("temps");
+    std::fs::create_dir(&temps).unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(&bas, "PRINT 1\n").unwrap();
+    let compile = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+            .arg(&bas)
+            .args(args)
+            .env("TMPDIR", &temps)
+            .output()
+            .unwrap()
+    };
+    let files = |dir: &std::path::Path| {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+
+    let out = compile(&["--as", "as"]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert_eq!(files(tmp.path()), ["prog", "prog.bas", "temps"]);
+    assert!(files(&temps).is_empty());
+
+    let out = compile(&["--as", "as", "--cc", "no-such-cc", "-o", "other"]);
+    assert!(!out.status.success());
+    assert!(files(&temps).is_empty());
+
+    for args in [&["--keep-temps"][..], &["--save-temps", "--as", "as"]] {
+        std::fs::remove_file(tmp.path().join("prog")).unwrap();
+        let out = compile(args);
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(
+            files(tmp.path()),
+            ["prog", "prog.bas", "prog.o", "prog.s", "temps"]
+        );
+        std::fs::remove_file(tmp.path().join("prog.o")).unwrap();
+        std::fs::remove_file(tmp.path().join("prog.s")).unwrap();
+    }
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_runtime_library() {
+    use std::process::Command;
+
+    // With an external assembler the runtime is assembled into a library in
+    // the cache once, and programs after that link against it
+    let tmp = tempfile::TempDir::new().unwrap();
+    let cache = tmp.path().join("cache");
+    let compile = |source: &str| {
+        let bas = tmp.path().join("prog.bas");
+        std::fs::write(&bas, source).unwrap();
+        let out = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+            .arg(&bas)
+            .args(["--as", "as", "--cc", "cc", "--timings"])
+            .env("XBASIC64_CACHE_DIR", &cache)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr).to_string();
+        assert!(out.status.success(), "{}", stderr);
+        let run = Command::new(tmp.path().join("prog")).output().unwrap();
+        (String::from_utf8_lossy(&run.stdout).to_string(), stderr)
+    };
+    let libraries = || std::fs::read_dir(&cache).unwrap().count();
+
+    let (out, timings) = compile("PRINT LEFT$(\"hello\", 4)\n");
+    assert_eq!(out, "hell\n");
+    assert!(timings.contains("\nruntime "), "{}", timings);
+    assert_eq!(libraries(), 1);
+
+    // The runtime's READ finds the program's DATA
+    let (out, timings) = compile("DATA 7, \"x\"\nREAD A, B$\nPRINT A * 6; B$\n");
+    assert_eq!(out, "42x\n");
+    assert!(!timings.contains("\nruntime "), "{}", timings);
+    assert_eq!(libraries(), 1);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_watch() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // Each change to the program or a file it includes rebuilds it, and
+    // with =run starts it; an error is reported and the watch goes on
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    let inc = tmp.path().join("value.bi");
+    std::fs::write(&bas, "INCLUDE \"value.bi\"\nPRINT \"X =\"; X\n").unwrap();
+    std::fs::write(&inc, "X = 1\n").unwrap();
+    let mut watcher = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg(&bas)
+        .arg("--watch=run")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (tx, rx) = mpsc::channel();
+    for pipe in [
+        Box::new(watcher.stdout.take().unwrap()) as Box<dyn std::io::Read + Send>,
+        Box::new(watcher.stderr.take().unwrap()),
+    ] {
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(pipe).lines() {
+                let _ = tx.send(line.unwrap());
+            }
+        });
+    }
+    // The program's output and the watch's messages interleave, so each
+    // wait looks at everything printed so far
+    let mut seen: Vec<String> = Vec::new();
+    let mut wait_for = |text: &str| {
+        while !seen.iter().any(|line| line.contains(text)) {
+            match rx.recv_timeout(Duration::from_secs(30)) {
+                Ok(line) => seen.push(line),
+                Err(_) => panic!("no {:?} in {:?}", text, seen),
+            }
+        }
+    };
+
+    wait_for("X =1");
+    wait_for("Watching");
+    std::fs::write(&inc, "X = )\n").unwrap();
+    wait_for("Parse error");
+    std::fs::write(&inc, "X = 22\n").unwrap();
+    wait_for("X =22");
+    std::fs::write(&bas, "INCLUDE \"value.bi\"\nPRINT \"X+1 =\"; X + 1\n").unwrap();
+    wait_for("X+1 =23");
+    watcher.kill().unwrap();
+    watcher.wait().unwrap();
+}
+
+#[test]
+fn test_annotated_assembly() {
+    use std::process::Command;
+
+    // -S shows each source line above its code, and local labels name the
+    // BASIC line they belong to
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(
+        &bas,
+        "' Count\n10 FOR I = 1 TO 3\n20   IF I = 2 THEN PRINT I\n30 NEXT I\n",
+    )
+    .unwrap();
+    for opt in ["--opt-level=0", "-O"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+            .arg(&bas)
+            .args(["-S", opt])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let asm = std::fs::read_to_string(tmp.path().join("prog.s")).unwrap();
+        assert!(
+            asm.contains("# line 2: 10 FOR I = 1 TO 3\n_line_10:\n"),
+            "{}",
+            asm
+        );
+        assert!(
+            asm.contains("# line 3: 20   IF I = 2 THEN PRINT I\n_line_20:\n"),
+            "{}",
+            asm
+        );
+        assert!(asm.contains("# line 4: 30 NEXT I\n"), "{}", asm);
+        assert!(asm.contains(".Lfor_0_line10:\n"), "{}", asm);
+        assert!(asm.contains("_line20\n"), "{}", asm);
+    }
+}
+
+#[test]
+fn test_target_windows_assembly() {
+    use std::process::Command;
+
+    // Any host can emit Win64 code: arguments in rcx/rdx/r8/r9, shadow
+    // space around C library calls, and the Win32 API runtime
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(&bas, "X = SIN(1)\nPRINT INSTR(2, \"abcabc\", \"c\"); X\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg(&bas)
+        .args(["--target", "windows", "-S"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let asm = std::fs::read_to_string(tmp.path().join("prog.s")).unwrap();
+    assert!(asm.contains("call _rt_init_console"), "{}", asm);
+    assert!(
+        asm.contains("sub rsp, 32\n    call sin\n    add rsp, 32"),
+        "{}",
+        asm
+    );
+    assert!(asm.contains("mov rcx, r12"), "{}", asm);
+    assert!(asm.contains("call WriteFile"), "{}", asm);
+    assert!(!asm.contains("call printf"), "{}", asm);
+    assert!(asm.contains(".section .rdata,\"dr\"\n_str_0:"), "{}", asm);
+}
+
+#[test]
+fn test_target_macos_assembly() {
+    use std::process::Command;
+
+    // Apple targets get x86-64 code with Mach-O symbol names
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(&bas, "PRINT SIN(1)\n").unwrap();
+    for target in ["macos", "x86_64-apple"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+            .arg(&bas)
+            .args(["--target", target, "-S"])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let asm = std::fs::read_to_string(tmp.path().join("prog.s")).unwrap();
+        assert!(asm.contains(".globl _main\n"), "{}", asm);
+        assert!(asm.contains("call _sin\n"), "{}", asm);
+        assert!(asm.contains(".equ TERMIOS_LFLAG, 24"), "{}", asm);
+    }
+
+    // There is no arm64 code generation
+    let output = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg(&bas)
+        .args(["--target", "aarch64-apple", "-S"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unsupported target"), "{}", stderr);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_no_external_tools() {
+    use std::process::Command;
+
+    // With no PATH, neither as nor cc can be found; the compiler assembles
+    // and links by itself
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(&bas, "X# = SQR(16)\nPRINT X#\nPRINT LEN(\"abc\")\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg(&bas)
+        .env("PATH", "")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let run = Command::new(tmp.path().join("prog")).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "4\n3\n");
+}
+
+#[test]
+fn test_stdin_and_stdout() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    // - reads the program from standard input; -S then writes the
+    // assembly to standard output, as -S -o - does for a file
+    let xbasic64 = |args: &[&str], stdin: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    let out = xbasic64(&["-S", "-"], "PRINT 2 + 2\n");
+    assert!(out.status.success());
+    let asm = String::from_utf8_lossy(&out.stdout);
+    assert!(asm.starts_with(".intel_syntax noprefix\n"), "{}", asm);
+    assert!(asm.contains("# line 1: PRINT 2 + 2\n"), "{}", asm);
+
+    let out = xbasic64(&["--run", "-"], "PRINT 2 + 2\n");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "4\n");
+
+    // Errors name the input <stdin>
+    let out = xbasic64(&["--emit-ast", "-"], "PRINT (1\n");
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(err.starts_with("<stdin>:1:9: Parse error"), "{}", err);
+
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(&bas, "PRINT 1\n").unwrap();
+    let out = xbasic64(&["-S", "-o", "-", bas.to_str().unwrap()], "");
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("# line 1: PRINT 1\n"));
+    assert!(!tmp.path().join("prog.s").exists());
+}
+
+#[test]
+fn test_cli_errors() {
+    use std::process::Command;
+
+    let xbasic64 = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+            .args(args)
+            .output()
+            .unwrap();
+        (
+            out.status.success(),
+            String::from_utf8_lossy(&out.stdout).to_string(),
+            String::from_utf8_lossy(&out.stderr).to_string(),
+        )
+    };
+
+    let (ok, out, _) = xbasic64(&["--version"]);
+    assert!(ok);
+    assert_eq!(out, format!("xbasic64 {}\n", env!("CARGO_PKG_VERSION")));
+    let (ok, out, _) = xbasic64(&["--help"]);
+    assert!(ok);
+    assert!(
+        out.contains("Usage: xbasic64 [OPTIONS] [INPUTS]..."),
+        "{}",
+        out
+    );
+
+    // Conflicting or incomplete options are usage errors
+    for (args, message) in [
+        (&["-o", "-", "prog.bas"][..], "only assembly (-S)"),
+        (&["-S"][..], "required arguments were not provided"),
+        (&["-S", "-c", "prog.bas"][..], "cannot be used with"),
+        (&["--emit-ir", "-S", "prog.bas"][..], "cannot be used with"),
+        (
+            &["--emit-ast", "--emit-ir", "prog.bas"][..],
+            "cannot be used with",
+        ),
+        (&["--run", "-o", "x", "prog.bas"][..], "cannot be used with"),
+        (
+            &["--run", "--locale", "prog.bas"][..],
+            "cannot be used with",
+        ),
+        (
+            &["prog.bas", "-"][..],
+            "standard input (-) can't be compiled",
+        ),
+        (&["--emit-ir", "a.bas", "b.bas"][..], "take one input file"),
+        (&["-S", "-o", "x.s", "a.bas", "b.bas"][..], "-o can't name"),
+        (&["--watch", "-"][..], "--watch needs input files"),
+        (
+            &["--watch=run", "-c", "prog.bas"][..],
+            "needs an executable",
+        ),
+        (
+            &["--watch", "--emit-ast", "prog.bas"][..],
+            "cannot be used with",
+        ),
+    ] {
+        let (ok, _, err) = xbasic64(args);
+        assert!(!ok, "{:?}", args);
+        assert!(err.contains(message), "{:?}: {}", args, err);
+    }
+}
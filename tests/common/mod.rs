@@ -5,7 +5,8 @@
 
 use std::fs;
 use std::io::Write;
-use std::process::{Command, Stdio};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
 use tempfile::TempDir;
 
 pub fn compile_and_run(source: &str) -> Result<String, String> {
@@ -13,6 +14,15 @@ pub fn compile_and_run(source: &str) -> Result<String, String> {
 }
 
 pub fn compile_and_run_with_stdin(source: &str, stdin_input: &str) -> Result<String, String> {
+    compile_and_run_full(source, &[], stdin_input)
+}
+
+/// Compile with extra compiler arguments (e.g. "--explicit") and run
+pub fn compile_and_run_with_args(source: &str, args: &[&str]) -> Result<String, String> {
+    compile_and_run_full(source, args, "")
+}
+
+fn compile_and_run_full(source: &str, args: &[&str], stdin_input: &str) -> Result<String, String> {
     let tmp = TempDir::new().map_err(|e| e.to_string())?;
     let bas_file = tmp.path().join("test.bas");
     let exe_file = tmp.path().join("test");
@@ -24,6 +34,7 @@ pub fn compile_and_run_with_stdin(source: &str, stdin_input: &str) -> Result<Str
         .arg(&bas_file)
         .arg("-o")
         .arg(&exe_file)
+        .args(args)
         .output()
         .map_err(|e| format!("Failed to run compiler: {}", e))?;
 
@@ -112,6 +123,51 @@ where
     Ok((String::from_utf8_lossy(&run_output.stdout).to_string(), tmp))
 }
 
+/// Run the compiler on a source file with the given arguments, without
+/// running anything it builds; returns its stdout
+pub fn run_compiler(source: &str, args: &[&str]) -> Result<String, String> {
+    let tmp = TempDir::new().map_err(|e| e.to_string())?;
+    let bas_file = tmp.path().join("test.bas");
+    fs::write(&bas_file, source).map_err(|e| e.to_string())?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg(&bas_file)
+        .args(args)
+        .current_dir(tmp.path())
+        .output()
+        .map_err(|e| format!("Failed to run compiler: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Compilation failed:\nstdout: {}\nstderr: {}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Run the compiler in `dir` with the given arguments
+pub fn run_xbasic64(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Write `files`, named relative to a new temp directory, and run the
+/// compiler there
+pub fn compile_files(files: &[(&str, &str)], args: &[&str]) -> (Output, TempDir) {
+    let tmp = TempDir::new().unwrap();
+    for (name, text) in files {
+        let path = tmp.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    (run_xbasic64(tmp.path(), args), tmp)
+}
+
 /// Normalize line endings for cross-platform test assertions (CRLF -> LF)
 pub fn normalize_output(s: &str) -> String {
     s.trim().replace("\r\n", "\n")
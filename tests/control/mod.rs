@@ -3,7 +3,7 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-use crate::common::compile_and_run;
+use crate::common::{compile_and_run, compile_and_run_with_args};
 
 #[test]
 fn test_for_loops() {
@@ -22,6 +22,95 @@ FOR I = 3 TO 1 STEP -1: PRINT I: NEXT I
     assert_eq!(&lines[7..10], &["3", "2", "1"], "for step-");
 }
 
+#[test]
+fn test_integer_for_loops() {
+    // INTEGER and LONG counters with constant steps, nested loops, a loop
+    // that runs zero times, and the variable's value after the loop
+    let output = compile_and_run(
+        r#"
+FOR I% = 1 TO 3: PRINT I%;: NEXT I%
+PRINT
+FOR J& = 10 TO 1 STEP -3: PRINT J&;: NEXT J&
+PRINT
+FOR I% = 5 TO 1: PRINT "never": NEXT I%
+PRINT I%
+FOR I% = 1 TO 2
+    FOR J& = 1 TO 3 STEP 2: PRINT I% * 10 + J&;: NEXT J&
+NEXT I%
+PRINT
+FOR I% = 32765 TO 32767: NEXT I%
+PRINT "wrapped"
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(
+        lines,
+        vec!["123", "10741", "5", "11132123", "wrapped"],
+        "integer for"
+    );
+}
+
+#[test]
+fn test_for_loop_variable_changes() {
+    // The body assigns the counter; a procedure with its own loops is called
+    // from inside a loop; the variable is SINGLE or the step is computed
+    let output = compile_and_run(
+        r#"
+SUB Inner
+    FOR K% = 1 TO 2
+        FOR L% = 1 TO 2: PRINT K% * L%;: NEXT L%
+    NEXT K%
+END SUB
+FOR I% = 1 TO 10
+    PRINT I%;
+    I% = I% * 2
+NEXT I%
+PRINT
+FOR I% = 1 TO 3: Inner: NEXT I%
+PRINT
+FOR X! = 0 TO 1 STEP 0.25: PRINT X!;: NEXT X!
+PRINT
+S = 2
+FOR I% = 1 TO 6 STEP S: PRINT I%;: NEXT I%
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["137", "122412241224", "00.250.50.751", "135"]);
+}
+
+#[test]
+fn test_jumps_out_of_and_into_loops() {
+    // GOTO out of a loop many times over; RETURN from inside a loop in a
+    // subroutine called from inside another; GOTO out and back to NEXT
+    let source = r#"
+FOR N = 1 TO 5000
+    FOR I = 1 TO 10
+        IF I = 3 THEN 20
+    NEXT I
+20 NEXT N
+PRINT I; N
+FOR I = 1 TO 3: GOSUB 100: NEXT I
+PRINT
+FOR K = 1 TO 3
+    GOTO 300
+250 NEXT K
+FOR X = 1 TO 2 STEP 0.5: PRINT X;: NEXT X
+PRINT
+END
+100 FOR J = 1 TO 5
+    PRINT J;
+    IF J = I THEN RETURN
+110 NEXT J
+RETURN
+300 PRINT K;: GOTO 250
+"#;
+    let output = compile_and_run(source).unwrap();
+    assert_eq!(output, "35001\n112123\n12311.52\n");
+    assert_eq!(compile_and_run_with_args(source, &["-O"]).unwrap(), output);
+}
+
 #[test]
 fn test_while_loop() {
     let output = compile_and_run(
@@ -103,6 +192,58 @@ END IF
     assert_eq!(lines[2], "two", "elseif");
 }
 
+#[test]
+fn test_single_line_if() {
+    // THEN/ELSE with a line number, and colon-separated statements per branch
+    let output = compile_and_run(
+        r#"
+10 X = 2
+20 IF X = 2 THEN 40 ELSE 50
+30 PRINT "skipped"
+40 PRINT "forty": IF X > 5 THEN 60
+50 IF X = 2 THEN A = 1: B = 2 ELSE A = 3: B = 4
+55 PRINT A; B: IF X = 2 THEN X = 9: GOTO 50
+60 FOR I = 1 TO 3: IF I = 2 THEN PRINT "two": NEXT I
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["forty", "12", "34", "two"]);
+}
+
+#[test]
+fn test_short_circuit() {
+    // The right operand of ANDALSO/ORELSE only runs when the left doesn't
+    // decide the result, so the guard keeps A(I) in bounds
+    let output = compile_and_run(
+        r#"
+FUNCTION Noisy(X)
+    PRINT "eval";
+    Noisy = X
+END FUNCTION
+DIM A(3)
+A(3) = 5
+N = 3
+I = 4
+IF I <= N ANDALSO A(I) <> 0 THEN PRINT "bad" ELSE PRINT "guarded"
+I = 3
+IF I <= N ANDALSO A(I) <> 0 THEN PRINT "found"
+IF I = 3 ORELSE A(99) THEN PRINT "either"
+WHILE NOT (I > 5 ORELSE I = 0)
+    I = I + 1
+WEND
+PRINT I
+PRINT 0 ANDALSO Noisy(1); 2 ORELSE Noisy(0); 1 ANDALSO Noisy(2); 0 ORELSE Noisy(0)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(
+        lines,
+        vec!["guarded", "found", "either", "6", "0-1eval-1eval0"]
+    );
+}
+
 #[test]
 fn test_goto_gosub() {
     // Test GOTO, GOSUB/RETURN, ON GOTO
@@ -170,6 +311,95 @@ END SELECT
     assert_eq!(lines[1], "other", "case else");
 }
 
+#[test]
+fn test_select_case_dispatch() {
+    // Enough integer cases go through a jump table (dense) or a binary
+    // search (sparse); the first matching case wins and a fraction matches
+    // no case
+    let output = compile_and_run(
+        r#"
+FOR I = -1 TO 6
+    SELECT CASE I
+        CASE 0
+            PRINT "zero ";
+        CASE 1
+            PRINT "one ";
+        CASE 2
+            PRINT "two ";
+        CASE 2
+            PRINT "again ";
+        CASE 4
+            PRINT "four ";
+        CASE ELSE
+            PRINT "other ";
+    END SELECT
+NEXT
+PRINT
+FOR I% = 1 TO 5
+    READ N&
+    SELECT CASE N&
+        CASE -100000
+            PRINT "low ";
+        CASE 7
+            PRINT "seven ";
+        CASE 1000
+            PRINT "k ";
+        CASE 99999
+            PRINT "high ";
+    END SELECT
+NEXT
+PRINT
+X = 1.5
+SELECT CASE X
+    CASE 0
+        PRINT "0"
+    CASE 1
+        PRINT "1"
+    CASE 2
+        PRINT "2"
+    CASE 3
+        PRINT "3"
+    CASE ELSE
+        PRINT "fraction"
+END SELECT
+DATA 99999, 8, -100000, 7, 1000
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().map(str::trim_end).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "other zero one two other four other other",
+            "high low seven k",
+            "fraction"
+        ]
+    );
+}
+
+#[test]
+fn test_on_goto_jump_table() {
+    // Many targets go through a jump table; out of range falls through
+    let output = compile_and_run(
+        r#"
+10 FOR I = 0 TO 7
+20 ON I GOTO 100, 200, 300, 400, 500, 600
+30 PRINT "none"
+40 NEXT
+50 END
+100 PRINT "a": GOTO 40
+200 PRINT "b": GOTO 40
+300 PRINT "c": GOTO 40
+400 PRINT "d": GOTO 40
+500 PRINT "e": GOTO 40
+600 PRINT "f": GOTO 40
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["none", "a", "b", "c", "d", "e", "f", "none"]);
+}
+
 #[test]
 fn test_end_stop() {
     // Test END and STOP statements
@@ -234,3 +464,183 @@ RETURN
         "nested gosub"
     );
 }
+
+#[test]
+fn test_gosub_in_procedures() {
+    // Line numbers are local to each procedure, so 100 and 200 appear three times
+    let output = compile_and_run(
+        r#"
+SUB Report(N)
+    T = 0
+    FOR I = 1 TO N
+        GOSUB 100
+    NEXT I
+    PRINT T
+    GOTO 200
+100 T = T + I
+    RETURN
+200 END SUB
+
+FUNCTION Twice(X)
+    GOSUB 100
+    Twice = R
+    GOTO 200
+100 R = X * 2
+    RETURN
+200 END FUNCTION
+
+GOSUB 100
+Report 4
+PRINT Twice(21)
+GOTO 200
+100 PRINT "main"
+RETURN
+200 PRINT "done"
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["main", "10", "42", "done"]);
+}
+
+#[test]
+fn test_gosub_stack_errors() {
+    // RETURN without GOSUB, in the main program and in a procedure called
+    // from a GOSUB (it may not pop the main program's return address), even
+    // in a program with no GOSUB at all
+    let sub_return = r#"
+SUB S
+    RETURN
+END SUB
+GOSUB 100
+END
+100 S
+RETURN
+"#;
+    let no_gosub = "SUB S\n    RETURN\nEND SUB\nS\n";
+    for source in ["PRINT 1\nRETURN", sub_return, no_gosub] {
+        let err = compile_and_run(source).unwrap_err();
+        assert!(err.contains("RETURN without GOSUB"), "{}: {}", source, err);
+    }
+    // Unbounded GOSUB recursion
+    let err = compile_and_run("10 GOSUB 10").unwrap_err();
+    assert!(err.contains("GOSUB stack overflow"), "{}", err);
+}
+
+#[test]
+fn test_control_flow_errors() {
+    // Bad jumps and unbalanced blocks are reported by name, before assembly,
+    // at the file, line and column they were found at
+    let cases = [
+        (
+            "10 GOTO 50",
+            "test.bas:1:4: Error: GOTO 50: undefined line number",
+        ),
+        (
+            "10 PRINT 1\n10 PRINT 2",
+            "test.bas:2:1: Error: Duplicate line number 10",
+        ),
+        (
+            "10 FOR I = 1 TO 3\n20 PRINT I",
+            "test.bas:1:4: Parse error: FOR without NEXT",
+        ),
+        (
+            "10 WHILE 1\n20 NEXT",
+            "test.bas:2:4: Parse error: NEXT without FOR",
+        ),
+        (
+            "FOR I = 1 TO 3\nFOR J = 1 TO 3\nNEXT I\nNEXT J",
+            "test.bas:3:1: Parse error: NEXT I doesn't match FOR J",
+        ),
+    ];
+    for (source, message) in cases {
+        let err = compile_and_run(source).unwrap_err();
+        assert!(err.contains(message), "{}", err);
+        assert!(!err.contains("_line_"), "{}", err);
+    }
+}
+
+#[test]
+fn test_all_jump_errors_reported() {
+    // Every bad jump is reported, not just the first
+    let err = compile_and_run("10 GOTO 99\n20 GOSUB 30\n").unwrap_err();
+    assert!(
+        err.contains("test.bas:1:4: Error: GOTO 99: undefined line number"),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains("test.bas:2:4: Error: GOSUB 30: undefined line number"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_error_shows_source_line() {
+    let err = compile_and_run("X = 1\nPRINT (X + 2\n").unwrap_err();
+    let expected = "test.bas:2:13: Parse error: Expected RParen, got Newline\n    2 | PRINT (X + 2\n      |             ^\n";
+    assert!(err.contains(expected), "{}", err);
+}
+
+#[test]
+fn test_deny_warnings() {
+    let source = "10 X = 5\n20 PRINT 1\n30 END\n40 PRINT 2";
+    // Warnings alone don't stop compilation
+    assert_eq!(compile_and_run(source).unwrap(), "1\n");
+    let err = compile_and_run_with_args(source, &["-W"]).unwrap_err();
+    assert!(
+        err.contains("test.bas:1:4: Warning: Variable X is assigned but never used"),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains("test.bas:4:4: Warning: Unreachable code"),
+        "{}",
+        err
+    );
+    assert!(err.contains("2 warning(s) denied"), "{}", err);
+}
+
+#[test]
+fn test_error_format_json() {
+    let err = compile_and_run_with_args("PRINT (1 + 2\n", &["--error-format=json"]).unwrap_err();
+    assert!(
+        err.contains(
+            r#"test.bas","line":1,"column":13,"severity":"error","code":"parse-error","message":"Expected RParen, got Newline"}"#
+        ),
+        "{}",
+        err
+    );
+    // Warnings are JSON too, one object per line, without the source excerpt
+    let source = "10 X = 5\n20 END\n30 PRINT \"done\"";
+    let err = compile_and_run_with_args(source, &["-W", "--error-format", "json"]).unwrap_err();
+    let lines: Vec<&str> = err.lines().filter(|l| l.contains("\"severity\"")).collect();
+    assert_eq!(lines.len(), 3, "{}", err);
+    assert!(
+        lines[0].contains(r#""line":1,"column":4,"severity":"warning","code":"unused-variable""#)
+    );
+    assert!(lines[1].contains(r#""code":"unreachable-code","message":"Unreachable code"}"#));
+    assert!(
+        lines[2]
+            .contains(r#""line":null,"column":null,"severity":"error","code":"denied-warnings""#)
+    );
+    assert!(!err.contains(" | "), "{}", err);
+}
+
+#[test]
+fn test_color_diagnostics() {
+    let source = "PRINT (1 + 2\n";
+    // Not a terminal, so no color by default
+    let err = compile_and_run(source).unwrap_err();
+    assert!(!err.contains('\x1b'), "{}", err);
+    let err = compile_and_run_with_args(source, &["--color=always"]).unwrap_err();
+    assert!(
+        err.contains("\x1b[1;31mParse error\x1b[0m: \x1b[1mExpected RParen, got Newline\x1b[0m"),
+        "{}",
+        err
+    );
+    assert!(err.contains("\x1b[1;31m^\x1b[0m"), "{}", err);
+    let err = compile_and_run_with_args(source, &["--color", "never"]).unwrap_err();
+    assert!(!err.contains('\x1b'), "{}", err);
+}
@@ -26,3 +26,40 @@ PRINT D
     assert_eq!(lines[0], "60", "data read sum");
     assert_eq!(lines[1], "10", "restore reads first data");
 }
+
+#[test]
+fn test_read_array_elements() {
+    // READ into numeric and string array elements, and string variables
+    let output = compile_and_run(
+        r#"
+DIM A(3), N$(2, 2)
+DATA 1.5, 2, 3, "one", "two, too"
+FOR I = 1 TO 3: READ A(I): NEXT I
+READ N$(1, 2), S$
+PRINT A(1) + A(2) + A(3)
+PRINT N$(1, 2); "/"; S$; "/"; LEN(S$)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["6.5", "one/two, too/8"]);
+}
+
+#[test]
+fn test_restore_line() {
+    // RESTORE n continues from the first DATA at or after line n
+    let output = compile_and_run(
+        r#"
+10 DATA 1, 2
+20 DATA 3, 4
+30 PRINT "no data here"
+40 DATA 5
+RESTORE 20: READ A: PRINT A
+RESTORE 30: READ A: PRINT A
+RESTORE 10: READ A, B, C: PRINT A; B; C
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["no data here", "3", "5", "123"]);
+}
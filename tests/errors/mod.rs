@@ -0,0 +1,296 @@
+//! Runtime error tests: the message, with the BASIC line number, on stderr
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run, run_compiler};
+use std::fs;
+use tempfile::TempDir;
+
+/// Run a program that should stop with a runtime error, returning what it
+/// reported
+fn run_error(source: &str) -> String {
+    match compile_and_run(source) {
+        Ok(output) => panic!("no runtime error, output was: {}", output),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn test_subscript_out_of_range() {
+    let source = r#"
+10 DIM A(5), B(2, 3)
+20 I = 6
+230 A(I) = 1
+"#;
+    let err = run_error(source);
+    assert!(err.contains("Subscript out of range in 230"), "{}", err);
+
+    // Reads, negative subscripts, the second dimension, and the wrong number
+    // of subscripts
+    for bad in ["PRINT A(6)", "PRINT A(-1)", "B(1, 4) = 0", "PRINT A(1, 1)"] {
+        let source = format!("10 DIM A(5), B(2, 3)\n20 {}\n", bad);
+        let err = run_error(&source);
+        assert!(err.contains("Subscript out of range in 20"), "{}", err);
+    }
+}
+
+#[test]
+fn test_division_by_zero() {
+    for expr in ["1 / Z", "7 \\ Z%", "7 MOD Z&"] {
+        let source = format!("10 Z = 0: Z% = 0: Z& = 0\n20 PRINT {}\n", expr);
+        let err = run_error(&source);
+        assert!(err.contains("Division by zero in 20"), "{}", err);
+    }
+}
+
+#[test]
+fn test_illegal_function_call() {
+    // ASC of an empty string or past the end, CHR$ outside 0 to 255,
+    // SCREEN off the 25x80 text screen, and STICK or STRIG out of range
+    for expr in [
+        "ASC(\"\")",
+        "ASC(\"ab\", 3)",
+        "ASC(\"ab\", 0)",
+        "CHR$(256)",
+        "CHR$(-1)",
+        "SCREEN(0, 1)",
+        "SCREEN(26, 1)",
+        "SCREEN(1, 81)",
+        "STICK(4)",
+        "STRIG(-1)",
+        "STRIG(8)",
+    ] {
+        let source = format!("10 S$ = \"\"\n20 PRINT {}\n", expr);
+        let err = run_error(&source);
+        assert!(err.contains("Illegal function call in 20"), "{}", err);
+    }
+}
+
+#[test]
+fn test_bad_file_number() {
+    // Never opened, closed, and out of range; without line numbers the
+    // message stands alone
+    let err = run_error("PRINT #3, \"x\"");
+    assert!(err.contains("Bad file number\n"), "{}", err);
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("out.txt");
+    let source = format!(
+        "10 OPEN \"{}\" FOR OUTPUT AS #1\n20 CLOSE #1\n30 PRINT #1, 42\n",
+        path.display()
+    );
+    let err = run_error(&source);
+    assert!(err.contains("Bad file number in 30"), "{}", err);
+    let source = format!("10 OPEN \"{}\" FOR OUTPUT AS #16", path.display());
+    let err = run_error(&source);
+    assert!(err.contains("Bad file number in 10"), "{}", err);
+}
+
+#[test]
+fn test_file_table_errors() {
+    // A number in use must be closed before OPEN reuses it, and every file
+    // statement, CLOSE included, needs an open file
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("table.txt");
+    let source = format!(
+        "10 OPEN \"{0}\" FOR OUTPUT AS #1\n20 OPEN \"{0}\" FOR INPUT AS #1\n",
+        path.display()
+    );
+    let err = run_error(&source);
+    assert!(err.contains("File already open in 20"), "{}", err);
+
+    let statements = [
+        "CLOSE #2",
+        "PRINT #2, 1",
+        "WRITE #2, 1",
+        "PRINT #2, USING \"##\"; 1",
+        "WIDTH #2, 20",
+        "INPUT #2, A",
+        "FLUSH #2",
+        "SEEK #2, 1",
+        "PRINT SEEK(2)",
+        "LOCK #2",
+    ];
+    for statement in statements {
+        let source = format!(
+            "10 OPEN \"{}\" FOR OUTPUT AS #2\n20 CLOSE #2\n30 {}\n",
+            path.display(),
+            statement
+        );
+        let err = run_error(&source);
+        assert!(
+            err.contains("Bad file number in 30"),
+            "{}: {}",
+            statement,
+            err
+        );
+    }
+
+    // A closed number can be opened again
+    let source = format!(
+        "OPEN \"{0}\" FOR OUTPUT AS #1\nPRINT #1, 7\nCLOSE #1\n\
+         OPEN \"{0}\" FOR INPUT AS #1\nINPUT #1, N\nPRINT N\n",
+        path.display()
+    );
+    assert_eq!(compile_and_run(&source).unwrap(), "7\n");
+}
+
+#[test]
+fn test_file_open_mode_errors() {
+    // OPEN FOR INPUT of a missing file, and INPUT # or LINE INPUT # of one
+    // opened FOR OUTPUT or APPEND; --run stops the same way
+    let tmp = TempDir::new().unwrap();
+    let missing = tmp.path().join("missing.txt");
+    let written = tmp.path().join("written.txt");
+    let mut cases = vec![(
+        format!("10 OPEN \"{}\" FOR INPUT AS #1\n", missing.display()),
+        "File not found in 10",
+    )];
+    for mode in ["OUTPUT", "APPEND"] {
+        for read in ["INPUT #1, A$", "LINE INPUT #1, A$", "INPUT #1, N"] {
+            let source = format!(
+                "10 OPEN \"{}\" FOR {} AS #1\n20 {}\n",
+                written.display(),
+                mode,
+                read
+            );
+            cases.push((source, "Bad file mode in 20"));
+        }
+    }
+    for (source, message) in &cases {
+        let err = run_error(source);
+        assert!(err.contains(message), "{}: {}", source, err);
+        let err = run_compiler(source, &["--run"]).unwrap_err();
+        assert!(err.contains(message), "{}: {}", source, err);
+    }
+    assert!(!missing.exists());
+}
+
+#[test]
+fn test_error_flushes_files() {
+    // What is waiting in a file's buffer is written when an error ends the
+    // program
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("log.txt");
+    let source = format!(
+        "10 OPEN \"{}\" FOR OUTPUT AS #1\n20 PRINT #1, \"before\"\n30 PRINT 1 / 0\n",
+        path.display()
+    );
+    let err = run_error(&source);
+    assert!(err.contains("Division by zero in 30"), "{}", err);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "before\n");
+}
+
+#[test]
+fn test_print_using_errors() {
+    // A value of the wrong type for its field, and a format without fields
+    let err = run_error("10 PRINT USING \"###\"; \"abc\"\n");
+    assert!(err.contains("Type mismatch in 10"), "{}", err);
+    let err = run_error("10 PRINT USING \"&\"; 1\n");
+    assert!(err.contains("Type mismatch in 10"), "{}", err);
+    let err = run_error("10 PRINT USING \"abc\"; 1\n");
+    assert!(err.contains("Illegal function call in 10"), "{}", err);
+}
+
+#[test]
+fn test_seek_errors() {
+    // Positions start at 1, and only disk files have one
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("seek.txt");
+    let source = format!(
+        "10 OPEN \"{}\" FOR OUTPUT AS #1\n20 SEEK #1, 0\n",
+        path.display()
+    );
+    let err = run_error(&source);
+    assert!(err.contains("Illegal function call in 20"), "{}", err);
+    let err = run_error("10 OPEN \"SCRN:\" FOR OUTPUT AS #1\n20 SEEK #1, 1\n");
+    assert!(err.contains("Bad file mode in 20"), "{}", err);
+    let err = run_error("10 OPEN \"PIPE:cat\" FOR OUTPUT AS #1\n20 PRINT SEEK(1)\n");
+    assert!(err.contains("Bad file mode in 20"), "{}", err);
+}
+
+#[test]
+fn test_dict_errors() {
+    // Only DICTNEW's handles name dictionaries, and keys are numbered from 1
+    let err = run_error("10 D = DICTNEW()\n20 DICTSET D + 1, \"k\", \"v\"\n");
+    assert!(err.contains("Illegal function call in 20"), "{}", err);
+    let err = run_error("10 PRINT DICTCOUNT(0)\n");
+    assert!(err.contains("Illegal function call in 10"), "{}", err);
+    for n in ["0", "2", "-1"] {
+        let source = format!(
+            "10 D = DICTNEW()\n20 DICTSET D, \"k\", \"v\"\n30 PRINT DICTKEY$(D, {})\n",
+            n
+        );
+        let err = run_error(&source);
+        assert!(err.contains("Illegal function call in 30"), "{}", err);
+    }
+    let source = "10 FOR I = 1 TO 300\n20 D = DICTNEW()\n30 NEXT\n";
+    let err = run_error(source);
+    assert!(err.contains("Out of memory in 20"), "{}", err);
+}
+
+#[test]
+fn test_lock_range_errors() {
+    // Records are numbered from 1, and a range can't run backwards
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("lock.txt");
+    for range in ["0", "5 TO 2", "-1 TO 3"] {
+        let source = format!(
+            "10 OPEN \"{}\" FOR OUTPUT AS #1\n20 LOCK #1, {}\n",
+            path.display(),
+            range
+        );
+        let err = run_error(&source);
+        assert!(err.contains("Illegal function call in 20"), "{}", err);
+    }
+    let err = run_error("10 UNLOCK #2\n");
+    assert!(err.contains("Bad file number in 10"), "{}", err);
+}
+
+#[test]
+fn test_error_line_after_return() {
+    // After a GOSUB or procedure call returns, errors report the caller's line
+    let source = r#"
+SUB Nothing
+END SUB
+10 DIM A(2)
+20 GOSUB 100: Nothing: A(3) = 1
+100 X = 1
+110 RETURN
+"#;
+    let err = run_error(source);
+    assert!(err.contains("Subscript out of range in 20"), "{}", err);
+}
+
+#[test]
+fn test_not_an_array() {
+    // UBOUND and LBOUND of a scalar or undeclared name is a compile error,
+    // not a crash in code generation
+    for source in [
+        "X = 1\nPRINT UBOUND(X)\n",
+        "PRINT LBOUND(Y, 1)\n",
+        "SUB S\nPRINT UBOUND(Z)\nEND SUB\nS\n",
+    ] {
+        for args in [&["--check"][..], &[]] {
+            let err = run_compiler(source, args).unwrap_err();
+            assert!(err.contains("is not an array"), "{}", err);
+            assert!(!err.contains("panicked"), "{}", err);
+        }
+    }
+    let output = compile_and_run("DIM A(4)\nPRINT UBOUND(A)\n").unwrap();
+    assert_eq!(output.trim(), "4");
+}
+
+#[test]
+fn test_array_parameter_not_an_array() {
+    // A scalar or undeclared name passed for an A() parameter
+    let procs = "SUB S(A())\nPRINT UBOUND(A)\nEND SUB\nFUNCTION F(A())\nF = 1\nEND FUNCTION\n";
+    for call in ["X = 1\nS X", "CALL S(Y)", "PRINT F(Z)"] {
+        let source = format!("{}{}\n", procs, call);
+        for args in [&["--check"][..], &[]] {
+            let err = run_compiler(&source, args).unwrap_err();
+            assert!(err.contains("is not an array"), "{}", err);
+            assert!(!err.contains("panicked"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Event trapping and input device tests (ON KEY, ON TIMER, ON BREAK,
+//! ON MOUSE, STICK, STRIG)
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run, compile_and_run_with_stdin};
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+// Keys arrive on stdin as ANSI/xterm escape sequences
+const F1: &str = "\x1bOP";
+const F12: &str = "\x1b[24~";
+const RIGHT: &str = "\x1b[C";
+
+#[test]
+fn test_on_key_gosub() {
+    let source = r#"
+KEY OFF
+ON KEY(1) GOSUB 100
+KEY(1) ON
+WHILE N = 0 AND T < 10000000
+    T = T + 1
+WEND
+PRINT "hits"; N
+END
+100 PRINT "F1 pressed"
+N = N + 1
+RETURN
+"#;
+    let output = compile_and_run_with_stdin(source, F1).unwrap();
+    assert_eq!(output, "F1 pressed\nhits1\n");
+}
+
+#[test]
+fn test_key_trap_all() {
+    let source = r#"
+ON KEY(13) GOSUB 100
+ON KEY(31) GOSUB 200
+KEY(0) ON
+WHILE N < 2 AND T < 10000000
+    T = T + 1
+WEND
+PRINT "hits"; N
+END
+100 PRINT "right"
+N = N + 1
+RETURN
+200 PRINT "F12"
+N = N + 1
+RETURN
+"#;
+    let input = format!("{}{}", F12, RIGHT);
+    let output = compile_and_run_with_stdin(source, &input).unwrap();
+    // Other keys can interrupt a running handler, so the order may vary
+    assert!(output.contains("right\n"), "got: {}", output);
+    assert!(output.contains("F12\n"), "got: {}", output);
+    assert!(output.ends_with("hits2\n"), "got: {}", output);
+}
+
+#[test]
+fn test_key_stop_defers_handler() {
+    // A key pressed while stopped runs the handler once the trap is ON
+    let source = r#"
+ON KEY(1) GOSUB 100
+KEY(1) STOP
+FOR I = 1 TO 2000000: NEXT I
+PRINT "before"
+KEY(1) ON
+PRINT "after"
+END
+100 PRINT "handler"
+RETURN
+"#;
+    let output = compile_and_run_with_stdin(source, F1).unwrap();
+    assert_eq!(output, "before\nhandler\nafter\n");
+}
+
+#[test]
+fn test_key_off_forgets_keys() {
+    // OFF drops a key remembered while stopped
+    let source = r#"
+ON KEY(1) GOSUB 100
+KEY(1) STOP
+FOR I = 1 TO 2000000: NEXT I
+KEY(1) OFF
+KEY(1) ON
+FOR I = 1 TO 1000: NEXT I
+PRINT "done"
+END
+100 PRINT "handler"
+RETURN
+"#;
+    let output = compile_and_run_with_stdin(source, F1).unwrap();
+    assert_eq!(output, "done\n");
+}
+
+#[test]
+fn test_key_rejects_bad_number() {
+    assert!(compile_and_run("KEY(15) ON").is_err());
+    assert!(compile_and_run("ON KEY(0) GOSUB 10\n10 END").is_err());
+}
+
+#[test]
+fn test_on_timer_gosub() {
+    let source = r#"
+T0 = TIMER()
+ON TIMER(1) GOSUB 100
+TIMER ON
+WHILE N < 2 AND TIMER() - T0 < 10
+WEND
+TIMER OFF
+PRINT N
+END
+100 N = N + 1
+RETURN
+"#;
+    let output = compile_and_run(source).unwrap();
+    assert_eq!(output.trim(), "2");
+}
+
+#[test]
+fn test_timer_stop_defers_handler() {
+    // The timer keeps counting while stopped; the event runs once it is ON
+    let source = r#"
+ON TIMER(1) GOSUB 100
+TIMER ON
+TIMER STOP
+T0 = TIMER()
+WHILE TIMER() - T0 < 3
+WEND
+PRINT "before"
+TIMER ON
+PRINT "after"
+END
+100 PRINT "tick"
+TIMER OFF
+RETURN
+"#;
+    let output = compile_and_run(source).unwrap();
+    assert_eq!(output, "before\ntick\nafter\n");
+}
+
+#[test]
+fn test_on_timer_rejects_bad_interval() {
+    assert!(compile_and_run("ON TIMER(0) GOSUB 10\n10 END").is_err());
+    assert!(compile_and_run("ON TIMER(86401) GOSUB 10\n10 END").is_err());
+}
+
+// Mouse reports arrive on stdin in the xterm SGR encoding:
+// ESC [ < button ; column ; row M (press or motion) or m (release)
+#[test]
+fn test_on_mouse_gosub() {
+    let source = r#"
+ON MOUSE GOSUB 100
+MOUSE ON
+WHILE N = 0 AND T < 10000000
+    T = T + 1
+WEND
+PRINT "clicks"; N
+END
+100 PRINT MOUSEX(); ","; MOUSEY(); ","; MOUSEB()
+N = N + 1
+RETURN
+"#;
+    // Motion without a button is not an event; the right button is
+    let input = "\x1b[<35;3;4M\x1b[<2;10;5M";
+    let output = compile_and_run_with_stdin(source, input).unwrap();
+    assert_eq!(output, "10,5,2\nclicks1\n");
+}
+
+#[test]
+fn test_mouse_polling() {
+    // MOUSE STOP reports the mouse without a handler; buttons are held
+    // until released, and the wheel doesn't change them
+    let source = r#"
+PRINT MOUSEX(); ","; MOUSEY(); ","; MOUSEB()
+MOUSE STOP
+WHILE MOUSEY() <> 9 AND T < 10000000
+    T = T + 1
+WEND
+PRINT MOUSEX(); ","; MOUSEY(); ","; MOUSEB()
+"#;
+    let input = "\x1b[<0;7;8M\x1b[<1;7;8M\x1b[<64;7;8M\x1b[<0;7;9m";
+    let output = compile_and_run_with_stdin(source, input).unwrap();
+    assert_eq!(output, "0,0,0\n7,9,4\n");
+}
+
+#[test]
+fn test_stick_strig_without_joystick() {
+    // Sticks read as centred and buttons as up
+    let source = r#"
+PRINT STICK(0); STICK(1); STICK(2); STICK(3)
+FOR I = 0 TO 7: PRINT STRIG(I); : NEXT
+"#;
+    let tmp = TempDir::new().unwrap();
+    let device = tmp.path().join("none");
+    let output = run_with_joystick(source, &device);
+    assert_eq!(output, "100100100100\n00000000");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_stick_strig_read_joystick() {
+    // struct js_event: time, value, type (1 = button, 2 = axis, 0x80 =
+    // initial state), number
+    let event = |value: i16, kind: u8, number: u8| {
+        let mut bytes = vec![0; 4];
+        bytes.extend(value.to_le_bytes());
+        bytes.extend([kind, number]);
+        bytes
+    };
+    let events = [
+        event(-32768, 0x82, 0),
+        event(32767, 0x82, 1),
+        event(16384, 2, 2),
+        event(1, 1, 0),
+        event(0, 1, 0),
+        event(1, 1, 1),
+        event(1, 1, 5),
+    ]
+    .concat();
+    let tmp = TempDir::new().unwrap();
+    let device = tmp.path().join("js0");
+    fs::write(&device, events).unwrap();
+    let source = r#"
+PRINT STICK(0); ","; STICK(1); ","; STICK(2); ","; STICK(3)
+FOR I = 0 TO 7: PRINT STRIG(I); : NEXT
+PRINT
+PRINT STRIG(0); STRIG(2)
+"#;
+    // A1 was pressed and released, B1 is held down; a press is reported
+    // once by STRIG(0) or STRIG(2)
+    let output = run_with_joystick(source, &device);
+    assert_eq!(output, "1,200,150,100\n-10-1-10000\n00\n");
+}
+
+/// Compile `source` and run it with XBASIC64_JOYSTICK set to `device`
+fn run_with_joystick(source: &str, device: &std::path::Path) -> String {
+    let tmp = TempDir::new().unwrap();
+    let bas_file = tmp.path().join("test.bas");
+    let exe_file = tmp.path().join("test");
+    fs::write(&bas_file, source).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg(&bas_file)
+        .arg("-o")
+        .arg(&exe_file)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let run = Command::new(&exe_file)
+        .env("XBASIC64_JOYSTICK", device)
+        .output()
+        .unwrap();
+    assert!(run.status.success());
+    String::from_utf8_lossy(&run.stdout).to_string()
+}
+
+// Programs press Ctrl-C themselves by raising SIGINT
+#[cfg(unix)]
+const RAISE: &str = "DECLARE FUNCTION Raise% LIB \"c\" ALIAS \"raise\" (BYVAL S%)\n";
+
+#[cfg(unix)]
+#[test]
+fn test_on_break_gosub() {
+    let source = format!(
+        "{}{}",
+        RAISE,
+        r#"
+ON BREAK GOSUB 100
+BREAK ON
+R = Raise%(2)
+PRINT "after"
+BREAK STOP
+R = Raise%(2)
+PRINT "stopped"
+BREAK ON
+PRINT "end"
+END
+100 PRINT "break"
+RETURN
+"#
+    );
+    let output = compile_and_run(&source).unwrap();
+    assert_eq!(output, "break\nafter\nstopped\nbreak\nend\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_break_ends_program() {
+    // Untrapped, Ctrl-C stops the program with "Break in <line>", flushing
+    // its files first (here a pipe that copies to stderr)
+    let source = format!(
+        "{}{}",
+        RAISE,
+        r#"
+10 OPEN "PIPE:cat >&2" FOR OUTPUT AS #1
+20 PRINT #1, "saved"
+30 R = Raise%(2)
+40 PRINT "not reached"
+"#
+    );
+    let err = compile_and_run(&source).unwrap_err();
+    assert!(err.contains("Break in 30"), "{}", err);
+    assert!(err.contains("saved"), "{}", err);
+    assert!(!err.contains("not reached"), "{}", err);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_break_stops_plain_loop() {
+    // A program with no events or files stops the same way, and its
+    // buffered output is flushed
+    let tmp = TempDir::new().unwrap();
+    let bas_file = tmp.path().join("test.bas");
+    let exe_file = tmp.path().join("test");
+    fs::write(&bas_file, "10 PRINT \"go\"\n20 GOTO 20\n").unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg(&bas_file)
+        .arg("-o")
+        .arg(&exe_file)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let child = Command::new(&exe_file)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let status = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let run = child.wait_with_output().unwrap();
+    let err = String::from_utf8_lossy(&run.stderr);
+    assert!(err.contains("Break in 20"), "{}", err);
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "go\n");
+}
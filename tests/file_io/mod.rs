@@ -3,8 +3,16 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-use crate::common::compile_and_run_with_files;
+use crate::common::{
+    compile_and_run, compile_and_run_with_files, compile_and_run_with_stdin, run_compiler,
+};
 use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
 
 #[test]
 fn test_file_write() {
@@ -44,6 +52,52 @@ PRINT X + Y
     assert!(output.contains("30"), "Output was: {}", output);
 }
 
+#[test]
+fn test_file_read_array_elements() {
+    let source = r#"
+DIM V(2), W$(2)
+OPEN "input.txt" FOR INPUT AS #1
+INPUT #1, W$(2), V(1), V(2)
+CLOSE #1
+PRINT V(1) + V(2); W$(2)
+"#;
+
+    let (output, _tmp) = compile_and_run_with_files(source, |path| {
+        fs::write(path.join("input.txt"), "abc\n10\n20\n").map_err(|e| e.to_string())
+    })
+    .unwrap();
+    assert!(output.contains("30abc"), "Output was: {}", output);
+}
+
+#[test]
+fn test_file_read_fields() {
+    // INPUT # splits lines at commas, honors quotes, and reads a string after
+    // a number on the next line
+    let source = r#"
+OPEN "input.txt" FOR INPUT AS #1
+INPUT #1, A, B$, C
+INPUT #1, N
+INPUT #1, S$
+INPUT #1, Q$, R
+CLOSE #1
+PRINT A + C; B$; N; S$; "|"; Q$; "|"; R
+"#;
+
+    let (output, _tmp) = compile_and_run_with_files(source, |path| {
+        fs::write(
+            path.join("input.txt"),
+            "1, two ,3\n42\nforty two\n\"a, b\", 7\n",
+        )
+        .map_err(|e| e.to_string())
+    })
+    .unwrap();
+    assert!(
+        output.contains("4two42forty two|a, b|7"),
+        "Output was: {}",
+        output
+    );
+}
+
 #[test]
 fn test_file_append() {
     let source = r#"
@@ -67,3 +121,306 @@ PRINT "appended"
         assert_eq!(lines, vec!["Line 1", "Line 2", "Line 3"]);
     }
 }
+
+#[test]
+fn test_file_width() {
+    let source = r#"
+OPEN "wide.txt" FOR OUTPUT AS #1
+WIDTH #1, 10
+PRINT #1, "ABCDEFGHIJKLMNO"
+CLOSE #1
+OPEN "long.txt" FOR OUTPUT AS #1
+PRINT #1, "ABCDEFGHIJKLMNOPQRSTUVWXYZABCDEFGHIJKLMNOPQRSTUVWXYZABCDEFGHIJKLMNOPQRSTUVWXYZABCDEFGHIJ"
+CLOSE #1
+PRINT "done"
+"#;
+
+    let (output, tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    assert!(output.contains("done"), "Output was: {}", output);
+
+    let wide = fs::read_to_string(tmp.path().join("wide.txt")).unwrap();
+    assert_eq!(
+        wide.lines().collect::<Vec<_>>(),
+        vec!["ABCDEFGHIJ", "KLMNO"]
+    );
+    // Files default to width 255, so long lines are never wrapped
+    let long = fs::read_to_string(tmp.path().join("long.txt")).unwrap();
+    assert_eq!(long.lines().count(), 1);
+}
+
+#[test]
+fn test_device_files() {
+    // SCRN:, KYBD: and CONS: are the console, sharing PRINT's and INPUT's
+    // buffers; CLOSE leaves the console open
+    let source = r#"
+PRINT "before"
+OPEN "scrn:" FOR OUTPUT AS #1
+PRINT #1, "to screen"; 42
+OPEN "KYBD:" FOR INPUT AS #2
+INPUT #2, A$, N
+PRINT "got "; A$; N
+CLOSE #1: CLOSE #2
+INPUT "again"; B$
+OPEN "CONS:" FOR OUTPUT AS #1
+PRINT #1, B$
+"#;
+    let output = compile_and_run_with_stdin(source, "hello, 7\nworld\n").unwrap();
+    assert_eq!(output, "before\nto screen42\ngot hello7\nagain? world\n");
+
+    // STDERR: writes to standard error; a device opened the wrong way is
+    // an error
+    let err = compile_and_run(
+        "OPEN \"StdErr:\" FOR OUTPUT AS #3\nPRINT #3, \"to stderr\"\nOPEN \"SCRN:\" FOR INPUT AS #4\n",
+    )
+    .unwrap_err();
+    assert!(err.contains("to stderr\nBad file mode"), "{}", err);
+    let err = compile_and_run("OPEN \"KYBD:\" FOR APPEND AS #1\n").unwrap_err();
+    assert!(err.contains("Bad file mode"), "{}", err);
+}
+
+#[test]
+fn test_file_print_formatting() {
+    // PRINT # shares the console's zones, TAB and SPC, and takes USING;
+    // WRITE # quotes strings and separates items with commas
+    let source = r##"
+OPEN "report.txt" FOR OUTPUT AS #1
+PRINT #1, "Item", "Qty", "Price"
+PRINT #1, "Apple"; TAB(9); "x"; SPC(2); "y"
+PRINT #1, USING "\      \###  $$#.##"; "Widgets"; 3; 2.5
+WRITE #1, "a", 1, 2.5, "b c"
+WRITE #1,
+CLOSE #1
+PRINT "done"
+"##;
+    let (output, tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    assert_eq!(output, "done\n");
+    let contents = fs::read_to_string(tmp.path().join("report.txt")).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "Item          Qty           Price",
+            "Apple   x  y",
+            "Widgets   3   $2.50",
+            "\"a\",1,2.5,\"b c\"",
+            "",
+        ]
+    );
+}
+
+#[test]
+fn test_file_flush() {
+    // Output waits in the file's buffer until FLUSH #, CLOSE or the end of
+    // the program
+    let source = r#"
+OPEN "log.txt" FOR OUTPUT AS #1
+PRINT #1, "first"
+OPEN "log.txt" FOR INPUT AS #2
+INPUT #2, A$
+PRINT "["; A$; "]"
+FLUSH #1
+OPEN "log.txt" FOR INPUT AS #3
+INPUT #3, A$
+PRINT "["; A$; "]"
+PRINT #1, "last"
+"#;
+    let (output, tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    assert_eq!(output, "[]\n[first]\n");
+    let contents = fs::read_to_string(tmp.path().join("log.txt")).unwrap();
+    assert_eq!(contents, "first\nlast\n");
+
+    let err = compile_and_run("FLUSH #4\n").unwrap_err();
+    assert!(err.contains("Bad file number"), "{}", err);
+}
+
+#[test]
+fn test_file_seek() {
+    // SEEK # moves to a byte position (1 is the first), for writing and
+    // reading; SEEK() is where the next read or write happens, even in the
+    // middle of a line INPUT # has read
+    let source = r#"
+OPEN "seek.txt" FOR OUTPUT AS #1
+PRINT SEEK(1)
+PRINT #1, "alpha,beta"
+PRINT #1, "gamma"
+PRINT SEEK(#1)
+SEEK #1, 1
+PRINT #1, "ALPHA"
+PRINT SEEK(1)
+CLOSE #1
+OPEN "seek.txt" FOR INPUT AS #2
+INPUT #2, A$
+PRINT A$; SEEK(2)
+INPUT #2, A$
+PRINT A$; SEEK(2)
+SEEK #2, 8
+INPUT #2, A$
+PRINT A$; SEEK(2)
+SEEK #2, 1
+INPUT #2, A$
+PRINT A$
+"#;
+    let (output, tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    assert_eq!(output, "1\n18\n7\nALPHA7\nbeta12\neta12\nALPHA\n");
+    let contents = fs::read_to_string(tmp.path().join("seek.txt")).unwrap();
+    assert_eq!(contents, "ALPHA\nbeta\ngamma\n");
+
+    let err = compile_and_run("SEEK #4, 1\n").unwrap_err();
+    assert!(err.contains("Bad file number"), "{}", err);
+}
+
+#[test]
+fn test_file_lock() {
+    // A file one program has LOCKed can't be locked by another until the
+    // first UNLOCKs it
+    let tmp = TempDir::new().unwrap();
+    let data = tmp.path().join("shared.txt");
+    let holder_exe = tmp.path().join("holder");
+    // It reports on STDERR:, which isn't buffered, and waits for a line
+    let holder = format!(
+        r#"
+OPEN "{}" FOR OUTPUT AS #1
+OPEN "STDERR:" FOR OUTPUT AS #2
+PRINT #1, "held"
+LOCK #1
+PRINT #2, "locked"
+INPUT A$
+UNLOCK #1
+PRINT #2, "unlocked"
+INPUT A$
+"#,
+        data.display()
+    );
+    run_compiler(&holder, &["-o", holder_exe.to_str().unwrap()]).unwrap();
+    let mut child = Command::new(&holder_exe)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    assert_eq!(line, "locked\n");
+
+    let other = format!(
+        "OPEN \"{}\" FOR APPEND AS #1\nLOCK #1, 2 TO 5\nPRINT \"got it\"\nUNLOCK #1, 2 TO 5\n",
+        data.display()
+    );
+    let err = compile_and_run(&other).unwrap_err();
+    assert!(err.contains("Permission denied"), "{}", err);
+
+    // UNLOCK writes out what was printed before it
+    writeln!(stdin).unwrap();
+    line.clear();
+    stderr.read_line(&mut line).unwrap();
+    assert_eq!(line, "unlocked\n");
+    assert_eq!(fs::read_to_string(&data).unwrap(), "held\n");
+    assert_eq!(compile_and_run(&other).unwrap(), "got it\n");
+    writeln!(stdin).unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_pipe_files() {
+    // PIPE: for INPUT reads a command's output, for OUTPUT feeds its input;
+    // the command's output stays in order with the program's
+    let source = r#"
+OPEN "pipe:echo hello, 42; echo world" FOR INPUT AS #1
+INPUT #1, A$, N
+INPUT #1, B$
+CLOSE #1
+PRINT A$; N + 1; B$
+OPEN "PIPE:tr a-z A-Z" FOR OUTPUT AS #2
+PRINT #2, "shout"; 1
+CLOSE #2
+PRINT "done"
+"#;
+    let output = compile_and_run(source).unwrap();
+    assert_eq!(output, "hello43world\nSHOUT1\ndone\n");
+}
+
+#[test]
+fn test_tcp_files() {
+    // TCP: connects to a server; OPEN with no FOR reads and writes
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut line = String::new();
+        BufReader::new(stream.try_clone().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        write!(stream, "got {}", line.to_uppercase()).unwrap();
+        line
+    });
+    let source = format!(
+        "OPEN \"tcp:127.0.0.1:{}\" AS #1\nPRINT #1, \"ping\"; 7\nINPUT #1, A$\nCLOSE #1\nPRINT A$\n",
+        port
+    );
+    assert_eq!(compile_and_run(&source).unwrap(), "got PING7\n");
+    assert_eq!(server.join().unwrap(), "ping7\n");
+
+    // TCPL: waits for a client to connect
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let client = thread::spawn(move || {
+        let mut stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(20)),
+            }
+        };
+        stream.write_all(b"hello, 41\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        line
+    });
+    let source = format!(
+        "OPEN \"TCPL:{}\" FOR INPUT AS #1\nINPUT #1, A$, N\nPRINT #1, A$; N + 1\nCLOSE #1\nPRINT \"done\"\n",
+        port
+    );
+    assert_eq!(compile_and_run(&source).unwrap(), "done\n");
+    assert_eq!(client.join().unwrap(), "hello42\n");
+
+    // Files only go one way
+    let err = compile_and_run("OPEN \"data.txt\" AS #1\n").unwrap_err();
+    assert!(err.contains("Bad file mode"), "{}", err);
+}
+
+#[test]
+fn test_tcp_line_input() {
+    // LINE INPUT # reads whole lines from a socket, commas and all, and the
+    // rest of a line INPUT # is partway through; --run does the same
+    let source = r#"
+LINE INPUT #1, L$
+INPUT #1, A$
+LINE INPUT #1, R$
+LINE INPUT #1, E$
+CLOSE #1
+PRINT L$
+PRINT A$
+PRINT "["; R$; "]"; LEN(E$)
+"#;
+    for run in [false, true] {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"a, \"b\", c\r\nfirst, second\n").unwrap();
+        });
+        let open = format!("OPEN \"TCP:127.0.0.1:{}\" FOR INPUT AS #1", port);
+        let program = format!("{}{}", open, source);
+        let output = if run {
+            run_compiler(&program, &["--run"])
+        } else {
+            compile_and_run(&program)
+        };
+        assert_eq!(output.unwrap(), "a, \"b\", c\nfirst\n[ second]0\n");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+//! Source tool tests (xbasic64 fmt and renum)
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run, run_xbasic64};
+
+const MESSY: &str = "10 rem squares\n20 for i=1 to 3:print i*i;:next\n\
+                     30 if x=0 then print \"done\" ' always\n";
+
+const TIDY: &str = "10 REM squares\n20 FOR i = 1 TO 3: PRINT i * i;: NEXT i\n\
+                    30 IF x = 0 THEN PRINT \"done\" ' always\n";
+
+#[test]
+fn test_fmt_prints() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    let bas = tmp.path().join("prog.bas");
+    std::fs::write(&bas, MESSY).unwrap();
+    let out = run_xbasic64(tmp.path(), &["fmt", "prog.bas"]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), TIDY);
+    // The file is left alone, and the formatted program runs the same
+    assert_eq!(std::fs::read_to_string(&bas).unwrap(), MESSY);
+    assert_eq!(compile_and_run(TIDY), compile_and_run(MESSY));
+}
+
+#[test]
+fn test_fmt_write_and_check() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::write(tmp.path().join("messy.bas"), MESSY).unwrap();
+    std::fs::write(tmp.path().join("tidy.bas"), TIDY).unwrap();
+    let (messy, tidy) = ("messy.bas", "tidy.bas");
+
+    let out = run_xbasic64(tmp.path(), &["fmt", "--check", messy, tidy]);
+    assert!(!out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "messy.bas needs formatting\n"
+    );
+
+    let out = run_xbasic64(tmp.path(), &["fmt", "--write", messy, tidy]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "Formatted messy.bas\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(tmp.path().join(messy)).unwrap(),
+        TIDY
+    );
+
+    let out = run_xbasic64(tmp.path(), &["fmt", "--check", messy, tidy]);
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn test_fmt_errors() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::write(tmp.path().join("bad.bas"), "PRINT 1\nPRINT (2\n").unwrap();
+    let out = run_xbasic64(tmp.path(), &["fmt", "--error-format=json", "bad.bas"]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(err.contains(r#""file":"bad.bas","line":2"#), "{}", err);
+
+    for (args, message) in [
+        (&["fmt", "a.bas", "b.bas"][..], "fmt prints one file"),
+        (&["fmt", "--write", "-"][..], "can't rewrite standard input"),
+        (
+            &["fmt", "--write", "--check", "a.bas"][..],
+            "cannot be used with",
+        ),
+    ] {
+        let out = run_xbasic64(tmp.path(), args);
+        assert!(!out.status.success());
+        let err = String::from_utf8_lossy(&out.stderr);
+        assert!(err.contains(message), "{:?}: {}", args, err);
+    }
+}
+
+#[test]
+fn test_renum() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    let source = "5 GOSUB 7\n6 END\n7 PRINT \"hi\": RETURN\n";
+    std::fs::write(tmp.path().join("prog.bas"), source).unwrap();
+    let renumbered = "100 GOSUB 120\n110 END\n120 PRINT \"hi\": RETURN\n";
+
+    let out = run_xbasic64(tmp.path(), &["renum", "--start", "100", "prog.bas"]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), renumbered);
+    assert_eq!(compile_and_run(renumbered), compile_and_run(source));
+
+    let out = run_xbasic64(tmp.path(), &["renum", "-w", "--start", "100", "prog.bas"]);
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "Renumbered prog.bas\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(tmp.path().join("prog.bas")).unwrap(),
+        renumbered
+    );
+
+    std::fs::write(tmp.path().join("bad.bas"), "10 GOTO 20\n").unwrap();
+    let out = run_xbasic64(tmp.path(), &["renum", "bad.bas"]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(err.contains("GOTO 20: undefined line number"), "{}", err);
+}
@@ -0,0 +1,224 @@
+//! Graphics tests (SCREEN, PSET, LINE, CIRCLE, PAINT, DRAW, GET, PUT, POINT,
+//! PALETTE)
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run_with_files, normalize_output};
+use std::fs;
+
+/// Split a binary PPM image into (width, height, RGB pixel data)
+fn parse_ppm(data: &[u8]) -> (usize, usize, &[u8]) {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        let start = pos;
+        while !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+        pos += 1;
+    }
+    assert_eq!(fields[0], "P6");
+    assert_eq!(fields[3], "255");
+    let width: usize = fields[1].parse().unwrap();
+    let height: usize = fields[2].parse().unwrap();
+    (width, height, &data[pos..])
+}
+
+#[test]
+fn test_point_reads_back_drawing() {
+    let source = r#"
+SCREEN 13
+PSET (10, 10), 4
+PRINT POINT(10, 10); POINT(11, 10); POINT(-1, 5)
+LINE (20, 20)-(30, 30), 5, BF
+PRINT POINT(25, 25)
+LINE -(40, 20), 6
+PRINT POINT(40, 20)
+LINE (50, 50)-(60, 60), 7, B
+PRINT POINT(55, 50); POINT(55, 55)
+CIRCLE (100, 100), 20, 9
+PRINT POINT(120, 100); POINT(100, 80); POINT(100, 100)
+PRESET (10, 10)
+PRINT POINT(10, 10)
+"#;
+    let (output, _tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "40-1", "PSET and POINT");
+    assert_eq!(lines[1], "5", "filled box");
+    assert_eq!(lines[2], "6", "LINE from last point");
+    assert_eq!(lines[3], "70", "box outline only");
+    assert_eq!(lines[4], "990", "circle outline only");
+    assert_eq!(lines[5], "0", "PRESET uses background");
+}
+
+#[test]
+fn test_framebuffer_written_on_exit() {
+    let source = r#"
+SCREEN 13
+PSET (0, 0), 15
+PSET (319, 199), 1
+CLS
+PSET (1, 0), 4
+END
+"#;
+    let (_, tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    let data = fs::read(tmp.path().join("screen.ppm")).unwrap();
+    let (width, height, pixels) = parse_ppm(&data);
+    assert_eq!((width, height), (320, 200));
+    assert_eq!(pixels.len(), 320 * 200 * 3);
+    assert_eq!(&pixels[0..3], &[0, 0, 0], "CLS clears the framebuffer");
+    assert_eq!(&pixels[3..6], &[170, 0, 0], "color 4 is red");
+}
+
+#[test]
+fn test_display_and_screen_modes() {
+    // DISPLAY writes the current image; later drawing replaces it at exit
+    let source = r#"
+SCREEN 1
+PSET (0, 0)
+DISPLAY
+PRINT "shown"
+SCREEN 2
+PRINT POINT(0, 0)
+"#;
+    let (output, tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    assert_eq!(normalize_output(&output), "shown\n0");
+    let data = fs::read(tmp.path().join("screen.ppm")).unwrap();
+    let (width, height, _) = parse_ppm(&data);
+    assert_eq!((width, height), (640, 200));
+}
+
+#[test]
+fn test_paint_fills_to_border() {
+    let source = r#"
+SCREEN 13
+LINE (10, 10)-(50, 50), 1, B
+PAINT (20, 20), 2, 1
+PRINT POINT(20, 20); POINT(49, 49); POINT(10, 10); POINT(60, 60)
+CIRCLE (100, 100), 10, 12
+PAINT (100, 100), 13, 12
+PRINT POINT(100, 100); POINT(110, 100); POINT(90, 90)
+"#;
+    let (output, _tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "2210", "box interior filled, border kept");
+    assert_eq!(
+        lines[1], "13120",
+        "circle interior filled, outside untouched"
+    );
+}
+
+#[test]
+fn test_draw_commands() {
+    let source = r#"
+SCREEN 13
+DRAW "BM100,100 C4 U10 R10 D10 L10"
+PRINT POINT(100, 95); POINT(105, 90); POINT(110, 95); POINT(105, 100); POINT(105, 95)
+DRAW "BM200,100 C5 S8 R5 S4"
+PRINT POINT(209, 100); POINT(211, 100)
+DRAW "A1 BM150,150 C6 U5 A0"
+PRINT POINT(147, 150); POINT(150, 147)
+DRAW "BM250,10 C3 M+5,+5 NR4 D2"
+PRINT POINT(253, 13); POINT(259, 15); POINT(255, 17)
+S$ = "BM100,150 C1 R20 D20 L20 U20"
+DRAW S$ + " BM+5,+5 P9,1"
+PRINT POINT(110, 160); POINT(130, 160)
+"#;
+    let (output, _tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "44440", "square outline");
+    assert_eq!(lines[1], "50", "S8 doubles the distance");
+    assert_eq!(lines[2], "60", "A1 turns U into L");
+    assert_eq!(lines[3], "333", "relative M and N prefix");
+    assert_eq!(lines[4], "90", "P fills the enclosed square");
+}
+
+#[test]
+fn test_draw_rejects_unknown_command() {
+    let result = compile_and_run_with_files("SCREEN 13\nDRAW \"Q5\"", |_| Ok(()));
+    assert!(result.is_err(), "unknown DRAW command should fail");
+}
+
+#[test]
+fn test_get_and_put_sprite() {
+    let source = r#"
+SCREEN 13
+DIM S(20)
+LINE (0, 0)-(3, 3), 5, BF
+PSET (1, 1), 9
+GET (0, 0)-(3, 3), S
+PUT (100, 100), S, PSET
+PRINT POINT(100, 100); POINT(101, 101); POINT(103, 103); POINT(104, 104)
+PUT (100, 100), S
+PRINT POINT(100, 100); POINT(101, 101)
+PSET (50, 50), 3
+PUT (50, 50), S, OR
+PRINT POINT(50, 50)
+PUT (50, 50), S, AND
+PRINT POINT(50, 50)
+"#;
+    let (output, _tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "5950", "PSET copies the sprite");
+    assert_eq!(lines[1], "00", "XOR twice restores the background");
+    assert_eq!(lines[2], "7", "OR");
+    assert_eq!(lines[3], "5", "AND");
+}
+
+#[test]
+fn test_get_rejects_small_array() {
+    let result =
+        compile_and_run_with_files("SCREEN 13\nDIM T(1)\nGET (0, 0)-(3, 3), T", |_| Ok(()));
+    assert!(result.is_err(), "array too small for the image should fail");
+}
+
+#[test]
+fn test_illegal_screen_mode() {
+    let result = compile_and_run_with_files("SCREEN 5", |_| Ok(()));
+    assert!(result.is_err(), "unsupported mode should fail");
+}
+
+#[test]
+fn test_palette_remaps_colors() {
+    // VGA colors scale 0-63 to 0-255, RGB() colors are used as they are,
+    // and PALETTE alone restores the defaults
+    let source = r#"
+SCREEN 13
+PSET (0, 0), 1
+PSET (1, 0), 2
+PSET (2, 0), 3
+PSET (3, 0), 4
+PALETTE 4, 0
+PALETTE
+PALETTE 1, 63 + 42 * 256 + 21 * 65536
+PALETTE 2, RGB(10, 20, 300)
+PALETTE 3, &HFF405060
+PRINT RGB(255, 128, 1); RGB(-5, 0, 16.4); POINT(0, 0)
+"#;
+    let (output, tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    assert_eq!(output, "-32767-167772001\n");
+    let data = fs::read(tmp.path().join("screen.ppm")).unwrap();
+    let (_, _, pixels) = parse_ppm(&data);
+    assert_eq!(&pixels[0..3], &[255, 170, 85], "VGA color");
+    assert_eq!(&pixels[3..6], &[10, 20, 255], "RGB color");
+    assert_eq!(&pixels[6..9], &[64, 80, 96], "&HFFRRGGBB literal");
+    assert_eq!(&pixels[9..12], &[170, 0, 0], "restored default");
+}
+
+#[test]
+fn test_palette_rejects_bad_arguments() {
+    for source in [
+        "PALETTE",
+        "PALETTE 1, 0",
+        "SCREEN 13\nPALETTE 256, 0",
+        "SCREEN 13\nPALETTE -1, 0",
+        "SCREEN 13\nPALETTE 1, 64",
+        "SCREEN 13\nPALETTE 1, &H1000000",
+        "SCREEN 1\nPALETTE 4, 0",
+    ] {
+        let result = compile_and_run_with_files(source, |_| Ok(()));
+        assert!(result.is_err(), "{:?} should fail", source);
+    }
+}
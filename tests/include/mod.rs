@@ -0,0 +1,94 @@
+//! $INCLUDE tests: shared files spliced into a program
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run_with_files, compile_files};
+use std::fs;
+
+#[test]
+fn test_include_files() {
+    // Both spellings, nested includes found next to the including file
+    let source = r#"'$INCLUDE: 'common.bi'
+INCLUDE "util/strings.bas"
+GREET "World"
+PRINT SHOUT$("hi"); LIMIT
+"#;
+    let (out, _tmp) = compile_and_run_with_files(source, |dir| {
+        fs::create_dir(dir.join("util")).unwrap();
+        fs::write(
+            dir.join("common.bi"),
+            "LIMIT = 10\nSUB GREET(N$)\n    PRINT \"Hello, \"; N$\nEND SUB\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("util/strings.bas"),
+            "REM $INCLUDE: \"bang.bi\"\nFUNCTION SHOUT$(S$)\n    SHOUT$ = BANG$(S$ + S$)\nEND FUNCTION\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("util/bang.bi"),
+            "FUNCTION BANG$(S$)\n    BANG$ = S$ + \"!\"\nEND FUNCTION\n",
+        )
+        .unwrap();
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(out, "Hello, World\nhihi!10\n");
+}
+
+#[test]
+fn test_include_path() {
+    let files = [
+        ("main.bas", "'$INCLUDE: 'defs.bi'\nPRINT X\n"),
+        ("lib/defs.bi", "X = 1\n"),
+    ];
+    let (out, _tmp) = compile_files(&files, &["-S", "main.bas"]);
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(
+        err.contains("main.bas:1:1: Error: Include file not found: defs.bi"),
+        "{}",
+        err
+    );
+    let (out, _tmp) = compile_files(&files, &["-S", "main.bas", "-I", "lib"]);
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(out.status.success(), "{}", err);
+}
+
+#[test]
+fn test_include_errors() {
+    // Errors in an included file point into that file
+    let files = [
+        ("main.bas", "PRINT 1\nINCLUDE \"bad.bi\"\n"),
+        ("bad.bi", "X = 1\nPRINT (X\n"),
+    ];
+    let (out, _tmp) = compile_files(&files, &["-S", "main.bas"]);
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(err.contains("bad.bi:2:9: Parse error"), "{}", err);
+    assert!(err.contains("    2 | PRINT (X"), "{}", err);
+
+    // A file can't include itself, directly or through another
+    let files = [
+        ("main.bas", "INCLUDE \"a.bi\"\n"),
+        ("a.bi", "PRINT 1\n'$INCLUDE: 'b.bi'\n"),
+        ("b.bi", "  '$INCLUDE: 'a.bi'\n"),
+    ];
+    let (out, _tmp) = compile_files(&files, &["-S", "main.bas"]);
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(
+        err.contains("b.bi:1:3: Error: Include cycle: a.bi -> b.bi -> a.bi"),
+        "{}",
+        err
+    );
+
+    let (out, _tmp) = compile_files(
+        &[("main.bas", "'$INCLUDE common.bi\n")],
+        &["-S", "main.bas"],
+    );
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(!out.status.success());
+    assert!(err.contains("Expected '$INCLUDE: 'file'"), "{}", err);
+}
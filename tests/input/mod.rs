@@ -41,3 +41,81 @@ PRINT A$
     .unwrap();
     assert!(output.contains("Hello, World!"));
 }
+
+#[test]
+fn test_input_array_elements() {
+    // INPUT and LINE INPUT store into array elements
+    let output = compile_and_run_with_stdin(
+        r#"
+DIM A%(3), N$(2)
+FOR I = 1 TO 3: INPUT A%(I): NEXT I
+LINE INPUT N$(2)
+PRINT A%(1) + A%(2) + A%(3); N$(2)
+"#,
+        "1\n2\n3\nhello, world\n",
+    )
+    .unwrap();
+    assert!(output.contains("6hello, world"), "Output was: {}", output);
+}
+
+#[test]
+fn test_input_fields() {
+    // One line holds a comma-separated field per variable
+    let output = compile_and_run_with_stdin(
+        r#"
+INPUT "Coords"; X, Y
+PRINT X + Y
+"#,
+        "3, 4\n",
+    )
+    .unwrap();
+    assert!(output.starts_with("Coords? 7"), "Output was: {}", output);
+
+    // Quotes keep commas in a string; an empty field is 0 or ""
+    let output = compile_and_run_with_stdin(
+        r#"
+INPUT A$, N, B$
+PRINT A$; "|"; N; "|"; B$; "|"
+"#,
+        "\"Smith, J\", ,\n",
+    )
+    .unwrap();
+    assert!(
+        output.starts_with("? Smith, J|0||"),
+        "Output was: {}",
+        output
+    );
+}
+
+#[test]
+fn test_input_prompts() {
+    // A comma after the prompt leaves out the question mark
+    let output = compile_and_run_with_stdin(
+        r#"
+INPUT "Name: ", N$
+INPUT X
+PRINT N$; X
+"#,
+        "Ann\n5\n",
+    )
+    .unwrap();
+    assert!(output.starts_with("Name: ? Ann5"), "Output was: {}", output);
+}
+
+#[test]
+fn test_input_redo() {
+    // Bad numbers and wrong field counts ask for the line again
+    let output = compile_and_run_with_stdin(
+        r#"
+INPUT "Coords"; X, Y
+PRINT X * Y
+"#,
+        "abc, 1\n3\n3, 4, 5\n6, 7\n",
+    )
+    .unwrap();
+    let redo = "?Redo from start\n";
+    assert_eq!(
+        output,
+        format!("Coords? {redo}Coords? {redo}Coords? {redo}Coords? 42\n"),
+    );
+}
@@ -7,13 +7,26 @@ mod common;
 
 mod arithmetic;
 mod arrays;
+mod asm;
+mod cli;
 mod control;
 mod data;
+mod errors;
+mod events;
 mod file_io;
+mod fmt;
+mod graphics;
+mod include;
 mod input;
+mod library;
+mod lsp;
 mod math;
+mod memory;
+mod modules;
 mod print;
 mod procedures;
+mod run;
+mod spec;
 mod strings;
 mod types;
 mod variables;
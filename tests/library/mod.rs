@@ -0,0 +1,111 @@
+//! Library tests: compiling through the xbasic64 crate's Compiler API
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+use xbasic64::{Compiler, CompilerOptions, Output, Severity, Span};
+
+#[test]
+fn test_compile_to_executable() {
+    let tmp = TempDir::new().unwrap();
+    let bas = tmp.path().join("hello.bas");
+    fs::write(&bas, "FOR I = 1 TO 3\nPRINT \"Hello\"; I\nNEXT I\n").unwrap();
+
+    let mut compiler = Compiler::new(CompilerOptions::default());
+    let exe = compiler.compile_to_executable(&bas).unwrap();
+    assert_eq!(exe, tmp.path().join("hello"));
+    let out = Command::new(&exe).output().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "Hello1\nHello2\nHello3\n"
+    );
+}
+
+#[test]
+fn test_compile_steps() {
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("prog.o");
+    let path = path.to_str().unwrap();
+
+    let mut compiler = Compiler::new(CompilerOptions {
+        opt_level: 1,
+        ..CompilerOptions::default()
+    });
+    let source = compiler
+        .load("prog.bas", "PRINT 2 + 2\n".into(), None)
+        .unwrap();
+    let programs = vec![compiler.parse(&source).unwrap()];
+    let sources = [source];
+    compiler.check(&sources, &programs, false).unwrap();
+    let modules = compiler.generate(programs);
+    let asms = compiler.emit(&sources, &modules);
+    let written = compiler
+        .build(&sources, asms, Output::Object, path)
+        .unwrap();
+    assert_eq!(written, [path]);
+    assert!(fs::metadata(path).unwrap().len() > 0);
+}
+
+#[test]
+fn test_diagnostics() {
+    let mut compiler = Compiler::new(CompilerOptions::default());
+    let err = compiler.compile_to_asm("GOTO 100\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "<input>:1:1: Error: GOTO 100: undefined line number\n    1 | GOTO 100\n      | ^"
+    );
+    let diag = &err.iter().next().unwrap().diagnostic;
+    assert_eq!(
+        (diag.severity, diag.code, diag.span),
+        (
+            Severity::Error,
+            "semantic-error",
+            Some(Span { line: 1, col: 1 })
+        )
+    );
+
+    compiler.compile_to_asm("X = 1\nPRINT 2\n").unwrap();
+    let warning = &compiler.warnings().iter().next().unwrap().diagnostic;
+    assert_eq!(
+        (warning.severity, warning.code),
+        (Severity::Warning, "unused-variable")
+    );
+}
+
+#[test]
+fn test_runtime_cache_from_threads() {
+    // Builds on two threads make the runtime library in one cache, each in a
+    // work directory of its own, and leave only the library there
+    let cache = TempDir::new().unwrap();
+    let builds: Vec<_> = (0..2)
+        .map(|i| {
+            let cache = cache.path().to_path_buf();
+            std::thread::spawn(move || {
+                let tmp = TempDir::new().unwrap();
+                let bas = tmp.path().join("prog.bas");
+                fs::write(&bas, format!("PRINT {}\n", i)).unwrap();
+                let mut compiler = Compiler::new(CompilerOptions {
+                    assembler: Some("as".into()),
+                    cc: Some("cc".into()),
+                    runtime_cache: Some(cache),
+                    ..CompilerOptions::default()
+                });
+                let exe = compiler.compile_to_executable(&bas).unwrap();
+                let out = Command::new(&exe).output().unwrap();
+                assert_eq!(String::from_utf8_lossy(&out.stdout), format!("{}\n", i));
+            })
+        })
+        .collect();
+    for build in builds {
+        build.join().unwrap();
+    }
+    let entries: Vec<_> = fs::read_dir(cache.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(entries.len(), 1, "{:?}", entries);
+    assert!(entries[0].ends_with(".a"), "{:?}", entries);
+}
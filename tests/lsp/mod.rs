@@ -0,0 +1,54 @@
+//! Language server tests (xbasic64 lsp)
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Frame a JSON-RPC message as the protocol sends it
+fn frame(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+#[test]
+fn test_lsp_session() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let messages = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":
+            {"uri":"file:///nowhere/a.bas","languageId":"basic","version":1,
+             "text":"PRINT 1\nGOTO 50\n"}}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ];
+    let input: String = messages.iter().map(|m| frame(m)).collect();
+    server
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let out = server.wait_with_output().unwrap();
+    assert!(out.status.success());
+
+    let out = String::from_utf8_lossy(&out.stdout);
+    let bodies: Vec<&str> = out.split("Content-Length: ").skip(1).collect();
+    assert_eq!(bodies.len(), 3, "{}", out);
+    assert!(bodies[0].contains(r#""id":1"#), "{}", bodies[0]);
+    assert!(bodies[0].contains(r#""documentSymbolProvider":true"#));
+    assert!(bodies[1].contains("textDocument/publishDiagnostics"));
+    assert!(bodies[1].contains("GOTO 50: undefined line number"));
+    assert!(
+        bodies[1].contains(r#""start":{"character":0,"line":1}"#),
+        "{}",
+        bodies[1]
+    );
+    assert!(bodies[2].contains(r#""id":2"#) && bodies[2].contains(r#""result":null"#));
+}
@@ -47,6 +47,19 @@ A# = -3.14159: PRINT ABS(A#)
     assert_eq!(lines[4], "3.14159", "abs double");
 }
 
+#[test]
+fn test_single_results() {
+    // ABS, SGN, INT, FIX and SQR of a SINGLE give a SINGLE
+    let output = compile_and_run(
+        r#"
+B! = 0.1: PRINT SQR(B! * B!): PRINT INT(-B!): PRINT FIX(B! + 2): PRINT SGN(-B!): PRINT ABS(B!)
+X# = ABS(B!): PRINT X#
+"#,
+    )
+    .unwrap();
+    assert_eq!(output, "0.1\n-1\n2\n-1\n0.1\n0.10000000149011612\n");
+}
+
 #[test]
 fn test_int_fix() {
     // INT floors, FIX truncates toward zero
@@ -173,6 +186,29 @@ A# = 1.0: PRINT INT(LOG(A#))
     assert_eq!(lines[6], "0", "log double");
 }
 
+#[test]
+fn test_round_min_max_log10() {
+    // ROUND rounds half away from zero, to tens with negative digits
+    let output = compile_and_run(
+        r#"
+PRINT ROUND(2.5); ROUND(-2.5); ROUND(0.49999999999999994); ROUND(1.4999)
+PRINT ROUND(3.14159, 2); ROUND(-3.14159, 3); ROUND(1234.5, -2); ROUND(7, -400)
+A! = 2.25: PRINT ROUND(A!, 1); ROUND(1E300, 5)
+PRINT MIN(3, 4); MAX(3, 4); MIN(-1.5, 2%); MAX(2&, 7!)
+X = 10: PRINT MAX(MIN(X, 5), -X)
+PRINT LOG10(1000); LOG10(0.01); INT(LOG10(2) * 1000)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "3-301", "half away from zero");
+    assert_eq!(lines[1], "3.14-3.14212000", "decimal places");
+    assert_eq!(lines[2], "2.31e+300", "single, and too big to scale");
+    assert_eq!(lines[3], "34-1.57", "min and max");
+    assert_eq!(lines[4], "5", "nested");
+    assert_eq!(lines[5], "3-2301", "log10");
+}
+
 #[test]
 fn test_rnd_timer() {
     // RND returns 0-1, TIMER returns seconds since midnight
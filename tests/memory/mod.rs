@@ -0,0 +1,118 @@
+//! Emulated memory tests (PEEK, POKE, DEF SEG, VARPTR, VARSEG, BSAVE, BLOAD)
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run, compile_and_run_with_files};
+use std::fs;
+
+#[test]
+fn test_poke_and_peek() {
+    let source = r#"
+FOR I = 0 TO 9
+POKE 100 + I, I * I
+NEXT I
+PRINT PEEK(104); PEEK(109); PEEK(200)
+"#;
+    let output = compile_and_run(source).unwrap();
+    assert_eq!(output.trim(), "16810");
+}
+
+#[test]
+fn test_def_seg() {
+    let source = r#"
+POKE 5, 1
+DEF SEG = &H1000
+PRINT PEEK(5)
+POKE 0, 77
+DEF SEG = &HFFF
+PRINT PEEK(16)
+DEF SEG
+PRINT PEEK(5)
+DEF SEG = &HFFFF
+POKE -1, 2
+PRINT PEEK(65535)
+"#;
+    let output = compile_and_run(source).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "0", "segments are separate");
+    assert_eq!(lines[1], "77", "segments overlap every 16 bytes");
+    assert_eq!(lines[2], "1", "DEF SEG alone restores the default");
+    assert_eq!(lines[3], "2", "negative offsets wrap like 16-bit integers");
+}
+
+#[test]
+fn test_poke_rejects_out_of_range() {
+    assert!(compile_and_run("POKE 0, 256").is_err());
+    assert!(compile_and_run("X = PEEK(65536)").is_err());
+}
+
+#[test]
+fn test_varptr_and_varseg() {
+    let source = r#"
+X = 3.5
+DEF SEG = VARSEG(X)
+PRINT PEEK(VARPTR(X) + 7)
+DIM A(10)
+A(4) = 1
+DEF SEG = VARSEG(A(4))
+PRINT PEEK(VARPTR(A(4)) + 6)
+POKE VARPTR(A(4)) + 6, 0
+POKE VARPTR(A(4)) + 7, 0
+PRINT A(4)
+"#;
+    let output = compile_and_run(source).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "64", "high byte of 3.5");
+    assert_eq!(lines[1], "240", "array element bytes");
+    assert_eq!(lines[2], "0", "POKE writes the real array");
+}
+
+#[test]
+fn test_bsave_and_bload() {
+    let source = r#"
+DEF SEG = &H2000
+FOR I = 0 TO 9
+POKE I, I * 3
+NEXT I
+BSAVE "table.bin", 0, 10
+DEF SEG = &H3000
+BLOAD "table.bin", 100
+PRINT PEEK(100); PEEK(109)
+DEF SEG = &H2000
+POKE 9, 0
+BLOAD "table.bin"
+PRINT PEEK(9)
+"#;
+    let (output, tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "027", "BLOAD at an offset");
+    assert_eq!(lines[1], "27", "BLOAD where the file was saved from");
+
+    let data = fs::read(tmp.path().join("table.bin")).unwrap();
+    assert_eq!(&data[..7], &[0xFD, 0x00, 0x20, 0x00, 0x00, 0x0A, 0x00]);
+    assert_eq!(&data[7..], &[0, 3, 6, 9, 12, 15, 18, 21, 24, 27]);
+}
+
+#[test]
+fn test_bsave_array() {
+    let source = r#"
+DIM A(3)
+A(0) = 1.5
+A(3) = -2
+DEF SEG = VARSEG(A(0))
+BSAVE "array.bin", VARPTR(A(0)), 32
+DIM B(3)
+DEF SEG = VARSEG(B(0))
+BLOAD "array.bin", VARPTR(B(0))
+PRINT B(0); B(3)
+"#;
+    let (output, _tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    assert_eq!(output.trim(), "1.5-2");
+}
+
+#[test]
+fn test_bload_missing_file() {
+    let result = compile_and_run_with_files("BLOAD \"missing.bin\"", |_| Ok(()));
+    assert!(result.is_err(), "BLOAD of a missing file should fail");
+}
@@ -0,0 +1,195 @@
+//! Separate compilation tests: programs built from several source files
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::compile_files;
+use std::fs;
+use std::process::Command;
+
+const MAIN: &str = r#"DECLARE SUB Greet(N$)
+DECLARE FUNCTION Twice#(X#)
+SUB Shout(S$)
+    PRINT S$; "!"
+END SUB
+Greet "World"
+PRINT Twice#(21)
+"#;
+
+const GREET: &str = r#"DECLARE FUNCTION Twice#(X#)
+DECLARE SUB Shout(S$)
+SUB Greet(N$)
+    Shout "Hello, " + N$
+    PRINT Twice#(1.5)
+END SUB
+"#;
+
+const TWICE: &str = "FUNCTION Twice#(X#)\n    Twice# = X# * 2\nEND FUNCTION\n";
+
+fn files() -> [(&'static str, &'static str); 3] {
+    [
+        ("main.bas", MAIN),
+        ("greet.bas", GREET),
+        ("twice.bas", TWICE),
+    ]
+}
+
+#[test]
+fn test_linked_modules() {
+    let expected = "Hello, World!\n3\n42\n";
+    for opt in ["--opt-level=0", "-O"] {
+        let args = ["main.bas", "greet.bas", "twice.bas", "-o", "app", opt];
+        let (output, tmp) = compile_files(&files(), &args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}", stderr);
+        let run = Command::new(tmp.path().join("app")).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&run.stdout), expected);
+    }
+
+    // --run puts the modules' procedures into one program
+    let args = ["--run", "main.bas", "greet.bas", "twice.bas"];
+    let (output, _tmp) = compile_files(&files(), &args);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn test_modules_with_one_name() {
+    // Modules named alike in different directories get temp files of their
+    // own with the external assembler and linker
+    let files = [
+        (
+            "main.bas",
+            "DECLARE SUB Greet(N$)\nDECLARE FUNCTION Twice#(X#)\nGreet \"You\"\nPRINT Twice#(4)\n",
+        ),
+        (
+            "a/util.bas",
+            "SUB Greet(N$)\n    PRINT \"Hi, \"; N$\nEND SUB\n",
+        ),
+        ("b/util.bas", TWICE),
+    ];
+    let args = ["main.bas", "a/util.bas", "b/util.bas", "-o", "app"];
+    let args = [&args[..], &["--as", "as", "--cc", "cc"]].concat();
+    let (output, tmp) = compile_files(&files, &args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    let run = Command::new(tmp.path().join("app")).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "Hi, You\n8\n");
+}
+
+#[test]
+fn test_separate_objects() {
+    // -c writes an object next to each source; the modules exporting a
+    // procedure make it global
+    let (output, tmp) = compile_files(&files(), &["-c", "main.bas", "greet.bas", "twice.bas"]);
+    assert!(output.status.success());
+    for object in ["main.o", "greet.o", "twice.o"] {
+        assert!(tmp.path().join(object).exists(), "{}", object);
+    }
+
+    // Undefined procedures are left for the linker when not linking
+    let (output, tmp) = compile_files(&files(), &["-S", "main.bas", "greet.bas"]);
+    assert!(output.status.success());
+    let greet = fs::read_to_string(tmp.path().join("greet.s")).unwrap();
+    assert!(greet.contains(".globl _proc_GREET"));
+    assert!(!greet.contains("main:"));
+
+    // One file per module can't be named by -o
+    let (output, _tmp) = compile_files(&files(), &["-c", "main.bas", "greet.bas", "-o", "x.o"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_module_errors() {
+    let error = |files: &[(&str, &str)], inputs: &[&str]| {
+        let (output, _tmp) = compile_files(files, inputs);
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+
+    // A declared procedure no module defines
+    let stderr = error(&files(), &["main.bas", "greet.bas"]);
+    assert!(
+        stderr.contains("main.bas:7:")
+            && stderr.contains("FUNCTION TWICE# is declared but not defined in any module"),
+        "{}",
+        stderr
+    );
+
+    // A call to another module's procedure needs a DECLARE
+    let main = "Greet \"World\"\n";
+    let stderr = error(
+        &[("main.bas", main), ("greet.bas", GREET)],
+        &["main.bas", "greet.bas"],
+    );
+    assert!(
+        stderr.contains("SUB GREET is defined in greet.bas; DECLARE it to call it from main.bas"),
+        "{}",
+        stderr
+    );
+
+    // Defined twice, or not matching its DECLARE
+    let files = [
+        ("main.bas", MAIN),
+        ("greet.bas", GREET),
+        ("twice.bas", TWICE),
+    ];
+    let stderr = error(&files, &["main.bas", "greet.bas", "twice.bas", "twice.bas"]);
+    assert!(
+        stderr.contains("FUNCTION TWICE# is already defined in twice.bas"),
+        "{}",
+        stderr
+    );
+    let wrong = "FUNCTION Twice#(X#, Y#)\n    Twice# = X#\nEND FUNCTION\n";
+    let stderr = error(
+        &[
+            ("main.bas", MAIN),
+            ("greet.bas", GREET),
+            ("twice.bas", wrong),
+        ],
+        &["main.bas", "greet.bas", "twice.bas"],
+    );
+    assert!(
+        stderr.contains("main.bas:2:")
+            && stderr.contains("DECLARE FUNCTION TWICE# doesn't match the definition in twice.bas"),
+        "{}",
+        stderr
+    );
+
+    // Only the main module has a main program and DATA
+    let stray = "PRINT 1\n";
+    let stderr = error(
+        &[("main.bas", MAIN), ("stray.bas", stray)],
+        &["main.bas", "stray.bas"],
+    );
+    assert!(stderr.contains("stray.bas:1:"), "{}", stderr);
+    let data = "SUB D\n    DATA 1, 2\nEND SUB\n";
+    let stderr = error(
+        &[("main.bas", "PRINT 1\n"), ("data.bas", data)],
+        &["main.bas", "data.bas"],
+    );
+    assert!(
+        stderr.contains("DATA can only be used in the main module"),
+        "{}",
+        stderr
+    );
+
+    // Errors in one module don't hide those in the next
+    let bad_greet = "SUB Greet(N$)
+    GOTO 10
+END SUB
+";
+    let bad_twice = "FUNCTION Twice#(X#)
+    Twice# = \"x\"
+END FUNCTION
+";
+    let stderr = error(
+        &[
+            ("main.bas", MAIN),
+            ("greet.bas", bad_greet),
+            ("twice.bas", bad_twice),
+        ],
+        &["main.bas", "greet.bas", "twice.bas"],
+    );
+    assert!(stderr.contains("greet.bas:2:5:"), "{}", stderr);
+    assert!(stderr.contains("twice.bas:2:14:"), "{}", stderr);
+}
@@ -25,3 +25,171 @@ PRINT "C"
     assert_eq!(lines[3], "B", "multi-b");
     assert_eq!(lines[4], "C", "multi-c");
 }
+
+#[test]
+fn test_print_question_mark() {
+    // ? is shorthand for PRINT, also after a line number or colon
+    let output = compile_and_run("? \"A\"; 1\n10 ?2: ?\"?\"").unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["A1", "2", "?"]);
+}
+
+#[test]
+fn test_print_zones_tab_spc() {
+    let output = compile_and_run(
+        r#"
+PRINT 1, 2, 3
+PRINT "A"; TAB(10); "B"; SPC(3); "C"
+PRINT "XY"; TAB(1); "Z"
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "1             2             3");
+    assert_eq!(lines[1], "A        B   C");
+    assert_eq!(lines[2], "XY");
+    assert_eq!(lines[3], "Z");
+}
+
+#[test]
+fn test_print_using() {
+    let output = compile_and_run(
+        r####"
+PRINT USING "##.##"; 3.14159
+PRINT USING "###.## "; -2.5; 10
+PRINT USING "Total: $$#,###.##"; 1234.5
+PRINT USING "**##.##"; 12.3
+PRINT USING "+#.# #.#- #.#-"; 1.5; 2.5; -3.5
+PRINT USING "###"; 12345
+PRINT USING "#.##^^^^ ##.##^^^^^"; 234.5; -0.00123
+PRINT USING "!\  \|&|"; "hello"; "abcdef"; "x"
+PRINT USING "Name: &, age ###"; "Bob"; 42
+PRINT USING "_# ##: "; 5
+PRINT USING "[##]"; 1; 2; 3;
+PRINT "!"
+"####,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], " 3.14");
+    assert_eq!(lines[1], " -2.50  10.00 ");
+    assert_eq!(lines[2], "Total:  $1,234.50");
+    assert_eq!(lines[3], "**12.30");
+    assert_eq!(lines[4], "+1.5 2.5  3.5-");
+    assert_eq!(lines[5], "%12345");
+    assert_eq!(lines[6], " .23E+03 -1.23E-003");
+    assert_eq!(lines[7], "habcd|x|");
+    assert_eq!(lines[8], "Name: Bob, age  42");
+    assert_eq!(lines[9], "#  5: ");
+    assert_eq!(lines[10], "[ 1][ 2][ 3]!");
+}
+
+#[test]
+fn test_write() {
+    let output = compile_and_run("WRITE \"x\", -3, 2.5, \"a b\"\nWRITE\nWRITE 1\n").unwrap();
+    assert_eq!(output, "\"x\",-3,2.5,\"a b\"\n\n1\n");
+}
+
+#[test]
+fn test_width_wraps_output() {
+    let output = compile_and_run(
+        r#"
+WIDTH 20
+PRINT "ABCDEFGHIJKLMNOPQRSTUVWXYZ"
+PRINT 1, 2
+PRINT "ABCDEFGHIJKLMNOPQ"; 12345
+PRINT "X"; TAB(25); "Y"
+WIDTH 80
+PRINT 1, 2
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "ABCDEFGHIJKLMNOPQRST");
+    assert_eq!(lines[1], "UVWXYZ");
+    assert_eq!(lines[2], "1");
+    assert_eq!(lines[3], "2");
+    assert_eq!(lines[4], "ABCDEFGHIJKLMNOPQ");
+    assert_eq!(lines[5], "12345");
+    assert_eq!(lines[6], "X   Y");
+    assert_eq!(lines[7], "1             2");
+}
+
+#[test]
+fn test_width_rejects_zero() {
+    assert!(compile_and_run("WIDTH 0").is_err());
+    assert!(compile_and_run("WIDTH 256").is_err());
+}
+
+#[test]
+fn test_screen_function() {
+    // SCREEN(row, col) reads back what PRINT put on the 25x80 text screen:
+    // blank cells are spaces, long lines wrap at column 80, CLS clears it,
+    // and the screen scrolls once output passes the last row
+    let output = compile_and_run(
+        r#"
+PRINT "Hello"
+PRINT "World", 42
+A$ = ""
+FOR C = 1 TO 5: A$ = A$ + CHR$(SCREEN(1, C)): NEXT
+PRINT A$; SCREEN(2, 15); SCREEN(2, 16); SCREEN(2, 17); SCREEN(25, 80)
+FOR I = 1 TO 81: PRINT "*";: NEXT: PRINT
+PRINT SCREEN(4, 80); SCREEN(5, 1); SCREEN(5, 2)
+CLS
+PRINT "x"; SCREEN(1, 1)
+FOR I = 1 TO 30: PRINT I: NEXT
+PRINT SCREEN(24, 1); SCREEN(24, 2); SCREEN(23, 1); SCREEN(1, 1); SCREEN(1.6, 1.4)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[2], "Hello52503232");
+    assert_eq!(lines[5], "424232");
+    assert!(lines[6].ends_with("x120"), "{}", lines[6]);
+    assert_eq!(lines.last().copied(), Some("5148505556"));
+}
+
+#[test]
+fn test_locale_decimal_point() {
+    use crate::common::run_compiler;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    // Without --locale, and in a locale that isn't there, numbers use '.'
+    let source = r#"
+X = 3.25
+PRINT X; STR$(-X); VAL("2,5"); VAL("2.5")
+OPEN "n.txt" FOR OUTPUT AS #1: PRINT #1, X: CLOSE #1
+OPEN "n.txt" FOR INPUT AS #1: INPUT #1, Y: PRINT Y = X
+"#;
+    let tmp = TempDir::new().unwrap();
+    let exe = tmp.path().join("locale");
+    let run = |args: &[&str], locale: &str, locpath: &std::path::Path| {
+        run_compiler(source, &[&["-o", exe.to_str().unwrap()], args].concat()).unwrap();
+        let out = Command::new(&exe)
+            .current_dir(tmp.path())
+            .env("LC_ALL", locale)
+            .env("LOCPATH", locpath)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&out.stdout).to_string()
+    };
+    let expected = "3.25-3.2522.5\n-1\n";
+    assert_eq!(run(&[], "de_DE.UTF-8", tmp.path()), expected);
+    assert_eq!(run(&["--locale"], "C", tmp.path()), expected);
+    assert_eq!(run(&["--locale"], "xx_XX.none", tmp.path()), expected);
+
+    // With --locale, the console, STR$ and VAL follow the locale, and files
+    // keep '.' (only where localedef can build a German locale to test with)
+    let locales = tmp.path().join("locales");
+    std::fs::create_dir(&locales).unwrap();
+    let built = Command::new("localedef")
+        .args(["-i", "de_DE", "-f", "UTF-8"])
+        .arg(locales.join("de_DE.UTF-8"))
+        .output();
+    if built.is_ok_and(|out| out.status.success()) {
+        assert_eq!(run(&[], "de_DE.UTF-8", &locales), expected);
+        let out = run(&["--locale"], "de_DE.UTF-8", &locales);
+        assert_eq!(out, "3,25-3,252,52,5\n-1\n");
+    }
+}
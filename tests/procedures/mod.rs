@@ -76,6 +76,37 @@ PRINT Sum10(1, 2, 3, 4, 5, 6, 7, 8, 9, 10)
     assert_eq!(lines[2], "55", "10 params: 1+..+10");
 }
 
+#[test]
+fn test_many_mixed_params() {
+    // Stack arguments of every type, a string split between the last
+    // register and the stack, and recursion passing spilled arguments on
+    let output = compile_and_run(
+        r#"
+FUNCTION Mix(A%, B!, C&, D#, E%, F!, G&, H#, I$, J%)
+    Mix = A% + B! + C& + D# + E% + F! + G& + H# + LEN(I$) + J%
+END FUNCTION
+
+SUB Split(A, B, C, D, E, S$, T$)
+    PRINT A + B + C + D + E; S$; T$
+END SUB
+
+FUNCTION Rot(N, A, B, C, D, E, F, G)
+    IF N = 0 THEN
+        Rot = A * 1000000 + B * 100000 + C * 10000 + D * 1000 + E * 100 + F * 10 + G
+    ELSE
+        Rot = Rot(N - 1, B, C, D, E, F, G, A)
+    END IF
+END FUNCTION
+
+PRINT Mix(1, 2.5, 3, 4, 5, 6, 7, 8, "abc", 10)
+Split 1, 2, 3, 4, 5, "six", "seven"
+PRINT Rot(3, 1, 2, 3, 4, 5, 6, 7)
+"#,
+    )
+    .unwrap();
+    assert_eq!(output, "49.5\n15sixseven\n4567123\n");
+}
+
 #[test]
 fn test_nested_calls() {
     // Test nested function calls in arguments
@@ -102,3 +133,329 @@ PRINT AddThree(Mul(2, 3), Mul(4, 5), Mul(6, 7))
     assert_eq!(lines[0], "26", "nested: 2*3 + 4*5 = 6+20");
     assert_eq!(lines[1], "68", "nested three: 6+20+42");
 }
+
+#[test]
+fn test_typed_params_and_returns() {
+    // AS clauses on parameters and return types from the FUNCTION name's suffix
+    let output = compile_and_run(
+        r#"
+FUNCTION Area# (R AS DOUBLE)
+    Area# = 3.5 * R * R
+END FUNCTION
+
+FUNCTION Greet$ (Who AS STRING, N AS INTEGER)
+    Greet$ = "Hi " + Who + "-" + STR$(N)
+END FUNCTION
+
+FUNCTION Half! (X AS SINGLE)
+    Half! = X / 2
+END FUNCTION
+
+FUNCTION Twice& (N AS LONG)
+    Twice& = N * 2
+END FUNCTION
+
+SUB Show (N AS INTEGER, S AS STRING)
+    PRINT S; N \ 2
+END SUB
+
+PRINT Area#(2)
+PRINT Greet$("Bob", 7)
+PRINT Half!(5)
+PRINT Twice&(100000)
+Show 9, "half:"
+A$ = Greet$("X", 1) + "!"
+PRINT A$; LEN(A$)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "14", "double return");
+    assert_eq!(lines[1], "Hi Bob- 7", "string return");
+    assert_eq!(lines[2], "2.5", "single param and return");
+    assert_eq!(lines[3], "200000", "long param and return");
+    assert_eq!(lines[4], "half:4", "integer and string params");
+    assert_eq!(lines[5], "Hi X- 1!8", "string return in expression");
+}
+
+#[test]
+fn test_string_params_and_returns() {
+    // Strings travel as (pointer, length) pairs, so a string argument takes
+    // two argument words and long lists spill onto the stack
+    let output = compile_and_run(
+        r#"
+FUNCTION Greet$(Name$)
+    Greet$ = "Hello, " + Name$ + "!"
+END FUNCTION
+
+SUB Show(Msg$)
+    PRINT "["; Msg$; "]"
+    Msg$ = "changed"
+END SUB
+
+FUNCTION Join$(A$, B$, C$, N%, D$, E#, F$)
+    Join$ = A$ + B$ + C$ + STR$(N%) + D$ + STR$(E#) + F$
+END FUNCTION
+
+M$ = "hi"
+CALL Show(M$)
+PRINT M$
+PRINT Greet$("Bob")
+PRINT Greet$(Greet$(M$))
+L$ = ""
+FOR I = 1 TO 40: L$ = L$ + "abcdefghij": NEXT I
+PRINT LEN(Greet$(L$)); RIGHT$(Greet$(L$), 3)
+PRINT Join$("a", "b", "c", 4, M$, 1.5, LEFT$("xyz", 2))
+IF Greet$("x") = "Hello, x!" THEN PRINT "equal"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        output,
+        "[hi]\nhi\nHello, Bob!\nHello, Hello, hi!!\n408ij!\nabc 4hi 1.5xy\nequal\n"
+    );
+}
+
+#[test]
+fn test_array_params() {
+    // A() passes the array itself: the callee can modify it and ask its bounds
+    let output = compile_and_run(
+        r#"
+SUB Sort(A(), N)
+    FOR I = 0 TO N - 2
+        FOR J = 0 TO N - 2 - I
+            IF A(J) > A(J + 1) THEN
+                T = A(J)
+                A(J) = A(J + 1)
+                A(J + 1) = T
+            END IF
+        NEXT J
+    NEXT I
+END SUB
+
+FUNCTION Total(A())
+    S = 0
+    FOR I = 0 TO UBOUND(A)
+        S = S + A(I)
+    NEXT I
+    Total = S
+END FUNCTION
+
+SUB Fill(M(), V)
+    FOR I = 0 TO UBOUND(M, 1)
+        FOR J = 0 TO UBOUND(M, 2)
+            M(I, J) = V + I * 10 + J
+        NEXT J
+    NEXT I
+END SUB
+
+SUB Name(N$())
+    N$(1) = "hi"
+END SUB
+
+DIM A(4)
+A(0) = 5: A(1) = 3: A(2) = 9: A(3) = 1: A(4) = 7
+CALL Sort(A(), 5)
+FOR I = 0 TO 4: PRINT A(I);: NEXT I
+PRINT
+PRINT Total(A()); UBOUND(A); LBOUND(A)
+DIM M(2, 3)
+Fill M(), 100
+PRINT M(2, 3); M(1, 2); UBOUND(M, 2)
+DIM N$(2)
+CALL Name(N$())
+PRINT N$(1)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "13579", "sorted in place");
+    assert_eq!(lines[1], "2540", "UBOUND inside the callee");
+    assert_eq!(lines[2], "1231123", "2-D array");
+    assert_eq!(lines[3], "hi", "string array");
+}
+
+#[test]
+fn test_recursive_functions() {
+    // Each call gets its own frame: parameters, locals and the return value
+    let output = compile_and_run(
+        r#"
+FUNCTION Fact(N)
+    IF N <= 1 THEN
+        Fact = 1
+    ELSE
+        Fact = N * Fact(N - 1)
+    END IF
+END FUNCTION
+
+FUNCTION Fib(N)
+    IF N < 2 THEN
+        Fib = N
+    ELSE
+        Fib = Fib(N - 1) + Fib(N - 2)
+    END IF
+END FUNCTION
+
+FUNCTION Rev$(S$)
+    IF LEN(S$) <= 1 THEN
+        Rev$ = S$
+    ELSE
+        Rev$ = Rev$(MID$(S$, 2)) + LEFT$(S$, 1)
+    END IF
+END FUNCTION
+
+PRINT Fact(10)
+PRINT Fib(15)
+PRINT Rev$("hello")
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "3628800", "factorial");
+    assert_eq!(lines[1], "610", "double recursion");
+    assert_eq!(lines[2], "olleh", "string recursion");
+}
+
+#[test]
+fn test_recursion_with_locals() {
+    // Bodies with more locals than a small fixed frame would hold
+    let output = compile_and_run(
+        r#"
+FUNCTION Many(N)
+    A = N: B = N + 1: C = N + 2: D = N + 3: E = N + 4
+    F = N + 5: G = N + 6: H = N + 7: I = N + 8: J = N + 9
+    K = N + 10: L = N + 11
+    IF N > 0 THEN
+        R = Many(N - 1)
+    ELSE
+        R = 0
+    END IF
+    Many = R + A + B + C + D + E + F + G + H + I + J + K + L
+END FUNCTION
+
+FUNCTION Sum(N)
+    DIM T(3)
+    T(0) = N
+    T(1) = 0
+    IF N > 0 THEN
+        T(1) = Sum(N - 1)
+    END IF
+    Sum = T(0) + T(1)
+END FUNCTION
+
+SUB Hanoi(N, Src, Dst, Via)
+    IF N > 0 THEN
+        Hanoi N - 1, Src, Via, Dst
+        PRINT Src; Dst; " ";
+        Hanoi N - 1, Via, Dst, Src
+    END IF
+END SUB
+
+PRINT Many(3)
+PRINT Sum(5)
+Hanoi 3, 1, 3, 2
+PRINT
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "336", "locals survive the recursive call");
+    assert_eq!(lines[1], "15", "local array per call");
+    assert_eq!(lines[2], "13 12 32 13 21 23 13", "recursive SUB");
+}
+
+#[test]
+fn test_shared_variables() {
+    // Procedures see main-program names only through DIM SHARED or SHARED
+    let output = compile_and_run(
+        r#"
+DIM SHARED Count, Log$, Grid(2, 3)
+X = 10
+N$ = "main"
+DIM V(4)
+
+SUB Bump(K)
+    Count = Count + K
+    Log$ = Log$ + "b"
+    Grid(2, 3) = Grid(2, 3) + 1
+END SUB
+
+SUB Touch
+    SHARED X, N$, V()
+    X = X * 2
+    N$ = N$ + "!"
+    V(UBOUND(V)) = 42
+END SUB
+
+SUB Own
+    X = 99
+    PRINT X
+END SUB
+
+FUNCTION Depth(D)
+    Count = Count + 1
+    IF D > 0 THEN
+        Depth = Depth(D - 1) + 1
+    ELSE
+        Depth = 0
+    END IF
+END FUNCTION
+
+Bump 3
+Bump 4
+PRINT Count; " "; Log$; Grid(2, 3)
+Touch
+PRINT X; " "; N$; V(4)
+Own
+PRINT X
+Count = 0
+PRINT Depth(5); Count
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "7 bb2", "DIM SHARED");
+    assert_eq!(lines[1], "20 main!42", "SHARED statement");
+    assert_eq!(lines[2], "99", "local of the same name");
+    assert_eq!(lines[3], "20", "main variable untouched by the local");
+    assert_eq!(lines[4], "56", "shared across recursive calls");
+}
+
+#[test]
+fn test_unshared_main_variable() {
+    let err = compile_and_run("X = 1\nSUB S\n    PRINT X\nEND SUB\nS").unwrap_err();
+    assert!(err.contains("SHARED"), "{}", err);
+}
+
+#[test]
+fn test_declare_lib() {
+    // C library functions called directly, with arguments passed by value
+    let output = compile_and_run(
+        r#"
+DECLARE FUNCTION Labs& LIB "c" ALIAS "labs" (BYVAL N&)
+DECLARE FUNCTION StrLen% LIB "c" (BYVAL S$)
+DECLARE FUNCTION GetEnv$ LIB "c" (BYVAL Name$)
+DECLARE FUNCTION Pow# LIB "m" (BYVAL X#, BYVAL Y#)
+DECLARE FUNCTION Ldexp! LIB "m" ALIAS "ldexpf" (BYVAL X!, BYVAL E&)
+DECLARE SUB Srand LIB "c" (BYVAL Seed&)
+PRINT Labs&(-42)
+PRINT StrLen%("Hello" + ", world")
+PRINT "["; GetEnv$("XBASIC64_NO_SUCH_VARIABLE"); "]"
+PRINT Pow#(2, 10)
+PRINT Ldexp!(1.5, 3)
+Srand 1
+CALL Srand(2)
+PRINT "done"
+"#,
+    )
+    .unwrap();
+    assert_eq!(output, "42\n12\n[]\n1024\n12\ndone\n");
+}
+
+#[test]
+fn test_declare_lib_errors() {
+    let err = compile_and_run("DECLARE SUB Bad LIB \"c\" (A())\n").unwrap_err();
+    assert!(err.contains("arrays can't be passed"), "{}", err);
+    let err = compile_and_run("SUB Show (BYVAL N)\nEND SUB\n").unwrap_err();
+    assert!(err.contains("BYVAL is only allowed"), "{}", err);
+}
@@ -0,0 +1,308 @@
+//! Interpreter (--run) tests: programs run directly must behave like
+//! their compiled versions
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_and_run, compile_and_run_with_files, run_compiler};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+/// Run a program with --run, feeding it stdin; returns whether it
+/// succeeded, its stdout and its stderr
+fn run_interpreted(source: &str, stdin: &str) -> (bool, String, String) {
+    let tmp = TempDir::new().unwrap();
+    let bas_file = tmp.path().join("test.bas");
+    fs::write(&bas_file, source).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .arg("--run")
+        .arg(&bas_file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn test_run_matches_compiled() {
+    let programs = [
+        // Number formatting, print zones and wrapping
+        r#"
+PRINT 1, -2.5, 1 / 3, 1E20, 0.00001
+S! = 2 / 3: PRINT S!; S! * 3
+PRINT "x"; TAB(10); "y"; SPC(3); "z"
+WIDTH 20
+PRINT "abcdefghijklmnopqrstuvwxyz"
+PRINT 123456789; 123456789; 123456789
+PRINT 1, 2, 3
+PRINT SCREEN(3, 1); SCREEN(5, 7); SCREEN(8, 2); SCREEN(9, 80)
+"#,
+        // Integer widths, conversions and operators
+        r#"
+A% = 32767: A% = A% + 1: B& = 2147483647: B& = B& + 1
+C% = 3.7: D& = -2.5
+PRINT A%; B&; C%; D&; CINT(2.5); CLNG(-3.5); INT(-2.5); FIX(-2.5)
+PRINT 7 \ 2; -7 MOD 3; 2 ^ 10; 5 AND 3; 5 OR 8; 6 XOR 3; NOT 0; SHL(1, 40); SHR(-1, 60)
+PRINT 1 < 2; "b" > "a"; 1 = 2; SGN(-4); ABS(-1.5); SQR(2); RND(1); RND(1)
+PRINT ROUND(2.5); ROUND(-3.14159, 3); ROUND(1234.5, -2); MIN(3, -4.5); MAX(2&, 7!); LOG10(2); RGB(1, 2, 300)
+"#,
+        // Strings
+        r#"
+S$ = "Hello, World"
+PRINT LEFT$(S$, 5); "|"; RIGHT$(S$, 5); "|"; MID$(S$, 8, 3); "|"; MID$(S$, 8)
+PRINT LEN(S$); INSTR(S$, "o"); INSTR(6, S$, "o"); ASC("A"); ASC(S$, 2); CHR$(66); VAL("12.5e1x")
+PRINT INSTR(20, S$, "o"); INSTR(0, S$, ""); INSTRREV(S$, "o"); INSTRREV(8, S$, "o")
+PRINT STR$(2.5); STR$(-7); "abc" + "def"
+"#,
+        // Control flow
+        r#"
+FOR I% = 10 TO 1 STEP -4: PRINT I%;: NEXT: PRINT I%
+FOR X = 0 TO 1 STEP 0.25: PRINT X;: NEXT: PRINT X
+DO WHILE N < 3: N = N + 1: LOOP: PRINT N
+WHILE N < 20: N = N * 2: WEND: PRINT N
+FOR K = 1 TO 4
+    ON K GOTO 100, 200
+    PRINT "none"; K: GOTO 300
+100 PRINT "100": GOTO 300
+200 PRINT "200"
+300 NEXT
+SELECT CASE "b"
+CASE "a": PRINT "A"
+CASE "b": PRINT "B"
+CASE ELSE: PRINT "?"
+END SELECT
+GOSUB 400: GOSUB 400
+END
+400 PRINT "sub": RETURN
+"#,
+        // Procedures, arrays and DATA
+        r#"
+DIM SHARED TOTAL
+FUNCTION REV$(S$)
+    FOR I = LEN(S$) TO 1 STEP -1: R$ = R$ + MID$(S$, I, 1): NEXT
+    REV$ = R$
+END FUNCTION
+FUNCTION FIB(N)
+    IF N < 2 THEN FIB = N ELSE FIB = FIB(N - 1) + FIB(N - 2)
+END FUNCTION
+SUB ADD(A(), N%)
+    FOR I = 0 TO N%: TOTAL = TOTAL + A(I): NEXT
+    A(0) = -1
+END SUB
+DIM V(4), M(2, 3)
+FOR I = 0 TO 4: READ V(I): NEXT
+M(2, 3) = 6
+ADD V(), 4
+PRINT REV$("stressed"); FIB(15); TOTAL; V(0); M(2, 3); UBOUND(M, 2)
+RESTORE 20
+READ W$: PRINT W$
+10 DATA 1, 2, 3, 4, 5
+20 DATA "last"
+"#,
+        // PRINT USING and WRITE
+        r#"
+PRINT USING "$$#,###.## +#.# **#.#-"; 1234.5; -2.25; 3; -4
+PRINT USING "x.##^^^^ \ \ & !"; 0.5; "abc"; "de"; "fg"
+PRINT USING "+###"; -7; 8;
+WRITE "a", 1.5, -2
+"#,
+        // SINGLE results of math functions
+        r#"
+B! = 0.1: PRINT SQR(B! * B!): PRINT INT(-B!): PRINT FIX(B! + 2): PRINT SGN(-B!): PRINT ABS(B!)
+"#,
+        // Dictionaries
+        r#"
+D = DICTNEW()
+DICTSET D, "b", "two": DICTSET D, "a", "one": DICTSET D, "c", "three"
+DICTSET D, "b", "TWO": DICTDEL D, "a": DICTDEL D, "zz"
+FOR I = 1 TO DICTCOUNT(D): PRINT DICTKEY$(D, I); "="; DICTGET$(D, DICTKEY$(D, I)): NEXT
+PRINT DICTHAS(D, "a"); DICTHAS(D, "c"); "["; DICTGET$(D, "a"); "]"; DICTNEW()
+"#,
+    ];
+    for source in programs {
+        let compiled = compile_and_run(source).unwrap();
+        let run = run_compiler(source, &["--run"]).unwrap();
+        assert_eq!(run, compiled, "{}", source);
+    }
+}
+
+#[test]
+fn test_run_input() {
+    let source = r#"
+INPUT "Name"; N$
+INPUT A, B
+LINE INPUT "Line: "; L$
+PRINT N$; A + B; "["; L$; "]"
+"#;
+    let (ok, out, _) = run_interpreted(source, "Ann\n1, x\n1, 2\n  a, b  \n");
+    assert!(ok);
+    assert_eq!(out, "Name? ? ?Redo from start\n? Line: Ann3[  a, b  ]\n");
+}
+
+#[test]
+fn test_run_files() {
+    let source = r#"
+OPEN "data.txt" FOR OUTPUT AS #1
+PRINT #1, "a,b"; ","; 12
+PRINT #1, "x", "y"
+CLOSE #1
+OPEN "data.txt" FOR APPEND AS #2
+PRINT #2, 3.5
+CLOSE #2
+OPEN "data.txt" FOR INPUT AS #1
+INPUT #1, P$, Q$, R
+INPUT #1, S$
+INPUT #1, T
+PRINT P$; "|"; Q$; "|"; R; "|"; S$; "|"; T
+CLOSE #1
+"#;
+    // Relative paths are resolved in run_compiler's temp directory
+    let out = run_compiler(source, &["--run"]).unwrap();
+    assert_eq!(out, "a|b|12|x             y|3.5\n");
+
+    // FLUSH # writes out what the file has buffered
+    let source = r#"
+OPEN "log.txt" FOR OUTPUT AS #1
+PRINT #1, "first"
+OPEN "log.txt" FOR INPUT AS #2
+INPUT #2, A$
+FLUSH #1
+OPEN "log.txt" FOR INPUT AS #3
+INPUT #3, B$
+PRINT "["; A$; "]["; B$; "]"
+"#;
+    let out = run_compiler(source, &["--run"]).unwrap();
+    assert_eq!(out, "[][first]\n");
+
+    // SEEK # moves to a byte position and SEEK() reports it, as compiled
+    let source = r#"
+OPEN "pos.txt" FOR OUTPUT AS #1
+PRINT #1, "ab,cd"
+SEEK #1, 1
+PRINT #1, "A";
+PRINT SEEK(1)
+CLOSE #1
+OPEN "pos.txt" FOR INPUT AS #1
+INPUT #1, A$
+PRINT A$; SEEK(#1)
+SEEK #1, 4
+INPUT #1, A$
+PRINT A$; SEEK(1)
+"#;
+    let out = run_compiler(source, &["--run"]).unwrap();
+    assert_eq!(out, "2\nAb4\ncd7\n");
+
+    // A file opened FOR APPEND starts at its end
+    let source = r#"
+OPEN "app.txt" FOR OUTPUT AS #1
+PRINT #1, "abc"
+CLOSE #1
+OPEN "app.txt" FOR APPEND AS #1
+PRINT SEEK(1)
+PRINT #1, "de"
+PRINT SEEK(1)
+"#;
+    let (compiled, _tmp) = compile_and_run_with_files(source, |_| Ok(())).unwrap();
+    assert_eq!(compiled, "5\n8\n");
+    assert_eq!(run_compiler(source, &["--run"]).unwrap(), compiled);
+
+    // Reusing a number that is still open is an error, as compiled
+    let source = "OPEN \"a.txt\" FOR OUTPUT AS #1\nOPEN \"b.txt\" FOR OUTPUT AS #1\n";
+    let err = run_compiler(source, &["--run"]).unwrap_err();
+    assert!(err.contains("File already open"), "{}", err);
+    let err = run_compiler("CLOSE #1\n", &["--run"]).unwrap_err();
+    assert!(err.contains("Bad file number"), "{}", err);
+}
+
+#[test]
+fn test_run_errors() {
+    // Runtime errors are reported like the compiled program's
+    let (ok, out, err) = run_interpreted("10 PRINT \"a\"\n20 PRINT 1 / 0\n", "");
+    assert!(!ok);
+    assert_eq!(out, "a\n");
+    assert_eq!(err.trim(), "Division by zero in 20");
+
+    let source = "A% = 32767\nA% = A% + 1\nPRINT A%\n";
+    assert_eq!(run_compiler(source, &["--run"]).unwrap(), "-32768\n");
+    let err = run_compiler(source, &["--run", "--overflow-check"]).unwrap_err();
+    assert!(err.contains("Overflow"), "{}", err);
+
+    // Statements that need the compiled runtime are rejected up front
+    let err = run_compiler("PRINT 1\nSCREEN 13\n", &["--run"]).unwrap_err();
+    assert!(
+        err.contains("test.bas:2:1: Error: SCREEN is not supported by --run"),
+        "{}",
+        err
+    );
+    let err = run_compiler("OPEN \"x\" FOR INPUT AS #1\nLOCK #1\n", &["--run"]).unwrap_err();
+    assert!(err.contains("LOCK is not supported by --run"), "{}", err);
+    let err = run_compiler("DECLARE SUB Nap LIB \"c\" (BYVAL N&)\n", &["--run"]).unwrap_err();
+    assert!(err.contains("DECLARE ... LIB is not supported"), "{}", err);
+}
+
+#[test]
+fn test_interactive_session() {
+    // No source file starts a session at the prompt; SAVE and LOAD use
+    // the current directory
+    let tmp = TempDir::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_xbasic64"))
+        .current_dir(tmp.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let session = r#"PRINT 2 + 2
+20 PRINT "X ="; X
+10 X = 5
+30 PRINT "gone"
+30
+LIST
+RUN
+PRINT X * 2
+SAVE "prog.bas"
+NEW
+LIST
+PRINT (1
+LOAD "prog.bas"
+LIST 20
+SYSTEM
+PRINT "not reached"
+"#;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(session.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let out = String::from_utf8_lossy(&output.stdout);
+    let out = out.split_once('\n').unwrap().1; // skip the banner
+    assert_eq!(
+        out,
+        "Ok\n4\nOk\nOk\nOk\nOk\nOk\n10 X = 5\n20 PRINT \"X =\"; X\nOk\n\
+         X =5\nOk\n10\nOk\nOk\nOk\nOk\nOk\nOk\n20 PRINT \"X =\"; X\nOk\n"
+    );
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(err.contains("immediate:1:9: Parse error"), "{}", err);
+    assert_eq!(
+        fs::read_to_string(tmp.path().join("prog.bas")).unwrap(),
+        "10 X = 5\n20 PRINT \"X =\"; X\n"
+    );
+}
@@ -0,0 +1,78 @@
+//! Spec test runner tests (xbasic64 test)
+
+// Copyright (c) 2025-2026 Jeff Garzik
+// SPDX-License-Identifier: MIT
+
+use crate::common::{compile_files, run_xbasic64};
+
+#[test]
+fn test_spec_runner() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    let dir = tmp.path();
+    std::fs::create_dir(dir.join("more")).unwrap();
+    let greet =
+        "INPUT \"Name\"; N$\nPRINT \"Hello, \"; N$\n'STDIN: Ada\n'EXPECT: Name? Hello, Ada\n";
+    std::fs::write(dir.join("greet.bas"), greet).unwrap();
+    // The program runs in its own directory
+    let files = "OPEN \"data.txt\" FOR INPUT AS #1\nINPUT #1, A$\nCLOSE #1\nPRINT A$ 'EXPECT: hi\n";
+    std::fs::write(dir.join("more/files.bas"), files).unwrap();
+    std::fs::write(dir.join("more/data.txt"), "hi\n").unwrap();
+    // Programs without an EXPECT are left alone
+    std::fs::write(dir.join("more/plain.bas"), "PRINT (\n").unwrap();
+
+    let out = run_xbasic64(dir, &["test"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert_eq!(
+        stdout.replace('\\', "/"),
+        "PASS ./greet.bas\nPASS ./more/files.bas\n2 passed, 0 failed\n"
+    );
+
+    std::fs::write(dir.join("wrong.bas"), "PRINT 2\n'EXPECT: 3\n").unwrap();
+    std::fs::write(dir.join("bad.bas"), "X = \"a\" + 1\n'EXPECT: 3\n").unwrap();
+    let out = run_xbasic64(dir, &["test", "wrong.bas", "bad.bas", "greet.bas"]);
+    assert!(!out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert_eq!(
+        stdout,
+        "FAIL wrong.bas\n     Line 1: expected \"3\", got \"2\"\n\
+         FAIL bad.bas\nPASS greet.bas\n1 passed, 2 failed\n"
+    );
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Type mismatch"));
+
+    let out = run_xbasic64(&dir.join("more"), &["test", "plain.bas"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("No programs"));
+}
+
+#[test]
+fn test_spec_timeout() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    std::fs::write(tmp.path().join("loop.bas"), "10 GOTO 10\n'EXPECT: never\n").unwrap();
+    let out = run_xbasic64(tmp.path(), &["test", "--timeout", "1"]);
+    assert!(!out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("Still running after 1s"), "{}", stdout);
+}
+
+#[test]
+fn test_spec_compiler_options() {
+    // Options before `test` are used to build each program
+    let files = [
+        ("main.bas", "'$INCLUDE: 'defs.bi'\nPRINT X 'EXPECT: 1\n"),
+        ("lib/defs.bi", "X = 1\n"),
+        (
+            "dict.bas",
+            "D = DICTNEW()\nDICTSET D, \"k\", \"v\"\nPRINT 1 'EXPECT: 1\n",
+        ),
+    ];
+    let (out, tmp) = compile_files(&files, &["test", "main.bas"]);
+    assert!(!out.status.success());
+    let out = run_xbasic64(tmp.path(), &["-I", "lib", "test", "main.bas", "dict.bas"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    let out = run_xbasic64(tmp.path(), &["--dialect", "qb45", "test", "dict.bas"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("DICTSET is not part of"), "{}", stderr);
+}
@@ -3,7 +3,7 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-use crate::common::compile_and_run;
+use crate::common::{compile_and_run, run_compiler};
 
 #[test]
 fn test_string_functions() {
@@ -30,10 +30,110 @@ PRINT INSTR("Hello World", "World")
     assert_eq!(lines[4], "A", "chr$");
     assert_eq!(lines[5], "65", "asc");
     assert_eq!(lines[6], "50", "val");
-    assert_eq!(lines[7], "100", "str$");
+    assert_eq!(lines[7], " 100", "str$");
     assert_eq!(lines[8], "7", "instr");
 }
 
+#[test]
+fn test_instr_start() {
+    // The start position is honored, clamped, and arguments that call
+    // functions or read a FOR counter keep the stack and registers intact
+    let output = compile_and_run(
+        r#"
+A$ = "abcabcabc"
+PRINT INSTR(2, A$, "abc"); INSTR(8, A$, "abc"); INSTR(20, A$, "a"); INSTR(-3, A$, "b")
+PRINT INSTR(4, A$, ""); INSTR(20, A$, ""); INSTR(1, "", "")
+S! = 5.7: PRINT INSTR(S!, A$, "b")
+FOR I% = 1 TO 9: PRINT INSTR(I%, A$, MID$("xc", 2, 1));: NEXT
+PRINT
+FOR K = 1 TO 2: PRINT INSTR(K * 5, LEFT$(A$, 8) + "!", "c" + ""); : NEXT
+PRINT
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(
+        lines[0], "4002",
+        "start past the match, the end or before 1"
+    );
+    assert_eq!(lines[1], "4201", "empty needle");
+    assert_eq!(lines[2], "5", "single start");
+    assert_eq!(lines[3], "333666999", "for counter start");
+    assert_eq!(lines[4], "60", "calls in the arguments");
+}
+
+#[test]
+fn test_instrrev() {
+    // INSTRREV([start,] s$, find$): the last match starting at or before start
+    let output = compile_and_run(
+        r#"
+A$ = "abcabcabc"
+PRINT INSTRREV(A$, "abc"); INSTRREV(6, A$, "abc"); INSTRREV(3, A$, "abc"); INSTRREV(A$, "z")
+PRINT INSTRREV(0, A$, "a"); INSTRREV(99, A$, "c"); INSTRREV(2, "ab", "abc")
+PRINT INSTRREV(A$, ""); INSTRREV(4, A$, ""); INSTRREV("", "")
+P$ = "dir/sub/file.bas": PRINT MID$(P$, INSTRREV(P$, "/") + 1)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "7410");
+    assert_eq!(lines[1], "090");
+    assert_eq!(lines[2], "940");
+    assert_eq!(lines[3], "file.bas");
+}
+
+#[test]
+fn test_str_round_trip() {
+    // STR$ gives every digit a DOUBLE needs, and a space for the sign
+    let output = compile_and_run(
+        r#"
+X = 0.1 + 0.2: PRINT X; VAL(STR$(X)) = X
+Y = 1 / 3: PRINT VAL(STR$(Y)) = Y
+Z = 1E+300 / 7: PRINT VAL(STR$(Z)) = Z
+PRINT "["; STR$(42); "]["; STR$(-42); "]["; STR$(0); "]"
+S! = 1 / 3: PRINT STR$(S!)
+PRINT 2.5; 1E+20; 1E-5
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "0.30000000000000004-1");
+    assert_eq!(lines[1], "-1");
+    assert_eq!(lines[2], "-1");
+    assert_eq!(lines[3], "[ 42][-42][ 0]");
+    assert_eq!(lines[4], " 0.3333333", "single keeps 7 digits");
+    assert_eq!(lines[5], "2.51e+201e-05");
+}
+
+#[test]
+fn test_control_characters_in_literals() {
+    // Tabs, control characters and NULs in literals reach the program as is
+    let output = compile_and_run(
+        "A$ = \"a\tb\u{1}c\\d\"\"e\": PRINT A$; LEN(A$)\n\
+         PRINT \"x\0y\"; LEN(\"x\0y\")\n\
+         READ B$: PRINT B$; LEN(B$); VAL(B$)\n\
+         DATA \"7\0z\u{7f}\"\n",
+    )
+    .unwrap();
+    assert_eq!(output, "a\tb\u{1}c\\d\"e9\nx\0y3\n7\0z\u{7f}47\n");
+}
+
+#[test]
+fn test_asc_position() {
+    // ASC takes an optional 1-based position; CHR$ truncates like other
+    // integer arguments, SINGLE included
+    let output = compile_and_run(
+        r#"
+S$ = "Hello"
+PRINT ASC(S$); ASC(S$, 2); ASC(S$, 5); ASC(S$, 2.9)
+A! = 66.5
+PRINT CHR$(72); CHR$(0.9 + 65); CHR$(A!); LEN(CHR$(255)); ASC(CHR$(0))
+"#,
+    )
+    .unwrap();
+    assert_eq!(output, "72101111101\nHAB10\n");
+}
+
 #[test]
 fn test_nested_string_calls() {
     // Test LEFT$, RIGHT$, MID$ with nested function calls
@@ -75,3 +175,92 @@ PRINT A$ + B$ + C$
     .unwrap();
     assert_eq!(output.trim(), "Hello World");
 }
+
+#[test]
+fn test_string_comparison() {
+    let output = compile_and_run(
+        r#"
+A$ = "pear"
+PRINT A$ = "pear"; A$ <> "pear"; "abc" < "abd"; "b" > "abc"
+PRINT "ab" < "abc"; "abc" <= "ab"; "" < "a"; "x" >= "x"
+IF A$ = "pe" + "ar" THEN PRINT "equal"
+SELECT CASE A$
+CASE "apple"
+    PRINT "apple"
+CASE "pear"
+    PRINT "pear"
+CASE ELSE
+    PRINT "other"
+END SELECT
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "-10-1-1");
+    assert_eq!(lines[1], "-10-1-1");
+    assert_eq!(lines[2], "equal");
+    assert_eq!(lines[3], "pear");
+}
+
+#[test]
+fn test_type_mismatch_rejected() {
+    let err = compile_and_run("10 PRINT \"abc\" * 2").unwrap_err();
+    assert!(
+        err.contains("test.bas:1:16: Error: Type mismatch"),
+        "{}",
+        err
+    );
+    // The caret is under the operator
+    assert!(
+        err.contains("    1 | 10 PRINT \"abc\" * 2\n      |                ^\n"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_dictionaries() {
+    // Keys map to copies of their values and stay in the order first set;
+    // the index grows past its first size and is rebuilt after DICTDEL
+    let output = compile_and_run(
+        r#"
+D = DICTNEW()
+K$ = "apple": V$ = "red"
+DICTSET D, K$, V$
+K$ = "x": V$ = "y"
+DICTSET D, "banana", "yellow"
+DICTSET D, "apple", "green"
+DICTSET D, "", "empty"
+PRINT DICTGET$(D, "apple"); " "; DICTGET$(D, "banana"); " ["; DICTGET$(D, "kiwi"); "] "; DICTGET$(D, "")
+PRINT DICTHAS(D, "banana"); DICTHAS(D, "kiwi"); DICTCOUNT(D)
+DICTDEL D, "apple": DICTDEL D, "apple"
+PRINT DICTKEY$(D, 1); DICTKEY$(D, 2); DICTCOUNT(D); DICTHAS(D, "apple")
+E = DICTNEW()
+FOR I = 1 TO 100: DICTSET E, "k" + STR$(I), STR$(I * I): NEXT
+FOR I = 1 TO 100 STEP 2: DICTDEL E, "k" + STR$(I): NEXT
+S = 0
+FOR I = 1 TO 100: S = S + VAL(DICTGET$(E, "k" + STR$(I))): NEXT
+PRINT S; DICTCOUNT(E); DICTKEY$(E, 1); DICTCOUNT(D)
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "green yellow [] empty");
+    assert_eq!(lines[1], "-103");
+    assert_eq!(lines[2], "banana20");
+    assert_eq!(lines[3], "17170050k 22");
+}
+
+#[test]
+fn test_dictnew_without_parentheses() {
+    // DICTNEW, like TIMER and RND, is a call without its parentheses, not a
+    // variable holding 0
+    let source = r#"
+K$ = "apple": V$ = "red"
+D = DICTNEW : DICTSET D, K$, V$
+PRINT DICTGET$(D, K$); " "; DICTCOUNT(D); " "; D = DICTNEW
+"#;
+    let expected = "red 1 0\n";
+    assert_eq!(compile_and_run(source).unwrap(), expected);
+    assert_eq!(run_compiler(source, &["--run"]).unwrap(), expected);
+}
@@ -3,7 +3,7 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-use crate::common::compile_and_run;
+use crate::common::{compile_and_run, compile_and_run_with_args, compile_and_run_with_stdin};
 
 #[test]
 fn test_type_conversions() {
@@ -198,3 +198,113 @@ A% = 10: B& = 20: C! = 0.5: D# = 100.0: PRINT A% + B& * C! + D#
     assert_eq!(lines[5], "1024", "single^double");
     assert_eq!(lines[6], "120", "mixed expression");
 }
+
+#[test]
+fn test_integer_arrays_and_input() {
+    // % and & arrays, READ and INPUT keep the integer type of their target
+    let output = compile_and_run_with_stdin(
+        r#"
+DIM A%(3), B&(3)
+A%(1) = 7: B&(2) = 100000
+PRINT A%(1) + 1; " "; B&(2) * 3; " "; A%(1) \ 2
+READ P%, Q&
+PRINT P% + Q&
+INPUT R%
+PRINT R% * 2
+DATA 12, 70000
+"#,
+        "21\n",
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "8 300000 3", "integer arrays");
+    assert_eq!(lines[1], "70012", "READ into integers");
+    assert!(lines[2].ends_with("42"), "INPUT into integer: {}", lines[2]);
+}
+
+#[test]
+fn test_large_integer_literals() {
+    // Literals past the Long range are Double constants
+    let output = compile_and_run(
+        r#"
+F# = 3000000000
+PRINT F#
+PRINT 2000000000 \ 3; " "; 2000000000 MOD 7
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "3000000000");
+    assert_eq!(lines[1], "666666666 5");
+}
+
+#[test]
+fn test_typed_literals() {
+    // A suffix gives a literal its type
+    let output = compile_and_run(
+        r#"
+PRINT 30000& + 30000&
+PRINT 1.1! * 1.1!
+PRINT 1D0# / 3
+DATA 7%, -2.5!
+READ A%, B!
+PRINT A%; B!
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines, vec!["60000", "1.21", "0.3333333333333333", "7-2.5"]);
+    assert!(compile_and_run("PRINT 40000%").is_err());
+
+    // INTEGER + INTEGER is an INTEGER result, which overflows past 32767
+    let check = &["--overflow-check"];
+    let ok = compile_and_run_with_args("PRINT 30000 + 30000", check).unwrap();
+    assert_eq!(ok.trim(), "60000");
+    assert!(compile_and_run_with_args("PRINT 30000% + 30000%", check).is_err());
+}
+
+#[test]
+fn test_single_precision() {
+    // SINGLE values are 32-bit floats and print with 7 significant digits
+    let output = compile_and_run(
+        r#"
+X! = 1 / 3
+PRINT X!
+Y! = 16777217
+PRINT Y!
+C! = 1.1 * 1.1
+PRINT C!
+D# = 1 / 3
+PRINT D#
+"#,
+    )
+    .unwrap();
+    let lines: Vec<&str> = output.trim().lines().collect();
+    assert_eq!(lines[0], "0.3333333");
+    assert_eq!(lines[1], "16777216", "rounded to 24-bit mantissa");
+    assert_eq!(lines[2], "1.21");
+    assert_eq!(lines[3], "0.3333333333333333");
+}
+
+#[test]
+fn test_integer_overflow() {
+    // Integer results wrap by default
+    let output = compile_and_run("A% = 32767\nA% = A% + 1\nPRINT A%").unwrap();
+    assert_eq!(output.trim(), "-32768");
+
+    // --overflow-check raises "Overflow" instead
+    let check = &["--overflow-check"];
+    let ok = compile_and_run_with_args("A% = 32766\nA% = A% + 1\nPRINT A%", check).unwrap();
+    assert_eq!(ok.trim(), "32767");
+    for source in [
+        "A% = 32767\nA% = A% + 1",
+        "A% = -32768\nA% = -A%",
+        "L& = 2147483647\nL& = L& + 1",
+        "L& = 65536\nL& = L& * L&",
+        "A% = 40000",
+        "L& = 1E10",
+    ] {
+        let result = compile_and_run_with_args(source, check);
+        assert!(result.is_err(), "no overflow: {}", source);
+    }
+}
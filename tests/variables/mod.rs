@@ -3,7 +3,7 @@
 // Copyright (c) 2025-2026 Jeff Garzik
 // SPDX-License-Identifier: MIT
 
-use crate::common::{compile_and_run, normalize_output};
+use crate::common::{compile_and_run, compile_and_run_with_args, normalize_output, run_compiler};
 
 #[test]
 fn test_variable_types() {
@@ -47,3 +47,49 @@ PRINT "after"
     assert_eq!(lines[3], "before", "before comment");
     assert_eq!(lines[4], "after", "after comment");
 }
+
+#[test]
+fn test_const() {
+    let source = r#"
+CONST ROWS = 5, COLS = ROWS * 2, TITLE$ = "Table"
+CONST HALF% = 2.5, BIG& = 70000
+DIM Grid(ROWS, COLS)
+PRINT TITLE$; UBOUND(Grid, 2)
+PRINT HALF%; BIG& \ 7
+Show
+SUB Show
+    CONST LOCAL = ROWS + 1
+    PRINT "in sub"; LOCAL * COLS
+END SUB
+"#;
+    let output = compile_and_run(source).unwrap();
+    assert_eq!(output, "Table10\n210000\nin sub60\n");
+    assert_eq!(run_compiler(source, &["--run"]).unwrap(), output);
+}
+
+#[test]
+fn test_option_explicit() {
+    let source = r#"
+OPTION EXPLICIT
+DIM A(3)
+FOR I = 1 TO 3
+    A(I) = I * I
+NEXT I
+TOTAL = A(1) + A(2) + A(3)
+PRINT TOTAL
+"#;
+    let output = compile_and_run(source).unwrap();
+    assert_eq!(output.trim(), "14");
+
+    // A misspelled variable is a compile error instead of a silent zero
+    let err = compile_and_run("OPTION EXPLICIT\nTOTAL = 5\nPRINT TOTL").unwrap_err();
+    assert!(err.contains("TOTL"), "{}", err);
+}
+
+#[test]
+fn test_explicit_flag() {
+    assert_eq!(compile_and_run("PRINT X").unwrap().trim(), "0");
+    assert!(compile_and_run_with_args("PRINT X", &["--explicit"]).is_err());
+    let output = compile_and_run_with_args("X = 7: PRINT X", &["--explicit"]).unwrap();
+    assert_eq!(output.trim(), "7");
+}